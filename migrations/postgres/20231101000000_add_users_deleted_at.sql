@@ -0,0 +1,2 @@
+-- Soft-deleted users keep their row and record when they were deleted
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
@@ -0,0 +1,2 @@
+-- Every update bumps the version so clients can detect concurrent changes
+ALTER TABLE users ADD COLUMN IF NOT EXISTS version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,4 @@
+-- Index names and emails for full-text search; email separators become spaces so each part is a word
+ALTER TABLE users ADD COLUMN IF NOT EXISTS search_vector tsvector
+    GENERATED ALWAYS AS (to_tsvector('simple', name || ' ' || translate(email, '@.', '  '))) STORED;
+CREATE INDEX IF NOT EXISTS users_search_vector_idx ON users USING GIN (search_vector);
@@ -0,0 +1,2 @@
+-- Record when each user was created; existing users get the time of the migration
+ALTER TABLE users ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
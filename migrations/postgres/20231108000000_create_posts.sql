@@ -0,0 +1,11 @@
+-- Create the posts table; purging a user removes their posts too
+CREATE TABLE IF NOT EXISTS posts (
+    id BIGSERIAL PRIMARY KEY,
+    author_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Index posts by author for loading every post of a batch of users
+CREATE INDEX IF NOT EXISTS posts_author_id_idx ON posts (author_id, id);
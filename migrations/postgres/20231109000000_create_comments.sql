@@ -0,0 +1,14 @@
+-- Create the comments table; replies point at the comment they answer, and removing a post,
+-- an author, or a comment removes the comments below it
+CREATE TABLE IF NOT EXISTS comments (
+    id BIGSERIAL PRIMARY KEY,
+    post_id BIGINT NOT NULL REFERENCES posts (id) ON DELETE CASCADE,
+    parent_id BIGINT REFERENCES comments (id) ON DELETE CASCADE,
+    author_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    body TEXT NOT NULL,
+    depth INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Index comments by thread for paging through the comments on a post or the replies to a comment
+CREATE INDEX IF NOT EXISTS comments_thread_idx ON comments (post_id, parent_id, id);
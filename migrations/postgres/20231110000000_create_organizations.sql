@@ -0,0 +1,19 @@
+-- Create the organizations table
+CREATE TABLE IF NOT EXISTS organizations (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Create the memberships table; a user belongs to an organization at most once, and removing
+-- either side removes the membership
+CREATE TABLE IF NOT EXISTS memberships (
+    organization_id BIGINT NOT NULL REFERENCES organizations (id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    role TEXT NOT NULL,
+    joined_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (organization_id, user_id)
+);
+
+-- Index memberships by user for listing the organizations a user belongs to
+CREATE INDEX IF NOT EXISTS memberships_user_idx ON memberships (user_id);
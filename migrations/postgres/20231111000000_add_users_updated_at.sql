@@ -0,0 +1,4 @@
+-- Record when each user was last updated; existing users count as updated when they were created
+ALTER TABLE users ADD COLUMN updated_at TIMESTAMPTZ;
+UPDATE users SET updated_at = created_at;
+ALTER TABLE users ALTER COLUMN updated_at SET NOT NULL, ALTER COLUMN updated_at SET DEFAULT now();
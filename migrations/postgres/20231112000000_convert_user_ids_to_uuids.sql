@@ -0,0 +1,16 @@
+-- Switch user IDs to UUIDs generated by the application. Existing numeric IDs become the UUID with
+-- the same value, so they keep sorting before every generated ID and in their original order
+ALTER TABLE posts DROP CONSTRAINT posts_author_id_fkey;
+ALTER TABLE comments DROP CONSTRAINT comments_author_id_fkey;
+ALTER TABLE memberships DROP CONSTRAINT memberships_user_id_fkey;
+
+ALTER TABLE users ALTER COLUMN id DROP DEFAULT;
+ALTER TABLE users ALTER COLUMN id TYPE UUID USING lpad(to_hex(id), 32, '0')::UUID;
+ALTER TABLE posts ALTER COLUMN author_id TYPE UUID USING lpad(to_hex(author_id), 32, '0')::UUID;
+ALTER TABLE comments ALTER COLUMN author_id TYPE UUID USING lpad(to_hex(author_id), 32, '0')::UUID;
+ALTER TABLE memberships ALTER COLUMN user_id TYPE UUID USING lpad(to_hex(user_id), 32, '0')::UUID;
+DROP SEQUENCE users_id_seq;
+
+ALTER TABLE posts ADD CONSTRAINT posts_author_id_fkey FOREIGN KEY (author_id) REFERENCES users (id) ON DELETE CASCADE;
+ALTER TABLE comments ADD CONSTRAINT comments_author_id_fkey FOREIGN KEY (author_id) REFERENCES users (id) ON DELETE CASCADE;
+ALTER TABLE memberships ADD CONSTRAINT memberships_user_id_fkey FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
@@ -0,0 +1,2 @@
+-- Store free-form client metadata with each user; the server limits its size before writing it
+ALTER TABLE users ADD COLUMN metadata JSONB;
@@ -0,0 +1,2 @@
+-- Store an optional postal address with each user as a JSON object with street, city, country and postal_code
+ALTER TABLE users ADD COLUMN address JSONB;
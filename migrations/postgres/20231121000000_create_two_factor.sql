@@ -0,0 +1,15 @@
+-- Create the tables for TOTP two-factor enrollments: one secret per user, pending until it is confirmed,
+-- and only SHA-256 hashes of the recovery codes not used yet. Removing the user removes their enrollment,
+-- and removing an enrollment removes its recovery codes
+CREATE TABLE IF NOT EXISTS two_factor (
+    user_id UUID PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    secret TEXT NOT NULL,
+    confirmed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    user_id UUID NOT NULL REFERENCES two_factor (user_id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,7 @@
+-- Every write records a change event in the same transaction; the relay publishes and removes them in ID order
+CREATE TABLE IF NOT EXISTS outbox (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Record when each user was created; existing users get the time of the migration.
+-- SQLite cannot add a column with a non-constant default, so inserts set it explicitly
+ALTER TABLE users ADD COLUMN created_at TEXT;
+UPDATE users SET created_at = strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now') WHERE created_at IS NULL;
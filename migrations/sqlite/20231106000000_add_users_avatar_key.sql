@@ -0,0 +1,2 @@
+-- Name the uploaded avatar image of each user in the avatar store
+ALTER TABLE users ADD COLUMN avatar_key TEXT;
@@ -0,0 +1,2 @@
+-- Index emails case-insensitively for lookups by email
+CREATE INDEX IF NOT EXISTS users_email_lower_idx ON users (lower(email));
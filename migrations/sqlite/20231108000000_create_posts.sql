@@ -0,0 +1,11 @@
+-- Create the posts table; purging a user removes their posts too
+CREATE TABLE IF NOT EXISTS posts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    author_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+-- Index posts by author for loading every post of a batch of users
+CREATE INDEX IF NOT EXISTS posts_author_id_idx ON posts (author_id, id);
@@ -0,0 +1,4 @@
+-- Record when each user was last updated; existing users count as updated when they were created.
+-- As with created_at, inserts set it explicitly
+ALTER TABLE users ADD COLUMN updated_at TEXT;
+UPDATE users SET updated_at = created_at;
@@ -0,0 +1,66 @@
+-- Switch user IDs to UUIDs generated by the application. Existing numeric IDs become the UUID with
+-- the same value, so they keep sorting before every generated ID and in their original order.
+-- SQLite cannot change a column's type, so the users table and the tables pointing at it are rebuilt
+-- side by side; the old tables are dropped children first so no cascade reaches the copied rows,
+-- and renaming the new tables carries their foreign keys over to the new names
+PRAGMA defer_foreign_keys = ON;
+
+CREATE TABLE users_new (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    deleted_at TEXT,
+    version INTEGER NOT NULL DEFAULT 1,
+    created_at TEXT,
+    avatar_key TEXT,
+    updated_at TEXT
+);
+INSERT INTO users_new (id, name, email, deleted_at, version, created_at, avatar_key, updated_at)
+SELECT printf('00000000-0000-0000-0000-%012x', id), name, email, deleted_at, version, created_at, avatar_key, updated_at FROM users;
+
+CREATE TABLE posts_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    author_id TEXT NOT NULL REFERENCES users_new (id) ON DELETE CASCADE,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+INSERT INTO posts_new (id, author_id, title, body, created_at)
+SELECT id, printf('00000000-0000-0000-0000-%012x', author_id), title, body, created_at FROM posts;
+
+CREATE TABLE comments_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    post_id INTEGER NOT NULL REFERENCES posts_new (id) ON DELETE CASCADE,
+    parent_id INTEGER REFERENCES comments_new (id) ON DELETE CASCADE,
+    author_id TEXT NOT NULL REFERENCES users_new (id) ON DELETE CASCADE,
+    body TEXT NOT NULL,
+    depth INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+INSERT INTO comments_new (id, post_id, parent_id, author_id, body, depth, created_at)
+SELECT id, post_id, parent_id, printf('00000000-0000-0000-0000-%012x', author_id), body, depth, created_at FROM comments;
+
+CREATE TABLE memberships_new (
+    organization_id INTEGER NOT NULL REFERENCES organizations (id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL REFERENCES users_new (id) ON DELETE CASCADE,
+    role TEXT NOT NULL,
+    joined_at TEXT NOT NULL,
+    PRIMARY KEY (organization_id, user_id)
+);
+INSERT INTO memberships_new (organization_id, user_id, role, joined_at)
+SELECT organization_id, printf('00000000-0000-0000-0000-%012x', user_id), role, joined_at FROM memberships;
+
+DROP TABLE memberships;
+DROP TABLE comments;
+DROP TABLE posts;
+DROP TABLE users;
+
+ALTER TABLE users_new RENAME TO users;
+ALTER TABLE posts_new RENAME TO posts;
+ALTER TABLE comments_new RENAME TO comments;
+ALTER TABLE memberships_new RENAME TO memberships;
+
+CREATE INDEX users_email_lower_idx ON users (lower(email));
+CREATE INDEX posts_author_id_idx ON posts (author_id, id);
+CREATE INDEX comments_thread_idx ON comments (post_id, parent_id, id);
+CREATE INDEX memberships_user_idx ON memberships (user_id);
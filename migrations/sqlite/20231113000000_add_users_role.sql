@@ -0,0 +1,2 @@
+-- Record each user's role across the service; existing users become members
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'member' CHECK (role IN ('admin', 'member', 'guest'));
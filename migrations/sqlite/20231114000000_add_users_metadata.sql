@@ -0,0 +1,2 @@
+-- Store free-form client metadata with each user as JSON text; the server limits its size before writing it
+ALTER TABLE users ADD COLUMN metadata TEXT CHECK (metadata IS NULL OR json_valid(metadata));
@@ -0,0 +1,2 @@
+-- Store an optional phone number with each user, always in E.164 form
+ALTER TABLE users ADD COLUMN phone TEXT;
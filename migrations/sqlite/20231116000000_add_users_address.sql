@@ -0,0 +1,2 @@
+-- Store an optional postal address with each user as JSON text with street, city, country and postal_code
+ALTER TABLE users ADD COLUMN address TEXT CHECK (address IS NULL OR json_valid(address));
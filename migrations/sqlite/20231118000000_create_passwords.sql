@@ -0,0 +1,6 @@
+-- Create the passwords table holding one Argon2 hash per user; removing the user removes their password
+CREATE TABLE IF NOT EXISTS passwords (
+    user_id TEXT PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    password_hash TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
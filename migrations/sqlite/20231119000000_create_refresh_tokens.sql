@@ -0,0 +1,10 @@
+-- Create the refresh_tokens table; only a hash of each token is stored, used tokens are kept as revoked
+-- so that one presented again can be recognized, and removing the user removes their tokens
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    expires_at TEXT NOT NULL,
+    revoked_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_user_id_idx ON refresh_tokens (user_id);
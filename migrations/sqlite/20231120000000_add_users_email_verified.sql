@@ -0,0 +1,2 @@
+-- Record whether each user has verified their email address; users stored before verification existed count as verified
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT 1;
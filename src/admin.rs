@@ -0,0 +1,339 @@
+// Import necessary libraries and modules
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::stream::{self, Stream};
+use serde::Serialize;
+use std::collections::HashMap;
+use warp::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use warp::http::{Response, StatusCode};
+use warp::hyper::body::{Body, Bytes};
+use warp::{Filter, Rejection, Reply};
+
+use crate::auth::{auth_context, tokens_match, AuthContext, Authenticator};
+use crate::config::Settings;
+use crate::log_error;
+use crate::model::{User, UserFilter, UserRole};
+use crate::repository::{RepositoryError, RepositoryResult, SharedRepository};
+
+// Define how many users the export reads from the repository at a time
+pub const EXPORT_PAGE_SIZE: usize = 500;
+
+// Read the bearer token required by the admin routes from ADMIN_TOKEN; returns None when they are disabled
+pub fn admin_token_from_settings(settings: &Settings) -> Option<String> {
+    settings.var("ADMIN_TOKEN").filter(|token| !token.is_empty())
+}
+
+// Compare an Authorization header with the expected bearer token without leaking how much of it matched
+fn is_authorized(header: Option<&str>, token: &str) -> bool {
+    let Some(presented) = header.and_then(|header| header.strip_prefix("Bearer ")) else {
+        return false;
+    };
+    tokens_match(presented, token)
+}
+
+// Define the JSON body of admin errors
+#[derive(Serialize)]
+struct AdminError {
+    error: String,
+}
+
+// Build an error response with a JSON body
+fn error_response(code: StatusCode, error: impl Into<String>) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&AdminError { error: error.into() }), code).into_response()
+}
+
+// Define the formats users can be exported in
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    // Parse the `format` query parameter, defaulting to JSON
+    fn parse(format: Option<&str>) -> Result<Self, String> {
+        match format {
+            None | Some("json") => Ok(ExportFormat::Json),
+            Some("csv") => Ok(ExportFormat::Csv),
+            Some(other) => Err(format!("format must be csv or json, got {:?}", other)),
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Json => "application/json",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+
+    // Encode one page of users; the first page opens the document and the last one closes it
+    fn encode(self, users: &[User], first: bool, last: bool) -> RepositoryResult<Bytes> {
+        let encoding_error = |error: String| RepositoryError::Backend(format!("failed to encode export: {}", error));
+        let mut chunk = Vec::new();
+        match self {
+            ExportFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(&mut chunk);
+                if first {
+                    writer
+                        .write_record(["id", "name", "email", "version", "created_at", "deleted_at"])
+                        .map_err(|error| encoding_error(error.to_string()))?;
+                }
+                for user in users {
+                    let deleted_at = user.deleted_at.map(|deleted_at| deleted_at.to_rfc3339()).unwrap_or_default();
+                    writer
+                        .write_record([
+                            user.id.as_str(),
+                            &user.name,
+                            &user.email,
+                            &user.version.to_string(),
+                            &user.created_at.to_rfc3339(),
+                            &deleted_at,
+                        ])
+                        .map_err(|error| encoding_error(error.to_string()))?;
+                }
+                writer.flush().map_err(|error| encoding_error(error.to_string()))?;
+            }
+            ExportFormat::Json => {
+                if first {
+                    chunk.push(b'[');
+                }
+                for (index, user) in users.iter().enumerate() {
+                    if !first || index > 0 {
+                        chunk.push(b',');
+                    }
+                    serde_json::to_writer(&mut chunk, user).map_err(|error| encoding_error(error.to_string()))?;
+                }
+                if last {
+                    chunk.push(b']');
+                }
+            }
+        }
+        Ok(Bytes::from(chunk))
+    }
+}
+
+// Parse a date filter given either as an RFC 3339 timestamp or as a date meaning midnight UTC
+fn parse_timestamp(name: &str, value: Option<&str>) -> Result<Option<DateTime<Utc>>, String> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(Some(timestamp.with_timezone(&Utc)));
+    }
+    match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(date) => Ok(Some(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())),
+        Err(_) => Err(format!("{} must be an RFC 3339 timestamp or a YYYY-MM-DD date, got {:?}", name, value)),
+    }
+}
+
+// Stream every user covered by the filter, reading one page at a time and encoding it as it arrives
+fn export_stream(
+    repository: SharedRepository,
+    filter: UserFilter,
+    format: ExportFormat,
+    page_size: usize,
+) -> impl Stream<Item = RepositoryResult<Bytes>> + Send + 'static {
+    // The state is the cursor after the last exported user and whether the first page has been sent
+    stream::unfold(Some((None::<String>, true)), move |state| {
+        let repository = repository.clone();
+        let filter = filter.clone();
+        async move {
+            let (after, first) = state?;
+            let page = match repository.list_page(&filter, after.as_deref(), page_size).await {
+                Ok(page) => page,
+                Err(error) => {
+                    log_error!("Export failed: {}", error);
+                    return Some((Err(error), None));
+                }
+            };
+            let last = page.len() < page_size;
+            let next = page.last().map(|user| user.id.clone()).or(after);
+            let chunk = format.encode(&page, first, last);
+            Some((chunk, (!last).then_some((next, false))))
+        }
+    })
+}
+
+// Build the GET /admin/export route, which streams users as CSV or JSON to callers presenting
+// `Authorization: Bearer <ADMIN_TOKEN>` or the credentials of an admin user; other users are refused
+// with 403. Without a token configured only admin users may export
+pub fn export_route(
+    repository: SharedRepository,
+    token: Option<String>,
+    authenticator: Authenticator,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+    warp::path!("admin" / "export")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(auth_context(authenticator))
+        .and(warp::query::<HashMap<String, String>>())
+        .then(move |authorization: Option<String>, auth: AuthContext, query: HashMap<String, String>| {
+            let repository = repository.clone();
+            let token = token.clone();
+            async move {
+                if token.as_deref().is_some_and(|token| is_authorized(authorization.as_deref(), token)) {
+                    return export_response(repository, &query, EXPORT_PAGE_SIZE);
+                }
+                let Some(user_id) = auth.principal() else {
+                    if token.is_none() {
+                        return error_response(StatusCode::FORBIDDEN, "the admin API is disabled; set ADMIN_TOKEN to enable it");
+                    }
+                    return error_response(StatusCode::UNAUTHORIZED, "a valid admin bearer token is required");
+                };
+                match repository.get(user_id).await {
+                    Ok(Some(user)) if user.role.includes(UserRole::Admin) => export_response(repository, &query, EXPORT_PAGE_SIZE),
+                    Ok(Some(_)) => error_response(StatusCode::FORBIDDEN, "only admins can export users"),
+                    Ok(None) => error_response(StatusCode::UNAUTHORIZED, "the user the credentials belong to no longer exists"),
+                    Err(error) => error_response(StatusCode::INTERNAL_SERVER_ERROR, error.to_string()),
+                }
+            }
+        })
+}
+
+// Read the export format and filter from the query parameters
+fn parse_export_query(query: &HashMap<String, String>) -> Result<(ExportFormat, UserFilter), String> {
+    let format = ExportFormat::parse(query.get("format").map(String::as_str))?;
+    let include_deleted = match query.get("include_deleted").map(String::as_str) {
+        None | Some("false") => false,
+        Some("true") => true,
+        Some(other) => return Err(format!("include_deleted must be true or false, got {:?}", other)),
+    };
+    let filter = UserFilter {
+        include_deleted,
+        created_after: parse_timestamp("created_after", query.get("created_after").map(String::as_str))?,
+        created_before: parse_timestamp("created_before", query.get("created_before").map(String::as_str))?,
+        ..Default::default()
+    };
+    Ok((format, filter))
+}
+
+// Validate the query and start streaming the export
+fn export_response(repository: SharedRepository, query: &HashMap<String, String>, page_size: usize) -> warp::reply::Response {
+    let (format, filter) = match parse_export_query(query) {
+        Ok(request) => request,
+        Err(error) => return error_response(StatusCode::BAD_REQUEST, error),
+    };
+
+    let filename = format!("users-{}.{}", Utc::now().format("%Y%m%d-%H%M%S"), format.extension());
+    let body = Body::wrap_stream(export_stream(repository, filter, format, page_size));
+    Response::builder()
+        .header(CONTENT_TYPE, format.content_type())
+        .header(CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))
+        .body(body)
+        .unwrap_or_else(|error| error_response(StatusCode::INTERNAL_SERVER_ERROR, error.to_string()))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{legacy_user_id, NewUser, UserUpdate};
+    use crate::repository::{InMemoryRepository, UserRepository};
+    use chrono::Duration;
+    use std::sync::Arc;
+
+    // Send a request to the export route with the given query and Authorization header
+    async fn export(repository: SharedRepository, query: &str, authorization: Option<&str>) -> warp::http::Response<Bytes> {
+        let route = export_route(repository, Some("secret".to_string()), Authenticator::default());
+        let mut request = warp::test::request().method("GET").path(&format!("/admin/export{}", query));
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        request.reply(&route).await
+    }
+
+    // Define a test that the export requires the admin token
+    #[tokio::test]
+    async fn test_export_requires_token() {
+        let repository: SharedRepository = Arc::new(InMemoryRepository::with_sample_users());
+        for authorization in [None, Some("Bearer wrong"), Some("secret"), Some("Bearer secret2")] {
+            let response = export(repository.clone(), "", authorization).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        // Without ADMIN_TOKEN the route is disabled for callers who are not users
+        let route = export_route(repository, None, Authenticator::default());
+        let response = warp::test::request().path("/admin/export").header("authorization", "Bearer ").reply(&route).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    // Define a test for CSV output and the download filename
+    #[tokio::test]
+    async fn test_export_csv() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        repository.delete(&legacy_user_id(2).to_string()).await.unwrap();
+        let response = export(repository.clone(), "?format=csv", Some("Bearer secret")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/csv; charset=utf-8");
+        let disposition = response.headers()[CONTENT_DISPOSITION].to_str().unwrap();
+        assert!(disposition.starts_with("attachment; filename=\"users-"));
+        assert!(disposition.ends_with(".csv\""));
+
+        let pavel = repository.get(&legacy_user_id(1).to_string()).await.unwrap().unwrap();
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        let expected = format!(
+            "id,name,email,version,created_at,deleted_at\n{},Pavel,Pavelboukine@gmail.com,1,{},\n",
+            pavel.id,
+            pavel.created_at.to_rfc3339()
+        );
+        assert_eq!(body, expected);
+
+        // Deleted users are included on request
+        let response = export(repository, "?format=csv&include_deleted=true", Some("Bearer secret")).await;
+        assert_eq!(String::from_utf8(response.body().to_vec()).unwrap().lines().count(), 3);
+    }
+
+    // Define a test that JSON output spans several pages and honours the date filters
+    #[tokio::test]
+    async fn test_export_json_pages_and_filters() {
+        let repository = Arc::new(InMemoryRepository::new());
+        let new_users = (0..EXPORT_PAGE_SIZE + 1)
+            .map(|index| NewUser { name: format!("User {}", index), email: format!("user{}@example.com", index), ..Default::default() })
+            .collect();
+        repository.create_many(new_users).await.unwrap();
+
+        let response = export(repository.clone(), "", Some("Bearer secret")).await;
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        let users: Vec<User> = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(users.len(), EXPORT_PAGE_SIZE + 1);
+        assert_eq!(users.last().unwrap().name, format!("User {}", EXPORT_PAGE_SIZE));
+
+        // Every user was created just now, so a window in the past matches nothing
+        let tomorrow = (Utc::now() + Duration::days(1)).format("%Y-%m-%d");
+        let query = format!("?created_before=2023-01-01&created_after={}", tomorrow);
+        let response = export(repository.clone(), &query, Some("Bearer secret")).await;
+        assert_eq!(response.body().as_ref(), b"[]");
+        let query = format!("?created_after=2023-01-01T00:00:00Z&created_before={}", tomorrow);
+        let users: Vec<User> = serde_json::from_slice(export(repository.clone(), &query, Some("Bearer secret")).await.body()).unwrap();
+        assert_eq!(users.len(), EXPORT_PAGE_SIZE + 1);
+
+        // Bad parameters are rejected before anything is streamed
+        for query in ["?format=xml", "?created_after=yesterday", "?include_deleted=maybe"] {
+            let response = export(repository.clone(), query, Some("Bearer secret")).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    // Define a test that admin users may export with their own credentials and other users are refused
+    #[tokio::test]
+    async fn test_export_by_role() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        let (pavel, charlie) = (legacy_user_id(1).to_string(), legacy_user_id(2).to_string());
+        repository.update(&pavel, UserUpdate { role: Some(UserRole::Admin), ..Default::default() }).await.unwrap();
+        let tokens = HashMap::from([("pavel-token".to_string(), pavel), ("charlie-token".to_string(), charlie)]);
+        for token in [Some("secret".to_string()), None] {
+            let route = export_route(repository.clone(), token, Authenticator::new(tokens.clone()));
+            let send = |authorization: &str| warp::test::request().path("/admin/export").header("authorization", authorization).reply(&route);
+            assert_eq!(send("Bearer pavel-token").await.status(), StatusCode::OK);
+            let response = send("Bearer charlie-token").await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            assert_eq!(response.body().as_ref(), br#"{"error":"only admins can export users"}"#);
+        }
+    }
+}
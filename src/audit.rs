@@ -0,0 +1,237 @@
+// Import necessary libraries and modules
+use async_graphql::{Context, ErrorExtensions, Json, Object, Result, ID};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::loader::UserDataLoader;
+use crate::model::User;
+use crate::node::{global_id, USER};
+use crate::repository::RepositoryResult;
+use crate::scalars::Timestamp;
+
+// Define how many entries the in-memory audit store keeps before it drops the oldest ones
+pub const DEFAULT_AUDIT_CAPACITY: usize = 10_000;
+
+// Define the fields left out of diffs, since every write changes them and the entry has its own timestamp
+const UNAUDITED_FIELDS: [&str; 2] = ["version", "updated_at"];
+
+// Define one recorded mutation: who made it, what it did and to which object, when, and what changed
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    pub id: u64,
+    // The user whose bearer token made the request, or None for anonymous requests
+    pub actor_id: Option<String>,
+    // The name of the mutation, such as `updateUser`
+    pub action: String,
+    // The global ID of the object the mutation changed, if it changed one
+    pub target_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    // An object with the `before` and `after` values of every changed field
+    pub diff: serde_json::Value,
+}
+
+// Implement GraphQL Object for the AuditEntry struct
+#[Object]
+impl AuditEntry {
+    async fn id(&self) -> ID {
+        ID(self.id.to_string())
+    }
+
+    async fn actor_id(&self) -> Option<ID> {
+        self.actor_id.as_deref().map(|actor_id| global_id(USER, actor_id))
+    }
+
+    // Resolve the actor through the user loader; null for anonymous requests and deleted users
+    async fn actor(&self, ctx: &Context<'_>) -> Result<Option<User>> {
+        let Some(actor_id) = &self.actor_id else {
+            return Ok(None);
+        };
+        let loader = ctx.data::<UserDataLoader>()?;
+        loader.load_one(actor_id.clone()).await.map_err(|error| error.extend())
+    }
+
+    async fn action(&self) -> &str {
+        &self.action
+    }
+
+    async fn target_id(&self) -> Option<ID> {
+        self.target_id.clone().map(ID)
+    }
+
+    async fn created_at(&self) -> Timestamp {
+        self.created_at.into()
+    }
+
+    async fn diff(&self) -> Json<&serde_json::Value> {
+        Json(&self.diff)
+    }
+}
+
+// Define the fields needed to record an entry; the store assigns its ID and timestamp
+#[derive(Clone, Debug, PartialEq)]
+pub struct NewAuditEntry {
+    pub actor_id: Option<String>,
+    pub action: String,
+    pub target_id: Option<String>,
+    pub diff: serde_json::Value,
+}
+
+// Define the conditions audit entries can be narrowed by; every given condition must hold
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AuditFilter {
+    pub action: Option<String>,
+    pub actor_id: Option<String>,
+    pub target_id: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+impl AuditFilter {
+    // Report whether an entry meets every condition of the filter
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.action.as_ref().is_none_or(|action| entry.action == *action)
+            && self.actor_id.as_ref().is_none_or(|actor_id| entry.actor_id.as_ref() == Some(actor_id))
+            && self.target_id.as_ref().is_none_or(|target_id| entry.target_id.as_ref() == Some(target_id))
+            && self.created_after.is_none_or(|after| entry.created_at > after)
+            && self.created_before.is_none_or(|before| entry.created_at < before)
+    }
+}
+
+// Define the operations the audit log needs from wherever its entries are kept
+#[async_trait]
+pub trait AuditStore: Send + Sync {
+    // Store an entry and return it with its ID and timestamp
+    async fn record(&self, entry: NewAuditEntry) -> RepositoryResult<AuditEntry>;
+
+    // Return up to `limit` matching entries, newest first, starting below the entry with ID `before` if given
+    async fn list(&self, filter: &AuditFilter, before: Option<u64>, limit: usize) -> RepositoryResult<Vec<AuditEntry>>;
+}
+
+// Define a shared, thread-safe handle to the audit store
+pub type SharedAuditStore = Arc<dyn AuditStore>;
+
+// Define an audit store that keeps the most recent entries in memory; they are lost when the server stops
+pub struct InMemoryAuditStore {
+    capacity: usize,
+    state: Mutex<AuditState>,
+}
+
+// Define the entries kept so far, oldest first, and the ID the next one gets
+struct AuditState {
+    entries: VecDeque<AuditEntry>,
+    next_id: u64,
+}
+
+impl InMemoryAuditStore {
+    // Create a store that keeps at most `capacity` entries
+    pub fn new(capacity: usize) -> Self {
+        InMemoryAuditStore { capacity, state: Mutex::new(AuditState { entries: VecDeque::new(), next_id: 1 }) }
+    }
+}
+
+impl Default for InMemoryAuditStore {
+    fn default() -> Self {
+        InMemoryAuditStore::new(DEFAULT_AUDIT_CAPACITY)
+    }
+}
+
+#[async_trait]
+impl AuditStore for InMemoryAuditStore {
+    async fn record(&self, entry: NewAuditEntry) -> RepositoryResult<AuditEntry> {
+        let mut state = self.state.lock().unwrap();
+        let entry = AuditEntry {
+            id: state.next_id,
+            actor_id: entry.actor_id,
+            action: entry.action,
+            target_id: entry.target_id,
+            created_at: Utc::now(),
+            diff: entry.diff,
+        };
+        state.next_id += 1;
+        if state.entries.len() == self.capacity {
+            state.entries.pop_front();
+        }
+        state.entries.push_back(entry.clone());
+        Ok(entry)
+    }
+
+    async fn list(&self, filter: &AuditFilter, before: Option<u64>, limit: usize) -> RepositoryResult<Vec<AuditEntry>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| before.is_none_or(|before| entry.id < before) && filter.matches(entry))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+}
+
+// Compare an object before and after a mutation, returning the `before` and `after` values of every
+// top-level field that differs. A created object has no before and a removed one no after, so all of
+// their fields are listed, with null on the missing side
+pub fn diff<T: Serialize>(before: Option<&T>, after: Option<&T>) -> serde_json::Value {
+    let fields = |value: Option<&T>| match value.map(serde_json::to_value) {
+        Some(Ok(serde_json::Value::Object(fields))) => fields,
+        _ => serde_json::Map::new(),
+    };
+    let (before, after) = (fields(before), fields(after));
+    let mut changes = serde_json::Map::new();
+    for name in before.keys().chain(after.keys().filter(|name| !before.contains_key(*name))) {
+        let (old, new) = (before.get(name), after.get(name));
+        if old != new && !UNAUDITED_FIELDS.contains(&name.as_str()) {
+            let change = serde_json::json!({ "before": old.cloned().unwrap_or_default(), "after": new.cloned().unwrap_or_default() });
+            changes.insert(name.clone(), change);
+        }
+    }
+    serde_json::Value::Object(changes)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build an entry to record
+    fn entry(action: &str, actor_id: Option<&str>) -> NewAuditEntry {
+        NewAuditEntry { actor_id: actor_id.map(str::to_string), action: action.to_string(), target_id: None, diff: serde_json::json!({}) }
+    }
+
+    // Define a test that entries are listed newest first, filtered, paged, and dropped past the capacity
+    #[tokio::test]
+    async fn test_in_memory_audit_store() {
+        let store = InMemoryAuditStore::new(3);
+        for (action, actor_id) in [("createUser", Some("1")), ("updateUser", None), ("updateUser", Some("1")), ("deleteUser", Some("2"))] {
+            store.record(entry(action, actor_id)).await.unwrap();
+        }
+        let ids = |entries: Vec<AuditEntry>| entries.into_iter().map(|entry| entry.id).collect::<Vec<_>>();
+
+        // The oldest entry was dropped to stay within the capacity
+        assert_eq!(ids(store.list(&AuditFilter::default(), None, 10).await.unwrap()), [4, 3, 2]);
+        assert_eq!(ids(store.list(&AuditFilter::default(), Some(4), 1).await.unwrap()), [3]);
+
+        let filter = AuditFilter { action: Some("updateUser".to_string()), ..Default::default() };
+        assert_eq!(ids(store.list(&filter, None, 10).await.unwrap()), [3, 2]);
+        let filter = AuditFilter { actor_id: Some("1".to_string()), ..Default::default() };
+        assert_eq!(ids(store.list(&filter, None, 10).await.unwrap()), [3]);
+        let filter = AuditFilter { created_after: Some(Utc::now()), ..Default::default() };
+        assert_eq!(ids(store.list(&filter, None, 10).await.unwrap()), Vec::<u64>::new());
+    }
+
+    // Define a test that diffs list only the fields that changed
+    #[test]
+    fn test_diff() {
+        let before = serde_json::json!({ "name": "Ada", "email": "ada@example.com", "version": 1 });
+        let after = serde_json::json!({ "name": "Ada L", "email": "ada@example.com", "version": 2 });
+        assert_eq!(diff(Some(&before), Some(&after)), serde_json::json!({ "name": { "before": "Ada", "after": "Ada L" } }));
+        assert_eq!(
+            diff(None, Some(&before)),
+            serde_json::json!({ "name": { "before": null, "after": "Ada" }, "email": { "before": null, "after": "ada@example.com" } })
+        );
+        assert_eq!(diff(Some(&before), Some(&before)), serde_json::json!({}));
+    }
+}
@@ -0,0 +1,764 @@
+// Import necessary libraries and modules
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use warp::http::HeaderMap;
+use warp::hyper::body::Bytes;
+use warp::{Filter, Rejection};
+
+use crate::cache::CacheStore;
+use crate::config::Settings;
+use crate::csrf::{csrf_token, CsrfCheck, CsrfPolicy, CSRF_COOKIE, CSRF_HEADER};
+use crate::jwt::{JwtVerifier, TokenClaims};
+use crate::log_error;
+use crate::model::{parse_user_id, ApiKey};
+use crate::rate_limit::{client_ip, peer_addr, trusted_proxies_from_settings};
+use crate::repository::{RepositoryError, RepositoryResult, SharedRepository};
+use crate::signing::{parse_signing_clients, verify_signature, Signature, SigningClient, CLIENT_ID_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+
+// Define the header machine clients send their API key in
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+// Define the cookie browsers send their session token in once they have logged in
+pub const SESSION_COOKIE: &str = "session";
+
+// Define how long a session lasts when SESSION_TTL_SECONDS is not set
+pub const DEFAULT_SESSION_TTL: Duration = Duration::hours(24);
+
+// Define who a GraphQL request was made by, injected into the request data by the GraphQL route
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Viewer {
+    // No credentials were sent
+    #[default]
+    Anonymous,
+    // Credentials were sent but are not valid
+    InvalidCredentials,
+    // A bearer token bound to the user with this ID was sent
+    User(String),
+}
+
+// Define the client certificate the TLS connection of a request was made with, once verified against the
+// client CA; the TLS server passes it to the routes as a request extension
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientCertificate {
+    // The certificate's subject as an RFC 4514 string, such as CN=billing,O=Example
+    pub subject: String,
+}
+
+// Define what the GraphQL route knows about the sender of a request, injected into the request data:
+// the viewer and, when a JWT, an API key, a session cookie, or a signature was sent, the claims it was
+// verified with, the key itself, the session token, or the ID of the client that signed it, along with
+// the outcome of the CSRF check, the client certificate of the connection, if it presented one, and the
+// address of the client, when it is known
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AuthContext {
+    pub viewer: Viewer,
+    pub claims: Option<TokenClaims>,
+    pub api_key: Option<ApiKey>,
+    pub session: Option<String>,
+    pub signing_client: Option<String>,
+    pub csrf: CsrfCheck,
+    pub client_certificate: Option<ClientCertificate>,
+    pub client_ip: Option<IpAddr>,
+}
+
+impl AuthContext {
+    // Return the ID of the user who sent the request, if valid credentials were sent
+    pub fn principal(&self) -> Option<&str> {
+        match &self.viewer {
+            Viewer::User(user_id) => Some(user_id),
+            _ => None,
+        }
+    }
+}
+
+impl From<Viewer> for AuthContext {
+    fn from(viewer: Viewer) -> Self {
+        AuthContext { viewer, ..Default::default() }
+    }
+}
+
+// Define the credentials a request can carry, as read from its headers and, for signed requests, its body
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Credentials<'a> {
+    pub authorization: Option<&'a str>,
+    pub api_key: Option<&'a str>,
+    pub session: Option<&'a str>,
+    pub csrf_token: Option<&'a str>,
+    pub client_certificate: Option<&'a ClientCertificate>,
+    pub signature: Option<Signature<'a>>,
+}
+
+// Define the sessions browsers have established by logging in, by session token. They are kept in
+// memory, where they are lost when the server restarts, unless a shared store such as Redis is given
+#[derive(Clone)]
+pub struct Sessions {
+    ttl: Duration,
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+    store: Option<Arc<dyn CacheStore>>,
+}
+
+// Define one session: the user it belongs to and when it ends
+struct Session {
+    user_id: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl Sessions {
+    // Create a store whose sessions last for `ttl`
+    pub fn new(ttl: Duration) -> Self {
+        Sessions { ttl, sessions: Arc::default(), store: None }
+    }
+
+    // Keep sessions in the given store instead, which expires them itself; only a hash of each token is
+    // used as its key
+    pub fn with_store(mut self, store: Arc<dyn CacheStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    // Read SESSION_TTL_SECONDS (default a day)
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        match settings.var("SESSION_TTL_SECONDS") {
+            Some(value) => match value.parse() {
+                Ok(seconds) if seconds > 0 => Ok(Sessions::new(Duration::seconds(seconds))),
+                _ => Err(format!("SESSION_TTL_SECONDS must be a positive number, got {:?}", value)),
+            },
+            None => Ok(Sessions::default()),
+        }
+    }
+
+    // Return how long sessions last
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    // Start a session for a user and return its token, dropping any in-memory sessions that have expired
+    pub async fn create(&self, user_id: &str) -> RepositoryResult<String> {
+        let token = random_token();
+        if let Some(store) = &self.store {
+            let ttl = self.ttl.to_std().map_err(|_| RepositoryError::Config("Sessions must last a positive time".to_string()))?;
+            store.set(&session_key(&token), user_id.to_string(), ttl).await?;
+            return Ok(token);
+        }
+        let now = Utc::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(token.clone(), Session { user_id: user_id.to_string(), expires_at: now + self.ttl });
+        Ok(token)
+    }
+
+    // Return the user a session token belongs to, if the session exists and has not expired
+    pub async fn user_id(&self, token: &str) -> RepositoryResult<Option<String>> {
+        if let Some(store) = &self.store {
+            return store.get(&session_key(token)).await;
+        }
+        let sessions = self.sessions.lock().unwrap();
+        Ok(sessions.get(token).filter(|session| session.expires_at > Utc::now()).map(|session| session.user_id.clone()))
+    }
+
+    // End a session, if it exists
+    pub async fn end(&self, token: &str) -> RepositoryResult<()> {
+        if let Some(store) = &self.store {
+            return store.delete(&session_key(token)).await;
+        }
+        self.sessions.lock().unwrap().remove(token);
+        Ok(())
+    }
+
+    // Return the Set-Cookie values that hand a session token and its CSRF token to the browser. The
+    // session cookie is out of reach of scripts, and neither is sent with cross-site requests other than
+    // top-level navigations
+    pub fn cookies(&self, token: &str) -> [String; 2] {
+        let max_age = self.ttl.num_seconds();
+        [
+            format!("{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax", SESSION_COOKIE, token, max_age),
+            format!("{}={}; Path=/; Max-Age={}; SameSite=Lax", CSRF_COOKIE, csrf_token(token), max_age),
+        ]
+    }
+
+    // Return the Set-Cookie values that remove both cookies from the browser
+    pub fn removal_cookies(&self) -> [String; 2] {
+        [format!("{}=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax", SESSION_COOKIE), format!("{}=; Path=/; Max-Age=0; SameSite=Lax", CSRF_COOKIE)]
+    }
+}
+
+// Key a session in a shared store by the hash of its token
+fn session_key(token: &str) -> String {
+    format!("session:{}", hash_api_key(token))
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Sessions::new(DEFAULT_SESSION_TTL)
+    }
+}
+
+// Define the credentials the server accepts: opaque bearer tokens bound to users, JWTs when a verifier
+// is configured, API keys when there is a repository to look them up in, session cookies when there is
+// a session store, client certificates whose subjects are bound to users, and request bodies signed
+// with the secret of a configured API client
+#[derive(Clone, Default)]
+pub struct Authenticator {
+    tokens: HashMap<String, String>,
+    client_certificates: HashMap<String, String>,
+    signing_clients: HashMap<String, SigningClient>,
+    jwt: Option<JwtVerifier>,
+    api_keys: Option<SharedRepository>,
+    sessions: Option<Sessions>,
+    csrf: CsrfPolicy,
+    trusted_proxies: Vec<IpAddr>,
+}
+
+impl Authenticator {
+    // Accept the given tokens, each bound to a user ID
+    pub fn new(tokens: HashMap<String, String>) -> Self {
+        Authenticator { tokens, ..Default::default() }
+    }
+
+    // Also accept client certificates with the given subjects, each bound to a user ID
+    pub fn with_client_certificates(mut self, client_certificates: HashMap<String, String>) -> Self {
+        self.client_certificates = client_certificates;
+        self
+    }
+
+    // Also accept requests signed with the secrets of the given API clients, by client ID
+    pub fn with_signing_clients(mut self, signing_clients: HashMap<String, SigningClient>) -> Self {
+        self.signing_clients = signing_clients;
+        self
+    }
+
+    // Also accept session cookies for the sessions in the store
+    pub fn with_sessions(mut self, sessions: Sessions) -> Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
+    // Decide which requests need the CSRF header with the given policy; without sessions none do
+    pub fn with_csrf_policy(mut self, policy: CsrfPolicy) -> Self {
+        self.csrf = policy;
+        self
+    }
+
+    // Also accept API keys stored in the repository, sent by the user each key was created for
+    pub fn with_api_keys(mut self, repository: SharedRepository) -> Self {
+        self.api_keys = Some(repository);
+        self
+    }
+
+    // Work out client addresses believing X-Forwarded-For from the given proxies
+    pub fn with_trusted_proxies(mut self, trusted_proxies: Vec<IpAddr>) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    // Also accept JWTs the verifier can verify, sent by the user their `sub` claim names
+    pub fn with_jwt(mut self, verifier: JwtVerifier) -> Self {
+        self.jwt = Some(verifier);
+        self
+    }
+
+    // Read tokens from USER_TOKENS, a comma-separated list of `token=user ID` entries, client certificate
+    // subjects from CLIENT_CERT_USERS, a JSON object of `"subject": "user ID"` entries, signing clients
+    // from SIGNING_CLIENTS (see `parse_signing_clients`), and JWT keys from the JWT_* settings; without
+    // them no token, certificate, or signature is accepted. Client addresses are worked out with the
+    // proxies in RATE_LIMIT_TRUSTED_PROXIES
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        let mut authenticator = match settings.var("USER_TOKENS") {
+            Some(value) => parse_user_tokens(&value).map(Authenticator::new)?,
+            None => Authenticator::default(),
+        }
+        .with_trusted_proxies(trusted_proxies_from_settings(settings)?);
+        if let Some(value) = settings.var("CLIENT_CERT_USERS") {
+            authenticator = authenticator.with_client_certificates(parse_client_certificate_users(&value)?);
+        }
+        if let Some(value) = settings.var("SIGNING_CLIENTS") {
+            authenticator = authenticator.with_signing_clients(parse_signing_clients(&value)?);
+        }
+        Ok(match JwtVerifier::from_settings(settings)? {
+            Some(verifier) => authenticator.with_jwt(verifier),
+            None => authenticator,
+        })
+    }
+
+    // Work out who sent a request from the credentials in its headers and the client certificate of its
+    // connection, recording the client address from its peer address and X-Forwarded-For header; for
+    // servers that read requests themselves as well as the `auth_context` filter
+    pub async fn authenticate_headers(&self, headers: &HeaderMap, client_certificate: Option<&ClientCertificate>, peer: Option<SocketAddr>) -> AuthContext {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let credentials = Credentials {
+            authorization: header("authorization"),
+            api_key: header(API_KEY_HEADER),
+            session: cookie(headers, SESSION_COOKIE),
+            csrf_token: header(CSRF_HEADER),
+            client_certificate,
+            signature: None,
+        };
+        let mut auth = self.authenticate(credentials).await;
+        auth.client_ip = peer.map(|peer| client_ip(&self.trusted_proxies, peer.ip(), header("x-forwarded-for")));
+        auth
+    }
+
+    // Work out who sent a request from its credentials. A signature takes precedence over everything else,
+    // an Authorization header over an API key, an API key over a session cookie, and a session cookie over
+    // a client certificate, which only authenticates requests when its subject is bound to a user. When
+    // sessions are enabled, the CSRF header is checked as well
+    pub async fn authenticate(&self, credentials: Credentials<'_>) -> AuthContext {
+        let mut auth = match credentials {
+            Credentials { signature: Some(signature), .. } => self.authenticate_signature(signature),
+            Credentials { authorization: None, api_key: Some(api_key), .. } => self.authenticate_api_key(api_key).await,
+            Credentials { authorization: None, session: Some(session), .. } => self.authenticate_session(session).await,
+            Credentials { authorization: None, client_certificate: Some(certificate), .. } => self.authenticate_client_certificate(certificate),
+            Credentials { authorization, .. } => self.authenticate_bearer(authorization),
+        };
+        auth.client_certificate = credentials.client_certificate.cloned();
+        if self.sessions.is_some() {
+            let token_request = credentials.authorization.is_some() || credentials.api_key.is_some();
+            auth.csrf = self.csrf.check(credentials.csrf_token, auth.session.as_deref(), token_request);
+        }
+        auth
+    }
+
+    // Check a request was signed recently with the secret of the client it names, and act as that client's
+    // user; unknown clients, missing or stale timestamps, and signatures that do not match count as invalid
+    // credentials
+    fn authenticate_signature(&self, signature: Signature<'_>) -> AuthContext {
+        let Some((client_id, client)) = signature.client_id.and_then(|client_id| self.signing_clients.get_key_value(client_id)) else {
+            return Viewer::InvalidCredentials.into();
+        };
+        let Some(timestamp) = signature.timestamp else {
+            return Viewer::InvalidCredentials.into();
+        };
+        if !verify_signature(client.secret.as_bytes(), timestamp, signature.body, signature.signature, Utc::now().timestamp()) {
+            return Viewer::InvalidCredentials.into();
+        }
+        AuthContext { viewer: Viewer::User(client.user_id.clone()), signing_client: Some(client_id.clone()), ..Default::default() }
+    }
+
+    // Find the user a client certificate's subject is bound to; other certificates leave the request
+    // anonymous, since the TLS server has already verified them
+    fn authenticate_client_certificate(&self, certificate: &ClientCertificate) -> AuthContext {
+        match self.client_certificates.get(&certificate.subject) {
+            Some(user_id) => Viewer::User(user_id.clone()).into(),
+            None => Viewer::Anonymous.into(),
+        }
+    }
+
+    // Find the user of an unexpired session; lookups that fail count as invalid credentials
+    async fn authenticate_session(&self, token: &str) -> AuthContext {
+        let Some(sessions) = &self.sessions else {
+            return Viewer::InvalidCredentials.into();
+        };
+        match sessions.user_id(token).await {
+            Ok(Some(user_id)) => AuthContext { viewer: Viewer::User(user_id), session: Some(token.to_string()), ..Default::default() },
+            Ok(None) => Viewer::InvalidCredentials.into(),
+            Err(error) => {
+                log_error!("Failed to look up a session: {}", error);
+                Viewer::InvalidCredentials.into()
+            }
+        }
+    }
+
+    // Find the stored key with the hash of the presented one; lookups that fail count as invalid credentials
+    async fn authenticate_api_key(&self, presented: &str) -> AuthContext {
+        let Some(api_keys) = self.api_keys.as_ref().and_then(|repository| repository.api_keys()) else {
+            return Viewer::InvalidCredentials.into();
+        };
+        match api_keys.get_api_key_by_hash(&hash_api_key(presented)).await {
+            Ok(Some(api_key)) => AuthContext { viewer: Viewer::User(api_key.user_id.clone()), api_key: Some(api_key), ..Default::default() },
+            Ok(None) => Viewer::InvalidCredentials.into(),
+            Err(error) => {
+                log_error!("Failed to look up an API key: {}", error);
+                Viewer::InvalidCredentials.into()
+            }
+        }
+    }
+
+    // Check a bearer token. Tokens shaped like a JWT are verified as one when JWTs are accepted; any
+    // other token must be one of the opaque tokens
+    fn authenticate_bearer(&self, authorization: Option<&str>) -> AuthContext {
+        let Some(authorization) = authorization else {
+            return Viewer::Anonymous.into();
+        };
+        let Some(presented) = authorization.strip_prefix("Bearer ") else {
+            return Viewer::InvalidCredentials.into();
+        };
+        if let Some(verifier) = self.jwt.as_ref().filter(|_| presented.split('.').count() == 3) {
+            return match verifier.verify(presented) {
+                Ok(claims) => AuthContext { viewer: Viewer::User(claims.subject.clone()), claims: Some(claims), ..Default::default() },
+                Err(_) => Viewer::InvalidCredentials.into(),
+            };
+        }
+        // Compare against every token, so the time taken does not reveal which ones exist
+        let mut viewer = Viewer::InvalidCredentials;
+        for (token, user_id) in &self.tokens {
+            if tokens_match(presented, token) {
+                viewer = Viewer::User(user_id.clone());
+            }
+        }
+        viewer.into()
+    }
+}
+
+// Compare a presented token with an expected one without leaking how much of it matched
+pub(crate) fn tokens_match(presented: &str, token: &str) -> bool {
+    presented.len() == token.len() && presented.bytes().zip(token.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+// Generate a new API key from 32 random bytes
+pub fn generate_api_key() -> String {
+    format!("key_{}", random_token())
+}
+
+// Generate a new refresh token from 32 random bytes; like API keys, only its hash is stored
+pub fn generate_refresh_token() -> String {
+    format!("refresh_{}", random_token())
+}
+
+// Generate a random token from 32 random bytes, base64url-encoded
+pub(crate) fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+// Hash an API key or refresh token as it is stored and looked up, as a lowercase hex string. Both are
+// random and long, so a plain SHA-256 is enough to keep them from being recovered
+pub fn hash_api_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Hash a password with Argon2id and a random salt, in the PHC string format that records the parameters
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default().hash_password(password.as_bytes(), &salt).map(|hash| hash.to_string()).map_err(|error| error.to_string())
+}
+
+// Check a password against a stored hash; a hash that cannot be read matches nothing
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+// Find a cookie in a request's Cookie headers
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all("cookie")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+// Parse `token=user ID` entries; user IDs are read like user ID arguments, so numeric legacy IDs work too
+fn parse_user_tokens(value: &str) -> Result<HashMap<String, String>, String> {
+    let mut tokens = HashMap::new();
+    for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let Some((token, user_id)) = entry.split_once('=') else {
+            return Err("USER_TOKENS entries must be of the form token=user ID".to_string());
+        };
+        if token.trim().is_empty() {
+            return Err("USER_TOKENS entries need a non-empty token".to_string());
+        }
+        let Some(uuid) = parse_user_id(user_id.trim()) else {
+            return Err(format!("{:?} is not a valid user ID", user_id.trim()));
+        };
+        if tokens.insert(token.trim().to_string(), uuid.to_string()).is_some() {
+            return Err("USER_TOKENS lists a token more than once".to_string());
+        }
+    }
+    Ok(tokens)
+}
+
+// Parse a JSON object of `"subject": "user ID"` entries, as given in CLIENT_CERT_USERS; subjects are
+// RFC 4514 strings, and user IDs are read like user ID arguments
+fn parse_client_certificate_users(value: &str) -> Result<HashMap<String, String>, String> {
+    let entries: HashMap<String, String> = serde_json::from_str(value).map_err(|_| "CLIENT_CERT_USERS must be a JSON object of \"subject\": \"user ID\" entries".to_string())?;
+    entries
+        .into_iter()
+        .map(|(subject, user_id)| match parse_user_id(user_id.trim()) {
+            Some(uuid) => Ok((subject, uuid.to_string())),
+            None => Err(format!("{:?} is not a valid user ID", user_id.trim())),
+        })
+        .collect()
+}
+
+// Build a filter that extracts the auth context of a request from its Authorization, X-Api-Key, and
+// X-CSRF-Token headers, its session cookie, and the client certificate of its connection, along with
+// the client address from its peer address and X-Forwarded-For header
+pub fn auth_context(authenticator: Authenticator) -> impl Filter<Extract = (AuthContext,), Error = Infallible> + Clone {
+    let client_certificate = warp::ext::optional::<ClientCertificate>();
+    warp::header::headers_cloned().and(client_certificate).and(peer_addr()).then(move |headers: HeaderMap, client_certificate: Option<ClientCertificate>, peer: Option<SocketAddr>| {
+        let authenticator = authenticator.clone();
+        async move { authenticator.authenticate_headers(&headers, client_certificate.as_ref(), peer).await }
+    })
+}
+
+// Build a filter for requests carrying an X-Signature header that reads their body, which it hands on, to
+// check the signature over X-Signature-Timestamp and the body with the client named in X-Client-Id. Signed requests are server-to-server, so no
+// other credentials are read from them; requests without the header are rejected, so they can fall through
+// to the `auth_context` route
+pub fn signed_auth_context(authenticator: Authenticator) -> impl Filter<Extract = (AuthContext, Bytes), Error = Rejection> + Clone {
+    let client = peer_addr().and(warp::header::optional::<String>("x-forwarded-for"));
+    let signed = warp::header::<String>(SIGNATURE_HEADER).and(warp::header::optional::<String>(CLIENT_ID_HEADER)).and(warp::header::optional::<String>(TIMESTAMP_HEADER));
+    signed.and(warp::body::bytes()).and(client).then(
+        move |signature: String, client_id: Option<String>, timestamp: Option<String>, body: Bytes, peer: Option<SocketAddr>, forwarded_for: Option<String>| {
+            let authenticator = authenticator.clone();
+            async move {
+                let signature = Signature { client_id: client_id.as_deref(), signature: &signature, timestamp: timestamp.as_deref(), body: &body };
+                let mut auth = authenticator.authenticate(Credentials { signature: Some(signature), ..Default::default() }).await;
+                auth.client_ip = peer.map(|peer| client_ip(&authenticator.trusted_proxies, peer.ip(), forwarded_for.as_deref()));
+                (auth, body)
+            }
+        },
+    )
+    .untuple_one()
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::tests::MapCache;
+    use crate::jwt::tests::{sign, TEST_RSA_PUBLIC_KEY};
+    use crate::model::{legacy_user_id, NewApiKey};
+    use crate::repository::InMemoryRepository;
+
+    // Define a test for telling requests apart by their credentials
+    #[tokio::test]
+    async fn test_viewer_filter() {
+        let tokens = parse_user_tokens("secret=00000000-0000-0000-0000-000000000001, other=2").unwrap();
+        let filter = auth_context(Authenticator::new(tokens));
+        let viewer_for = |authorization: Option<&'static str>| {
+            let filter = filter.clone();
+            async move {
+                let request = warp::test::request();
+                let request = match authorization {
+                    Some(authorization) => request.header("authorization", authorization),
+                    None => request,
+                };
+                request.filter(&filter).await.unwrap().viewer
+            }
+        };
+
+        assert_eq!(viewer_for(None).await, Viewer::Anonymous);
+        assert_eq!(viewer_for(Some("Bearer secret")).await, Viewer::User("00000000-0000-0000-0000-000000000001".to_string()));
+        assert_eq!(viewer_for(Some("Bearer other")).await, Viewer::User("00000000-0000-0000-0000-000000000002".to_string()));
+        assert_eq!(viewer_for(Some("Bearer secrets")).await, Viewer::InvalidCredentials);
+        assert_eq!(viewer_for(Some("Basic secret")).await, Viewer::InvalidCredentials);
+    }
+
+    // Define a test that JWTs are verified alongside opaque tokens, carrying their claims into the auth context
+    #[tokio::test]
+    async fn test_jwt_authentication() {
+        let verifier = JwtVerifier::default().with_secret(b"shared-secret").with_rsa_pem(TEST_RSA_PUBLIC_KEY.as_bytes()).unwrap();
+        let filter = auth_context(Authenticator::new(parse_user_tokens("secret=1").unwrap()).with_jwt(verifier));
+        let context_for = |token: String| {
+            let filter = filter.clone();
+            async move { warp::test::request().header("authorization", format!("Bearer {}", token)).filter(&filter).await.unwrap() }
+        };
+        let exp = chrono::Utc::now().timestamp() + 600;
+
+        let context = context_for(sign(serde_json::json!({ "sub": "2", "exp": exp }), Some(b"shared-secret"), None)).await;
+        assert_eq!(context.principal(), Some("00000000-0000-0000-0000-000000000002"));
+        assert_eq!(context.claims.unwrap().expires_at.timestamp(), exp);
+        let context = context_for(sign(serde_json::json!({ "sub": "3", "exp": exp }), None, None)).await;
+        assert_eq!(context.principal(), Some("00000000-0000-0000-0000-000000000003"));
+
+        // Opaque tokens still work, without claims
+        assert_eq!(context_for("secret".to_string()).await, Viewer::User("00000000-0000-0000-0000-000000000001".to_string()).into());
+
+        // Expired tokens and ones signed with another key are invalid credentials
+        let context = context_for(sign(serde_json::json!({ "sub": "2", "exp": exp - 1200 }), Some(b"shared-secret"), None)).await;
+        assert_eq!(context, Viewer::InvalidCredentials.into());
+        let context = context_for(sign(serde_json::json!({ "sub": "2", "exp": exp }), Some(b"other-secret"), None)).await;
+        assert_eq!(context, Viewer::InvalidCredentials.into());
+
+        // Without a verifier, JWTs are compared against the opaque tokens and match none of them
+        let filter = auth_context(Authenticator::new(parse_user_tokens("secret=1").unwrap()));
+        let token = sign(serde_json::json!({ "sub": "2", "exp": exp }), Some(b"shared-secret"), None);
+        let context = warp::test::request().header("authorization", format!("Bearer {}", token)).filter(&filter).await.unwrap();
+        assert_eq!(context, Viewer::InvalidCredentials.into());
+    }
+
+    // Define a test that API keys are looked up by their hash, and only when no bearer token is sent
+    #[tokio::test]
+    async fn test_api_key_authentication() {
+        let repository: SharedRepository = std::sync::Arc::new(InMemoryRepository::with_sample_users());
+        let key = generate_api_key();
+        let new_api_key = NewApiKey { name: "CI".to_string(), user_id: legacy_user_id(2).to_string(), key_hash: hash_api_key(&key) };
+        let api_key = repository.api_keys().unwrap().create_api_key(new_api_key).await.unwrap();
+        let filter = auth_context(Authenticator::new(parse_user_tokens("secret=1").unwrap()).with_api_keys(repository));
+        let context_for = |headers: &[(&str, &str)]| {
+            let mut request = warp::test::request();
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            let filter = filter.clone();
+            async move { request.filter(&filter).await.unwrap() }
+        };
+
+        let context = context_for(&[("x-api-key", &key)]).await;
+        assert_eq!(context.principal(), Some(api_key.user_id.as_str()));
+        assert_eq!(context.api_key, Some(api_key));
+        assert_eq!(context_for(&[("x-api-key", "key_unknown")]).await, Viewer::InvalidCredentials.into());
+
+        // A bearer token takes precedence over an API key
+        let context = context_for(&[("authorization", "Bearer secret"), ("x-api-key", &key)]).await;
+        assert_eq!(context, Viewer::User(legacy_user_id(1).to_string()).into());
+
+        // Without a repository, API keys are not accepted
+        let request = warp::test::request().header("x-api-key", &key);
+        let context = request.filter(&auth_context(Authenticator::default())).await.unwrap();
+        assert_eq!(context, Viewer::InvalidCredentials.into());
+    }
+
+    // Define a test that session cookies are accepted until their session expires
+    #[tokio::test]
+    async fn test_session_authentication() {
+        let sessions = Sessions::default();
+        let token = sessions.create(&legacy_user_id(2).to_string()).await.unwrap();
+        let expired = Sessions::new(Duration::seconds(-1));
+        let stale = expired.create(&legacy_user_id(2).to_string()).await.unwrap();
+        let context_for = |authenticator: Authenticator, headers: Vec<(&'static str, String)>| async move {
+            let mut request = warp::test::request();
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            request.filter(&auth_context(authenticator)).await.unwrap()
+        };
+        let authenticator = Authenticator::new(parse_user_tokens("secret=1").unwrap()).with_sessions(sessions.clone());
+
+        let context = context_for(authenticator.clone(), vec![("cookie", format!("theme=dark; session={}", token))]).await;
+        assert_eq!((context.viewer, context.session), (Viewer::User(legacy_user_id(2).to_string()), Some(token.clone())));
+        assert_eq!(context_for(authenticator.clone(), vec![("cookie", "session=unknown".to_string())]).await, Viewer::InvalidCredentials.into());
+        let context = context_for(authenticator.with_sessions(expired), vec![("cookie", format!("session={}", stale))]).await;
+        assert_eq!(context, Viewer::InvalidCredentials.into());
+
+        // A bearer token takes precedence over the session
+        let authenticator = Authenticator::new(parse_user_tokens("secret=1").unwrap()).with_sessions(sessions);
+        let headers = vec![("authorization", "Bearer secret".to_string()), ("cookie", format!("session={}", token))];
+        assert_eq!(context_for(authenticator, headers).await, Viewer::User(legacy_user_id(1).to_string()).into());
+    }
+
+    // Define a test that client certificates authenticate as the user their subject is bound to, and are
+    // carried into the auth context either way
+    #[tokio::test]
+    async fn test_client_certificate_authentication() {
+        let users = parse_client_certificate_users(r#"{ "CN=billing,O=Example": "2" }"#).unwrap();
+        let authenticator = Authenticator::new(parse_user_tokens("secret=1").unwrap()).with_client_certificates(users);
+        let context_for = |subject: &str, authorization: Option<&str>| {
+            let mut request = warp::test::request().extension(ClientCertificate { subject: subject.to_string() });
+            if let Some(authorization) = authorization {
+                request = request.header("authorization", authorization);
+            }
+            let filter = auth_context(authenticator.clone());
+            async move { request.filter(&filter).await.unwrap() }
+        };
+        let billing = Some(ClientCertificate { subject: "CN=billing,O=Example".to_string() });
+        let context = context_for("CN=billing,O=Example", None).await;
+        assert_eq!((context.viewer, context.client_certificate), (Viewer::User(legacy_user_id(2).to_string()), billing.clone()));
+        assert_eq!(context_for("CN=other", None).await.viewer, Viewer::Anonymous);
+
+        // A bearer token takes precedence over the certificate
+        let context = context_for("CN=billing,O=Example", Some("Bearer secret")).await;
+        assert_eq!((context.viewer, context.client_certificate), (Viewer::User(legacy_user_id(1).to_string()), billing));
+
+        assert!(parse_client_certificate_users("CN=billing=2").is_err());
+        assert_eq!(parse_client_certificate_users(r#"{ "CN=billing": "bob" }"#).unwrap_err(), "\"bob\" is not a valid user ID");
+    }
+
+    // Define a test that signed requests authenticate as their client's user only when the body matches the
+    // signature, and that requests without one are left to the other route
+    #[tokio::test]
+    async fn test_signed_requests() {
+        let clients = crate::signing::parse_signing_clients(r#"{ "billing": { "secret": "s3cret", "userId": "2" } }"#).unwrap();
+        let filter = signed_auth_context(Authenticator::new(parse_user_tokens("secret=1").unwrap()).with_signing_clients(clients));
+        let (body, now) = (r#"{"query":"{ users { id } }"}"#, Utc::now().timestamp());
+        let request = |client_id: &str, signature: &str, body: &str| {
+            let request = warp::test::request().method("POST").header(CLIENT_ID_HEADER, client_id).header(SIGNATURE_HEADER, signature);
+            request.header(TIMESTAMP_HEADER, now).body(body)
+        };
+
+        let signature = crate::signing::sign_body(b"s3cret", now, body.as_bytes());
+        let (context, received) = request("billing", &signature, body).filter(&filter).await.unwrap();
+        assert_eq!((context.viewer, context.signing_client.as_deref()), (Viewer::User(legacy_user_id(2).to_string()), Some("billing")));
+        assert_eq!(&received[..], body.as_bytes());
+
+        // Another body, another client, another secret, and bearer tokens alongside the signature are no good,
+        // and neither is a request signed too long ago or sent without its timestamp
+        let stale = now - crate::signing::SIGNATURE_WINDOW_SECS - 60;
+        let cases = [
+            request("billing", &signature, r#"{"query":"{ user(id: 1) { id } }"}"#),
+            request("payroll", &signature, body),
+            request("billing", &crate::signing::sign_body(b"other", now, body.as_bytes()), body),
+            request("billing", "sha256=00", body).header("authorization", "Bearer secret"),
+            request("billing", &crate::signing::sign_body(b"s3cret", stale, body.as_bytes()), body).header(TIMESTAMP_HEADER, stale),
+            warp::test::request().method("POST").header(CLIENT_ID_HEADER, "billing").header(SIGNATURE_HEADER, &signature).body(body),
+        ];
+        for case in cases {
+            assert_eq!(case.filter(&filter).await.unwrap().0, Viewer::InvalidCredentials.into());
+        }
+        assert!(warp::test::request().method("POST").body(body).filter(&filter).await.is_err());
+    }
+
+    // Define a test that sessions kept in a shared store are found, end, and are keyed by a hash of their token
+    #[tokio::test]
+    async fn test_stored_sessions() {
+        let store = Arc::new(MapCache::default());
+        let sessions = Sessions::default().with_store(store.clone());
+        let user_id = legacy_user_id(2).to_string();
+        let token = sessions.create(&user_id).await.unwrap();
+        assert_eq!(sessions.user_id(&token).await.unwrap().as_deref(), Some(user_id.as_str()));
+        assert_eq!(store.get(&format!("session:{}", hash_api_key(&token))).await.unwrap().as_deref(), Some(user_id.as_str()));
+        assert_eq!(store.get(&format!("session:{}", token)).await.unwrap(), None);
+
+        // Sessions in the store are shared by every server that uses it
+        let elsewhere = Sessions::default().with_store(store);
+        assert_eq!(elsewhere.user_id(&token).await.unwrap().as_deref(), Some(user_id.as_str()));
+        elsewhere.end(&token).await.unwrap();
+        assert_eq!(sessions.user_id(&token).await.unwrap(), None);
+        assert_eq!(sessions.user_id("unknown").await.unwrap(), None);
+    }
+
+    // Define a test that generated API keys are distinct and hash to different values
+    #[test]
+    fn test_generate_api_key() {
+        let (first, second) = (generate_api_key(), generate_api_key());
+        assert!(first.starts_with("key_") && first.len() == 47);
+        assert_ne!(first, second);
+        assert_eq!(hash_api_key(&first), hash_api_key(&first));
+        assert_ne!(hash_api_key(&first), hash_api_key(&second));
+        assert_eq!(hash_api_key("").len(), 64);
+    }
+
+    // Define a test that passwords are hashed with a fresh salt and only match the password they were made from
+    #[test]
+    fn test_hash_password() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert_ne!(hash, hash_password("correct horse").unwrap());
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("wrong horse", &hash));
+        assert!(!verify_password("correct horse", "not a hash"));
+    }
+
+    // Define a test that malformed token configuration is rejected
+    #[test]
+    fn test_parse_user_tokens() {
+        assert_eq!(parse_user_tokens("").unwrap(), HashMap::new());
+        assert_eq!(parse_user_tokens("secret").unwrap_err(), "USER_TOKENS entries must be of the form token=user ID");
+        assert_eq!(parse_user_tokens(" =1").unwrap_err(), "USER_TOKENS entries need a non-empty token");
+        assert_eq!(parse_user_tokens("secret=bob").unwrap_err(), "\"bob\" is not a valid user ID");
+        assert_eq!(parse_user_tokens("secret=1,secret=2").unwrap_err(), "USER_TOKENS lists a token more than once");
+    }
+}
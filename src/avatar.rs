@@ -0,0 +1,479 @@
+// Import necessary libraries and modules
+use async_trait::async_trait;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use warp::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use warp::http::{Response, StatusCode};
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::Settings;
+use crate::log_error;
+use crate::repository::{RepositoryError, RepositoryResult};
+
+// Define the largest upload accepted when AVATAR_MAX_BYTES is not set
+const DEFAULT_MAX_BYTES: usize = 2 * 1024 * 1024;
+
+// Define how long signed URLs stay valid when AVATAR_URL_TTL_SECS is not set
+const DEFAULT_URL_TTL_SECS: u64 = 900;
+
+// Define the address local avatar URLs point at when AVATAR_BASE_URL is not set
+const DEFAULT_BASE_URL: &str = "http://127.0.0.1:3030";
+
+// Define the image formats accepted as avatars and the extensions they are stored with
+const IMAGE_TYPES: [(&str, &str); 4] = [
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+];
+
+// Define the reasons an avatar upload is refused
+#[derive(Debug, thiserror::Error)]
+pub enum AvatarError {
+    #[error("avatar is {size} bytes, larger than the limit of {max} bytes")]
+    TooLarge { size: u64, max: usize },
+    #[error("avatar content type {0:?} is not supported; use image/png, image/jpeg, image/gif, or image/webp")]
+    UnsupportedType(String),
+    #[error("avatar content does not look like {0}")]
+    ContentMismatch(String),
+    #[error(transparent)]
+    Store(#[from] RepositoryError),
+}
+
+// Report whether the content starts with the signature of the given image type
+fn has_signature(content_type: &str, content: &[u8]) -> bool {
+    match content_type {
+        "image/png" => content.starts_with(b"\x89PNG\r\n\x1a\n"),
+        "image/jpeg" => content.starts_with(b"\xff\xd8\xff"),
+        "image/gif" => content.starts_with(b"GIF87a") || content.starts_with(b"GIF89a"),
+        "image/webp" => content.len() >= 12 && content.starts_with(b"RIFF") && &content[8..12] == b"WEBP",
+        _ => false,
+    }
+}
+
+// Check an upload against the size limit and the declared content type, returning the file extension to store it under
+pub fn validate_image(content_type: Option<&str>, content: &[u8], max_bytes: usize) -> Result<&'static str, AvatarError> {
+    if content.len() > max_bytes {
+        return Err(AvatarError::TooLarge { size: content.len() as u64, max: max_bytes });
+    }
+    let content_type = content_type.unwrap_or_default();
+    let Some((content_type, extension)) = IMAGE_TYPES.iter().find(|(name, _)| content_type.eq_ignore_ascii_case(name)) else {
+        return Err(AvatarError::UnsupportedType(content_type.to_string()));
+    };
+    if !has_signature(content_type, content) {
+        return Err(AvatarError::ContentMismatch(content_type.to_string()));
+    }
+    Ok(extension)
+}
+
+// Look up the content type of a stored avatar from its extension
+fn content_type_for(key: &str) -> Option<&'static str> {
+    let extension = key.rsplit_once('.')?.1;
+    IMAGE_TYPES.iter().find(|(_, known)| *known == extension).map(|(content_type, _)| *content_type)
+}
+
+// Build a fresh storage key for a user's avatar; anything but letters and digits is dropped from the ID
+fn avatar_key(user_id: &str, extension: &str) -> String {
+    let id: String = user_id.chars().filter(char::is_ascii_alphanumeric).collect();
+    format!("{}-{}.{}", id, Utc::now().timestamp_millis(), extension)
+}
+
+// Report whether a key is one `avatar_key` could have produced, so it is safe to use as a file name
+fn is_valid_key(key: &str) -> bool {
+    !key.starts_with('.') && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+// Define where avatar images are kept and how clients are given access to them
+#[async_trait]
+pub trait AvatarStore: Send + Sync {
+    // Store an image under the given key, replacing any image already there
+    async fn put(&self, key: &str, content: Vec<u8>, content_type: &str) -> RepositoryResult<()>;
+
+    // Remove the image stored under the given key, if any
+    async fn delete(&self, key: &str) -> RepositoryResult<()>;
+
+    // Return a URL that lets anyone holding it fetch the image until it expires
+    async fn signed_url(&self, key: &str, ttl: Duration) -> RepositoryResult<String>;
+}
+
+// Define a shared, thread-safe handle to an avatar store
+pub type SharedAvatarStore = Arc<dyn AvatarStore>;
+
+// Define the avatar service injected into the schema: a store plus the upload limits
+#[derive(Clone)]
+pub struct Avatars {
+    store: SharedAvatarStore,
+    max_bytes: usize,
+    url_ttl: Duration,
+}
+
+impl Avatars {
+    // Accept uploads of up to `max_bytes` into the store, handing out URLs valid for `url_ttl`
+    pub fn new(store: SharedAvatarStore, max_bytes: usize, url_ttl: Duration) -> Self {
+        Avatars { store, max_bytes, url_ttl }
+    }
+
+    // Return the largest upload accepted
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    // Validate and store a new avatar for the user, returning its key
+    pub async fn upload(&self, user_id: &str, content_type: Option<&str>, content: Vec<u8>) -> Result<String, AvatarError> {
+        let extension = validate_image(content_type, &content, self.max_bytes)?;
+        let key = avatar_key(user_id, extension);
+        let content_type = content_type_for(&key).unwrap_or_default();
+        self.store.put(&key, content, content_type).await?;
+        Ok(key)
+    }
+
+    // Remove an avatar that is no longer referenced
+    pub async fn remove(&self, key: &str) -> RepositoryResult<()> {
+        self.store.delete(key).await
+    }
+
+    // Return a signed URL for the avatar with the given key
+    pub async fn url(&self, key: &str) -> RepositoryResult<String> {
+        self.store.signed_url(key, self.url_ttl).await
+    }
+}
+
+// Define a LocalAvatarStore that keeps images in a directory and serves them through `avatar_route`.
+// URLs are signed with HMAC-SHA256 over the key and expiry time, for development without S3
+pub struct LocalAvatarStore {
+    directory: PathBuf,
+    base_url: String,
+    signing_key: Vec<u8>,
+}
+
+impl LocalAvatarStore {
+    // Store images under the given directory, creating it if needed, and link to them from `base_url`
+    pub fn new(directory: impl Into<PathBuf>, base_url: &str, signing_key: Vec<u8>) -> RepositoryResult<Self> {
+        let directory = directory.into();
+        std::fs::create_dir_all(&directory).map_err(|error| {
+            RepositoryError::Config(format!("cannot create avatar directory {}: {}", directory.display(), error))
+        })?;
+        Ok(LocalAvatarStore {
+            directory,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            signing_key,
+        })
+    }
+
+    // Start the HMAC over the signed part of a URL
+    fn mac(&self, key: &str, expires: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.signing_key).expect("HMAC accepts keys of any length");
+        mac.update(format!("{}:{}", key, expires).as_bytes());
+        mac
+    }
+
+    // Sign a key with its expiry time as a lowercase hex string
+    fn signature(&self, key: &str, expires: i64) -> String {
+        self.mac(key, expires).finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    // Check a signature in constant time and that it has not expired
+    fn verify(&self, key: &str, expires: i64, signature: &str) -> bool {
+        let Some(signature) = decode_hex(signature) else {
+            return false;
+        };
+        expires >= Utc::now().timestamp() && self.mac(key, expires).verify_slice(&signature).is_ok()
+    }
+
+    // Read a stored image, returning None when there is none under the key
+    async fn read(&self, key: &str) -> RepositoryResult<Option<Vec<u8>>> {
+        match tokio::fs::read(self.directory.join(key)).await {
+            Ok(content) => Ok(Some(content)),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(RepositoryError::Backend(error.to_string())),
+        }
+    }
+}
+
+// Decode a hex string, returning None if it is malformed
+pub(crate) fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(value.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[async_trait]
+impl AvatarStore for LocalAvatarStore {
+    async fn put(&self, key: &str, content: Vec<u8>, _content_type: &str) -> RepositoryResult<()> {
+        tokio::fs::write(self.directory.join(key), content)
+            .await
+            .map_err(|error| RepositoryError::Backend(format!("failed to store avatar {}: {}", key, error)))
+    }
+
+    async fn delete(&self, key: &str) -> RepositoryResult<()> {
+        match tokio::fs::remove_file(self.directory.join(key)).await {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                Err(RepositoryError::Backend(format!("failed to remove avatar {}: {}", key, error)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn signed_url(&self, key: &str, ttl: Duration) -> RepositoryResult<String> {
+        let expires = Utc::now().timestamp() + ttl.as_secs() as i64;
+        Ok(format!(
+            "{}/avatars/{}?expires={}&signature={}",
+            self.base_url,
+            key,
+            expires,
+            self.signature(key, expires)
+        ))
+    }
+}
+
+// Define an S3AvatarStore that keeps images in a bucket and hands out presigned GET URLs
+#[cfg(feature = "s3")]
+pub struct S3AvatarStore {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    prefix: String,
+}
+
+#[cfg(feature = "s3")]
+impl S3AvatarStore {
+    // Load the AWS configuration from the standard environment and store objects under `prefix` in the bucket
+    pub async fn connect(bucket: &str, prefix: &str) -> Self {
+        let aws_config = aws_config::load_from_env().await;
+        S3AvatarStore {
+            client: aws_sdk_s3::Client::new(&aws_config),
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+        }
+    }
+
+    fn object_key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+// Convert S3 request failures into backend errors with the service's error details
+#[cfg(feature = "s3")]
+fn s3_error<E: std::error::Error + 'static, R: std::fmt::Debug>(error: aws_sdk_s3::error::SdkError<E, R>) -> RepositoryError {
+    RepositoryError::Backend(aws_sdk_s3::error::DisplayErrorContext(error).to_string())
+}
+
+#[cfg(feature = "s3")]
+#[async_trait]
+impl AvatarStore for S3AvatarStore {
+    async fn put(&self, key: &str, content: Vec<u8>, content_type: &str) -> RepositoryResult<()> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.object_key(key))
+            .content_type(content_type)
+            .body(content.into())
+            .send()
+            .await
+            .map_err(s3_error)?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> RepositoryResult<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(self.object_key(key))
+            .send()
+            .await
+            .map_err(s3_error)?;
+        Ok(())
+    }
+
+    async fn signed_url(&self, key: &str, ttl: Duration) -> RepositoryResult<String> {
+        let presigning = aws_sdk_s3::presigning::PresigningConfig::expires_in(ttl)
+            .map_err(|error| RepositoryError::Config(error.to_string()))?;
+        let request = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.object_key(key))
+            .presigned(presigning)
+            .await
+            .map_err(s3_error)?;
+        Ok(request.uri().to_string())
+    }
+}
+
+// Define where avatars are stored
+#[derive(Clone, Debug, PartialEq)]
+pub enum AvatarBackend {
+    Local { directory: PathBuf, base_url: String },
+    S3 { bucket: String, prefix: String },
+}
+
+// Define the avatar settings read from the environment
+#[derive(Clone, Debug)]
+pub struct AvatarConfig {
+    pub backend: AvatarBackend,
+    pub max_bytes: usize,
+    pub url_ttl: Duration,
+}
+
+// Read an optional numeric setting
+fn number_var<T: std::str::FromStr>(settings: &Settings, name: &str, default: T) -> RepositoryResult<T> {
+    match settings.var(name) {
+        Some(value) => value
+            .parse()
+            .map_err(|_| RepositoryError::Config(format!("{} must be a number, got {:?}", name, value))),
+        None => Ok(default),
+    }
+}
+
+impl AvatarConfig {
+    // Read AVATAR_STORE (`s3://<bucket>[/<prefix>]` or `file:<directory>`), AVATAR_MAX_BYTES (default 2 MiB),
+    // AVATAR_URL_TTL_SECS (default 900) and, for local storage, AVATAR_BASE_URL; returns None when it is not set
+    pub fn from_settings(settings: &Settings) -> RepositoryResult<Option<Self>> {
+        let Some(store) = settings.var("AVATAR_STORE") else {
+            return Ok(None);
+        };
+        let backend = if let Some(location) = store.strip_prefix("s3://") {
+            let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+            if bucket.is_empty() {
+                return Err(RepositoryError::Config("AVATAR_STORE must name an S3 bucket".to_string()));
+            }
+            let prefix = match prefix.trim_end_matches('/') {
+                "" => String::new(),
+                prefix => format!("{}/", prefix),
+            };
+            AvatarBackend::S3 { bucket: bucket.to_string(), prefix }
+        } else if let Some(directory) = store.strip_prefix("file:") {
+            let base_url = settings.var("AVATAR_BASE_URL").unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+            AvatarBackend::Local { directory: PathBuf::from(directory), base_url }
+        } else {
+            return Err(RepositoryError::Config(format!(
+                "AVATAR_STORE must start with s3:// or file:, got {:?}",
+                store
+            )));
+        };
+        Ok(Some(AvatarConfig {
+            backend,
+            max_bytes: number_var(settings, "AVATAR_MAX_BYTES", DEFAULT_MAX_BYTES)?,
+            url_ttl: Duration::from_secs(number_var(settings, "AVATAR_URL_TTL_SECS", DEFAULT_URL_TTL_SECS)?),
+        }))
+    }
+}
+
+// Read the key local URLs are signed with from AVATAR_SIGNING_KEY. Without one a random key is used,
+// so links handed out before a restart stop working
+pub fn signing_key_from_settings(settings: &Settings) -> Vec<u8> {
+    match settings.var("AVATAR_SIGNING_KEY") {
+        Some(key) if !key.is_empty() => key.into_bytes(),
+        _ => {
+            use std::hash::{BuildHasher, Hasher};
+            (0..4)
+                .flat_map(|_| std::collections::hash_map::RandomState::new().build_hasher().finish().to_le_bytes())
+                .collect()
+        }
+    }
+}
+
+// Build the GET /avatars/<key> route serving images from a local store to holders of a signed URL.
+// Without a local store every request falls through to the other routes
+pub fn avatar_route(
+    store: Option<Arc<LocalAvatarStore>>,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+    warp::path!("avatars" / String)
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(move |key: String, query: HashMap<String, String>| {
+            let store = store.clone();
+            async move {
+                let Some(store) = store else {
+                    return Err(warp::reject::not_found());
+                };
+                Ok(avatar_response(&store, &key, &query).await)
+            }
+        })
+}
+
+// Check the signature and serve the image
+async fn avatar_response(store: &LocalAvatarStore, key: &str, query: &HashMap<String, String>) -> warp::reply::Response {
+    let status = |code: StatusCode| warp::reply::with_status(warp::reply(), code).into_response();
+    let expires = query.get("expires").and_then(|expires| expires.parse().ok());
+    let signature = query.get("signature");
+    let (Some(expires), Some(signature)) = (expires, signature) else {
+        return status(StatusCode::FORBIDDEN);
+    };
+    if !is_valid_key(key) || !store.verify(key, expires, signature) {
+        return status(StatusCode::FORBIDDEN);
+    }
+    let content = match store.read(key).await {
+        Ok(Some(content)) => content,
+        Ok(None) => return status(StatusCode::NOT_FOUND),
+        Err(error) => {
+            log_error!("Failed to read avatar {}: {}", key, error);
+            return status(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    Response::builder()
+        .header(CONTENT_TYPE, content_type_for(key).unwrap_or("application/octet-stream"))
+        .header(CACHE_CONTROL, "private, max-age=300")
+        .body(content.into())
+        .unwrap_or_else(|_| status(StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    // Define a test for size and content-type validation
+    #[test]
+    fn test_validate_image() {
+        assert_eq!(validate_image(Some("image/png"), PNG, 1024).unwrap(), "png");
+        assert_eq!(validate_image(Some("IMAGE/JPEG"), b"\xff\xd8\xff\xe0", 1024).unwrap(), "jpg");
+        assert_eq!(validate_image(Some("image/webp"), b"RIFF\0\0\0\0WEBPVP8 ", 1024).unwrap(), "webp");
+
+        assert!(matches!(validate_image(Some("image/png"), PNG, 8), Err(AvatarError::TooLarge { max: 8, .. })));
+        assert!(matches!(validate_image(Some("image/svg+xml"), b"<svg/>", 1024), Err(AvatarError::UnsupportedType(_))));
+        assert!(matches!(validate_image(None, PNG, 1024), Err(AvatarError::UnsupportedType(_))));
+        assert!(matches!(validate_image(Some("image/gif"), PNG, 1024), Err(AvatarError::ContentMismatch(_))));
+    }
+
+    // Define a test that local avatars are only served to holders of a valid, unexpired signed URL
+    #[tokio::test]
+    async fn test_local_store_signed_urls() {
+        let directory = tempfile::tempdir().unwrap();
+        let store = Arc::new(LocalAvatarStore::new(directory.path(), "http://localhost:3030/", b"secret".to_vec()).unwrap());
+        let avatars = Avatars::new(store.clone(), 1024, Duration::from_secs(60));
+        let key = avatars.upload("../1", Some("image/png"), PNG.to_vec()).await.unwrap();
+        assert!(key.starts_with("1-") && key.ends_with(".png"));
+
+        let url = avatars.url(&key).await.unwrap();
+        let path = url.strip_prefix("http://localhost:3030").unwrap();
+        let route = avatar_route(Some(store.clone()));
+        let response = warp::test::request().path(path).reply(&route).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "image/png");
+        assert_eq!(response.body().as_ref(), PNG);
+
+        // A tampered signature, an expired link, or a missing signature are all refused
+        let tampered = format!("{}0", path.trim_end_matches(|c: char| c.is_ascii_hexdigit()));
+        let expired = format!("/avatars/{}?expires=1&signature={}", key, store.signature(&key, 1));
+        for path in [tampered, expired, format!("/avatars/{}", key)] {
+            let response = warp::test::request().path(&path).reply(&route).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", path);
+        }
+
+        // Removed avatars are gone
+        avatars.remove(&key).await.unwrap();
+        let response = warp::test::request().path(path).reply(&route).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
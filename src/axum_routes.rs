@@ -0,0 +1,272 @@
+// Import necessary libraries and modules
+use async_graphql::http::receive_batch_body;
+use async_graphql_warp::GraphQLBadRequest;
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::Router;
+use futures::TryStreamExt;
+use std::future::Future;
+use std::sync::Arc;
+use warp::http::header::{AsHeaderName, ACCEPT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ORIGIN};
+use warp::http::request::Parts;
+use warp::http::{HeaderMap, Request, StatusCode};
+use warp::hyper::service::service_fn;
+use warp::hyper::Body;
+
+use crate::auth::{AuthContext, Authenticator, ClientCertificate};
+use crate::compression::{compress_response, CompressionConfig};
+use crate::ip_filter::IpFilter;
+use crate::rate_limit::{admit, admit_batch, with_quota, PeerAddr, Quota, RateLimiter};
+use crate::reload::SharedRoutes;
+use crate::request_limits::RequestLimits;
+use crate::schema::AppSchema;
+use crate::signing::SIGNATURE_HEADER;
+use crate::upload::UploadLimits;
+
+// Define the GraphQL endpoint as axum serves it, for deployments that wrap the server in tower
+// middleware. POST requests to /graphql are answered with the same schema, authentication, rate limits,
+// request limits, compression, IP filter, and CORS settings as the warp routes, which answer every other
+// request, including signed ones and WebSocket subscriptions
+#[derive(Clone)]
+pub struct AxumRoutes {
+    schema: AppSchema,
+    authenticator: Authenticator,
+    rate_limiter: Option<RateLimiter>,
+    request_limits: RequestLimits,
+    upload_limits: UploadLimits,
+    compression: Option<CompressionConfig>,
+    ip_filter: Option<IpFilter>,
+}
+
+impl AxumRoutes {
+    // Serve the schema to the requests the authenticator lets through, with the default request and
+    // upload limits, and without rate limits, compression, or an IP filter
+    pub fn new(schema: AppSchema, authenticator: Authenticator) -> Self {
+        AxumRoutes {
+            schema,
+            authenticator,
+            rate_limiter: None,
+            request_limits: RequestLimits::default(),
+            upload_limits: UploadLimits::default(),
+            compression: None,
+            ip_filter: None,
+        }
+    }
+
+    // Count requests against the limiter's buckets
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    // Hold requests to other body size limits and timeout
+    pub fn with_request_limits(mut self, request_limits: RequestLimits) -> Self {
+        self.request_limits = request_limits;
+        self
+    }
+
+    // Hold multipart requests to other upload limits
+    pub fn with_upload_limits(mut self, upload_limits: UploadLimits) -> Self {
+        self.upload_limits = upload_limits;
+        self
+    }
+
+    // Gzip large responses for clients that accept it
+    pub fn with_compression(mut self, compression: CompressionConfig) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    // Refuse requests from addresses the filter does not allow
+    pub fn with_ip_filter(mut self, ip_filter: IpFilter) -> Self {
+        self.ip_filter = Some(ip_filter);
+        self
+    }
+
+    // Build the router, which hands the requests it does not answer itself to the routes. Their CORS
+    // settings apply to the requests it answers too, and follow them when a reload replaces them
+    pub fn router(self, routes: SharedRoutes) -> Router {
+        let fallback = {
+            let routes = routes.clone();
+            service_fn(move |request| {
+                let routes = routes.clone();
+                async move { routes.answer(request).await }
+            })
+        };
+        let endpoint = Arc::new(Endpoint { graphql: self, routes });
+        let execute = move |request: Request<Body>| async move { endpoint.execute(request).await };
+        Router::new().route("/graphql", post(execute).fallback_service(fallback.clone())).fallback_service(fallback)
+    }
+}
+
+// Define the handlers of /graphql, with the routes they hand other requests to
+struct Endpoint {
+    graphql: AxumRoutes,
+    routes: SharedRoutes,
+}
+
+impl Endpoint {
+    // Run the operation a POST request sends, charging batches a token for each of their operations.
+    // Signed requests are handed to the routes, which check the signature against the body
+    async fn execute(&self, request: Request<Body>) -> Response {
+        if request.headers().contains_key(SIGNATURE_HEADER) {
+            return self.forward(request).await;
+        }
+        self.cross_origin(request, |request| async move {
+            let (parts, body) = request.into_parts();
+            let graphql = &self.graphql;
+            if let Some(refused) = self.refuse_ip(&parts) {
+                return refused;
+            }
+            let content_type = header(&parts.headers, CONTENT_TYPE);
+            let content_length = header(&parts.headers, CONTENT_LENGTH).and_then(|length| length.trim().parse().ok());
+            if let Some(refused) = graphql.request_limits.refuse_body(graphql.upload_limits, content_type, content_length) {
+                return refused.into_response();
+            }
+            let (auth, quota) = match self.authenticate(&parts).await {
+                Ok(admitted) => admitted,
+                Err(refused) => return refused,
+            };
+            let body = body.map_err(std::io::Error::other).into_async_read();
+            let request = match receive_batch_body(content_type, body, graphql.upload_limits.multipart_options()).await {
+                Ok(request) => request,
+                Err(error) => {
+                    let error = GraphQLBadRequest(error);
+                    return (error.status(), error.to_string()).into_response();
+                }
+            };
+            let peer = parts.extensions.get::<PeerAddr>().map(|peer| peer.0);
+            let operations = request.iter().count();
+            let quota = match admit_batch(graphql.rate_limiter.as_ref(), peer, header(&parts.headers, "x-forwarded-for"), &auth, operations, quota).await {
+                Ok(quota) => quota,
+                Err(limited) => return limited.response().into_response(),
+            };
+            let response = graphql.request_limits.execute_batch(request.data(auth), |request| graphql.schema.execute(request)).await;
+            compress_response(graphql.compression, header(&parts.headers, ACCEPT_ENCODING), with_quota(response, quota)).await.into_response()
+        })
+        .await
+    }
+
+    // Answer a request as the routes would with their CORS settings: requests from pages on origins they
+    // do not allow are refused, and the responses to the others get the CORS headers
+    async fn cross_origin<F: Future<Output = Response>>(&self, request: Request<Body>, answer: impl FnOnce(Request<Body>) -> F) -> Response {
+        let (Some(cors), Some(origin)) = (self.routes.cors(), request.headers().get(ORIGIN).cloned()) else {
+            return answer(request).await;
+        };
+        if !origin.to_str().is_ok_and(|origin| cors.allows_origin(origin)) {
+            return (StatusCode::FORBIDDEN, "CORS request forbidden: origin not allowed").into_response();
+        }
+        let mut response = answer(request).await;
+        cors.add_headers(origin, response.headers_mut());
+        response
+    }
+
+    // Refuse a request from an address the IP filter does not allow with 403, as the routes do
+    fn refuse_ip(&self, parts: &Parts) -> Option<Response> {
+        let ip_filter = self.graphql.ip_filter.as_ref()?;
+        let peer = parts.extensions.get::<PeerAddr>().map(|peer| peer.0);
+        match ip_filter.allows_peer(peer, header(&parts.headers, "x-forwarded-for"), false) {
+            true => None,
+            false => Some((StatusCode::FORBIDDEN, "Forbidden").into_response()),
+        }
+    }
+
+    // Authenticate a request and take a token from its principal's bucket, answering with 429 when there
+    // is none left
+    async fn authenticate(&self, parts: &Parts) -> Result<(AuthContext, Option<Quota>), Response> {
+        let peer = parts.extensions.get::<PeerAddr>().map(|peer| peer.0);
+        let auth = self.graphql.authenticator.authenticate_headers(&parts.headers, parts.extensions.get::<ClientCertificate>(), peer).await;
+        match admit(self.graphql.rate_limiter.as_ref(), peer, header(&parts.headers, "x-forwarded-for"), &auth).await {
+            Ok(quota) => Ok((auth, quota)),
+            Err(limited) => Err(limited.response().into_response()),
+        }
+    }
+
+    // Hand a request to the routes
+    async fn forward(&self, request: Request<Body>) -> Response {
+        let Ok(response) = self.routes.answer(request).await;
+        response.into_response()
+    }
+}
+
+// Read a header as text, if it is set and is text
+fn header(headers: &HeaderMap, name: impl AsHeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Settings;
+    use crate::cors::CorsConfig;
+    use crate::rate_limit::{parse_tiers, RateLimitConfig};
+    use crate::repository::InMemoryRepository;
+    use crate::schema::{build_schema, AppState};
+    use std::collections::HashMap;
+    use warp::Filter;
+
+    // Build the routes in front of warp routes that answer every request with "warp", allowing pages on
+    // one origin, and limiting each client to four requests
+    fn routes() -> SharedRoutes {
+        let schema = build_schema(AppState::new(Arc::new(InMemoryRepository::with_sample_users())));
+        let warp_routes = warp::any().map(|| Box::new("warp") as Box<dyn warp::Reply>).boxed();
+        let cors = CorsConfig::from_settings(&Settings::from_vars([("CORS_ALLOWED_ORIGINS", "https://app.example.com")]).unwrap()).unwrap();
+        let shared = SharedRoutes::new(warp_routes, cors);
+        let limiter = RateLimiter::new(RateLimitConfig { per_minute: 4, burst: 4, trusted_proxies: Vec::new(), tiers: parse_tiers("admin=unlimited").unwrap() });
+        let router = AxumRoutes::new(schema, Authenticator::new(HashMap::new())).with_rate_limiter(limiter).router(shared.clone());
+        shared.with_router(router)
+    }
+
+    // Send a request from a client and return its status, headers, and body
+    async fn send(routes: &SharedRoutes, request: warp::http::request::Builder, body: &str) -> (StatusCode, HeaderMap, String) {
+        let mut request = request.header(CONTENT_LENGTH, body.len()).body(Body::from(body.to_string())).unwrap();
+        request.extensions_mut().insert(PeerAddr("203.0.113.7:4000".parse().unwrap()));
+        let (parts, body) = routes.answer(request).await.unwrap().into_parts();
+        let body = warp::hyper::body::to_bytes(body).await.unwrap();
+        (parts.status, parts.headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    // Define a test that axum runs GraphQL requests with the same limits and CORS settings as the warp
+    // routes, and hands them everything else
+    #[tokio::test]
+    async fn test_axum_routes() {
+        let routes = routes();
+        let query = r#"{"query":"{ __typename }"}"#;
+        let post = || Request::post("/graphql").header(CONTENT_TYPE, "application/json");
+
+        let (status, headers, body) = send(&routes, post().header(ORIGIN, "https://app.example.com"), query).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#""__typename":"QueryRoot""#), "{}", body);
+        assert_eq!(headers["access-control-allow-origin"], "https://app.example.com");
+        assert_eq!(headers["x-ratelimit-remaining"], "3");
+
+        // Batches are answered with an array of responses, and take a token for each operation
+        let (status, headers, body) = send(&routes, post(), &format!("[{},{}]", query, query)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["x-ratelimit-remaining"], "1");
+        assert!(body.starts_with(r#"[{"data":{"__typename":"QueryRoot"}"#), "{}", body);
+
+        // Pages on other origins are refused before they count against the limit
+        let (status, _, _) = send(&routes, post().header(ORIGIN, "https://evil.example.com"), query).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Bodies over the limit are refused before they are read
+        let (status, _, body) = send(&routes, post(), &" ".repeat(RequestLimits::default().max_body_bytes + 1)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(body.contains("PAYLOAD_TOO_LARGE"), "{}", body);
+
+        // Signed requests, other methods and paths, and GET requests, including WebSocket upgrades, are
+        // answered by the warp routes
+        assert_eq!(send(&routes, post().header(SIGNATURE_HEADER, "signature"), query).await.2, "warp");
+        assert_eq!(send(&routes, Request::get("/graphql"), "").await.2, "warp");
+        assert_eq!(send(&routes, Request::options("/graphql"), "").await.2, "warp");
+        assert_eq!(send(&routes, Request::get("/healthz"), "").await.2, "warp");
+
+        let (status, _, body) = send(&routes, post(), "{").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        let (status, headers, _) = send(&routes, post(), query).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(headers.contains_key("retry-after"));
+    }
+}
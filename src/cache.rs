@@ -0,0 +1,533 @@
+// Import necessary libraries and modules
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextRequest};
+use async_graphql::{value, Response};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::Settings;
+use crate::log_warn;
+use crate::model::{NewUser, User, UserFilter, UserOrder, UserStats, UserUpdate};
+use crate::outbox::ChangeEvent;
+use crate::repository::{ApiKeyRepository, OrganizationRepository, PasswordRepository, PoolStats, PostRepository, RefreshTokenRepository, RepositoryError, RepositoryResult, SharedRepository, TwoFactorRepository, UserRepository, UserTransaction};
+use crate::search::UserSearchResult;
+
+// Define the TTL used when CACHE_TTL_SECS is not set
+const DEFAULT_TTL_SECS: u64 = 60;
+
+// Define the key-value operations the cache layer needs from its store
+#[async_trait]
+pub trait CacheStore: Send + Sync {
+    // Read a cached value, if present and not expired
+    async fn get(&self, key: &str) -> RepositoryResult<Option<String>>;
+
+    // Store a value that expires after the given TTL
+    async fn set(&self, key: &str, value: String, ttl: Duration) -> RepositoryResult<()>;
+
+    // Remove a cached value
+    async fn delete(&self, key: &str) -> RepositoryResult<()>;
+
+    // Check that the cache server is reachable
+    async fn ping(&self) -> RepositoryResult<()> {
+        Ok(())
+    }
+}
+
+// Define shared hit/miss counters reported in the response extensions
+#[derive(Clone, Default)]
+pub struct CacheStats {
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl CacheStats {
+    // Return the number of lookups served from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    // Return the number of lookups that fell through to the repository
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+// Define a CachedRepository that reads through and writes through a cache in front of another repository
+pub struct CachedRepository {
+    inner: SharedRepository,
+    cache: Arc<dyn CacheStore>,
+    ttl: Duration,
+    stats: CacheStats,
+}
+
+impl CachedRepository {
+    // Wrap a repository with a cache whose entries expire after `ttl`
+    pub fn new(inner: SharedRepository, cache: Arc<dyn CacheStore>, ttl: Duration) -> Self {
+        CachedRepository {
+            inner,
+            cache,
+            ttl,
+            stats: CacheStats::default(),
+        }
+    }
+
+    // Return the counters updated by this cache
+    pub fn stats(&self) -> CacheStats {
+        self.stats.clone()
+    }
+
+    // Look a user up in the cache, counting the hit or miss.
+    // A cache that cannot be read is treated as a miss so lookups still succeed
+    async fn cached(&self, id: &str) -> Option<User> {
+        match self.cache.get(&cache_key(id)).await {
+            Ok(Some(value)) => {
+                if let Ok(user) = serde_json::from_str(&value) {
+                    self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                    return Some(user);
+                }
+            }
+            Ok(None) => {}
+            Err(error) => log_warn!("Cache read failed, falling back to repository: {}", error),
+        }
+        self.stats.misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    // Store a user in the cache under its ID
+    async fn store(&self, user: &User) -> RepositoryResult<()> {
+        let value = serde_json::to_string(user).map_err(|error| RepositoryError::Backend(error.to_string()))?;
+        self.cache.set(&cache_key(&user.id), value, self.ttl).await
+    }
+}
+
+// Build the cache key for a user ID
+fn cache_key(id: &str) -> String {
+    format!("user:{}", id)
+}
+
+// Implement the repository operations, consulting the cache for single-user lookups
+#[async_trait]
+impl UserRepository for CachedRepository {
+    async fn get(&self, id: &str) -> RepositoryResult<Option<User>> {
+        if let Some(user) = self.cached(id).await {
+            return Ok(Some(user));
+        }
+
+        let user = self.inner.get(id).await?;
+        if let Some(user) = &user {
+            self.store(user).await?;
+        }
+        Ok(user)
+    }
+
+    async fn get_including_deleted(&self, id: &str) -> RepositoryResult<Option<User>> {
+        // Only live users are cached, so deleted ones always come from the repository
+        self.inner.get_including_deleted(id).await
+    }
+
+    async fn get_by_email(&self, email: &str) -> RepositoryResult<Option<User>> {
+        // The cache is keyed by ID, so email lookups go to the repository
+        self.inner.get_by_email(email).await
+    }
+
+    async fn get_many(&self, ids: &[String]) -> RepositoryResult<HashMap<String, User>> {
+        // Serve what the cache has, then load the rest from the repository in one call
+        let mut users = HashMap::new();
+        let mut missing = Vec::new();
+        for id in ids {
+            match self.cached(id).await {
+                Some(user) => {
+                    users.insert(user.id.clone(), user);
+                }
+                None => missing.push(id.clone()),
+            }
+        }
+        if missing.is_empty() {
+            return Ok(users);
+        }
+
+        for user in self.inner.get_many(&missing).await?.into_values() {
+            self.store(&user).await?;
+            users.insert(user.id.clone(), user);
+        }
+        Ok(users)
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<User>> {
+        self.inner.list().await
+    }
+
+    async fn list_including_deleted(&self) -> RepositoryResult<Vec<User>> {
+        self.inner.list_including_deleted().await
+    }
+
+    async fn list_page(&self, filter: &UserFilter, after: Option<&str>, limit: usize) -> RepositoryResult<Vec<User>> {
+        self.inner.list_page(filter, after, limit).await
+    }
+
+    async fn list_range(&self, filter: &UserFilter, order: &[UserOrder], offset: usize, limit: usize) -> RepositoryResult<Vec<User>> {
+        self.inner.list_range(filter, order, offset, limit).await
+    }
+
+    async fn count(&self, filter: &UserFilter) -> RepositoryResult<usize> {
+        self.inner.count(filter).await
+    }
+
+    async fn stats(&self, created_since: DateTime<Utc>) -> RepositoryResult<UserStats> {
+        self.inner.stats(created_since).await
+    }
+
+    async fn search(&self, query: &str, limit: usize, offset: usize) -> RepositoryResult<Vec<UserSearchResult>> {
+        self.inner.search(query, limit, offset).await
+    }
+
+    async fn create(&self, new_user: NewUser) -> RepositoryResult<User> {
+        let user = self.inner.create(new_user).await?;
+        self.store(&user).await?;
+        Ok(user)
+    }
+
+    async fn create_many(&self, new_users: Vec<NewUser>) -> RepositoryResult<Vec<User>> {
+        let users = self.inner.create_many(new_users).await?;
+        for user in &users {
+            self.store(user).await?;
+        }
+        Ok(users)
+    }
+
+    async fn update(&self, id: &str, update: UserUpdate) -> RepositoryResult<Option<User>> {
+        let user = match self.inner.update(id, update).await {
+            // A version conflict means another writer got there first, so the cached copy may be stale
+            Err(error @ RepositoryError::VersionConflict { .. }) => {
+                self.cache.delete(&cache_key(id)).await?;
+                return Err(error);
+            }
+            result => result?,
+        };
+        match &user {
+            Some(user) => self.store(user).await?,
+            None => self.cache.delete(&cache_key(id)).await?,
+        }
+        Ok(user)
+    }
+
+    async fn delete(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let user = self.inner.delete(id).await?;
+        self.cache.delete(&cache_key(id)).await?;
+        Ok(user)
+    }
+
+    async fn restore(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let user = self.inner.restore(id).await?;
+        if let Some(user) = &user {
+            self.store(user).await?;
+        }
+        Ok(user)
+    }
+
+    async fn purge(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let user = self.inner.purge(id).await?;
+        self.cache.delete(&cache_key(id)).await?;
+        Ok(user)
+    }
+
+    // A cache outage would fail every lookup, so readiness needs both the cache and the repository
+    async fn ping(&self) -> RepositoryResult<()> {
+        self.inner.ping().await?;
+        self.cache.ping().await
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        self.inner.pool_stats()
+    }
+
+    async fn close(&self) {
+        self.inner.close().await;
+    }
+
+    async fn pending_migrations(&self) -> RepositoryResult<usize> {
+        self.inner.pending_migrations().await
+    }
+
+    fn posts(&self) -> Option<&dyn PostRepository> {
+        self.inner.posts()
+    }
+
+    fn organizations(&self) -> Option<&dyn OrganizationRepository> {
+        self.inner.organizations()
+    }
+
+    fn api_keys(&self) -> Option<&dyn ApiKeyRepository> {
+        self.inner.api_keys()
+    }
+
+    fn passwords(&self) -> Option<&dyn PasswordRepository> {
+        self.inner.passwords()
+    }
+
+    fn refresh_tokens(&self) -> Option<&dyn RefreshTokenRepository> {
+        self.inner.refresh_tokens()
+    }
+
+    fn two_factor(&self) -> Option<&dyn TwoFactorRepository> {
+        self.inner.two_factor()
+    }
+
+    async fn begin(&self) -> RepositoryResult<Box<dyn UserTransaction>> {
+        Ok(Box::new(CachedTransaction {
+            inner: self.inner.begin().await?,
+            cache: self.cache.clone(),
+            touched: Vec::new(),
+        }))
+    }
+
+    async fn pending_events(&self, limit: usize) -> RepositoryResult<Vec<ChangeEvent>> {
+        self.inner.pending_events(limit).await
+    }
+
+    async fn acknowledge_events(&self, ids: &[i64]) -> RepositoryResult<()> {
+        self.inner.acknowledge_events(ids).await
+    }
+}
+
+// Define a CachedTransaction that invalidates the users it wrote once the transaction commits
+struct CachedTransaction {
+    inner: Box<dyn UserTransaction>,
+    cache: Arc<dyn CacheStore>,
+    touched: Vec<String>,
+}
+
+#[async_trait]
+impl UserTransaction for CachedTransaction {
+    async fn get(&mut self, id: &str) -> RepositoryResult<Option<User>> {
+        self.inner.get(id).await
+    }
+
+    async fn create(&mut self, new_user: NewUser) -> RepositoryResult<User> {
+        let user = self.inner.create(new_user).await?;
+        self.touched.push(user.id.clone());
+        Ok(user)
+    }
+
+    async fn update(&mut self, id: &str, update: UserUpdate) -> RepositoryResult<Option<User>> {
+        self.touched.push(id.to_string());
+        self.inner.update(id, update).await
+    }
+
+    async fn delete(&mut self, id: &str) -> RepositoryResult<Option<User>> {
+        self.touched.push(id.to_string());
+        self.inner.delete(id).await
+    }
+
+    async fn commit(self: Box<Self>) -> RepositoryResult<()> {
+        self.inner.commit().await?;
+        for id in &self.touched {
+            self.cache.delete(&cache_key(id)).await?;
+        }
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> RepositoryResult<()> {
+        self.inner.rollback().await
+    }
+}
+
+// Define a schema extension that adds the cache counters to every response
+pub struct CacheMetrics {
+    stats: CacheStats,
+}
+
+impl CacheMetrics {
+    // Report the given counters
+    pub fn new(stats: CacheStats) -> Self {
+        CacheMetrics { stats }
+    }
+}
+
+impl ExtensionFactory for CacheMetrics {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(CacheMetricsExtension {
+            stats: self.stats.clone(),
+        })
+    }
+}
+
+struct CacheMetricsExtension {
+    stats: CacheStats,
+}
+
+#[async_trait]
+impl Extension for CacheMetricsExtension {
+    async fn request(&self, ctx: &ExtensionContext<'_>, next: NextRequest<'_>) -> Response {
+        let response = next.run(ctx).await;
+        let hits = self.stats.hits();
+        let misses = self.stats.misses();
+        response.extension("cache", value!({ "hits": hits, "misses": misses }))
+    }
+}
+
+// Define the settings used to connect to the cache
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+    pub url: String,
+    pub ttl: Duration,
+}
+
+impl CacheConfig {
+    // Read REDIS_URL and the optional CACHE_TTL_SECS (default 60); returns None when caching is disabled
+    pub fn from_settings(settings: &Settings) -> RepositoryResult<Option<Self>> {
+        let Some(url) = settings.var("REDIS_URL") else {
+            return Ok(None);
+        };
+        let ttl_secs = match settings.var("CACHE_TTL_SECS") {
+            Some(value) => value.parse().map_err(|_| {
+                RepositoryError::Config(format!("CACHE_TTL_SECS must be a number, got {:?}", value))
+            })?,
+            None => DEFAULT_TTL_SECS,
+        };
+        Ok(Some(CacheConfig {
+            url,
+            ttl: Duration::from_secs(ttl_secs),
+        }))
+    }
+}
+
+// Define a RedisCache that stores entries with SET ... EX
+#[cfg(feature = "redis-cache")]
+#[derive(Clone)]
+pub struct RedisCache {
+    connection: redis::aio::ConnectionManager,
+}
+
+#[cfg(feature = "redis-cache")]
+impl RedisCache {
+    // Connect to the Redis server, reconnecting automatically if the connection drops
+    pub async fn connect(url: &str) -> RepositoryResult<Self> {
+        let client = redis::Client::open(url)?;
+        let connection = redis::aio::ConnectionManager::new(client).await?;
+        Ok(RedisCache { connection })
+    }
+}
+
+// Convert Redis errors into backend errors
+#[cfg(feature = "redis-cache")]
+impl From<redis::RedisError> for RepositoryError {
+    fn from(error: redis::RedisError) -> Self {
+        RepositoryError::Backend(error.to_string())
+    }
+}
+
+#[cfg(feature = "redis-cache")]
+#[async_trait]
+impl CacheStore for RedisCache {
+    async fn get(&self, key: &str) -> RepositoryResult<Option<String>> {
+        use redis::AsyncCommands;
+        let mut connection = self.connection.clone();
+        Ok(connection.get(key).await?)
+    }
+
+    async fn set(&self, key: &str, value: String, ttl: Duration) -> RepositoryResult<()> {
+        use redis::AsyncCommands;
+        let mut connection = self.connection.clone();
+        connection.set_ex::<_, _, ()>(key, value, ttl.as_secs().max(1)).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> RepositoryResult<()> {
+        use redis::AsyncCommands;
+        let mut connection = self.connection.clone();
+        connection.del::<_, ()>(key).await?;
+        Ok(())
+    }
+
+    async fn ping(&self) -> RepositoryResult<()> {
+        let mut connection = self.connection.clone();
+        redis::cmd("PING").query_async::<()>(&mut connection).await?;
+        Ok(())
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::model::legacy_user_id;
+    use crate::repository::InMemoryRepository;
+    use crate::schema::{build_schema, AppState};
+    use std::collections::HashMap;
+    use tokio::sync::Mutex;
+
+    // Define a cache store backed by a map, ignoring TTLs
+    #[derive(Default)]
+    pub(crate) struct MapCache {
+        entries: Mutex<HashMap<String, String>>,
+    }
+
+    #[async_trait]
+    impl CacheStore for MapCache {
+        async fn get(&self, key: &str) -> RepositoryResult<Option<String>> {
+            Ok(self.entries.lock().await.get(key).cloned())
+        }
+
+        async fn set(&self, key: &str, value: String, _ttl: Duration) -> RepositoryResult<()> {
+            self.entries.lock().await.insert(key.to_string(), value);
+            Ok(())
+        }
+
+        async fn delete(&self, key: &str) -> RepositoryResult<()> {
+            self.entries.lock().await.remove(key);
+            Ok(())
+        }
+    }
+
+    // Define a test for read-through caching and invalidation on delete
+    #[tokio::test]
+    async fn test_read_through_and_invalidation() {
+        let inner = Arc::new(InMemoryRepository::with_sample_users());
+        let cache = Arc::new(MapCache::default());
+        let repository = CachedRepository::new(inner.clone(), cache.clone(), Duration::from_secs(60));
+        let stats = repository.stats();
+        let pavel = legacy_user_id(1).to_string();
+
+        // The first lookup misses and populates the cache, the second hits
+        let user = repository.get(&pavel).await.unwrap().unwrap();
+        assert_eq!(repository.get(&pavel).await.unwrap(), Some(user));
+        assert_eq!((stats.hits(), stats.misses()), (1, 1));
+        assert!(cache.get(&format!("user:{}", pavel)).await.unwrap().is_some());
+
+        // Updates write the new value through to the cache
+        let update = UserUpdate { name: Some("Pasha".to_string()), ..Default::default() };
+        repository.update(&pavel, update).await.unwrap();
+        assert_eq!(repository.get(&pavel).await.unwrap().unwrap().name, "Pasha");
+        assert_eq!((stats.hits(), stats.misses()), (2, 1));
+
+        // Deletes invalidate the entry so the user is no longer returned
+        repository.delete(&pavel).await.unwrap();
+        assert!(cache.get(&format!("user:{}", pavel)).await.unwrap().is_none());
+        assert_eq!(repository.get(&pavel).await.unwrap(), None);
+        assert_eq!(inner.get(&pavel).await.unwrap(), None);
+
+        // Restoring writes the user back to the cache
+        repository.restore(&pavel).await.unwrap();
+        assert!(cache.get(&format!("user:{}", pavel)).await.unwrap().is_some());
+        assert_eq!(repository.get(&pavel).await.unwrap().unwrap().name, "Pasha");
+    }
+
+    // Define a test for the cache counters in the response extensions
+    #[tokio::test]
+    async fn test_cache_metrics_extension() {
+        let inner = Arc::new(InMemoryRepository::with_sample_users());
+        let repository = CachedRepository::new(inner, Arc::new(MapCache::default()), Duration::from_secs(60));
+        let stats = repository.stats();
+        let schema = build_schema(AppState::new(Arc::new(repository)).with_cache_stats(stats));
+
+        // Query the same user twice and check the counters reported with the second response
+        schema.execute(r#"{ userById(id: "2") { name } }"#).await;
+        let response = schema.execute(r#"{ userById(id: "2") { name } }"#).await;
+        let extensions = serde_json::to_value(&response.extensions).expect("Failed to convert extensions to JSON");
+        assert_eq!(extensions["cache"], serde_json::json!({ "hits": 1, "misses": 1 }));
+    }
+}
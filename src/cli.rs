@@ -0,0 +1,85 @@
+// Import necessary libraries and modules
+use clap::{Args, Parser, Subcommand};
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+// Define the command line: the command, and the config file every command reads its settings from.
+// Without a command the server serves, taking serve's options directly
+#[derive(Clone, Debug, Parser, PartialEq)]
+#[command(name = "rust_graphql_server", version, about = "Serve and maintain the GraphQL API", args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[arg(long, global = true, value_name = "FILE", help = "Read settings from this TOML file instead of config.toml")]
+    pub config: Option<PathBuf>,
+    #[arg(long, hide = true, help = "Apply pending database migrations and exit, as the migrate command does")]
+    pub migrate_only: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub serve: ServeArgs,
+}
+
+impl Cli {
+    // Return the command to run, which is serve unless another is given; `--migrate-only` is read as migrate
+    pub fn into_command(self) -> Command {
+        match (self.command, self.migrate_only) {
+            (Some(command), _) => command,
+            (None, true) => Command::Migrate,
+            (None, false) => Command::Serve(self.serve),
+        }
+    }
+}
+
+// Define what the server was asked to do
+#[derive(Clone, Debug, PartialEq, Subcommand)]
+pub enum Command {
+    #[command(about = "Serve the GraphQL API (the default)")]
+    Serve(ServeArgs),
+    #[command(about = "Apply pending database migrations and exit")]
+    Migrate,
+    #[command(about = "Print the GraphQL schema in SDL")]
+    ExportSchema {
+        #[arg(long, value_name = "FILE", help = "Write the schema to this file instead of stdout")]
+        output: Option<PathBuf>,
+    },
+    #[command(about = "Load the seed file into the database and exit")]
+    Seed {
+        #[arg(long, value_name = "FILE", help = "Load this seed file instead of SEED_FILE or seed.json")]
+        file: Option<PathBuf>,
+        #[arg(long, help = "Skip invalid seed records instead of stopping")]
+        ignore_seed_errors: bool,
+    },
+}
+
+// Define the options of the serve command
+#[derive(Args, Clone, Debug, Default, PartialEq)]
+pub struct ServeArgs {
+    #[arg(long, value_name = "ADDRESS", help = "Listen on this IP address")]
+    pub host: Option<IpAddr>,
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..), help = "Listen on this port")]
+    pub port: Option<u16>,
+    #[arg(long, help = "Skip invalid seed records instead of stopping")]
+    pub ignore_seed_errors: bool,
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    // Read a command line given as one string
+    fn parse(line: &str) -> Result<Command, clap::Error> {
+        Cli::try_parse_from(std::iter::once("rust_graphql_server").chain(line.split_whitespace())).map(Cli::into_command)
+    }
+
+    // Define a test that the command line definition is consistent, serving is the default, and the older
+    // --migrate-only flag still migrates
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+        assert_eq!(parse("").unwrap(), Command::Serve(ServeArgs::default()));
+        assert_eq!(parse("--port 8080").unwrap(), Command::Serve(ServeArgs { port: Some(8080), ..Default::default() }));
+        assert_eq!(parse("--migrate-only").unwrap(), Command::Migrate);
+        assert_eq!(parse("serve --port 0").unwrap_err().kind(), clap::error::ErrorKind::ValueValidation);
+    }
+}
@@ -0,0 +1,120 @@
+// Import necessary libraries and modules
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextRequest, NextValidation};
+use async_graphql::{ErrorExtensions, Pos, Response, ServerError, ValidationResult, Value};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+
+use crate::error::AppError;
+use crate::schema::DEFAULT_MAX_PAGE_SIZE;
+
+// Define the largest cost a query may have when no other budget is configured
+pub const DEFAULT_MAX_QUERY_COST: usize = 10_000;
+
+// Define the response extension the cost of a query and the budget it was checked against are reported in
+pub const QUERY_COST: &str = "cost";
+
+// Define the cost of resolvers that do more than load stored objects: searches rank every match, and
+// stats aggregates over every user
+pub const SEARCH_COST: usize = 10;
+pub const STATS_COST: usize = 10;
+
+// Define the most a single field can cost, so that adding up the costs of a query cannot overflow
+const MAX_FIELD_COST: usize = u32::MAX as usize;
+
+// Return how many items a page argument asks for; pages without one are charged as a full page
+pub fn page_items(first: Option<i32>) -> usize {
+    first.map_or(DEFAULT_MAX_PAGE_SIZE, |first| first.max(0) as usize)
+}
+
+// Compute the cost of a list field: one for the field itself plus the cost of its selection for every
+// item it may return
+pub fn list_cost(items: usize, child_complexity: usize) -> usize {
+    items.saturating_mul(child_complexity).saturating_add(1).min(MAX_FIELD_COST)
+}
+
+// Define the extension that refuses queries costing more than the budget before they run, and reports
+// the cost of every query, refused or not; it is installed on every schema
+pub struct QueryCost {
+    budget: usize,
+}
+
+impl QueryCost {
+    // Refuse queries costing more than the given budget
+    pub fn new(budget: usize) -> Self {
+        QueryCost { budget }
+    }
+}
+
+impl ExtensionFactory for QueryCost {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(QueryCostExtension { budget: self.budget, cost: Mutex::default() })
+    }
+}
+
+// Each request gets its own extension, which remembers the cost computed while validating the query
+struct QueryCostExtension {
+    budget: usize,
+    cost: Mutex<Option<usize>>,
+}
+
+#[async_trait]
+impl Extension for QueryCostExtension {
+    async fn request(&self, ctx: &ExtensionContext<'_>, next: NextRequest<'_>) -> Response {
+        let mut response = next.run(ctx).await;
+        if let Some(cost) = *self.cost.lock().unwrap() {
+            let report = Value::from_json(serde_json::json!({ "cost": cost, "budget": self.budget })).unwrap_or_default();
+            response.extensions.insert(QUERY_COST.to_string(), report);
+        }
+        response
+    }
+
+    async fn validation(&self, ctx: &ExtensionContext<'_>, next: NextValidation<'_>) -> Result<ValidationResult, Vec<ServerError>> {
+        let result = next.run(ctx).await?;
+        *self.cost.lock().unwrap() = Some(result.complexity);
+        if result.complexity > self.budget {
+            let error = AppError::TooComplex { cost: result.complexity, budget: self.budget };
+            return Err(vec![error.extend().into_server_error(Pos::default())]);
+        }
+        Ok(result)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::InMemoryRepository;
+    use crate::schema::tests::sample_schema;
+    use crate::schema::{build_schema, AppState};
+
+    // Define a test that list fields cost their selection once for every item they ask for
+    #[tokio::test]
+    async fn test_query_cost() {
+        let schema = sample_schema();
+        let cost = |query: &'static str| {
+            let schema = schema.clone();
+            async move { schema.execute(query).await.extensions[QUERY_COST].clone().into_json().unwrap()["cost"].clone() }
+        };
+        assert_eq!(cost(r#"{ userById(id: "1") { name } }"#).await, 2);
+        // Five edges of four fields each (edges, node, id, and title), plus posts and userById
+        assert_eq!(cost(r#"{ userById(id: "1") { posts(first: 5) { edges { node { id title } } } } }"#).await, 5 * 4 + 2);
+        assert_eq!(cost(r#"{ userById(id: "1") { posts { edges { node { id } } } } }"#).await, DEFAULT_MAX_PAGE_SIZE as u64 * 3 + 2);
+        assert_eq!(cost("{ users(limit: 3) { users { name } } }").await, 1 + 3 * 2);
+        assert_eq!(cost("{ stats { userCount } }").await, STATS_COST as u64 + 1);
+    }
+
+    // Define a test that queries over the budget are refused with their cost
+    #[tokio::test]
+    async fn test_query_cost_budget() {
+        let schema = build_schema(AppState::new(Arc::new(InMemoryRepository::with_sample_users())).with_max_query_cost(30));
+        let response = schema.execute(r#"{ userById(id: "1") { posts(first: 5) { edges { node { id title } } } } }"#).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+        let response = schema.execute(r#"{ userById(id: "1") { posts(first: 10) { edges { node { id title } } } } }"#).await;
+        assert_eq!(response.data, Value::Null);
+        let error = serde_json::to_value(&response.errors[0]).unwrap();
+        assert_eq!(error["message"], "The query costs 42, which is over the budget of 30");
+        assert_eq!(error["extensions"]["code"], "QUERY_TOO_COMPLEX");
+        assert_eq!(response.extensions[QUERY_COST], Value::from_json(serde_json::json!({ "cost": 42, "budget": 30 })).unwrap());
+    }
+}
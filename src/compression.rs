@@ -0,0 +1,251 @@
+// Import necessary libraries and modules
+use flate2::write::GzEncoder;
+use std::convert::Infallible;
+use std::io::Write;
+use warp::http::header::{HeaderMap, HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use warp::http::StatusCode;
+use warp::hyper::body::{to_bytes, Body};
+use warp::reply::Response;
+use warp::Filter;
+
+use crate::config::Settings;
+use crate::log_error;
+
+// Define the smallest response compressed when COMPRESSION_MIN_BYTES is not set; below it the gzip
+// header and trailer cost more than they save
+pub const DEFAULT_MIN_BYTES: usize = 1024;
+
+// Define when responses are compressed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompressionConfig {
+    pub min_bytes: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig { min_bytes: DEFAULT_MIN_BYTES }
+    }
+}
+
+impl CompressionConfig {
+    // Read COMPRESSION (true or false, default true) and COMPRESSION_MIN_BYTES, the size in bytes below
+    // which responses are sent as they are. Returns None when compression is turned off
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+        let enabled = match settings.var("COMPRESSION").as_deref() {
+            None | Some("true") => true,
+            Some("false") => false,
+            Some(other) => return Err(format!("COMPRESSION must be true or false, got {:?}", other)),
+        };
+        let min_bytes = match settings.var("COMPRESSION_MIN_BYTES") {
+            Some(value) => value.trim().parse().map_err(|_| format!("COMPRESSION_MIN_BYTES must be a number of bytes, got {:?}", value))?,
+            None => DEFAULT_MIN_BYTES,
+        };
+        Ok(Some(CompressionConfig { min_bytes }).filter(|_| enabled))
+    }
+}
+
+// Extract the Accept-Encoding header of a request, if it has one
+pub fn accept_encoding() -> impl Filter<Extract = (Option<String>,), Error = Infallible> + Clone {
+    warp::header::optional::<String>("accept-encoding").or(warp::any().map(|| None)).unify()
+}
+
+// Define the encodings responses can be compressed with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+// Define the brotli quality and window used for responses; the highest qualities compress a little better
+// but are far too slow to run on every response
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW_BITS: u32 = 22;
+
+impl Encoding {
+    // Return the Content-Encoding name of the encoding
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    // Compress bytes with the encoding
+    pub fn encode(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW_BITS);
+                encoder.write_all(data)?;
+                Ok(encoder.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+// Pick the encoding an Accept-Encoding header prefers, by name or through `*`, among those with a q-value
+// above 0; brotli wins ties, since it compresses JSON better
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let (mut brotli, mut gzip, mut any) = (None, None, None);
+    for coding in accept_encoding.split(',') {
+        let mut params = coding.split(';');
+        let name = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let quality = params.find_map(|param| param.trim().strip_prefix("q=")).map_or(1.0, |q| q.trim().parse::<f32>().unwrap_or(0.0));
+        match name.as_str() {
+            "br" => brotli = Some(quality),
+            "gzip" | "x-gzip" => gzip = Some(quality),
+            "*" => any = Some(quality),
+            _ => {}
+        }
+    }
+    let (brotli, gzip) = (brotli.or(any).unwrap_or(0.0), gzip.or(any).unwrap_or(0.0));
+    if brotli > 0.0 && brotli >= gzip {
+        Some(Encoding::Brotli)
+    } else if gzip > 0.0 {
+        Some(Encoding::Gzip)
+    } else {
+        None
+    }
+}
+
+// Tell whether a response is text worth compressing: JSON, which GraphQL responses are, or any text type
+fn compressible(headers: &HeaderMap) -> bool {
+    let content_type = headers.get(CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or_default();
+    let essence = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    essence == "application/json" || essence.ends_with("+json") || essence.starts_with("text/")
+}
+
+// Compress a response with brotli or gzip, whichever the client prefers, when compression is on and the
+// body is JSON or text of at least the configured size that is not already encoded. Compression runs on
+// the blocking pool, off the threads that serve requests. Every response that could be compressed says it
+// varies by Accept-Encoding, so caches keep the forms apart
+pub async fn compress_response(config: Option<CompressionConfig>, accept_encoding: Option<&str>, response: Response) -> Response {
+    let Some(config) = config else {
+        return response;
+    };
+    if response.headers().contains_key(CONTENT_ENCODING) || !compressible(response.headers()) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    parts.headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    let Some(encoding) = accept_encoding.and_then(negotiate) else {
+        return Response::from_parts(parts, body);
+    };
+    let body = match to_bytes(body).await {
+        Ok(body) => body,
+        Err(error) => {
+            log_error!("Failed to read a response to compress: {}", error);
+            parts.status = StatusCode::INTERNAL_SERVER_ERROR;
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    if body.len() < config.min_bytes {
+        return Response::from_parts(parts, Body::from(body));
+    }
+    let compressed = tokio::task::spawn_blocking(move || encoding.encode(&body)).await.map_err(std::io::Error::other);
+    let compressed = match compressed.and_then(|compressed| compressed) {
+        Ok(compressed) => compressed,
+        Err(error) => {
+            log_error!("Failed to compress a response: {}", error);
+            parts.status = StatusCode::INTERNAL_SERVER_ERROR;
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    parts.headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(compressed))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    // Build a JSON list of users like the one a large users query returns
+    fn user_list(count: usize) -> String {
+        let users: Vec<String> = (0..count).map(|id| format!("{{\"id\":\"{}\",\"name\":\"User {}\",\"email\":\"user{}@example.com\"}}", id, id, id)).collect();
+        format!("{{\"data\":{{\"users\":[{}]}}}}", users.join(","))
+    }
+
+    // Decompress a body with the decoder of its encoding
+    fn decode(encoding: &str, body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        match encoding {
+            "gzip" => flate2::read::GzDecoder::new(body).read_to_end(&mut out).unwrap(),
+            "br" => brotli::Decompressor::new(body, 4096).read_to_end(&mut out).unwrap(),
+            other => panic!("unexpected encoding {}", other),
+        };
+        out
+    }
+
+    // Define a test that compressed bytes decompress to the original with standard decoders, and repetitive JSON shrinks
+    #[test]
+    fn test_encode_round_trip() {
+        let users = user_list(1000);
+        for encoding in [Encoding::Brotli, Encoding::Gzip] {
+            for data in [&b""[..], b"a", users.as_bytes()] {
+                assert_eq!(decode(encoding.name(), &encoding.encode(data).unwrap()), data);
+            }
+            let compressed = encoding.encode(users.as_bytes()).unwrap();
+            assert!(compressed.len() * 4 < users.len(), "{}: {} bytes from {}", encoding.name(), compressed.len(), users.len());
+        }
+    }
+
+    // Define a test for picking the encoding a client prefers
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br, gzip, deflate"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("deflate;q=0.5, GZIP;q=0.8, br;q=0.6"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("br;q=0, *"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate("deflate"), None);
+        assert_eq!(negotiate("gzip;q=0"), None);
+        assert_eq!(negotiate("gzip;q=0, br;q=0, *"), None);
+        assert_eq!(negotiate(""), None);
+    }
+
+    // Define a test that JSON responses over the threshold are compressed with the encoding the client prefers
+    #[tokio::test]
+    async fn test_compress_response() {
+        let config = Some(CompressionConfig { min_bytes: 100 });
+        let body = user_list(50);
+        let json = || {
+            let mut response = Response::new(Body::from(body.clone()));
+            response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            response
+        };
+        let read = |response: Response| async { to_bytes(response.into_body()).await.unwrap().to_vec() };
+
+        for (accept_encoding, encoding) in [("gzip, br", "br"), ("gzip", "gzip"), ("br;q=0.5, gzip", "gzip")] {
+            let response = compress_response(config, Some(accept_encoding), json()).await;
+            assert_eq!(response.headers()[CONTENT_ENCODING], encoding);
+            assert_eq!(response.headers()[VARY], "accept-encoding");
+            assert_eq!(decode(encoding, &read(response).await), body.as_bytes());
+        }
+
+        // Clients that accept neither, and turning compression off, leave the body as it was
+        let response = compress_response(config, Some("deflate"), json()).await;
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(response.headers()[VARY], "accept-encoding");
+        assert_eq!(read(response).await, body.as_bytes());
+        assert!(!compress_response(config, None, json()).await.headers().contains_key(CONTENT_ENCODING));
+        assert!(!compress_response(None, Some("gzip"), json()).await.headers().contains_key(CONTENT_ENCODING));
+
+        // Nor are bodies under the threshold, nor ones that are not text
+        let response = compress_response(Some(CompressionConfig { min_bytes: body.len() + 1 }), Some("gzip"), json()).await;
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(read(response).await, body.as_bytes());
+        let mut image = Response::new(Body::from(vec![0; 4096]));
+        image.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
+        let response = compress_response(config, Some("gzip"), image).await;
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert!(!response.headers().contains_key(VARY));
+    }
+}
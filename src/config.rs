@@ -0,0 +1,313 @@
+// Import necessary libraries and modules
+use figment::providers::{Env, Format, Serialized, Toml};
+use figment::value::{Dict, Value};
+use figment::{Figment, Profile, Provider};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::num::{NonZeroU16, NonZeroUsize};
+use std::path::{Path, PathBuf};
+
+// Define the config file read from the working directory when CONFIG_FILE is not set
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+// Define the address and port the server listens on when HOST and PORT are not set
+pub const DEFAULT_HOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+pub const DEFAULT_PORT: u16 = 3030;
+
+// Define where sessions are kept
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStore {
+    #[default]
+    Memory,
+    Redis,
+}
+
+// Define the settings the server starts with, layered from the config file, the environment, and the
+// command line. The ones startup reads itself are typed; the others are kept by name as the text they
+// were set to, for each module to read its own with `from_settings`. Not Debug, since they hold secrets
+#[derive(Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct Settings {
+    #[serde(default = "default_host")]
+    pub host: IpAddr,
+    #[serde(default = "default_port")]
+    pub port: NonZeroU16,
+    pub seed_file: Option<PathBuf>,
+    pub users_max_page_size: Option<NonZeroUsize>,
+    pub max_query_depth: Option<NonZeroUsize>,
+    pub max_query_cost: Option<NonZeroUsize>,
+    pub audit_log_capacity: Option<NonZeroUsize>,
+    #[serde(default)]
+    pub session_store: SessionStore,
+    #[serde(skip)]
+    vars: HashMap<String, String>,
+}
+
+fn default_host() -> IpAddr {
+    DEFAULT_HOST
+}
+
+fn default_port() -> NonZeroU16 {
+    NonZeroU16::new(DEFAULT_PORT).expect("the default port is not 0")
+}
+
+impl Settings {
+    // Read the config file, the one given or else the one CONFIG_FILE names or config.toml, under the
+    // environment, which takes precedence. Layer the command line over them with `Settings::extract`
+    pub fn figment(config: Option<&Path>) -> Result<Figment, String> {
+        let vars = environment();
+        let path = match config {
+            Some(path) if path.is_file() => Some(path.to_path_buf()),
+            Some(path) => return Err(format!("{} does not exist", path.display())),
+            None => config_path(&vars)?,
+        };
+        let file = match path {
+            Some(path) => file_settings(&path)?,
+            None => HashMap::new(),
+        };
+        Ok(Figment::new().merge(Serialized::defaults(resolve_config(&vars, file))).merge(Serialized::defaults(vars)))
+    }
+
+    // Read the settings from the config file and the environment
+    pub fn load(config: Option<&Path>) -> Result<Self, String> {
+        Settings::extract(&Settings::figment(config)?)
+    }
+
+    // Read the settings from their layers. Every value is text, as in the environment, and the typed
+    // settings are read from it
+    pub fn extract(figment: &Figment) -> Result<Self, String> {
+        let invalid = |error: figment::Error| {
+            let errors: Vec<_> = error.into_iter().map(|error| format!("{}: {}", error.path.join("_"), error.kind)).collect();
+            format!("Invalid settings: {}", errors.join("; "))
+        };
+        let mut settings: Settings = figment.extract_lossy().map_err(invalid)?;
+        settings.vars = figment.extract().map_err(invalid)?;
+        Ok(settings)
+    }
+
+    // Read settings given by name, as they would be set in the environment
+    pub fn from_vars<'a>(vars: impl IntoIterator<Item = (&'a str, &'a str)>) -> Result<Self, String> {
+        let vars: HashMap<&str, &str> = vars.into_iter().collect();
+        Settings::extract(&Figment::from(Serialized::defaults(vars)))
+    }
+
+    // Return a setting by name, if it is set
+    pub fn var(&self, name: &str) -> Option<String> {
+        self.vars.get(name).cloned()
+    }
+
+    // Return every setting by name
+    pub fn vars(&self) -> &HashMap<String, String> {
+        &self.vars
+    }
+
+    // Set a setting that is read from elsewhere, such as a secret read from a file or Vault
+    pub fn set(&mut self, name: &str, value: String) {
+        self.vars.insert(name.to_string(), value);
+    }
+}
+
+// Read the environment, keeping every value as the text it was set to; figment's own Env provider would
+// read a value such as 0123 or 1.10 as a number, which would change a secret
+fn environment() -> HashMap<String, String> {
+    Env::raw().lowercase(false).iter().map(|(name, value)| (name.as_str().to_string(), value)).collect()
+}
+
+// Define where the server listens
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ServerConfig {
+    pub address: SocketAddr,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig { address: SocketAddr::new(DEFAULT_HOST, DEFAULT_PORT) }
+    }
+}
+
+impl ServerConfig {
+    // Listen on HOST, an IP address such as 0.0.0.0 to listen on every interface, and PORT, falling back
+    // to 127.0.0.1:3030
+    pub fn from_settings(settings: &Settings) -> Self {
+        ServerConfig { address: SocketAddr::new(settings.host, settings.port.get()) }
+    }
+}
+
+// Find the config file: the one CONFIG_FILE names, which must exist, or config.toml when there is one
+pub fn config_path(vars: &HashMap<String, String>) -> Result<Option<PathBuf>, String> {
+    match vars.get("CONFIG_FILE") {
+        Some(path) if PathBuf::from(path).is_file() => Ok(Some(PathBuf::from(path))),
+        Some(path) => Err(format!("CONFIG_FILE {} does not exist", path)),
+        None => Ok(Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.is_file())),
+    }
+}
+
+// Read the settings in a config file. Keys are named after the environment variables they stand in for,
+// in any case, and keys in tables are prefixed with the table's name, so `port = 8080` sets PORT and `url`
+// under `[database]` sets DATABASE_URL. Numbers and booleans are written as they would be in the
+// environment, and arrays are joined with commas for the settings that take lists
+pub fn parse_config(contents: &str) -> Result<HashMap<String, String>, String> {
+    flatten_toml(Toml::string(contents))
+}
+
+// Read the settings in the config file at the given path
+pub fn file_settings(path: &Path) -> Result<HashMap<String, String>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|error| format!("Failed to read {}: {}", path.display(), error))?;
+    parse_config(&contents).map_err(|error| format!("Invalid config file {}: {}", path.display(), error))
+}
+
+// Read a TOML provider's tables into settings
+fn flatten_toml(toml: impl Provider) -> Result<HashMap<String, String>, String> {
+    let mut data = toml.data().map_err(|error| error.to_string().trim_end().to_string())?;
+    let mut settings = HashMap::new();
+    flatten_table("", &data.remove(&Profile::Default).unwrap_or_default(), &mut settings)?;
+    Ok(settings)
+}
+
+// Add the settings in a table, naming them after its keys with the given prefix
+fn flatten_table(prefix: &str, table: &Dict, settings: &mut HashMap<String, String>) -> Result<(), String> {
+    for (key, value) in table {
+        let name = setting_name(prefix, key)?;
+        let value = match value {
+            Value::Dict(_, table) => {
+                flatten_table(&name, table, settings)?;
+                continue;
+            }
+            Value::Array(_, values) if values.iter().any(|value| matches!(value, Value::Dict(..))) => {
+                return Err(format!("{} is an array of tables, which no setting takes", name));
+            }
+            Value::Array(_, values) => values
+                .iter()
+                .map(|value| scalar(value).ok_or_else(|| format!("{} must be a list of strings, numbers, or booleans", name)))
+                .collect::<Result<Vec<_>, _>>()?
+                .join(","),
+            value => scalar(value).ok_or_else(|| format!("{} must be a string, number, boolean, or list", name))?,
+        };
+        if settings.insert(name.clone(), value).is_some() {
+            return Err(format!("{} is set more than once", name));
+        }
+    }
+    Ok(())
+}
+
+// Write a string, number, or boolean as it would be set in the environment
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(_, value) => Some(value.clone()),
+        Value::Char(_, value) => Some(value.to_string()),
+        Value::Num(..) | Value::Bool(..) => serde_json::to_string(value).ok(),
+        Value::Empty(..) | Value::Array(..) | Value::Dict(..) => None,
+    }
+}
+
+// Name the setting a key stands for under the given prefix, refusing keys no environment variable could have
+fn setting_name(prefix: &str, key: &str) -> Result<String, String> {
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("{:?} is not a setting name; use letters, digits, and underscores", key));
+    }
+    let key = key.to_ascii_uppercase();
+    Ok(if prefix.is_empty() { key } else { format!("{}_{}", prefix, key) })
+}
+
+// Work out the settings from the config file to lay under the environment: the environment takes
+// precedence, so a setting is skipped when the NAME_FILE standing in for it is set there
+pub fn resolve_config(vars: &HashMap<String, String>, file: HashMap<String, String>) -> HashMap<String, String> {
+    file.into_iter().filter(|(name, _)| !vars.contains_key(name) && !vars.contains_key(&format!("{}_FILE", name))).collect()
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build settings from name and value pairs
+    fn settings(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    // Define a test that keys, tables, and values are read as the environment variables they stand in for
+    #[test]
+    fn test_parse_config() {
+        let config = r#"
+            host = "0.0.0.0"
+            port = 8080
+            MAX_QUERY_DEPTH = 10
+            csrf_exempt_token_requests = false
+            cors_allowed_origins = ["https://app.example.com", "https://admin.example.com"]
+            rate_limit = { per_ip = 100 }
+
+            [database]
+            url = "postgres://app@db/app"
+            max_connections = 20
+        "#;
+        assert_eq!(
+            parse_config(config).unwrap(),
+            settings(&[
+                ("HOST", "0.0.0.0"),
+                ("PORT", "8080"),
+                ("MAX_QUERY_DEPTH", "10"),
+                ("CSRF_EXEMPT_TOKEN_REQUESTS", "false"),
+                ("CORS_ALLOWED_ORIGINS", "https://app.example.com,https://admin.example.com"),
+                ("RATE_LIMIT_PER_IP", "100"),
+                ("DATABASE_URL", "postgres://app@db/app"),
+                ("DATABASE_MAX_CONNECTIONS", "20"),
+            ])
+        );
+    }
+
+    // Define a test that malformed files and values no setting takes are refused, saying where
+    #[test]
+    fn test_invalid_config() {
+        assert!(parse_config("port = ").unwrap_err().contains("line 1"));
+        assert!(parse_config("database_url = \"a\"\n[database]\nurl = \"b\"").unwrap_err().contains("DATABASE_URL is set more than once"));
+        assert!(parse_config("cors_allowed_origins = [[\"a\"]]").unwrap_err().contains("CORS_ALLOWED_ORIGINS must be a list"));
+        assert!(parse_config("[[users]]\nname = \"Ada\"").unwrap_err().contains("USERS is an array of tables"));
+        assert!(parse_config("\"max-depth\" = 3").unwrap_err().contains("\"max-depth\" is not a setting name"));
+    }
+
+    // Define a test that the environment takes precedence over the file
+    #[test]
+    fn test_resolve_config() {
+        let vars = settings(&[("PORT", "9000"), ("JWT_SECRET_FILE", "/run/secrets/jwt")]);
+        let file = settings(&[("PORT", "8080"), ("JWT_SECRET", "from-file"), ("HOST", "0.0.0.0")]);
+        assert_eq!(resolve_config(&vars, file), settings(&[("HOST", "0.0.0.0")]));
+    }
+
+    // Define a test that the config file given is read under the environment, and the command line over both
+    #[test]
+    fn test_load_settings() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("server.toml");
+        std::fs::write(&path, "port = 8080\n[rate_limit]\nper_minute = 60").unwrap();
+        let figment = Settings::figment(Some(&path)).unwrap().merge(("PORT", "9000"));
+        let loaded = Settings::extract(&figment).unwrap();
+        assert_eq!(loaded.port.get(), 9000);
+        assert_eq!(loaded.var("RATE_LIMIT_PER_MINUTE").as_deref(), Some("60"));
+
+        std::fs::write(&path, "port = ").unwrap();
+        assert!(Settings::load(Some(&path)).err().unwrap().starts_with(&format!("Invalid config file {}", path.display())));
+        std::fs::remove_file(&path).unwrap();
+        assert!(Settings::load(Some(&path)).err().unwrap().ends_with("does not exist"));
+    }
+
+    // Define a test for reading the typed settings, and keeping the others as the text they were set to
+    #[test]
+    fn test_settings() {
+        let loaded = Settings::from_vars([("HOST", "::1"), ("MAX_QUERY_DEPTH", "10"), ("SESSION_STORE", "redis"), ("JWT_SECRET", "0123")]).unwrap();
+        assert_eq!(ServerConfig::from_settings(&loaded).address, "[::1]:3030".parse().unwrap());
+        assert_eq!(loaded.max_query_depth, NonZeroUsize::new(10));
+        assert_eq!(loaded.max_query_cost, None);
+        assert_eq!(loaded.session_store, SessionStore::Redis);
+        assert_eq!(loaded.var("JWT_SECRET").as_deref(), Some("0123"));
+        assert_eq!(ServerConfig::from_settings(&Settings::from_vars([]).unwrap()), ServerConfig::default());
+
+        let invalid = |name, value| Settings::from_vars([(name, value)]).err().unwrap_or_default();
+        assert!(invalid("HOST", "localhost").contains("HOST"));
+        assert!(invalid("PORT", "0").contains("PORT"));
+        assert!(invalid("PORT", "70000").contains("PORT"));
+        assert!(invalid("MAX_QUERY_DEPTH", "0").contains("MAX_QUERY_DEPTH"));
+        assert!(invalid("SESSION_STORE", "disk").contains("SESSION_STORE"));
+    }
+}
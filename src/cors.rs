@@ -0,0 +1,232 @@
+// Import necessary libraries and modules
+use warp::http::header::{HeaderName, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS, RETRY_AFTER};
+use warp::http::{HeaderMap, Method};
+use warp::{Filter, Reply};
+
+use crate::auth::API_KEY_HEADER;
+use crate::config::Settings;
+use crate::csrf::CSRF_HEADER;
+use crate::rate_limit::{LIMIT_HEADER, REMAINING_HEADER};
+use crate::reload::Routes;
+
+// Define how long browsers may cache a preflight response when CORS_MAX_AGE_SECONDS is not set
+pub const DEFAULT_MAX_AGE_SECONDS: u32 = 600;
+
+// Define the origins pages may call the server from
+#[derive(Clone, Debug, PartialEq)]
+pub enum AllowedOrigins {
+    Any,
+    List(Vec<String>),
+}
+
+// Define which cross-origin requests browsers may make: from which origins, with which methods and
+// request headers, and whether they may send cookies. Responses expose the rate limit headers, so
+// pages can read how much of their quota is left
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorsConfig {
+    pub allowed_origins: AllowedOrigins,
+    pub allowed_methods: Vec<Method>,
+    pub allowed_headers: Vec<HeaderName>,
+    pub allow_credentials: bool,
+    pub max_age_seconds: u32,
+}
+
+impl CorsConfig {
+    // Read CORS_ALLOWED_ORIGINS, a comma-separated list of origins such as https://app.example.com or *
+    // for any, along with CORS_ALLOWED_METHODS (default GET,POST,OPTIONS), CORS_ALLOWED_HEADERS (default
+    // the headers the GraphQL route reads), CORS_ALLOW_CREDENTIALS (true or false, default false), and
+    // CORS_MAX_AGE_SECONDS (default 600). Without origins cross-origin requests are not allowed
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+        let Some(origins) = settings.var("CORS_ALLOWED_ORIGINS") else {
+            return Ok(None);
+        };
+        let allowed_origins = parse_origins(&origins)?;
+        let allowed_methods = match settings.var("CORS_ALLOWED_METHODS") {
+            Some(methods) => list(&methods).map(|method| Method::from_bytes(method.to_uppercase().as_bytes()).map_err(|_| format!("{:?} is not an HTTP method", method))).collect::<Result<_, _>>()?,
+            None => vec![Method::GET, Method::POST, Method::OPTIONS],
+        };
+        let allowed_headers = match settings.var("CORS_ALLOWED_HEADERS") {
+            Some(headers) => list(&headers).map(|header| HeaderName::from_bytes(header.as_bytes()).map_err(|_| format!("{:?} is not a header name", header))).collect::<Result<_, _>>()?,
+            None => default_headers(),
+        };
+        let allow_credentials = match settings.var("CORS_ALLOW_CREDENTIALS").as_deref() {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(other) => return Err(format!("CORS_ALLOW_CREDENTIALS must be true or false, got {:?}", other)),
+        };
+        // Browsers refuse credentials for a wildcard origin, so the two are not combined
+        if allow_credentials && allowed_origins == AllowedOrigins::Any {
+            return Err("CORS_ALLOW_CREDENTIALS needs CORS_ALLOWED_ORIGINS to list origins rather than *".to_string());
+        }
+        let max_age_seconds = match settings.var("CORS_MAX_AGE_SECONDS") {
+            Some(value) => value.parse().map_err(|_| format!("CORS_MAX_AGE_SECONDS must be a number, got {:?}", value))?,
+            None => DEFAULT_MAX_AGE_SECONDS,
+        };
+        Ok(Some(CorsConfig { allowed_origins, allowed_methods, allowed_headers, allow_credentials, max_age_seconds }))
+    }
+
+    // Build the warp wrapper that answers preflight requests and adds the CORS headers to responses;
+    // requests from origins that are not allowed are refused with 403
+    pub fn filter(&self) -> warp::cors::Builder {
+        let builder = match &self.allowed_origins {
+            AllowedOrigins::Any => warp::cors().allow_any_origin(),
+            AllowedOrigins::List(origins) => warp::cors().allow_origins(origins.iter().map(String::as_str)),
+        };
+        builder
+            .allow_methods(self.allowed_methods.clone())
+            .allow_headers(self.allowed_headers.clone())
+            .expose_headers([LIMIT_HEADER.parse::<HeaderName>().unwrap(), REMAINING_HEADER.parse().unwrap(), RETRY_AFTER])
+            .allow_credentials(self.allow_credentials)
+            .max_age(self.max_age_seconds)
+    }
+
+    // Tell whether pages on an origin may call the server
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        match &self.allowed_origins {
+            AllowedOrigins::Any => true,
+            AllowedOrigins::List(origins) => origins.iter().any(|allowed| allowed == origin),
+        }
+    }
+
+    // Add the headers the warp wrapper adds to responses to requests from an allowed origin; for servers
+    // that answer requests themselves
+    pub fn add_headers(&self, origin: HeaderValue, headers: &mut HeaderMap) {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        let exposed = format!("{}, {}, {}", LIMIT_HEADER, REMAINING_HEADER, RETRY_AFTER);
+        headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, HeaderValue::from_str(&exposed).unwrap());
+        if self.allow_credentials {
+            headers.insert(ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+        }
+    }
+}
+
+// Tell whether a WebSocket upgrade sent from a page on the given origin may act for the browser's
+// cookies. Browsers send cookies with upgrades any page makes, and WebSocket connections are not held to
+// CORS, so only pages on the server's own origin or one the CORS settings allow may use them. Upgrades
+// without an Origin were not sent by a browser
+pub fn allows_cookie_origin(cors: Option<&CorsConfig>, origin: Option<&str>, host: Option<&str>) -> bool {
+    let Some(origin) = origin else {
+        return true;
+    };
+    let same_origin = origin.split_once("://").is_some_and(|(_, origin_host)| Some(origin_host) == host);
+    same_origin || cors.is_some_and(|cors| cors.allows_origin(origin))
+}
+
+// Wrap the routes with the CORS settings, if there are any; without them browsers keep pages on other
+// origins from calling the server
+pub fn with_cors(routes: Routes, cors: Option<&CorsConfig>) -> Routes {
+    match cors {
+        Some(cors) => routes.with(cors.filter()).map(|reply| Box::new(reply) as Box<dyn Reply>).boxed(),
+        None => routes,
+    }
+}
+
+// Split a comma-separated list, ignoring blank entries
+fn list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|entry| !entry.is_empty())
+}
+
+// Parse the allowed origins. Each must be a scheme and host, with an optional port, and nothing after;
+// browsers send exactly that in the Origin header
+pub fn parse_origins(value: &str) -> Result<AllowedOrigins, String> {
+    if value.trim() == "*" {
+        return Ok(AllowedOrigins::Any);
+    }
+    let origins = list(value)
+        .map(|origin| {
+            let host = origin.strip_prefix("https://").or_else(|| origin.strip_prefix("http://"));
+            match host {
+                Some(host) if !host.is_empty() && !host.contains('/') => Ok(origin.to_string()),
+                _ => Err(format!("{:?} is not an origin such as https://app.example.com", origin)),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if origins.is_empty() {
+        return Err("CORS_ALLOWED_ORIGINS must list at least one origin".to_string());
+    }
+    Ok(AllowedOrigins::List(origins))
+}
+
+// Return the request headers the GraphQL route reads
+fn default_headers() -> Vec<HeaderName> {
+    ["content-type", "authorization", API_KEY_HEADER, CSRF_HEADER].into_iter().map(|header| header.parse().unwrap()).collect()
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    // Build a config allowing the given origins with the default methods and headers
+    fn config(origins: &str) -> CorsConfig {
+        let allowed_origins = parse_origins(origins).unwrap();
+        CorsConfig { allowed_origins, allowed_methods: vec![Method::GET, Method::POST], allowed_headers: default_headers(), allow_credentials: true, max_age_seconds: 600 }
+    }
+
+    // Define a test that only pages on the server's own origin or an allowed one may use cookies with
+    // WebSocket upgrades
+    #[test]
+    fn test_allows_cookie_origin() {
+        let cors = config("https://app.example.com");
+        let host = Some("api.example.com");
+        assert!(allows_cookie_origin(None, None, host));
+        assert!(allows_cookie_origin(None, Some("https://api.example.com"), host));
+        assert!(!allows_cookie_origin(None, Some("https://evil.example"), host));
+        assert!(!allows_cookie_origin(None, Some("https://app.example.com"), host));
+        assert!(allows_cookie_origin(Some(&cors), Some("https://app.example.com"), host));
+        assert!(!allows_cookie_origin(Some(&cors), Some("https://evil.example"), host));
+        assert!(!allows_cookie_origin(Some(&cors), Some("null"), None));
+    }
+
+    // Define a test for parsing allowed origins
+    #[test]
+    fn test_parse_origins() {
+        assert_eq!(parse_origins(" * "), Ok(AllowedOrigins::Any));
+        let origins = vec!["https://app.example.com".to_string(), "http://localhost:5173".to_string()];
+        assert_eq!(parse_origins("https://app.example.com, http://localhost:5173,"), Ok(AllowedOrigins::List(origins)));
+        assert!(parse_origins("app.example.com").is_err());
+        assert!(parse_origins("https://app.example.com/graphql").is_err());
+        assert!(parse_origins(",").is_err());
+    }
+
+    // Define a test that preflight requests from allowed origins are answered and others refused
+    #[tokio::test]
+    async fn test_cors_preflight() {
+        let route = warp::path("graphql").and(warp::post()).map(|| "ok").with(config("https://app.example.com").filter());
+        let preflight = |origin: &str, method: &str, headers: &str| {
+            warp::test::request()
+                .method("OPTIONS")
+                .path("/graphql")
+                .header("origin", origin)
+                .header("access-control-request-method", method)
+                .header("access-control-request-headers", headers)
+                .reply(&route)
+        };
+        let response = preflight("https://app.example.com", "POST", "content-type, x-csrf-token").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["access-control-allow-origin"], "https://app.example.com");
+        assert_eq!(response.headers()["access-control-allow-credentials"], "true");
+        assert_eq!(response.headers()["access-control-max-age"], "600");
+        assert_eq!(preflight("https://evil.example.com", "POST", "content-type").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(preflight("https://app.example.com", "DELETE", "content-type").await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(preflight("https://app.example.com", "POST", "x-unknown").await.status(), StatusCode::FORBIDDEN);
+
+        // Actual requests get the CORS headers, including the exposed rate limit headers
+        let response = warp::test::request().method("POST").path("/graphql").header("origin", "https://app.example.com").reply(&route).await;
+        assert_eq!(response.body(), "ok");
+        assert_eq!(response.headers()["access-control-allow-origin"], "https://app.example.com");
+        assert!(response.headers()["access-control-expose-headers"].to_str().unwrap().contains(LIMIT_HEADER));
+
+        // Servers answering requests themselves add the same headers
+        let config = config("https://app.example.com");
+        assert!(config.allows_origin("https://app.example.com") && !config.allows_origin("https://evil.example.com"));
+        let mut headers = HeaderMap::new();
+        config.add_headers(HeaderValue::from_static("https://app.example.com"), &mut headers);
+        for name in ["access-control-allow-origin", "access-control-allow-credentials"] {
+            assert_eq!(headers[name], response.headers()[name]);
+        }
+        assert!(headers["access-control-expose-headers"].to_str().unwrap().contains(REMAINING_HEADER));
+    }
+}
@@ -0,0 +1,180 @@
+// Import necessary libraries and modules
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextParseQuery, NextPrepareRequest};
+use async_graphql::parser::types::{ExecutableDocument, OperationType};
+use async_graphql::{ErrorExtensions, Pos, Request, ServerResult, Variables};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+
+use crate::auth::{hash_api_key, tokens_match, AuthContext};
+use crate::config::Settings;
+use crate::error::AppError;
+use crate::http_get::operation_type;
+
+// Define the header clients send the CSRF token in with mutations
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+// Define the cookie the CSRF token of a session is handed to the browser in; unlike the session cookie,
+// scripts on the page can read it so they can copy it into the header
+pub const CSRF_COOKIE: &str = "csrf_token";
+
+// Derive the CSRF token of a session from the session's token. A page on another site can make the
+// browser send the session cookie, but it cannot read either cookie, so it cannot send the header
+pub fn csrf_token(session: &str) -> String {
+    hash_api_key(&format!("csrf:{}", session))
+}
+
+// Define the outcome of a request's CSRF check, which mutations look at
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CsrfCheck {
+    // The request was not authenticated with a session cookie, or is exempt
+    #[default]
+    NotRequired,
+    // The request sent the CSRF header it needed
+    Passed,
+    // The request needed the CSRF header but did not send it, or sent the wrong token
+    Failed,
+}
+
+// Define which requests need the CSRF header: those authenticated with a session cookie always do, and
+// those authenticated with a bearer token or API key only when they are not exempt. Browsers never attach
+// those credentials on their own, which is why they are exempt by default
+#[derive(Clone, Copy, Debug)]
+pub struct CsrfPolicy {
+    pub exempt_token_requests: bool,
+}
+
+impl Default for CsrfPolicy {
+    fn default() -> Self {
+        CsrfPolicy { exempt_token_requests: true }
+    }
+}
+
+impl CsrfPolicy {
+    // Read CSRF_EXEMPT_TOKEN_REQUESTS (true or false, default true)
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        match settings.var("CSRF_EXEMPT_TOKEN_REQUESTS").as_deref() {
+            None | Some("true") => Ok(CsrfPolicy::default()),
+            Some("false") => Ok(CsrfPolicy { exempt_token_requests: false }),
+            Some(other) => Err(format!("CSRF_EXEMPT_TOKEN_REQUESTS must be true or false, got {:?}", other)),
+        }
+    }
+
+    // Check the CSRF header of a request. Session requests must send their session's token; token
+    // requests that are not exempt must send the header, which cross-site pages cannot add
+    pub fn check(&self, header: Option<&str>, session: Option<&str>, token_request: bool) -> CsrfCheck {
+        let passed = if let Some(session) = session {
+            header.is_some_and(|header| tokens_match(header, &csrf_token(session)))
+        } else if token_request && !self.exempt_token_requests {
+            header.is_some_and(|header| !header.trim().is_empty())
+        } else {
+            return CsrfCheck::NotRequired;
+        };
+        if passed {
+            CsrfCheck::Passed
+        } else {
+            CsrfCheck::Failed
+        }
+    }
+}
+
+// Define the extension that refuses mutations from requests that failed their CSRF check; queries are
+// still answered, since they change nothing. It is installed on every schema
+pub struct CsrfProtection;
+
+impl ExtensionFactory for CsrfProtection {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(CsrfProtectionExtension::default())
+    }
+}
+
+// Each request gets its own extension, which remembers the operation the request asked to run
+#[derive(Default)]
+struct CsrfProtectionExtension {
+    operation_name: Mutex<Option<String>>,
+}
+
+#[async_trait]
+impl Extension for CsrfProtectionExtension {
+    async fn prepare_request(&self, ctx: &ExtensionContext<'_>, request: Request, next: NextPrepareRequest<'_>) -> ServerResult<Request> {
+        self.operation_name.lock().unwrap().clone_from(&request.operation_name);
+        next.run(ctx, request).await
+    }
+
+    async fn parse_query(&self, ctx: &ExtensionContext<'_>, query: &str, variables: &Variables, next: NextParseQuery<'_>) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+        let failed = ctx.data_opt::<AuthContext>().is_some_and(|auth| auth.csrf == CsrfCheck::Failed);
+        let operation_name = self.operation_name.lock().unwrap().clone();
+        if failed && operation_type(&document, operation_name.as_deref()) == Some(OperationType::Mutation) {
+            let message = format!("Mutations need the {} header with the token from the {} cookie", CSRF_HEADER, CSRF_COOKIE);
+            return Err(AppError::Forbidden(message).extend().into_server_error(Pos::default()));
+        }
+        Ok(document)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{auth_context, Authenticator, Sessions, Viewer};
+    use crate::schema::tests::{sample_schema, PAVEL};
+
+    // Define a test for which requests pass, fail, or skip the CSRF check
+    #[test]
+    fn test_csrf_check() {
+        let (policy, strict) = (CsrfPolicy::default(), CsrfPolicy { exempt_token_requests: false });
+        let token = csrf_token("session-1");
+        assert_ne!(token, csrf_token("session-2"));
+
+        // Session requests need their own session's token
+        assert_eq!(policy.check(Some(&token), Some("session-1"), false), CsrfCheck::Passed);
+        assert_eq!(policy.check(Some(&token), Some("session-2"), false), CsrfCheck::Failed);
+        assert_eq!(policy.check(None, Some("session-1"), false), CsrfCheck::Failed);
+
+        // Token requests are exempt unless the policy says otherwise, and then need the header
+        assert_eq!(policy.check(None, None, true), CsrfCheck::NotRequired);
+        assert_eq!(strict.check(None, None, true), CsrfCheck::Failed);
+        assert_eq!(strict.check(Some("1"), None, true), CsrfCheck::Passed);
+        assert_eq!(strict.check(None, None, false), CsrfCheck::NotRequired);
+    }
+
+    // Define a test that requests failing the check can still query but not mutate
+    #[tokio::test]
+    async fn test_csrf_protection() {
+        let schema = sample_schema();
+        let auth = |csrf: CsrfCheck| AuthContext { viewer: Viewer::User(PAVEL.to_string()), session: Some("session-1".to_string()), csrf, ..Default::default() };
+        let mutation = r#"mutation Rename { updateUser(id: "1", expectedVersion: 1, input: { name: "Pavel B" }) { name } }"#;
+        let response = schema.execute(Request::new(mutation).data(auth(CsrfCheck::Failed))).await;
+        assert_eq!(response.errors[0].extensions.as_ref().unwrap().get("code"), Some(&async_graphql::Value::from("FORBIDDEN")));
+        assert!(schema.execute(Request::new(mutation).data(auth(CsrfCheck::Passed))).await.is_ok());
+        assert!(schema.execute(Request::new("{ userById(id: \"1\") { name } }").data(auth(CsrfCheck::Failed))).await.is_ok());
+
+        // Only the operation that runs counts
+        let document = format!("query Read {{ userById(id: \"1\") {{ name }} }} {}", mutation);
+        assert!(schema.execute(Request::new(document.clone()).operation_name("Read").data(auth(CsrfCheck::Failed))).await.is_ok());
+        assert!(schema.execute(Request::new(document).operation_name("Rename").data(auth(CsrfCheck::Failed))).await.is_err());
+    }
+
+    // Define a test that the auth filter checks the header against the session cookie's token
+    #[tokio::test]
+    async fn test_csrf_header() {
+        let sessions = Sessions::default();
+        let session = sessions.create(PAVEL).await.unwrap();
+        let authenticator = Authenticator::default().with_sessions(sessions);
+        let check = |headers: Vec<(&'static str, String)>| {
+            let authenticator = authenticator.clone();
+            async move {
+                let mut request = warp::test::request();
+                for (name, value) in headers {
+                    request = request.header(name, value);
+                }
+                request.filter(&auth_context(authenticator)).await.unwrap().csrf
+            }
+        };
+        let cookie = ("cookie", format!("session={}", session));
+        assert_eq!(check(vec![cookie.clone(), (CSRF_HEADER, csrf_token(&session))]).await, CsrfCheck::Passed);
+        assert_eq!(check(vec![cookie.clone(), (CSRF_HEADER, csrf_token("other"))]).await, CsrfCheck::Failed);
+        assert_eq!(check(vec![cookie]).await, CsrfCheck::Failed);
+        assert_eq!(check(vec![("authorization", "Bearer unknown".to_string())]).await, CsrfCheck::NotRequired);
+    }
+}
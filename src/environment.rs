@@ -0,0 +1,129 @@
+// Import necessary libraries and modules
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextResolve, ResolveInfo};
+use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
+use async_graphql::{ErrorExtensions, Pos, ServerResult, Value};
+use async_trait::async_trait;
+use serde_json::json;
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::Settings;
+use crate::error::AppError;
+
+// Define the environment the server is deployed to, which decides whether the developer tools are on
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AppEnv {
+    #[default]
+    Development,
+    Production,
+}
+
+impl AppEnv {
+    // Read APP_ENV (development or production, default development)
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        match settings.var("APP_ENV").as_deref() {
+            None | Some("development") => Ok(AppEnv::Development),
+            Some("production") => Ok(AppEnv::Production),
+            Some(other) => Err(format!("APP_ENV must be development or production, got {:?}", other)),
+        }
+    }
+}
+
+// Define which developer tools are served: introspection queries, which describe the whole schema, and
+// the playground page at GET /graphql
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DevTools {
+    pub introspection: bool,
+    pub playground: bool,
+}
+
+impl Default for DevTools {
+    fn default() -> Self {
+        DevTools::for_env(AppEnv::Development)
+    }
+}
+
+impl DevTools {
+    // Turn the developer tools on in development and off in production
+    pub fn for_env(env: AppEnv) -> Self {
+        let enabled = env == AppEnv::Development;
+        DevTools { introspection: enabled, playground: enabled }
+    }
+
+    // Start from the defaults of APP_ENV, then apply GRAPHQL_INTROSPECTION and GRAPHQL_PLAYGROUND (true or
+    // false) when a deployment sets them
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        let defaults = DevTools::for_env(AppEnv::from_settings(settings)?);
+        Ok(DevTools {
+            introspection: bool_var(settings, "GRAPHQL_INTROSPECTION")?.unwrap_or(defaults.introspection),
+            playground: bool_var(settings, "GRAPHQL_PLAYGROUND")?.unwrap_or(defaults.playground),
+        })
+    }
+}
+
+// Read true or false from a setting, if it is set
+fn bool_var(settings: &Settings, name: &str) -> Result<Option<bool>, String> {
+    match settings.var(name).as_deref() {
+        None => Ok(None),
+        Some("true") => Ok(Some(true)),
+        Some("false") => Ok(Some(false)),
+        Some(other) => Err(format!("{} must be true or false, got {:?}", name, other)),
+    }
+}
+
+// Define the extension installed on schemas without introspection, which answers the __schema and
+// __type fields with an error; left alone, they would quietly resolve to null
+pub struct IntrospectionDisabled;
+
+impl ExtensionFactory for IntrospectionDisabled {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(IntrospectionDisabled)
+    }
+}
+
+#[async_trait]
+impl Extension for IntrospectionDisabled {
+    async fn resolve(&self, ctx: &ExtensionContext<'_>, info: ResolveInfo<'_>, next: NextResolve<'_>) -> ServerResult<Option<Value>> {
+        if info.path_node.parent.is_none() && matches!(info.name, "__schema" | "__type") {
+            return Err(AppError::Forbidden("Introspection is disabled".to_string()).extend().into_server_error(Pos::default()));
+        }
+        next.run(ctx, info).await
+    }
+}
+
+// Build the GET /graphql route, serving the playground when it is enabled and a GraphQL-style JSON error
+// with 404 otherwise
+pub fn playground_route(enabled: bool) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("graphql").and(warp::get()).map(move || {
+        if enabled {
+            let page = playground_source(GraphQLPlaygroundConfig::new("/graphql").subscription_endpoint("/graphql"));
+            return warp::reply::html(page).into_response();
+        }
+        let body = json!({ "errors": [{ "message": "The playground is disabled", "extensions": { "code": "NOT_FOUND" } }] });
+        warp::reply::with_status(warp::reply::json(&body), StatusCode::NOT_FOUND).into_response()
+    })
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Define a test that the developer tools are only on by default in development
+    #[test]
+    fn test_dev_tools_for_env() {
+        assert_eq!(DevTools::for_env(AppEnv::Development), DevTools { introspection: true, playground: true });
+        assert_eq!(DevTools::for_env(AppEnv::Production), DevTools { introspection: false, playground: false });
+        assert_eq!(DevTools::default(), DevTools::for_env(AppEnv::Development));
+    }
+
+    // Define a test that the playground route answers with a JSON error when it is disabled
+    #[tokio::test]
+    async fn test_playground_disabled() {
+        let response = warp::test::request().path("/graphql").reply(&playground_route(false)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["errors"][0]["extensions"]["code"], "NOT_FOUND");
+    }
+}
@@ -0,0 +1,160 @@
+// Import necessary libraries and modules
+use async_graphql::{Error, ErrorExtensions};
+
+use crate::avatar::AvatarError;
+use crate::repository::RepositoryError;
+
+// Define the errors resolvers report. Each one reaches the client with a machine-readable
+// `extensions.code`, so clients can branch on the code instead of the message
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Validation(String),
+    #[error("{0}")]
+    Unauthenticated(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0:?} is not a valid user ID")]
+    InvalidId(String),
+    #[error("orderBy lists {0} more than once")]
+    InvalidOrder(String),
+    #[error(transparent)]
+    InvalidAvatar(AvatarError),
+    #[error("{0}")]
+    Unsupported(String),
+    #[error("Verify your email address first")]
+    EmailNotVerified,
+    #[error("Enter a code from your authenticator app or a recovery code")]
+    TwoFactorRequired,
+    #[error("Too many failed login attempts; try again in {0} seconds")]
+    TooManyAttempts(u64),
+    #[error("The query costs {cost}, which is over the budget of {budget}")]
+    TooComplex { cost: usize, budget: usize },
+    #[error("The request body is larger than the server accepts")]
+    RequestTooLarge,
+    #[error("Send a Content-Length header with the request body")]
+    LengthRequired,
+    #[error("The operation took too long and was cancelled")]
+    Timeout,
+    #[error("{}", crate::persisted::PERSISTED_QUERY_NOT_FOUND)]
+    PersistedQueryNotFound,
+    #[error("Batches may hold at most {0} operations")]
+    BatchTooLarge(usize),
+    #[error("Batches may hold at most {0} operations under your rate limit")]
+    BatchOverRateLimit(u32),
+    #[error("GET requests can only run queries; send mutations with POST")]
+    MethodNotAllowed,
+    #[error(transparent)]
+    Repository(#[from] RepositoryError),
+}
+
+impl AppError {
+    // Return the code reported in the error's extensions
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "NOT_FOUND",
+            AppError::Validation(_) => "VALIDATION_FAILED",
+            AppError::Unauthenticated(_) => "UNAUTHENTICATED",
+            AppError::Forbidden(_) => "FORBIDDEN",
+            AppError::Conflict(_) => "CONFLICT",
+            AppError::InvalidId(_) => "INVALID_ID",
+            AppError::InvalidOrder(_) => "INVALID_ORDER",
+            AppError::InvalidAvatar(AvatarError::Store(error)) => repository_code(error),
+            AppError::InvalidAvatar(_) => "INVALID_AVATAR",
+            AppError::Unsupported(_) => "UNSUPPORTED",
+            AppError::EmailNotVerified => "EMAIL_NOT_VERIFIED",
+            AppError::TwoFactorRequired => "TWO_FACTOR_REQUIRED",
+            AppError::TooManyAttempts(_) => "TOO_MANY_ATTEMPTS",
+            AppError::TooComplex { .. } => "QUERY_TOO_COMPLEX",
+            AppError::RequestTooLarge => "PAYLOAD_TOO_LARGE",
+            AppError::LengthRequired => "LENGTH_REQUIRED",
+            AppError::Timeout => "TIMEOUT",
+            AppError::PersistedQueryNotFound => "PERSISTED_QUERY_NOT_FOUND",
+            AppError::BatchTooLarge(_) => "BATCH_TOO_LARGE",
+            AppError::BatchOverRateLimit(_) => "BATCH_OVER_RATE_LIMIT",
+            AppError::MethodNotAllowed => "METHOD_NOT_ALLOWED",
+            AppError::Repository(error) => repository_code(error),
+        }
+    }
+}
+
+// Return the code for a storage error: duplicates and stale versions are the client's to resolve,
+// anything else is a failure on the server's side
+fn repository_code(error: &RepositoryError) -> &'static str {
+    match error {
+        RepositoryError::Conflict(_) => "CONFLICT",
+        RepositoryError::VersionConflict { .. } => "VERSION_CONFLICT",
+        RepositoryError::InvalidId(_) => "INVALID_ID",
+        RepositoryError::Backend(_) | RepositoryError::Config(_) => "INTERNAL",
+    }
+}
+
+// Convert an AppError into a GraphQL error carrying its code
+impl ErrorExtensions for AppError {
+    fn extend(&self) -> Error {
+        match self {
+            AppError::Repository(error) | AppError::InvalidAvatar(AvatarError::Store(error)) => error.extend(),
+            error => Error::new(error.to_string()).extend_with(|_, extensions| extensions.set("code", error.code())),
+        }
+    }
+}
+
+// Convert a storage error into a GraphQL error carrying its code, so repository results can be returned
+// from resolvers with `.extend()?`; version conflicts also report both versions so the client can retry
+impl ErrorExtensions for RepositoryError {
+    fn extend(&self) -> Error {
+        Error::new(self.to_string()).extend_with(|_, extensions| {
+            extensions.set("code", repository_code(self));
+            if let RepositoryError::VersionConflict { expected, actual, .. } = self {
+                extensions.set("expectedVersion", *expected);
+                extensions.set("currentVersion", *actual);
+            }
+        })
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Define a test for the codes and messages errors reach clients with
+    #[test]
+    fn test_error_extensions() {
+        let code = |error: Error| error.extensions.and_then(|extensions| extensions.get("code").cloned()).map(|code| code.to_string());
+        let cases = [
+            (AppError::NotFound("User 1 not found".to_string()), "User 1 not found", "NOT_FOUND"),
+            (AppError::Validation("Post title must not be empty".to_string()), "Post title must not be empty", "VALIDATION_FAILED"),
+            (AppError::Unauthenticated("Sign in first".to_string()), "Sign in first", "UNAUTHENTICATED"),
+            (AppError::Forbidden("Admins only".to_string()), "Admins only", "FORBIDDEN"),
+            (AppError::InvalidId("x".to_string()), "\"x\" is not a valid user ID", "INVALID_ID"),
+            (AppError::EmailNotVerified, "Verify your email address first", "EMAIL_NOT_VERIFIED"),
+            (AppError::TwoFactorRequired, "Enter a code from your authenticator app or a recovery code", "TWO_FACTOR_REQUIRED"),
+            (AppError::TooManyAttempts(30), "Too many failed login attempts; try again in 30 seconds", "TOO_MANY_ATTEMPTS"),
+            (AppError::Timeout, "The operation took too long and was cancelled", "TIMEOUT"),
+            (AppError::PersistedQueryNotFound, "PersistedQueryNotFound", "PERSISTED_QUERY_NOT_FOUND"),
+            (AppError::BatchTooLarge(10), "Batches may hold at most 10 operations", "BATCH_TOO_LARGE"),
+            (AppError::MethodNotAllowed, "GET requests can only run queries; send mutations with POST", "METHOD_NOT_ALLOWED"),
+            (RepositoryError::Conflict("user already exists".to_string()).into(), "user already exists", "CONFLICT"),
+            (RepositoryError::Backend("timed out".to_string()).into(), "storage backend error: timed out", "INTERNAL"),
+            (AppError::InvalidAvatar(AvatarError::UnsupportedType("text/plain".to_string())), "avatar content type \"text/plain\" is not supported; use image/png, image/jpeg, image/gif, or image/webp", "INVALID_AVATAR"),
+            (AppError::InvalidAvatar(RepositoryError::Backend("disk full".to_string()).into()), "storage backend error: disk full", "INTERNAL"),
+        ];
+        for (error, message, expected) in cases {
+            let error = error.extend();
+            assert_eq!(error.message, message);
+            assert_eq!(code(error).as_deref(), Some(format!("{:?}", expected).as_str()));
+        }
+
+        // Version conflicts carry both versions
+        let error = RepositoryError::VersionConflict { id: "1".to_string(), expected: 1, actual: 2 }.extend();
+        let extensions = error.extensions.expect("Missing extensions");
+        assert_eq!(extensions.get("code").map(ToString::to_string).as_deref(), Some("\"VERSION_CONFLICT\""));
+        assert_eq!(extensions.get("expectedVersion").map(ToString::to_string).as_deref(), Some("1"));
+        assert_eq!(extensions.get("currentVersion").map(ToString::to_string).as_deref(), Some("2"));
+    }
+}
@@ -0,0 +1,212 @@
+// Import necessary libraries and modules
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+use crate::log_warn;
+use crate::repository::{SharedRepository, UserRepository};
+
+// Define how long the readiness probe waits for the backing store before reporting it unavailable
+pub const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Define the state the readiness probe reports besides the backing store: whether the schema has been
+// built. Clones share it, so the route sees the schema being built after it was created
+#[derive(Clone, Default)]
+pub struct Readiness {
+    schema_built: Arc<AtomicBool>,
+}
+
+impl Readiness {
+    // Report the schema as built, once it is
+    pub fn mark_schema_built(&self) {
+        self.schema_built.store(true, Ordering::Relaxed);
+    }
+}
+
+// Define the result of each readiness check: "ok", or what is wrong
+#[derive(Serialize)]
+pub struct ReadyChecks {
+    pub schema: String,
+    pub storage: String,
+    pub migrations: String,
+}
+
+impl ReadyChecks {
+    // Return the first check that failed, if any
+    pub fn failure(&self) -> Option<&str> {
+        [&self.schema, &self.storage, &self.migrations].into_iter().map(String::as_str).find(|result| *result != OK)
+    }
+}
+
+// Define what passing checks report
+const OK: &str = "ok";
+
+// Define the JSON body returned by the readiness probe
+#[derive(Serialize)]
+struct ReadyStatus {
+    status: &'static str,
+    checks: ReadyChecks,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// Define the JSON body returned by the liveness probe
+#[derive(Serialize)]
+struct LiveStatus {
+    status: &'static str,
+}
+
+// Ping the repository (and any cache in front of it), failing if it errors or does not answer in time
+pub async fn check_ready(repository: &dyn UserRepository, timeout: Duration) -> Result<(), String> {
+    match tokio::time::timeout(timeout, repository.ping()).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(error)) => Err(error.to_string()),
+        Err(_) => Err(format!("backing store did not respond within {} ms", timeout.as_millis())),
+    }
+}
+
+// Check that the migrations this build has are all applied, within the timeout
+pub async fn check_migrations(repository: &dyn UserRepository, timeout: Duration) -> Result<(), String> {
+    match tokio::time::timeout(timeout, repository.pending_migrations()).await {
+        Ok(Ok(0)) => Ok(()),
+        Ok(Ok(pending)) => Err(format!("{} migrations have not been applied", pending)),
+        Ok(Err(error)) => Err(format!("failed to read the applied migrations: {}", error)),
+        Err(_) => Err(format!("backing store did not respond within {} ms", timeout.as_millis())),
+    }
+}
+
+// Run every readiness check; the migrations are only checked once the store is reachable
+pub async fn ready_checks(repository: &dyn UserRepository, readiness: &Readiness, timeout: Duration) -> ReadyChecks {
+    let schema = if readiness.schema_built.load(Ordering::Relaxed) { OK.to_string() } else { "the schema has not been built".to_string() };
+    let (storage, migrations) = match check_ready(repository, timeout).await {
+        Ok(()) => (OK.to_string(), check_migrations(repository, timeout).await.err().unwrap_or_else(|| OK.to_string())),
+        Err(error) => (error, "not checked, since the backing store is unavailable".to_string()),
+    };
+    ReadyChecks { schema, storage, migrations }
+}
+
+// Build the GET /ready route, answering 200 when the schema is built, the backing store is reachable,
+// and its migrations are applied, and 503 otherwise, with the result of each check
+pub fn ready_route(
+    repository: SharedRepository,
+    readiness: Readiness,
+    timeout: Duration,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("ready").and(warp::path::end()).and(warp::get()).then(move || {
+        let (repository, readiness) = (repository.clone(), readiness.clone());
+        async move {
+            let checks = ready_checks(repository.as_ref(), &readiness, timeout).await;
+            let (code, status) = match checks.failure().map(str::to_string) {
+                None => (StatusCode::OK, ReadyStatus { status: "ready", checks, error: None }),
+                Some(error) => {
+                    log_warn!("Readiness check failed: {}", error);
+                    (StatusCode::SERVICE_UNAVAILABLE, ReadyStatus { status: "unavailable", checks, error: Some(error) })
+                }
+            };
+            warp::reply::with_status(warp::reply::json(&status), code)
+        }
+    })
+}
+
+// Build the GET /healthz route, which answers 200 whenever the process can answer at all. It checks
+// nothing else, so a store that is down takes the server out of rotation without getting it restarted
+pub fn liveness_route() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("healthz").and(warp::path::end()).and(warp::get()).map(|| warp::reply::json(&LiveStatus { status: "alive" }))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{CacheStore, CachedRepository};
+    use crate::repository::{InMemoryRepository, RepositoryError, RepositoryResult};
+    use async_trait::async_trait;
+
+    // Define a cache that is either down or hangs, to stand in for an unavailable Redis
+    struct BrokenCache {
+        hang: bool,
+    }
+
+    #[async_trait]
+    impl CacheStore for BrokenCache {
+        async fn get(&self, _key: &str) -> RepositoryResult<Option<String>> {
+            Ok(None)
+        }
+
+        async fn set(&self, _key: &str, _value: String, _ttl: Duration) -> RepositoryResult<()> {
+            Ok(())
+        }
+
+        async fn delete(&self, _key: &str) -> RepositoryResult<()> {
+            Ok(())
+        }
+
+        async fn ping(&self) -> RepositoryResult<()> {
+            if self.hang {
+                std::future::pending::<()>().await;
+            }
+            Err(RepositoryError::Backend("connection refused".to_string()))
+        }
+    }
+
+    // Send a readiness probe through the route, with the schema built, and return the status code and JSON body
+    async fn probe(repository: SharedRepository) -> (StatusCode, serde_json::Value) {
+        let readiness = Readiness::default();
+        readiness.mark_schema_built();
+        probe_with(repository, readiness).await
+    }
+
+    // Send a readiness probe through the route and return the status code and JSON body
+    async fn probe_with(repository: SharedRepository, readiness: Readiness) -> (StatusCode, serde_json::Value) {
+        let route = ready_route(repository, readiness, Duration::from_millis(50));
+        let response = warp::test::request().method("GET").path("/ready").reply(&route).await;
+        let body = serde_json::from_slice(response.body()).expect("Readiness body is not JSON");
+        (response.status(), body)
+    }
+
+    // Define a test that a reachable store reports ready
+    #[tokio::test]
+    async fn test_ready_when_store_is_reachable() {
+        let (status, body) = probe(Arc::new(InMemoryRepository::new())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!({ "status": "ready", "checks": { "schema": "ok", "storage": "ok", "migrations": "ok" } }));
+    }
+
+    // Define a test that the server is not ready until the schema is built
+    #[tokio::test]
+    async fn test_unavailable_until_schema_is_built() {
+        let readiness = Readiness::default();
+        let (status, body) = probe_with(Arc::new(InMemoryRepository::new()), readiness.clone()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["checks"]["schema"], "the schema has not been built");
+        assert_eq!(body["checks"]["storage"], "ok");
+        readiness.mark_schema_built();
+        assert_eq!(probe_with(Arc::new(InMemoryRepository::new()), readiness).await.0, StatusCode::OK);
+    }
+
+    // Define a test that the liveness probe answers without checking anything
+    #[tokio::test]
+    async fn test_liveness() {
+        let response = warp::test::request().method("GET").path("/healthz").reply(&liveness_route()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body().as_ref(), br#"{"status":"alive"}"#);
+    }
+
+    // Define a test that a failing or unresponsive store reports 503
+    #[tokio::test]
+    async fn test_unavailable_when_store_fails_or_times_out() {
+        for hang in [false, true] {
+            let cache = Arc::new(BrokenCache { hang });
+            let repository = CachedRepository::new(Arc::new(InMemoryRepository::new()), cache, Duration::from_secs(60));
+            let (status, body) = probe(Arc::new(repository)).await;
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(body["status"], "unavailable");
+            assert_eq!(body["checks"]["migrations"], "not checked, since the backing store is unavailable");
+            let expected = if hang { "did not respond within 50 ms" } else { "connection refused" };
+            assert!(body["error"].as_str().unwrap().contains(expected));
+        }
+    }
+}
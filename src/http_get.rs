@@ -0,0 +1,205 @@
+// Import necessary libraries and modules
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextParseQuery, NextPrepareRequest};
+use async_graphql::parser::types::{ExecutableDocument, OperationType};
+use async_graphql::{ErrorExtensions, ParseRequestError, Pos, Request, ServerResult, Value, Variables};
+use async_graphql_warp::{GraphQLBadRequest, GraphQLResponse};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex};
+use warp::filters::BoxedFilter;
+use warp::http::header::{HeaderValue, ALLOW, CACHE_CONTROL, CONTENT_LENGTH, ETAG};
+use warp::http::StatusCode;
+use warp::hyper::Body;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::error::AppError;
+
+// Mark a request that arrived with GET, which may only run queries: caches and browsers repeat GET
+// requests, and links can make browsers send them, so they must not change anything
+#[derive(Clone, Copy, Debug)]
+pub struct GetRequest;
+
+// Find the type of the operation a document runs: the one named by the request, or its only operation
+pub fn operation_type(document: &ExecutableDocument, operation_name: Option<&str>) -> Option<OperationType> {
+    let mut operations = document.operations.iter();
+    let (_, operation) = operations.find(|(name, _)| operation_name.is_none() || name.map(|name| name.as_str()) == operation_name)?;
+    Some(operation.node.ty)
+}
+
+// Define the parameters of a GraphQL request sent with GET, as named by the GraphQL over HTTP spec;
+// variables and extensions are JSON
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetParams {
+    #[serde(default)]
+    query: String,
+    operation_name: Option<String>,
+    variables: Option<String>,
+    extensions: Option<String>,
+}
+
+// Build a filter reading a GraphQL request from the query string of a GET request. Requests with neither
+// a query nor extensions are rejected as not found, so they reach the playground; ones whose variables or
+// extensions are not JSON objects are answered with 400 by recover_bad_request
+pub fn get_request() -> BoxedFilter<(Request,)> {
+    warp::get()
+        .and(warp::query::<GetParams>())
+        .and_then(|params: GetParams| async move {
+            if params.query.is_empty() && params.extensions.is_none() {
+                return Err(warp::reject::not_found());
+            }
+            let mut request = Request::new(params.query).variables(Variables::from_json(json(params.variables)?));
+            request.extensions = json(params.extensions)?;
+            if let Some(operation_name) = params.operation_name {
+                request = request.operation_name(operation_name);
+            }
+            Ok(request.data(GetRequest))
+        })
+        .boxed()
+}
+
+// Read a JSON parameter, which is empty when it is not sent
+fn json<T: DeserializeOwned + Default>(value: Option<String>) -> Result<T, Rejection> {
+    let value = value.as_deref().map(serde_json::from_str).transpose();
+    let value = value.map_err(|error| warp::reject::custom(GraphQLBadRequest(ParseRequestError::InvalidRequest(Box::new(error)))))?;
+    Ok(value.unwrap_or_default())
+}
+
+// Answer with the GraphQL response to a GET request, with 405 and an Allow header naming POST when the
+// operation was refused for not being a query
+pub fn get_response(response: async_graphql::Response) -> Response {
+    let code = Value::from(AppError::MethodNotAllowed.code());
+    let refused = response.errors.iter().any(|error| error.extensions.as_ref().and_then(|extensions| extensions.get("code")) == Some(&code));
+    let mut reply = GraphQLResponse::from(response).into_response();
+    if refused {
+        *reply.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+        reply.headers_mut().insert(ALLOW, HeaderValue::from_static("POST"));
+    }
+    reply
+}
+
+// Add a strong ETag, the SHA-256 of the body as it is sent, to a cacheable answer to a GET request, and
+// answer 304 Not Modified without the body when If-None-Match names it, so clients polling a query only
+// download it again once it changes. Answers that may not be cached are left as they are
+pub async fn with_etag(response: Response, if_none_match: Option<&str>) -> Response {
+    let cacheable = response.headers().get(CACHE_CONTROL).is_some_and(|cache_control| cache_control != "no-store");
+    if response.status() != StatusCode::OK || !cacheable {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let body = warp::hyper::body::to_bytes(body).await.unwrap_or_default();
+    let etag = format!("\"{}\"", Sha256::digest(&body).iter().map(|byte| format!("{:02x}", byte)).collect::<String>());
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        parts.headers.insert(ETAG, value);
+    }
+    // Clients may list several tags, weak ones included, or * for any
+    let matched = if_none_match.is_some_and(|tags| tags.split(',').map(|tag| tag.trim()).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag));
+    if !matched {
+        return Response::from_parts(parts, Body::from(body));
+    }
+    parts.status = StatusCode::NOT_MODIFIED;
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, Body::empty())
+}
+
+// Define the extension that refuses mutations and subscriptions from GET requests; it is installed on
+// every schema and runs after persisted queries are resolved, so they are held to it too
+pub struct QueriesOnlyOverGet;
+
+impl ExtensionFactory for QueriesOnlyOverGet {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(QueriesOnlyOverGetExtension::default())
+    }
+}
+
+// Each request gets its own extension, which remembers the operation the request asked to run
+#[derive(Default)]
+struct QueriesOnlyOverGetExtension {
+    operation_name: Mutex<Option<String>>,
+}
+
+#[async_trait]
+impl Extension for QueriesOnlyOverGetExtension {
+    async fn prepare_request(&self, ctx: &ExtensionContext<'_>, request: Request, next: NextPrepareRequest<'_>) -> ServerResult<Request> {
+        self.operation_name.lock().unwrap().clone_from(&request.operation_name);
+        next.run(ctx, request).await
+    }
+
+    async fn parse_query(&self, ctx: &ExtensionContext<'_>, query: &str, variables: &Variables, next: NextParseQuery<'_>) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+        let operation_name = self.operation_name.lock().unwrap().clone();
+        let ty = operation_type(&document, operation_name.as_deref());
+        if ctx.data_opt::<GetRequest>().is_some() && ty.is_some_and(|ty| ty != OperationType::Query) {
+            return Err(AppError::MethodNotAllowed.extend().into_server_error(Pos::default()));
+        }
+        Ok(document)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::tests::sample_schema;
+
+    // Run a GET request to /graphql with the given query string through the filter and the schema
+    async fn get(query: &str) -> Response {
+        let request = warp::test::request().path(&format!("/graphql?{}", query)).filter(&get_request()).await.unwrap();
+        get_response(sample_schema().execute(request).await)
+    }
+
+    // Define a test that queries run over GET and that other operations are refused with 405
+    #[tokio::test]
+    async fn test_get_requests() {
+        let response = get("query=query%20User(%24id%3A%20ID!)%20%7B%20userById(id%3A%20%24id)%20%7B%20name%20%7D%20%7D&variables=%7B%22id%22%3A%221%22%7D&operationName=User").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&warp::hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body["data"]["userById"]["name"], "Pavel");
+
+        let response = get("query=mutation%20%7B%20deleteUser(id%3A%20%221%22)%20%7D").await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[ALLOW], "POST");
+        let body: serde_json::Value = serde_json::from_slice(&warp::hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body["errors"][0]["extensions"]["code"], "METHOD_NOT_ALLOWED");
+
+        // Only the operation that runs counts
+        let document = "query%20Read%20%7B%20__typename%20%7D%20mutation%20Write%20%7B%20deleteUser(id%3A%20%221%22)%20%7D";
+        assert_eq!(get(&format!("query={}&operationName=Read", document)).await.status(), StatusCode::OK);
+        assert_eq!(get(&format!("query={}&operationName=Write", document)).await.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        // Requests without a query are left to the other routes, and bad variables are refused
+        assert!(warp::test::request().path("/graphql").filter(&get_request()).await.is_err());
+        assert!(warp::test::request().path("/graphql?token=1").filter(&get_request()).await.is_err());
+        let rejection = warp::test::request().path("/graphql?query=%7B__typename%7D&variables=x").filter(&get_request()).await.unwrap_err();
+        assert!(rejection.find::<GraphQLBadRequest>().is_some());
+    }
+
+    // Define a test that cacheable answers get an ETag, and that clients sending it back get 304
+    #[tokio::test]
+    async fn test_etag() {
+        let answer = |cache_control: &'static str| warp::reply::with_header(r#"{"data":{"stats":{"userCount":2}}}"#, CACHE_CONTROL, cache_control).into_response();
+        let response = with_etag(answer("max-age=30, public"), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[ETAG].to_str().unwrap().to_string();
+        assert!(etag.starts_with('"') && etag.len() == 66, "{}", etag);
+        assert_eq!(warp::hyper::body::to_bytes(response.into_body()).await.unwrap(), r#"{"data":{"stats":{"userCount":2}}}"#);
+
+        // The tag is matched among several, weak or not, and the body is left out
+        for if_none_match in [etag.clone(), format!("\"other\", W/{}", etag), "*".to_string()] {
+            let response = with_etag(answer("max-age=30, public"), Some(&if_none_match)).await;
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+            assert_eq!(response.headers()[ETAG], etag.as_str());
+            assert_eq!(response.headers()[CACHE_CONTROL], "max-age=30, public");
+            assert!(warp::hyper::body::to_bytes(response.into_body()).await.unwrap().is_empty());
+        }
+        assert_eq!(with_etag(answer("max-age=30, public"), Some("\"other\"")).await.status(), StatusCode::OK);
+
+        // Answers that may not be cached get no tag
+        let response = with_etag(answer("no-store"), Some(&etag)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(ETAG));
+    }
+}
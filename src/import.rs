@@ -0,0 +1,240 @@
+// Import necessary libraries and modules
+use async_graphql::SimpleObject;
+use std::collections::HashSet;
+
+use crate::model::{is_valid_email, parse_user_id, MAX_NAME_LENGTH, NewUser, User};
+use crate::repository::{RepositoryResult, UserRepository};
+
+// Define how many valid rows are inserted per repository batch
+pub const IMPORT_BATCH_SIZE: usize = 100;
+
+// Define the outcome for one CSV row: the created user on success, otherwise the reason it was rejected
+#[derive(Clone, Debug, SimpleObject)]
+pub struct ImportRowResult {
+    // Line number of the row in the CSV content, counting the header as line 1
+    pub row: u64,
+    pub user: Option<User>,
+    pub error: Option<String>,
+}
+
+impl ImportRowResult {
+    fn created(row: u64, user: User) -> Self {
+        ImportRowResult { row, user: Some(user), error: None }
+    }
+
+    fn failed(row: u64, error: impl Into<String>) -> Self {
+        ImportRowResult { row, user: None, error: Some(error.into()) }
+    }
+}
+
+// Define the errors that stop the whole import before any row is inserted
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("CSV header must include {0:?} column")]
+    MissingColumn(&'static str),
+    #[error("failed to read CSV header: {0}")]
+    Header(String),
+}
+
+// Define a row that passed validation and is waiting to be inserted
+struct PendingRow {
+    row: u64,
+    new_user: NewUser,
+}
+
+// Define the positions of the known columns in the header
+struct Columns {
+    id: Option<usize>,
+    name: usize,
+    email: usize,
+}
+
+impl Columns {
+    // Locate the columns by name, ignoring case and surrounding whitespace
+    fn from_header(header: &csv::StringRecord) -> Result<Self, ImportError> {
+        let find = |name: &str| header.iter().position(|column| column.trim().eq_ignore_ascii_case(name));
+        Ok(Columns {
+            id: find("id"),
+            name: find("name").ok_or(ImportError::MissingColumn("name"))?,
+            email: find("email").ok_or(ImportError::MissingColumn("email"))?,
+        })
+    }
+}
+
+// Import users from CSV content with a `name,email` header and an optional `id` column.
+// Every row gets a result; invalid rows are reported and skipped while valid rows are inserted in batches
+pub async fn import_csv(repository: &dyn UserRepository, content: &str) -> Result<Vec<ImportRowResult>, ImportError> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(content.as_bytes());
+    let header = reader.headers().map_err(|error| ImportError::Header(error.to_string()))?.clone();
+    let columns = Columns::from_header(&header)?;
+
+    // Validate every row, remembering requested IDs so duplicates within the file are caught
+    let mut results = Vec::new();
+    let mut pending = Vec::new();
+    let mut seen_ids = HashSet::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(error) => {
+                let row = error.position().map_or(0, |position| position.line());
+                results.push(ImportRowResult::failed(row, format!("malformed row: {}", error)));
+                continue;
+            }
+        };
+        let row = record.position().map_or(0, |position| position.line());
+        match validate_row(&record, &columns, &mut seen_ids) {
+            Ok(new_user) => pending.push(PendingRow { row, new_user }),
+            Err(error) => results.push(ImportRowResult::failed(row, error)),
+        }
+    }
+
+    // Reject rows that ask for IDs already in use
+    let requested: Vec<String> = pending.iter().filter_map(|pending| pending.new_user.id.clone()).collect();
+    let existing = match repository.get_many(&requested).await {
+        Ok(existing) => existing,
+        Err(error) => {
+            results.extend(pending.into_iter().map(|pending| ImportRowResult::failed(pending.row, error.to_string())));
+            results.sort_by_key(|result| result.row);
+            return Ok(results);
+        }
+    };
+    let (taken, pending): (Vec<_>, Vec<_>) = pending
+        .into_iter()
+        .partition(|pending| pending.new_user.id.as_ref().is_some_and(|id| existing.contains_key(id)));
+    for pending in taken {
+        let id = pending.new_user.id.unwrap_or_default();
+        results.push(ImportRowResult::failed(pending.row, format!("user {} already exists", id)));
+    }
+
+    // Insert the remaining rows batch by batch
+    let mut pending = pending.into_iter().peekable();
+    while pending.peek().is_some() {
+        let batch: Vec<PendingRow> = pending.by_ref().take(IMPORT_BATCH_SIZE).collect();
+        results.extend(insert_batch(repository, batch).await);
+    }
+
+    results.sort_by_key(|result| result.row);
+    Ok(results)
+}
+
+// Check one record, returning the user to create or a description of what is wrong
+fn validate_row(record: &csv::StringRecord, columns: &Columns, seen_ids: &mut HashSet<String>) -> Result<NewUser, String> {
+    let name = record.get(columns.name).unwrap_or_default();
+    let email = record.get(columns.email).unwrap_or_default();
+    let id = columns.id.and_then(|index| record.get(index)).filter(|id| !id.is_empty());
+
+    if name.is_empty() {
+        return Err("name must not be empty".to_string());
+    }
+    if name.chars().count() > MAX_NAME_LENGTH {
+        return Err(format!("name must be at most {} characters", MAX_NAME_LENGTH));
+    }
+    if !is_valid_email(email) {
+        return Err(format!("{:?} is not a valid email address", email));
+    }
+    // Numeric legacy IDs are stored as the UUIDs they map to, so they clash with those UUIDs too
+    let id = match id {
+        Some(id) => {
+            let Some(uuid) = parse_user_id(id) else {
+                return Err(format!("{:?} is not a valid ID; IDs must be UUIDs or positive integers", id));
+            };
+            if !seen_ids.insert(uuid.to_string()) {
+                return Err(format!("ID {} appears more than once in the file", id));
+            }
+            Some(uuid.to_string())
+        }
+        None => None,
+    };
+
+    Ok(NewUser {
+        id,
+        name: name.to_string(),
+        email: email.to_string(),
+        ..Default::default()
+    })
+}
+
+// Insert one batch; if the batch fails as a whole, retry row by row so only the bad rows are reported
+async fn insert_batch(repository: &dyn UserRepository, batch: Vec<PendingRow>) -> Vec<ImportRowResult> {
+    let new_users = batch.iter().map(|pending| pending.new_user.clone()).collect();
+    if let Ok(users) = repository.create_many(new_users).await {
+        return batch
+            .iter()
+            .zip(users)
+            .map(|(pending, user)| ImportRowResult::created(pending.row, user))
+            .collect();
+    }
+
+    let mut results = Vec::with_capacity(batch.len());
+    for pending in batch {
+        let result: RepositoryResult<User> = repository.create(pending.new_user).await;
+        results.push(match result {
+            Ok(user) => ImportRowResult::created(pending.row, user),
+            Err(error) => ImportRowResult::failed(pending.row, error.to_string()),
+        });
+    }
+    results
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::InMemoryRepository;
+
+    // Define a test for per-row results across valid and invalid rows
+    #[tokio::test]
+    async fn test_import_reports_each_row() {
+        let repository = InMemoryRepository::with_sample_users();
+        let csv = "id,name,email\n\
+                   ,Ada,ada@example.com\n\
+                   20,Grace,grace@example.com\n\
+                   2,Taken,taken@example.com\n\
+                   20,Again,again@example.com\n\
+                   abc,Letters,letters@example.com\n\
+                   ,,blank@example.com\n\
+                   ,Bad Email,not-an-email\n";
+        let results = import_csv(&repository, csv).await.unwrap();
+
+        // Generated IDs are new UUIDs, and numeric IDs are stored as the UUIDs they map to
+        assert!(results[0].user.as_ref().is_some_and(|user| user.id != "00000000-0000-0000-0000-000000000003"));
+        let summary: Vec<(u64, Option<&str>, Option<&str>)> = results[1..]
+            .iter()
+            .map(|result| (result.row, result.user.as_ref().map(|user| user.id.as_str()), result.error.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (3, Some("00000000-0000-0000-0000-000000000014"), None),
+                (4, None, Some("user 00000000-0000-0000-0000-000000000002 already exists")),
+                (5, None, Some("ID 20 appears more than once in the file")),
+                (6, None, Some("\"abc\" is not a valid ID; IDs must be UUIDs or positive integers")),
+                (7, None, Some("name must not be empty")),
+                (8, None, Some("\"not-an-email\" is not a valid email address")),
+            ]
+        );
+        assert_eq!(repository.get("00000000-0000-0000-0000-000000000014").await.unwrap().unwrap().name, "Grace");
+    }
+
+    // Define a test that a header without the required columns is rejected
+    #[tokio::test]
+    async fn test_import_requires_columns() {
+        let repository = InMemoryRepository::new();
+        let error = import_csv(&repository, "name\nAda\n").await.unwrap_err();
+        assert_eq!(error.to_string(), "CSV header must include \"email\" column");
+    }
+
+    // Define a test that rows beyond one batch are all inserted
+    #[tokio::test]
+    async fn test_import_spans_batches() {
+        let repository = InMemoryRepository::new();
+        let mut csv = String::from("name,email\n");
+        for index in 0..(IMPORT_BATCH_SIZE + 5) {
+            csv.push_str(&format!("User {},user{}@example.com\n", index, index));
+        }
+        let results = import_csv(&repository, &csv).await.unwrap();
+        assert_eq!(results.len(), IMPORT_BATCH_SIZE + 5);
+        assert!(results.iter().all(|result| result.user.is_some()));
+        assert_eq!(repository.list().await.unwrap().len(), IMPORT_BATCH_SIZE + 5);
+    }
+}
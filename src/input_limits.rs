@@ -0,0 +1,170 @@
+// Import necessary libraries and modules
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextParseQuery};
+use async_graphql::parser::types::{ExecutableDocument, FragmentDefinition, Selection, SelectionSet};
+use async_graphql::{ErrorExtensions, Name, Pos, Positioned, ServerResult, Value, Variables};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use crate::config::Settings;
+use crate::error::AppError;
+use crate::upload::number_var;
+
+// Define the limits used when none are configured; larger CSV files can be sent as uploads
+pub const DEFAULT_MAX_STRING_LENGTH: usize = 100_000;
+pub const DEFAULT_MAX_LIST_LENGTH: usize = 1_000;
+pub const DEFAULT_MAX_ALIASES: usize = 50;
+
+// Define how large the input of an operation may be: the characters in any string argument or variable,
+// the items in any list, and the aliased fields in the operation, counting those of the fragments it
+// spreads every time they are spread. Anything larger is refused before it is validated or run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputLimits {
+    pub max_string_length: usize,
+    pub max_list_length: usize,
+    pub max_aliases: usize,
+}
+
+impl Default for InputLimits {
+    fn default() -> Self {
+        InputLimits { max_string_length: DEFAULT_MAX_STRING_LENGTH, max_list_length: DEFAULT_MAX_LIST_LENGTH, max_aliases: DEFAULT_MAX_ALIASES }
+    }
+}
+
+impl InputLimits {
+    // Read MAX_INPUT_STRING_LENGTH (default 100000), MAX_INPUT_LIST_LENGTH (default 1000), and MAX_ALIASES
+    // (default 50)
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        Ok(InputLimits {
+            max_string_length: number_var(settings, "MAX_INPUT_STRING_LENGTH", DEFAULT_MAX_STRING_LENGTH)?,
+            max_list_length: number_var(settings, "MAX_INPUT_LIST_LENGTH", DEFAULT_MAX_LIST_LENGTH)?,
+            max_aliases: number_var(settings, "MAX_ALIASES", DEFAULT_MAX_ALIASES)?,
+        })
+    }
+
+    // Check the arguments and aliases of every operation in a document, and the variables sent with it
+    pub fn check(&self, document: &ExecutableDocument, variables: &Variables) -> Result<(), AppError> {
+        variables.values().try_for_each(|value| self.check_value(value))?;
+        let mut fragment_aliases = HashMap::new();
+        for (_, operation) in document.operations.iter() {
+            let aliases = self.check_selection_set(&operation.node.selection_set.node, &document.fragments, &mut fragment_aliases)?;
+            if aliases > self.max_aliases {
+                return Err(AppError::Validation(format!("Operations may alias at most {} fields, this one aliases {}", self.max_aliases, aliases)));
+            }
+        }
+        Ok(())
+    }
+
+    // Check the arguments of a selection set and return how many aliased fields it selects. Fragments are
+    // counted once and remembered, so spreading them many times costs no more to count; a fragment that
+    // spreads itself counts as none, and is refused by validation afterwards
+    fn check_selection_set(
+        &self,
+        selection_set: &SelectionSet,
+        fragments: &HashMap<Name, Positioned<FragmentDefinition>>,
+        fragment_aliases: &mut HashMap<Name, usize>,
+    ) -> Result<usize, AppError> {
+        let mut aliases = 0usize;
+        for selection in &selection_set.items {
+            let count = match &selection.node {
+                Selection::Field(field) => {
+                    for (_, argument) in &field.node.arguments {
+                        // Variables are checked with the values sent for them
+                        let value = argument.node.clone().into_const_with(|_| Ok::<_, Infallible>(Value::Null)).unwrap_or_default();
+                        self.check_value(&value)?;
+                    }
+                    let nested = self.check_selection_set(&field.node.selection_set.node, fragments, fragment_aliases)?;
+                    nested.saturating_add(usize::from(field.node.alias.is_some()))
+                }
+                Selection::InlineFragment(fragment) => self.check_selection_set(&fragment.node.selection_set.node, fragments, fragment_aliases)?,
+                Selection::FragmentSpread(spread) => {
+                    let name = &spread.node.fragment_name.node;
+                    match (fragment_aliases.get(name), fragments.get(name)) {
+                        (Some(count), _) => *count,
+                        (None, Some(fragment)) => {
+                            fragment_aliases.insert(name.clone(), 0);
+                            let count = self.check_selection_set(&fragment.node.selection_set.node, fragments, fragment_aliases)?;
+                            fragment_aliases.insert(name.clone(), count);
+                            count
+                        }
+                        (None, None) => 0,
+                    }
+                }
+            };
+            aliases = aliases.saturating_add(count);
+        }
+        Ok(aliases)
+    }
+
+    // Check a value and everything nested in it
+    fn check_value(&self, value: &Value) -> Result<(), AppError> {
+        match value {
+            Value::String(string) if string.chars().count() > self.max_string_length => {
+                Err(AppError::Validation(format!("Strings may be at most {} characters long", self.max_string_length)))
+            }
+            Value::List(items) if items.len() > self.max_list_length => {
+                Err(AppError::Validation(format!("Lists may have at most {} items", self.max_list_length)))
+            }
+            Value::List(items) => items.iter().try_for_each(|item| self.check_value(item)),
+            Value::Object(fields) => fields.values().try_for_each(|field| self.check_value(field)),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Implement the extension that refuses operations over the limits as soon as they are parsed; it is
+// installed on every schema
+impl ExtensionFactory for InputLimits {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(*self)
+    }
+}
+
+#[async_trait]
+impl Extension for InputLimits {
+    async fn parse_query(&self, ctx: &ExtensionContext<'_>, query: &str, variables: &Variables, next: NextParseQuery<'_>) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+        self.check(&document, variables).map_err(|error| error.extend().into_server_error(Pos::default()))?;
+        Ok(document)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::InMemoryRepository;
+    use crate::schema::{build_schema, AppState};
+    use async_graphql::Request;
+
+    // Define a test that operations over any of the limits are refused before they run
+    #[tokio::test]
+    async fn test_input_limits() {
+        let limits = InputLimits { max_string_length: 10, max_list_length: 3, max_aliases: 2 };
+        let schema = build_schema(AppState::new(Arc::new(InMemoryRepository::with_sample_users())).with_input_limits(limits));
+        let refused = |response: async_graphql::Response| {
+            let error = serde_json::to_value(&response.errors[0]).unwrap();
+            assert_eq!(response.data, Value::Null);
+            assert_eq!(error["extensions"]["code"], "VALIDATION_FAILED");
+            error["message"].as_str().unwrap().to_string()
+        };
+
+        // Strings and lists, whether sent inline, nested in input objects, or as variables
+        assert!(schema.execute(r#"{ searchUsers(query: "pavel") { rank } }"#).await.is_ok());
+        assert_eq!(refused(schema.execute(r#"{ searchUsers(query: "pavel boukine") { rank } }"#).await), "Strings may be at most 10 characters long");
+        let create = r#"mutation { createUser(input: { name: "Pavel Boukine", email: "p@x.io" }) { id } }"#;
+        refused(schema.execute(create).await);
+        let by_ids = "query Users($ids: [ID!]!) { usersByIds(ids: $ids) { name } }";
+        let variables = Variables::from_json(serde_json::json!({ "ids": ["1", "2", "3", "4"] }));
+        assert_eq!(refused(schema.execute(Request::new(by_ids).variables(variables)).await), "Lists may have at most 3 items");
+        assert!(refused(schema.execute(r#"{ usersByIds(ids: ["1", "2", "3", "4"]) { name } }"#).await).starts_with("Lists"));
+
+        // Aliases count every time their fragment is spread
+        assert!(schema.execute(r#"{ a: userById(id: "1") { name } b: userById(id: "2") { name } }"#).await.is_ok());
+        let aliases = r#"{ a: userById(id: "1") { name } b: userById(id: "2") { name } c: userById(id: "1") { name } }"#;
+        assert_eq!(refused(schema.execute(aliases).await), "Operations may alias at most 2 fields, this one aliases 3");
+        let fragments = r#"{ userById(id: "1") { ...Names posts { edges { node { author { ... on User { ...Names } } } } } } } fragment Names on User { first: name second: name }"#;
+        assert!(refused(schema.execute(fragments).await).contains("aliases 4"));
+    }
+}
@@ -0,0 +1,192 @@
+// Import necessary libraries and modules
+use std::net::{IpAddr, SocketAddr};
+use warp::http::StatusCode;
+use warp::reject::Reject;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::Settings;
+use crate::rate_limit::{client_ip, peer_addr, trusted_proxies_from_settings};
+
+// Define a range of addresses, such as 10.0.0.0/8 or 2001:db8::/32
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    // Parse a range, or a single address, which is a range of one
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || format!("{:?} is not an IP address or CIDR range", value);
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let network: IpAddr = address.parse().map_err(|_| invalid())?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().ok().filter(|prefix| *prefix <= bits).ok_or_else(invalid)?,
+            None => bits,
+        };
+        Ok(Cidr { network, prefix })
+    }
+
+    // Tell whether an address is in the range; IPv4 addresses mapped into IPv6 count as IPv4
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => prefix_matches(u32::from(network).into(), u32::from(ip).into(), self.prefix, 32),
+            (IpAddr::V6(network), IpAddr::V6(ip)) => prefix_matches(network.into(), ip.into(), self.prefix, 128),
+            _ => false,
+        }
+    }
+}
+
+// Compare the first `prefix` bits of two addresses of `bits` bits
+fn prefix_matches(network: u128, ip: u128, prefix: u8, bits: u8) -> bool {
+    prefix == 0 || (network ^ ip) >> (bits - prefix) == 0
+}
+
+// Parse a comma-separated list of ranges, ignoring blank entries
+pub fn parse_cidrs(value: &str) -> Result<Vec<Cidr>, String> {
+    value.split(',').map(str::trim).filter(|entry| !entry.is_empty()).map(Cidr::parse).collect()
+}
+
+// Define which client addresses may call the server. Addresses in a denied range are always refused;
+// when there are allowed ranges, addresses outside them are refused too. Requests from the trusted
+// proxies are judged by the address the proxy appended to X-Forwarded-For
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IpFilter {
+    pub allowed: Vec<Cidr>,
+    pub denied: Vec<Cidr>,
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl IpFilter {
+    // Read IP_ALLOWLIST and IP_DENYLIST, comma-separated addresses and CIDR ranges, along with the same
+    // RATE_LIMIT_TRUSTED_PROXIES rate limiting uses. Without either list every address is allowed
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+        let list = |name: &str| match settings.var(name) {
+            Some(value) => parse_cidrs(&value).map_err(|error| format!("Invalid {}: {}", name, error)),
+            None => Ok(Vec::new()),
+        };
+        let (allowed, denied) = (list("IP_ALLOWLIST")?, list("IP_DENYLIST")?);
+        if allowed.is_empty() && denied.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(IpFilter { allowed, denied, trusted_proxies: trusted_proxies_from_settings(settings)? }))
+    }
+
+    // Tell whether a client address may call the server, or may call it regardless of the allowlist
+    pub fn allows(&self, ip: IpAddr, bypass_allowlist: bool) -> bool {
+        if self.denied.iter().any(|range| range.contains(ip)) {
+            return false;
+        }
+        bypass_allowlist || self.allowed.is_empty() || self.allowed.iter().any(|range| range.contains(ip))
+    }
+
+    // Tell whether a request from a peer may call the server, judging requests from trusted proxies by
+    // their X-Forwarded-For header. When there is an allowlist, requests whose peer is unknown are refused
+    pub fn allows_peer(&self, peer: Option<SocketAddr>, forwarded_for: Option<&str>, bypass_allowlist: bool) -> bool {
+        match peer {
+            Some(peer) => self.allows(client_ip(&self.trusted_proxies, peer.ip(), forwarded_for), bypass_allowlist),
+            None => bypass_allowlist || self.allowed.is_empty(),
+        }
+    }
+}
+
+// Define the rejection for a request from an address the filter refuses
+#[derive(Debug)]
+pub struct IpDenied;
+
+impl Reject for IpDenied {}
+
+// Build a filter that rejects requests from addresses the IP filter refuses with IpDenied, optionally
+// ignoring the allowlist
+pub fn ip_filter(filter: Option<IpFilter>, bypass_allowlist: bool) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let forwarded_for = warp::header::optional::<String>("x-forwarded-for").or(warp::any().map(|| None)).unify();
+    peer_addr()
+        .and(forwarded_for)
+        .and_then(move |peer: Option<SocketAddr>, forwarded_for: Option<String>| {
+            let filter = filter.clone();
+            async move {
+                let Some(filter) = filter else {
+                    return Ok(());
+                };
+                if filter.allows_peer(peer, forwarded_for.as_deref(), bypass_allowlist) {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(IpDenied))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+// Answer requests rejected with IpDenied with 403
+pub async fn recover_ip_denied(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    match rejection.find::<IpDenied>() {
+        Some(IpDenied) => Ok(warp::reply::with_status("Forbidden".to_string(), StatusCode::FORBIDDEN)),
+        None => Err(rejection),
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parse an IP address
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    // Define a test for parsing ranges and telling which addresses are in them
+    #[test]
+    fn test_cidr() {
+        let range = Cidr::parse("10.1.0.0/16").unwrap();
+        assert!(range.contains(ip("10.1.255.7")));
+        assert!(!range.contains(ip("10.2.0.1")));
+        assert!(range.contains(ip("::ffff:10.1.0.1")));
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(ip("192.0.2.1")));
+        assert!(Cidr::parse("192.0.2.1").unwrap().contains(ip("192.0.2.1")));
+        assert!(!Cidr::parse("192.0.2.1").unwrap().contains(ip("192.0.2.2")));
+        let range = Cidr::parse("2001:db8::/32").unwrap();
+        assert!(range.contains(ip("2001:db8:1::1")));
+        assert!(!range.contains(ip("2001:db9::1")));
+        assert!(!range.contains(ip("10.1.0.1")));
+
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("10.0.0/8").is_err());
+        assert_eq!(parse_cidrs("10.0.0.0/8, ,192.0.2.1").unwrap().len(), 2);
+    }
+
+    // Define a test that denied ranges are refused, and addresses outside the allowlist unless it is bypassed
+    #[tokio::test]
+    async fn test_ip_filter() {
+        let filter = IpFilter { allowed: parse_cidrs("10.0.0.0/8").unwrap(), denied: parse_cidrs("10.9.0.0/16").unwrap(), trusted_proxies: vec![ip("10.0.0.1")] };
+        let route = |bypass_allowlist: bool| ip_filter(Some(filter.clone()), bypass_allowlist).map(|| "ok").recover(recover_ip_denied);
+        let send = |address: &str, forwarded_for: Option<&str>, bypass_allowlist: bool| {
+            let mut request = warp::test::request().remote_addr(format!("{}:4000", address).parse().unwrap());
+            if let Some(forwarded_for) = forwarded_for {
+                request = request.header("x-forwarded-for", forwarded_for);
+            }
+            let route = route(bypass_allowlist);
+            async move { request.reply(&route).await.status() }
+        };
+        assert_eq!(send("10.1.2.3", None, false).await, StatusCode::OK);
+        assert_eq!(send("192.0.2.1", None, false).await, StatusCode::FORBIDDEN);
+        assert_eq!(send("10.9.0.1", None, false).await, StatusCode::FORBIDDEN);
+
+        // Health checks skip the allowlist, but not the denylist
+        assert_eq!(send("192.0.2.1", None, true).await, StatusCode::OK);
+        assert_eq!(send("10.9.0.1", None, true).await, StatusCode::FORBIDDEN);
+
+        // Addresses are those the trusted proxies report
+        assert_eq!(send("10.0.0.1", Some("192.0.2.1"), false).await, StatusCode::FORBIDDEN);
+        assert_eq!(send("10.0.0.2", Some("10.9.0.1"), false).await, StatusCode::OK);
+
+        // Without a filter everything goes through
+        let open = ip_filter(None, false).map(|| "ok");
+        assert_eq!(warp::test::request().reply(&open).await.status(), StatusCode::OK);
+    }
+}
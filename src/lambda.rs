@@ -0,0 +1,100 @@
+// Import necessary libraries and modules
+use lambda_http::request::RequestContext;
+use lambda_http::{service_fn, Body as LambdaBody, Error, Request, RequestExt, Response};
+use std::net::{IpAddr, SocketAddr};
+use warp::http::header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH};
+use warp::hyper::Body;
+
+use crate::rate_limit::PeerAddr;
+use crate::reload::SharedRoutes;
+
+// Tell whether the process was started by the Lambda runtime, which sets AWS_LAMBDA_RUNTIME_API to the
+// address it hands out invocations on
+pub fn in_lambda() -> bool {
+    std::env::var("AWS_LAMBDA_RUNTIME_API").is_ok()
+}
+
+// Answer the invocations the Lambda runtime hands out with the routes until it stops the function,
+// instead of listening for connections
+pub async fn run(routes: SharedRoutes) -> Result<(), Error> {
+    lambda_http::run(service_fn(move |request| {
+        let routes = routes.clone();
+        async move { answer(&routes, request).await }
+    }))
+    .await
+}
+
+// Answer a request from API Gateway or a load balancer with the routes, as the server answers requests
+// over HTTP. The client address it reports is the request's peer address, and a Content-Length is added
+// to bodies that arrive without one, since the event carries the whole body
+pub async fn answer(routes: &SharedRoutes, request: Request) -> Result<Response<LambdaBody>, Error> {
+    let peer = source_ip(&request).map(|ip| SocketAddr::new(ip, 0));
+    let (mut parts, body) = request.into_parts();
+    let body = match body {
+        LambdaBody::Empty => Vec::new(),
+        LambdaBody::Text(text) => text.into_bytes(),
+        LambdaBody::Binary(bytes) => bytes,
+    };
+    if !body.is_empty() && !parts.headers.contains_key(CONTENT_LENGTH) {
+        parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+    }
+    let mut request = warp::http::Request::from_parts(parts, Body::from(body));
+    if let Some(peer) = peer {
+        request.extensions_mut().insert(PeerAddr(peer));
+    }
+    let Ok(response) = routes.answer(request).await;
+    let (parts, body) = response.into_parts();
+    let body = warp::hyper::body::to_bytes(body).await?;
+    // Compressed and other binary bodies are sent base64-encoded, and text as it is
+    let body = match (parts.headers.contains_key(CONTENT_ENCODING), String::from_utf8(body.to_vec())) {
+        (_, Ok(text)) if text.is_empty() => LambdaBody::Empty,
+        (false, Ok(text)) => LambdaBody::Text(text),
+        _ => LambdaBody::Binary(body.to_vec()),
+    };
+    Ok(Response::from_parts(parts, body))
+}
+
+// Read the address of the client that sent a request from its API Gateway request context; load
+// balancers only report it in X-Forwarded-For
+fn source_ip(request: &Request) -> Option<IpAddr> {
+    let source_ip = match request.request_context_ref()? {
+        RequestContext::ApiGatewayV2(context) => context.http.source_ip.as_deref(),
+        RequestContext::ApiGatewayV1(context) => context.identity.source_ip.as_deref(),
+        _ => None,
+    };
+    source_ip?.parse().ok()
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reload::Routes;
+    use warp::{Filter, Reply};
+
+    // Define a test that invocations are answered by the routes, with the client address and body length
+    // they would have over HTTP
+    #[tokio::test]
+    async fn test_answer() {
+        let routes: Routes = warp::path("graphql")
+            .and(crate::rate_limit::peer_addr())
+            .and(warp::body::content_length_limit(64))
+            .and(warp::body::bytes())
+            .map(|peer: Option<SocketAddr>, body: warp::hyper::body::Bytes| Box::new(format!("{} {}", peer.unwrap().ip(), String::from_utf8_lossy(&body))) as Box<dyn Reply>)
+            .boxed();
+        let routes = SharedRoutes::new(routes, None);
+        let event = serde_json::json!({
+            "version": "2.0",
+            "rawPath": "/graphql",
+            "rawQueryString": "",
+            "headers": { "content-type": "application/json" },
+            "requestContext": { "http": { "method": "POST", "path": "/graphql", "sourceIp": "203.0.113.7" } },
+            "body": "{\"query\":\"{ __typename }\"}",
+            "isBase64Encoded": false
+        });
+        let request = lambda_http::request::from_str(&event.to_string()).unwrap();
+        let response = answer(&routes, request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body(), &LambdaBody::Text("203.0.113.7 {\"query\":\"{ __typename }\"}".to_string()));
+    }
+}
@@ -0,0 +1,118 @@
+/*
+Library crate for the Rust GraphQL server.
+
+-admin: token-protected admin routes, including the user export
+-audit: the audit log of mutations and the stores that keep it
+-auth: bearer tokens and the auth context of each GraphQL request
+-axum_routes: the POST /graphql endpoint served with axum, in front of the warp routes (with the axum feature)
+-avatar: avatar image validation, storage in S3 or on disk, and signed URLs
+-cache: read-through cache layer in front of the repository
+-cli: the command line: serving, migrating, exporting the schema, and seeding
+-complexity: the cost of queries, with list fields charged per item, and the extension that enforces a budget
+-compression: brotli and gzip compression of GraphQL responses for clients that accept them
+-config: the typed settings layered from the defaults, the config file, and the environment, and the address the server listens on
+-cors: the origins, methods, and headers browsers may call the server with from other origins
+-csrf: CSRF tokens for session cookies and the extension that refuses mutations without them
+-environment: the deployment environment and the developer tools (introspection and playground) it enables
+-error: the errors resolvers report and the codes clients see
+-health: the liveness probe, and the readiness probe for the schema, backing store, and migrations
+-http_get: running queries sent with GET, and the extension that refuses other operations from them
+-import: bulk user import from CSV
+-input_limits: the longest strings and lists, and the most aliases, an operation may send
+-ip_filter: the client address ranges allowed or denied, and the filter that refuses the rest
+-jwt: verifying JWT bearer tokens against HS256 secrets and RS256 keys
+-lambda: answering API Gateway invocations of an AWS Lambda function with the routes (with the lambda feature)
+-logging: the log level and the macros that log at it
+-login_throttle: backoff and lockout after failed password logins, per account and client address
+-loader: DataLoader batching user lookups within a request
+-masking: the @masked directive and the extension that masks fields for callers without its role
+-metrics: Prometheus metrics for the connection pool and cache
+-model: domain types exposed through the schema
+-node: global object IDs and refetching objects by them
+-oidc: logging in through an OpenID Connect provider and starting a session (with the oidc feature)
+-password_reset: signed, single-use links that let users who forgot their password choose a new one
+-permissions: field permissions that withhold values from callers who may not read them, and the extension that reports them
+-persisted: persisted queries run by hash, automatic persisted queries clients register, and the mode that only runs them
+-rate_limit: per-IP and per-principal token buckets in front of /graphql
+-outbox: change events recorded with every write and relayed to a sink
+-reload: reloading the log level, rate limits, and CORS settings on SIGHUP, and the routes a reload swaps
+-repository: storage abstraction and its backends
+-request_limits: the largest request body and batch, and the longest an operation may run before it is cancelled
+-response_cache: @cacheControl hints, the cache policy and Cache-Control header they give responses, and the cache of public responses
+-runtime: the Tokio runtime the server runs on: its flavor, worker threads, and blocking threads
+-scalars: custom GraphQL scalars
+-schema: the GraphQL schema, merged from the query and mutation resolvers of each domain (users, posts, organizations, admin)
+-search: free-text user search results and the substring fallback
+-secrets: secret settings read from files and Vault, and the check that the required ones are set
+-seed: loading initial users from a JSON file
+-service_accounts: the service accounts configured with the server
+-shutdown: stopping on SIGTERM or SIGINT after the requests in flight finish
+-signing: HMAC signatures over timestamped request bodies that authenticate server-to-server API clients
+-subscription: GraphQL subscriptions and the user events mutations publish
+-tls: serving HTTPS with a certificate that reloads on SIGHUP, verifying client certificates, and redirecting plain HTTP to it (with the tls feature)
+-totp: TOTP codes, secrets, and recovery codes for two-factor authentication
+-unix_socket: serving on a Unix domain socket instead of TCP, for reverse proxies on the same host (on Unix)
+-upload: limits on files sent with multipart GraphQL requests
+-verification: email verification links for registered users and the mailers that send them
+-websocket: the subscription routes at /graphql/ws and /graphql, with connection_init authentication and keep-alive pings
+*/
+
+pub mod admin;
+pub mod audit;
+pub mod auth;
+pub mod avatar;
+#[cfg(feature = "axum")]
+pub mod axum_routes;
+pub mod cache;
+pub mod cli;
+pub mod complexity;
+pub mod compression;
+pub mod config;
+pub mod cors;
+pub mod csrf;
+pub mod environment;
+pub mod error;
+pub mod health;
+pub mod http_get;
+pub mod import;
+pub mod input_limits;
+pub mod ip_filter;
+pub mod jwt;
+#[cfg(feature = "lambda")]
+pub mod lambda;
+pub mod loader;
+pub mod logging;
+pub mod login_throttle;
+pub mod masking;
+pub mod metrics;
+pub mod model;
+pub mod node;
+#[cfg(feature = "oidc")]
+pub mod oidc;
+pub mod outbox;
+pub mod password_reset;
+pub mod permissions;
+pub mod persisted;
+pub mod rate_limit;
+pub mod reload;
+pub mod repository;
+pub mod request_limits;
+pub mod response_cache;
+pub mod runtime;
+pub mod scalars;
+pub mod schema;
+pub mod search;
+pub mod secrets;
+pub mod seed;
+pub mod service_accounts;
+pub mod shutdown;
+pub mod signing;
+pub mod subscription;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod totp;
+#[cfg(unix)]
+pub mod unix_socket;
+pub mod upload;
+pub mod verification;
+pub mod websocket;
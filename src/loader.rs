@@ -0,0 +1,314 @@
+// Import necessary libraries and modules
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::model::{Membership, Organization, Post, PostPage, User};
+use crate::repository::{RepositoryError, SharedRepository};
+
+// Define a UserLoader that batches user lookups into a single repository call
+pub struct UserLoader {
+    repository: SharedRepository,
+}
+
+impl UserLoader {
+    // Create a loader over the given repository
+    pub fn new(repository: SharedRepository) -> Self {
+        UserLoader { repository }
+    }
+}
+
+// Define the DataLoader type stored in the schema data
+pub type UserDataLoader = DataLoader<UserLoader>;
+
+// Build a DataLoader that spawns its batch loads on the Tokio runtime
+pub fn user_data_loader(repository: SharedRepository) -> UserDataLoader {
+    DataLoader::new(UserLoader::new(repository), tokio::spawn)
+}
+
+// Implement batch loading by ID
+#[async_trait]
+impl Loader<String> for UserLoader {
+    type Value = User;
+    type Error = Arc<RepositoryError>;
+
+    async fn load(&self, keys: &[String]) -> Result<HashMap<String, User>, Self::Error> {
+        self.repository.get_many(keys).await.map_err(Arc::new)
+    }
+}
+
+// Define a PostLoader that batches pages of posts of several authors into a single repository call
+pub struct PostLoader {
+    repository: SharedRepository,
+}
+
+// Define the DataLoader type for posts stored in the schema data
+pub type PostDataLoader = DataLoader<PostLoader>;
+
+// Build a DataLoader for posts that spawns its batch loads on the Tokio runtime
+pub fn post_data_loader(repository: SharedRepository) -> PostDataLoader {
+    DataLoader::new(PostLoader { repository }, tokio::spawn)
+}
+
+// Implement batch loading by page, so users paging on different cursors still share one query;
+// backends without post storage have no posts to load
+#[async_trait]
+impl Loader<PostPage> for PostLoader {
+    type Value = Vec<Post>;
+    type Error = Arc<RepositoryError>;
+
+    async fn load(&self, keys: &[PostPage]) -> Result<HashMap<PostPage, Vec<Post>>, Self::Error> {
+        match self.repository.posts() {
+            Some(posts) => posts.posts_by_author_pages(keys).await.map_err(Arc::new),
+            None => Ok(HashMap::new()),
+        }
+    }
+}
+
+// Define an OrganizationLoader that batches organization lookups into a single repository call
+pub struct OrganizationLoader {
+    repository: SharedRepository,
+}
+
+// Define the DataLoader type for organizations stored in the schema data
+pub type OrganizationDataLoader = DataLoader<OrganizationLoader>;
+
+// Build a DataLoader for organizations that spawns its batch loads on the Tokio runtime
+pub fn organization_data_loader(repository: SharedRepository) -> OrganizationDataLoader {
+    DataLoader::new(OrganizationLoader { repository }, tokio::spawn)
+}
+
+// Implement batch loading by organization ID
+#[async_trait]
+impl Loader<String> for OrganizationLoader {
+    type Value = Organization;
+    type Error = Arc<RepositoryError>;
+
+    async fn load(&self, keys: &[String]) -> Result<HashMap<String, Organization>, Self::Error> {
+        match self.repository.organizations() {
+            Some(organizations) => organizations.get_organizations(keys).await.map_err(Arc::new),
+            None => Ok(HashMap::new()),
+        }
+    }
+}
+
+// Define a MembershipLoader that batches the memberships of several users into a single repository call
+pub struct MembershipLoader {
+    repository: SharedRepository,
+}
+
+// Define the DataLoader type for memberships stored in the schema data
+pub type MembershipDataLoader = DataLoader<MembershipLoader>;
+
+// Build a DataLoader for memberships that spawns its batch loads on the Tokio runtime
+pub fn membership_data_loader(repository: SharedRepository) -> MembershipDataLoader {
+    DataLoader::new(MembershipLoader { repository }, tokio::spawn)
+}
+
+// Implement batch loading by user ID
+#[async_trait]
+impl Loader<String> for MembershipLoader {
+    type Value = Vec<Membership>;
+    type Error = Arc<RepositoryError>;
+
+    async fn load(&self, keys: &[String]) -> Result<HashMap<String, Vec<Membership>>, Self::Error> {
+        match self.repository.organizations() {
+            Some(organizations) => organizations.memberships_by_users(keys).await.map_err(Arc::new),
+            None => Ok(HashMap::new()),
+        }
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{legacy_user_id, Comment, NewComment, NewPost, NewUser, UserUpdate};
+    use crate::repository::{InMemoryRepository, PostRepository, RepositoryResult, UserRepository};
+    use crate::schema::{build_schema, AppState};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Define a repository wrapper that counts how many storage calls are made
+    struct CountingRepository {
+        inner: InMemoryRepository,
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl UserRepository for CountingRepository {
+        async fn get(&self, id: &str) -> RepositoryResult<Option<User>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.inner.get(id).await
+        }
+
+        async fn get_many(&self, ids: &[String]) -> RepositoryResult<HashMap<String, User>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.inner.get_many(ids).await
+        }
+
+        async fn get_including_deleted(&self, id: &str) -> RepositoryResult<Option<User>> {
+            self.inner.get_including_deleted(id).await
+        }
+
+        async fn list(&self) -> RepositoryResult<Vec<User>> {
+            self.inner.list().await
+        }
+
+        async fn list_including_deleted(&self) -> RepositoryResult<Vec<User>> {
+            self.inner.list_including_deleted().await
+        }
+
+        async fn create(&self, new_user: NewUser) -> RepositoryResult<User> {
+            self.inner.create(new_user).await
+        }
+
+        async fn update(&self, id: &str, update: UserUpdate) -> RepositoryResult<Option<User>> {
+            self.inner.update(id, update).await
+        }
+
+        async fn delete(&self, id: &str) -> RepositoryResult<Option<User>> {
+            self.inner.delete(id).await
+        }
+
+        async fn restore(&self, id: &str) -> RepositoryResult<Option<User>> {
+            self.inner.restore(id).await
+        }
+
+        async fn purge(&self, id: &str) -> RepositoryResult<Option<User>> {
+            self.inner.purge(id).await
+        }
+
+        fn posts(&self) -> Option<&dyn PostRepository> {
+            Some(self)
+        }
+    }
+
+    #[async_trait]
+    impl PostRepository for CountingRepository {
+        async fn create_post(&self, new_post: NewPost) -> RepositoryResult<Post> {
+            self.inner.create_post(new_post).await
+        }
+
+        async fn get_post(&self, id: &str) -> RepositoryResult<Option<Post>> {
+            self.inner.get_post(id).await
+        }
+
+        async fn search_posts(&self, query: &str, limit: usize) -> RepositoryResult<Vec<Post>> {
+            self.inner.search_posts(query, limit).await
+        }
+
+        async fn posts_by_authors(&self, author_ids: &[String]) -> RepositoryResult<HashMap<String, Vec<Post>>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.inner.posts_by_authors(author_ids).await
+        }
+
+        async fn create_comment(&self, new_comment: NewComment) -> RepositoryResult<Comment> {
+            self.inner.create_comment(new_comment).await
+        }
+
+        async fn get_comment(&self, id: &str) -> RepositoryResult<Option<Comment>> {
+            self.inner.get_comment(id).await
+        }
+
+        async fn list_comments(
+            &self,
+            post_id: &str,
+            parent_id: Option<&str>,
+            after: Option<&str>,
+            limit: usize,
+        ) -> RepositoryResult<Vec<Comment>> {
+            self.inner.list_comments(post_id, parent_id, after, limit).await
+        }
+    }
+
+    // Define a test that several user lookups in one request share a single storage call
+    #[tokio::test]
+    async fn test_lookups_are_batched() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let repository = CountingRepository {
+            inner: InMemoryRepository::with_sample_users(),
+            calls: calls.clone(),
+        };
+        let schema = build_schema(AppState::new(Arc::new(repository)));
+
+        let response = schema
+            .execute(r#"{ a: userById(id: "1") { name } b: userById(id: "2") { name } c: userById(id: "9") { name } }"#)
+            .await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(
+            response_data,
+            serde_json::json!({ "a": { "name": "Pavel" }, "b": { "name": "Charlie" }, "c": null })
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    // Define a test that the posts of every listed user, and the authors of those posts, take one storage call each
+    #[tokio::test]
+    async fn test_nested_posts_are_batched() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let inner = InMemoryRepository::with_sample_users();
+        for (author, title) in [(1, "First"), (2, "Second"), (1, "Third")] {
+            let new_post = NewPost { author_id: legacy_user_id(author).to_string(), title: title.to_string(), body: String::new() };
+            inner.create_post(new_post).await.unwrap();
+        }
+        let schema = build_schema(AppState::new(Arc::new(CountingRepository { inner, calls: calls.clone() })));
+
+        let response = schema
+            .execute("{ users { users { name, posts { nodes { title, author { displayName } } } } } }")
+            .await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(
+            response_data["users"]["users"],
+            serde_json::json!([
+                { "name": "Pavel", "posts": { "nodes": [
+                    { "title": "First", "author": { "displayName": "Pavel" } },
+                    { "title": "Third", "author": { "displayName": "Pavel" } },
+                ] } },
+                { "name": "Charlie", "posts": { "nodes": [{ "title": "Second", "author": { "displayName": "Charlie" } }] } },
+            ])
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Users paging on different cursors still share one storage call for their posts, after one for the users
+        let response = schema.execute(r#"{ a: userById(id: "1") { posts(first: 1) { edges { cursor } } } }"#).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        let cursor = response_data["a"]["posts"]["edges"][0]["cursor"].as_str().unwrap().to_string();
+        calls.store(0, Ordering::SeqCst);
+        let query = format!(
+            r#"{{ a: userById(id: "1") {{ posts(first: 1, after: "{}") {{ nodes {{ title }} }} }} b: userById(id: "2") {{ posts(first: 1) {{ nodes {{ title }} }} }} }}"#,
+            cursor
+        );
+        let response = schema.execute(query).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(
+            response_data,
+            serde_json::json!({ "a": { "posts": { "nodes": [{ "title": "Third" }] } }, "b": { "posts": { "nodes": [{ "title": "Second" }] } } })
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    // Define a test that usersByIds keeps the requested order and loads every ID in one storage call
+    #[tokio::test]
+    async fn test_users_by_ids() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let repository = CountingRepository {
+            inner: InMemoryRepository::with_sample_users(),
+            calls: calls.clone(),
+        };
+        let schema = build_schema(AppState::new(Arc::new(repository)).with_max_page_size(4));
+
+        let response = schema.execute(r#"{ usersByIds(ids: ["2", "9", "1", "2"]) { name } }"#).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(
+            response_data,
+            serde_json::json!({ "usersByIds": [{ "name": "Charlie" }, null, { "name": "Pavel" }, { "name": "Charlie" }] })
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Asking for more IDs than a page holds is refused without touching storage
+        let response = schema.execute(r#"{ usersByIds(ids: ["1", "2", "3", "4", "5"]) { name } }"#).await;
+        assert_eq!(response.errors[0].message, "usersByIds accepts at most 4 IDs");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
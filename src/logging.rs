@@ -0,0 +1,100 @@
+// Import necessary libraries and modules
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::config::Settings;
+
+// Define how much the server logs, from only errors to everything; each level includes the ones before it
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+}
+
+// Define the level the server logs at, which a configuration reload can change while it runs
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+impl LogLevel {
+    // Read LOG_LEVEL (error, warn, or info, the default)
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        LogLevel::parse(settings.var("LOG_LEVEL").as_deref())
+    }
+
+    // Read the log level setting, if set
+    pub fn parse(value: Option<&str>) -> Result<Self, String> {
+        match value.map(|value| value.trim().to_ascii_lowercase()).as_deref() {
+            None | Some("info") => Ok(LogLevel::Info),
+            Some("warn") => Ok(LogLevel::Warn),
+            Some("error") => Ok(LogLevel::Error),
+            Some(_) => Err(format!("LOG_LEVEL must be error, warn, or info, got {:?}", value.unwrap_or_default())),
+        }
+    }
+
+    // Return the name the level is set with
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+        }
+    }
+}
+
+// Log at the given level from now on
+pub fn set_log_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+// Tell whether messages of the given level are logged
+pub fn log_enabled(level: LogLevel) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+// Log an error to stderr; errors are always logged
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        if $crate::logging::log_enabled($crate::logging::LogLevel::Error) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+// Log a warning, something that went wrong but was worked around, to stderr
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        if $crate::logging::log_enabled($crate::logging::LogLevel::Warn) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+// Log what the server is doing to stdout
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        if $crate::logging::log_enabled($crate::logging::LogLevel::Info) {
+            println!($($arg)*);
+        }
+    };
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Define a test for reading log levels and which messages each one logs
+    #[test]
+    fn test_log_level() {
+        assert_eq!(LogLevel::parse(None), Ok(LogLevel::Info));
+        assert_eq!(LogLevel::parse(Some(" WARN ")), Ok(LogLevel::Warn));
+        assert_eq!(LogLevel::parse(Some("error")), Ok(LogLevel::Error));
+        assert!(LogLevel::parse(Some("debug")).unwrap_err().contains("LOG_LEVEL must be error, warn, or info"));
+        assert!(LogLevel::Error < LogLevel::Warn && LogLevel::Warn < LogLevel::Info);
+        assert_eq!(LogLevel::Warn.name(), "warn");
+        // The level is shared by the whole process and tests run in parallel, so it is left at the default
+        assert!(log_enabled(LogLevel::Error) && log_enabled(LogLevel::Info));
+    }
+}
@@ -0,0 +1,222 @@
+// Import necessary libraries and modules
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::Settings;
+
+// Define how many accounts and client addresses are tracked before those whose failures have expired
+// are dropped
+const MAX_TRACKED_LOGINS: usize = 100_000;
+
+// Define when failed password logins are throttled. After each failure an account, or a client address,
+// must wait before its next attempt: `backoff` after the first failure, and twice as long after each
+// one since. Once it reaches its maximum number of failures it is locked out for `lockout`. Failures are
+// forgotten after `lockout` passes without another one, and an account's when it logs in. Client
+// addresses are allowed more failures than accounts, since several users can share one
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoginThrottleConfig {
+    pub max_account_failures: u32,
+    pub max_client_failures: u32,
+    pub backoff: Duration,
+    pub lockout: Duration,
+}
+
+impl Default for LoginThrottleConfig {
+    fn default() -> Self {
+        LoginThrottleConfig { max_account_failures: 5, max_client_failures: 20, backoff: Duration::from_secs(1), lockout: Duration::from_secs(15 * 60) }
+    }
+}
+
+impl LoginThrottleConfig {
+    // Read LOGIN_MAX_FAILURES (default 5), LOGIN_MAX_FAILURES_PER_IP (default 20), LOGIN_BACKOFF_SECONDS
+    // (default 1), and LOGIN_LOCKOUT_SECONDS (default 900)
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        let defaults = LoginThrottleConfig::default();
+        let seconds = |name: &str, default: Duration| Ok::<_, String>(positive_var(settings, name)?.map_or(default, |seconds| Duration::from_secs(seconds.into())));
+        Ok(LoginThrottleConfig {
+            max_account_failures: positive_var(settings, "LOGIN_MAX_FAILURES")?.unwrap_or(defaults.max_account_failures),
+            max_client_failures: positive_var(settings, "LOGIN_MAX_FAILURES_PER_IP")?.unwrap_or(defaults.max_client_failures),
+            backoff: seconds("LOGIN_BACKOFF_SECONDS", defaults.backoff)?,
+            lockout: seconds("LOGIN_LOCKOUT_SECONDS", defaults.lockout)?,
+        })
+    }
+}
+
+// Read a positive number from a setting, if it is set
+fn positive_var(settings: &Settings, name: &str) -> Result<Option<u32>, String> {
+    match settings.var(name) {
+        Some(value) => match value.parse() {
+            Ok(number) if number > 0 => Ok(Some(number)),
+            _ => Err(format!("{} must be a positive number, got {:?}", name, value)),
+        },
+        None => Ok(None),
+    }
+}
+
+// Define what failures are counted against: the email address a login was attempted for, whether or not
+// it belongs to a user, so that throttling does not reveal which do, or the client address it came from
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LoginKey {
+    Account(String),
+    Client(IpAddr),
+}
+
+// Define the failures counted against a key and when it may try again
+#[derive(Clone, Copy, Debug)]
+struct Failures {
+    count: u32,
+    last_failure: Instant,
+    retry_at: Instant,
+}
+
+// Define a lockout a failure triggered, for the audit log
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lockout {
+    pub key: LoginKey,
+    pub failures: u32,
+    pub duration: Duration,
+}
+
+// Define the throttle shared by every login; failures are counted in memory, so every instance of the
+// server throttles on its own
+#[derive(Clone, Default)]
+pub struct LoginThrottle {
+    config: Arc<LoginThrottleConfig>,
+    failures: Arc<Mutex<HashMap<LoginKey, Failures>>>,
+}
+
+impl LoginThrottle {
+    // Create a throttle with no failures counted
+    pub fn new(config: LoginThrottleConfig) -> Self {
+        LoginThrottle { config: Arc::new(config), failures: Arc::default() }
+    }
+
+    // Return the keys a login for the email address from the client address is counted against
+    fn keys(email: &str, client: Option<IpAddr>) -> Vec<LoginKey> {
+        let account = LoginKey::Account(email.trim().to_lowercase());
+        std::iter::once(account).chain(client.map(LoginKey::Client)).collect()
+    }
+
+    // Let a login for the email address from the client address go ahead, or return how long until it may
+    // be attempted, if the account or the address must wait
+    pub fn check(&self, email: &str, client: Option<IpAddr>, now: Instant) -> Result<(), Duration> {
+        let failures = self.failures.lock().unwrap();
+        let wait = LoginThrottle::keys(email, client)
+            .iter()
+            .filter_map(|key| failures.get(key))
+            .map(|failures| failures.retry_at.saturating_duration_since(now))
+            .max()
+            .unwrap_or_default();
+        if wait.is_zero() {
+            Ok(())
+        } else {
+            Err(wait)
+        }
+    }
+
+    // Count a failed login for the email address from the client address, and return the lockouts it
+    // triggered
+    pub fn record_failure(&self, email: &str, client: Option<IpAddr>, now: Instant) -> Vec<Lockout> {
+        let config = &self.config;
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() >= MAX_TRACKED_LOGINS {
+            failures.retain(|_, failures| now.saturating_duration_since(failures.last_failure) < config.lockout);
+        }
+        let mut lockouts = Vec::new();
+        for key in LoginThrottle::keys(email, client) {
+            let max_failures = match key {
+                LoginKey::Account(_) => config.max_account_failures,
+                LoginKey::Client(_) => config.max_client_failures,
+            };
+            let entry = failures.entry(key.clone()).or_insert(Failures { count: 0, last_failure: now, retry_at: now });
+            if now.saturating_duration_since(entry.last_failure) >= config.lockout {
+                entry.count = 0;
+            }
+            entry.count += 1;
+            entry.last_failure = now;
+            let wait = if entry.count >= max_failures {
+                config.lockout
+            } else {
+                config.backoff.saturating_mul(1 << (entry.count - 1).min(31)).min(config.lockout)
+            };
+            entry.retry_at = now + wait;
+            // Failures that raced past the check while locked out extend the lockout without reporting it again
+            if entry.count == max_failures {
+                lockouts.push(Lockout { key, failures: entry.count, duration: config.lockout });
+            }
+        }
+        lockouts
+    }
+
+    // Forget the failures counted against the email address once its user logs in. Those counted against
+    // the client address are kept, so logging in to one account does not buy more guesses at others
+    pub fn record_success(&self, email: &str) {
+        let account = LoginKey::Account(email.trim().to_lowercase());
+        self.failures.lock().unwrap().remove(&account);
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build a throttle locking accounts out after 3 failures and client addresses after 4
+    fn throttle() -> LoginThrottle {
+        LoginThrottle::new(LoginThrottleConfig { max_account_failures: 3, max_client_failures: 4, backoff: Duration::from_secs(1), lockout: Duration::from_secs(60) })
+    }
+
+    // Define a test that the wait doubles after each failure until the account is locked out
+    #[test]
+    fn test_backoff_and_lockout() {
+        let throttle = throttle();
+        let start = Instant::now();
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+        assert_eq!(throttle.check("ada@example.com", None, start), Ok(()));
+        assert_eq!(throttle.record_failure("ada@example.com", None, start), Vec::new());
+        assert_eq!(throttle.check("ADA@example.com", None, start), Err(Duration::from_secs(1)));
+        assert_eq!(throttle.check("ada@example.com", None, at(1)), Ok(()));
+        assert_eq!(throttle.record_failure("ada@example.com", None, at(1)), Vec::new());
+        assert_eq!(throttle.check("ada@example.com", None, at(2)), Err(Duration::from_secs(1)));
+        assert_eq!(throttle.check("ada@example.com", None, at(3)), Ok(()));
+
+        // Other accounts are not held up
+        assert_eq!(throttle.check("grace@example.com", None, at(1)), Ok(()));
+
+        // The third failure locks the account out, and only reports it once
+        let lockout = Lockout { key: LoginKey::Account("ada@example.com".to_string()), failures: 3, duration: Duration::from_secs(60) };
+        assert_eq!(throttle.record_failure("ada@example.com", None, at(3)), vec![lockout]);
+        assert_eq!(throttle.check("ada@example.com", None, at(33)), Err(Duration::from_secs(30)));
+        assert_eq!(throttle.record_failure("ada@example.com", None, at(33)), Vec::new());
+
+        // Once the lockout is over the failures are forgotten
+        assert_eq!(throttle.check("ada@example.com", None, at(93)), Ok(()));
+        assert_eq!(throttle.record_failure("ada@example.com", None, at(93)), Vec::new());
+        assert_eq!(throttle.check("ada@example.com", None, at(94)), Ok(()));
+
+        // Logging in forgets them too
+        throttle.record_success("ada@example.com");
+        assert_eq!(throttle.check("ada@example.com", None, at(93)), Ok(()));
+    }
+
+    // Define a test that failures from one client address against many accounts lock the address out
+    #[test]
+    fn test_client_lockout() {
+        let throttle = throttle();
+        let now = Instant::now();
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        for (index, email) in ["a@example.com", "b@example.com", "c@example.com"].iter().enumerate() {
+            let later = now + Duration::from_secs(index as u64 * 10);
+            assert_eq!(throttle.check(email, Some(client), later), Ok(()));
+            assert_eq!(throttle.record_failure(email, Some(client), later), Vec::new());
+        }
+        throttle.record_success("a@example.com");
+        let later = now + Duration::from_secs(30);
+        let lockouts = throttle.record_failure("d@example.com", Some(client), later);
+        assert_eq!(lockouts, vec![Lockout { key: LoginKey::Client(client), failures: 4, duration: Duration::from_secs(60) }]);
+        assert_eq!(throttle.check("e@example.com", Some(client), later), Err(Duration::from_secs(60)));
+        assert_eq!(throttle.check("e@example.com", Some("192.0.2.2".parse().unwrap()), later), Ok(()));
+    }
+}
@@ -15,143 +15,733 @@ Application Info:
 */
 
 // Import necessary libraries and modules
-use async_graphql::{EmptyMutation, EmptySubscription, Object, Result, Schema};
-use async_graphql_warp::graphql;
-use warp::{Filter, Rejection};
-use async_graphql::http::playground_source;
-use async_graphql::http::GraphQLPlaygroundConfig;
+use async_graphql::BatchRequest;
+use clap::Parser;
+use async_graphql_warp::graphql_batch_opts;
+use warp::hyper::body::Bytes;
+use warp::hyper::server::conn::AddrStream;
+use warp::hyper::service::{make_service_fn, service_fn};
+use warp::hyper::Server;
+use warp::{Filter, Rejection, Reply};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-// Define a User struct to represent a user with id, name, and email fields
-#[derive(Clone)]
-struct User {
-    id: String,
-    name: String,
-    email: String,
+use rust_graphql_server::{log_error, log_info, log_warn};
+use rust_graphql_server::admin::{admin_token_from_settings, export_route};
+use rust_graphql_server::audit::InMemoryAuditStore;
+use rust_graphql_server::auth::{AuthContext, Authenticator, Sessions};
+use rust_graphql_server::cli::{Cli, Command, ServeArgs};
+use rust_graphql_server::compression::{accept_encoding, compress_response, CompressionConfig};
+use rust_graphql_server::config::{ServerConfig, SessionStore, Settings};
+use rust_graphql_server::cors::CorsConfig;
+use rust_graphql_server::csrf::CsrfPolicy;
+use rust_graphql_server::environment::{playground_route, DevTools};
+use rust_graphql_server::jwt::JwtSigner;
+use rust_graphql_server::logging::{set_log_level, LogLevel};
+use rust_graphql_server::login_throttle::{LoginThrottle, LoginThrottleConfig};
+use rust_graphql_server::avatar::{avatar_route, signing_key_from_settings, AvatarBackend, AvatarConfig, Avatars, LocalAvatarStore, SharedAvatarStore};
+use rust_graphql_server::health::{liveness_route, ready_route, Readiness, READY_TIMEOUT};
+use rust_graphql_server::http_get::{get_request, get_response, with_etag};
+use rust_graphql_server::input_limits::InputLimits;
+use rust_graphql_server::ip_filter::{ip_filter, recover_ip_denied, IpFilter};
+use rust_graphql_server::metrics::metrics_route;
+use rust_graphql_server::outbox::{spawn_relay, OutboxConfig};
+use rust_graphql_server::password_reset::PasswordReset;
+use rust_graphql_server::persisted::PersistedQueries;
+use rust_graphql_server::repository::{unsupported_backend, SharedRepository};
+use rust_graphql_server::request_limits::{recover_body_limit, RequestLimits};
+use rust_graphql_server::response_cache::ResponseCache;
+use rust_graphql_server::runtime::RuntimeConfig;
+#[cfg(feature = "memory")]
+use rust_graphql_server::repository::InMemoryRepository;
+use rust_graphql_server::schema::{build_schema, schema_sdl, AppSchema, AppState};
+use rust_graphql_server::search::SharedSearchIndex;
+use rust_graphql_server::secrets::load_secrets;
+use rust_graphql_server::rate_limit::{admit_batch, forwarded_for, peer_addr, rate_limit, recover_rate_limited, signed_rate_limit, with_quota, PeerAddr, Quota, RateLimitConfig, RateLimiter};
+#[cfg(unix)]
+use rust_graphql_server::reload::Reloader;
+use rust_graphql_server::reload::SharedRoutes;
+use rust_graphql_server::seed::load_seed;
+use rust_graphql_server::service_accounts::parse_service_accounts;
+use rust_graphql_server::shutdown::{drain, drain_timeout_from_settings, shutdown_signal};
+use rust_graphql_server::signing::parse_signed_request;
+use rust_graphql_server::totp::TotpIssuer;
+#[cfg(unix)]
+use rust_graphql_server::unix_socket::{UnixSocket, UnixSocketConfig};
+use rust_graphql_server::upload::{recover_bad_request, UploadLimits};
+use rust_graphql_server::verification::EmailVerification;
+use rust_graphql_server::websocket::{keep_alive_from_settings, websocket_route};
+#[cfg(feature = "redis-cache")]
+use rust_graphql_server::cache::{CacheConfig, CachedRepository, RedisCache};
+use rust_graphql_server::repository::DatabaseConfig;
+#[cfg(feature = "dynamodb")]
+use rust_graphql_server::repository::DynamoRepository;
+#[cfg(feature = "mongodb")]
+use rust_graphql_server::repository::MongoRepository;
+#[cfg(feature = "postgres")]
+use rust_graphql_server::repository::{PostgresRepository, ReplicatedRepository};
+#[cfg(feature = "sqlite")]
+use rust_graphql_server::repository::SqliteRepository;
+#[cfg(feature = "elasticsearch")]
+use rust_graphql_server::search::{ElasticsearchIndex, IndexedRepository, SearchConfig};
+#[cfg(feature = "s3")]
+use rust_graphql_server::avatar::S3AvatarStore;
+#[cfg(feature = "oidc")]
+use rust_graphql_server::oidc::{login_routes, OidcConfig, OidcProvider};
+#[cfg(feature = "axum")]
+use rust_graphql_server::axum_routes::AxumRoutes;
+#[cfg(feature = "lambda")]
+use rust_graphql_server::lambda::{in_lambda, run as run_lambda};
+#[cfg(feature = "tls")]
+use rust_graphql_server::tls::{redirect_route, Certificates, TlsConfig};
+#[cfg(feature = "tls")]
+use tokio::net::TcpListener;
+
+// Report whether DATABASE_URL names a database rather than the in-memory backend
+fn uses_database(settings: &Settings) -> bool {
+    settings.var("DATABASE_URL").is_some_and(|url| !url.is_empty() && !url.starts_with("memory:"))
+}
+
+// Choose the storage backend from the DATABASE_URL scheme among those compiled in. Without DATABASE_URL,
+// or with `memory:`, an empty in-memory repository is used; anything else exits listing the compiled-in backends
+async fn build_repository(settings: &Settings) -> SharedRepository {
+    #[cfg(feature = "memory")]
+    if !uses_database(settings) {
+        return Arc::new(InMemoryRepository::new());
+    }
+
+    if uses_database(settings) {
+        let config = DatabaseConfig::from_settings(settings).expect("Invalid database configuration");
+        let replicas = config.replicas_from_settings(settings);
+
+        // SQL backends apply pending migrations while connecting; replicas are never migrated
+        #[cfg(feature = "postgres")]
+        if config.url.starts_with("postgres:") || config.url.starts_with("postgresql:") {
+            let repository = PostgresRepository::connect(&config)
+                .await
+                .expect("Failed to connect to PostgreSQL");
+            if replicas.is_empty() {
+                return Arc::new(repository);
+            }
+
+            // Queries go to the replicas in turn; mutations and transactions go to the primary
+            let replicas = replicas
+                .iter()
+                .map(|replica| {
+                    let replica = PostgresRepository::connect_replica(replica).expect("Invalid read replica URL");
+                    Arc::new(replica) as SharedRepository
+                })
+                .collect();
+            return Arc::new(ReplicatedRepository::new(Arc::new(repository), replicas));
+        }
+
+        // Other backends replicate inside the database itself (e.g. MongoDB read preferences)
+        assert!(replicas.is_empty(), "DATABASE_REPLICA_URLS is only supported with PostgreSQL");
+
+        #[cfg(feature = "sqlite")]
+        if config.url.starts_with("sqlite:") {
+            let repository = SqliteRepository::connect(&config)
+                .await
+                .expect("Failed to open SQLite database");
+            return Arc::new(repository);
+        }
+
+        #[cfg(feature = "mongodb")]
+        if config.url.starts_with("mongodb:") || config.url.starts_with("mongodb+srv:") {
+            let repository = MongoRepository::connect(&config)
+                .await
+                .expect("Failed to connect to MongoDB");
+            return Arc::new(repository);
+        }
+
+        // DynamoDB reads its region and credentials from the standard AWS environment
+        #[cfg(feature = "dynamodb")]
+        if config.url.starts_with("dynamodb:") {
+            let repository = DynamoRepository::connect(&config)
+                .await
+                .expect("Failed to connect to DynamoDB");
+            return Arc::new(repository);
+        }
+
+    }
+
+    log_error!("{}", unsupported_backend(&settings.var("DATABASE_URL").unwrap_or_default()));
+    std::process::exit(1);
 }
 
-// Implement GraphQL Object for the User struct
-#[Object]
-impl User {
-    async fn id(&self) -> &str {
-        &self.id
+// Keep an Elasticsearch or OpenSearch index in step with the repository when compiled in and ELASTICSEARCH_URL is set
+async fn build_search(settings: &Settings, repository: SharedRepository) -> (SharedRepository, Option<SharedSearchIndex>) {
+    #[cfg(feature = "elasticsearch")]
+    if let Some(config) = SearchConfig::from_settings(settings) {
+        let index: SharedSearchIndex = Arc::new(
+            ElasticsearchIndex::connect(&config)
+                .await
+                .expect("Failed to connect to the search engine"),
+        );
+        return (Arc::new(IndexedRepository::new(repository, index.clone())), Some(index));
     }
 
-    async fn name(&self) -> &str {
-        &self.name
+    #[cfg(not(feature = "elasticsearch"))]
+    let _ = settings;
+    (repository, None)
+}
+
+// Use SEED_FILE when set; without a database the bundled seed.json is used if present
+fn seed_path(settings: &Settings) -> Option<PathBuf> {
+    if let Some(path) = &settings.seed_file {
+        return Some(path.clone());
     }
+    let bundled = PathBuf::from("seed.json");
+    (!uses_database(settings) && bundled.exists()).then_some(bundled)
+}
 
-    async fn email(&self) -> &str {
-        &self.email
+// Load the seed file into an empty repository, refusing to start on bad data unless told to ignore it
+async fn seed_repository(repository: &SharedRepository, path: &Path, ignore_errors: bool) {
+    match load_seed(path, repository.as_ref(), ignore_errors).await {
+        Ok(0) => {}
+        Ok(count) => log_info!("Seeded {} users from {}", count, path.display()),
+        Err(error) if ignore_errors => log_warn!("Ignoring seed data: {}", error),
+        Err(error) => {
+            log_error!("{}", error);
+            log_error!("Fix the seed file or pass --ignore-seed-errors to skip the bad records");
+            std::process::exit(1);
+        }
     }
 }
 
-// Define a QueryRoot struct for handling GraphQL queries
-struct QueryRoot;
+// Apply USERS_MAX_PAGE_SIZE, MAX_QUERY_DEPTH, MAX_QUERY_COST, SERVICE_ACCOUNTS, and AUDIT_LOG_CAPACITY, and put the Redis cache in front of the repository when compiled in and REDIS_URL is set
+async fn build_state(settings: &Settings, repository: SharedRepository, search_index: Option<SharedSearchIndex>) -> AppState {
+    let mut state = AppState::new(repository);
+    state.search_index = search_index;
+    if let Some(max_page_size) = settings.users_max_page_size {
+        state = state.with_max_page_size(max_page_size.get());
+    }
+    if let Some(max_query_depth) = settings.max_query_depth {
+        state = state.with_max_query_depth(max_query_depth.get());
+    }
+    if let Some(max_query_cost) = settings.max_query_cost {
+        state = state.with_max_query_cost(max_query_cost.get());
+    }
+    if let Some(value) = settings.var("SERVICE_ACCOUNTS") {
+        let service_accounts = parse_service_accounts(&value).unwrap_or_else(|error| panic!("Invalid SERVICE_ACCOUNTS: {}", error));
+        state = state.with_service_accounts(service_accounts);
+    }
+    if let Some(capacity) = settings.audit_log_capacity {
+        state = state.with_audit_store(Arc::new(InMemoryAuditStore::new(capacity.get())));
+    }
+
+    #[cfg(not(feature = "redis-cache"))]
+    if settings.var("REDIS_URL").is_some() {
+        log_error!("REDIS_URL is set but the redis-cache feature is not compiled into this build");
+        std::process::exit(1);
+    }
 
-// Implement GraphQL Object for the QueryRoot struct
-#[Object]
-impl QueryRoot {
-    async fn user_by_id(&self, id: String) -> Result<Option<User>> {
-        // Simulate data retrieval 
-        let user1 = User {
-            id: "1".to_string(),
-            name: "Pavel".to_string(),
-            email: "Pavelboukine@gmail.com".to_string(),
-        };
-        let user2 = User {
-            id: "2".to_string(),
-            name: "Charlie".to_string(),
-            email: "charlie.gracie@noibu.com".to_string(),
-        };
+    #[cfg(feature = "redis-cache")]
+    if let Some(config) = CacheConfig::from_settings(settings).expect("Invalid cache configuration") {
+        let cache = RedisCache::connect(&config.url)
+            .await
+            .expect("Failed to connect to Redis");
+        let cached = CachedRepository::new(state.repository.clone(), Arc::new(cache), config.ttl);
+        let stats = cached.stats();
+        state.repository = Arc::new(cached);
+        state = state.with_cache_stats(stats);
+    }
 
-        // Return a user based on the provided ID
-        match id.as_str() {
-            "1" => Ok(Some(user1)),
-            "2" => Ok(Some(user2)),
-            _ => Ok(None),
+    state
+}
+
+// Keep sessions in memory, or in Redis when SESSION_STORE=redis so that every instance shares them and
+// they outlive restarts
+async fn build_sessions(settings: &Settings) -> Sessions {
+    let sessions = Sessions::from_settings(settings).unwrap_or_else(|error| panic!("Invalid session settings: {}", error));
+    match settings.session_store {
+        SessionStore::Memory => sessions,
+        #[cfg(feature = "redis-cache")]
+        SessionStore::Redis => {
+            let url = settings.var("REDIS_URL").expect("SESSION_STORE=redis needs REDIS_URL");
+            let store = RedisCache::connect(&url).await.expect("Failed to connect to Redis");
+            sessions.with_store(Arc::new(store))
+        }
+        #[cfg(not(feature = "redis-cache"))]
+        SessionStore::Redis => {
+            log_error!("SESSION_STORE=redis needs the redis-cache feature, which is not compiled into this build");
+            std::process::exit(1);
         }
     }
 }
 
-#[tokio::main]
-async fn main() {
-    // Build the GraphQL schema with QueryRoot, EmptyMutation, and EmptySubscription
-    let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription).finish();
+// Enable avatar uploads when AVATAR_STORE is set, returning the local store too so its images can be served
+async fn build_avatars(settings: &Settings) -> (Option<Avatars>, Option<Arc<LocalAvatarStore>>) {
+    let Some(config) = AvatarConfig::from_settings(settings).expect("Invalid avatar configuration") else {
+        return (None, None);
+    };
+    let (store, local): (SharedAvatarStore, _) = match &config.backend {
+        AvatarBackend::Local { directory, base_url } => {
+            let store = Arc::new(
+                LocalAvatarStore::new(directory, base_url, signing_key_from_settings(settings)).expect("Failed to open the avatar directory"),
+            );
+            (store.clone(), Some(store))
+        }
+        #[cfg(feature = "s3")]
+        AvatarBackend::S3 { bucket, prefix } => (Arc::new(S3AvatarStore::connect(bucket, prefix).await), None),
+        #[cfg(not(feature = "s3"))]
+        AvatarBackend::S3 { .. } => panic!("AVATAR_STORE names an S3 bucket, but S3 support is not compiled in"),
+    };
+    (Some(Avatars::new(store, config.max_bytes, config.url_ttl)), local)
+}
 
-// Create a GraphQL endpoint using Warp
-let graphql_endpoint = warp::path("graphql")
-    .and(warp::post())
-    .and(graphql(schema).and_then(|(schema, request): (Schema<QueryRoot, EmptyMutation, EmptySubscription>, async_graphql::Request)| async move {
-        let response = schema.execute(request).await;  // Execute the GraphQL request
-        Ok::<_, Rejection>(warp::reply::json(&response))  // Convert the response to JSON
-    }));
+// Publish change events from the outbox in the background when OUTBOX_SINK is set
+async fn start_outbox_relay(settings: &Settings, repository: SharedRepository) {
+    let Some(config) = OutboxConfig::from_settings(settings).expect("Invalid outbox configuration") else {
+        return;
+    };
+    repository
+        .pending_events(0)
+        .await
+        .expect("OUTBOX_SINK is set but the outbox cannot be read");
+    let sink = config.connect_sink().await.expect("Failed to connect to the outbox sink");
+    spawn_relay(repository, sink, config.poll_interval);
+}
 
-// Create a GraphQL Playground route
-let playground = warp::path("graphql")
-    .and(warp::get())
-    .map(|| warp::reply::html(playground_source(GraphQLPlaygroundConfig::new("/graphql"))));
+// Log browsers in through the OpenID Connect provider when compiled in and OIDC_ISSUER_URL is set
+#[cfg(feature = "oidc")]
+async fn build_oidc_provider(settings: &Settings) -> Option<Arc<OidcProvider>> {
+    let config = OidcConfig::from_settings(settings).unwrap_or_else(|error| panic!("Invalid OIDC configuration: {}", error))?;
+    let provider = OidcProvider::discover(config).await.unwrap_or_else(|error| panic!("Failed to discover the OIDC provider: {}", error));
+    Some(Arc::new(provider))
+}
 
- // Combine GraphQL endpoint and Playground routes into a single Warp filter
-    let routes = warp::any().and(graphql_endpoint.or(playground));
+// Print the schema in SDL, or write it to the given file
+fn export_schema(output: Option<PathBuf>) {
+    let sdl = schema_sdl();
+    match output {
+        Some(path) => std::fs::write(&path, sdl).unwrap_or_else(|error| panic!("Failed to write {}: {}", path.display(), error)),
+        None => print!("{}", sdl),
+    }
+}
 
-    // Serve the routes on the specified address and port
-    warp::serve(routes)
-        .run(([127, 0, 0, 1], 3030))
-        .await;
+// Load the seed file into the database, for databases that are not seeded as the server starts. The
+// in-memory backend starts empty each time, so there is nothing to load it into
+async fn seed(settings: &Settings, file: Option<PathBuf>, ignore_errors: bool) {
+    if !uses_database(settings) {
+        log_error!("The seed command loads a database; set DATABASE_URL, since the in-memory backend is seeded each time the server starts");
+        std::process::exit(1);
+    }
+    let Some(path) = file.or_else(|| seed_path(settings)) else {
+        log_error!("No seed file; pass --file or set SEED_FILE");
+        std::process::exit(1);
+    };
+    let (repository, _) = build_search(settings, build_repository(settings).await).await;
+    if !repository.list_including_deleted().await.expect("Failed to read the repository").is_empty() {
+        log_info!("The database already holds users, so {} was not loaded", path.display());
+        return;
+    }
+    seed_repository(&repository, &path, ignore_errors).await;
 }
 
-// Integration tests
+fn main() {
+    let cli = Cli::parse();
+    let config = cli.config.clone();
+    let command = cli.into_command();
+    if let Command::ExportSchema { output } = command {
+        return export_schema(output);
+    }
+
+    // Command-line options take precedence over the environment, which takes precedence over the config
+    // file
+    let mut figment = Settings::figment(config.as_deref()).unwrap_or_else(|error| panic!("{}", error));
+    if let Command::Serve(ServeArgs { host, port, .. }) = &command {
+        if let Some(host) = host {
+            figment = figment.merge(("HOST", host.to_string()));
+        }
+        if let Some(port) = port {
+            figment = figment.merge(("PORT", port.to_string()));
+        }
+    }
+    let settings = Settings::extract(&figment).unwrap_or_else(|error| panic!("{}", error));
+    set_log_level(LogLevel::from_settings(&settings).unwrap_or_else(|error| panic!("Invalid log settings: {}", error)));
 
-// Define a test for GraphQL queries
-use async_graphql::Request;
+    // Start the runtime once the settings have been read, since it is tuned there too
+    let runtime_config = RuntimeConfig::from_settings(&settings).unwrap_or_else(|error| panic!("Invalid runtime settings: {}", error));
+    let runtime = runtime_config.build().unwrap_or_else(|error| panic!("Failed to start the runtime: {}", error));
+    if let Command::Serve(_) = command {
+        log_info!("Running on a {}", runtime_config);
+    }
+    runtime.block_on(run(command, config, settings));
+}
 
-#[tokio::test]
-async fn test_graphql_query() {
-    let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription).finish();
+// Run a command with the settings, once the secrets mounted as files or kept in Vault are added to them.
+// The config file named on the command line, if any, is read again on reloads
+async fn run(command: Command, config: Option<PathBuf>, mut settings: Settings) {
+    load_secrets(&mut settings).await.unwrap_or_else(|error| panic!("Failed to load secrets: {}", error));
 
-    // Create a request for the "user_by_id" query
-    let request = Request::new(r#"{ "query": "{ user_by_id(id: \"1\") { id, name, email } }" }"#);
+    match command {
+        // Connecting to a SQL backend applies pending migrations
+        Command::Migrate => {
+            build_repository(&settings).await;
+            log_info!("Migrations are up to date");
+        }
+        Command::Seed { file, ignore_seed_errors } => seed(&settings, file, ignore_seed_errors).await,
+        Command::Serve(ServeArgs { ignore_seed_errors, .. }) => serve(&settings, ignore_seed_errors, config).await,
+        Command::ExportSchema { .. } => unreachable!("answered before reading settings"),
+    }
+}
 
-    // Simulate a GraphQL query by executing the request against the schema
-    let response = schema.execute(request).await;
+// Serve the GraphQL API with the given settings, reloading the ones that can change on SIGHUP
+async fn serve(settings: &Settings, ignore_seed_errors: bool, config: Option<PathBuf>) {
+    let server_config = ServerConfig::from_settings(settings);
 
-    // Assert that the response is successful
-    assert_eq!(response.is_ok(), true);
+    // Connecting to a SQL backend applies pending migrations
+    let repository = build_repository(settings).await;
 
-    // Convert the async_graphql::Value to serde_json::Value
-    let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+    // Index writes from here on, so seeded users are searchable too
+    let (repository, search_index) = build_search(settings, repository).await;
 
-    // Assert that the response data matches the expected JSON
-    let expected_response = r#"{
-        "data": {
-            "user_by_id": {
-                "id": "1",
-                "name": "Pavel",
-                "email": "Pavelboukine@gmail.com"
+    // Load initial users before serving any requests
+    if let Some(path) = seed_path(settings) {
+        seed_repository(&repository, &path, ignore_seed_errors).await;
+    }
+
+    // Build the GraphQL schema backed by the configured repository; the readiness probe
+    // pings the same repository, including the cache when there is one
+    let mut state = build_state(settings, repository, search_index).await;
+    start_outbox_relay(settings, state.repository.clone()).await;
+    let (avatars, local_avatars) = build_avatars(settings).await;
+    if let Some(avatars) = avatars {
+        state = state.with_avatars(avatars);
+    }
+    let upload_limits = UploadLimits::from_settings(settings).unwrap_or_else(|error| panic!("Invalid upload limits: {}", error));
+    state = state.with_upload_limits(upload_limits);
+    state = state.with_input_limits(InputLimits::from_settings(settings).unwrap_or_else(|error| panic!("Invalid input limits: {}", error)));
+    let dev_tools = DevTools::from_settings(settings).unwrap_or_else(|error| panic!("Invalid environment settings: {}", error));
+    state = state.with_introspection(dev_tools.introspection);
+    if let Some(persisted_queries) = PersistedQueries::from_settings(settings).unwrap_or_else(|error| panic!("Invalid persisted query settings: {}", error)) {
+        state = state.with_persisted_queries(persisted_queries);
+    }
+    if let Some(response_cache) = ResponseCache::from_settings(settings).unwrap_or_else(|error| panic!("Invalid response cache settings: {}", error)) {
+        state = state.with_response_cache(response_cache);
+    }
+    if let Some(signer) = JwtSigner::from_settings(settings).unwrap_or_else(|error| panic!("Invalid authentication settings: {}", error)) {
+        state = state.with_jwt_signer(signer);
+    }
+    let login_throttle = LoginThrottleConfig::from_settings(settings).unwrap_or_else(|error| panic!("Invalid login throttle settings: {}", error));
+    state = state.with_login_throttle(LoginThrottle::new(login_throttle));
+    state = state.with_totp_issuer(TotpIssuer::from_settings(settings).unwrap_or_else(|error| panic!("Invalid two-factor settings: {}", error)));
+    if let Some(verification) = EmailVerification::from_settings(settings).unwrap_or_else(|error| panic!("Invalid email verification settings: {}", error)) {
+        state = state.with_email_verification(verification);
+    }
+    if let Some(password_reset) = PasswordReset::from_settings(settings).unwrap_or_else(|error| panic!("Invalid password reset settings: {}", error)) {
+        state = state.with_password_reset(password_reset);
+    }
+    let readiness = Readiness::default();
+    let ready = ready_route(state.repository.clone(), readiness.clone(), READY_TIMEOUT);
+    let metrics = metrics_route(state.repository.clone(), state.cache_stats.clone());
+    let avatar_files = avatar_route(local_avatars);
+    let sessions = build_sessions(settings).await;
+    state = state.with_sessions(sessions.clone());
+    let authenticator = Authenticator::from_settings(settings)
+        .unwrap_or_else(|error| panic!("Invalid authentication settings: {}", error))
+        .with_api_keys(state.repository.clone())
+        .with_sessions(sessions.clone())
+        .with_csrf_policy(CsrfPolicy::from_settings(settings).unwrap_or_else(|error| panic!("Invalid CSRF settings: {}", error)));
+    let export = export_route(state.repository.clone(), admin_token_from_settings(settings), authenticator.clone());
+
+    #[cfg(not(feature = "oidc"))]
+    if settings.var("OIDC_ISSUER_URL").is_some() {
+        log_error!("OIDC_ISSUER_URL is set but the oidc feature is not compiled into this build");
+        std::process::exit(1);
+    }
+    #[cfg(feature = "oidc")]
+    let login = login_routes(build_oidc_provider(settings).await, state.repository.clone(), sessions);
+    let limiter_repository = state.repository.clone();
+    let repository = state.repository.clone();
+    let schema = build_schema(state);
+    readiness.mark_schema_built();
+    let get_schema = schema.clone();
+    #[cfg(feature = "axum")]
+    let (axum_schema, axum_authenticator) = (schema.clone(), authenticator.clone());
+    let rate_limiter = RateLimiter::disabled().with_repository(limiter_repository);
+    rate_limiter.reconfigure(RateLimitConfig::from_settings(settings).unwrap_or_else(|error| panic!("Invalid rate limit settings: {}", error)));
+    let signed_limit = signed_rate_limit(Some(rate_limiter.clone()), authenticator.clone());
+    let (websocket_schema, websocket_authenticator) = (schema.clone(), authenticator.clone());
+    let batch_limiter = rate_limiter.clone();
+    let limit = rate_limit(Some(rate_limiter.clone()), authenticator);
+    let compression = CompressionConfig::from_settings(settings).unwrap_or_else(|error| panic!("Invalid compression settings: {}", error));
+    let request_limits = RequestLimits::from_settings(settings).unwrap_or_else(|error| panic!("Invalid request limits: {}", error));
+    let body_limit = request_limits.body_limit(upload_limits);
+    let keep_alive = keep_alive_from_settings(settings).unwrap_or_else(|error| panic!("Invalid WebSocket settings: {}", error));
+
+// Answer GraphQL requests signed by API clients, whose JSON bodies are read here, within the body limit,
+// so the signature can be checked over them. Bodies that are not JSON and clients over their limit are
+// answered here as well, so only requests without an X-Signature header fall through to the endpoint below
+let signed_schema = schema.clone();
+let signed_graphql_endpoint = warp::path("graphql")
+    .and(warp::post())
+    .and(body_limit.clone())
+    .and(signed_limit)
+    .and(accept_encoding())
+    .and_then(move |auth: AuthContext, quota: Option<Quota>, body: Bytes, encodings: Option<String>| {
+        let schema = signed_schema.clone();
+        async move {
+            let request = parse_signed_request(&body)?;
+            let response = request_limits.execute(schema.execute(request.data(auth))).await;
+            Ok::<_, Rejection>(compress_response(compression, encodings.as_deref(), with_quota(response, quota)).await)
+        }
+    })
+    .recover(recover_body_limit)
+    .recover(recover_bad_request)
+    .recover(recover_rate_limited);
+
+// Create a GraphQL endpoint using Warp, telling resolvers who sent each request and the client how much
+// of its quota is left; multipart requests carry files for Upload arguments, and ones over the upload
+// limits are answered with the parser's error. A body may hold an array of operations, which run
+// concurrently and are answered with an array of responses, each operation counting against the rate
+// limit. Bodies over the request limit are refused before they are read, operations that run past the
+// timeout are cancelled, and large responses are compressed for clients that accept brotli or gzip
+let graphql_endpoint = warp::path("graphql")
+    .and(warp::post())
+    .and(body_limit)
+    .and(limit.clone())
+    .and(graphql_batch_opts(schema, upload_limits.multipart_options()))
+    .and(accept_encoding())
+    .and(peer_addr())
+    .and(forwarded_for())
+    .and_then(move |auth: AuthContext, quota: Option<Quota>, (schema, request): (AppSchema, BatchRequest), encodings: Option<String>, peer: Option<SocketAddr>, forwarded_for: Option<String>| {
+        let rate_limiter = batch_limiter.clone();
+        async move {
+            let operations = request.iter().count();
+            let quota = admit_batch(Some(&rate_limiter), peer, forwarded_for.as_deref(), &auth, operations, quota).await.map_err(warp::reject::custom)?;  // Charge a batch for each of its operations
+            let response = request_limits.execute_batch(request.data(auth), |request| schema.execute(request)).await;  // Execute the GraphQL request or batch, cancelling operations past the timeout
+            let response = with_quota(response, quota);  // Add the quota headers to the JSON response, with any headers resolvers set
+            Ok::<_, Rejection>(compress_response(compression, encodings.as_deref(), response).await)  // Compress it for clients that accept brotli or gzip
+        }
+    })
+    .recover(recover_body_limit)
+    .recover(recover_bad_request)
+    .recover(recover_rate_limited);
+
+// Serve subscriptions at /graphql/ws over graphql-transport-ws, and at /graphql for older clients, acting
+// for the credentials sent with connection_init and pinging idle connections
+let websocket = websocket_route(websocket_schema, Some(rate_limiter.clone()), websocket_authenticator, keep_alive).recover(recover_rate_limited);
+
+// Run queries sent with GET, with the operation in the query string, so caches and CDNs can answer repeats;
+// mutations are refused with 405. Cacheable answers get an ETag, and clients that already have them get
+// 304. GET requests without a query are left to the playground
+let get_endpoint = warp::path("graphql")
+    .and(get_request())
+    .and(limit)
+    .and(accept_encoding())
+    .and(warp::header::optional::<String>("if-none-match"))
+    .and_then(move |request: async_graphql::Request, auth: AuthContext, quota: Option<Quota>, encodings: Option<String>, if_none_match: Option<String>| {
+        let schema = get_schema.clone();
+        async move {
+            let response = match request_limits.run(schema.execute(request.data(auth))).await {
+                Ok(response) => get_response(response),
+                Err(timeout) => timeout,
+            };
+            let response = compress_response(compression, encodings.as_deref(), with_quota(response, quota)).await;
+            Ok::<_, Rejection>(with_etag(response, if_none_match.as_deref()).await)
+        }
+    })
+    .recover(recover_bad_request)
+    .recover(recover_rate_limited);
+
+// Create a GraphQL Playground route, which answers with a JSON error when the playground is disabled
+let playground = playground_route(dev_tools.playground);
+
+ // Combine GraphQL endpoints, the subscription route, GET queries, Playground, metrics, admin, and avatar routes into a single Warp filter
+    let routes = warp::any().and(signed_graphql_endpoint.or(graphql_endpoint).or(websocket).or(get_endpoint).or(playground).or(metrics).or(export).or(avatar_files));
+
+    // Add the login routes when compiled in
+    #[cfg(feature = "oidc")]
+    let routes = routes.or(login);
+
+    // Refuse requests from addresses the IP filter does not allow before they reach any route; the
+    // liveness and readiness probes skip the allowlist, so orchestrators and load balancers outside it
+    // can still check the server
+    let ip_rules = IpFilter::from_settings(settings).unwrap_or_else(|error| panic!("Invalid IP filter settings: {}", error));
+    #[cfg(feature = "axum")]
+    let axum_ip_rules = ip_rules.clone();
+    let routes = ip_filter(ip_rules.clone(), true).and(liveness_route().or(ready)).or(ip_filter(ip_rules, false).and(routes)).recover(recover_ip_denied);
+
+    // Answer preflight requests and add the CORS headers when origins are configured. SIGHUP rereads the
+    // config file and swaps in routes with its CORS settings, along with its log level and rate limits
+    let routes = routes.map(|reply| Box::new(reply) as Box<dyn Reply>).boxed();
+    let cors = CorsConfig::from_settings(settings).unwrap_or_else(|error| panic!("Invalid CORS settings: {}", error));
+    let shared_routes = SharedRoutes::new(routes.clone(), cors);
+
+    // With the axum feature, an axum router answers POST requests and subscriptions to /graphql in front
+    // of the routes, which answer the rest
+    #[cfg(feature = "axum")]
+    let shared_routes = {
+        let mut axum_routes = AxumRoutes::new(axum_schema, axum_authenticator)
+            .with_rate_limiter(rate_limiter.clone())
+            .with_request_limits(request_limits)
+            .with_upload_limits(upload_limits);
+        if let Some(compression) = compression {
+            axum_routes = axum_routes.with_compression(compression);
+        }
+        if let Some(ip_rules) = axum_ip_rules {
+            axum_routes = axum_routes.with_ip_filter(ip_rules);
+        }
+        shared_routes.clone().with_router(axum_routes.router(shared_routes))
+    };
+    #[cfg(unix)]
+    Reloader::new(config, rate_limiter, routes, shared_routes.clone()).reload_on_hangup().expect("Failed to listen for SIGHUP");
+    #[cfg(not(unix))]
+    let _ = (config, rate_limiter);
+
+    // In a Lambda function, answer the invocations the runtime hands out instead of listening for
+    // connections. Without the lambda feature the function would wait for connections that never come
+    #[cfg(feature = "lambda")]
+    if in_lambda() {
+        log_info!("Answering Lambda invocations");
+        if let Err(error) = run_lambda(shared_routes).await {
+            log_error!("The Lambda runtime failed: {}", error);
+            std::process::exit(1);
+        }
+        repository.close().await;
+        return;
+    }
+    #[cfg(not(feature = "lambda"))]
+    if std::env::var("AWS_LAMBDA_RUNTIME_API").is_ok() {
+        log_error!("Running in AWS Lambda, but the lambda feature is not compiled into this build");
+        std::process::exit(1);
+    }
+
+    // Serve the routes on the configured address and port until asked to stop. The server then stops
+    // accepting connections and gets SHUTDOWN_TIMEOUT_SECS to finish the requests in flight before the
+    // database connections are closed
+    let drain_timeout = drain_timeout_from_settings(settings).unwrap_or_else(|error| panic!("Invalid shutdown settings: {}", error));
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let mut server = tokio::spawn(serve_routes(settings.clone(), server_config.address, shared_routes, async {
+        stopped.await.ok();
+    }));
+    tokio::select! {
+        _ = shutdown_signal() => {}
+        // The server only stops by itself when it fails to start, which it reports by panicking
+        result = &mut server => {
+            if let Err(error) = result {
+                if error.is_panic() {
+                    std::panic::resume_unwind(error.into_panic());
+                }
             }
+            return;
         }
-    }"#;
-    assert_eq!(response_data, serde_json::json!(expected_response));
+    }
+    let _ = stop.send(());
+    if !drain(server, drain_timeout).await {
+        log_warn!("Requests were still in flight after {} seconds; dropping them", drain_timeout.as_secs());
+    }
+    repository.close().await;
+    log_info!("Shut down");
 }
 
-// Define a test for the GraphQL Playground route
-#[tokio::test]
-async fn test_graphql_playground() {
-    // Create a Warp filter for the playground route
-    let playground_filter = warp::path("graphql")
-        .and(warp::get())
-        .map(|| warp::reply::html(playground_source(GraphQLPlaygroundConfig::new("/graphql"))));
+// Serve the routes on the given address, over HTTPS when a certificate is configured, or on the Unix
+// socket at UNIX_SOCKET_PATH instead when it is set, until `shutdown` completes; then stop accepting
+// connections and return once the open ones have finished their requests
+async fn serve_routes(settings: Settings, address: SocketAddr, routes: SharedRoutes, shutdown: impl Future<Output = ()> + Send + 'static) {
+    #[cfg(not(feature = "tls"))]
+    if settings.var("TLS_CERT_FILE").is_some() {
+        log_error!("TLS_CERT_FILE is set but the tls feature is not compiled into this build");
+        std::process::exit(1);
+    }
+    #[cfg(feature = "tls")]
+    if let Some(config) = TlsConfig::from_settings(&settings).unwrap_or_else(|error| panic!("Invalid TLS settings: {}", error)) {
+        if settings.var("UNIX_SOCKET_PATH").is_some() {
+            log_error!("UNIX_SOCKET_PATH and TLS_CERT_FILE cannot both be set; the reverse proxy in front of the socket terminates TLS");
+            std::process::exit(1);
+        }
+        serve_tls(config, address, routes, shutdown).await;
+        return;
+    }
+    #[cfg(unix)]
+    if let Some(config) = UnixSocketConfig::from_settings(&settings).unwrap_or_else(|error| panic!("Invalid Unix socket settings: {}", error)) {
+        let socket = UnixSocket::bind(&config).await.unwrap_or_else(|error| {
+            log_error!("{}", error);
+            std::process::exit(1);
+        });
+        log_info!("Listening on unix:{}", socket.path().display());
+        socket.serve(routes, shutdown).await;
+        return;
+    }
+    #[cfg(not(unix))]
+    if settings.var("UNIX_SOCKET_PATH").is_some() {
+        log_error!("UNIX_SOCKET_PATH is set but Unix domain sockets are not supported on this platform");
+        std::process::exit(1);
+    }
+    // Each request is answered by the routes current when it arrives, with its peer address as a PeerAddr
+    // extension
+    let server = Server::try_bind(&address).unwrap_or_else(|error| listen_failed(address, error));
+    let server = server.serve(make_service_fn(move |connection: &AddrStream| {
+        let (routes, peer) = (routes.clone(), connection.remote_addr());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut request| {
+                request.extensions_mut().insert(PeerAddr(peer));
+                let routes = routes.clone();
+                async move { routes.answer(request).await }
+            }))
+        }
+    }));
+    log_info!("Listening on http://{}", server.local_addr());
+    if let Err(error) = server.with_graceful_shutdown(shutdown).await {
+        log_error!("Server error: {}", error);
+    }
+}
 
-    // Simulate a GET request to the playground route
-    let response = warp::test::request()
-        .method("GET")
-        .path("/graphql")
-        .reply(&playground_filter)
-        .await;
+// Stop at once when the address cannot be listened on, most often because another process has the port
+fn listen_failed(address: SocketAddr, error: impl std::fmt::Display) -> ! {
+    log_error!("Failed to listen on {}: {}", address, error);
+    log_error!("If another process is using port {}, stop it, or set PORT or pass --port to listen on another", address.port());
+    std::process::exit(1);
+}
+
+// Serve the routes over HTTPS, reloading the certificate on SIGHUP, and redirect plain HTTP to them when
+// TLS_REDIRECT_HTTP_PORT is set
+#[cfg(feature = "tls")]
+async fn serve_tls(config: TlsConfig, address: SocketAddr, routes: SharedRoutes, shutdown: impl Future<Output = ()>) {
+    if let Some(port) = config.redirect_http_port {
+        let redirect_address = SocketAddr::new(address.ip(), port);
+        let (bound, redirect) = warp::serve(redirect_route(address.port())).try_bind_ephemeral(redirect_address).unwrap_or_else(|error| listen_failed(redirect_address, error));
+        log_info!("Redirecting http://{} to HTTPS", bound);
+        tokio::spawn(redirect);
+    }
+    let certificates = Certificates::load(config).unwrap_or_else(|error| panic!("Invalid TLS certificate: {}", error));
+    #[cfg(unix)]
+    certificates.reload_on_hangup().expect("Failed to listen for SIGHUP");
+    let listener = TcpListener::bind(address).await.unwrap_or_else(|error| listen_failed(address, error));
+    log_info!("Listening on https://{}", listener.local_addr().unwrap_or(address));
+    certificates.serve(listener, routes, shutdown).await;
+}
 
-    // Assert that the response contains the expected HTML content
-    let expected_content = r#"<!DOCTYPE html>
+// Integration tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
+
+    // Define a test for the GraphQL Playground route
+    #[tokio::test]
+    async fn test_graphql_playground() {
+        // Create a Warp filter for the playground route
+        let playground_filter = warp::path("graphql")
+            .and(warp::get())
+            .map(|| warp::reply::html(playground_source(GraphQLPlaygroundConfig::new("/graphql"))));
+
+        // Simulate a GET request to the playground route
+        let response = warp::test::request()
+            .method("GET")
+            .path("/graphql")
+            .reply(&playground_filter)
+            .await;
+
+        // Assert that the response contains the expected HTML content
+        let expected_content = r#"<!DOCTYPE html>
     <!-- ... Include the expected HTML content of the playground ... -->
 </html>"#;
-    assert_eq!(response.body(), expected_content.as_bytes());
-}
\ No newline at end of file
+        assert_eq!(response.body(), expected_content.as_bytes());
+    }
+}
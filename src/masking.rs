@@ -0,0 +1,141 @@
+// Import necessary libraries and modules
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextResolve, ResolveInfo};
+use async_graphql::{ServerResult, TypeDirective, Value};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+use crate::auth::AuthContext;
+use crate::loader::UserDataLoader;
+use crate::model::UserRole;
+
+// Define the @masked directive. A field carrying it is masked by the Masking extension for callers
+// without the role it requires, named as roles are stored (`admin`, `member`, or `guest`). The directive
+// is kept with the field in the schema registry, and appears on it in the federation SDL export
+#[TypeDirective(location = "FieldDefinition")]
+pub fn masked(requires: String) {}
+
+// Define the extension that masks @masked fields; it is installed on every schema
+pub struct Masking;
+
+impl ExtensionFactory for Masking {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(MaskingExtension::default())
+    }
+}
+
+// Each request gets its own extension, so the caller's role is looked up at most once per request
+#[derive(Default)]
+struct MaskingExtension {
+    viewer_role: OnceCell<Option<UserRole>>,
+}
+
+#[async_trait]
+impl Extension for MaskingExtension {
+    async fn resolve(&self, ctx: &ExtensionContext<'_>, info: ResolveInfo<'_>, next: NextResolve<'_>) -> ServerResult<Option<Value>> {
+        let required = match info.is_for_introspection {
+            true => None,
+            false => required_role(ctx, info.parent_type, info.name),
+        };
+        let value = next.run(ctx, info).await?;
+        let Some(required) = required else {
+            return Ok(value);
+        };
+        let role = *self.viewer_role.get_or_init(|| viewer_role(ctx)).await;
+        match role.is_some_and(|role| role.includes(required)) {
+            true => Ok(value),
+            false => Ok(value.map(mask_value)),
+        }
+    }
+}
+
+// Return the role a field's @masked directive requires, if it has one; a role that cannot be read
+// requires an admin, so a mistake masks too much rather than too little
+fn required_role(ctx: &ExtensionContext<'_>, type_name: &str, field_name: &str) -> Option<UserRole> {
+    let field = ctx.schema_env.registry.types.get(type_name)?.field_by_name(field_name)?;
+    let directive = field.directive_invocations.iter().find(|directive| directive.name == "masked")?;
+    let requires = directive.args.get("requires").cloned();
+    Some(parse_role(requires).unwrap_or(UserRole::Admin))
+}
+
+// Read a role from a directive argument
+fn parse_role(value: Option<Value>) -> Option<UserRole> {
+    match value {
+        Some(Value::String(role)) => UserRole::parse(&role),
+        _ => None,
+    }
+}
+
+// Look up the role of the user who made the request; anonymous callers and unknown users have none
+async fn viewer_role(ctx: &ExtensionContext<'_>) -> Option<UserRole> {
+    let user_id = ctx.data_opt::<AuthContext>().and_then(AuthContext::principal)?;
+    let loader = ctx.data_opt::<UserDataLoader>()?;
+    loader.load_one(user_id.to_string()).await.ok().flatten().map(|user| user.role)
+}
+
+// Mask every string in a resolved value, leaving nulls and other values as they are
+fn mask_value(value: Value) -> Value {
+    match value {
+        Value::String(text) => Value::String(mask(&text)),
+        Value::List(values) => Value::List(values.into_iter().map(mask_value).collect()),
+        value => value,
+    }
+}
+
+// Mask text down to its first character. An email address keeps its domain, since that is rarely
+// sensitive and says which organization the user belongs to: `pavel@gmail.com` becomes `p***@gmail.com`
+pub fn mask(text: &str) -> String {
+    let (local, domain) = match text.rsplit_once('@') {
+        Some((local, domain)) => (local, Some(domain)),
+        None => (text, None),
+    };
+    let hidden = match local.chars().next() {
+        Some(first) => format!("{}***", first),
+        None => "***".to_string(),
+    };
+    match domain {
+        Some(domain) => format!("{}@{}", hidden, domain),
+        None => hidden,
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Define a test for how text and values are masked
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask("Pavelboukine@gmail.com"), "P***@gmail.com");
+        assert_eq!(mask("a@example.com"), "a***@example.com");
+        assert_eq!(mask("@example.com"), "***@example.com");
+        assert_eq!(mask("555-0100"), "5***");
+        assert_eq!(mask("é"), "é***");
+        assert_eq!(mask(""), "***");
+
+        assert_eq!(mask_value(Value::String("ada@example.com".to_string())), Value::String("a***@example.com".to_string()));
+        assert_eq!(
+            mask_value(Value::List(vec![Value::String("ada".to_string()), Value::Null])),
+            Value::List(vec![Value::String("a***".to_string()), Value::Null])
+        );
+        assert_eq!(mask_value(Value::Null), Value::Null);
+        assert_eq!(mask_value(Value::Boolean(true)), Value::Boolean(true));
+    }
+
+    // Define a test for reading the role @masked requires and which callers have it
+    #[test]
+    fn test_parse_required_role() {
+        assert_eq!(parse_role(Some(Value::String("member".to_string()))), Some(UserRole::Member));
+        assert_eq!(parse_role(Some(Value::String("owner".to_string()))), None);
+        assert_eq!(parse_role(Some(Value::Enum(async_graphql::Name::new("MEMBER")))), None);
+        assert_eq!(parse_role(None), None);
+
+        // Callers need the required role or one above it
+        assert!(UserRole::Admin.includes(UserRole::Admin));
+        assert!(UserRole::Admin.includes(UserRole::Guest));
+        assert!(UserRole::Member.includes(UserRole::Guest));
+        assert!(!UserRole::Member.includes(UserRole::Admin));
+        assert!(!UserRole::Guest.includes(UserRole::Member));
+    }
+}
@@ -0,0 +1,126 @@
+// Import necessary libraries and modules
+use std::fmt::Write;
+use warp::http::header::CONTENT_TYPE;
+use warp::{Filter, Rejection, Reply};
+
+use crate::cache::CacheStats;
+use crate::repository::{SharedRepository, UserRepository};
+
+// Define the content type of the Prometheus text format
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// Append one metric with its help text, type, and samples, each sample being a label set and a value
+fn write_metric(output: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, String)]) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        let _ = writeln!(output, "{}{} {}", name, labels, value);
+    }
+}
+
+// Render the connection pool and cache statistics in the Prometheus text format
+pub fn render_metrics(repository: &dyn UserRepository, cache_stats: Option<&CacheStats>) -> String {
+    let mut output = String::new();
+    if let Some(pool) = repository.pool_stats() {
+        write_metric(
+            &mut output,
+            "db_pool_max_connections",
+            "gauge",
+            "Largest number of connections the pool will open.",
+            &[("", pool.max_connections.to_string())],
+        );
+        write_metric(
+            &mut output,
+            "db_pool_connections",
+            "gauge",
+            "Open connections in the pool by state.",
+            &[(r#"{state="in_use"}"#, pool.in_use().to_string()), (r#"{state="idle"}"#, pool.idle.to_string())],
+        );
+        write_metric(
+            &mut output,
+            "db_pool_acquires_total",
+            "counter",
+            "Connections checked out of the pool.",
+            &[("", pool.acquires.to_string())],
+        );
+        write_metric(
+            &mut output,
+            "db_pool_acquire_wait_seconds_total",
+            "counter",
+            "Total time spent waiting to check out a connection.",
+            &[("", pool.acquire_wait.as_secs_f64().to_string())],
+        );
+    }
+    if let Some(stats) = cache_stats {
+        write_metric(
+            &mut output,
+            "cache_lookups_total",
+            "counter",
+            "User lookups by whether the cache answered them.",
+            &[(r#"{result="hit"}"#, stats.hits().to_string()), (r#"{result="miss"}"#, stats.misses().to_string())],
+        );
+    }
+    output
+}
+
+// Build the GET /metrics route for Prometheus to scrape
+pub fn metrics_route(
+    repository: SharedRepository,
+    cache_stats: Option<CacheStats>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("metrics").and(warp::path::end()).and(warp::get()).map(move || {
+        let body = render_metrics(repository.as_ref(), cache_stats.as_ref());
+        warp::reply::with_header(body, CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)
+    })
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    // Send a request to the metrics route and return the body
+    async fn scrape(repository: SharedRepository, cache_stats: Option<CacheStats>) -> String {
+        let route = metrics_route(repository, cache_stats);
+        let response = warp::test::request().path("/metrics").reply(&route).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[CONTENT_TYPE], PROMETHEUS_CONTENT_TYPE);
+        String::from_utf8(response.body().to_vec()).unwrap()
+    }
+
+    // Define a test that backends without a pool only report the cache
+    #[tokio::test]
+    async fn test_metrics_without_pool() {
+        use crate::repository::InMemoryRepository;
+
+        let body = scrape(Arc::new(InMemoryRepository::new()), Some(CacheStats::default())).await;
+        assert!(!body.contains("db_pool"));
+        assert!(body.contains("# TYPE cache_lookups_total counter\n"));
+        assert!(body.contains("cache_lookups_total{result=\"hit\"} 0\n"));
+    }
+
+    // Define a test that SQL backends report their pool size, usage, and checkouts
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_pool_metrics() {
+        use crate::repository::{DatabaseConfig, SqliteRepository};
+
+        // A database file lets every pooled connection see the migrated schema
+        let directory = tempfile::tempdir().unwrap();
+        let url = format!("sqlite:{}", directory.path().join("app.db").display());
+        let config = DatabaseConfig { max_connections: 3, ..DatabaseConfig::new(url) };
+        let repository = Arc::new(SqliteRepository::connect(&config).await.expect("Failed to open SQLite"));
+        repository.list().await.unwrap();
+        repository.list().await.unwrap();
+
+        let body = scrape(repository, None).await;
+        assert!(body.contains("# TYPE db_pool_connections gauge\n"));
+        assert!(body.contains("db_pool_max_connections 3\n"));
+        assert!(body.contains("db_pool_connections{state=\"in_use\"} "));
+        assert!(body.contains("db_pool_connections{state=\"idle\"} "));
+        assert!(body.contains("db_pool_acquires_total 2\n"));
+        assert!(body.contains("db_pool_acquire_wait_seconds_total "));
+        assert!(!body.contains("cache_lookups_total"));
+    }
+}
@@ -0,0 +1,891 @@
+// Import necessary libraries and modules
+use async_graphql::connection::{query, Connection, Edge, OpaqueCursor};
+use async_graphql::{Context, Enum, ErrorExtensions, InputObject, Interface, Json, Object, Result, ResultExt, SimpleObject, ID};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use uuid::Uuid;
+
+use crate::avatar::Avatars;
+use crate::complexity::{list_cost, page_items};
+use crate::loader::{MembershipDataLoader, OrganizationDataLoader, PostDataLoader, UserDataLoader};
+use crate::masking::masked;
+use crate::node::{global_id, COMMENT, ORGANIZATION, POST, SERVICE_ACCOUNT, USER};
+use crate::permissions::FieldPermission;
+use crate::repository::{RepositoryError, SharedRepository};
+use crate::response_cache::cache_control;
+use crate::scalars::{PhoneNumber, Timestamp};
+use crate::schema::{MaxPageSize, DEFAULT_MAX_PAGE_SIZE};
+
+// Define the Relay Node interface implemented by every type that can be refetched by its global ID
+#[derive(Interface)]
+#[graphql(field(name = "id", ty = "ID"))]
+pub enum Node {
+    User(User),
+    Post(Post),
+    Comment(Comment),
+    Organization(Organization),
+    ServiceAccount(ServiceAccount),
+}
+
+// Define the Actor interface for anyone who can act in the service: people, and the integrations
+// that act on their behalf
+#[derive(Interface)]
+#[graphql(field(name = "id", ty = "ID"), field(name = "display_name", ty = "String"))]
+#[allow(clippy::large_enum_variant)]
+pub enum Actor {
+    User(User),
+    ServiceAccount(ServiceAccount),
+}
+
+// Define a User struct to represent a user with id, name, and email fields.
+// The version starts at 1 and goes up with every update, and the update time moves with it; soft-deleted users
+// keep their data and record when they were deleted. Users stored before creation or update times were recorded
+// have the Unix epoch.
+// The avatar key names the uploaded image in the avatar store; clients see a signed URL instead.
+// Users stored before roles existed are members.
+// Metadata is free-form JSON set by clients; users without any have None rather than a JSON null.
+// Phone numbers are optional and stored in E.164 form, and so is the postal address.
+// Users who registered themselves have not verified their email address until they follow the link sent to
+// it; every other user, including those stored before verification existed, counts as verified
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct User {
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    pub version: i32,
+    #[serde(default)]
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub updated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub avatar_key: Option<String>,
+    #[serde(default)]
+    pub role: UserRole,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+    #[serde(default)]
+    pub phone: Option<String>,
+    #[serde(default)]
+    pub address: Option<Address>,
+    #[serde(default = "default_email_verified")]
+    pub email_verified: bool,
+}
+
+// Count users stored without a verification flag as verified
+pub(crate) fn default_email_verified() -> bool {
+    true
+}
+
+// Implement GraphQL Object for the User struct
+#[Object]
+impl User {
+    async fn id(&self) -> ID {
+        global_id(USER, &self.id)
+    }
+
+    // The user's own ID without the type prefix of the global ID
+    async fn uuid(&self) -> Result<Uuid> {
+        parse_user_id(&self.id).ok_or_else(|| RepositoryError::InvalidId(self.id.clone()).extend())
+    }
+
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    // The name to show wherever the user appears as an actor
+    async fn display_name(&self) -> String {
+        self.name.clone()
+    }
+
+    // Only the user and admins see the email address; everyone else gets null
+    async fn email(&self, ctx: &Context<'_>) -> Option<&str> {
+        FieldPermission::owner_or_role(&self.id, UserRole::Admin).resolve(ctx, self.email.as_str()).await
+    }
+
+    // Only admins see the phone number in full; everyone else sees it masked, like `+***`
+    #[graphql(directive = masked::apply(UserRole::Admin.as_str().to_string()))]
+    async fn phone(&self) -> Option<PhoneNumber> {
+        self.phone.clone().map(PhoneNumber)
+    }
+
+    async fn address(&self) -> Option<&Address> {
+        self.address.as_ref()
+    }
+
+    // Whether the user has followed the verification link sent when they registered
+    async fn email_verified(&self) -> bool {
+        self.email_verified
+    }
+
+    async fn version(&self) -> i32 {
+        self.version
+    }
+
+    async fn role(&self) -> UserRole {
+        self.role
+    }
+
+    async fn created_at(&self) -> Timestamp {
+        self.created_at.into()
+    }
+
+    async fn updated_at(&self) -> Timestamp {
+        self.updated_at.into()
+    }
+
+    async fn deleted_at(&self) -> Option<Timestamp> {
+        self.deleted_at.map(Timestamp)
+    }
+
+    // The JSON object set by setUserMetadata, or null if none is set
+    async fn metadata(&self) -> Option<Json<&serde_json::Value>> {
+        self.metadata.as_ref().map(Json)
+    }
+
+    // Hand out a short-lived signed URL rather than the storage key, so the store itself stays private
+    async fn avatar_url(&self, ctx: &Context<'_>) -> Result<Option<String>> {
+        let (Some(key), Some(avatars)) = (&self.avatar_key, ctx.data_opt::<Avatars>()) else {
+            return Ok(None);
+        };
+        Ok(Some(avatars.url(key).await.extend()?))
+    }
+
+    // Page through the user's posts, oldest first. Each user pages on their own cursors, and the pages of
+    // every user in the response are loaded in one batch; backends that cannot store posts have none
+    #[graphql(complexity = "list_cost(page_items(first), child_complexity)")]
+    async fn posts(&self, ctx: &Context<'_>, first: Option<i32>, after: Option<String>) -> Result<PostConnection> {
+        let loader = ctx.data::<PostDataLoader>()?;
+        let max_page_size = ctx.data_opt::<MaxPageSize>().map_or(DEFAULT_MAX_PAGE_SIZE, |max| max.0);
+        query(after, None, first, None, |after: Option<OpaqueCursor<String>>, _: Option<OpaqueCursor<String>>, first, _| async move {
+            let limit = first.unwrap_or(max_page_size).min(max_page_size);
+            let page = PostPage { author_id: self.id.clone(), after: after.as_ref().map(|after| after.0.clone()), limit: limit + 1 };
+            let mut posts = loader.load_one(page).await.map_err(|error| error.extend())?.unwrap_or_default();
+            let has_next_page = posts.len() > limit;
+            posts.truncate(limit);
+
+            let mut connection = Connection::new(after.is_some(), has_next_page);
+            connection.edges.extend(posts.into_iter().map(|post| Edge::new(OpaqueCursor(post.id.clone()), post)));
+            Ok::<_, async_graphql::Error>(connection)
+        })
+        .await
+    }
+
+    // Load the memberships of every user in the response in one batch, oldest first
+    async fn memberships(&self, ctx: &Context<'_>) -> Result<Vec<Membership>> {
+        let loader = ctx.data::<MembershipDataLoader>()?;
+        Ok(loader.load_one(self.id.clone()).await.map_err(|error| error.extend())?.unwrap_or_default())
+    }
+}
+
+// Define a Post written by a user, identified by its own ID and pointing at its author by user ID
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Post {
+    pub id: String,
+    pub author_id: String,
+    pub title: String,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Define the connection of posts returned by User.posts; cursors are opaque post IDs
+pub type PostConnection = Connection<OpaqueCursor<String>, Post>;
+
+// Define a page of one author's posts to load: up to `limit` posts after the post with ID `after`, or from
+// the first post without it, in creation order
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct PostPage {
+    pub author_id: String,
+    pub after: Option<String>,
+    pub limit: usize,
+}
+
+// Implement GraphQL Object for the Post struct; posts may be cached for a minute
+#[Object(directive = cache_control::apply(Some(60), None))]
+impl Post {
+    async fn id(&self) -> ID {
+        global_id(POST, &self.id)
+    }
+
+    async fn title(&self) -> &str {
+        &self.title
+    }
+
+    async fn body(&self) -> &str {
+        &self.body
+    }
+
+    async fn author_id(&self) -> ID {
+        global_id(USER, &self.author_id)
+    }
+
+    async fn created_at(&self) -> Timestamp {
+        self.created_at.into()
+    }
+
+    // Resolve the author through the user loader, so a list of posts costs one user lookup;
+    // null once the author has been deleted
+    async fn author(&self, ctx: &Context<'_>) -> Result<Option<Actor>> {
+        let loader = ctx.data::<UserDataLoader>()?;
+        Ok(loader.load_one(self.author_id.clone()).await.map_err(|error| error.extend())?.map(Actor::User))
+    }
+
+    // Page through the top-level comments on the post, oldest first
+    #[graphql(complexity = "list_cost(page_items(first), child_complexity)")]
+    async fn comments(&self, ctx: &Context<'_>, first: Option<i32>, after: Option<String>) -> Result<CommentConnection> {
+        comment_connection(ctx, &self.id, None, first, after).await
+    }
+}
+
+// Define how many levels of replies can be nested below a top-level comment, which also bounds
+// how deep a query can recurse through replies
+pub const MAX_COMMENT_DEPTH: i32 = 5;
+
+// Define a Comment on a post; replies point at the comment they answer and sit one level deeper
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Comment {
+    pub id: String,
+    pub post_id: String,
+    pub parent_id: Option<String>,
+    pub author_id: String,
+    pub body: String,
+    // Number of comments above this one in its thread; top-level comments are at depth 0
+    pub depth: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+// Define the connection of comments returned by Post.comments and Comment.replies; cursors are opaque comment IDs
+pub type CommentConnection = Connection<OpaqueCursor<String>, Comment>;
+
+// Implement GraphQL Object for the Comment struct
+#[Object]
+impl Comment {
+    async fn id(&self) -> ID {
+        global_id(COMMENT, &self.id)
+    }
+
+    async fn post_id(&self) -> ID {
+        global_id(POST, &self.post_id)
+    }
+
+    async fn parent_id(&self) -> Option<ID> {
+        self.parent_id.as_ref().map(|parent_id| global_id(COMMENT, parent_id))
+    }
+
+    async fn author_id(&self) -> ID {
+        global_id(USER, &self.author_id)
+    }
+
+    async fn body(&self) -> &str {
+        &self.body
+    }
+
+    async fn depth(&self) -> i32 {
+        self.depth
+    }
+
+    async fn created_at(&self) -> Timestamp {
+        self.created_at.into()
+    }
+
+    // Resolve the author through the user loader; null once the author has been deleted
+    async fn author(&self, ctx: &Context<'_>) -> Result<Option<Actor>> {
+        let loader = ctx.data::<UserDataLoader>()?;
+        Ok(loader.load_one(self.author_id.clone()).await.map_err(|error| error.extend())?.map(Actor::User))
+    }
+
+    // Page through the direct replies, oldest first. Comments at the deepest level cannot be replied to,
+    // so their replies are empty without asking the repository
+    #[graphql(complexity = "list_cost(page_items(first), child_complexity)")]
+    async fn replies(&self, ctx: &Context<'_>, first: Option<i32>, after: Option<String>) -> Result<CommentConnection> {
+        if self.depth >= MAX_COMMENT_DEPTH {
+            return Ok(Connection::new(false, false));
+        }
+        comment_connection(ctx, &self.post_id, Some(&self.id), first, after).await
+    }
+}
+
+// Load a page of the comments on a post directly under `parent_id`, or the top-level ones without it,
+// at most one page size at a time; one extra comment is read to tell whether more follow
+async fn comment_connection(
+    ctx: &Context<'_>,
+    post_id: &str,
+    parent_id: Option<&str>,
+    first: Option<i32>,
+    after: Option<String>,
+) -> Result<CommentConnection> {
+    let repository = ctx.data::<SharedRepository>()?;
+    let max_page_size = ctx.data_opt::<MaxPageSize>().map_or(DEFAULT_MAX_PAGE_SIZE, |max| max.0);
+    query(after, None, first, None, |after: Option<OpaqueCursor<String>>, _: Option<OpaqueCursor<String>>, first, _| async move {
+        let Some(posts) = repository.posts() else {
+            return Ok(Connection::new(false, false));
+        };
+        let limit = first.unwrap_or(max_page_size).min(max_page_size);
+        let mut comments = posts.list_comments(post_id, parent_id, after.as_ref().map(|after| after.0.as_str()), limit + 1).await.extend()?;
+        let has_next_page = comments.len() > limit;
+        comments.truncate(limit);
+
+        let mut connection = Connection::new(after.is_some(), has_next_page);
+        connection.edges.extend(comments.into_iter().map(|comment| Edge::new(OpaqueCursor(comment.id.clone()), comment)));
+        Ok::<_, async_graphql::Error>(connection)
+    })
+    .await
+}
+
+// Define the fields needed to create a comment; the repository assigns the ID and creation time
+#[derive(Clone, Debug)]
+pub struct NewComment {
+    pub post_id: String,
+    pub parent_id: Option<String>,
+    pub author_id: String,
+    pub body: String,
+    pub depth: i32,
+}
+
+// Define a user's postal address. The country is an ISO 3166-1 alpha-2 code, such as `CA`; not every
+// country uses postal codes, so the postal code is optional
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Address {
+    pub street: String,
+    pub city: String,
+    pub country: String,
+    #[serde(default)]
+    pub postal_code: Option<String>,
+}
+
+// Implement GraphQL Object for the Address struct; the street and postal code locate the user, so only
+// admins see them in full
+#[Object]
+impl Address {
+    #[graphql(directive = masked::apply(UserRole::Admin.as_str().to_string()))]
+    async fn street(&self) -> &str {
+        &self.street
+    }
+
+    async fn city(&self) -> &str {
+        &self.city
+    }
+
+    async fn country(&self) -> &str {
+        &self.country
+    }
+
+    #[graphql(directive = masked::apply(UserRole::Admin.as_str().to_string()))]
+    async fn postal_code(&self) -> Option<&str> {
+        self.postal_code.as_deref()
+    }
+}
+
+// Define the fields needed to create a post; the repository assigns the ID and creation time
+#[derive(Clone, Debug)]
+pub struct NewPost {
+    pub author_id: String,
+    pub title: String,
+    pub body: String,
+}
+
+// Define the fields needed to create a user; the repository assigns the ID unless one is requested.
+// New users count as verified unless they register themselves
+#[derive(Clone, Debug)]
+pub struct NewUser {
+    pub id: Option<String>,
+    pub name: String,
+    pub email: String,
+    pub role: UserRole,
+    pub phone: Option<String>,
+    pub address: Option<Address>,
+    pub email_verified: bool,
+}
+
+impl Default for NewUser {
+    fn default() -> Self {
+        NewUser {
+            id: None,
+            name: String::new(),
+            email: String::new(),
+            role: UserRole::default(),
+            phone: None,
+            address: None,
+            email_verified: true,
+        }
+    }
+}
+
+// Define a partial update to a user; fields left as None are unchanged, metadata set to a JSON null is cleared,
+// and a phone or address set to Some(None) is removed.
+// With an expected version the update only applies if the stored user still has that version
+#[derive(Clone, Debug, Default)]
+pub struct UserUpdate {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub avatar_key: Option<String>,
+    pub role: Option<UserRole>,
+    pub metadata: Option<serde_json::Value>,
+    pub phone: Option<Option<String>>,
+    pub address: Option<Option<Address>>,
+    pub email_verified: Option<bool>,
+    pub expected_version: Option<i32>,
+}
+
+// Define which users a listing covers: live users unless deleted ones are included,
+// optionally only those created at or after `created_after` and before `created_before`,
+// whose name contains `name_contains`, whose email is at `email_domain`, and who have `role`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UserFilter {
+    pub include_deleted: bool,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    // Case-insensitive substring of the name
+    pub name_contains: Option<String>,
+    // Case-insensitive domain of the email, the part after the @
+    pub email_domain: Option<String>,
+    pub role: Option<UserRole>,
+}
+
+impl UserFilter {
+    // Check whether a user is covered by the filter
+    pub fn matches(&self, user: &User) -> bool {
+        (self.include_deleted || user.deleted_at.is_none())
+            && self.created_after.is_none_or(|after| user.created_at >= after)
+            && self.created_before.is_none_or(|before| user.created_at < before)
+            && self.name_contains.as_ref().is_none_or(|part| user.name.to_lowercase().contains(&part.to_lowercase()))
+            && self.email_domain.as_ref().is_none_or(|domain| {
+                user.email.split_once('@').is_some_and(|(_, email_domain)| email_domain.eq_ignore_ascii_case(domain))
+            })
+            && self.role.is_none_or(|role| user.role == role)
+    }
+}
+
+// Define the role a user has across the whole service, which decides what they are allowed to do
+#[derive(Clone, Copy, Debug, Default, Enum, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserRole {
+    Admin,
+    #[default]
+    Member,
+    Guest,
+}
+
+impl UserRole {
+    // Return the name the role is stored under
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UserRole::Admin => "admin",
+            UserRole::Member => "member",
+            UserRole::Guest => "guest",
+        }
+    }
+
+    // Parse a stored role name
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "admin" => Some(UserRole::Admin),
+            "member" => Some(UserRole::Member),
+            "guest" => Some(UserRole::Guest),
+            _ => None,
+        }
+    }
+
+    // Tell whether the role grants everything another role does: admins can do anything members can,
+    // and members anything guests can
+    pub fn includes(self, other: UserRole) -> bool {
+        let rank = |role: UserRole| match role {
+            UserRole::Admin => 2,
+            UserRole::Member => 1,
+            UserRole::Guest => 0,
+        };
+        rank(self) >= rank(other)
+    }
+}
+
+// Define counts over the live users, computed by the storage backend without loading the users; they may
+// be cached for 30 seconds, as may their groups
+#[derive(Clone, Debug, Default, PartialEq, SimpleObject)]
+#[graphql(directive = cache_control::apply(Some(30), None))]
+pub struct UserStats {
+    pub user_count: usize,
+    // Users created since the start of the current UTC day
+    pub users_created_today: usize,
+    // Counts for each role at least one user has, largest first
+    pub by_role: Vec<RoleCount>,
+    // Counts for each email domain, lowercased, largest first
+    pub by_domain: Vec<DomainCount>,
+}
+
+#[derive(Clone, Debug, PartialEq, SimpleObject)]
+#[graphql(directive = cache_control::apply(Some(30), None))]
+pub struct RoleCount {
+    pub role: UserRole,
+    pub count: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, SimpleObject)]
+#[graphql(directive = cache_control::apply(Some(30), None))]
+pub struct DomainCount {
+    pub domain: String,
+    pub count: usize,
+}
+
+// Return the domain of an email address as the stats group it, lowercased; an address without an `@`
+// counts under its whole text, as the SQL backends' substring arithmetic does
+pub fn email_domain(email: &str) -> String {
+    email.split_once('@').map_or(email, |(_, domain)| domain).to_lowercase()
+}
+
+// Define a ServiceAccount, a non-human actor such as an integration; service accounts are configured
+// with the server rather than stored, so they are the same on every backend
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceAccount {
+    pub id: String,
+    pub name: String,
+}
+
+// Implement GraphQL Object for the ServiceAccount struct
+#[Object]
+impl ServiceAccount {
+    async fn id(&self) -> ID {
+        global_id(SERVICE_ACCOUNT, &self.id)
+    }
+
+    async fn display_name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+// Define an Organization that users belong to through memberships
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Organization {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Implement GraphQL Object for the Organization struct
+#[Object]
+impl Organization {
+    async fn id(&self) -> ID {
+        global_id(ORGANIZATION, &self.id)
+    }
+
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn created_at(&self) -> Timestamp {
+        self.created_at.into()
+    }
+
+    // List the memberships of the organization, oldest first
+    async fn members(&self, ctx: &Context<'_>) -> Result<Vec<Membership>> {
+        let repository = ctx.data::<SharedRepository>()?;
+        let Some(organizations) = repository.organizations() else {
+            return Ok(Vec::new());
+        };
+        let mut members = organizations.memberships_by_organizations(std::slice::from_ref(&self.id)).await.extend()?;
+        Ok(members.remove(&self.id).unwrap_or_default())
+    }
+}
+
+// Define the role a user has within an organization
+#[derive(Clone, Copy, Debug, Default, Enum, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MembershipRole {
+    Owner,
+    Admin,
+    #[default]
+    Member,
+}
+
+impl MembershipRole {
+    // Return the name the role is stored under
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MembershipRole::Owner => "owner",
+            MembershipRole::Admin => "admin",
+            MembershipRole::Member => "member",
+        }
+    }
+
+    // Parse a stored role name
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "owner" => Some(MembershipRole::Owner),
+            "admin" => Some(MembershipRole::Admin),
+            "member" => Some(MembershipRole::Member),
+            _ => None,
+        }
+    }
+}
+
+// Define a Membership linking a user to an organization with a role; each user belongs to an organization at most once
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Membership {
+    pub organization_id: String,
+    pub user_id: String,
+    pub role: MembershipRole,
+    pub joined_at: DateTime<Utc>,
+}
+
+// Implement GraphQL Object for the Membership struct
+#[Object]
+impl Membership {
+    async fn organization_id(&self) -> ID {
+        global_id(ORGANIZATION, &self.organization_id)
+    }
+
+    async fn user_id(&self) -> ID {
+        global_id(USER, &self.user_id)
+    }
+
+    async fn role(&self) -> MembershipRole {
+        self.role
+    }
+
+    async fn joined_at(&self) -> Timestamp {
+        self.joined_at.into()
+    }
+
+    // Resolve the user through the user loader; null once the user has been deleted
+    async fn user(&self, ctx: &Context<'_>) -> Result<Option<User>> {
+        let loader = ctx.data::<UserDataLoader>()?;
+        loader.load_one(self.user_id.clone()).await.map_err(|error| error.extend())
+    }
+
+    // Resolve the organization through the organization loader, so a list of memberships costs one lookup
+    async fn organization(&self, ctx: &Context<'_>) -> Result<Option<Organization>> {
+        let loader = ctx.data::<OrganizationDataLoader>()?;
+        loader.load_one(self.organization_id.clone()).await.map_err(|error| error.extend())
+    }
+}
+
+// Define an ApiKey that lets a machine client act as a user. Only a hash of the key is stored, and it
+// is never exposed through the schema or recorded in the audit log
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub user_id: String,
+    #[serde(skip)]
+    pub key_hash: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Implement GraphQL Object for the ApiKey struct
+#[Object]
+impl ApiKey {
+    async fn id(&self) -> ID {
+        ID(self.id.clone())
+    }
+
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn user_id(&self) -> ID {
+        global_id(USER, &self.user_id)
+    }
+
+    async fn created_at(&self) -> Timestamp {
+        self.created_at.into()
+    }
+
+    // Resolve the user the key acts as through the user loader; null once the user has been deleted
+    async fn user(&self, ctx: &Context<'_>) -> Result<Option<User>> {
+        let loader = ctx.data::<UserDataLoader>()?;
+        loader.load_one(self.user_id.clone()).await.map_err(|error| error.extend())
+    }
+}
+
+// Define the fields needed to store an API key; the repository assigns the ID and creation time
+#[derive(Clone, Debug)]
+pub struct NewApiKey {
+    pub name: String,
+    pub user_id: String,
+    pub key_hash: String,
+}
+
+// Define a stored refresh token. Only a hash of the token is kept, and a token is revoked rather than
+// removed once it is used, so that a stolen token presented again can be recognized
+#[derive(Clone, Debug, PartialEq)]
+pub struct RefreshToken {
+    pub user_id: String,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+// Define a user's TOTP two-factor enrollment. The secret is kept so codes can be checked, recovery codes
+// only as hashes, and an enrollment is pending, and not asked for at login, until a code confirms it
+#[derive(Clone, Debug, PartialEq)]
+pub struct TwoFactor {
+    pub secret: String,
+    pub confirmed: bool,
+    pub recovery_code_hashes: Vec<String>,
+}
+
+// Define the user fields a listing can be sorted by
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum UserSortField {
+    Name,
+    Email,
+    CreatedAt,
+}
+
+// Define which way a sort key orders users
+#[derive(Clone, Copy, Debug, Default, Enum, Eq, PartialEq)]
+pub enum SortDirection {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortDirection {
+    // Return the SQL keyword for the direction
+    pub fn sql(self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
+}
+
+// Define one key of a listing's sort order; later keys only break ties left by earlier ones
+#[derive(Clone, Copy, Debug, Eq, InputObject, PartialEq)]
+pub struct UserOrder {
+    pub field: UserSortField,
+    #[graphql(default)]
+    pub direction: SortDirection,
+}
+
+impl UserOrder {
+    // Compare two users by this key alone
+    pub fn compare(&self, a: &User, b: &User) -> Ordering {
+        let ordering = match self.field {
+            UserSortField::Name => a.name.cmp(&b.name),
+            UserSortField::Email => a.email.cmp(&b.email),
+            UserSortField::CreatedAt => a.created_at.cmp(&b.created_at),
+        };
+        match self.direction {
+            SortDirection::Asc => ordering,
+            SortDirection::Desc => ordering.reverse(),
+        }
+    }
+}
+
+// Sort users by each key in turn; the sort is stable, so users that tie on every key keep their listing order
+pub fn sort_users(users: &mut [User], order: &[UserOrder]) {
+    users.sort_by(|a, b| order.iter().map(|key| key.compare(a, b)).find(|ordering| ordering.is_ne()).unwrap_or(Ordering::Equal));
+}
+
+// Find the first field that appears more than once in a sort order
+pub fn duplicate_sort_field(order: &[UserOrder]) -> Option<UserSortField> {
+    order.iter().enumerate().find(|(index, key)| order[..*index].iter().any(|earlier| earlier.field == key.field)).map(|(_, key)| key.field)
+}
+
+// Define the longest name a user or organization may have, in characters; the schema's validator
+// attributes only take literals, so they repeat it
+pub const MAX_NAME_LENGTH: usize = 100;
+
+// Define the largest metadata a user may have, in bytes of compact JSON
+pub const MAX_METADATA_BYTES: usize = 16 * 1024;
+
+// Define how deeply objects and arrays may nest in metadata; the top-level object is depth 1
+pub const MAX_METADATA_DEPTH: usize = 8;
+
+// Check that metadata is a JSON object within the size and nesting limits, describing the first problem found
+pub fn validate_metadata(metadata: &serde_json::Value) -> Result<(), String> {
+    if !metadata.is_object() {
+        return Err("Metadata must be a JSON object".to_string());
+    }
+    let size = metadata.to_string().len();
+    if size > MAX_METADATA_BYTES {
+        return Err(format!("Metadata is {} bytes, over the {} byte limit", size, MAX_METADATA_BYTES));
+    }
+    if json_depth(metadata) > MAX_METADATA_DEPTH {
+        return Err(format!("Metadata nests more than {} levels deep", MAX_METADATA_DEPTH));
+    }
+    Ok(())
+}
+
+// Count the levels of objects and arrays in a JSON value; scalars have none
+fn json_depth(value: &serde_json::Value) -> usize {
+    match value {
+        serde_json::Value::Object(fields) => 1 + fields.values().map(json_depth).max().unwrap_or(0),
+        serde_json::Value::Array(items) => 1 + items.iter().map(json_depth).max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+// Read stored metadata, treating a stored JSON null as no metadata; updates clear metadata by storing one
+pub fn stored_metadata(metadata: Option<serde_json::Value>) -> Option<serde_json::Value> {
+    metadata.filter(|metadata| !metadata.is_null())
+}
+
+// Check that an email address has no whitespace, a single @, a non-empty local part, and a dotted domain
+pub fn is_valid_email(email: &str) -> bool {
+    if email.chars().any(char::is_whitespace) {
+        return false;
+    }
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty() && !domain.contains('@') && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.')
+        }
+        None => false,
+    }
+}
+
+// Define how many digits an E.164 number may have, country code included
+pub const PHONE_DIGITS: std::ops::RangeInclusive<usize> = 7..=15;
+
+// Normalize a phone number to E.164: a + followed by the country code and number with nothing between the
+// digits. Spaces, dashes, dots, and parentheses are dropped, and a leading 00 international prefix counts as
+// the +. Numbers without a country code cannot be normalized, so they are rejected, as is anything else
+// that cannot be dialled; the error says why
+pub fn normalize_phone(phone: &str) -> Result<String, String> {
+    let trimmed = phone.trim();
+    let Some(number) = trimmed.strip_prefix('+').or_else(|| trimmed.strip_prefix("00")) else {
+        return Err(format!("{:?} is not a valid phone number: it must start with + and a country code", phone));
+    };
+    let mut digits = String::with_capacity(number.len());
+    for character in number.chars() {
+        match character {
+            '0'..='9' => digits.push(character),
+            ' ' | '-' | '.' | '(' | ')' => {}
+            _ => return Err(format!("{:?} is not a valid phone number: {:?} is not a digit or separator", phone, character)),
+        }
+    }
+    if digits.starts_with('0') {
+        return Err(format!("{:?} is not a valid phone number: country codes never start with 0", phone));
+    }
+    if !PHONE_DIGITS.contains(&digits.len()) {
+        return Err(format!(
+            "{:?} is not a valid phone number: it has {} digits, but E.164 numbers have {} to {}",
+            phone,
+            digits.len(),
+            PHONE_DIGITS.start(),
+            PHONE_DIGITS.end()
+        ));
+    }
+    Ok(format!("+{}", digits))
+}
+
+// Generate the ID of a new user; version 7 UUIDs start with their creation time, so they sort in creation order
+pub fn new_user_id() -> Uuid {
+    Uuid::now_v7()
+}
+
+// Map a numeric user ID from before IDs were UUIDs to the UUID it was migrated to, which sorts before every generated one
+pub fn legacy_user_id(number: u64) -> Uuid {
+    Uuid::from_u128(number.into())
+}
+
+// Parse a user ID given as a UUID in any of its text forms, or as a positive numeric legacy ID
+pub fn parse_user_id(id: &str) -> Option<Uuid> {
+    match id.parse::<u64>() {
+        Ok(number) if number > 0 => Some(legacy_user_id(number)),
+        Ok(_) => None,
+        Err(_) => Uuid::parse_str(id).ok(),
+    }
+}
@@ -0,0 +1,91 @@
+// Import necessary libraries and modules
+use async_graphql::{Context, ErrorExtensions, Result, ResultExt, ID};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::loader::UserDataLoader;
+use crate::model::{parse_user_id, Node};
+use crate::repository::SharedRepository;
+use crate::service_accounts::ServiceAccounts;
+
+// Define the type names global IDs are tagged with, matching the GraphQL type names
+pub const USER: &str = "User";
+pub const POST: &str = "Post";
+pub const COMMENT: &str = "Comment";
+pub const ORGANIZATION: &str = "Organization";
+pub const SERVICE_ACCOUNT: &str = "ServiceAccount";
+
+// Encode a type name and a storage ID as a global ID, the base64 of "Type:id"
+pub fn global_id(type_name: &str, id: &str) -> ID {
+    ID(STANDARD.encode(format!("{}:{}", type_name, id)))
+}
+
+// Split a global ID into its type name and storage ID, or return None if it is not one
+pub fn decode_global_id(id: &str) -> Option<(String, String)> {
+    let decoded = String::from_utf8(STANDARD.decode(id).ok()?).ok()?;
+    let (type_name, local_id) = decoded.split_once(':')?;
+    Some((type_name.to_string(), local_id.to_string()))
+}
+
+// Turn an ID argument into the storage ID of a type. Global IDs of the type are decoded; anything else,
+// including plain storage IDs from clients written before global IDs, is passed through unchanged,
+// so global IDs of another type match nothing
+pub fn local_id(type_name: &str, id: &str) -> String {
+    match decode_global_id(id) {
+        Some((decoded_type, local_id)) if decoded_type == type_name => local_id,
+        _ => id.to_string(),
+    }
+}
+
+// Fetch whatever object a global ID names; unknown types, malformed IDs, and missing objects are null
+pub async fn fetch_node(ctx: &Context<'_>, id: &str) -> Result<Option<Node>> {
+    let Some((type_name, id)) = decode_global_id(id) else {
+        return Ok(None);
+    };
+    let repository = ctx.data::<SharedRepository>()?;
+    match type_name.as_str() {
+        USER => match parse_user_id(&id) {
+            Some(uuid) => Ok(ctx.data::<UserDataLoader>()?.load_one(uuid.to_string()).await.map_err(|error| error.extend())?.map(Node::User)),
+            None => Ok(None),
+        },
+        POST => match repository.posts() {
+            Some(posts) => Ok(posts.get_post(&id).await.extend()?.map(Node::Post)),
+            None => Ok(None),
+        },
+        COMMENT => match repository.posts() {
+            Some(posts) => Ok(posts.get_comment(&id).await.extend()?.map(Node::Comment)),
+            None => Ok(None),
+        },
+        ORGANIZATION => match repository.organizations() {
+            Some(organizations) => {
+                let mut found = organizations.get_organizations(std::slice::from_ref(&id)).await.extend()?;
+                Ok(found.remove(&id).map(Node::Organization))
+            }
+            None => Ok(None),
+        },
+        SERVICE_ACCOUNT => Ok(ctx.data_opt::<ServiceAccounts>().and_then(|accounts| accounts.get(&id)).cloned().map(Node::ServiceAccount)),
+        _ => Ok(None),
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Define a test that global IDs round-trip and that other IDs pass through as storage IDs
+    #[test]
+    fn test_global_ids() {
+        let id = global_id(USER, "42");
+        assert_eq!(id.as_str(), "VXNlcjo0Mg==");
+        assert_eq!(decode_global_id(&id), Some((USER.to_string(), "42".to_string())));
+        assert_eq!(local_id(USER, &id), "42");
+
+        // Plain storage IDs and global IDs of another type are left alone
+        assert_eq!(local_id(USER, "42"), "42");
+        assert_eq!(local_id(USER, "1406"), "1406");
+        let post_id = global_id(POST, "42");
+        assert_eq!(local_id(USER, &post_id), post_id.as_str());
+        assert_eq!(decode_global_id("not base64!"), None);
+    }
+}
@@ -0,0 +1,398 @@
+// Import necessary libraries and modules
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::http::header::{HeaderValue, LOCATION, SET_COOKIE};
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+use crate::auth::{random_token, Sessions};
+use crate::config::Settings;
+use crate::model::{NewUser, User};
+use crate::repository::SharedRepository;
+use crate::{log_error, log_warn};
+
+// Define the cookie that ties a login to the browser that started it
+const STATE_COOKIE: &str = "oidc_state";
+
+// Define how long a login may take between /auth/login and /auth/callback
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(600);
+
+// Define the provider and client settings of the login flow
+#[derive(Clone, Debug, PartialEq)]
+pub struct OidcConfig {
+    // The issuer URL, under which the provider publishes /.well-known/openid-configuration
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    // The URL the provider sends browsers back to, which must reach /auth/callback
+    pub redirect_url: String,
+    // Where browsers are sent once they have logged in
+    pub post_login_url: String,
+}
+
+impl OidcConfig {
+    // Read OIDC_ISSUER_URL, OIDC_CLIENT_ID, OIDC_CLIENT_SECRET, OIDC_REDIRECT_URL, and the optional
+    // OIDC_POST_LOGIN_URL (default /graphql); returns None when OIDC_ISSUER_URL is not set
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+        let Some(issuer_url) = settings.var("OIDC_ISSUER_URL") else {
+            return Ok(None);
+        };
+        let required = |name: &str| settings.var(name).filter(|value| !value.is_empty()).ok_or_else(|| format!("{} must be set", name));
+        Ok(Some(OidcConfig {
+            issuer_url: issuer_url.trim_end_matches('/').to_string(),
+            client_id: required("OIDC_CLIENT_ID")?,
+            client_secret: required("OIDC_CLIENT_SECRET")?,
+            redirect_url: required("OIDC_REDIRECT_URL")?,
+            post_login_url: settings.var("OIDC_POST_LOGIN_URL").unwrap_or_else(|| "/graphql".to_string()),
+        }))
+    }
+}
+
+// Define the endpoints read from the provider's discovery document
+#[derive(Clone, Debug, Deserialize)]
+struct ProviderMetadata {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+// Define the part of the token response the flow needs
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+// Define who the provider says logged in, as read from its userinfo endpoint
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Identity {
+    pub sub: String,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub name: Option<String>,
+}
+
+// Define a provider to log in with through the authorization code flow with PKCE. Logins that have
+// been started but not completed are kept in memory by their state, with the PKCE verifier they need
+pub struct OidcProvider {
+    config: OidcConfig,
+    metadata: ProviderMetadata,
+    client: reqwest::Client,
+    pending: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl OidcProvider {
+    // Read the provider's endpoints from its discovery document
+    pub async fn discover(config: OidcConfig) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|error| error.to_string())?;
+        let url = format!("{}/.well-known/openid-configuration", config.issuer_url);
+        let response = client.get(&url).send().await.and_then(|response| response.error_for_status());
+        let metadata = response.map_err(|error| format!("Could not read {}: {}", url, error))?;
+        let metadata = metadata.json().await.map_err(|error| format!("Invalid discovery document at {}: {}", url, error))?;
+        Ok(OidcProvider { config, metadata, client, pending: Mutex::new(HashMap::new()) })
+    }
+
+    // Start a login, returning the provider URL to send the browser to and the state that identifies the login
+    fn start_login(&self) -> (String, String) {
+        let (state, verifier) = (random_token(), random_token());
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, (_, started)| started.elapsed() < LOGIN_TIMEOUT);
+        pending.insert(state.clone(), (verifier, Instant::now()));
+        let params = [
+            ("response_type", "code"),
+            ("client_id", self.config.client_id.as_str()),
+            ("redirect_uri", self.config.redirect_url.as_str()),
+            ("scope", "openid email profile"),
+            ("state", state.as_str()),
+            ("code_challenge", challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ];
+        let url = reqwest::Url::parse_with_params(&self.metadata.authorization_endpoint, params)
+            .map_or_else(|_| self.metadata.authorization_endpoint.clone(), String::from);
+        (url, state)
+    }
+
+    // Finish a login: exchange the code for an access token and read who logged in. Each state can only be used once
+    async fn complete_login(&self, state: &str, code: &str) -> Result<Identity, LoginError> {
+        let pending = self.pending.lock().unwrap().remove(state);
+        let Some((verifier, _)) = pending.filter(|(_, started)| started.elapsed() < LOGIN_TIMEOUT) else {
+            return Err(LoginError::BadRequest("The login has expired or was not started here".to_string()));
+        };
+        let form = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_url.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("client_secret", self.config.client_secret.as_str()),
+            ("code_verifier", verifier.as_str()),
+        ];
+        let token: TokenResponse = self.fetch(self.client.post(&self.metadata.token_endpoint).form(&form)).await?;
+        self.fetch(self.client.get(&self.metadata.userinfo_endpoint).bearer_auth(token.access_token)).await
+    }
+
+    // Send a request to the provider and read its JSON response
+    async fn fetch<T: serde::de::DeserializeOwned>(&self, request: reqwest::RequestBuilder) -> Result<T, LoginError> {
+        let response = request.send().await.and_then(|response| response.error_for_status());
+        let response = response.map_err(|error| LoginError::Provider(error.to_string()))?;
+        response.json().await.map_err(|error| LoginError::Provider(error.to_string()))
+    }
+}
+
+// Define why a login could not be completed
+#[derive(Debug, PartialEq)]
+enum LoginError {
+    // The callback did not belong to a login started by this browser, or the provider refused it
+    BadRequest(String),
+    // The provider could not be reached or answered with something unexpected
+    Provider(String),
+    // The provider did not vouch for an email address to match a user by
+    Identity(String),
+}
+
+// Find the local user for an identity by its email address, creating one on their first login. The
+// provider must not report the address as unverified
+async fn local_user(repository: &SharedRepository, identity: &Identity) -> Result<User, LoginError> {
+    let Some(email) = identity.email.as_deref().map(str::trim).filter(|email| !email.is_empty()) else {
+        return Err(LoginError::Identity("The provider did not share an email address".to_string()));
+    };
+    if identity.email_verified == Some(false) {
+        return Err(LoginError::Identity("The email address has not been verified by the provider".to_string()));
+    }
+    if let Some(user) = repository.get_by_email(email).await.map_err(|error| LoginError::Provider(error.to_string()))? {
+        return Ok(user);
+    }
+    let name = identity.name.as_deref().map(str::trim).filter(|name| !name.is_empty()).unwrap_or(email);
+    let new_user = NewUser { name: name.chars().take(100).collect(), email: email.to_string(), ..Default::default() };
+    repository.create(new_user).await.map_err(|error| LoginError::Provider(error.to_string()))
+}
+
+// Build a response sending the browser elsewhere, setting cookies on the way
+fn redirect(location: &str, cookies: impl IntoIterator<Item = String>) -> warp::reply::Response {
+    let mut response = warp::reply::with_header(StatusCode::FOUND, LOCATION, location).into_response();
+    for cookie in cookies {
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(SET_COOKIE, cookie);
+        }
+    }
+    response
+}
+
+// Build the /auth/login and /auth/callback routes of the login flow. Logging in sends the browser to the
+// provider; the callback completes the login, starts a session for the matching user, and sends the
+// browser on with the session cookie. Without a provider both routes are not found
+pub fn login_routes(
+    provider: Option<Arc<OidcProvider>>,
+    repository: SharedRepository,
+    sessions: Sessions,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+    let login_provider = provider.clone();
+    let login = warp::path!("auth" / "login").and(warp::get()).and_then(move || {
+        let provider = login_provider.clone();
+        async move {
+            let Some(provider) = provider else {
+                return Err(warp::reject::not_found());
+            };
+            let (url, state) = provider.start_login();
+            let cookie = format!("{}={}; Path=/auth; Max-Age={}; HttpOnly; SameSite=Lax", STATE_COOKIE, state, LOGIN_TIMEOUT.as_secs());
+            Ok(redirect(&url, [cookie]))
+        }
+    });
+    let callback = warp::path!("auth" / "callback")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::cookie::optional::<String>(STATE_COOKIE))
+        .and_then(move |query: HashMap<String, String>, state_cookie: Option<String>| {
+            let (provider, repository, sessions) = (provider.clone(), repository.clone(), sessions.clone());
+            async move {
+                let Some(provider) = provider else {
+                    return Err(warp::reject::not_found());
+                };
+                Ok(callback_response(&provider, &repository, &sessions, &query, state_cookie.as_deref()).await)
+            }
+        });
+    login.or(callback).unify()
+}
+
+// Complete a login from the callback's query parameters and answer the browser
+async fn callback_response(
+    provider: &OidcProvider,
+    repository: &SharedRepository,
+    sessions: &Sessions,
+    query: &HashMap<String, String>,
+    state_cookie: Option<&str>,
+) -> warp::reply::Response {
+    let outcome = async {
+        if let Some(error) = query.get("error") {
+            return Err(LoginError::BadRequest(format!("The provider refused the login: {}", error)));
+        }
+        let (Some(code), Some(state)) = (query.get("code"), query.get("state")) else {
+            return Err(LoginError::BadRequest("The callback needs a code and a state".to_string()));
+        };
+        if state_cookie != Some(state.as_str()) {
+            return Err(LoginError::BadRequest("The login was not started by this browser".to_string()));
+        }
+        let identity = provider.complete_login(state, code).await?;
+        local_user(repository, &identity).await
+    };
+    match outcome.await {
+        Ok(user) => {
+            let token = match sessions.create(&user.id).await {
+                Ok(token) => token,
+                Err(error) => {
+                    log_error!("Failed to start a session: {}", error);
+                    return warp::reply::with_status("The session could not be started".to_string(), StatusCode::INTERNAL_SERVER_ERROR).into_response();
+                }
+            };
+            redirect(&provider.config.post_login_url, sessions.cookies(&token))
+        }
+        Err(LoginError::BadRequest(message)) => warp::reply::with_status(message, StatusCode::BAD_REQUEST).into_response(),
+        Err(LoginError::Identity(message)) => warp::reply::with_status(message, StatusCode::FORBIDDEN).into_response(),
+        Err(LoginError::Provider(message)) => {
+            log_warn!("Login failed: {}", message);
+            warp::reply::with_status("The login provider could not complete the login".to_string(), StatusCode::BAD_GATEWAY).into_response()
+        }
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::InMemoryRepository;
+
+    // Serve a fake provider that accepts the code `good` and reports the given identity
+    async fn fake_provider(identity: serde_json::Value) -> String {
+        let base = Arc::new(Mutex::new(String::new()));
+        let discovery_base = base.clone();
+        let discovery = warp::path!(".well-known" / "openid-configuration").map(move || {
+            let base = discovery_base.lock().unwrap().clone();
+            warp::reply::json(&serde_json::json!({
+                "authorization_endpoint": format!("{}/authorize", base),
+                "token_endpoint": format!("{}/token", base),
+                "userinfo_endpoint": format!("{}/userinfo", base),
+            }))
+        });
+        let token = warp::path!("token").and(warp::post()).and(warp::body::form()).map(|form: HashMap<String, String>| {
+            let valid = form.get("code").map(String::as_str) == Some("good")
+                && form.get("client_secret").map(String::as_str) == Some("client-secret")
+                && form.get("code_verifier").is_some_and(|verifier| !verifier.is_empty());
+            match valid {
+                true => warp::reply::with_status(warp::reply::json(&serde_json::json!({ "access_token": "access", "token_type": "Bearer" })), StatusCode::OK),
+                false => warp::reply::with_status(warp::reply::json(&serde_json::json!({ "error": "invalid_grant" })), StatusCode::BAD_REQUEST),
+            }
+        });
+        let userinfo = warp::path!("userinfo").and(warp::header::exact("authorization", "Bearer access")).map(move || warp::reply::json(&identity));
+        let (address, server) = warp::serve(discovery.or(token).or(userinfo)).bind_ephemeral(([127, 0, 0, 1], 0));
+        *base.lock().unwrap() = format!("http://{}", address);
+        tokio::spawn(server);
+        format!("http://{}", address)
+    }
+
+    // Build the login routes against a fake provider reporting the given identity
+    async fn provider_routes(
+        identity: serde_json::Value,
+        repository: SharedRepository,
+        sessions: Sessions,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+        let config = OidcConfig {
+            issuer_url: fake_provider(identity).await,
+            client_id: "client".to_string(),
+            client_secret: "client-secret".to_string(),
+            redirect_url: "http://localhost:3030/auth/callback".to_string(),
+            post_login_url: "/graphql".to_string(),
+        };
+        let provider = OidcProvider::discover(config).await.unwrap();
+        login_routes(Some(Arc::new(provider)), repository, sessions)
+    }
+
+    // Start a login and return the state the provider is sent, checking it matches the state cookie
+    async fn start(routes: &(impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone + 'static)) -> String {
+        let response = warp::test::request().path("/auth/login").reply(routes).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        let location = reqwest::Url::parse(response.headers()[LOCATION].to_str().unwrap()).unwrap();
+        let params: HashMap<String, String> = location.query_pairs().into_owned().collect();
+        assert_eq!(location.path(), "/authorize");
+        assert_eq!((params["client_id"].as_str(), params["code_challenge_method"].as_str()), ("client", "S256"));
+        let cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        assert!(cookie.starts_with(&format!("oidc_state={};", params["state"])) && cookie.contains("HttpOnly"));
+        params["state"].clone()
+    }
+
+    // Send the provider's callback with the given code and state, and the state cookie
+    async fn callback(
+        routes: &(impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone + 'static),
+        code: &str,
+        state: &str,
+        cookie: &str,
+    ) -> warp::http::Response<warp::hyper::body::Bytes> {
+        let path = format!("/auth/callback?code={}&state={}", code, state);
+        warp::test::request().path(&path).header("cookie", format!("oidc_state={}", cookie)).reply(routes).await
+    }
+
+    // Return the session token set by a callback response
+    fn session_token(response: &warp::http::Response<warp::hyper::body::Bytes>) -> String {
+        let cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        cookie.strip_prefix("session=").unwrap().split(';').next().unwrap().to_string()
+    }
+
+    // Define a test that a login through the provider starts a session for the user with the same email
+    #[tokio::test]
+    async fn test_login_flow() {
+        let repository: SharedRepository = Arc::new(InMemoryRepository::with_sample_users());
+        let sessions = Sessions::default();
+        let identity = serde_json::json!({ "sub": "external-1", "email": "pavelboukine@gmail.com", "email_verified": true, "name": "P" });
+        let routes = provider_routes(identity, repository.clone(), sessions.clone()).await;
+
+        let state = start(&routes).await;
+        let response = callback(&routes, "good", &state, &state).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.headers()[LOCATION], "/graphql");
+        let pavel = repository.get_by_email("Pavelboukine@gmail.com").await.unwrap().unwrap();
+        assert_eq!(sessions.user_id(&session_token(&response)).await.unwrap(), Some(pavel.id));
+
+        // Each login can only be completed once, by the browser that started it, with a code the provider accepts
+        assert_eq!(callback(&routes, "good", &state, &state).await.status(), StatusCode::BAD_REQUEST);
+        let state = start(&routes).await;
+        assert_eq!(callback(&routes, "good", &state, "other").await.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(callback(&routes, "bad", &state, &state).await.status(), StatusCode::BAD_GATEWAY);
+        let response = warp::test::request().path("/auth/callback?error=access_denied").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(repository.list().await.unwrap().len(), 2);
+    }
+
+    // Define a test that the first login of an unknown email creates a user, and unverified emails are refused
+    #[tokio::test]
+    async fn test_login_creates_users() {
+        let repository: SharedRepository = Arc::new(InMemoryRepository::new());
+        let sessions = Sessions::default();
+        let identity = serde_json::json!({ "sub": "external-2", "email": "ada@example.com", "name": "Ada Lovelace" });
+        let routes = provider_routes(identity, repository.clone(), sessions.clone()).await;
+        let state = start(&routes).await;
+        let response = callback(&routes, "good", &state, &state).await;
+        let ada = repository.get_by_email("ada@example.com").await.unwrap().unwrap();
+        assert_eq!(ada.name, "Ada Lovelace");
+        assert_eq!(sessions.user_id(&session_token(&response)).await.unwrap(), Some(ada.id));
+
+        let identity = serde_json::json!({ "sub": "external-3", "email": "eve@example.com", "email_verified": false });
+        let routes = provider_routes(identity, repository.clone(), sessions).await;
+        let state = start(&routes).await;
+        assert_eq!(callback(&routes, "good", &state, &state).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(repository.get_by_email("eve@example.com").await.unwrap(), None);
+    }
+
+    // Define a test that the routes are not found without a provider
+    #[tokio::test]
+    async fn test_login_disabled() {
+        let routes = login_routes(None, Arc::new(InMemoryRepository::new()), Sessions::default());
+        assert_eq!(warp::test::request().path("/auth/login").reply(&routes).await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(warp::test::request().path("/auth/callback?code=a&state=b").reply(&routes).await.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,430 @@
+// Import necessary libraries and modules
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::Settings;
+use crate::log_warn;
+use crate::model::User;
+use crate::repository::{RepositoryError, RepositoryResult, SharedRepository, UserRepository};
+
+// Define how many events the relay publishes per batch
+pub const RELAY_BATCH_SIZE: usize = 100;
+
+// Define the poll interval used when OUTBOX_POLL_INTERVAL_MS is not set
+const DEFAULT_POLL_INTERVAL_MS: u64 = 1000;
+
+// Define the Kafka topic used when KAFKA_TOPIC is not set
+const DEFAULT_KAFKA_TOPIC: &str = "user-changes";
+
+// Define the kinds of change recorded in the outbox
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+    Restored,
+    Purged,
+}
+
+impl ChangeKind {
+    // Return the name stored in the outbox and sent to sinks
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChangeKind::Created => "created",
+            ChangeKind::Updated => "updated",
+            ChangeKind::Deleted => "deleted",
+            ChangeKind::Restored => "restored",
+            ChangeKind::Purged => "purged",
+        }
+    }
+
+    // Parse a stored change name
+    pub fn parse(value: &str) -> RepositoryResult<Self> {
+        match value {
+            "created" => Ok(ChangeKind::Created),
+            "updated" => Ok(ChangeKind::Updated),
+            "deleted" => Ok(ChangeKind::Deleted),
+            "restored" => Ok(ChangeKind::Restored),
+            "purged" => Ok(ChangeKind::Purged),
+            other => Err(RepositoryError::Backend(format!("unknown change kind {:?} in the outbox", other))),
+        }
+    }
+}
+
+// Define one change event: what happened, the user as it was left by the change, and when.
+// IDs are unique and increase as events are recorded, so consumers can use them to drop duplicates
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChangeEvent {
+    pub id: i64,
+    pub kind: ChangeKind,
+    pub user: User,
+    pub created_at: DateTime<Utc>,
+}
+
+// Define a destination that change events are published to
+#[async_trait]
+pub trait OutboxSink: Send + Sync {
+    // Publish a batch of events in order, failing if any of them could not be delivered
+    async fn publish(&self, events: &[ChangeEvent]) -> RepositoryResult<()>;
+}
+
+// Define a shared, thread-safe handle to a sink
+pub type SharedSink = Arc<dyn OutboxSink>;
+
+// Serialize an event as JSON for sinks that send text
+fn event_json(event: &ChangeEvent) -> RepositoryResult<String> {
+    serde_json::to_string(event).map_err(|error| RepositoryError::Backend(error.to_string()))
+}
+
+// Define a LogSink that prints every event as a line of JSON
+pub struct LogSink;
+
+#[async_trait]
+impl OutboxSink for LogSink {
+    async fn publish(&self, events: &[ChangeEvent]) -> RepositoryResult<()> {
+        for event in events {
+            println!("Change event: {}", event_json(event)?);
+        }
+        Ok(())
+    }
+}
+
+// Define a WebhookSink that POSTs each batch as a JSON array
+#[cfg(feature = "webhook")]
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[cfg(feature = "webhook")]
+impl WebhookSink {
+    // Send batches to the given URL, giving up on a request after 10 seconds
+    pub fn new(url: &str) -> RepositoryResult<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|error| RepositoryError::Config(error.to_string()))?;
+        Ok(WebhookSink { client, url: url.to_string() })
+    }
+}
+
+#[cfg(feature = "webhook")]
+#[async_trait]
+impl OutboxSink for WebhookSink {
+    async fn publish(&self, events: &[ChangeEvent]) -> RepositoryResult<()> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(events)
+            .send()
+            .await
+            .map_err(|error| RepositoryError::Backend(error.to_string()))?;
+        if !response.status().is_success() {
+            return Err(RepositoryError::Backend(format!("webhook returned {}", response.status())));
+        }
+        Ok(())
+    }
+}
+
+// Define a KafkaSink that produces each event to partition 0 of a topic, keyed by user ID.
+// A single partition keeps every consumer seeing the events in commit order
+#[cfg(feature = "kafka")]
+pub struct KafkaSink {
+    partition: rskafka::client::partition::PartitionClient,
+}
+
+#[cfg(feature = "kafka")]
+impl KafkaSink {
+    // Connect to the brokers and look up the topic's partition
+    pub async fn connect(brokers: Vec<String>, topic: &str) -> RepositoryResult<Self> {
+        use rskafka::client::partition::UnknownTopicHandling;
+        let client = rskafka::client::ClientBuilder::new(brokers).build().await?;
+        let partition = client.partition_client(topic, 0, UnknownTopicHandling::Retry).await?;
+        Ok(KafkaSink { partition })
+    }
+}
+
+// Convert Kafka client errors into backend errors
+#[cfg(feature = "kafka")]
+impl From<rskafka::client::error::Error> for RepositoryError {
+    fn from(error: rskafka::client::error::Error) -> Self {
+        RepositoryError::Backend(error.to_string())
+    }
+}
+
+#[cfg(feature = "kafka")]
+#[async_trait]
+impl OutboxSink for KafkaSink {
+    async fn publish(&self, events: &[ChangeEvent]) -> RepositoryResult<()> {
+        use rskafka::client::partition::Compression;
+        use rskafka::record::Record;
+        let records = events
+            .iter()
+            .map(|event| {
+                Ok(Record {
+                    key: Some(event.user.id.clone().into_bytes()),
+                    value: Some(event_json(event)?.into_bytes()),
+                    headers: Default::default(),
+                    timestamp: event.created_at,
+                })
+            })
+            .collect::<RepositoryResult<Vec<_>>>()?;
+        self.partition.produce(records, Compression::NoCompression).await?;
+        Ok(())
+    }
+}
+
+// Define where the relay sends events
+#[derive(Clone, Debug, PartialEq)]
+pub enum SinkConfig {
+    Log,
+    Webhook { url: String },
+    Kafka { brokers: Vec<String>, topic: String },
+}
+
+// Define the settings for the outbox relay
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutboxConfig {
+    pub sink: SinkConfig,
+    pub poll_interval: Duration,
+}
+
+// Read a required setting for the chosen sink
+fn required_var(settings: &Settings, name: &str, sink: &str) -> RepositoryResult<String> {
+    settings.var(name).ok_or_else(|| RepositoryError::Config(format!("{} must be set for the {} sink", name, sink)))
+}
+
+impl OutboxConfig {
+    // Read OUTBOX_SINK (log, webhook, or kafka) and its settings; returns None when the relay is disabled.
+    // The webhook sink needs OUTBOX_WEBHOOK_URL, and the kafka sink needs KAFKA_BROKERS and an optional
+    // KAFKA_TOPIC (default "user-changes"). OUTBOX_POLL_INTERVAL_MS defaults to 1000
+    pub fn from_settings(settings: &Settings) -> RepositoryResult<Option<Self>> {
+        let Some(sink) = settings.var("OUTBOX_SINK") else {
+            return Ok(None);
+        };
+        let sink = match sink.as_str() {
+            "log" => SinkConfig::Log,
+            "webhook" => SinkConfig::Webhook { url: required_var(settings, "OUTBOX_WEBHOOK_URL", "webhook")? },
+            "kafka" => SinkConfig::Kafka {
+                brokers: required_var(settings, "KAFKA_BROKERS", "kafka")?
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|broker| !broker.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+                topic: settings.var("KAFKA_TOPIC").unwrap_or_else(|| DEFAULT_KAFKA_TOPIC.to_string()),
+            },
+            other => {
+                return Err(RepositoryError::Config(format!(
+                    "OUTBOX_SINK must be log, webhook, or kafka, got {:?}",
+                    other
+                )))
+            }
+        };
+        let poll_interval_ms = match settings.var("OUTBOX_POLL_INTERVAL_MS") {
+            Some(value) => value.parse().map_err(|_| {
+                RepositoryError::Config(format!("OUTBOX_POLL_INTERVAL_MS must be a number, got {:?}", value))
+            })?,
+            None => DEFAULT_POLL_INTERVAL_MS,
+        };
+        Ok(Some(OutboxConfig {
+            sink,
+            poll_interval: Duration::from_millis(poll_interval_ms),
+        }))
+    }
+
+    // Create the configured sink, failing if it was not compiled in
+    pub async fn connect_sink(&self) -> RepositoryResult<SharedSink> {
+        match &self.sink {
+            SinkConfig::Log => Ok(Arc::new(LogSink)),
+            #[cfg(feature = "webhook")]
+            SinkConfig::Webhook { url } => Ok(Arc::new(WebhookSink::new(url)?)),
+            #[cfg(feature = "kafka")]
+            SinkConfig::Kafka { brokers, topic } => Ok(Arc::new(KafkaSink::connect(brokers.clone(), topic).await?)),
+            #[allow(unreachable_patterns)]
+            _ => Err(RepositoryError::Config(format!(
+                "the {:?} outbox sink is not compiled in; enable its cargo feature",
+                self.sink
+            ))),
+        }
+    }
+}
+
+// Publish one batch of pending events and remove it from the outbox, returning how many were sent.
+// Events are only removed after the sink accepts them, so delivery is at least once
+pub async fn relay_once(repository: &dyn UserRepository, sink: &dyn OutboxSink, limit: usize) -> RepositoryResult<usize> {
+    let events = repository.pending_events(limit).await?;
+    if events.is_empty() {
+        return Ok(0);
+    }
+    sink.publish(&events).await?;
+    let ids: Vec<i64> = events.iter().map(|event| event.id).collect();
+    repository.acknowledge_events(&ids).await?;
+    Ok(events.len())
+}
+
+// Run the relay in the background, draining the outbox in batches and polling when it is empty.
+// Failed batches are retried on the next poll
+pub fn spawn_relay(repository: SharedRepository, sink: SharedSink, poll_interval: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            match relay_once(repository.as_ref(), sink.as_ref(), RELAY_BATCH_SIZE).await {
+                // A full batch suggests more are waiting, so keep going without sleeping
+                Ok(count) if count == RELAY_BATCH_SIZE => continue,
+                Ok(_) => {}
+                Err(error) => log_warn!("Publishing change events failed, retrying: {}", error),
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    })
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{NewUser, UserRole, UserUpdate};
+    use crate::repository::{with_transaction, InMemoryRepository};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::sync::Mutex;
+
+    // Define a sink that collects published events and can be made to fail
+    #[derive(Default)]
+    struct CollectingSink {
+        published: Mutex<Vec<ChangeEvent>>,
+        failing: AtomicBool,
+    }
+
+    #[async_trait]
+    impl OutboxSink for CollectingSink {
+        async fn publish(&self, events: &[ChangeEvent]) -> RepositoryResult<()> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(RepositoryError::Backend("sink unavailable".to_string()));
+            }
+            self.published.lock().await.extend_from_slice(events);
+            Ok(())
+        }
+    }
+
+    // Define a test that every write records an event and the relay publishes each one once
+    #[tokio::test]
+    async fn test_relay_publishes_every_change() {
+        let repository = InMemoryRepository::new();
+        let sink = CollectingSink::default();
+        let ada = repository
+            .create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() })
+            .await
+            .unwrap();
+        let update = UserUpdate { name: Some("Ada Lovelace".to_string()), ..Default::default() };
+        repository.update(&ada.id, update).await.unwrap();
+        repository.delete(&ada.id).await.unwrap();
+        repository.restore(&ada.id).await.unwrap();
+        repository.purge(&ada.id).await.unwrap();
+
+        // Writes that change nothing record nothing
+        repository.delete("missing").await.unwrap();
+
+        // Events go out in batches and in order, and are gone from the outbox once published
+        assert_eq!(relay_once(&repository, &sink, 3).await.unwrap(), 3);
+        assert_eq!(relay_once(&repository, &sink, 3).await.unwrap(), 2);
+        assert_eq!(relay_once(&repository, &sink, 3).await.unwrap(), 0);
+        let published = sink.published.lock().await;
+        let kinds: Vec<ChangeKind> = published.iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![ChangeKind::Created, ChangeKind::Updated, ChangeKind::Deleted, ChangeKind::Restored, ChangeKind::Purged]
+        );
+        assert_eq!(published.iter().map(|event| event.id).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+        assert_eq!(published[1].user.name, "Ada Lovelace");
+        assert!(published[2].user.deleted_at.is_some());
+    }
+
+    // Define a test that events stay in the outbox until the sink accepts them
+    #[tokio::test]
+    async fn test_failed_publish_is_retried() {
+        let repository = InMemoryRepository::new();
+        let sink = CollectingSink::default();
+        repository
+            .create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() })
+            .await
+            .unwrap();
+
+        sink.failing.store(true, Ordering::SeqCst);
+        assert!(relay_once(&repository, &sink, 10).await.is_err());
+        assert_eq!(repository.pending_events(10).await.unwrap().len(), 1);
+
+        sink.failing.store(false, Ordering::SeqCst);
+        assert_eq!(relay_once(&repository, &sink, 10).await.unwrap(), 1);
+        assert_eq!(repository.pending_events(10).await.unwrap(), Vec::new());
+    }
+
+    // Define a test that transactional writes only record events when they commit
+    #[tokio::test]
+    async fn test_transaction_events_follow_commit() {
+        let repository = InMemoryRepository::new();
+        let result: RepositoryResult<()> = with_transaction(&repository, |transaction| {
+            Box::pin(async move {
+                transaction
+                    .create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() })
+                    .await?;
+                Err(RepositoryError::Conflict("abort".to_string()))
+            })
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(repository.pending_events(10).await.unwrap(), Vec::new());
+
+        with_transaction(&repository, |transaction| {
+            Box::pin(async move {
+                let user = transaction
+                    .create(NewUser { name: "Grace".to_string(), email: "grace@example.com".to_string(), ..Default::default() })
+                    .await?;
+                transaction.delete(&user.id).await
+            })
+        })
+        .await
+        .unwrap();
+        let kinds: Vec<ChangeKind> = repository.pending_events(10).await.unwrap().into_iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, vec![ChangeKind::Created, ChangeKind::Deleted]);
+    }
+
+    // Define a test for the JSON shape sent to sinks
+    #[test]
+    fn test_event_json() {
+        let event = ChangeEvent {
+            id: 7,
+            kind: ChangeKind::Updated,
+            user: User {
+                id: "1".to_string(),
+                name: "Ada".to_string(),
+                email: "ada@example.com".to_string(),
+                version: 2,
+                created_at: DateTime::from_timestamp(1_699_999_000, 0).unwrap(),
+                updated_at: DateTime::from_timestamp(1_699_999_500, 0).unwrap(),
+                deleted_at: None,
+                avatar_key: None,
+                role: UserRole::Admin,
+                metadata: None,
+                phone: None,
+                address: None,
+                email_verified: true,
+            },
+            created_at: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        };
+        let json: serde_json::Value = serde_json::from_str(&event_json(&event).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "id": 7,
+                "kind": "updated",
+                "user": { "id": "1", "name": "Ada", "email": "ada@example.com", "version": 2,
+                    "created_at": "2023-11-14T21:56:40Z", "updated_at": "2023-11-14T22:05:00Z", "deleted_at": null, "avatar_key": null, "role": "admin", "metadata": null, "phone": null, "address": null, "email_verified": true },
+                "created_at": "2023-11-14T22:13:20Z"
+            })
+        );
+    }
+}
@@ -0,0 +1,137 @@
+// Import necessary libraries and modules
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::Duration;
+
+use crate::avatar::decode_hex;
+use crate::config::Settings;
+use crate::model::User;
+use crate::verification::{mailer_from_settings, EmailMessage, SharedMailer};
+
+// Define how long reset links stay valid when PASSWORD_RESET_TTL_SECS is not set
+const DEFAULT_TTL_SECS: u64 = 60 * 60;
+
+// Define how users who forgot their password set a new one: they are mailed a link to `link_url`
+// carrying a token signed with HMAC-SHA256 over their ID, the link's expiry time, and their current
+// password hash. Setting a password changes the hash, so each link works once, and only until it expires
+#[derive(Clone)]
+pub struct PasswordReset {
+    signing_key: Vec<u8>,
+    link_url: String,
+    ttl: Duration,
+    mailer: SharedMailer,
+}
+
+impl PasswordReset {
+    // Sign tokens with the given key and mail links to the given page, valid for an hour
+    pub fn new(signing_key: Vec<u8>, link_url: &str, mailer: SharedMailer) -> Self {
+        PasswordReset { signing_key, link_url: link_url.to_string(), ttl: Duration::from_secs(DEFAULT_TTL_SECS), mailer }
+    }
+
+    // Keep links valid for the given time instead
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    // Read PASSWORD_RESET_URL, the page links point to, which turns resets on; without it None is returned.
+    // PASSWORD_RESET_SIGNING_KEY must then be set, PASSWORD_RESET_TTL_SECS defaults to 3600, and the mailer
+    // is read by `mailer_from_settings`
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+        let Some(link_url) = settings.var("PASSWORD_RESET_URL") else {
+            return Ok(None);
+        };
+        let signing_key = match settings.var("PASSWORD_RESET_SIGNING_KEY") {
+            Some(key) if !key.is_empty() => key.into_bytes(),
+            _ => return Err("PASSWORD_RESET_SIGNING_KEY must be set to sign reset links".to_string()),
+        };
+        let ttl = match settings.var("PASSWORD_RESET_TTL_SECS") {
+            Some(value) => match value.parse() {
+                Ok(seconds) if seconds > 0 => Duration::from_secs(seconds),
+                _ => return Err(format!("PASSWORD_RESET_TTL_SECS must be a positive number, got {:?}", value)),
+            },
+            None => Duration::from_secs(DEFAULT_TTL_SECS),
+        };
+        Ok(Some(PasswordReset::new(signing_key, &link_url, mailer_from_settings(settings)?).with_ttl(ttl)))
+    }
+
+    // Start the HMAC over the signed part of a token
+    fn mac(&self, user_id: &str, password_hash: &str, expires: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.signing_key).expect("HMAC accepts keys of any length");
+        mac.update(format!("reset-password:{}:{}:{}", user_id, expires, password_hash).as_bytes());
+        mac
+    }
+
+    // Build the token for a user with the given password hash, expiring at the given Unix time, as
+    // `<user ID>.<expiry>.<hex signature>`
+    fn token_at(&self, user_id: &str, password_hash: &str, expires: i64) -> String {
+        let signature: String = self.mac(user_id, password_hash, expires).finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("{}.{}.{}", user_id, expires, signature)
+    }
+
+    // Build a token for a user with the given password hash that expires after the configured time
+    pub fn token(&self, user_id: &str, password_hash: &str) -> String {
+        let ttl = i64::try_from(self.ttl.as_secs()).unwrap_or(i64::MAX);
+        self.token_at(user_id, password_hash, Utc::now().timestamp().saturating_add(ttl))
+    }
+
+    // Return the ID of the user a token was issued to, if it is well-formed
+    pub fn token_user_id(token: &str) -> Option<&str> {
+        token.split('.').next().filter(|user_id| !user_id.is_empty())
+    }
+
+    // Check a token was issued to the user while they had the given password hash and has not expired,
+    // comparing the signature in constant time
+    pub fn verify(&self, token: &str, user_id: &str, password_hash: &str) -> bool {
+        let mut parts = token.split('.');
+        let (Some(token_user_id), Some(expires), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return false;
+        };
+        let (Ok(expires), Some(signature)) = (expires.parse::<i64>(), decode_hex(signature)) else {
+            return false;
+        };
+        token_user_id == user_id && expires >= Utc::now().timestamp() && self.mac(user_id, password_hash, expires).verify_slice(&signature).is_ok()
+    }
+
+    // Mail the user a link that lets them set a new password in place of the one with the given hash
+    pub async fn send(&self, user: &User, password_hash: &str) -> Result<(), String> {
+        let separator = if self.link_url.contains('?') { '&' } else { '?' };
+        let link = format!("{}{}token={}", self.link_url, separator, self.token(&user.id, password_hash));
+        let body = format!(
+            "Hi {},\n\nOpen this link to choose a new password:\n{}\n\nThe link works once, for {} minutes. If you did not ask to reset your password, you can ignore this email.",
+            user.name,
+            link,
+            self.ttl.as_secs().div_ceil(60)
+        );
+        let message = EmailMessage { to: user.email.clone(), subject: "Reset your password".to_string(), body };
+        self.mailer.send(&message).await
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verification::LogMailer;
+    use std::sync::Arc;
+
+    // Define a test that tokens verify only for the user and password hash they were issued for, until they expire
+    #[test]
+    fn test_reset_tokens() {
+        let reset = PasswordReset::new(b"signing-key".to_vec(), "https://app.example.com/reset", Arc::new(LogMailer));
+        let token = reset.token("1", "$argon2id$old");
+        assert_eq!(PasswordReset::token_user_id(&token), Some("1"));
+        assert!(reset.verify(&token, "1", "$argon2id$old"));
+
+        // Another user, a changed password, another key, a tampered signature, and an expired link are all refused
+        assert!(!reset.verify(&token, "2", "$argon2id$old"));
+        assert!(!reset.verify(&token, "1", "$argon2id$new"));
+        let other_key = PasswordReset::new(b"other-key".to_vec(), "https://app.example.com/reset", Arc::new(LogMailer));
+        assert!(!other_key.verify(&token, "1", "$argon2id$old"));
+        let tampered = format!("{}0", token.trim_end_matches(|c: char| c.is_ascii_hexdigit()));
+        assert!(!reset.verify(&tampered, "1", "$argon2id$old"));
+        assert!(!reset.verify(&reset.token_at("1", "$argon2id$old", 1), "1", "$argon2id$old"));
+        assert!(!reset.verify("not-a-token", "1", "$argon2id$old"));
+    }
+}
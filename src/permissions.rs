@@ -0,0 +1,108 @@
+// Import necessary libraries and modules
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextExecute, NextPrepareRequest};
+use async_graphql::{Context, Request, Response, ServerResult, Value};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+use crate::auth::AuthContext;
+use crate::loader::UserDataLoader;
+use crate::model::UserRole;
+
+// Define the response extension listing the fields that were withheld from the caller
+pub const REDACTED_FIELDS: &str = "redactedFields";
+
+// Define who may read a protected field: the user the object belongs to, if any, and every user whose
+// role includes the given one. Resolvers return the field through `resolve`, which gives everyone else
+// null and names the field in the response's `extensions.redactedFields`
+#[derive(Clone, Copy, Debug)]
+pub struct FieldPermission<'a> {
+    owner_id: Option<&'a str>,
+    role: UserRole,
+}
+
+impl<'a> FieldPermission<'a> {
+    // Allow only users whose role includes the given one
+    pub fn role(role: UserRole) -> Self {
+        FieldPermission { owner_id: None, role }
+    }
+
+    // Allow the user the object belongs to as well
+    pub fn owner_or_role(owner_id: &'a str, role: UserRole) -> Self {
+        FieldPermission { owner_id: Some(owner_id), role }
+    }
+
+    // Tell whether the caller may read the field; anonymous callers never may
+    pub async fn allows(&self, ctx: &Context<'_>) -> bool {
+        let Some(viewer_id) = ctx.data_opt::<AuthContext>().and_then(AuthContext::principal) else {
+            return false;
+        };
+        if self.owner_id == Some(viewer_id) {
+            return true;
+        }
+        let role = match ctx.data_opt::<Arc<PermissionState>>() {
+            Some(state) => *state.viewer_role.get_or_init(|| viewer_role(ctx, viewer_id)).await,
+            None => viewer_role(ctx, viewer_id).await,
+        };
+        role.is_some_and(|role| role.includes(self.role))
+    }
+
+    // Return the value if the caller may read the field, and None otherwise, noting the field's path
+    pub async fn resolve<T>(&self, ctx: &Context<'_>, value: T) -> Option<T> {
+        if self.allows(ctx).await {
+            return Some(value);
+        }
+        if let (Some(state), Some(path)) = (ctx.data_opt::<Arc<PermissionState>>(), ctx.path_node) {
+            state.redacted.lock().unwrap().push(path.to_string());
+        }
+        None
+    }
+}
+
+// Look up the role of the user who made the request; unknown users have none
+async fn viewer_role(ctx: &Context<'_>, viewer_id: &str) -> Option<UserRole> {
+    let loader = ctx.data_opt::<UserDataLoader>()?;
+    loader.load_one(viewer_id.to_string()).await.ok().flatten().map(|user| user.role)
+}
+
+// Define the extension that reports redacted fields; it is installed on every schema
+pub struct FieldPermissions;
+
+impl ExtensionFactory for FieldPermissions {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(FieldPermissionsExtension::default())
+    }
+}
+
+// Define the state shared by one request's resolvers: the caller's role, looked up at most once, and
+// the paths of the fields withheld so far
+#[derive(Default)]
+struct PermissionState {
+    viewer_role: OnceCell<Option<UserRole>>,
+    redacted: Mutex<Vec<String>>,
+}
+
+// Each request gets its own extension, which hands its state to the resolvers through the request data
+#[derive(Default)]
+struct FieldPermissionsExtension {
+    state: Arc<PermissionState>,
+}
+
+#[async_trait]
+impl Extension for FieldPermissionsExtension {
+    async fn prepare_request(&self, ctx: &ExtensionContext<'_>, request: Request, next: NextPrepareRequest<'_>) -> ServerResult<Request> {
+        next.run(ctx, request.data(self.state.clone())).await
+    }
+
+    async fn execute(&self, ctx: &ExtensionContext<'_>, operation_name: Option<&str>, next: NextExecute<'_>) -> Response {
+        let mut response = next.run(ctx, operation_name).await;
+        // Fields resolve concurrently, so the paths are sorted to keep the list stable
+        let mut redacted = std::mem::take(&mut *self.state.redacted.lock().unwrap());
+        redacted.sort();
+        if !redacted.is_empty() {
+            let paths = Value::List(redacted.into_iter().map(Value::String).collect());
+            response.extensions.insert(REDACTED_FIELDS.to_string(), paths);
+        }
+        response
+    }
+}
@@ -0,0 +1,266 @@
+// Import necessary libraries and modules
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextPrepareRequest};
+use async_graphql::{ErrorExtensions, Pos, Request, ServerResult, Value};
+use async_trait::async_trait;
+use lru::LruCache;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::config::Settings;
+use crate::error::AppError;
+use crate::upload::number_var;
+
+// Define the message clients get for a hash that is not registered, which Apollo clients recognise
+pub const PERSISTED_QUERY_NOT_FOUND: &str = "PersistedQueryNotFound";
+
+// Define how many automatically persisted queries are kept when PERSISTED_QUERIES_CACHE_SIZE is not set
+pub const DEFAULT_CACHE_SIZE: usize = 1000;
+
+// Compute the hash a persisted query is registered under: the hex SHA-256 of its text, as clients send
+// in the persistedQuery request extension
+pub fn persisted_query_hash(query: &str) -> String {
+    Sha256::digest(query.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Define a manifest of the operations to register at startup, in the format of Apollo's persisted query
+// manifests; each operation's ID, when given, must be the hash of its body
+#[derive(Deserialize)]
+struct Manifest {
+    operations: Vec<ManifestOperation>,
+}
+
+#[derive(Deserialize)]
+struct ManifestOperation {
+    id: Option<String>,
+    body: String,
+}
+
+// Define the registered queries, by hash. When they are required, they are the only queries the server
+// runs; otherwise clients may also send any query in full. With automatic persisted queries, clients
+// register queries themselves by sending them with their hash, and those are kept in a cache of their own
+#[derive(Clone, Default)]
+pub struct PersistedQueries {
+    queries: Arc<RwLock<HashMap<String, String>>>,
+    // The queries clients registered, of which the least recently used are evicted once it is full
+    automatic: Option<Arc<Mutex<LruCache<String, String>>>>,
+    required: bool,
+}
+
+impl PersistedQueries {
+    // Create an empty set of persisted queries, required or not
+    pub fn new(required: bool) -> Self {
+        PersistedQueries { queries: Arc::default(), automatic: None, required }
+    }
+
+    // Let clients register queries by sending them with their hash, keeping up to `capacity` of them
+    pub fn with_automatic(mut self, capacity: NonZeroUsize) -> Self {
+        self.automatic = Some(Arc::new(Mutex::new(LruCache::new(capacity))));
+        self
+    }
+
+    // Read PERSISTED_QUERIES_FILE, the path of a manifest to register, PERSISTED_QUERIES_ONLY (true or
+    // false, default false), PERSISTED_QUERIES_AUTOMATIC (true or false, default false), which turns on
+    // automatic persisted queries, and PERSISTED_QUERIES_CACHE_SIZE, how many of those are kept (default
+    // 1000). Without any of them persisted queries are not enabled
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+        let flag = |name: &str| match settings.var(name).as_deref() {
+            None | Some("false") => Ok(false),
+            Some("true") => Ok(true),
+            Some(other) => Err(format!("{} must be true or false, got {:?}", name, other)),
+        };
+        let (required, automatic) = (flag("PERSISTED_QUERIES_ONLY")?, flag("PERSISTED_QUERIES_AUTOMATIC")?);
+        if required && automatic {
+            return Err("PERSISTED_QUERIES_AUTOMATIC cannot be combined with PERSISTED_QUERIES_ONLY, which only runs registered queries".to_string());
+        }
+        let cache_size = number_var(settings, "PERSISTED_QUERIES_CACHE_SIZE", DEFAULT_CACHE_SIZE)?;
+        let manifest = settings.var("PERSISTED_QUERIES_FILE");
+        if manifest.is_none() && !required && !automatic {
+            return Ok(None);
+        }
+        let mut persisted = PersistedQueries::new(required);
+        if automatic {
+            persisted = persisted.with_automatic(NonZeroUsize::new(cache_size).unwrap_or(NonZeroUsize::MIN));
+        }
+        if let Some(path) = manifest {
+            let contents = std::fs::read_to_string(&path).map_err(|error| format!("Failed to read {}: {}", path, error))?;
+            persisted.load_manifest(&contents).map_err(|error| format!("Invalid manifest {}: {}", path, error))?;
+        }
+        Ok(Some(persisted))
+    }
+
+    // Tell whether only persisted queries are run
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    // Register every operation in a manifest and return how many there were
+    pub fn load_manifest(&self, manifest: &str) -> Result<usize, String> {
+        let manifest: Manifest = serde_json::from_str(manifest).map_err(|error| error.to_string())?;
+        for operation in &manifest.operations {
+            let hash = self.register(&operation.body)?;
+            if operation.id.as_ref().is_some_and(|id| *id != hash) {
+                return Err(format!("Operation {} is not the hash of its body, which is {}", operation.id.as_deref().unwrap_or_default(), hash));
+            }
+        }
+        Ok(manifest.operations.len())
+    }
+
+    // Register a query, which must parse, and return its hash
+    pub fn register(&self, query: &str) -> Result<String, String> {
+        async_graphql::parser::parse_query(query).map_err(|error| error.to_string())?;
+        let hash = persisted_query_hash(query);
+        self.queries.write().unwrap().insert(hash.clone(), query.to_string());
+        Ok(hash)
+    }
+
+    // Return the query registered under a hash, by the server or, with automatic persisted queries, by a
+    // client
+    pub fn get(&self, hash: &str) -> Option<String> {
+        if let Some(query) = self.queries.read().unwrap().get(hash) {
+            return Some(query.clone());
+        }
+        self.automatic.as_ref()?.lock().unwrap().get(hash).cloned()
+    }
+
+    // Find the query a request runs: a request naming a hash runs the query registered under it, or the
+    // query it also sent if that hashes the same, which automatic persisted queries then register. When
+    // persisted queries are required, queries sent in full must be registered too
+    fn resolve(&self, request: &mut Request) -> Result<(), AppError> {
+        let hash = match request.extensions.get("persistedQuery") {
+            Some(Value::Object(persisted)) => match persisted.get("sha256Hash") {
+                Some(Value::String(hash)) => Some(hash.clone()),
+                _ => return Err(AppError::Validation("persistedQuery needs a sha256Hash".to_string())),
+            },
+            _ => None,
+        };
+        match hash {
+            Some(hash) if request.query.is_empty() => {
+                request.query = self.get(&hash).ok_or(AppError::PersistedQueryNotFound)?;
+                Ok(())
+            }
+            Some(hash) if hash != persisted_query_hash(&request.query) => Err(AppError::Validation("The sha256Hash is not the hash of the query".to_string())),
+            _ if self.required && self.get(&persisted_query_hash(&request.query)).is_none() => {
+                Err(AppError::Forbidden("Only persisted queries are allowed".to_string()))
+            }
+            Some(hash) => {
+                if let Some(automatic) = &self.automatic {
+                    automatic.lock().unwrap().put(hash, request.query.clone());
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+// Implement the extension that swaps persisted query hashes for the queries registered under them and,
+// when persisted queries are required, refuses the rest before they are parsed
+impl ExtensionFactory for PersistedQueries {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(self.clone())
+    }
+}
+
+#[async_trait]
+impl Extension for PersistedQueries {
+    async fn prepare_request(&self, ctx: &ExtensionContext<'_>, mut request: Request, next: NextPrepareRequest<'_>) -> ServerResult<Request> {
+        self.resolve(&mut request).map_err(|error| error.extend().into_server_error(Pos::default()))?;
+        next.run(ctx, request).await
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build a request naming a persisted query by hash
+    fn by_hash(query: &str, hash: &str) -> Request {
+        let extension = serde_json::json!({ "sha256Hash": hash, "version": 1 });
+        let mut request = Request::new(query);
+        request.extensions.insert("persistedQuery".to_string(), Value::from_json(extension).unwrap());
+        request
+    }
+
+    // Define a test for which requests persisted queries let through, and which query they run
+    #[test]
+    fn test_resolve_persisted_queries() {
+        let (optional, required) = (PersistedQueries::new(false), PersistedQueries::new(true));
+        let query = "{ users { name } }";
+        let hash = optional.register(query).unwrap();
+        required.register(query).unwrap();
+        assert_eq!(hash, persisted_query_hash(query));
+        assert!(optional.register("{ users {").is_err());
+
+        // Hashes are swapped for their queries, and unknown hashes are reported so clients can send the query
+        let mut request = by_hash("", &hash);
+        optional.resolve(&mut request).unwrap();
+        assert_eq!(request.query, query);
+        assert!(matches!(optional.resolve(&mut by_hash("", "unknown")), Err(AppError::PersistedQueryNotFound)));
+        assert!(matches!(optional.resolve(&mut by_hash("{ posts { title } }", &hash)), Err(AppError::Validation(_))));
+
+        // Only required persisted queries refuse unregistered query text
+        assert!(optional.resolve(&mut Request::new("{ posts { title } }")).is_ok());
+        assert!(matches!(required.resolve(&mut Request::new("{ posts { title } }")), Err(AppError::Forbidden(_))));
+        assert!(required.resolve(&mut Request::new(query)).is_ok());
+        assert!(required.resolve(&mut by_hash("", &hash)).is_ok());
+    }
+
+    // Define a test that clients register queries by sending them with their hash, and that the least
+    // recently used are evicted
+    #[test]
+    fn test_automatic_persisted_queries() {
+        let persisted = PersistedQueries::new(false).with_automatic(NonZeroUsize::new(2).unwrap());
+        let queries = ["{ users { name } }", "{ posts { title } }", "{ me { name } }"];
+        let hashes = queries.map(persisted_query_hash);
+
+        // A hash is not found until the client sends it with its query
+        assert!(matches!(persisted.resolve(&mut by_hash("", &hashes[0])), Err(AppError::PersistedQueryNotFound)));
+        persisted.resolve(&mut by_hash(queries[0], &hashes[0])).unwrap();
+        let mut request = by_hash("", &hashes[0]);
+        persisted.resolve(&mut request).unwrap();
+        assert_eq!(request.query, queries[0]);
+
+        // Queries are not registered under hashes that are not theirs, or without a hash
+        assert!(persisted.resolve(&mut by_hash(queries[1], &hashes[0])).is_err());
+        persisted.resolve(&mut Request::new(queries[1])).unwrap();
+        assert!(persisted.get(&hashes[1]).is_none());
+
+        // Using the first query keeps it while the second is evicted for the third
+        persisted.resolve(&mut by_hash(queries[1], &hashes[1])).unwrap();
+        assert!(persisted.get(&hashes[0]).is_some());
+        persisted.resolve(&mut by_hash(queries[2], &hashes[2])).unwrap();
+        assert!(persisted.get(&hashes[1]).is_none());
+        assert_eq!(persisted.get(&hashes[0]).as_deref(), Some(queries[0]));
+        assert_eq!(persisted.get(&hashes[2]).as_deref(), Some(queries[2]));
+
+        // Without automatic persisted queries, queries sent with their hash are not registered
+        let optional = PersistedQueries::new(false);
+        optional.resolve(&mut by_hash(queries[0], &hashes[0])).unwrap();
+        assert!(optional.get(&hashes[0]).is_none());
+    }
+
+    // Define a test for registering the operations of a manifest
+    #[test]
+    fn test_load_manifest() {
+        let persisted = PersistedQueries::new(true);
+        let query = "query Users { users { name } }";
+        let manifest = serde_json::json!({
+            "format": "apollo-persisted-query-manifest",
+            "version": 1,
+            "operations": [
+                { "id": persisted_query_hash(query), "name": "Users", "type": "query", "body": query },
+                { "body": "{ posts { title } }" }
+            ]
+        });
+        assert_eq!(persisted.load_manifest(&manifest.to_string()), Ok(2));
+        assert_eq!(persisted.get(&persisted_query_hash(query)).as_deref(), Some(query));
+
+        // Operation IDs must match their bodies
+        let manifest = serde_json::json!({ "operations": [{ "id": "0123", "body": query }] });
+        assert!(persisted.load_manifest(&manifest.to_string()).is_err());
+    }
+}
@@ -0,0 +1,652 @@
+// Import necessary libraries and modules
+use std::collections::HashMap;
+use lru::LruCache;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use warp::http::header::{HeaderValue, RETRY_AFTER};
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::reject::Reject;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::auth::{auth_context, signed_auth_context, AuthContext, Authenticator};
+use crate::config::Settings;
+use crate::error::AppError;
+use crate::log_warn;
+use crate::model::UserRole;
+use crate::repository::SharedRepository;
+use crate::request_limits::error_response;
+
+// Define how many principals' buckets are kept; past that the least recently seen principal's bucket is
+// dropped, and it starts again with a full one
+const MAX_TRACKED_CLIENTS: NonZeroUsize = NonZeroUsize::new(100_000).unwrap();
+
+// Define how long a user's role is trusted once it has been read, so the role that decides their tier is
+// not read again for every request
+pub const DEFAULT_ROLE_TTL: Duration = Duration::from_secs(30);
+
+// Define the headers that tell clients how many requests their bucket holds and how many are left
+pub const LIMIT_HEADER: &str = "x-ratelimit-limit";
+pub const REMAINING_HEADER: &str = "x-ratelimit-remaining";
+
+// Define the token bucket every client IP gets: it holds up to `burst` requests and refills at
+// `per_minute` requests a minute. Requests from one of the trusted proxies are attributed to the address
+// it reports in X-Forwarded-For instead. Authenticated requests get a bucket per API key or user instead,
+// sized by the tier of the user's role; roles without a tier get the same rate as client IPs, and a tier
+// of None is unlimited
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimitConfig {
+    pub per_minute: u32,
+    pub burst: u32,
+    pub trusted_proxies: Vec<IpAddr>,
+    pub tiers: HashMap<UserRole, Option<u32>>,
+}
+
+impl RateLimitConfig {
+    // Read RATE_LIMIT_PER_MINUTE along with the optional RATE_LIMIT_BURST (default the per-minute rate),
+    // RATE_LIMIT_TRUSTED_PROXIES, a comma-separated list of IP addresses, and RATE_LIMIT_TIERS, a
+    // comma-separated list of role=rate entries such as "admin=unlimited,guest=60" (default
+    // "admin=unlimited"). Without a rate requests are not limited
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+        let Some(per_minute) = positive_var(settings, "RATE_LIMIT_PER_MINUTE")? else {
+            return Ok(None);
+        };
+        let burst = positive_var(settings, "RATE_LIMIT_BURST")?.unwrap_or(per_minute);
+        let trusted_proxies = trusted_proxies_from_settings(settings)?;
+        let tiers = parse_tiers(&settings.var("RATE_LIMIT_TIERS").unwrap_or_else(|| "admin=unlimited".to_string()))?;
+        Ok(Some(RateLimitConfig { per_minute, burst, trusted_proxies, tiers }))
+    }
+}
+
+// Read a positive number from a setting, if it is set
+fn positive_var(settings: &Settings, name: &str) -> Result<Option<u32>, String> {
+    match settings.var(name) {
+        Some(value) => match value.parse() {
+            Ok(number) if number > 0 => Ok(Some(number)),
+            _ => Err(format!("{} must be a positive number, got {:?}", name, value)),
+        },
+        None => Ok(None),
+    }
+}
+
+// Work out which client sent a request. While the hop that delivered it is a trusted proxy, the address
+// that proxy appended to X-Forwarded-For is believed instead; the entries before it could have been
+// written by anyone
+pub fn client_ip(trusted_proxies: &[IpAddr], peer: IpAddr, forwarded_for: Option<&str>) -> IpAddr {
+    let mut client = peer;
+    for hop in forwarded_for.into_iter().flat_map(|forwarded_for| forwarded_for.rsplit(',')) {
+        if !trusted_proxies.contains(&client) {
+            break;
+        }
+        match hop.trim().parse() {
+            Ok(ip) => client = ip,
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+// Parse a comma-separated list of proxy IP addresses, ignoring blank entries
+pub fn parse_trusted_proxies(value: &str) -> Result<Vec<IpAddr>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| entry.parse().map_err(|_| format!("{:?} is not an IP address", entry)))
+        .collect()
+}
+
+// Read RATE_LIMIT_TRUSTED_PROXIES, the proxies whose X-Forwarded-For is believed wherever the server
+// works out client addresses; without it none are trusted
+pub fn trusted_proxies_from_settings(settings: &Settings) -> Result<Vec<IpAddr>, String> {
+    match settings.var("RATE_LIMIT_TRUSTED_PROXIES") {
+        Some(value) => parse_trusted_proxies(&value),
+        None => Ok(Vec::new()),
+    }
+}
+
+// Parse a comma-separated list of role=rate tiers, where the rate is a positive number of requests a
+// minute or "unlimited", ignoring blank entries
+pub fn parse_tiers(value: &str) -> Result<HashMap<UserRole, Option<u32>>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (role, rate) = entry.split_once('=').ok_or_else(|| format!("{:?} is not a role=rate tier", entry))?;
+            let role = UserRole::parse(role.trim()).ok_or_else(|| format!("{:?} is not a role", role.trim()))?;
+            let rate = match rate.trim() {
+                "unlimited" => None,
+                rate => Some(rate.parse().ok().filter(|rate| *rate > 0).ok_or_else(|| format!("{:?} is not a positive rate or unlimited", rate))?),
+            };
+            Ok((role, rate))
+        })
+        .collect()
+}
+
+// Define who a bucket belongs to: the API key or user a request authenticated as, or else its client IP
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Principal {
+    Client(IpAddr),
+    User(String),
+    ApiKey(String),
+}
+
+// Define the size of a bucket and how quickly it refills
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+impl Rate {
+    fn per_second(self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+// Define how many requests a principal's bucket holds and how many it has left after this one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quota {
+    pub limit: u32,
+    pub remaining: u32,
+}
+
+// Define the level of one principal's bucket as of the last time it was checked, and the rate it
+// refills at
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    rate: Rate,
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        (self.tokens + elapsed * self.rate.per_second()).min(f64::from(self.rate.burst))
+    }
+}
+
+// Define a user's role as read from the repository, and when it was read
+#[derive(Clone, Copy)]
+struct CachedRole {
+    role: Option<UserRole>,
+    read_at: Instant,
+}
+
+// Define the limiter shared by every request; each principal's bucket lives in memory, so every instance
+// of the server limits on its own, and only the most recently seen principals are tracked. The roles
+// that decide users' tiers are read from the repository, and kept for `role_ttl`. The limits can be
+// changed while the server runs, and a limiter without them lets every request through
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: Arc<RwLock<Option<Arc<RateLimitConfig>>>>,
+    buckets: Arc<Mutex<LruCache<Principal, Bucket>>>,
+    roles: Arc<Mutex<LruCache<String, CachedRole>>>,
+    role_ttl: Duration,
+    repository: Option<SharedRepository>,
+}
+
+impl RateLimiter {
+    // Create a limiter with every bucket full
+    pub fn new(config: RateLimitConfig) -> Self {
+        let limiter = RateLimiter::disabled();
+        limiter.reconfigure(Some(config));
+        limiter
+    }
+
+    // Create a limiter that lets every request through until it is given limits
+    pub fn disabled() -> Self {
+        RateLimiter {
+            config: Arc::default(),
+            buckets: Arc::new(Mutex::new(LruCache::new(MAX_TRACKED_CLIENTS))),
+            roles: Arc::new(Mutex::new(LruCache::new(MAX_TRACKED_CLIENTS))),
+            role_ttl: DEFAULT_ROLE_TTL,
+            repository: None,
+        }
+    }
+
+    // Track the buckets of up to `capacity` principals instead of the default
+    pub fn with_capacity(mut self, capacity: NonZeroUsize) -> Self {
+        self.buckets = Arc::new(Mutex::new(LruCache::new(capacity)));
+        self
+    }
+
+    // Limit requests by the given settings from now on, or stop limiting them. Buckets keep their level
+    // and refill at their principal's new rate from the next request
+    pub fn reconfigure(&self, config: Option<RateLimitConfig>) {
+        *self.config.write().unwrap() = config.map(Arc::new);
+    }
+
+    // Return the current settings, if requests are limited
+    fn config(&self) -> Option<Arc<RateLimitConfig>> {
+        self.config.read().unwrap().clone()
+    }
+
+    // Look users' roles up in the given repository; without one every user gets the client IP rate
+    pub fn with_repository(mut self, repository: SharedRepository) -> Self {
+        self.repository = Some(repository);
+        self
+    }
+
+    // Read users' roles again once they are older than `role_ttl` instead
+    pub fn with_role_ttl(mut self, role_ttl: Duration) -> Self {
+        self.role_ttl = role_ttl;
+        self
+    }
+
+    // Work out which client sent a request, believing X-Forwarded-For from the trusted proxies
+    pub fn client_ip(&self, peer: IpAddr, forwarded_for: Option<&str>) -> IpAddr {
+        let trusted_proxies = self.config().map(|config| config.trusted_proxies.clone()).unwrap_or_default();
+        client_ip(&trusted_proxies, peer, forwarded_for)
+    }
+
+    // Return the rate of the given role's tier, or None if it is unlimited; anonymous requests get the
+    // client IP rate. Without limits every rate is unlimited
+    pub fn rate(&self, role: Option<UserRole>) -> Option<Rate> {
+        let config = self.config()?;
+        let client_rate = Rate { per_minute: config.per_minute, burst: config.burst };
+        match role.and_then(|role| config.tiers.get(&role)) {
+            Some(Some(per_minute)) => Some(Rate { per_minute: *per_minute, burst: *per_minute }),
+            Some(None) => None,
+            None => Some(client_rate),
+        }
+    }
+
+    // Work out whose bucket a request is counted against and at what rate. A user whose role cannot be
+    // read is limited like a client IP
+    pub async fn principal(&self, auth: &AuthContext, client: Option<IpAddr>) -> Option<(Principal, Option<Rate>)> {
+        self.config()?;
+        let Some(user_id) = auth.principal() else {
+            return client.map(|client| (Principal::Client(client), self.rate(None)));
+        };
+        let role = match &self.repository {
+            Some(repository) => self.role(repository, user_id).await,
+            None => None,
+        };
+        let principal = match &auth.api_key {
+            Some(api_key) => Principal::ApiKey(api_key.id.clone()),
+            None => Principal::User(user_id.to_string()),
+        };
+        Some((principal, self.rate(role)))
+    }
+
+    // Return a user's role as it was read from the repository within `role_ttl`, reading it again when it is
+    // older. A role that cannot be read is not kept, so it is read again on the next request
+    async fn role(&self, repository: &SharedRepository, user_id: &str) -> Option<UserRole> {
+        let now = Instant::now();
+        let cached = self.roles.lock().unwrap().get(user_id).copied();
+        if let Some(cached) = cached.filter(|cached| now.saturating_duration_since(cached.read_at) < self.role_ttl) {
+            return cached.role;
+        }
+        let role = match repository.get(user_id).await {
+            Ok(user) => user.map(|user| user.role),
+            Err(error) => {
+                log_warn!("Failed to look up the role of user {} for rate limiting: {}", user_id, error);
+                return None;
+            }
+        };
+        self.roles.lock().unwrap().put(user_id.to_string(), CachedRole { role, read_at: now });
+        role
+    }
+
+    // Take a token from the principal's bucket and return what is left of it, or return how long until a
+    // token is available
+    pub fn check(&self, principal: &Principal, rate: Rate, now: Instant) -> Result<Quota, Duration> {
+        self.take(principal, rate, 1, now)
+    }
+
+    // Take the given number of tokens from the principal's bucket at once and return what is left of it,
+    // or take none and return how long until that many are available
+    pub fn take(&self, principal: &Principal, rate: Rate, tokens: u32, now: Instant) -> Result<Quota, Duration> {
+        let tokens = f64::from(tokens);
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.get_or_insert_mut(principal.clone(), || Bucket { tokens: f64::from(rate.burst), updated_at: now, rate });
+        bucket.rate = rate;
+        bucket.tokens = bucket.refilled(now);
+        bucket.updated_at = now;
+        if bucket.tokens >= tokens {
+            bucket.tokens -= tokens;
+            Ok(Quota { limit: rate.burst, remaining: bucket.tokens.floor() as u32 })
+        } else {
+            Err(Duration::from_secs_f64((tokens - bucket.tokens) / rate.per_second()))
+        }
+    }
+}
+
+// Define the rejection for a request its principal's bucket cannot admit: one over the limit, with how
+// long the client should wait, or a batch with more operations than the bucket holds, which no wait
+// would let through
+#[derive(Debug)]
+pub enum RateLimited {
+    Exhausted { retry_after: Duration, limit: u32 },
+    BatchOverLimit { limit: u32 },
+}
+
+impl Reject for RateLimited {}
+
+impl RateLimited {
+    // Return the number of requests the principal's bucket holds
+    pub fn limit(&self) -> u32 {
+        match self {
+            RateLimited::Exhausted { limit, .. } | RateLimited::BatchOverLimit { limit } => *limit,
+        }
+    }
+
+    // Answer with 429, a Retry-After header in whole seconds, and the quota headers, or for a batch
+    // over the limit with 413 and a BATCH_OVER_RATE_LIMIT error naming it, since retrying cannot help
+    pub fn response(&self) -> Response {
+        match self {
+            RateLimited::Exhausted { retry_after, limit } => {
+                let seconds = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+                let reply = warp::reply::with_status("Too many requests".to_string(), StatusCode::TOO_MANY_REQUESTS);
+                let reply = warp::reply::with_header(reply, RETRY_AFTER, seconds.to_string());
+                with_quota(reply, Some(Quota { limit: *limit, remaining: 0 }))
+            }
+            RateLimited::BatchOverLimit { limit } => error_response(AppError::BatchOverRateLimit(*limit), StatusCode::PAYLOAD_TOO_LARGE),
+        }
+    }
+}
+
+// Define the peer address of a connection the server accepted itself rather than through warp::serve,
+// such as over native TLS; it is passed to the routes as a request extension
+#[derive(Clone, Copy, Debug)]
+pub struct PeerAddr(pub SocketAddr);
+
+// Build a filter extracting the address of the peer that sent a request, however its connection was
+// accepted
+pub fn peer_addr() -> impl Filter<Extract = (Option<SocketAddr>,), Error = std::convert::Infallible> + Clone {
+    warp::addr::remote().and(warp::ext::optional::<PeerAddr>()).map(|remote: Option<SocketAddr>, peer: Option<PeerAddr>| remote.or(peer.map(|peer| peer.0)))
+}
+
+// Build a filter extracting the X-Forwarded-For header, if it is set and is text
+pub fn forwarded_for() -> impl Filter<Extract = (Option<String>,), Error = std::convert::Infallible> + Clone {
+    warp::header::optional::<String>("x-forwarded-for").or(warp::any().map(|| None)).unify()
+}
+
+// Build a filter that authenticates a request and lets it through if its principal has a token left,
+// along with the quota it has left, and rejects it with RateLimited otherwise. Without a limiter, for
+// unlimited tiers, and for anonymous requests whose peer address is unknown, every request goes through
+// without a quota
+pub fn rate_limit(limiter: Option<RateLimiter>, authenticator: Authenticator) -> impl Filter<Extract = (AuthContext, Option<Quota>), Error = Rejection> + Clone {
+    peer_addr()
+        .and(forwarded_for())
+        .and(auth_context(authenticator))
+        .and_then(move |peer: Option<SocketAddr>, forwarded_for: Option<String>, auth: AuthContext| {
+            let limiter = limiter.clone();
+            async move {
+                let quota = admit(limiter.as_ref(), peer, forwarded_for.as_deref(), &auth).await.map_err(warp::reject::custom)?;
+                Ok::<_, Rejection>((auth, quota))
+            }
+        })
+        .untuple_one()
+}
+
+// Build the same filter for signed requests, which also hands on the body the signature was checked
+// against (see `signed_auth_context`)
+pub fn signed_rate_limit(limiter: Option<RateLimiter>, authenticator: Authenticator) -> impl Filter<Extract = (AuthContext, Option<Quota>, Bytes), Error = Rejection> + Clone {
+    peer_addr()
+        .and(forwarded_for())
+        .and(signed_auth_context(authenticator))
+        .and_then(move |peer: Option<SocketAddr>, forwarded_for: Option<String>, auth: AuthContext, body: Bytes| {
+            let limiter = limiter.clone();
+            async move {
+                let quota = admit(limiter.as_ref(), peer, forwarded_for.as_deref(), &auth).await.map_err(warp::reject::custom)?;
+                Ok::<_, Rejection>((auth, quota, body))
+            }
+        })
+        .untuple_one()
+}
+
+// Take a token from the bucket of a request's principal, returning the quota it has left, if it has one
+pub async fn admit(limiter: Option<&RateLimiter>, peer: Option<SocketAddr>, forwarded_for: Option<&str>, auth: &AuthContext) -> Result<Option<Quota>, RateLimited> {
+    take(limiter, peer, forwarded_for, auth, 1, 0).await
+}
+
+// Charge a batch request for the operations after its first, which `admit` already counted, so every
+// operation costs a token. A batch its principal's bucket cannot cover is refused whole, before any of
+// its operations run, and one with more operations than the bucket holds is refused as over the limit
+// without taking any. The quota of a single operation is returned as it was
+pub async fn admit_batch(
+    limiter: Option<&RateLimiter>,
+    peer: Option<SocketAddr>,
+    forwarded_for: Option<&str>,
+    auth: &AuthContext,
+    operations: usize,
+    quota: Option<Quota>,
+) -> Result<Option<Quota>, RateLimited> {
+    if operations <= 1 {
+        return Ok(quota);
+    }
+    take(limiter, peer, forwarded_for, auth, u32::try_from(operations).unwrap_or(u32::MAX), 1).await
+}
+
+// Take a token for each of a request's operations but the ones already charged from the bucket of its
+// principal, if it is limited
+async fn take(limiter: Option<&RateLimiter>, peer: Option<SocketAddr>, forwarded_for: Option<&str>, auth: &AuthContext, operations: u32, charged: u32) -> Result<Option<Quota>, RateLimited> {
+    let Some(limiter) = limiter else {
+        return Ok(None);
+    };
+    let client = peer.map(|peer| limiter.client_ip(peer.ip(), forwarded_for));
+    let Some((principal, Some(rate))) = limiter.principal(auth, client).await else {
+        return Ok(None);
+    };
+    if operations > rate.burst {
+        return Err(RateLimited::BatchOverLimit { limit: rate.burst });
+    }
+    match limiter.take(&principal, rate, operations - charged, Instant::now()) {
+        Ok(quota) => Ok(Some(quota)),
+        Err(retry_after) => Err(RateLimited::Exhausted { retry_after, limit: rate.burst }),
+    }
+}
+
+// Add the quota headers to a reply, if the request was counted against a quota
+pub fn with_quota(reply: impl Reply, quota: Option<Quota>) -> Response {
+    let mut response = reply.into_response();
+    if let Some(quota) = quota {
+        let headers = response.headers_mut();
+        headers.insert(LIMIT_HEADER, HeaderValue::from(quota.limit));
+        headers.insert(REMAINING_HEADER, HeaderValue::from(quota.remaining));
+    }
+    response
+}
+
+// Answer requests over their limit with 429, a Retry-After header in whole seconds, and the quota
+// headers. Other rejections pass through
+pub async fn recover_rate_limited(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    match rejection.find::<RateLimited>() {
+        Some(limited) => Ok(limited.response()),
+        None => Err(rejection),
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Viewer;
+    use crate::repository::InMemoryRepository;
+    use crate::schema::tests::{promote_pavel, CHARLIE, PAVEL};
+
+    // Build a limiter trusting the given comma-separated proxies
+    fn limiter(per_minute: u32, burst: u32, trusted_proxies: &str) -> RateLimiter {
+        let trusted_proxies = parse_trusted_proxies(trusted_proxies).unwrap();
+        RateLimiter::new(RateLimitConfig { per_minute, burst, trusted_proxies, tiers: parse_tiers("admin=unlimited").unwrap() })
+    }
+
+    // Define a test that a bucket allows its burst, then refills at the configured rate
+    #[test]
+    fn test_token_bucket() {
+        let limiter = limiter(60, 3, "");
+        let rate = limiter.rate(None).unwrap();
+        let (ada, grace) = (Principal::Client("192.0.2.1".parse().unwrap()), Principal::Client("192.0.2.2".parse().unwrap()));
+        let start = Instant::now();
+        for remaining in [2, 1, 0] {
+            assert_eq!(limiter.check(&ada, rate, start), Ok(Quota { limit: 3, remaining }));
+        }
+        assert_eq!(limiter.check(&ada, rate, start), Err(Duration::from_secs(1)));
+
+        // Other principals have buckets of their own, and a token comes back every second
+        assert!(limiter.check(&grace, rate, start).is_ok());
+        assert!(limiter.check(&ada, rate, start + Duration::from_millis(500)).is_err());
+        assert!(limiter.check(&ada, rate, start + Duration::from_secs(1)).is_ok());
+    }
+
+    // Define a test that past its capacity the limiter drops the bucket of the least recently seen
+    // principal, and keeps the others
+    #[test]
+    fn test_bucket_eviction() {
+        let limiter = limiter(60, 1, "").with_capacity(NonZeroUsize::new(2).unwrap());
+        let rate = limiter.rate(None).unwrap();
+        let [ada, grace, linus] = ["192.0.2.1", "192.0.2.2", "192.0.2.3"].map(|ip| Principal::Client(ip.parse().unwrap()));
+        let start = Instant::now();
+        assert!(limiter.check(&ada, rate, start).is_ok());
+        assert!(limiter.check(&grace, rate, start).is_ok());
+        assert!(limiter.check(&ada, rate, start).is_err());
+
+        // Ada was seen after Grace, so Linus's bucket takes Grace's place and Ada stays limited
+        assert!(limiter.check(&linus, rate, start).is_ok());
+        assert!(limiter.check(&ada, rate, start).is_err());
+        assert!(limiter.check(&grace, rate, start).is_ok());
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 2);
+    }
+
+    // Define a test that X-Forwarded-For is only believed as far as the hops are trusted proxies
+    #[test]
+    fn test_client_ip() {
+        let limiter = limiter(60, 60, "10.0.0.1, 10.0.0.2");
+        let ip = |value: &str| value.parse::<IpAddr>().unwrap();
+        assert_eq!(limiter.client_ip(ip("10.0.0.1"), Some("192.0.2.1")), ip("192.0.2.1"));
+        assert_eq!(limiter.client_ip(ip("10.0.0.1"), Some("203.0.113.9, 192.0.2.1, 10.0.0.2")), ip("192.0.2.1"));
+        assert_eq!(limiter.client_ip(ip("10.0.0.1"), None), ip("10.0.0.1"));
+        assert_eq!(limiter.client_ip(ip("10.0.0.1"), Some("not an address")), ip("10.0.0.1"));
+
+        // Untrusted peers cannot pick the address they are limited by
+        assert_eq!(limiter.client_ip(ip("192.0.2.7"), Some("192.0.2.1")), ip("192.0.2.7"));
+        assert!(parse_trusted_proxies("10.0.0.1, proxy").is_err());
+    }
+
+    // Define a test for parsing role tiers
+    #[test]
+    fn test_parse_tiers() {
+        let tiers = parse_tiers("admin=unlimited, guest=60,").unwrap();
+        assert_eq!(tiers, HashMap::from([(UserRole::Admin, None), (UserRole::Guest, Some(60))]));
+        assert!(parse_tiers("owner=60").is_err());
+        assert!(parse_tiers("guest=0").is_err());
+        assert!(parse_tiers("guest").is_err());
+    }
+
+    // Define a test that requests over the limit are answered with 429, Retry-After, and the quota headers
+    #[tokio::test]
+    async fn test_rate_limit_filter() {
+        let route = rate_limit(Some(limiter(1, 1, "")), Authenticator::default()).map(|_, quota| with_quota("ok", quota)).recover(recover_rate_limited);
+        let send = |address: &str| warp::test::request().remote_addr(address.parse().unwrap()).reply(&route);
+        let response = send("192.0.2.1:4000").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!((&response.headers()[LIMIT_HEADER], &response.headers()[REMAINING_HEADER]), (&HeaderValue::from(1), &HeaderValue::from(0)));
+        let response = send("192.0.2.1:4001").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "60");
+        assert_eq!(response.headers()[REMAINING_HEADER], "0");
+        assert_eq!(send("192.0.2.2:4000").await.status(), StatusCode::OK);
+
+        // Without a limiter nothing is limited
+        let route = rate_limit(None, Authenticator::default()).map(|_, quota| with_quota("ok", quota));
+        for _ in 0..3 {
+            let response = warp::test::request().remote_addr("192.0.2.1:4000".parse().unwrap()).reply(&route).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(!response.headers().contains_key(LIMIT_HEADER));
+        }
+    }
+
+    // Define a test that a batch costs a token for each of its operations, and is refused whole when its
+    // principal's bucket cannot cover them
+    #[tokio::test]
+    async fn test_admit_batch() {
+        let limiter = limiter(60, 5, "");
+        let (peer, auth) = (Some("192.0.2.1:4000".parse().unwrap()), AuthContext::default());
+        let quota = admit(Some(&limiter), peer, None, &auth).await.unwrap();
+        assert_eq!(quota, Some(Quota { limit: 5, remaining: 4 }));
+        assert_eq!(admit_batch(Some(&limiter), peer, None, &auth, 1, quota).await.unwrap(), quota);
+        assert_eq!(admit_batch(Some(&limiter), peer, None, &auth, 3, quota).await.unwrap(), Some(Quota { limit: 5, remaining: 2 }));
+
+        // The token left cannot cover the rest of a batch of four, which is refused without taking it
+        let quota = admit(Some(&limiter), peer, None, &auth).await.unwrap();
+        assert!(matches!(admit_batch(Some(&limiter), peer, None, &auth, 4, quota).await, Err(RateLimited::Exhausted { limit: 5, .. })));
+        assert_eq!(admit_batch(Some(&limiter), peer, None, &auth, 2, quota).await.unwrap(), Some(Quota { limit: 5, remaining: 0 }));
+
+        // A batch of one more operation than the bucket holds could never be admitted, so it is refused
+        // as over the limit, with 413 and no Retry-After, instead of being told to wait
+        let limiter = self::limiter(60, 5, "");
+        let quota = admit(Some(&limiter), peer, None, &auth).await.unwrap();
+        let refused = admit_batch(Some(&limiter), peer, None, &auth, 6, quota).await.unwrap_err();
+        assert!(matches!(refused, RateLimited::BatchOverLimit { limit: 5 }));
+        let response = refused.response();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(!response.headers().contains_key(RETRY_AFTER));
+        let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["errors"][0]["extensions"]["code"], "BATCH_OVER_RATE_LIMIT");
+        assert_eq!(body["errors"][0]["message"], "Batches may hold at most 5 operations under your rate limit");
+        assert_eq!(admit_batch(Some(&limiter), peer, None, &auth, 5, quota).await.unwrap(), Some(Quota { limit: 5, remaining: 0 }));
+
+        // Without a limiter nothing is charged
+        assert_eq!(admit_batch(None, peer, None, &auth, 4, None).await.unwrap(), None);
+    }
+
+    // Define a test that authenticated users are limited by their own bucket at their role's tier
+    #[tokio::test]
+    async fn test_principal_tiers() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        promote_pavel(&repository).await;
+        let config = RateLimitConfig { per_minute: 1, burst: 1, trusted_proxies: Vec::new(), tiers: parse_tiers("admin=unlimited,member=2").unwrap() };
+        let limiter = RateLimiter::new(config).with_repository(repository);
+        let tokens = HashMap::from([("pavel-token".to_string(), PAVEL.to_string()), ("charlie-token".to_string(), CHARLIE.to_string())]);
+        let route = rate_limit(Some(limiter), Authenticator::new(tokens)).map(|_, quota| with_quota("ok", quota)).recover(recover_rate_limited);
+        let send = |token: Option<&str>| {
+            let mut request = warp::test::request().remote_addr("192.0.2.1:4000".parse().unwrap());
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            request.reply(&route)
+        };
+
+        // Charlie is a member, whose tier allows two requests no matter which address they come from
+        for remaining in ["1", "0"] {
+            let response = send(Some("charlie-token")).await;
+            assert_eq!((&response.headers()[LIMIT_HEADER], &response.headers()[REMAINING_HEADER]), (&HeaderValue::from(2), &HeaderValue::from_static(remaining)));
+        }
+        assert_eq!(send(Some("charlie-token")).await.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // The address Charlie used still has its own bucket, and Pavel is an admin, who is never limited
+        assert_eq!(send(None).await.status(), StatusCode::OK);
+        assert_eq!(send(None).await.status(), StatusCode::TOO_MANY_REQUESTS);
+        for _ in 0..5 {
+            let response = send(Some("pavel-token")).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(!response.headers().contains_key(LIMIT_HEADER));
+        }
+    }
+
+    // Define a test that a user's role is read once and kept for the role TTL, so a change to it reaches
+    // the limiter only once the TTL has passed
+    #[tokio::test]
+    async fn test_role_cache() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        let config = RateLimitConfig { per_minute: 1, burst: 1, trusted_proxies: Vec::new(), tiers: parse_tiers("admin=unlimited,member=2").unwrap() };
+        let auth = AuthContext::from(Viewer::User(PAVEL.to_string()));
+        let cached = RateLimiter::new(config.clone()).with_repository(repository.clone());
+        let uncached = RateLimiter::new(config).with_repository(repository.clone()).with_role_ttl(Duration::ZERO);
+        let member = Some(Rate { per_minute: 2, burst: 2 });
+        assert_eq!(cached.principal(&auth, None).await.unwrap().1, member);
+        assert_eq!(uncached.principal(&auth, None).await.unwrap().1, member);
+
+        // Pavel is made an admin, which the limiter that read his role moments ago does not see yet
+        promote_pavel(&repository).await;
+        assert_eq!(cached.principal(&auth, None).await.unwrap().1, member);
+        assert_eq!(uncached.principal(&auth, None).await.unwrap().1, None);
+    }
+}
@@ -0,0 +1,195 @@
+// Import necessary libraries and modules
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use warp::filters::BoxedFilter;
+use warp::hyper::service::Service;
+use warp::hyper::{Body, Request};
+use warp::reply::Response;
+use warp::Reply;
+
+use crate::config::Settings;
+use crate::cors::{with_cors, CorsConfig};
+use crate::logging::{set_log_level, LogLevel};
+use crate::rate_limit::{RateLimitConfig, RateLimiter};
+use crate::{log_info, log_warn};
+
+// Define the routes the server answers with
+pub type Routes = BoxedFilter<(Box<dyn Reply>,)>;
+
+// Define the routes every connection is served with, wrapped with the CORS settings, which a reload swaps
+// for routes built from new settings; requests already being answered finish with the routes they
+// started with. With the axum feature, an axum router can answer requests in front of them
+#[derive(Clone)]
+pub struct SharedRoutes {
+    current: Arc<RwLock<(Routes, Option<CorsConfig>)>>,
+    // Routers are not Sync, so each request clones the router from behind a lock
+    #[cfg(feature = "axum")]
+    router: Option<Arc<std::sync::Mutex<axum::Router>>>,
+}
+
+impl SharedRoutes {
+    // Serve the given routes with the CORS settings until they are replaced
+    pub fn new(routes: Routes, cors: Option<CorsConfig>) -> Self {
+        let current = (with_cors(routes, cors.as_ref()), cors);
+        SharedRoutes {
+            current: Arc::new(RwLock::new(current)),
+            #[cfg(feature = "axum")]
+            router: None,
+        }
+    }
+
+    // Answer requests with an axum router instead, which hands the ones it does not answer itself to the
+    // routes; the router keeps its own handle on them, without the router
+    #[cfg(feature = "axum")]
+    pub fn with_router(mut self, router: axum::Router) -> Self {
+        self.router = Some(Arc::new(std::sync::Mutex::new(router)));
+        self
+    }
+
+    // Answer the requests that arrive from now on with other routes and CORS settings
+    pub fn replace(&self, routes: Routes, cors: Option<CorsConfig>) {
+        *self.current.write().unwrap() = (with_cors(routes, cors.as_ref()), cors);
+    }
+
+    // Return the CORS settings the routes are wrapped with
+    pub fn cors(&self) -> Option<CorsConfig> {
+        self.current.read().unwrap().1.clone()
+    }
+
+    // Answer a request with the router, if there is one, or with the routes current when it arrived. The
+    // request carries the CORS settings, if there are any, for routes that check origins themselves
+    pub async fn answer(&self, mut request: Request<Body>) -> Result<Response, Infallible> {
+        if let Some(cors) = self.cors() {
+            request.extensions_mut().insert(cors);
+        }
+        #[cfg(feature = "axum")]
+        if let Some(router) = &self.router {
+            use warp::hyper::body::HttpBody;
+
+            let mut router = router.lock().unwrap().clone();
+            let (parts, body) = router.call(request).await?.into_parts();
+            // Axum answers with a body type of its own, which is streamed into a hyper body
+            let chunks = futures::stream::unfold(body, |mut body| async move { body.data().await.map(|chunk| (chunk, body)) });
+            return Ok(Response::from_parts(parts, Body::wrap_stream(chunks)));
+        }
+        let routes = self.current.read().unwrap().0.clone();
+        warp::service(routes).call(request).await
+    }
+}
+
+// Define what a configuration reload changes: the log level, the rate limits, and the CORS settings the
+// routes are wrapped with. Everything else is read once at startup
+pub struct Reloader {
+    config: Option<PathBuf>,
+    rate_limiter: RateLimiter,
+    routes: Routes,
+    shared: SharedRoutes,
+}
+
+impl Reloader {
+    // Create a reloader for a server started with the given config file, if one was named on the command
+    // line, and which serves `shared`, built from `routes`
+    pub fn new(config: Option<PathBuf>, rate_limiter: RateLimiter, routes: Routes, shared: SharedRoutes) -> Self {
+        Reloader { config, rate_limiter, routes, shared }
+    }
+
+    // Read the config file and the environment again and apply the settings that can change
+    pub fn reload(&self) -> Result<(), String> {
+        self.apply(&Settings::load(self.config.as_deref())?)
+    }
+
+    // Apply the log level, rate limit, and CORS settings. All of them are read before any is applied, so
+    // an invalid setting leaves the server as it was
+    pub fn apply(&self, settings: &Settings) -> Result<(), String> {
+        let level = LogLevel::from_settings(settings)?;
+        let rate_limit = RateLimitConfig::from_settings(settings).map_err(|error| format!("Invalid rate limit settings: {}", error))?;
+        let cors = CorsConfig::from_settings(settings).map_err(|error| format!("Invalid CORS settings: {}", error))?;
+        set_log_level(level);
+        self.rate_limiter.reconfigure(rate_limit);
+        self.shared.replace(self.routes.clone(), cors);
+        Ok(())
+    }
+
+    // Reload the settings whenever the process gets SIGHUP, keeping the current ones when the new ones
+    // cannot be read
+    #[cfg(unix)]
+    pub fn reload_on_hangup(self) -> std::io::Result<tokio::task::JoinHandle<()>> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = signal(SignalKind::hangup())?;
+        Ok(tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                match self.reload() {
+                    Ok(()) => log_info!("Reloaded the configuration"),
+                    Err(error) => log_warn!("Failed to reload the configuration, keeping the current settings: {}", error),
+                }
+            }
+        }))
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AuthContext;
+    use crate::rate_limit::Principal;
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    // Build settings from name and value pairs
+    fn settings(vars: &[(&str, &str)]) -> Settings {
+        Settings::from_vars(vars.iter().copied()).unwrap()
+    }
+
+    // Send a request with an Origin header to the shared routes and return its status
+    async fn status(shared: &SharedRoutes, origin: &str) -> StatusCode {
+        let request = Request::post("/graphql").header("origin", origin).body(Body::empty()).unwrap();
+        shared.answer(request).await.unwrap().status()
+    }
+
+    // Define a test that requests are answered by the routes current when they arrive
+    #[tokio::test]
+    async fn test_shared_routes() {
+        let reply = |text: &'static str| warp::any().map(move || Box::new(text) as Box<dyn Reply>).boxed();
+        let shared = SharedRoutes::new(reply("first"), None);
+        let body = |shared: SharedRoutes| async move {
+            let response = shared.answer(Request::get("/").body(Body::empty()).unwrap()).await.unwrap();
+            warp::hyper::body::to_bytes(response.into_body()).await.unwrap()
+        };
+        assert_eq!(body(shared.clone()).await, "first");
+        shared.replace(reply("second"), None);
+        assert_eq!(body(shared.clone()).await, "second");
+    }
+
+    // Define a test that applying settings changes the rate limits and CORS origins, and that invalid
+    // settings change nothing
+    #[tokio::test]
+    async fn test_apply_settings() {
+        let routes = warp::path("graphql").map(|| Box::new("ok") as Box<dyn Reply>).boxed();
+        let shared = SharedRoutes::new(routes.clone(), None);
+        let limiter = RateLimiter::disabled();
+        let reloader = Reloader::new(None, limiter.clone(), routes, shared.clone());
+        let client = "203.0.113.7".parse().unwrap();
+        assert!(limiter.principal(&AuthContext::default(), Some(client)).await.is_none());
+
+        reloader.apply(&settings(&[("RATE_LIMIT_PER_MINUTE", "30"), ("CORS_ALLOWED_ORIGINS", "https://app.example.com")])).unwrap();
+        let (principal, rate) = limiter.principal(&AuthContext::default(), Some(client)).await.unwrap();
+        assert_eq!(principal, Principal::Client(client));
+        assert_eq!(rate.unwrap().per_minute, 30);
+        assert_eq!(status(&shared, "https://app.example.com").await, StatusCode::OK);
+        assert_eq!(status(&shared, "https://evil.example.com").await, StatusCode::FORBIDDEN);
+        assert!(shared.cors().unwrap().allows_origin("https://app.example.com"));
+
+        let error = reloader.apply(&settings(&[("RATE_LIMIT_PER_MINUTE", "60"), ("CORS_ALLOWED_ORIGINS", "evil.example.com")])).unwrap_err();
+        assert!(error.starts_with("Invalid CORS settings"), "{}", error);
+        assert_eq!(limiter.rate(None).unwrap().per_minute, 30);
+        assert_eq!(status(&shared, "https://app.example.com").await, StatusCode::OK);
+
+        reloader.apply(&settings(&[("CORS_ALLOWED_ORIGINS", "https://evil.example.com")])).unwrap();
+        assert!(limiter.rate(None).is_none());
+        assert_eq!(status(&shared, "https://evil.example.com").await, StatusCode::OK);
+        assert_eq!(status(&shared, "https://app.example.com").await, StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,646 @@
+// Import necessary libraries and modules
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, SubsecRound, Utc};
+use aws_sdk_dynamodb::client::Waiters;
+use aws_sdk_dynamodb::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_dynamodb::types::{
+    AttributeDefinition, AttributeValue, BillingMode, KeySchemaElement, KeyType, KeysAndAttributes, ReturnValue,
+    ScalarAttributeType,
+};
+use aws_sdk_dynamodb::Client;
+use std::collections::{BTreeSet, HashMap};
+
+use super::{DatabaseConfig, RepositoryError, RepositoryResult, UserRepository};
+use crate::model::{legacy_user_id, new_user_id, parse_user_id, stored_metadata, Address, NewUser, User, UserRole, UserUpdate};
+
+// Define the table used when DATABASE_URL does not name one
+const DEFAULT_TABLE: &str = "app";
+
+// Define the most keys DynamoDB accepts in one BatchGetItem request
+const BATCH_GET_LIMIT: usize = 100;
+
+// Define the sort key shared by every user item and the key of the counter item that handed out numeric IDs
+const USER_SORT_KEY: &str = "USER";
+const COUNTER_KEY: &str = "COUNTER#users";
+
+// Define a DynamoRepository that stores users in a single table keyed by `pk`/`sk`: users live under `USER#<id>`
+#[derive(Clone)]
+pub struct DynamoRepository {
+    client: Client,
+    table: String,
+}
+
+// Build the partition key of a user item
+fn user_key(id: &str) -> String {
+    format!("USER#{}", id)
+}
+
+// Build the primary key attributes of an item
+fn item_key(pk: String) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        ("pk".to_string(), AttributeValue::S(pk)),
+        ("sk".to_string(), AttributeValue::S(USER_SORT_KEY.to_string())),
+    ])
+}
+
+// Read a string attribute from an item
+fn string_attribute(item: &HashMap<String, AttributeValue>, name: &str) -> RepositoryResult<String> {
+    match item.get(name) {
+        Some(AttributeValue::S(value)) => Ok(value.clone()),
+        _ => Err(RepositoryError::Backend(format!("item is missing string attribute {:?}", name))),
+    }
+}
+
+// Read an optional RFC 3339 timestamp attribute from an item
+fn timestamp_attribute(item: &HashMap<String, AttributeValue>, name: &str) -> RepositoryResult<Option<DateTime<Utc>>> {
+    if !item.contains_key(name) {
+        return Ok(None);
+    }
+    let value = string_attribute(item, name)?;
+    let timestamp = DateTime::parse_from_rfc3339(&value)
+        .map_err(|error| RepositoryError::Backend(format!("invalid {} {:?}: {}", name, value, error)))?;
+    Ok(Some(timestamp.with_timezone(&Utc)))
+}
+
+// Format a timestamp the way it is stored in items
+fn timestamp_value(timestamp: DateTime<Utc>) -> AttributeValue {
+    AttributeValue::S(timestamp.to_rfc3339_opts(SecondsFormat::Micros, true))
+}
+
+// Convert a stored item back into a user; soft-deleted items carry an RFC 3339 `deleted_at`
+fn user_from_item(item: &HashMap<String, AttributeValue>) -> RepositoryResult<User> {
+    let deleted_at = timestamp_attribute(item, "deleted_at")?;
+    let created_at = timestamp_attribute(item, "created_at")?.unwrap_or_default();
+    let version = match item.get("version") {
+        Some(AttributeValue::N(version)) => version
+            .parse()
+            .map_err(|_| RepositoryError::Backend(format!("invalid version {:?}", version)))?,
+        _ => return Err(RepositoryError::Backend("item is missing number attribute \"version\"".to_string())),
+    };
+    // Items stored before roles existed are members
+    let role = match item.get("role") {
+        Some(_) => {
+            let role = string_attribute(item, "role")?;
+            UserRole::parse(&role).ok_or_else(|| RepositoryError::Backend(format!("unknown user role {:?}", role)))?
+        }
+        None => UserRole::Member,
+    };
+    // Metadata is stored as JSON text
+    let metadata = match item.get("metadata") {
+        Some(_) => {
+            let metadata = string_attribute(item, "metadata")?;
+            serde_json::from_str(&metadata).map_err(|error| RepositoryError::Backend(format!("invalid metadata: {}", error)))?
+        }
+        None => None,
+    };
+    // Addresses are stored as JSON text too
+    let address = match item.get("address") {
+        Some(_) => {
+            let address = string_attribute(item, "address")?;
+            Some(serde_json::from_str(&address).map_err(|error| RepositoryError::Backend(format!("invalid address: {}", error)))?)
+        }
+        None => None,
+    };
+    // Items stored before verification existed count as verified
+    let email_verified = match item.get("email_verified") {
+        Some(AttributeValue::Bool(email_verified)) => *email_verified,
+        Some(_) => return Err(RepositoryError::Backend("attribute \"email_verified\" is not a boolean".to_string())),
+        None => true,
+    };
+    Ok(User {
+        id: string_attribute(item, "id")?,
+        name: string_attribute(item, "name")?,
+        email: string_attribute(item, "email")?,
+        version,
+        created_at,
+        // Items stored before update times were recorded count as updated when they were created
+        updated_at: timestamp_attribute(item, "updated_at")?.unwrap_or(created_at),
+        deleted_at,
+        avatar_key: item.contains_key("avatar_key").then(|| string_attribute(item, "avatar_key")).transpose()?,
+        role,
+        metadata: stored_metadata(metadata),
+        phone: item.contains_key("phone").then(|| string_attribute(item, "phone")).transpose()?,
+        address,
+        email_verified,
+    })
+}
+
+// Encode an address as the JSON text stored in its attribute
+fn address_text(address: &Address) -> RepositoryResult<String> {
+    serde_json::to_string(address).map_err(|error| RepositoryError::Backend(error.to_string()))
+}
+
+// Report whether a request was rejected by its condition expression
+fn is_condition_failure<E: ProvideErrorMetadata, R>(error: &SdkError<E, R>) -> bool {
+    error.code() == Some("ConditionalCheckFailedException")
+}
+
+impl DynamoRepository {
+    // Load the AWS configuration from the standard environment and make sure the table exists.
+    // The table is named by `dynamodb://<table>`; region, credentials and endpoint come from
+    // AWS_REGION, AWS_ACCESS_KEY_ID/AWS_SECRET_ACCESS_KEY, AWS_PROFILE and AWS_ENDPOINT_URL.
+    pub async fn connect(config: &DatabaseConfig) -> RepositoryResult<Self> {
+        let table = config
+            .url
+            .strip_prefix("dynamodb://")
+            .ok_or_else(|| RepositoryError::Config(format!("expected a dynamodb:// URL, got {:?}", config.url)))?
+            .trim_end_matches('/');
+        let table = if table.is_empty() { DEFAULT_TABLE } else { table };
+
+        let aws_config = aws_config::load_from_env().await;
+        let repository = DynamoRepository {
+            client: Client::new(&aws_config),
+            table: table.to_string(),
+        };
+        repository.ensure_table().await?;
+        repository.convert_legacy_ids().await?;
+        Ok(repository)
+    }
+
+    // Create the table on demand so local development against DynamoDB Local needs no setup
+    async fn ensure_table(&self) -> RepositoryResult<()> {
+        match self.client.describe_table().table_name(&self.table).send().await {
+            Ok(_) => return Ok(()),
+            Err(error) if error.code() == Some("ResourceNotFoundException") => {}
+            Err(error) => return Err(error.into()),
+        }
+
+        let mut request = self
+            .client
+            .create_table()
+            .table_name(&self.table)
+            .billing_mode(BillingMode::PayPerRequest);
+        for (name, key_type) in [("pk", KeyType::Hash), ("sk", KeyType::Range)] {
+            request = request
+                .attribute_definitions(
+                    AttributeDefinition::builder()
+                        .attribute_name(name)
+                        .attribute_type(ScalarAttributeType::S)
+                        .build()?,
+                )
+                .key_schema(KeySchemaElement::builder().attribute_name(name).key_type(key_type).build()?);
+        }
+        match request.send().await {
+            // Another instance may have created the table in the meantime
+            Err(error) if error.code() != Some("ResourceInUseException") => return Err(error.into()),
+            _ => {}
+        }
+
+        self.client
+            .wait_until_table_exists()
+            .table_name(&self.table)
+            .wait(std::time::Duration::from_secs(60))
+            .await
+            .map_err(|error| RepositoryError::Backend(format!("table {} did not become active: {}", self.table, error)))?;
+        Ok(())
+    }
+
+    // Move users stored under numeric IDs to the UUIDs those IDs map to, the same conversion the SQL
+    // migrations apply, and drop the counter that handed them out. Each move puts the new item before
+    // deleting the old one, so an interrupted run is finished by the next
+    async fn convert_legacy_ids(&self) -> RepositoryResult<()> {
+        let mut start_key = None;
+        loop {
+            let output = self
+                .client
+                .scan()
+                .table_name(&self.table)
+                .filter_expression("begins_with(pk, :prefix)")
+                .expression_attribute_values(":prefix", AttributeValue::S(user_key("")))
+                .consistent_read(true)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+            for mut item in output.items.unwrap_or_default() {
+                let id = string_attribute(&item, "id")?;
+                let Ok(number) = id.parse::<u64>() else {
+                    continue;
+                };
+                let uuid = legacy_user_id(number).to_string();
+                item.insert("pk".to_string(), AttributeValue::S(user_key(&uuid)));
+                item.insert("id".to_string(), AttributeValue::S(uuid));
+                let result = self
+                    .client
+                    .put_item()
+                    .table_name(&self.table)
+                    .set_item(Some(item))
+                    .condition_expression("attribute_not_exists(pk)")
+                    .send()
+                    .await;
+                match result {
+                    Err(error) if !is_condition_failure(&error) => return Err(error.into()),
+                    _ => {}
+                }
+                self.client.delete_item().table_name(&self.table).set_key(Some(item_key(user_key(&id)))).send().await?;
+            }
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                break;
+            }
+        }
+        self.client.delete_item().table_name(&self.table).set_key(Some(item_key(COUNTER_KEY.to_string()))).send().await?;
+        Ok(())
+    }
+
+    // Scan every user item matching a filter expression over `:prefix`
+    async fn list_users(&self, filter: &str) -> RepositoryResult<Vec<User>> {
+        let mut users = Vec::new();
+        let mut start_key = None;
+        loop {
+            let output = self
+                .client
+                .scan()
+                .table_name(&self.table)
+                .filter_expression(filter)
+                .expression_attribute_values(":prefix", AttributeValue::S(user_key("")))
+                .consistent_read(true)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+            for item in output.items.unwrap_or_default() {
+                users.push(user_from_item(&item)?);
+            }
+            start_key = output.last_evaluated_key;
+            if start_key.is_none() {
+                break;
+            }
+        }
+
+        // Scans come back in hash order; UUIDv7 IDs sort in creation order like the other backends list them
+        users.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(users)
+    }
+
+    // Stamp or clear `deleted_at` on a stored user; stamping skips users that are already deleted
+    async fn set_deleted_at(&self, id: &str, deleted_at: Option<DateTime<Utc>>) -> RepositoryResult<Option<User>> {
+        let request = self
+            .client
+            .update_item()
+            .table_name(&self.table)
+            .set_key(Some(item_key(user_key(id))))
+            .return_values(ReturnValue::AllNew);
+        let request = match deleted_at {
+            Some(deleted_at) => request
+                .update_expression("SET deleted_at = :deleted_at")
+                .condition_expression("attribute_exists(pk) AND attribute_not_exists(deleted_at)")
+                .expression_attribute_values(":deleted_at", timestamp_value(deleted_at)),
+            None => request.update_expression("REMOVE deleted_at").condition_expression("attribute_exists(pk)"),
+        };
+        match request.send().await {
+            Ok(output) => output.attributes.as_ref().map(user_from_item).transpose(),
+            Err(error) if is_condition_failure(&error) => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+// Implement the repository operations as DynamoDB item requests
+#[async_trait]
+impl UserRepository for DynamoRepository {
+    async fn get(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let user = self.get_including_deleted(id).await?;
+        Ok(user.filter(|user| user.deleted_at.is_none()))
+    }
+
+    async fn get_including_deleted(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table)
+            .set_key(Some(item_key(user_key(id))))
+            .consistent_read(true)
+            .send()
+            .await?;
+        output.item.as_ref().map(user_from_item).transpose()
+    }
+
+    async fn get_many(&self, ids: &[String]) -> RepositoryResult<HashMap<String, User>> {
+        // BatchGetItem rejects requests that name the same key twice
+        let ids: Vec<&String> = ids.iter().collect::<BTreeSet<_>>().into_iter().collect();
+        let mut users = HashMap::new();
+        for chunk in ids.chunks(BATCH_GET_LIMIT) {
+            let mut keys = KeysAndAttributes::builder().consistent_read(true);
+            for id in chunk {
+                keys = keys.keys(item_key(user_key(id)));
+            }
+            let mut pending = Some(HashMap::from([(self.table.clone(), keys.build()?)]));
+
+            // DynamoDB may leave part of a batch unprocessed under load, so resend it until empty
+            while let Some(request_items) = pending.take().filter(|items| !items.is_empty()) {
+                let output = self.client.batch_get_item().set_request_items(Some(request_items)).send().await?;
+                for item in output.responses.unwrap_or_default().remove(&self.table).unwrap_or_default() {
+                    let user = user_from_item(&item)?;
+                    if user.deleted_at.is_none() {
+                        users.insert(user.id.clone(), user);
+                    }
+                }
+                pending = output.unprocessed_keys;
+            }
+        }
+        Ok(users)
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<User>> {
+        self.list_users("begins_with(pk, :prefix) AND attribute_not_exists(deleted_at)").await
+    }
+
+    async fn list_including_deleted(&self) -> RepositoryResult<Vec<User>> {
+        self.list_users("begins_with(pk, :prefix)").await
+    }
+
+    async fn create(&self, new_user: NewUser) -> RepositoryResult<User> {
+        let id = match new_user.id {
+            Some(id) => parse_user_id(&id).ok_or(RepositoryError::InvalidId(id))?.to_string(),
+            None => new_user_id().to_string(),
+        };
+        let now = Utc::now().trunc_subsecs(6);
+        let user = User {
+            id,
+            name: new_user.name,
+            email: new_user.email,
+            version: 1,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+            avatar_key: None,
+            role: new_user.role,
+            metadata: None,
+            phone: new_user.phone,
+            address: new_user.address,
+            email_verified: new_user.email_verified,
+        };
+
+        // Refuse to overwrite an existing user with the same ID
+        let mut item = item_key(user_key(&user.id));
+        item.insert("id".to_string(), AttributeValue::S(user.id.clone()));
+        item.insert("name".to_string(), AttributeValue::S(user.name.clone()));
+        item.insert("email".to_string(), AttributeValue::S(user.email.clone()));
+        item.insert("version".to_string(), AttributeValue::N(user.version.to_string()));
+        item.insert("created_at".to_string(), timestamp_value(user.created_at));
+        item.insert("updated_at".to_string(), timestamp_value(user.updated_at));
+        item.insert("role".to_string(), AttributeValue::S(user.role.as_str().to_string()));
+        item.insert("email_verified".to_string(), AttributeValue::Bool(user.email_verified));
+        if let Some(phone) = &user.phone {
+            item.insert("phone".to_string(), AttributeValue::S(phone.clone()));
+        }
+        if let Some(address) = &user.address {
+            item.insert("address".to_string(), AttributeValue::S(address_text(address)?));
+        }
+        let result = self
+            .client
+            .put_item()
+            .table_name(&self.table)
+            .set_item(Some(item))
+            .condition_expression("attribute_not_exists(pk)")
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(user),
+            Err(error) if is_condition_failure(&error) => {
+                Err(RepositoryError::Conflict(format!("User {} already exists", user.id)))
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn update(&self, id: &str, update: UserUpdate) -> RepositoryResult<Option<User>> {
+        let mut assignments = vec!["version = version + :one".to_string(), "updated_at = :updated_at".to_string()];
+        let mut request = self
+            .client
+            .update_item()
+            .table_name(&self.table)
+            .set_key(Some(item_key(user_key(id))))
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":updated_at", timestamp_value(Utc::now()));
+        let role = update.role.map(|role| role.as_str().to_string());
+        let metadata = update.metadata.map(|metadata| metadata.to_string());
+        let fields = [("name", update.name), ("email", update.email), ("avatar_key", update.avatar_key), ("role", role), ("metadata", metadata)];
+        for (field, value) in fields {
+            if let Some(value) = value {
+                assignments.push(format!("#{field} = :{field}"));
+                request = request
+                    .expression_attribute_names(format!("#{field}"), field)
+                    .expression_attribute_values(format!(":{field}"), AttributeValue::S(value));
+            }
+        }
+        if let Some(email_verified) = update.email_verified {
+            assignments.push("#email_verified = :email_verified".to_string());
+            request = request
+                .expression_attribute_names("#email_verified", "email_verified")
+                .expression_attribute_values(":email_verified", AttributeValue::Bool(email_verified));
+        }
+        // A cleared phone number or address is removed from the item rather than stored empty
+        let mut removals = Vec::new();
+        match update.phone {
+            Some(Some(phone)) => {
+                assignments.push("#phone = :phone".to_string());
+                request = request.expression_attribute_names("#phone", "phone").expression_attribute_values(":phone", AttributeValue::S(phone));
+            }
+            Some(None) => {
+                removals.push("#phone");
+                request = request.expression_attribute_names("#phone", "phone");
+            }
+            None => {}
+        }
+        match update.address {
+            Some(Some(address)) => {
+                assignments.push("#address = :address".to_string());
+                request = request
+                    .expression_attribute_names("#address", "address")
+                    .expression_attribute_values(":address", AttributeValue::S(address_text(&address)?));
+            }
+            Some(None) => {
+                removals.push("#address");
+                request = request.expression_attribute_names("#address", "address");
+            }
+            None => {}
+        }
+
+        // Only update a live user, and only at the expected version when one is given
+        let mut condition = "attribute_exists(pk) AND attribute_not_exists(deleted_at)".to_string();
+        if let Some(expected) = update.expected_version {
+            condition.push_str(" AND version = :expected");
+            request = request.expression_attribute_values(":expected", AttributeValue::N(expected.to_string()));
+        }
+        let mut expression = format!("SET {}", assignments.join(", "));
+        if !removals.is_empty() {
+            expression.push_str(&format!(" REMOVE {}", removals.join(", ")));
+        }
+        let result = request
+            .update_expression(expression)
+            .condition_expression(condition)
+            .return_values(ReturnValue::AllNew)
+            .send()
+            .await;
+        match result {
+            Ok(output) => output.attributes.as_ref().map(user_from_item).transpose(),
+            Err(error) if !is_condition_failure(&error) => Err(error.into()),
+            // The condition failed, so the user is either missing or has moved past the expected version
+            Err(_) => match (update.expected_version, self.get(id).await?) {
+                (Some(expected), Some(user)) => {
+                    Err(RepositoryError::VersionConflict { id: user.id, expected, actual: user.version })
+                }
+                _ => Ok(None),
+            },
+        }
+    }
+
+    async fn delete(&self, id: &str) -> RepositoryResult<Option<User>> {
+        self.set_deleted_at(id, Some(Utc::now())).await
+    }
+
+    async fn restore(&self, id: &str) -> RepositoryResult<Option<User>> {
+        self.set_deleted_at(id, None).await
+    }
+
+    async fn purge(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let output = self
+            .client
+            .delete_item()
+            .table_name(&self.table)
+            .set_key(Some(item_key(user_key(id))))
+            .return_values(ReturnValue::AllOld)
+            .send()
+            .await?;
+        output.attributes.as_ref().map(user_from_item).transpose()
+    }
+
+    async fn ping(&self) -> RepositoryResult<()> {
+        self.client.describe_table().table_name(&self.table).send().await?;
+        Ok(())
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Settings;
+
+    // Define a test for item conversion without a live table
+    #[test]
+    fn test_user_from_item() {
+        let mut item = item_key(user_key("7"));
+        item.insert("id".to_string(), AttributeValue::S("7".to_string()));
+        item.insert("name".to_string(), AttributeValue::S("Ada".to_string()));
+        item.insert("email".to_string(), AttributeValue::S("ada@example.com".to_string()));
+        item.insert("version".to_string(), AttributeValue::N("3".to_string()));
+        let user = user_from_item(&item).unwrap();
+        let expected = User {
+            id: "7".to_string(),
+            name: "Ada".to_string(),
+            email: "ada@example.com".to_string(),
+            version: 3,
+            created_at: Default::default(),
+            updated_at: Default::default(),
+            deleted_at: None,
+            avatar_key: None,
+            role: UserRole::Member,
+            metadata: None,
+            phone: None,
+            address: None,
+            email_verified: true,
+        };
+        assert_eq!(user, expected);
+
+        // Roles round-trip, and unknown ones are rejected
+        item.insert("role".to_string(), AttributeValue::S("admin".to_string()));
+        assert_eq!(user_from_item(&item).unwrap().role, UserRole::Admin);
+        item.insert("role".to_string(), AttributeValue::S("owner".to_string()));
+        assert!(matches!(user_from_item(&item), Err(RepositoryError::Backend(_))));
+        item.remove("role");
+
+        // Metadata is stored as JSON text, and text that is not JSON is rejected
+        item.insert("metadata".to_string(), AttributeValue::S(r#"{"team":"core"}"#.to_string()));
+        assert_eq!(user_from_item(&item).unwrap().metadata, Some(serde_json::json!({ "team": "core" })));
+        item.insert("metadata".to_string(), AttributeValue::S("null".to_string()));
+        assert_eq!(user_from_item(&item).unwrap().metadata, None);
+        item.insert("metadata".to_string(), AttributeValue::S("{".to_string()));
+        assert!(matches!(user_from_item(&item), Err(RepositoryError::Backend(_))));
+        item.remove("metadata");
+        item.insert("phone".to_string(), AttributeValue::S("+14155550123".to_string()));
+        assert_eq!(user_from_item(&item).unwrap().phone.as_deref(), Some("+14155550123"));
+        item.remove("phone");
+
+        // Addresses are stored as JSON text, and text that is not an address is rejected
+        item.insert("address".to_string(), AttributeValue::S(r#"{"street":"1 Main St","city":"Toronto","country":"CA"}"#.to_string()));
+        let address = user_from_item(&item).unwrap().address.unwrap();
+        assert_eq!((address.city.as_str(), address.country.as_str(), address.postal_code), ("Toronto", "CA", None));
+        item.insert("address".to_string(), AttributeValue::S(r#"{"street":"1 Main St"}"#.to_string()));
+        assert!(matches!(user_from_item(&item), Err(RepositoryError::Backend(_))));
+        item.remove("address");
+
+        // The verification flag is stored as a boolean
+        item.insert("email_verified".to_string(), AttributeValue::Bool(false));
+        assert!(!user_from_item(&item).unwrap().email_verified);
+        item.insert("email_verified".to_string(), AttributeValue::S("false".to_string()));
+        assert!(matches!(user_from_item(&item), Err(RepositoryError::Backend(_))));
+        item.remove("email_verified");
+
+        // Creation times round-trip; items written before they were recorded get the epoch
+        item.insert("created_at".to_string(), AttributeValue::S("2023-10-20T08:30:00.000000Z".to_string()));
+        let created_at = user_from_item(&item).unwrap().created_at;
+        assert_eq!(created_at.to_rfc3339_opts(SecondsFormat::Micros, true), "2023-10-20T08:30:00.000000Z");
+
+        // Update times default to the creation time until the item records its own
+        assert_eq!(user_from_item(&item).unwrap().updated_at, created_at);
+        item.insert("updated_at".to_string(), AttributeValue::S("2023-10-21T09:00:00.000000Z".to_string()));
+        let updated_at = user_from_item(&item).unwrap().updated_at;
+        assert_eq!(updated_at.to_rfc3339_opts(SecondsFormat::Micros, true), "2023-10-21T09:00:00.000000Z");
+
+        // Soft-deleted items round-trip their timestamp
+        item.insert("deleted_at".to_string(), AttributeValue::S("2023-11-01T12:00:00.000000Z".to_string()));
+        let deleted_at = user_from_item(&item).unwrap().deleted_at.unwrap();
+        assert_eq!(deleted_at.to_rfc3339_opts(SecondsFormat::Micros, true), "2023-11-01T12:00:00.000000Z");
+
+        item.remove("email");
+        assert!(matches!(user_from_item(&item), Err(RepositoryError::Backend(_))));
+    }
+
+    // Define a test for the full create/list/update/delete cycle against a live table
+    #[tokio::test]
+    #[ignore = "requires DynamoDB (or DynamoDB Local via AWS_ENDPOINT_URL) and a dynamodb:// DATABASE_URL"]
+    async fn test_crud_cycle() {
+        let config = DatabaseConfig::from_settings(&Settings::load(None).unwrap()).expect("DATABASE_URL must be set");
+        let repository = DynamoRepository::connect(&config).await.expect("Failed to connect to DynamoDB");
+        for user in repository.list_including_deleted().await.unwrap() {
+            repository.purge(&user.id).await.unwrap();
+        }
+
+        // Create a user and check it can be read back
+        let ada = repository
+            .create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(repository.get(&ada.id).await.unwrap(), Some(ada.clone()));
+        assert_eq!(repository.list().await.unwrap(), vec![ada.clone()]);
+
+        // The conditional put refuses to overwrite an existing ID
+        let duplicate = NewUser { id: Some(ada.id.clone()), name: "Dup".to_string(), email: "dup@example.com".to_string(), ..Default::default() };
+        assert!(matches!(repository.create(duplicate).await, Err(RepositoryError::Conflict(_))));
+
+        // Update one field and check the other is preserved; missing users are not created
+        let update = UserUpdate { name: Some("Ada Lovelace".to_string()), ..Default::default() };
+        let updated = repository.update(&ada.id, update.clone()).await.unwrap().unwrap();
+        assert_eq!(updated.name, "Ada Lovelace");
+        assert_eq!(updated.email, "ada@example.com");
+        assert_eq!(repository.update("missing", update).await.unwrap(), None);
+
+        // An update expecting the old version conflicts
+        let stale = UserUpdate { name: Some("Stale".to_string()), expected_version: Some(1), ..Default::default() };
+        let result = repository.update(&ada.id, stale).await;
+        assert!(matches!(result, Err(RepositoryError::VersionConflict { expected: 1, actual: 2, .. })));
+
+        // Soft-delete and check the user is hidden until restored
+        let deleted = repository.delete(&ada.id).await.unwrap().unwrap();
+        assert!(deleted.deleted_at.is_some());
+        assert_eq!(repository.get(&ada.id).await.unwrap(), None);
+        assert_eq!(repository.list().await.unwrap(), Vec::new());
+        assert_eq!(repository.delete(&ada.id).await.unwrap(), None);
+        assert_eq!(repository.restore(&ada.id).await.unwrap(), Some(updated.clone()));
+
+        // Purge and check the user is gone
+        assert_eq!(repository.purge(&ada.id).await.unwrap(), Some(updated));
+        assert_eq!(repository.get_including_deleted(&ada.id).await.unwrap(), None);
+    }
+}
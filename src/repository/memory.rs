@@ -0,0 +1,980 @@
+// Import necessary libraries and modules
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use indexmap::IndexMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedRwLockWriteGuard, RwLock};
+use uuid::Uuid;
+
+use super::{ApiKeyRepository, OrganizationRepository, PasswordRepository, PostRepository, RefreshTokenRepository, RepositoryError, RepositoryResult, TwoFactorRepository, UserRepository, UserTransaction};
+use crate::model::{
+    legacy_user_id, new_user_id, stored_metadata, ApiKey, Comment, Membership, MembershipRole, NewApiKey, NewComment, NewPost, NewUser, Organization, Post, RefreshToken, TwoFactor, User, UserRole,
+    UserUpdate,
+};
+use crate::outbox::{ChangeEvent, ChangeKind};
+use crate::search::{contains_terms, search_terms};
+
+// Define an InMemoryRepository that keeps users in insertion order behind a shared lock
+#[derive(Clone)]
+pub struct InMemoryRepository {
+    users: Arc<RwLock<IndexMap<String, User>>>,
+    outbox: Arc<Mutex<Outbox>>,
+    posts: Arc<RwLock<Posts>>,
+    organizations: Arc<RwLock<Organizations>>,
+    api_keys: Arc<RwLock<ApiKeys>>,
+    // Password hashes by user ID
+    passwords: Arc<RwLock<HashMap<String, String>>>,
+    // Refresh tokens by hash
+    refresh_tokens: Arc<RwLock<HashMap<String, RefreshToken>>>,
+    // Two-factor enrollments by user ID
+    two_factor: Arc<RwLock<HashMap<String, TwoFactor>>>,
+}
+
+// Define the stored API keys in creation order along with the last ID handed out
+#[derive(Default)]
+struct ApiKeys {
+    api_keys: IndexMap<String, ApiKey>,
+    last_id: u64,
+}
+
+// Define the stored organizations and memberships in creation order along with the last ID handed out
+#[derive(Default)]
+struct Organizations {
+    organizations: IndexMap<String, Organization>,
+    memberships: Vec<Membership>,
+    last_id: u64,
+}
+
+// Define the stored posts and comments in creation order along with the last IDs handed out
+#[derive(Default)]
+struct Posts {
+    posts: IndexMap<String, Post>,
+    last_id: u64,
+    comments: IndexMap<String, Comment>,
+    last_comment_id: u64,
+}
+
+impl Posts {
+    // Remove a user's posts and comments along with every comment on those posts and every reply below
+    // a removed comment, as the SQL backends' foreign keys do. Replies come after the comments they answer,
+    // so one pass in creation order catches whole threads
+    fn remove_author(&mut self, author_id: &str) {
+        self.posts.retain(|_, post| post.author_id != author_id);
+        let mut removed = HashSet::new();
+        for comment in self.comments.values() {
+            let orphaned = !self.posts.contains_key(&comment.post_id)
+                || comment.parent_id.as_ref().is_some_and(|parent_id| removed.contains(parent_id));
+            if orphaned || comment.author_id == author_id {
+                removed.insert(comment.id.clone());
+            }
+        }
+        self.comments.retain(|id, _| !removed.contains(id));
+    }
+}
+
+// Define the change events that have not been published yet
+#[derive(Default)]
+struct Outbox {
+    events: Vec<ChangeEvent>,
+    last_id: i64,
+}
+
+impl Outbox {
+    // Append an event for a change to a user
+    fn record(&mut self, kind: ChangeKind, user: &User) {
+        self.last_id += 1;
+        self.events.push(ChangeEvent {
+            id: self.last_id,
+            kind,
+            user: user.clone(),
+            created_at: Utc::now(),
+        });
+    }
+}
+
+impl InMemoryRepository {
+    // Create an empty repository
+    pub fn new() -> Self {
+        InMemoryRepository {
+            users: Arc::new(RwLock::new(IndexMap::new())),
+            outbox: Arc::default(),
+            posts: Arc::default(),
+            organizations: Arc::default(),
+            api_keys: Arc::default(),
+            passwords: Arc::default(),
+            refresh_tokens: Arc::default(),
+            two_factor: Arc::default(),
+        }
+    }
+
+    // Create a repository seeded with the sample users, whose IDs are the ones legacy IDs 1 and 2 map to
+    pub fn with_sample_users() -> Self {
+        let now = Utc::now();
+        let user1 = User {
+            id: legacy_user_id(1).to_string(),
+            name: "Pavel".to_string(),
+            email: "Pavelboukine@gmail.com".to_string(),
+            version: 1,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+            avatar_key: None,
+            role: UserRole::Member,
+            metadata: None,
+            phone: None,
+            address: None,
+            email_verified: true,
+        };
+        let user2 = User {
+            id: legacy_user_id(2).to_string(),
+            name: "Charlie".to_string(),
+            email: "charlie.gracie@noibu.com".to_string(),
+            version: 1,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+            avatar_key: None,
+            role: UserRole::Member,
+            metadata: None,
+            phone: None,
+            address: None,
+            email_verified: true,
+        };
+
+        let users = IndexMap::from([(user1.id.clone(), user1), (user2.id.clone(), user2)]);
+        InMemoryRepository {
+            users: Arc::new(RwLock::new(users)),
+            outbox: Arc::default(),
+            posts: Arc::default(),
+            organizations: Arc::default(),
+            api_keys: Arc::default(),
+            passwords: Arc::default(),
+            refresh_tokens: Arc::default(),
+            two_factor: Arc::default(),
+        }
+    }
+
+    // Record a change event; callers hold the users write lock so events are appended in commit order
+    fn record(&self, kind: ChangeKind, user: &User) {
+        self.outbox.lock().unwrap().record(kind, user);
+    }
+}
+
+impl Default for InMemoryRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Implement the repository operations against the in-memory map
+#[async_trait]
+impl UserRepository for InMemoryRepository {
+    async fn get(&self, id: &str) -> RepositoryResult<Option<User>> {
+        Ok(active_user(&*self.users.read().await, id).cloned())
+    }
+
+    async fn get_including_deleted(&self, id: &str) -> RepositoryResult<Option<User>> {
+        Ok(self.users.read().await.get(id).cloned())
+    }
+
+    async fn get_many(&self, ids: &[String]) -> RepositoryResult<HashMap<String, User>> {
+        let users = self.users.read().await;
+        Ok(ids
+            .iter()
+            .filter_map(|id| active_user(&users, id))
+            .map(|user| (user.id.clone(), user.clone()))
+            .collect())
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<User>> {
+        let users = self.users.read().await;
+        Ok(users.values().filter(|user| user.deleted_at.is_none()).cloned().collect())
+    }
+
+    async fn list_including_deleted(&self) -> RepositoryResult<Vec<User>> {
+        Ok(self.users.read().await.values().cloned().collect())
+    }
+
+    async fn create(&self, new_user: NewUser) -> RepositoryResult<User> {
+        let mut users = self.users.write().await;
+        let user = insert_user(&mut users, new_user)?;
+        self.record(ChangeKind::Created, &user);
+        Ok(user)
+    }
+
+    async fn update(&self, id: &str, update: UserUpdate) -> RepositoryResult<Option<User>> {
+        let mut users = self.users.write().await;
+        let user = update_user(&mut users, id, update)?;
+        if let Some(user) = &user {
+            self.record(ChangeKind::Updated, user);
+        }
+        Ok(user)
+    }
+
+    async fn delete(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let mut users = self.users.write().await;
+        let user = soft_delete_user(&mut users, id);
+        if let Some(user) = &user {
+            self.record(ChangeKind::Deleted, user);
+        }
+        Ok(user)
+    }
+
+    async fn restore(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let mut users = self.users.write().await;
+        let user = users.get_mut(id).map(|user| {
+            user.deleted_at = None;
+            user.clone()
+        });
+        if let Some(user) = &user {
+            self.record(ChangeKind::Restored, user);
+        }
+        Ok(user)
+    }
+
+    async fn purge(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let mut users = self.users.write().await;
+        let user = users.shift_remove(id);
+        if let Some(user) = &user {
+            // Purging takes the user's posts, comments, memberships, API keys, password, refresh tokens, and
+            // two-factor enrollment with it
+            self.posts.write().await.remove_author(&user.id);
+            self.organizations.write().await.memberships.retain(|membership| membership.user_id != user.id);
+            self.api_keys.write().await.api_keys.retain(|_, api_key| api_key.user_id != user.id);
+            self.passwords.write().await.remove(&user.id);
+            self.refresh_tokens.write().await.retain(|_, token| token.user_id != user.id);
+            self.two_factor.write().await.remove(&user.id);
+            self.record(ChangeKind::Purged, user);
+        }
+        Ok(user)
+    }
+
+    async fn begin(&self) -> RepositoryResult<Box<dyn UserTransaction>> {
+        // Holding the write lock serializes transactions with every other write
+        let users = self.users.clone().write_owned().await;
+        Ok(Box::new(InMemoryTransaction {
+            staged: users.clone(),
+            users,
+            outbox: self.outbox.clone(),
+            changes: Vec::new(),
+        }))
+    }
+
+    async fn pending_events(&self, limit: usize) -> RepositoryResult<Vec<ChangeEvent>> {
+        Ok(self.outbox.lock().unwrap().events.iter().take(limit).cloned().collect())
+    }
+
+    async fn acknowledge_events(&self, ids: &[i64]) -> RepositoryResult<()> {
+        self.outbox.lock().unwrap().events.retain(|event| !ids.contains(&event.id));
+        Ok(())
+    }
+
+    fn posts(&self) -> Option<&dyn PostRepository> {
+        Some(self)
+    }
+
+    fn organizations(&self) -> Option<&dyn OrganizationRepository> {
+        Some(self)
+    }
+
+    fn api_keys(&self) -> Option<&dyn ApiKeyRepository> {
+        Some(self)
+    }
+
+    fn passwords(&self) -> Option<&dyn PasswordRepository> {
+        Some(self)
+    }
+
+    fn refresh_tokens(&self) -> Option<&dyn RefreshTokenRepository> {
+        Some(self)
+    }
+
+    fn two_factor(&self) -> Option<&dyn TwoFactorRepository> {
+        Some(self)
+    }
+}
+
+// Implement the password operations against the in-memory hash map
+#[async_trait]
+impl PasswordRepository for InMemoryRepository {
+    async fn set_password_hash(&self, user_id: &str, password_hash: &str) -> RepositoryResult<()> {
+        // Hold the users lock so the user cannot be purged before the hash is stored
+        let users = self.users.read().await;
+        if !users.contains_key(user_id) {
+            return Err(RepositoryError::Conflict(format!("User {} does not exist", user_id)));
+        }
+        self.passwords.write().await.insert(user_id.to_string(), password_hash.to_string());
+        Ok(())
+    }
+
+    async fn get_password_hash(&self, user_id: &str) -> RepositoryResult<Option<String>> {
+        Ok(self.passwords.read().await.get(user_id).cloned())
+    }
+}
+
+// Implement the refresh token operations against the in-memory token map
+#[async_trait]
+impl RefreshTokenRepository for InMemoryRepository {
+    async fn create_refresh_token(&self, user_id: &str, token_hash: &str, expires_at: DateTime<Utc>) -> RepositoryResult<()> {
+        // Hold the users lock so the user cannot be purged before the token is stored
+        let users = self.users.read().await;
+        if !users.contains_key(user_id) {
+            return Err(RepositoryError::Conflict(format!("User {} does not exist", user_id)));
+        }
+        let token = RefreshToken { user_id: user_id.to_string(), token_hash: token_hash.to_string(), expires_at, revoked_at: None };
+        self.refresh_tokens.write().await.insert(token_hash.to_string(), token);
+        Ok(())
+    }
+
+    async fn revoke_refresh_token(&self, token_hash: &str) -> RepositoryResult<Option<RefreshToken>> {
+        let mut refresh_tokens = self.refresh_tokens.write().await;
+        Ok(refresh_tokens.get_mut(token_hash).map(|token| {
+            let before = token.clone();
+            token.revoked_at.get_or_insert_with(Utc::now);
+            before
+        }))
+    }
+
+    async fn revoke_refresh_tokens(&self, user_id: &str) -> RepositoryResult<u64> {
+        let now = Utc::now();
+        let mut revoked = 0;
+        for token in self.refresh_tokens.write().await.values_mut().filter(|token| token.user_id == user_id && token.revoked_at.is_none()) {
+            token.revoked_at = Some(now);
+            revoked += 1;
+        }
+        Ok(revoked)
+    }
+}
+
+// Implement the two-factor operations against the in-memory enrollment map
+#[async_trait]
+impl TwoFactorRepository for InMemoryRepository {
+    async fn set_two_factor(&self, user_id: &str, secret: &str, recovery_code_hashes: &[String]) -> RepositoryResult<()> {
+        // Hold the users lock so the user cannot be purged before the enrollment is stored
+        let users = self.users.read().await;
+        if !users.contains_key(user_id) {
+            return Err(RepositoryError::Conflict(format!("User {} does not exist", user_id)));
+        }
+        let two_factor = TwoFactor { secret: secret.to_string(), confirmed: false, recovery_code_hashes: recovery_code_hashes.to_vec() };
+        self.two_factor.write().await.insert(user_id.to_string(), two_factor);
+        Ok(())
+    }
+
+    async fn get_two_factor(&self, user_id: &str) -> RepositoryResult<Option<TwoFactor>> {
+        Ok(self.two_factor.read().await.get(user_id).cloned())
+    }
+
+    async fn confirm_two_factor(&self, user_id: &str) -> RepositoryResult<bool> {
+        Ok(self.two_factor.write().await.get_mut(user_id).map(|two_factor| two_factor.confirmed = true).is_some())
+    }
+
+    async fn use_recovery_code(&self, user_id: &str, code_hash: &str) -> RepositoryResult<bool> {
+        let mut enrollments = self.two_factor.write().await;
+        let Some(two_factor) = enrollments.get_mut(user_id) else {
+            return Ok(false);
+        };
+        let count = two_factor.recovery_code_hashes.len();
+        two_factor.recovery_code_hashes.retain(|hash| hash != code_hash);
+        Ok(two_factor.recovery_code_hashes.len() < count)
+    }
+}
+
+// Implement the API key operations against the in-memory key list
+#[async_trait]
+impl ApiKeyRepository for InMemoryRepository {
+    async fn create_api_key(&self, new_api_key: NewApiKey) -> RepositoryResult<ApiKey> {
+        // Hold the users lock so the user cannot be purged before the key is stored
+        let users = self.users.read().await;
+        if !users.contains_key(&new_api_key.user_id) {
+            return Err(RepositoryError::Conflict(format!("User {} does not exist", new_api_key.user_id)));
+        }
+        let mut api_keys = self.api_keys.write().await;
+        api_keys.last_id += 1;
+        let api_key = ApiKey {
+            id: api_keys.last_id.to_string(),
+            name: new_api_key.name,
+            user_id: new_api_key.user_id,
+            key_hash: new_api_key.key_hash,
+            created_at: Utc::now(),
+        };
+        api_keys.api_keys.insert(api_key.id.clone(), api_key.clone());
+        Ok(api_key)
+    }
+
+    async fn get_api_key_by_hash(&self, key_hash: &str) -> RepositoryResult<Option<ApiKey>> {
+        Ok(self.api_keys.read().await.api_keys.values().find(|api_key| api_key.key_hash == key_hash).cloned())
+    }
+}
+
+// Implement the organization operations against the in-memory organization list
+#[async_trait]
+impl OrganizationRepository for InMemoryRepository {
+    async fn create_organization(&self, name: String) -> RepositoryResult<Organization> {
+        let mut organizations = self.organizations.write().await;
+        organizations.last_id += 1;
+        let organization = Organization { id: organizations.last_id.to_string(), name, created_at: Utc::now() };
+        organizations.organizations.insert(organization.id.clone(), organization.clone());
+        Ok(organization)
+    }
+
+    async fn get_organizations(&self, ids: &[String]) -> RepositoryResult<HashMap<String, Organization>> {
+        let organizations = self.organizations.read().await;
+        Ok(ids
+            .iter()
+            .filter_map(|id| organizations.organizations.get(id))
+            .map(|organization| (organization.id.clone(), organization.clone()))
+            .collect())
+    }
+
+    async fn list_organizations(&self) -> RepositoryResult<Vec<Organization>> {
+        Ok(self.organizations.read().await.organizations.values().cloned().collect())
+    }
+
+    async fn add_member(&self, organization_id: &str, user_id: &str, role: MembershipRole) -> RepositoryResult<Membership> {
+        // Hold the users lock so the user cannot be purged before the membership is stored
+        let users = self.users.read().await;
+        let mut organizations = self.organizations.write().await;
+        if !users.contains_key(user_id) || !organizations.organizations.contains_key(organization_id) {
+            return Err(RepositoryError::Conflict(format!("User {} or organization {} does not exist", user_id, organization_id)));
+        }
+        let existing = organizations
+            .memberships
+            .iter_mut()
+            .find(|membership| membership.organization_id == organization_id && membership.user_id == user_id);
+        if let Some(membership) = existing {
+            membership.role = role;
+            return Ok(membership.clone());
+        }
+        let membership = Membership {
+            organization_id: organization_id.to_string(),
+            user_id: user_id.to_string(),
+            role,
+            joined_at: Utc::now(),
+        };
+        organizations.memberships.push(membership.clone());
+        Ok(membership)
+    }
+
+    async fn remove_member(&self, organization_id: &str, user_id: &str) -> RepositoryResult<Option<Membership>> {
+        let mut organizations = self.organizations.write().await;
+        let index = organizations
+            .memberships
+            .iter()
+            .position(|membership| membership.organization_id == organization_id && membership.user_id == user_id);
+        Ok(index.map(|index| organizations.memberships.remove(index)))
+    }
+
+    async fn memberships_by_organizations(&self, organization_ids: &[String]) -> RepositoryResult<HashMap<String, Vec<Membership>>> {
+        let mut memberships: HashMap<String, Vec<Membership>> = HashMap::new();
+        let organizations = self.organizations.read().await;
+        for membership in organizations.memberships.iter().filter(|membership| organization_ids.contains(&membership.organization_id)) {
+            memberships.entry(membership.organization_id.clone()).or_default().push(membership.clone());
+        }
+        Ok(memberships)
+    }
+
+    async fn memberships_by_users(&self, user_ids: &[String]) -> RepositoryResult<HashMap<String, Vec<Membership>>> {
+        let mut memberships: HashMap<String, Vec<Membership>> = HashMap::new();
+        let organizations = self.organizations.read().await;
+        for membership in organizations.memberships.iter().filter(|membership| user_ids.contains(&membership.user_id)) {
+            memberships.entry(membership.user_id.clone()).or_default().push(membership.clone());
+        }
+        Ok(memberships)
+    }
+}
+
+// Implement the post operations against the in-memory post list
+#[async_trait]
+impl PostRepository for InMemoryRepository {
+    async fn create_post(&self, new_post: NewPost) -> RepositoryResult<Post> {
+        // Hold the users lock so the author cannot be purged before the post is stored
+        let users = self.users.read().await;
+        if !users.contains_key(&new_post.author_id) {
+            return Err(RepositoryError::InvalidId(new_post.author_id));
+        }
+        let mut posts = self.posts.write().await;
+        posts.last_id += 1;
+        let post = Post {
+            id: posts.last_id.to_string(),
+            author_id: new_post.author_id,
+            title: new_post.title,
+            body: new_post.body,
+            created_at: Utc::now(),
+        };
+        posts.posts.insert(post.id.clone(), post.clone());
+        Ok(post)
+    }
+
+    async fn get_post(&self, id: &str) -> RepositoryResult<Option<Post>> {
+        Ok(self.posts.read().await.posts.get(id).cloned())
+    }
+
+    async fn search_posts(&self, query: &str, limit: usize) -> RepositoryResult<Vec<Post>> {
+        let terms = search_terms(query);
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        let posts = self.posts.read().await;
+        let matches = posts.posts.values().filter(|post| contains_terms(&format!("{} {}", post.title, post.body), &terms));
+        Ok(matches.take(limit).cloned().collect())
+    }
+
+    async fn posts_by_authors(&self, author_ids: &[String]) -> RepositoryResult<HashMap<String, Vec<Post>>> {
+        let mut posts: HashMap<String, Vec<Post>> = HashMap::new();
+        for post in self.posts.read().await.posts.values().filter(|post| author_ids.contains(&post.author_id)) {
+            posts.entry(post.author_id.clone()).or_default().push(post.clone());
+        }
+        Ok(posts)
+    }
+
+    async fn create_comment(&self, new_comment: NewComment) -> RepositoryResult<Comment> {
+        // Hold the users lock so the author cannot be purged before the comment is stored
+        let users = self.users.read().await;
+        if !users.contains_key(&new_comment.author_id) {
+            return Err(RepositoryError::InvalidId(new_comment.author_id));
+        }
+        let mut posts = self.posts.write().await;
+        let parent_missing = new_comment.parent_id.as_ref().is_some_and(|parent_id| !posts.comments.contains_key(parent_id));
+        if !posts.posts.contains_key(&new_comment.post_id) || parent_missing {
+            return Err(RepositoryError::Conflict(format!("Post {} or its parent comment does not exist", new_comment.post_id)));
+        }
+        posts.last_comment_id += 1;
+        let comment = Comment {
+            id: posts.last_comment_id.to_string(),
+            post_id: new_comment.post_id,
+            parent_id: new_comment.parent_id,
+            author_id: new_comment.author_id,
+            body: new_comment.body,
+            depth: new_comment.depth,
+            created_at: Utc::now(),
+        };
+        posts.comments.insert(comment.id.clone(), comment.clone());
+        Ok(comment)
+    }
+
+    async fn get_comment(&self, id: &str) -> RepositoryResult<Option<Comment>> {
+        Ok(self.posts.read().await.comments.get(id).cloned())
+    }
+
+    async fn list_comments(
+        &self,
+        post_id: &str,
+        parent_id: Option<&str>,
+        after: Option<&str>,
+        limit: usize,
+    ) -> RepositoryResult<Vec<Comment>> {
+        let posts = self.posts.read().await;
+        let start = match after {
+            Some(after) => posts.comments.get_index_of(after).map_or(posts.comments.len(), |index| index + 1),
+            None => 0,
+        };
+        Ok(posts
+            .comments
+            .values()
+            .skip(start)
+            .filter(|comment| comment.post_id == post_id && comment.parent_id.as_deref() == parent_id)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+}
+
+// Look up a user that has not been soft-deleted
+fn active_user<'a>(users: &'a IndexMap<String, User>, id: &str) -> Option<&'a User> {
+    users.get(id).filter(|user| user.deleted_at.is_none())
+}
+
+// Insert a user into the map, generating an ID unless one was requested; requested IDs are stored in canonical form
+fn insert_user(users: &mut IndexMap<String, User>, new_user: NewUser) -> RepositoryResult<User> {
+    let id = match new_user.id {
+        Some(id) => {
+            let uuid = Uuid::parse_str(&id).map_err(|_| RepositoryError::InvalidId(id))?.to_string();
+            if users.contains_key(&uuid) {
+                return Err(RepositoryError::Conflict(format!("User {} already exists", uuid)));
+            }
+            uuid
+        }
+        None => new_user_id().to_string(),
+    };
+    let now = Utc::now();
+    let user = User {
+        id,
+        name: new_user.name,
+        email: new_user.email,
+        version: 1,
+        created_at: now,
+        updated_at: now,
+        deleted_at: None,
+        avatar_key: None,
+        role: new_user.role,
+        metadata: None,
+        phone: new_user.phone,
+        address: new_user.address,
+        email_verified: new_user.email_verified,
+    };
+    users.insert(user.id.clone(), user.clone());
+    Ok(user)
+}
+
+// Apply the provided fields to a user in the map, bump its version, and stamp the update time
+fn update_user(users: &mut IndexMap<String, User>, id: &str, update: UserUpdate) -> RepositoryResult<Option<User>> {
+    let Some(user) = users.get_mut(id).filter(|user| user.deleted_at.is_none()) else {
+        return Ok(None);
+    };
+    if let Some(expected) = update.expected_version.filter(|expected| *expected != user.version) {
+        return Err(RepositoryError::VersionConflict { id: user.id.clone(), expected, actual: user.version });
+    }
+    if let Some(name) = update.name {
+        user.name = name;
+    }
+    if let Some(email) = update.email {
+        user.email = email;
+    }
+    if let Some(avatar_key) = update.avatar_key {
+        user.avatar_key = Some(avatar_key);
+    }
+    if let Some(role) = update.role {
+        user.role = role;
+    }
+    if let Some(metadata) = update.metadata {
+        user.metadata = stored_metadata(Some(metadata));
+    }
+    if let Some(phone) = update.phone {
+        user.phone = phone;
+    }
+    if let Some(address) = update.address {
+        user.address = address;
+    }
+    if let Some(email_verified) = update.email_verified {
+        user.email_verified = email_verified;
+    }
+    user.version += 1;
+    user.updated_at = Utc::now();
+    Ok(Some(user.clone()))
+}
+
+// Stamp a user that has not been soft-deleted yet with the deletion time
+fn soft_delete_user(users: &mut IndexMap<String, User>, id: &str) -> Option<User> {
+    let user = users.get_mut(id).filter(|user| user.deleted_at.is_none())?;
+    user.deleted_at = Some(Utc::now());
+    Some(user.clone())
+}
+
+// Define an InMemoryTransaction that stages changes on a copy of the map while holding the write lock.
+// Change events are held back too and only reach the outbox on commit
+pub struct InMemoryTransaction {
+    users: OwnedRwLockWriteGuard<IndexMap<String, User>>,
+    staged: IndexMap<String, User>,
+    outbox: Arc<Mutex<Outbox>>,
+    changes: Vec<(ChangeKind, User)>,
+}
+
+// Implement the transactional operations against the staged copy
+#[async_trait]
+impl UserTransaction for InMemoryTransaction {
+    async fn get(&mut self, id: &str) -> RepositoryResult<Option<User>> {
+        Ok(active_user(&self.staged, id).cloned())
+    }
+
+    async fn create(&mut self, new_user: NewUser) -> RepositoryResult<User> {
+        let user = insert_user(&mut self.staged, new_user)?;
+        self.changes.push((ChangeKind::Created, user.clone()));
+        Ok(user)
+    }
+
+    async fn update(&mut self, id: &str, update: UserUpdate) -> RepositoryResult<Option<User>> {
+        let user = update_user(&mut self.staged, id, update)?;
+        if let Some(user) = &user {
+            self.changes.push((ChangeKind::Updated, user.clone()));
+        }
+        Ok(user)
+    }
+
+    async fn delete(&mut self, id: &str) -> RepositoryResult<Option<User>> {
+        let user = soft_delete_user(&mut self.staged, id);
+        if let Some(user) = &user {
+            self.changes.push((ChangeKind::Deleted, user.clone()));
+        }
+        Ok(user)
+    }
+
+    async fn commit(self: Box<Self>) -> RepositoryResult<()> {
+        let InMemoryTransaction { mut users, staged, outbox, changes, .. } = *self;
+        *users = staged;
+        let mut outbox = outbox.lock().unwrap();
+        for (kind, user) in &changes {
+            outbox.record(*kind, user);
+        }
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> RepositoryResult<()> {
+        Ok(())
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::with_transaction;
+
+    // Name the sample users' IDs
+    const PAVEL: &str = "00000000-0000-0000-0000-000000000001";
+    const CHARLIE: &str = "00000000-0000-0000-0000-000000000002";
+
+    // Define a test for the full create/list/update/delete cycle
+    #[tokio::test]
+    async fn test_crud_cycle() {
+        let repository = InMemoryRepository::new();
+
+        // Create two users and check they are listed in insertion order
+        let ada = repository
+            .create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() })
+            .await
+            .unwrap();
+        let grace = repository
+            .create(NewUser { name: "Grace".to_string(), email: "grace@example.com".to_string(), ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(Uuid::parse_str(&ada.id).unwrap().get_version_num(), 7);
+        assert!(ada.id < grace.id);
+        assert_eq!(repository.list().await.unwrap(), vec![ada.clone(), grace.clone()]);
+
+        // Update one field and check the other is preserved
+        let update = UserUpdate { email: Some("ada@lovelace.dev".to_string()), ..Default::default() };
+        let updated = repository.update(&ada.id, update).await.unwrap().unwrap();
+        assert_eq!(updated.name, "Ada");
+        assert_eq!(updated.email, "ada@lovelace.dev");
+
+        // Delete and check the user is hidden from reads
+        let deleted = repository.delete(&ada.id).await.unwrap().unwrap();
+        assert!(deleted.deleted_at.is_some());
+        assert_eq!(User { deleted_at: None, ..deleted }, updated);
+        assert_eq!(repository.get(&ada.id).await.unwrap(), None);
+        assert_eq!(repository.list().await.unwrap(), vec![grace]);
+    }
+
+    // Define a test that updates bump the version and stale expected versions are rejected
+    #[tokio::test]
+    async fn test_update_with_expected_version() {
+        let repository = InMemoryRepository::with_sample_users();
+
+        // An update at the current version applies and bumps the version
+        let update = UserUpdate { name: Some("Pasha".to_string()), expected_version: Some(1), ..Default::default() };
+        let updated = repository.update(PAVEL, update.clone()).await.unwrap().unwrap();
+        assert_eq!((updated.name.as_str(), updated.version), ("Pasha", 2));
+        assert!(updated.updated_at > updated.created_at);
+
+        // Repeating it with the now stale version conflicts and changes nothing
+        let stale = UserUpdate { name: Some("Lost".to_string()), ..update };
+        let error = repository.update(PAVEL, stale).await.unwrap_err();
+        assert!(matches!(error, RepositoryError::VersionConflict { expected: 1, actual: 2, .. }));
+        assert_eq!(repository.get(PAVEL).await.unwrap(), Some(updated));
+
+        // Updates without an expected version always apply
+        let unchecked = UserUpdate { email: Some("pasha@example.com".to_string()), ..Default::default() };
+        assert_eq!(repository.update(PAVEL, unchecked).await.unwrap().unwrap().version, 3);
+    }
+
+    // Define a test for soft deletion, restoring, and purging
+    #[tokio::test]
+    async fn test_soft_delete_and_restore() {
+        let repository = InMemoryRepository::with_sample_users();
+        let pavel = repository.get(PAVEL).await.unwrap().unwrap();
+
+        // A soft-deleted user is kept but cannot be read, updated, or deleted again
+        let deleted = repository.delete(PAVEL).await.unwrap().unwrap();
+        assert_eq!(repository.get_including_deleted(PAVEL).await.unwrap(), Some(deleted.clone()));
+        assert_eq!(repository.get_many(&[PAVEL.to_string()]).await.unwrap(), HashMap::new());
+        assert_eq!(repository.list_including_deleted().await.unwrap().len(), 2);
+        let update = UserUpdate { name: Some("Renamed".to_string()), ..Default::default() };
+        assert_eq!(repository.update(PAVEL, update).await.unwrap(), None);
+        assert_eq!(repository.delete(PAVEL).await.unwrap(), None);
+
+        // Its ID stays taken while it is deleted
+        let reuse = NewUser { id: Some(PAVEL.to_string()), name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() };
+        assert!(matches!(repository.create(reuse).await, Err(RepositoryError::Conflict(_))));
+
+        // Restoring brings back the original user
+        assert_eq!(repository.restore(PAVEL).await.unwrap(), Some(pavel.clone()));
+        assert_eq!(repository.get(PAVEL).await.unwrap(), Some(pavel.clone()));
+        assert_eq!(repository.restore("9").await.unwrap(), None);
+
+        // Purging removes the user for good
+        assert_eq!(repository.purge(PAVEL).await.unwrap(), Some(pavel));
+        assert_eq!(repository.get_including_deleted(PAVEL).await.unwrap(), None);
+    }
+
+    // Define a test for creating users with requested IDs
+    #[tokio::test]
+    async fn test_create_with_requested_id() {
+        let repository = InMemoryRepository::with_sample_users();
+
+        // A free ID is stored in canonical form and generated IDs are new ones
+        let id = "0190B8D6-3C4A-7D2E-9F10-2A3B4C5D6E7F";
+        let requested = NewUser { id: Some(id.to_string()), name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() };
+        assert_eq!(repository.create(requested.clone()).await.unwrap().id, id.to_lowercase());
+        let generated = NewUser { id: None, ..requested.clone() };
+        assert_ne!(repository.create(generated).await.unwrap().id, id.to_lowercase());
+
+        // A taken ID is rejected without overwriting the existing user, and an ID that is not a UUID is invalid
+        assert!(matches!(repository.create(requested.clone()).await, Err(RepositoryError::Conflict(_))));
+        assert_eq!(repository.get(&id.to_lowercase()).await.unwrap().unwrap().name, "Ada");
+        let malformed = NewUser { id: Some("10".to_string()), ..requested };
+        assert!(matches!(repository.create(malformed).await, Err(RepositoryError::InvalidId(_))));
+    }
+
+    // Define a test that a failure partway through a transaction rolls back its earlier changes
+    #[tokio::test]
+    async fn test_transaction_rollback_on_failure() {
+        let repository = InMemoryRepository::with_sample_users();
+
+        // Create, rename, then fail on a conflicting ID; none of it should stick
+        let result = with_transaction(&repository, |transaction| {
+            Box::pin(async move {
+                let ada = transaction
+                    .create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() })
+                    .await?;
+                transaction.update(PAVEL, UserUpdate { name: Some("Renamed".to_string()), ..Default::default() }).await?;
+                assert_eq!(transaction.get(&ada.id).await?, Some(ada));
+                transaction
+                    .create(NewUser { id: Some(CHARLIE.to_string()), name: "Dup".to_string(), email: "dup@example.com".to_string(), ..Default::default() })
+                    .await
+            })
+        })
+        .await;
+        assert!(matches!(result, Err(RepositoryError::Conflict(_))));
+        assert_eq!(repository.get(PAVEL).await.unwrap().unwrap().name, "Pavel");
+        assert_eq!(repository.list_including_deleted().await.unwrap().len(), 2);
+
+        // A committed transaction applies all of its changes
+        let mut transaction = repository.begin().await.unwrap();
+        let ada = transaction
+            .create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() })
+            .await
+            .unwrap();
+        transaction.delete(CHARLIE).await.unwrap();
+        transaction.commit().await.unwrap();
+        assert_eq!(repository.get(&ada.id).await.unwrap(), Some(ada));
+        assert_eq!(repository.get(CHARLIE).await.unwrap(), None);
+    }
+    // Define a test that purging a user removes their posts and comments and the threads below them
+    #[tokio::test]
+    async fn test_purge_removes_posts_and_threads() {
+        let repository = InMemoryRepository::with_sample_users();
+        let new_post = |author_id: &str| NewPost { author_id: author_id.to_string(), title: "Post".to_string(), body: String::new() };
+        let pavels = repository.create_post(new_post(PAVEL)).await.unwrap();
+        let charlies = repository.create_post(new_post(CHARLIE)).await.unwrap();
+        let comment = |post: &Post, parent: Option<&Comment>, author_id: &str| NewComment {
+            post_id: post.id.clone(),
+            parent_id: parent.map(|parent| parent.id.clone()),
+            author_id: author_id.to_string(),
+            body: "Comment".to_string(),
+            depth: parent.map_or(0, |parent| parent.depth + 1),
+        };
+        let on_pavels = repository.create_comment(comment(&pavels, None, CHARLIE)).await.unwrap();
+        let by_pavel = repository.create_comment(comment(&charlies, None, PAVEL)).await.unwrap();
+        let reply = repository.create_comment(comment(&charlies, Some(&by_pavel), CHARLIE)).await.unwrap();
+        let kept = repository.create_comment(comment(&charlies, None, CHARLIE)).await.unwrap();
+
+        repository.purge(PAVEL).await.unwrap();
+        assert_eq!(repository.get_post(&pavels.id).await.unwrap(), None);
+        for removed in [&on_pavels, &by_pavel, &reply] {
+            assert_eq!(repository.get_comment(&removed.id).await.unwrap(), None);
+        }
+        assert_eq!(repository.list_comments(&charlies.id, None, None, 10).await.unwrap(), vec![kept]);
+
+        // Comments need an existing post, parent, and author
+        assert!(repository.create_comment(comment(&pavels, None, CHARLIE)).await.is_err());
+        assert!(repository.create_comment(comment(&charlies, Some(&by_pavel), CHARLIE)).await.is_err());
+        assert!(repository.create_comment(comment(&charlies, None, PAVEL)).await.is_err());
+    }
+
+    // Define a test that purging a user ends their memberships and that members must exist
+    #[tokio::test]
+    async fn test_purge_removes_memberships() {
+        let repository = InMemoryRepository::with_sample_users();
+        let organization = repository.create_organization("Acme".to_string()).await.unwrap();
+        repository.add_member(&organization.id, PAVEL, MembershipRole::Owner).await.unwrap();
+        let kept = repository.add_member(&organization.id, CHARLIE, MembershipRole::Member).await.unwrap();
+        assert!(repository.add_member(&organization.id, "99", MembershipRole::Member).await.is_err());
+        assert!(repository.add_member("99", PAVEL, MembershipRole::Member).await.is_err());
+
+        repository.purge(PAVEL).await.unwrap();
+        let members = repository.memberships_by_organizations(std::slice::from_ref(&organization.id)).await.unwrap();
+        assert_eq!(members, HashMap::from([(organization.id, vec![kept])]));
+    }
+
+    // Define a test that API keys are found by their hash and go away with their user
+    #[tokio::test]
+    async fn test_api_keys() {
+        let repository = InMemoryRepository::with_sample_users();
+        let new_api_key = |user_id: &str, key_hash: &str| NewApiKey { name: "CI".to_string(), user_id: user_id.to_string(), key_hash: key_hash.to_string() };
+        let api_key = repository.create_api_key(new_api_key(PAVEL, "hash-1")).await.unwrap();
+        assert_eq!((api_key.id.as_str(), api_key.user_id.as_str()), ("1", PAVEL));
+        assert!(repository.create_api_key(new_api_key("99", "hash-2")).await.is_err());
+
+        assert_eq!(repository.get_api_key_by_hash("hash-1").await.unwrap(), Some(api_key));
+        assert_eq!(repository.get_api_key_by_hash("hash-2").await.unwrap(), None);
+
+        repository.purge(PAVEL).await.unwrap();
+        assert_eq!(repository.get_api_key_by_hash("hash-1").await.unwrap(), None);
+    }
+
+    // Define a test that password hashes are replaced when set again and go away with their user
+    #[tokio::test]
+    async fn test_passwords() {
+        let repository = InMemoryRepository::with_sample_users();
+        assert_eq!(repository.get_password_hash(PAVEL).await.unwrap(), None);
+        repository.set_password_hash(PAVEL, "hash-1").await.unwrap();
+        repository.set_password_hash(PAVEL, "hash-2").await.unwrap();
+        assert_eq!(repository.get_password_hash(PAVEL).await.unwrap().as_deref(), Some("hash-2"));
+        assert!(repository.set_password_hash("99", "hash-1").await.is_err());
+
+        repository.purge(PAVEL).await.unwrap();
+        assert_eq!(repository.get_password_hash(PAVEL).await.unwrap(), None);
+    }
+
+    // Define a test that refresh tokens are revoked on use and all at once
+    #[tokio::test]
+    async fn test_refresh_tokens() {
+        let repository = InMemoryRepository::with_sample_users();
+        let expires_at = Utc::now() + chrono::Duration::days(1);
+        repository.create_refresh_token(PAVEL, "hash-1", expires_at).await.unwrap();
+        repository.create_refresh_token(PAVEL, "hash-2", expires_at).await.unwrap();
+        assert!(repository.create_refresh_token("99", "hash-3", expires_at).await.is_err());
+
+        // Revoking returns the token as it was, so only the first caller sees it unrevoked
+        let token = repository.revoke_refresh_token("hash-1").await.unwrap().unwrap();
+        assert_eq!((token.user_id.as_str(), token.expires_at, token.revoked_at), (PAVEL, expires_at, None));
+        assert!(repository.revoke_refresh_token("hash-1").await.unwrap().unwrap().revoked_at.is_some());
+        assert_eq!(repository.revoke_refresh_token("hash-3").await.unwrap(), None);
+
+        // Revoking the user's tokens only counts the ones still live, and purging removes them
+        assert_eq!(repository.revoke_refresh_tokens(PAVEL).await.unwrap(), 1);
+        assert_eq!(repository.revoke_refresh_tokens(PAVEL).await.unwrap(), 0);
+        repository.purge(PAVEL).await.unwrap();
+        assert_eq!(repository.revoke_refresh_token("hash-2").await.unwrap(), None);
+    }
+    // Define a test that two-factor enrollments start pending, are replaced when set again, and use each recovery code once
+    #[tokio::test]
+    async fn test_two_factor() {
+        let repository = InMemoryRepository::with_sample_users();
+        assert!(!repository.confirm_two_factor(PAVEL).await.unwrap());
+        repository.set_two_factor(PAVEL, "SECRET1", &["code-1".to_string(), "code-2".to_string()]).await.unwrap();
+        assert!(!repository.get_two_factor(PAVEL).await.unwrap().unwrap().confirmed);
+        assert!(repository.confirm_two_factor(PAVEL).await.unwrap());
+        assert!(repository.use_recovery_code(PAVEL, "code-1").await.unwrap());
+        assert!(!repository.use_recovery_code(PAVEL, "code-1").await.unwrap());
+        let enrolled = repository.get_two_factor(PAVEL).await.unwrap().unwrap();
+        assert_eq!(enrolled, TwoFactor { secret: "SECRET1".to_string(), confirmed: true, recovery_code_hashes: vec!["code-2".to_string()] });
+
+        repository.set_two_factor(PAVEL, "SECRET2", &[]).await.unwrap();
+        assert!(!repository.get_two_factor(PAVEL).await.unwrap().unwrap().confirmed);
+        assert!(repository.set_two_factor("99", "SECRET1", &[]).await.is_err());
+        repository.purge(PAVEL).await.unwrap();
+        assert_eq!(repository.get_two_factor(PAVEL).await.unwrap(), None);
+    }
+}
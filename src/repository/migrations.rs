@@ -0,0 +1,125 @@
+// Import necessary libraries and modules
+use sqlx::migrate::Migrator;
+
+// Define the embedded PostgreSQL migrations from migrations/postgres
+#[cfg(feature = "postgres")]
+pub static POSTGRES: Migrator = sqlx::migrate!("./migrations/postgres");
+
+// Define the embedded SQLite migrations from migrations/sqlite
+#[cfg(feature = "sqlite")]
+pub static SQLITE: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+// Count the migrations of a migrator whose versions are not among those applied
+pub(crate) fn count_pending(migrator: &Migrator, applied: &[i64]) -> usize {
+    migrator.iter().filter(|migration| !migration.migration_type.is_down_migration() && !applied.contains(&migration.version)).count()
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    // Define a test that migrations create the schema in a fresh database and can be re-run
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_sqlite_migrations_on_temp_database() {
+        use crate::repository::{DatabaseConfig, SqliteRepository, UserRepository};
+
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let url = format!("sqlite:{}", dir.path().join("app.db").display());
+        let config = DatabaseConfig { max_connections: 1, ..DatabaseConfig::new(url) };
+
+        // Connecting applies every migration to the new database
+        let repository = SqliteRepository::connect(&config).await.expect("Failed to open SQLite");
+        let pool = sqlx::SqlitePool::connect(&config.url).await.unwrap();
+        let applied: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM _sqlx_migrations WHERE success")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(applied as usize, super::SQLITE.iter().count());
+        let tables: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'users'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(tables, 1);
+
+        // Running them again is a no-op
+        repository.migrate().await.expect("Re-running migrations failed");
+        assert_eq!(repository.pending_migrations().await.unwrap(), 0);
+        assert_eq!(super::count_pending(&super::SQLITE, &[]), super::SQLITE.iter().count());
+    }
+
+    // Define a test that numeric user IDs written before the UUID migration are converted along with
+    // the rows pointing at them
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_sqlite_legacy_user_ids_are_converted() {
+        use sqlx::migrate::Migrator;
+        use std::borrow::Cow;
+
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let url = format!("sqlite:{}", dir.path().join("app.db").display());
+        let options = <sqlx::sqlite::SqliteConnectOptions as std::str::FromStr>::from_str(&url).unwrap().create_if_missing(true);
+        let pool = sqlx::SqlitePool::connect_with(options).await.unwrap();
+
+        // Build the schema as it was before user IDs became UUIDs and fill it with numeric IDs
+        let legacy = Migrator {
+            migrations: Cow::Owned(super::SQLITE.iter().filter(|m| m.version < 20231112000000).cloned().collect()),
+            ..Migrator::DEFAULT
+        };
+        legacy.run(&pool).await.expect("Legacy migrations failed");
+        for statement in [
+            "INSERT INTO users (id, name, email) VALUES (1, 'Pavel', 'pavel@example.com'), (26, 'Charlie', 'charlie@example.com')",
+            "INSERT INTO posts (id, author_id, title, body, created_at) VALUES (1, 26, 'Hello', 'World', '2023-11-12T00:00:00Z')",
+            "INSERT INTO comments (id, post_id, author_id, body, depth, created_at) VALUES (1, 1, 1, 'Hi', 0, '2023-11-12T00:00:00Z')",
+            "INSERT INTO organizations (id, name, created_at) VALUES (1, 'Acme', '2023-11-12T00:00:00Z')",
+            "INSERT INTO memberships (organization_id, user_id, role, joined_at) VALUES (1, 26, 'OWNER', '2023-11-12T00:00:00Z')",
+        ] {
+            sqlx::query(statement).execute(&pool).await.unwrap();
+        }
+
+        super::SQLITE.run(&pool).await.expect("Migrations failed");
+        let ids: Vec<String> = sqlx::query_scalar("SELECT id FROM users ORDER BY id").fetch_all(&pool).await.unwrap();
+        assert_eq!(ids, vec!["00000000-0000-0000-0000-000000000001", "00000000-0000-0000-0000-00000000001a"]);
+        let author: String = sqlx::query_scalar("SELECT author_id FROM posts").fetch_one(&pool).await.unwrap();
+        assert_eq!(author, "00000000-0000-0000-0000-00000000001a");
+        let commenter: String = sqlx::query_scalar("SELECT author_id FROM comments").fetch_one(&pool).await.unwrap();
+        assert_eq!(commenter, "00000000-0000-0000-0000-000000000001");
+        let member: String = sqlx::query_scalar("SELECT user_id FROM memberships").fetch_one(&pool).await.unwrap();
+        assert_eq!(member, "00000000-0000-0000-0000-00000000001a");
+
+        // The foreign keys now point at the rebuilt tables, so purging a user still removes their rows
+        sqlx::query("DELETE FROM users WHERE id = '00000000-0000-0000-0000-00000000001a'").execute(&pool).await.unwrap();
+        let left: i64 = sqlx::query_scalar("SELECT (SELECT COUNT(*) FROM posts) + (SELECT COUNT(*) FROM comments) + (SELECT COUNT(*) FROM memberships)")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(left, 0);
+    }
+
+    // Define a test that migrations apply to a freshly created PostgreSQL database
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    #[ignore = "requires a PostgreSQL server in DATABASE_URL"]
+    async fn test_postgres_migrations_on_temp_database() {
+        use sqlx::postgres::{PgConnectOptions, PgPool};
+        use std::str::FromStr;
+
+        // Create a throwaway database next to the one in DATABASE_URL
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let admin = PgPool::connect(&url).await.expect("Failed to connect to PostgreSQL");
+        let name = format!("migrations_test_{}", std::process::id());
+        sqlx::query(&format!("CREATE DATABASE {}", name)).execute(&admin).await.unwrap();
+
+        let options = PgConnectOptions::from_str(&url).unwrap().database(&name);
+        let pool = PgPool::connect_with(options).await.unwrap();
+        super::POSTGRES.run(&pool).await.expect("Migrations failed");
+        super::POSTGRES.run(&pool).await.expect("Re-running migrations failed");
+        let tables: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM information_schema.tables WHERE table_name = 'users'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(tables, 1);
+
+        pool.close().await;
+        sqlx::query(&format!("DROP DATABASE {}", name)).execute(&admin).await.unwrap();
+    }
+}
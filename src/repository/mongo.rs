@@ -0,0 +1,335 @@
+// Import necessary libraries and modules
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, to_bson, Document};
+use mongodb::options::{ClientOptions, Collation, CollationStrength, IndexOptions, ReturnDocument};
+use mongodb::{Client, Collection, Database, IndexModel};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::{DatabaseConfig, RepositoryError, RepositoryResult, UserRepository};
+use crate::model::{default_email_verified, legacy_user_id, new_user_id, parse_user_id, stored_metadata, Address, NewUser, User, UserRole, UserUpdate};
+
+// Define the database used when DATABASE_URL does not name one
+const DEFAULT_DATABASE: &str = "app";
+
+// Define a MongoRepository that stores users in a `users` collection
+#[derive(Clone)]
+pub struct MongoRepository {
+    users: Collection<UserDocument>,
+}
+
+// Define the document shape stored in the users collection
+#[derive(Serialize, Deserialize)]
+struct UserDocument {
+    id: String,
+    name: String,
+    email: String,
+    version: i32,
+    #[serde(default)]
+    created_at: DateTime<Utc>,
+    // Missing on users stored before update times were recorded, which count as updated when they were created
+    #[serde(default)]
+    updated_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    deleted_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    avatar_key: Option<String>,
+    // Missing on users stored before roles existed, who are members
+    #[serde(default)]
+    role: UserRole,
+    #[serde(default)]
+    metadata: Option<serde_json::Value>,
+    #[serde(default)]
+    phone: Option<String>,
+    #[serde(default)]
+    address: Option<Address>,
+    // Missing on users stored before verification existed, who count as verified
+    #[serde(default = "default_email_verified")]
+    email_verified: bool,
+}
+
+impl From<UserDocument> for User {
+    fn from(document: UserDocument) -> Self {
+        User {
+            id: document.id,
+            name: document.name,
+            email: document.email,
+            version: document.version,
+            created_at: document.created_at,
+            updated_at: document.updated_at.unwrap_or(document.created_at),
+            deleted_at: document.deleted_at,
+            avatar_key: document.avatar_key,
+            role: document.role,
+            metadata: stored_metadata(document.metadata),
+            phone: document.phone,
+            address: document.address,
+            email_verified: document.email_verified,
+        }
+    }
+}
+
+// Define the collation that makes email comparisons case-insensitive
+fn email_collation() -> Collation {
+    Collation::builder().locale("en").strength(CollationStrength::Secondary).build()
+}
+
+impl MongoRepository {
+    // Connect to the cluster and make sure the indexes on `id` and `email` exist
+    pub async fn connect(config: &DatabaseConfig) -> RepositoryResult<Self> {
+        let mut options = ClientOptions::parse(&config.url).await?;
+        options.max_pool_size = Some(config.max_connections);
+        options.max_idle_time = config.idle_timeout;
+        let client = Client::with_options(options)?;
+        let database = client
+            .default_database()
+            .unwrap_or_else(|| client.database(DEFAULT_DATABASE));
+
+        let repository = MongoRepository {
+            users: database.collection("users"),
+        };
+        repository.ensure_indexes().await?;
+        repository.convert_legacy_ids(&database).await?;
+        Ok(repository)
+    }
+
+    // Create the unique index used for lookups by ID and the case-insensitive one used for lookups by email
+    async fn ensure_indexes(&self) -> RepositoryResult<()> {
+        let index = IndexModel::builder()
+            .keys(doc! { "id": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.users.create_index(index).await?;
+        let index = IndexModel::builder()
+            .keys(doc! { "email": 1 })
+            .options(IndexOptions::builder().collation(email_collation()).build())
+            .build();
+        self.users.create_index(index).await?;
+        Ok(())
+    }
+
+    // List users matching a filter in insertion order
+    async fn list_users(&self, filter: Document) -> RepositoryResult<Vec<User>> {
+        let cursor = self.users.find(filter).sort(doc! { "_id": 1 }).await?;
+        let documents: Vec<UserDocument> = cursor.try_collect().await?;
+        Ok(documents.into_iter().map(User::from).collect())
+    }
+
+    // Move users stored under numeric IDs to the UUIDs those IDs map to, the same conversion the SQL
+    // migrations apply, and drop the counter that handed them out
+    async fn convert_legacy_ids(&self, database: &Database) -> RepositoryResult<()> {
+        let users = self.users.clone_with_type::<Document>();
+        let legacy: Vec<Document> = users.find(doc! { "id": { "$regex": "^[0-9]+$" } }).await?.try_collect().await?;
+        for document in legacy {
+            let (Ok(object_id), Some(number)) = (document.get_object_id("_id"), document.get_str("id").ok().and_then(|id| id.parse().ok())) else {
+                continue;
+            };
+            users.update_one(doc! { "_id": object_id }, doc! { "$set": { "id": legacy_user_id(number).to_string() } }).await?;
+        }
+        database.collection::<Document>("counters").delete_one(doc! { "_id": "users" }).await?;
+        Ok(())
+    }
+}
+
+// Implement the repository operations as collection queries
+#[async_trait]
+impl UserRepository for MongoRepository {
+    async fn get(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let document = self.users.find_one(doc! { "id": id, "deleted_at": null }).await?;
+        Ok(document.map(User::from))
+    }
+
+    async fn get_including_deleted(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let document = self.users.find_one(doc! { "id": id }).await?;
+        Ok(document.map(User::from))
+    }
+
+    // Queries with the index's collation compare emails ignoring case
+    async fn get_by_email(&self, email: &str) -> RepositoryResult<Option<User>> {
+        let document = self
+            .users
+            .find_one(doc! { "email": email, "deleted_at": null })
+            .collation(email_collation())
+            .sort(doc! { "_id": 1 })
+            .await?;
+        Ok(document.map(User::from))
+    }
+
+    async fn get_many(&self, ids: &[String]) -> RepositoryResult<HashMap<String, User>> {
+        let cursor = self.users.find(doc! { "id": { "$in": ids }, "deleted_at": null }).await?;
+        let documents: Vec<UserDocument> = cursor.try_collect().await?;
+        Ok(documents
+            .into_iter()
+            .map(|document| (document.id.clone(), document.into()))
+            .collect())
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<User>> {
+        self.list_users(doc! { "deleted_at": null }).await
+    }
+
+    async fn list_including_deleted(&self) -> RepositoryResult<Vec<User>> {
+        self.list_users(doc! {}).await
+    }
+
+    async fn create(&self, new_user: NewUser) -> RepositoryResult<User> {
+        let id = match new_user.id {
+            Some(id) => parse_user_id(&id).ok_or(RepositoryError::InvalidId(id))?.to_string(),
+            None => new_user_id().to_string(),
+        };
+        let now = Utc::now();
+        let document = UserDocument {
+            id,
+            name: new_user.name,
+            email: new_user.email,
+            version: 1,
+            created_at: now,
+            updated_at: Some(now),
+            deleted_at: None,
+            avatar_key: None,
+            role: new_user.role,
+            metadata: None,
+            phone: new_user.phone,
+            address: new_user.address,
+            email_verified: new_user.email_verified,
+        };
+        self.users.insert_one(&document).await?;
+        Ok(document.into())
+    }
+
+    async fn update(&self, id: &str, update: UserUpdate) -> RepositoryResult<Option<User>> {
+        // Store the timestamp in the same RFC 3339 form serde writes for UserDocument
+        let updated_at = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+        let mut fields = doc! { "updated_at": updated_at };
+        if let Some(name) = update.name {
+            fields.insert("name", name);
+        }
+        if let Some(email) = update.email {
+            fields.insert("email", email);
+        }
+        if let Some(avatar_key) = update.avatar_key {
+            fields.insert("avatar_key", avatar_key);
+        }
+        if let Some(role) = update.role {
+            fields.insert("role", role.as_str());
+        }
+        if let Some(metadata) = update.metadata {
+            let metadata = to_bson(&metadata).map_err(|error| RepositoryError::Backend(error.to_string()))?;
+            fields.insert("metadata", metadata);
+        }
+        if let Some(phone) = update.phone {
+            fields.insert("phone", phone);
+        }
+        if let Some(address) = update.address {
+            let address = to_bson(&address).map_err(|error| RepositoryError::Backend(error.to_string()))?;
+            fields.insert("address", address);
+        }
+        if let Some(email_verified) = update.email_verified {
+            fields.insert("email_verified", email_verified);
+        }
+        let changes = doc! { "$inc": { "version": 1 }, "$set": fields };
+        let mut filter = doc! { "id": id, "deleted_at": null };
+        if let Some(expected) = update.expected_version {
+            filter.insert("version", expected);
+        }
+
+        let document = self
+            .users
+            .find_one_and_update(filter, changes)
+            .return_document(ReturnDocument::After)
+            .await?;
+        if let Some(document) = document {
+            return Ok(Some(document.into()));
+        }
+
+        // Nothing matched, so the user is either missing or has moved past the expected version
+        match (update.expected_version, self.get(id).await?) {
+            (Some(expected), Some(user)) => Err(RepositoryError::VersionConflict { id: user.id, expected, actual: user.version }),
+            _ => Ok(None),
+        }
+    }
+
+    async fn delete(&self, id: &str) -> RepositoryResult<Option<User>> {
+        // Store the timestamp in the same RFC 3339 form serde writes for UserDocument
+        let deleted_at = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+        let document = self
+            .users
+            .find_one_and_update(doc! { "id": id, "deleted_at": null }, doc! { "$set": { "deleted_at": deleted_at } })
+            .return_document(ReturnDocument::After)
+            .await?;
+        Ok(document.map(User::from))
+    }
+
+    async fn restore(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let document = self
+            .users
+            .find_one_and_update(doc! { "id": id }, doc! { "$unset": { "deleted_at": "" } })
+            .return_document(ReturnDocument::After)
+            .await?;
+        Ok(document.map(User::from))
+    }
+
+    async fn purge(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let document = self.users.find_one_and_delete(doc! { "id": id }).await?;
+        Ok(document.map(User::from))
+    }
+
+    async fn ping(&self) -> RepositoryResult<()> {
+        self.users.client().database("admin").run_command(doc! { "ping": 1 }).await?;
+        Ok(())
+    }
+
+    // Wait for operations in progress to finish, then close the client's connections
+    async fn close(&self) {
+        self.users.client().clone().shutdown().await;
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Settings;
+
+    // Define a test for the full create/list/update/delete cycle against a live cluster
+    #[tokio::test]
+    #[ignore = "requires a MongoDB server in DATABASE_URL"]
+    async fn test_crud_cycle() {
+        let config = DatabaseConfig::from_settings(&Settings::load(None).unwrap()).expect("DATABASE_URL must be set");
+        let repository = MongoRepository::connect(&config).await.expect("Failed to connect to MongoDB");
+        repository.users.delete_many(doc! {}).await.unwrap();
+
+        // Create a user and check it can be read back
+        let ada = repository
+            .create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(repository.get(&ada.id).await.unwrap(), Some(ada.clone()));
+        assert_eq!(repository.list().await.unwrap(), vec![ada.clone()]);
+
+        // Update one field and check the other is preserved
+        let update = UserUpdate { name: Some("Ada Lovelace".to_string()), ..Default::default() };
+        let updated = repository.update(&ada.id, update).await.unwrap().unwrap();
+        assert_eq!(updated.name, "Ada Lovelace");
+        assert_eq!(updated.email, "ada@example.com");
+        assert_eq!(updated.version, 2);
+
+        // An update expecting the old version conflicts
+        let stale = UserUpdate { name: Some("Stale".to_string()), expected_version: Some(1), ..Default::default() };
+        let result = repository.update(&ada.id, stale).await;
+        assert!(matches!(result, Err(RepositoryError::VersionConflict { expected: 1, actual: 2, .. })));
+
+        // Soft-delete and check the user is hidden until restored
+        let deleted = repository.delete(&ada.id).await.unwrap().unwrap();
+        assert!(deleted.deleted_at.is_some());
+        assert_eq!(repository.get(&ada.id).await.unwrap(), None);
+        assert_eq!(repository.list().await.unwrap(), Vec::new());
+        assert_eq!(repository.get_including_deleted(&ada.id).await.unwrap(), Some(deleted));
+        assert_eq!(repository.restore(&ada.id).await.unwrap(), Some(updated.clone()));
+
+        // Purge and check the user is gone
+        assert_eq!(repository.purge(&ada.id).await.unwrap(), Some(updated));
+        assert_eq!(repository.get_including_deleted(&ada.id).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,68 @@
+// Import necessary libraries and modules
+use sqlx::pool::PoolConnection;
+use sqlx::{Database, Pool, Transaction};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{PoolStats, RepositoryResult};
+
+// Define a connection pool that times every checkout, since sqlx only reports how many connections are open
+pub(crate) struct TimedPool<DB: Database> {
+    pool: Pool<DB>,
+    acquires: Arc<AtomicU64>,
+    wait_micros: Arc<AtomicU64>,
+}
+
+impl<DB: Database> Clone for TimedPool<DB> {
+    fn clone(&self) -> Self {
+        TimedPool {
+            pool: self.pool.clone(),
+            acquires: self.acquires.clone(),
+            wait_micros: self.wait_micros.clone(),
+        }
+    }
+}
+
+impl<DB: Database> TimedPool<DB> {
+    pub(crate) fn new(pool: Pool<DB>) -> Self {
+        TimedPool { pool, acquires: Default::default(), wait_micros: Default::default() }
+    }
+
+    // Return the pool itself, for work like migrations that should not count towards the statistics
+    pub(crate) fn inner(&self) -> &Pool<DB> {
+        &self.pool
+    }
+
+    fn record(&self, started: Instant) {
+        self.acquires.fetch_add(1, Ordering::Relaxed);
+        self.wait_micros.fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+    }
+
+    // Check out a connection, waiting up to the acquire timeout for one to become free
+    pub(crate) async fn acquire(&self) -> RepositoryResult<PoolConnection<DB>> {
+        let started = Instant::now();
+        let connection = self.pool.acquire().await;
+        self.record(started);
+        Ok(connection?)
+    }
+
+    // Check out a connection and begin a transaction on it; the wait includes sending BEGIN
+    pub(crate) async fn begin(&self) -> RepositoryResult<Transaction<'static, DB>> {
+        let started = Instant::now();
+        let transaction = self.pool.begin().await;
+        self.record(started);
+        Ok(transaction?)
+    }
+
+    // Take a snapshot of the pool and the checkout counters
+    pub(crate) fn stats(&self) -> PoolStats {
+        PoolStats {
+            max_connections: self.pool.options().get_max_connections(),
+            connections: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            acquires: self.acquires.load(Ordering::Relaxed),
+            acquire_wait: Duration::from_micros(self.wait_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
@@ -0,0 +1,344 @@
+// Import necessary libraries and modules
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::log_warn;
+use super::{ApiKeyRepository, OrganizationRepository, PasswordRepository, PoolStats, PostRepository, RefreshTokenRepository, RepositoryError, RepositoryResult, SharedRepository, TwoFactorRepository, UserRepository, UserTransaction};
+use crate::model::{NewUser, User, UserFilter, UserOrder, UserStats, UserUpdate};
+use crate::outbox::ChangeEvent;
+use crate::search::UserSearchResult;
+
+// Define how long a replica that failed a read is skipped before it is tried again
+const REPLICA_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+// Define a read replica and when it last became unreachable
+struct Replica {
+    repository: SharedRepository,
+    unavailable_until: Mutex<Option<Instant>>,
+}
+
+impl Replica {
+    fn is_available(&self) -> bool {
+        let unavailable_until = self.unavailable_until.lock().unwrap();
+        unavailable_until.is_none_or(|until| Instant::now() >= until)
+    }
+
+    fn mark_unavailable(&self) {
+        *self.unavailable_until.lock().unwrap() = Some(Instant::now() + REPLICA_RETRY_AFTER);
+    }
+}
+
+// Define a ReplicatedRepository that sends reads to the replicas in turn and everything else to the primary.
+// A replica that fails a read is skipped for a while and the read is retried on the next replica,
+// falling back to the primary when no replica can answer.
+pub struct ReplicatedRepository {
+    primary: SharedRepository,
+    replicas: Vec<Replica>,
+    next: AtomicUsize,
+}
+
+impl ReplicatedRepository {
+    // Wrap a primary and its read replicas
+    pub fn new(primary: SharedRepository, replicas: Vec<SharedRepository>) -> Self {
+        ReplicatedRepository {
+            primary,
+            replicas: replicas
+                .into_iter()
+                .map(|repository| Replica { repository, unavailable_until: Mutex::new(None) })
+                .collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    // Run a read on the next available replica, failing over to the other replicas and then the primary
+    async fn read<'a, T>(
+        &self,
+        query: impl Fn(SharedRepository) -> BoxFuture<'a, RepositoryResult<T>>,
+    ) -> RepositoryResult<T> {
+        if !self.replicas.is_empty() {
+            let start = self.next.fetch_add(1, Ordering::Relaxed);
+            for offset in 0..self.replicas.len() {
+                let replica = &self.replicas[(start + offset) % self.replicas.len()];
+                if !replica.is_available() {
+                    continue;
+                }
+                match query(replica.repository.clone()).await {
+                    // Only connection-level failures mean the replica is unreachable
+                    Err(RepositoryError::Backend(message)) => {
+                        log_warn!("Read replica failed, trying the next one: {}", message);
+                        replica.mark_unavailable();
+                    }
+                    result => return result,
+                }
+            }
+        }
+        query(self.primary.clone()).await
+    }
+}
+
+// Route reads to replicas and writes to the primary
+#[async_trait]
+impl UserRepository for ReplicatedRepository {
+    async fn get(&self, id: &str) -> RepositoryResult<Option<User>> {
+        self.read(|repository| Box::pin(async move { repository.get(id).await })).await
+    }
+
+    async fn get_including_deleted(&self, id: &str) -> RepositoryResult<Option<User>> {
+        self.read(|repository| Box::pin(async move { repository.get_including_deleted(id).await })).await
+    }
+
+    async fn get_by_email(&self, email: &str) -> RepositoryResult<Option<User>> {
+        self.read(|repository| Box::pin(async move { repository.get_by_email(email).await })).await
+    }
+
+    async fn get_many(&self, ids: &[String]) -> RepositoryResult<HashMap<String, User>> {
+        self.read(|repository| Box::pin(async move { repository.get_many(ids).await })).await
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<User>> {
+        self.read(|repository| Box::pin(async move { repository.list().await })).await
+    }
+
+    async fn list_including_deleted(&self) -> RepositoryResult<Vec<User>> {
+        self.read(|repository| Box::pin(async move { repository.list_including_deleted().await })).await
+    }
+
+    async fn list_page(&self, filter: &UserFilter, after: Option<&str>, limit: usize) -> RepositoryResult<Vec<User>> {
+        self.read(|repository| Box::pin(async move { repository.list_page(filter, after, limit).await })).await
+    }
+
+    async fn list_range(&self, filter: &UserFilter, order: &[UserOrder], offset: usize, limit: usize) -> RepositoryResult<Vec<User>> {
+        self.read(|repository| Box::pin(async move { repository.list_range(filter, order, offset, limit).await })).await
+    }
+
+    async fn count(&self, filter: &UserFilter) -> RepositoryResult<usize> {
+        self.read(|repository| Box::pin(async move { repository.count(filter).await })).await
+    }
+
+    async fn stats(&self, created_since: DateTime<Utc>) -> RepositoryResult<UserStats> {
+        self.read(|repository| Box::pin(async move { repository.stats(created_since).await })).await
+    }
+
+    async fn search(&self, query: &str, limit: usize, offset: usize) -> RepositoryResult<Vec<UserSearchResult>> {
+        self.read(|repository| Box::pin(async move { repository.search(query, limit, offset).await })).await
+    }
+
+    async fn create(&self, new_user: NewUser) -> RepositoryResult<User> {
+        self.primary.create(new_user).await
+    }
+
+    async fn create_many(&self, new_users: Vec<NewUser>) -> RepositoryResult<Vec<User>> {
+        self.primary.create_many(new_users).await
+    }
+
+    async fn update(&self, id: &str, update: UserUpdate) -> RepositoryResult<Option<User>> {
+        self.primary.update(id, update).await
+    }
+
+    async fn delete(&self, id: &str) -> RepositoryResult<Option<User>> {
+        self.primary.delete(id).await
+    }
+
+    async fn restore(&self, id: &str) -> RepositoryResult<Option<User>> {
+        self.primary.restore(id).await
+    }
+
+    async fn purge(&self, id: &str) -> RepositoryResult<Option<User>> {
+        self.primary.purge(id).await
+    }
+
+    // Replica outages are absorbed by failing over, so only the primary decides readiness
+    async fn ping(&self) -> RepositoryResult<()> {
+        self.primary.ping().await
+    }
+
+    // Only the primary's pool is reported; the replicas share its settings
+    fn pool_stats(&self) -> Option<PoolStats> {
+        self.primary.pool_stats()
+    }
+
+    async fn close(&self) {
+        for replica in &self.replicas {
+            replica.repository.close().await;
+        }
+        self.primary.close().await;
+    }
+
+    // Replicas replay the primary's migrations, so only the primary is checked
+    async fn pending_migrations(&self) -> RepositoryResult<usize> {
+        self.primary.pending_migrations().await
+    }
+
+    // Posts and organizations are few next to the user queries, so they stay on the primary where changes
+    // are visible at once
+    fn posts(&self) -> Option<&dyn PostRepository> {
+        self.primary.posts()
+    }
+
+    fn organizations(&self) -> Option<&dyn OrganizationRepository> {
+        self.primary.organizations()
+    }
+
+    fn api_keys(&self) -> Option<&dyn ApiKeyRepository> {
+        self.primary.api_keys()
+    }
+
+    fn passwords(&self) -> Option<&dyn PasswordRepository> {
+        self.primary.passwords()
+    }
+
+    fn refresh_tokens(&self) -> Option<&dyn RefreshTokenRepository> {
+        self.primary.refresh_tokens()
+    }
+
+    fn two_factor(&self) -> Option<&dyn TwoFactorRepository> {
+        self.primary.two_factor()
+    }
+
+    // Reads inside a transaction must see its own writes, so transactions always run on the primary
+    async fn begin(&self) -> RepositoryResult<Box<dyn UserTransaction>> {
+        self.primary.begin().await
+    }
+
+    // Events are written with the data, so the relay reads them from the primary too
+    async fn pending_events(&self, limit: usize) -> RepositoryResult<Vec<ChangeEvent>> {
+        self.primary.pending_events(limit).await
+    }
+
+    async fn acknowledge_events(&self, ids: &[i64]) -> RepositoryResult<()> {
+        self.primary.acknowledge_events(ids).await
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::legacy_user_id;
+    use crate::repository::InMemoryRepository;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    // Define a replica that can be switched off to simulate an unreachable server
+    struct FlakyRepository {
+        inner: InMemoryRepository,
+        down: Arc<AtomicBool>,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl FlakyRepository {
+        fn check(&self) -> RepositoryResult<()> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            match self.down.load(Ordering::SeqCst) {
+                true => Err(RepositoryError::Backend("connection refused".to_string())),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl UserRepository for FlakyRepository {
+        async fn get(&self, id: &str) -> RepositoryResult<Option<User>> {
+            self.check()?;
+            self.inner.get(id).await
+        }
+
+        async fn get_including_deleted(&self, id: &str) -> RepositoryResult<Option<User>> {
+            self.check()?;
+            self.inner.get_including_deleted(id).await
+        }
+
+        async fn list(&self) -> RepositoryResult<Vec<User>> {
+            self.check()?;
+            self.inner.list().await
+        }
+
+        async fn list_including_deleted(&self) -> RepositoryResult<Vec<User>> {
+            self.check()?;
+            self.inner.list_including_deleted().await
+        }
+
+        async fn create(&self, new_user: NewUser) -> RepositoryResult<User> {
+            self.inner.create(new_user).await
+        }
+
+        async fn update(&self, id: &str, update: UserUpdate) -> RepositoryResult<Option<User>> {
+            self.inner.update(id, update).await
+        }
+
+        async fn delete(&self, id: &str) -> RepositoryResult<Option<User>> {
+            self.inner.delete(id).await
+        }
+
+        async fn restore(&self, id: &str) -> RepositoryResult<Option<User>> {
+            self.inner.restore(id).await
+        }
+
+        async fn purge(&self, id: &str) -> RepositoryResult<Option<User>> {
+            self.inner.purge(id).await
+        }
+    }
+
+    // Build a replica holding the sample users along with its off switch and read counter
+    fn replica() -> (SharedRepository, Arc<AtomicBool>, Arc<AtomicUsize>) {
+        let down = Arc::new(AtomicBool::new(false));
+        let reads = Arc::new(AtomicUsize::new(0));
+        let repository = FlakyRepository {
+            inner: InMemoryRepository::with_sample_users(),
+            down: down.clone(),
+            reads: reads.clone(),
+        };
+        (Arc::new(repository), down, reads)
+    }
+
+    // Define a test that reads alternate between replicas while writes only reach the primary
+    #[tokio::test]
+    async fn test_reads_round_robin_and_writes_use_primary() {
+        let (first, _, first_reads) = replica();
+        let (second, _, second_reads) = replica();
+        let primary = Arc::new(InMemoryRepository::new());
+        let repository = ReplicatedRepository::new(primary.clone(), vec![first, second]);
+        let pavel = legacy_user_id(1).to_string();
+
+        for _ in 0..4 {
+            assert_eq!(repository.get(&pavel).await.unwrap().unwrap().name, "Pavel");
+        }
+        assert_eq!(first_reads.load(Ordering::SeqCst), 2);
+        assert_eq!(second_reads.load(Ordering::SeqCst), 2);
+
+        // The replicas never see the new user; the primary does
+        let ada = repository
+            .create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(primary.get(&ada.id).await.unwrap(), Some(ada));
+        assert_eq!(repository.list().await.unwrap().len(), 2);
+    }
+
+    // Define a test that reads fail over to the next replica and then to the primary
+    #[tokio::test]
+    async fn test_failover_when_replicas_are_unreachable() {
+        let (first, first_down, first_reads) = replica();
+        let (second, second_down, _) = replica();
+        let primary = Arc::new(InMemoryRepository::new());
+        let repository = ReplicatedRepository::new(primary, vec![first, second]);
+
+        // One replica down: reads still come from the other replica
+        first_down.store(true, Ordering::SeqCst);
+        assert_eq!(repository.list().await.unwrap().len(), 2);
+        assert_eq!(repository.list().await.unwrap().len(), 2);
+
+        // The failed replica is skipped instead of being retried on every read
+        assert_eq!(first_reads.load(Ordering::SeqCst), 1);
+
+        // Both replicas down: reads come from the empty primary
+        second_down.store(true, Ordering::SeqCst);
+        assert_eq!(repository.list().await.unwrap(), Vec::new());
+        assert_eq!(repository.get(&legacy_user_id(1).to_string()).await.unwrap(), None);
+    }
+}
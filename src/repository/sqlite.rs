@@ -0,0 +1,1695 @@
+// Import necessary libraries and modules
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::types::Json;
+use sqlx::{Encode, QueryBuilder, Sqlite, SqliteConnection, Transaction, Type};
+use std::collections::HashMap;
+use std::str::FromStr;
+use uuid::Uuid;
+
+use super::pool::TimedPool;
+use super::{
+    migrations, order_by_clause, ApiKeyRepository, DatabaseConfig, OrganizationRepository, PasswordRepository, PoolStats, PostRepository, RefreshTokenRepository, RepositoryError, RepositoryResult,
+    TwoFactorRepository, UserRepository, UserTransaction,
+};
+use crate::model::{
+    new_user_id, stored_metadata, Address, ApiKey, Comment, DomainCount, Membership, MembershipRole, NewApiKey, NewComment, NewPost, NewUser, Organization, Post, PostPage, RefreshToken, RoleCount, TwoFactor, User, UserFilter, UserOrder,
+    UserRole, UserSortField, UserStats, UserUpdate,
+};
+use crate::outbox::{ChangeEvent, ChangeKind};
+use crate::search::search_terms;
+
+// Define a SqliteRepository that stores users in a local SQLite database file
+#[derive(Clone)]
+pub struct SqliteRepository {
+    pool: TimedPool<Sqlite>,
+}
+
+// Define the row shape returned by user queries
+#[derive(sqlx::FromRow)]
+struct UserRow {
+    id: String,
+    name: String,
+    email: String,
+    version: i32,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    deleted_at: Option<DateTime<Utc>>,
+    avatar_key: Option<String>,
+    role: String,
+    metadata: Option<Json<serde_json::Value>>,
+    phone: Option<String>,
+    address: Option<Json<Address>>,
+    email_verified: bool,
+}
+
+impl From<UserRow> for User {
+    fn from(row: UserRow) -> Self {
+        User {
+            id: row.id,
+            name: row.name,
+            email: row.email,
+            version: row.version,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+            deleted_at: row.deleted_at,
+            avatar_key: row.avatar_key,
+            // The column is constrained to the known role names
+            role: UserRole::parse(&row.role).unwrap_or_default(),
+            metadata: stored_metadata(row.metadata.map(|metadata| metadata.0)),
+            phone: row.phone,
+            address: row.address.map(|address| address.0),
+            email_verified: row.email_verified,
+        }
+    }
+}
+
+// Define the row shape returned by post queries
+#[derive(sqlx::FromRow)]
+struct PostRow {
+    id: i64,
+    author_id: String,
+    title: String,
+    body: String,
+    created_at: DateTime<Utc>,
+}
+
+// Define the row shape returned by paged post queries: a post and the position of the page it belongs to
+#[derive(sqlx::FromRow)]
+struct PagedPostRow {
+    page_index: i64,
+    #[sqlx(flatten)]
+    post: PostRow,
+}
+
+impl From<PostRow> for Post {
+    fn from(row: PostRow) -> Self {
+        Post {
+            id: row.id.to_string(),
+            author_id: row.author_id,
+            title: row.title,
+            body: row.body,
+            created_at: row.created_at,
+        }
+    }
+}
+
+// Define the row shape returned by comment queries
+#[derive(sqlx::FromRow)]
+struct CommentRow {
+    id: i64,
+    post_id: i64,
+    parent_id: Option<i64>,
+    author_id: String,
+    body: String,
+    depth: i32,
+    created_at: DateTime<Utc>,
+}
+
+impl From<CommentRow> for Comment {
+    fn from(row: CommentRow) -> Self {
+        Comment {
+            id: row.id.to_string(),
+            post_id: row.post_id.to_string(),
+            parent_id: row.parent_id.map(|parent_id| parent_id.to_string()),
+            author_id: row.author_id,
+            body: row.body,
+            depth: row.depth,
+            created_at: row.created_at,
+        }
+    }
+}
+
+// Define the row shape returned by organization queries
+#[derive(sqlx::FromRow)]
+struct OrganizationRow {
+    id: i64,
+    name: String,
+    created_at: DateTime<Utc>,
+}
+
+impl From<OrganizationRow> for Organization {
+    fn from(row: OrganizationRow) -> Self {
+        Organization { id: row.id.to_string(), name: row.name, created_at: row.created_at }
+    }
+}
+
+// Define the row shape returned by API key queries
+#[derive(sqlx::FromRow)]
+struct ApiKeyRow {
+    id: i64,
+    name: String,
+    user_id: String,
+    key_hash: String,
+    created_at: DateTime<Utc>,
+}
+
+impl From<ApiKeyRow> for ApiKey {
+    fn from(row: ApiKeyRow) -> Self {
+        ApiKey { id: row.id.to_string(), name: row.name, user_id: row.user_id.to_string(), key_hash: row.key_hash, created_at: row.created_at }
+    }
+}
+
+// Define the row shape returned by refresh token queries
+#[derive(sqlx::FromRow)]
+struct RefreshTokenRow {
+    user_id: String,
+    token_hash: String,
+    expires_at: DateTime<Utc>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<RefreshTokenRow> for RefreshToken {
+    fn from(row: RefreshTokenRow) -> Self {
+        RefreshToken { user_id: row.user_id.to_string(), token_hash: row.token_hash, expires_at: row.expires_at, revoked_at: row.revoked_at }
+    }
+}
+
+// Define the row shape returned by membership queries; the role is stored by name
+#[derive(sqlx::FromRow)]
+struct MembershipRow {
+    organization_id: i64,
+    user_id: String,
+    role: String,
+    joined_at: DateTime<Utc>,
+}
+
+impl TryFrom<MembershipRow> for Membership {
+    type Error = RepositoryError;
+
+    fn try_from(row: MembershipRow) -> RepositoryResult<Self> {
+        Ok(Membership {
+            organization_id: row.organization_id.to_string(),
+            user_id: row.user_id,
+            role: MembershipRole::parse(&row.role).ok_or_else(|| RepositoryError::Backend(format!("unknown membership role {:?}", row.role)))?,
+            joined_at: row.joined_at,
+        })
+    }
+}
+
+// Group membership rows by the ID a key function picks out
+fn group_memberships(rows: Vec<MembershipRow>, key: fn(&Membership) -> &String) -> RepositoryResult<HashMap<String, Vec<Membership>>> {
+    let mut memberships: HashMap<String, Vec<Membership>> = HashMap::new();
+    for row in rows {
+        let membership = Membership::try_from(row)?;
+        memberships.entry(key(&membership).clone()).or_default().push(membership);
+    }
+    Ok(memberships)
+}
+
+// Define the row shape of outbox events; the payload is the user as JSON
+#[derive(sqlx::FromRow)]
+struct EventRow {
+    id: i64,
+    kind: String,
+    payload: String,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<EventRow> for ChangeEvent {
+    type Error = RepositoryError;
+
+    fn try_from(row: EventRow) -> RepositoryResult<Self> {
+        Ok(ChangeEvent {
+            id: row.id,
+            kind: ChangeKind::parse(&row.kind)?,
+            user: serde_json::from_str(&row.payload).map_err(|error| RepositoryError::Backend(error.to_string()))?,
+            created_at: row.created_at,
+        })
+    }
+}
+
+impl SqliteRepository {
+    // Open the database (creating the file if needed) and apply pending migrations
+    pub async fn connect(config: &DatabaseConfig) -> RepositoryResult<Self> {
+        let options = SqliteConnectOptions::from_str(&config.url)?.create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(config.max_connections)
+            .acquire_timeout(config.acquire_timeout)
+            .idle_timeout(config.idle_timeout)
+            .connect_with(options)
+            .await?;
+        let repository = SqliteRepository { pool: TimedPool::new(pool) };
+        repository.migrate().await?;
+        Ok(repository)
+    }
+
+    // List users in ID order, skipping soft-deleted users unless asked to include them
+    async fn list_users(&self, include_deleted: bool) -> RepositoryResult<Vec<User>> {
+        let rows = sqlx::query_as::<_, UserRow>(
+            "SELECT id, name, email, version, created_at, updated_at, deleted_at, avatar_key, role, metadata, phone, address, email_verified FROM users WHERE ?1 OR deleted_at IS NULL ORDER BY id",
+        )
+        .bind(include_deleted)
+        .fetch_all(&mut *self.pool.acquire().await?)
+        .await?;
+        Ok(rows.into_iter().map(User::from).collect())
+    }
+
+    // Apply any migrations that have not been run yet
+    pub async fn migrate(&self) -> RepositoryResult<()> {
+        migrations::SQLITE.run(self.pool.inner()).await?;
+        Ok(())
+    }
+}
+
+// Name the column a sort field orders by
+fn order_column(field: UserSortField) -> &'static str {
+    match field {
+        UserSortField::Name => "name",
+        UserSortField::Email => "email",
+        UserSortField::CreatedAt => "julianday(created_at)",
+    }
+}
+
+// Parse an ID argument; IDs that are not numbers cannot match any row
+fn parse_id(id: &str) -> Option<i64> {
+    id.parse().ok()
+}
+
+// Parse a user ID argument into the canonical text form user IDs are stored in; user IDs that are not UUIDs cannot match any row
+fn parse_user_id(id: &str) -> Option<String> {
+    Uuid::parse_str(id).ok().map(|uuid| uuid.to_string())
+}
+
+// Record a change event for a user in the outbox, on the same connection (and transaction) as the change
+async fn record_event(connection: &mut SqliteConnection, kind: ChangeKind, user: &User) -> RepositoryResult<()> {
+    let payload = serde_json::to_string(user).map_err(|error| RepositoryError::Backend(error.to_string()))?;
+    sqlx::query("INSERT INTO outbox (kind, payload, created_at) VALUES (?1, ?2, ?3)")
+        .bind(kind.as_str())
+        .bind(payload)
+        .bind(Utc::now())
+        .execute(connection)
+        .await?;
+    Ok(())
+}
+
+// Record a change event when the change found a user
+async fn record_change(connection: &mut SqliteConnection, kind: ChangeKind, user: Option<User>) -> RepositoryResult<Option<User>> {
+    if let Some(user) = &user {
+        record_event(connection, kind, user).await?;
+    }
+    Ok(user)
+}
+
+// Insert one user, using the requested ID when there is one and generating one otherwise
+async fn insert_user(connection: &mut SqliteConnection, new_user: NewUser) -> RepositoryResult<User> {
+    let id = match new_user.id {
+        Some(id) => parse_user_id(&id).ok_or(RepositoryError::InvalidId(id))?,
+        None => new_user_id().to_string(),
+    };
+    let row = sqlx::query_as::<_, UserRow>(
+        "INSERT INTO users (id, name, email, created_at, updated_at, role, phone, address, email_verified) VALUES (?1, ?2, ?3, ?4, ?4, ?5, ?6, ?7, ?8)
+         RETURNING id, name, email, version, created_at, updated_at, deleted_at, avatar_key, role, metadata, phone, address, email_verified",
+    )
+    .bind(id)
+    .bind(new_user.name)
+    .bind(new_user.email)
+    .bind(Utc::now())
+    .bind(new_user.role.as_str())
+    .bind(new_user.phone)
+    .bind(new_user.address.map(Json))
+    .bind(new_user.email_verified)
+    .fetch_one(&mut *connection)
+    .await?;
+    let user = row.into();
+    record_event(connection, ChangeKind::Created, &user).await?;
+    Ok(user)
+}
+
+// Fetch one user by ID, skipping soft-deleted users unless asked to include them
+async fn select_user(connection: &mut SqliteConnection, id: &str, include_deleted: bool) -> RepositoryResult<Option<User>> {
+    let Some(id) = parse_user_id(id) else {
+        return Ok(None);
+    };
+    let row = sqlx::query_as::<_, UserRow>(
+        "SELECT id, name, email, version, created_at, updated_at, deleted_at, avatar_key, role, metadata, phone, address, email_verified FROM users WHERE id = ?1 AND (?2 OR deleted_at IS NULL)",
+    )
+    .bind(id)
+    .bind(include_deleted)
+    .fetch_optional(connection)
+    .await?;
+    Ok(row.map(User::from))
+}
+
+// Apply a partial update to one user, bump its version, and stamp the update time, checking the expected version if one is given
+async fn update_user(connection: &mut SqliteConnection, id: &str, update: UserUpdate) -> RepositoryResult<Option<User>> {
+    let Some(number) = parse_user_id(id) else {
+        return Ok(None);
+    };
+    let expected_version = update.expected_version;
+    let row = sqlx::query_as::<_, UserRow>(
+        "UPDATE users SET name = COALESCE(?2, name), email = COALESCE(?3, email),
+                avatar_key = COALESCE(?5, avatar_key), role = COALESCE(?7, role), metadata = COALESCE(?8, metadata),
+                phone = CASE WHEN ?9 THEN ?10 ELSE phone END,
+                address = CASE WHEN ?11 THEN ?12 ELSE address END, email_verified = COALESCE(?13, email_verified),
+                version = version + 1, updated_at = ?6
+         WHERE id = ?1 AND deleted_at IS NULL AND (?4 IS NULL OR version = ?4)
+         RETURNING id, name, email, version, created_at, updated_at, deleted_at, avatar_key, role, metadata, phone, address, email_verified",
+    )
+    .bind(number)
+    .bind(update.name)
+    .bind(update.email)
+    .bind(expected_version)
+    .bind(update.avatar_key)
+    .bind(Utc::now())
+    .bind(update.role.map(UserRole::as_str))
+    .bind(update.metadata.map(Json))
+    .bind(update.phone.is_some())
+    .bind(update.phone.flatten())
+    .bind(update.address.is_some())
+    .bind(update.address.flatten().map(Json))
+    .bind(update.email_verified)
+    .fetch_optional(&mut *connection)
+    .await?;
+    if let Some(row) = row {
+        return record_change(connection, ChangeKind::Updated, Some(row.into())).await;
+    }
+
+    // Nothing matched, so the user is either missing or has moved past the expected version
+    match (expected_version, select_user(connection, id, false).await?) {
+        (Some(expected), Some(user)) => Err(RepositoryError::VersionConflict { id: user.id, expected, actual: user.version }),
+        _ => Ok(None),
+    }
+}
+
+// Soft-delete one user that has not been deleted yet
+async fn delete_user(connection: &mut SqliteConnection, id: &str) -> RepositoryResult<Option<User>> {
+    let Some(id) = parse_user_id(id) else {
+        return Ok(None);
+    };
+    let row = sqlx::query_as::<_, UserRow>(
+        "UPDATE users SET deleted_at = ?2 WHERE id = ?1 AND deleted_at IS NULL
+         RETURNING id, name, email, version, created_at, updated_at, deleted_at, avatar_key, role, metadata, phone, address, email_verified",
+    )
+    .bind(id)
+    .bind(Utc::now())
+    .fetch_optional(&mut *connection)
+    .await?;
+    record_change(connection, ChangeKind::Deleted, row.map(User::from)).await
+}
+
+// Clear the deletion time on one user
+async fn restore_user(connection: &mut SqliteConnection, id: &str) -> RepositoryResult<Option<User>> {
+    let Some(id) = parse_user_id(id) else {
+        return Ok(None);
+    };
+    let row = sqlx::query_as::<_, UserRow>(
+        "UPDATE users SET deleted_at = NULL WHERE id = ?1 RETURNING id, name, email, version, created_at, updated_at, deleted_at, avatar_key, role, metadata, phone, address, email_verified",
+    )
+    .bind(id)
+    .fetch_optional(&mut *connection)
+    .await?;
+    record_change(connection, ChangeKind::Restored, row.map(User::from)).await
+}
+
+// Remove one user for good
+async fn purge_user(connection: &mut SqliteConnection, id: &str) -> RepositoryResult<Option<User>> {
+    let Some(id) = parse_user_id(id) else {
+        return Ok(None);
+    };
+    let row = sqlx::query_as::<_, UserRow>("DELETE FROM users WHERE id = ?1 RETURNING id, name, email, version, created_at, updated_at, deleted_at, avatar_key, role, metadata, phone, address, email_verified")
+        .bind(id)
+        .fetch_optional(&mut *connection)
+        .await?;
+    record_change(connection, ChangeKind::Purged, row.map(User::from)).await
+}
+
+// Implement the repository operations as SQL queries
+#[async_trait]
+impl UserRepository for SqliteRepository {
+    async fn get(&self, id: &str) -> RepositoryResult<Option<User>> {
+        select_user(&mut *self.pool.acquire().await?, id, false).await
+    }
+
+    async fn get_including_deleted(&self, id: &str) -> RepositoryResult<Option<User>> {
+        select_user(&mut *self.pool.acquire().await?, id, true).await
+    }
+
+    // Served by the index on lower(email)
+    async fn get_by_email(&self, email: &str) -> RepositoryResult<Option<User>> {
+        let row = sqlx::query_as::<_, UserRow>(
+            "SELECT id, name, email, version, created_at, updated_at, deleted_at, avatar_key, role, metadata, phone, address, email_verified FROM users
+             WHERE lower(email) = lower(?1) AND deleted_at IS NULL ORDER BY id LIMIT 1",
+        )
+        .bind(email)
+        .fetch_optional(&mut *self.pool.acquire().await?)
+        .await?;
+        Ok(row.map(User::from))
+    }
+
+    async fn get_many(&self, ids: &[String]) -> RepositoryResult<HashMap<String, User>> {
+        let ids: Vec<String> = ids.iter().filter_map(|id| parse_user_id(id)).collect();
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT id, name, email, version, created_at, updated_at, deleted_at, avatar_key, role, metadata, phone, address, email_verified FROM users WHERE deleted_at IS NULL AND id IN (",
+        );
+        let mut separated = query.separated(", ");
+        for id in ids {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        let rows = query.build_query_as::<UserRow>().fetch_all(&mut *self.pool.acquire().await?).await?;
+        Ok(rows.into_iter().map(|row| (row.id.clone(), row.into())).collect())
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<User>> {
+        self.list_users(false).await
+    }
+
+    async fn list_including_deleted(&self) -> RepositoryResult<Vec<User>> {
+        self.list_users(true).await
+    }
+
+    // Timestamps are stored as text, so they are compared as Julian days
+    async fn list_page(&self, filter: &UserFilter, after: Option<&str>, limit: usize) -> RepositoryResult<Vec<User>> {
+        let after = match after {
+            Some(after) => match parse_user_id(after) {
+                Some(after) => after,
+                None => return Ok(Vec::new()),
+            },
+            None => String::new(),
+        };
+        let rows = sqlx::query_as::<_, UserRow>(
+            "SELECT id, name, email, version, created_at, updated_at, deleted_at, avatar_key, role, metadata, phone, address, email_verified FROM users
+             WHERE id > ?1 AND (?2 OR deleted_at IS NULL)
+               AND (?3 IS NULL OR julianday(created_at) >= julianday(?3))
+               AND (?4 IS NULL OR julianday(created_at) < julianday(?4))
+               AND (?6 IS NULL OR instr(lower(name), lower(?6)) > 0)
+               AND (?7 IS NULL OR lower(substr(email, instr(email, '@') + 1)) = lower(?7))
+               AND (?8 IS NULL OR role = ?8)
+             ORDER BY id LIMIT ?5",
+        )
+        .bind(after)
+        .bind(filter.include_deleted)
+        .bind(filter.created_after)
+        .bind(filter.created_before)
+        .bind(limit as i64)
+        .bind(filter.name_contains.as_deref())
+        .bind(filter.email_domain.as_deref())
+        .bind(filter.role.map(UserRole::as_str))
+        .fetch_all(&mut *self.pool.acquire().await?)
+        .await?;
+        Ok(rows.into_iter().map(User::from).collect())
+    }
+
+    async fn list_range(&self, filter: &UserFilter, order: &[UserOrder], offset: usize, limit: usize) -> RepositoryResult<Vec<User>> {
+        let sql = format!(
+            "SELECT id, name, email, version, created_at, updated_at, deleted_at, avatar_key, role, metadata, phone, address, email_verified FROM users
+             WHERE (?1 OR deleted_at IS NULL)
+               AND (?2 IS NULL OR julianday(created_at) >= julianday(?2))
+               AND (?3 IS NULL OR julianday(created_at) < julianday(?3))
+               AND (?6 IS NULL OR instr(lower(name), lower(?6)) > 0)
+               AND (?7 IS NULL OR lower(substr(email, instr(email, '@') + 1)) = lower(?7))
+               AND (?8 IS NULL OR role = ?8)
+             ORDER BY {} LIMIT ?4 OFFSET ?5",
+            order_by_clause(order, order_column),
+        );
+        let rows = sqlx::query_as::<_, UserRow>(&sql)
+            .bind(filter.include_deleted)
+            .bind(filter.created_after)
+            .bind(filter.created_before)
+            .bind(limit as i64)
+            .bind(offset as i64)
+            .bind(filter.name_contains.as_deref())
+            .bind(filter.email_domain.as_deref())
+            .bind(filter.role.map(UserRole::as_str))
+            .fetch_all(&mut *self.pool.acquire().await?)
+            .await?;
+        Ok(rows.into_iter().map(User::from).collect())
+    }
+
+    async fn count(&self, filter: &UserFilter) -> RepositoryResult<usize> {
+        let count: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM users
+             WHERE (?1 OR deleted_at IS NULL)
+               AND (?2 IS NULL OR julianday(created_at) >= julianday(?2))
+               AND (?3 IS NULL OR julianday(created_at) < julianday(?3))
+               AND (?4 IS NULL OR instr(lower(name), lower(?4)) > 0)
+               AND (?5 IS NULL OR lower(substr(email, instr(email, '@') + 1)) = lower(?5))
+               AND (?6 IS NULL OR role = ?6)",
+        )
+        .bind(filter.include_deleted)
+        .bind(filter.created_after)
+        .bind(filter.created_before)
+        .bind(filter.name_contains.as_deref())
+        .bind(filter.email_domain.as_deref())
+        .bind(filter.role.map(UserRole::as_str))
+        .fetch_one(&mut *self.pool.acquire().await?)
+        .await?;
+        Ok(count as usize)
+    }
+
+    // Count and group the live users in the database, so no user rows are sent back
+    async fn stats(&self, created_since: DateTime<Utc>) -> RepositoryResult<UserStats> {
+        let mut connection = self.pool.acquire().await?;
+        let (user_count, users_created_today): (i64, i64) = sqlx::query_as(
+            "SELECT COUNT(*), COUNT(CASE WHEN julianday(created_at) >= julianday(?1) THEN 1 END) FROM users WHERE deleted_at IS NULL",
+        )
+        .bind(created_since)
+        .fetch_one(&mut *connection)
+        .await?;
+        let roles: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT role, COUNT(*) FROM users WHERE deleted_at IS NULL GROUP BY role ORDER BY COUNT(*) DESC, role",
+        )
+        .fetch_all(&mut *connection)
+        .await?;
+        let domains: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT lower(substr(email, instr(email, '@') + 1)) AS domain, COUNT(*) FROM users
+             WHERE deleted_at IS NULL GROUP BY domain ORDER BY COUNT(*) DESC, domain",
+        )
+        .fetch_all(&mut *connection)
+        .await?;
+        Ok(UserStats {
+            user_count: user_count as usize,
+            users_created_today: users_created_today as usize,
+            // The column is constrained to the known role names
+            by_role: roles.into_iter().map(|(role, count)| RoleCount { role: UserRole::parse(&role).unwrap_or_default(), count: count as usize }).collect(),
+            by_domain: domains.into_iter().map(|(domain, count)| DomainCount { domain, count: count as usize }).collect(),
+        })
+    }
+
+    async fn create(&self, new_user: NewUser) -> RepositoryResult<User> {
+        let mut users = self.create_many(vec![new_user]).await?;
+        Ok(users.remove(0))
+    }
+
+    async fn create_many(&self, new_users: Vec<NewUser>) -> RepositoryResult<Vec<User>> {
+        let mut transaction = self.pool.begin().await?;
+        let mut users = Vec::with_capacity(new_users.len());
+        for new_user in new_users {
+            users.push(insert_user(&mut transaction, new_user).await?);
+        }
+        transaction.commit().await?;
+        Ok(users)
+    }
+
+    // Each write runs in its own transaction so the change and its outbox event commit together
+    async fn update(&self, id: &str, update: UserUpdate) -> RepositoryResult<Option<User>> {
+        let mut transaction = self.pool.begin().await?;
+        let user = update_user(&mut transaction, id, update).await?;
+        transaction.commit().await?;
+        Ok(user)
+    }
+
+    async fn delete(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let mut transaction = self.pool.begin().await?;
+        let user = delete_user(&mut transaction, id).await?;
+        transaction.commit().await?;
+        Ok(user)
+    }
+
+    async fn restore(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let mut transaction = self.pool.begin().await?;
+        let user = restore_user(&mut transaction, id).await?;
+        transaction.commit().await?;
+        Ok(user)
+    }
+
+    async fn purge(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let mut transaction = self.pool.begin().await?;
+        let user = purge_user(&mut transaction, id).await?;
+        transaction.commit().await?;
+        Ok(user)
+    }
+
+    async fn ping(&self) -> RepositoryResult<()> {
+        sqlx::query("SELECT 1").execute(&mut *self.pool.acquire().await?).await?;
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(self.pool.stats())
+    }
+
+    // Wait for checked-out connections to be returned, then close them all
+    async fn close(&self) {
+        self.pool.inner().close().await;
+    }
+
+    async fn pending_migrations(&self) -> RepositoryResult<usize> {
+        let applied: Vec<i64> = sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success").fetch_all(&mut *self.pool.acquire().await?).await?;
+        Ok(migrations::count_pending(&migrations::SQLITE, &applied))
+    }
+
+    fn posts(&self) -> Option<&dyn PostRepository> {
+        Some(self)
+    }
+
+    fn organizations(&self) -> Option<&dyn OrganizationRepository> {
+        Some(self)
+    }
+
+    fn api_keys(&self) -> Option<&dyn ApiKeyRepository> {
+        Some(self)
+    }
+
+    fn passwords(&self) -> Option<&dyn PasswordRepository> {
+        Some(self)
+    }
+
+    fn refresh_tokens(&self) -> Option<&dyn RefreshTokenRepository> {
+        Some(self)
+    }
+
+    fn two_factor(&self) -> Option<&dyn TwoFactorRepository> {
+        Some(self)
+    }
+
+    async fn begin(&self) -> RepositoryResult<Box<dyn UserTransaction>> {
+        Ok(Box::new(SqliteTransaction {
+            transaction: self.pool.begin().await?,
+        }))
+    }
+
+    async fn pending_events(&self, limit: usize) -> RepositoryResult<Vec<ChangeEvent>> {
+        let rows = sqlx::query_as::<_, EventRow>("SELECT id, kind, payload, created_at FROM outbox ORDER BY id LIMIT ?1")
+            .bind(limit as i64)
+            .fetch_all(&mut *self.pool.acquire().await?)
+            .await?;
+        rows.into_iter().map(ChangeEvent::try_from).collect()
+    }
+
+    async fn acknowledge_events(&self, ids: &[i64]) -> RepositoryResult<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut query = QueryBuilder::<Sqlite>::new("DELETE FROM outbox WHERE id IN (");
+        let mut separated = query.separated(", ");
+        for id in ids {
+            separated.push_bind(*id);
+        }
+        separated.push_unseparated(")");
+        query.build().execute(&mut *self.pool.acquire().await?).await?;
+        Ok(())
+    }
+}
+
+// Implement the post operations as SQL queries over the posts table
+#[async_trait]
+impl PostRepository for SqliteRepository {
+    async fn create_post(&self, new_post: NewPost) -> RepositoryResult<Post> {
+        let author_id = parse_user_id(&new_post.author_id).ok_or(RepositoryError::InvalidId(new_post.author_id))?;
+        let row = sqlx::query_as::<_, PostRow>(
+            "INSERT INTO posts (author_id, title, body, created_at) VALUES (?1, ?2, ?3, ?4)
+             RETURNING id, author_id, title, body, created_at",
+        )
+        .bind(author_id)
+        .bind(new_post.title)
+        .bind(new_post.body)
+        .bind(Utc::now())
+        .fetch_one(&mut *self.pool.acquire().await?)
+        .await?;
+        Ok(row.into())
+    }
+
+    async fn get_post(&self, id: &str) -> RepositoryResult<Option<Post>> {
+        let Some(id) = parse_id(id) else {
+            return Ok(None);
+        };
+        let row = sqlx::query_as::<_, PostRow>("SELECT id, author_id, title, body, created_at FROM posts WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&mut *self.pool.acquire().await?)
+            .await?;
+        Ok(row.map(Post::from))
+    }
+
+    // Every term must appear somewhere in the title or body; SQLite's lower() only folds ASCII letters
+    async fn search_posts(&self, query: &str, limit: usize) -> RepositoryResult<Vec<Post>> {
+        let terms = search_terms(query);
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT id, author_id, title, body, created_at FROM posts WHERE 1 = 1");
+        for term in terms {
+            query.push(" AND instr(lower(title || ' ' || body), ").push_bind(term).push(") > 0");
+        }
+        query.push(" ORDER BY id LIMIT ").push_bind(limit as i64);
+
+        let rows = query.build_query_as::<PostRow>().fetch_all(&mut *self.pool.acquire().await?).await?;
+        Ok(rows.into_iter().map(Post::from).collect())
+    }
+
+    async fn posts_by_authors(&self, author_ids: &[String]) -> RepositoryResult<HashMap<String, Vec<Post>>> {
+        let author_ids: Vec<String> = author_ids.iter().filter_map(|id| parse_user_id(id)).collect();
+        if author_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT id, author_id, title, body, created_at FROM posts WHERE author_id IN (");
+        let mut separated = query.separated(", ");
+        for author_id in author_ids {
+            separated.push_bind(author_id);
+        }
+        separated.push_unseparated(") ORDER BY id");
+
+        let rows = query.build_query_as::<PostRow>().fetch_all(&mut *self.pool.acquire().await?).await?;
+        let mut posts: HashMap<String, Vec<Post>> = HashMap::new();
+        for post in rows.into_iter().map(Post::from) {
+            posts.entry(post.author_id.clone()).or_default().push(post);
+        }
+        Ok(posts)
+    }
+
+    // Read every page in one query: the pages are joined to their authors' posts after each cursor and
+    // numbered per page, so only up to each page's limit of posts is returned
+    async fn posts_by_author_pages(&self, pages: &[PostPage]) -> RepositoryResult<HashMap<PostPage, Vec<Post>>> {
+        // Pages for malformed author IDs or cursors cannot match any post
+        let pages: Vec<(&PostPage, String, i64)> = pages
+            .iter()
+            .filter_map(|page| {
+                let after = match &page.after {
+                    Some(after) => parse_id(after)?,
+                    None => 0,
+                };
+                Some((page, parse_user_id(&page.author_id)?, after))
+            })
+            .collect();
+        if pages.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut query = QueryBuilder::<Sqlite>::new("WITH page (page_index, author_id, after_id, page_limit) AS (");
+        query.push_values(pages.iter().enumerate(), |mut row, (index, (page, author_id, after))| {
+            row.push_bind(index as i64).push_bind(author_id.clone()).push_bind(*after).push_bind(page.limit as i64);
+        });
+        query.push(
+            ") SELECT page_index, id, author_id, title, body, created_at FROM (
+                 SELECT page.page_index, page.page_limit, posts.id, posts.author_id, posts.title, posts.body, posts.created_at,
+                        ROW_NUMBER() OVER (PARTITION BY page.page_index ORDER BY posts.id) AS position
+                 FROM page JOIN posts ON posts.author_id = page.author_id AND posts.id > page.after_id
+             )
+             WHERE position <= page_limit
+             ORDER BY page_index, id",
+        );
+
+        let rows = query.build_query_as::<PagedPostRow>().fetch_all(&mut *self.pool.acquire().await?).await?;
+        let mut posts: HashMap<PostPage, Vec<Post>> = HashMap::new();
+        for row in rows {
+            let (page, _, _) = pages[row.page_index as usize];
+            posts.entry(page.clone()).or_default().push(Post::from(row.post));
+        }
+        Ok(posts)
+    }
+
+    async fn create_comment(&self, new_comment: NewComment) -> RepositoryResult<Comment> {
+        let post_id = parse_id(&new_comment.post_id).ok_or(RepositoryError::InvalidId(new_comment.post_id))?;
+        let author_id = parse_user_id(&new_comment.author_id).ok_or(RepositoryError::InvalidId(new_comment.author_id))?;
+        let parent_id = match new_comment.parent_id {
+            Some(parent_id) => Some(parse_id(&parent_id).ok_or(RepositoryError::InvalidId(parent_id))?),
+            None => None,
+        };
+        let row = sqlx::query_as::<_, CommentRow>(
+            "INSERT INTO comments (post_id, parent_id, author_id, body, depth, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             RETURNING id, post_id, parent_id, author_id, body, depth, created_at",
+        )
+        .bind(post_id)
+        .bind(parent_id)
+        .bind(author_id)
+        .bind(new_comment.body)
+        .bind(new_comment.depth)
+        .bind(Utc::now())
+        .fetch_one(&mut *self.pool.acquire().await?)
+        .await?;
+        Ok(row.into())
+    }
+
+    async fn get_comment(&self, id: &str) -> RepositoryResult<Option<Comment>> {
+        let Some(id) = parse_id(id) else {
+            return Ok(None);
+        };
+        let row = sqlx::query_as::<_, CommentRow>(
+            "SELECT id, post_id, parent_id, author_id, body, depth, created_at FROM comments WHERE id = ?1",
+        )
+        .bind(id)
+        .fetch_optional(&mut *self.pool.acquire().await?)
+        .await?;
+        Ok(row.map(Comment::from))
+    }
+
+    // IS matches NULL parents too, so top-level comments and replies share one query
+    async fn list_comments(
+        &self,
+        post_id: &str,
+        parent_id: Option<&str>,
+        after: Option<&str>,
+        limit: usize,
+    ) -> RepositoryResult<Vec<Comment>> {
+        let (Some(post_id), Some(parent_id), Some(after)) = (
+            parse_id(post_id),
+            parent_id.map_or(Some(None), |parent_id| parse_id(parent_id).map(Some)),
+            after.map_or(Some(0), parse_id),
+        ) else {
+            return Ok(Vec::new());
+        };
+        let rows = sqlx::query_as::<_, CommentRow>(
+            "SELECT id, post_id, parent_id, author_id, body, depth, created_at FROM comments
+             WHERE post_id = ?1 AND parent_id IS ?2 AND id > ?3
+             ORDER BY id LIMIT ?4",
+        )
+        .bind(post_id)
+        .bind(parent_id)
+        .bind(after)
+        .bind(limit as i64)
+        .fetch_all(&mut *self.pool.acquire().await?)
+        .await?;
+        Ok(rows.into_iter().map(Comment::from).collect())
+    }
+}
+
+// Implement the API key operations as SQL queries over the api_keys table
+#[async_trait]
+impl ApiKeyRepository for SqliteRepository {
+    async fn create_api_key(&self, new_api_key: NewApiKey) -> RepositoryResult<ApiKey> {
+        let user_id = parse_user_id(&new_api_key.user_id).ok_or_else(|| RepositoryError::InvalidId(new_api_key.user_id.clone()))?;
+        let row = sqlx::query_as::<_, ApiKeyRow>(
+            "INSERT INTO api_keys (name, user_id, key_hash, created_at) VALUES (?1, ?2, ?3, ?4) RETURNING id, name, user_id, key_hash, created_at",
+        )
+        .bind(new_api_key.name)
+        .bind(user_id)
+        .bind(new_api_key.key_hash)
+        .bind(Utc::now())
+        .fetch_one(&mut *self.pool.acquire().await?)
+        .await?;
+        Ok(row.into())
+    }
+
+    // Served by the unique index on key_hash
+    async fn get_api_key_by_hash(&self, key_hash: &str) -> RepositoryResult<Option<ApiKey>> {
+        let row = sqlx::query_as::<_, ApiKeyRow>("SELECT id, name, user_id, key_hash, created_at FROM api_keys WHERE key_hash = ?1")
+            .bind(key_hash)
+            .fetch_optional(&mut *self.pool.acquire().await?)
+            .await?;
+        Ok(row.map(ApiKey::from))
+    }
+}
+
+// Implement the password operations as SQL queries over the passwords table
+#[async_trait]
+impl PasswordRepository for SqliteRepository {
+    async fn set_password_hash(&self, user_id: &str, password_hash: &str) -> RepositoryResult<()> {
+        let user_id = parse_user_id(user_id).ok_or_else(|| RepositoryError::InvalidId(user_id.to_string()))?;
+        sqlx::query(
+            "INSERT INTO passwords (user_id, password_hash, updated_at) VALUES (?1, ?2, ?3) \
+             ON CONFLICT (user_id) DO UPDATE SET password_hash = excluded.password_hash, updated_at = excluded.updated_at",
+        )
+        .bind(user_id)
+        .bind(password_hash)
+        .bind(Utc::now())
+        .execute(&mut *self.pool.acquire().await?)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_password_hash(&self, user_id: &str) -> RepositoryResult<Option<String>> {
+        let Some(user_id) = parse_user_id(user_id) else {
+            return Ok(None);
+        };
+        let hash = sqlx::query_scalar::<_, String>("SELECT password_hash FROM passwords WHERE user_id = ?1")
+            .bind(user_id)
+            .fetch_optional(&mut *self.pool.acquire().await?)
+            .await?;
+        Ok(hash)
+    }
+}
+
+// Implement the refresh token operations as SQL queries over the refresh_tokens table
+#[async_trait]
+impl RefreshTokenRepository for SqliteRepository {
+    async fn create_refresh_token(&self, user_id: &str, token_hash: &str, expires_at: DateTime<Utc>) -> RepositoryResult<()> {
+        let user_id = parse_user_id(user_id).ok_or_else(|| RepositoryError::InvalidId(user_id.to_string()))?;
+        sqlx::query("INSERT INTO refresh_tokens (token_hash, user_id, expires_at) VALUES (?1, ?2, ?3)")
+            .bind(token_hash)
+            .bind(user_id)
+            .bind(expires_at)
+            .execute(&mut *self.pool.acquire().await?)
+            .await?;
+        Ok(())
+    }
+
+    async fn revoke_refresh_token(&self, token_hash: &str) -> RepositoryResult<Option<RefreshToken>> {
+        // The conditional update lets exactly one caller revoke a live token; everyone else reads it as revoked
+        let mut connection = self.pool.acquire().await?;
+        let revoked = sqlx::query_as::<_, RefreshTokenRow>(
+            "UPDATE refresh_tokens SET revoked_at = ?2 WHERE token_hash = ?1 AND revoked_at IS NULL \
+             RETURNING user_id, token_hash, expires_at, NULL AS revoked_at",
+        )
+        .bind(token_hash)
+        .bind(Utc::now())
+        .fetch_optional(&mut *connection)
+        .await?;
+        if let Some(row) = revoked {
+            return Ok(Some(row.into()));
+        }
+        let row = sqlx::query_as::<_, RefreshTokenRow>("SELECT user_id, token_hash, expires_at, revoked_at FROM refresh_tokens WHERE token_hash = ?1")
+            .bind(token_hash)
+            .fetch_optional(&mut *connection)
+            .await?;
+        Ok(row.map(RefreshToken::from))
+    }
+
+    async fn revoke_refresh_tokens(&self, user_id: &str) -> RepositoryResult<u64> {
+        let Some(user_id) = parse_user_id(user_id) else {
+            return Ok(0);
+        };
+        let result = sqlx::query("UPDATE refresh_tokens SET revoked_at = ?2 WHERE user_id = ?1 AND revoked_at IS NULL")
+            .bind(user_id)
+            .bind(Utc::now())
+            .execute(&mut *self.pool.acquire().await?)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+// Implement the two-factor operations as SQL queries over the two_factor and recovery_codes tables
+#[async_trait]
+impl TwoFactorRepository for SqliteRepository {
+    async fn set_two_factor(&self, user_id: &str, secret: &str, recovery_code_hashes: &[String]) -> RepositoryResult<()> {
+        let user_id = parse_user_id(user_id).ok_or_else(|| RepositoryError::InvalidId(user_id.to_string()))?;
+        // Removing the earlier enrollment removes its recovery codes with it
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query("DELETE FROM two_factor WHERE user_id = ?1").bind(&user_id).execute(&mut *transaction).await?;
+        sqlx::query("INSERT INTO two_factor (user_id, secret, created_at) VALUES (?1, ?2, ?3)")
+            .bind(&user_id)
+            .bind(secret)
+            .bind(Utc::now())
+            .execute(&mut *transaction)
+            .await?;
+        for code_hash in recovery_code_hashes {
+            sqlx::query("INSERT INTO recovery_codes (user_id, code_hash) VALUES (?1, ?2)").bind(&user_id).bind(code_hash).execute(&mut *transaction).await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn get_two_factor(&self, user_id: &str) -> RepositoryResult<Option<TwoFactor>> {
+        let Some(user_id) = parse_user_id(user_id) else {
+            return Ok(None);
+        };
+        let mut connection = self.pool.acquire().await?;
+        let row = sqlx::query_as::<_, (String, bool)>("SELECT secret, confirmed_at IS NOT NULL FROM two_factor WHERE user_id = ?1")
+            .bind(&user_id)
+            .fetch_optional(&mut *connection)
+            .await?;
+        let Some((secret, confirmed)) = row else {
+            return Ok(None);
+        };
+        let recovery_code_hashes = sqlx::query_scalar::<_, String>("SELECT code_hash FROM recovery_codes WHERE user_id = ?1")
+            .bind(&user_id)
+            .fetch_all(&mut *connection)
+            .await?;
+        Ok(Some(TwoFactor { secret, confirmed, recovery_code_hashes }))
+    }
+
+    async fn confirm_two_factor(&self, user_id: &str) -> RepositoryResult<bool> {
+        let Some(user_id) = parse_user_id(user_id) else {
+            return Ok(false);
+        };
+        let result = sqlx::query("UPDATE two_factor SET confirmed_at = COALESCE(confirmed_at, ?2) WHERE user_id = ?1")
+            .bind(user_id)
+            .bind(Utc::now())
+            .execute(&mut *self.pool.acquire().await?)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn use_recovery_code(&self, user_id: &str, code_hash: &str) -> RepositoryResult<bool> {
+        let Some(user_id) = parse_user_id(user_id) else {
+            return Ok(false);
+        };
+        let result = sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?1 AND code_hash = ?2")
+            .bind(user_id)
+            .bind(code_hash)
+            .execute(&mut *self.pool.acquire().await?)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+// Implement the organization operations as SQL queries over the organizations and memberships tables
+#[async_trait]
+impl OrganizationRepository for SqliteRepository {
+    async fn create_organization(&self, name: String) -> RepositoryResult<Organization> {
+        let row = sqlx::query_as::<_, OrganizationRow>(
+            "INSERT INTO organizations (name, created_at) VALUES (?1, ?2) RETURNING id, name, created_at",
+        )
+        .bind(name)
+        .bind(Utc::now())
+        .fetch_one(&mut *self.pool.acquire().await?)
+        .await?;
+        Ok(row.into())
+    }
+
+    async fn get_organizations(&self, ids: &[String]) -> RepositoryResult<HashMap<String, Organization>> {
+        let ids: Vec<i64> = ids.iter().filter_map(|id| parse_id(id)).collect();
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT id, name, created_at FROM organizations WHERE id IN (");
+        let mut separated = query.separated(", ");
+        for id in ids {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        let rows = query.build_query_as::<OrganizationRow>().fetch_all(&mut *self.pool.acquire().await?).await?;
+        Ok(rows.into_iter().map(Organization::from).map(|organization| (organization.id.clone(), organization)).collect())
+    }
+
+    async fn list_organizations(&self) -> RepositoryResult<Vec<Organization>> {
+        let rows = sqlx::query_as::<_, OrganizationRow>("SELECT id, name, created_at FROM organizations ORDER BY id")
+            .fetch_all(&mut *self.pool.acquire().await?)
+            .await?;
+        Ok(rows.into_iter().map(Organization::from).collect())
+    }
+
+    // Adding an existing member keeps their join time and only changes the role
+    async fn add_member(&self, organization_id: &str, user_id: &str, role: MembershipRole) -> RepositoryResult<Membership> {
+        let organization_id = parse_id(organization_id).ok_or_else(|| RepositoryError::InvalidId(organization_id.to_string()))?;
+        let user_id = parse_user_id(user_id).ok_or_else(|| RepositoryError::InvalidId(user_id.to_string()))?;
+        let row = sqlx::query_as::<_, MembershipRow>(
+            "INSERT INTO memberships (organization_id, user_id, role, joined_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (organization_id, user_id) DO UPDATE SET role = excluded.role
+             RETURNING organization_id, user_id, role, joined_at",
+        )
+        .bind(organization_id)
+        .bind(user_id)
+        .bind(role.as_str())
+        .bind(Utc::now())
+        .fetch_one(&mut *self.pool.acquire().await?)
+        .await?;
+        row.try_into()
+    }
+
+    async fn remove_member(&self, organization_id: &str, user_id: &str) -> RepositoryResult<Option<Membership>> {
+        let (Some(organization_id), Some(user_id)) = (parse_id(organization_id), parse_user_id(user_id)) else {
+            return Ok(None);
+        };
+        let row = sqlx::query_as::<_, MembershipRow>(
+            "DELETE FROM memberships WHERE organization_id = ?1 AND user_id = ?2 RETURNING organization_id, user_id, role, joined_at",
+        )
+        .bind(organization_id)
+        .bind(user_id)
+        .fetch_optional(&mut *self.pool.acquire().await?)
+        .await?;
+        row.map(Membership::try_from).transpose()
+    }
+
+    async fn memberships_by_organizations(&self, organization_ids: &[String]) -> RepositoryResult<HashMap<String, Vec<Membership>>> {
+        let organization_ids = organization_ids.iter().filter_map(|id| parse_id(id)).collect();
+        self.memberships_where("organization_id", organization_ids, |membership| &membership.organization_id).await
+    }
+
+    async fn memberships_by_users(&self, user_ids: &[String]) -> RepositoryResult<HashMap<String, Vec<Membership>>> {
+        let user_ids = user_ids.iter().filter_map(|id| parse_user_id(id)).collect();
+        self.memberships_where("user_id", user_ids, |membership| &membership.user_id).await
+    }
+}
+
+impl SqliteRepository {
+    // Load the memberships whose column matches one of the parsed IDs, grouped by that ID, in the order they were joined
+    async fn memberships_where<T>(
+        &self,
+        column: &str,
+        ids: Vec<T>,
+        key: fn(&Membership) -> &String,
+    ) -> RepositoryResult<HashMap<String, Vec<Membership>>>
+    where
+        T: for<'q> Encode<'q, Sqlite> + Type<Sqlite> + Send,
+    {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut query = QueryBuilder::<Sqlite>::new(format!(
+            "SELECT organization_id, user_id, role, joined_at FROM memberships WHERE {} IN (",
+            column
+        ));
+        let mut separated = query.separated(", ");
+        for id in ids {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(") ORDER BY julianday(joined_at), organization_id, user_id");
+
+        let rows = query.build_query_as::<MembershipRow>().fetch_all(&mut *self.pool.acquire().await?).await?;
+        group_memberships(rows, key)
+    }
+}
+
+// Define a SqliteTransaction that runs every operation inside one database transaction
+pub struct SqliteTransaction {
+    transaction: Transaction<'static, Sqlite>,
+}
+
+// Implement the transactional operations with the same queries as the repository
+#[async_trait]
+impl UserTransaction for SqliteTransaction {
+    async fn get(&mut self, id: &str) -> RepositoryResult<Option<User>> {
+        select_user(&mut self.transaction, id, false).await
+    }
+
+    async fn create(&mut self, new_user: NewUser) -> RepositoryResult<User> {
+        insert_user(&mut self.transaction, new_user).await
+    }
+
+    async fn update(&mut self, id: &str, update: UserUpdate) -> RepositoryResult<Option<User>> {
+        update_user(&mut self.transaction, id, update).await
+    }
+
+    async fn delete(&mut self, id: &str) -> RepositoryResult<Option<User>> {
+        delete_user(&mut self.transaction, id).await
+    }
+
+    async fn commit(self: Box<Self>) -> RepositoryResult<()> {
+        self.transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> RepositoryResult<()> {
+        self.transaction.rollback().await?;
+        Ok(())
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{legacy_user_id, SortDirection};
+    use crate::repository::with_transaction;
+
+    // Define a test for the full create/list/update/delete cycle against an in-memory database
+    #[tokio::test]
+    async fn test_crud_cycle() {
+        // A single connection keeps every query on the same in-memory database
+        let config = DatabaseConfig { max_connections: 1, ..DatabaseConfig::new("sqlite::memory:") };
+        let repository = SqliteRepository::connect(&config).await.expect("Failed to open SQLite");
+
+        repository.ping().await.expect("Database should answer a ping");
+
+        // Create a user and check it can be read back
+        let ada = repository
+            .create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(Uuid::parse_str(&ada.id).unwrap().get_version_num(), 7);
+        assert_eq!(repository.get(&ada.id).await.unwrap(), Some(ada.clone()));
+        assert_eq!(ada.updated_at, ada.created_at);
+        assert_eq!(repository.list().await.unwrap(), vec![ada.clone()]);
+        let found = repository.get_many(&[ada.id.clone(), "999".to_string()]).await.unwrap();
+        assert_eq!(found, HashMap::from([(ada.id.clone(), ada.clone())]));
+        assert_eq!(repository.get_by_email("ADA@Example.com").await.unwrap(), Some(ada.clone()));
+        assert_eq!(repository.get_by_email("ada@example.org").await.unwrap(), None);
+
+        // Update one field and check the other is preserved
+        let update = UserUpdate { email: Some("ada@lovelace.dev".to_string()), ..Default::default() };
+        let updated = repository.update(&ada.id, update).await.unwrap().unwrap();
+        assert_eq!(updated.name, "Ada");
+        assert_eq!(updated.email, "ada@lovelace.dev");
+
+        // The update moved the update time but not the creation time
+        assert!(updated.updated_at > ada.updated_at);
+        assert_eq!(updated.created_at, ada.created_at);
+
+        // The update bumped the version, so an update expecting the old one conflicts
+        assert_eq!(updated.version, 2);
+        let stale = UserUpdate { name: Some("Stale".to_string()), expected_version: Some(1), ..Default::default() };
+        let result = repository.update(&ada.id, stale).await;
+        assert!(matches!(result, Err(RepositoryError::VersionConflict { expected: 1, actual: 2, .. })));
+        let current = UserUpdate { avatar_key: Some("1-1.png".to_string()), expected_version: Some(2), ..Default::default() };
+        let updated = repository.update(&ada.id, current).await.unwrap().unwrap();
+        assert_eq!(updated.version, 3);
+        assert_eq!(updated.avatar_key.as_deref(), Some("1-1.png"));
+
+        // Metadata is stored, kept by other updates, and cleared with a JSON null
+        let metadata = serde_json::json!({ "team": "core", "tags": ["a", 1], "nested": { "on": true } });
+        let update = UserUpdate { metadata: Some(metadata.clone()), ..Default::default() };
+        assert_eq!(repository.update(&ada.id, update).await.unwrap().unwrap().metadata, Some(metadata.clone()));
+        let update = UserUpdate { name: Some("Ada".to_string()), ..Default::default() };
+        assert_eq!(repository.update(&ada.id, update).await.unwrap().unwrap().metadata, Some(metadata));
+        let update = UserUpdate { metadata: Some(serde_json::Value::Null), ..Default::default() };
+        let updated = repository.update(&ada.id, update).await.unwrap().unwrap();
+        assert_eq!(updated.metadata, None);
+
+        // A phone number is kept by other updates and removed with Some(None)
+        let update = UserUpdate { phone: Some(Some("+14155550123".to_string())), ..Default::default() };
+        assert_eq!(repository.update(&ada.id, update).await.unwrap().unwrap().phone.as_deref(), Some("+14155550123"));
+        let update = UserUpdate { name: Some("Ada".to_string()), ..Default::default() };
+        assert_eq!(repository.update(&ada.id, update).await.unwrap().unwrap().phone.as_deref(), Some("+14155550123"));
+        let update = UserUpdate { phone: Some(None), ..Default::default() };
+        assert_eq!(repository.update(&ada.id, update).await.unwrap().unwrap().phone, None);
+
+        // An address is stored whole, kept by other updates, and removed with Some(None)
+        let address = Address { street: "1 Main St".to_string(), city: "Toronto".to_string(), country: "CA".to_string(), postal_code: None };
+        let update = UserUpdate { address: Some(Some(address.clone())), ..Default::default() };
+        assert_eq!(repository.update(&ada.id, update).await.unwrap().unwrap().address, Some(address.clone()));
+        let update = UserUpdate { name: Some("Ada".to_string()), ..Default::default() };
+        assert_eq!(repository.update(&ada.id, update).await.unwrap().unwrap().address, Some(address));
+        let update = UserUpdate { address: Some(None), ..Default::default() };
+        let updated = repository.update(&ada.id, update).await.unwrap().unwrap();
+        assert_eq!(updated.address, None);
+
+        // Users are verified unless created otherwise, and the flag is kept by other updates
+        assert!(updated.email_verified);
+        let update = UserUpdate { email_verified: Some(false), ..Default::default() };
+        assert!(!repository.update(&ada.id, update).await.unwrap().unwrap().email_verified);
+        let update = UserUpdate { name: Some("Ada".to_string()), ..Default::default() };
+        assert!(!repository.update(&ada.id, update).await.unwrap().unwrap().email_verified);
+
+        // Soft-delete and check the user is hidden but kept; malformed IDs never match
+        let deleted = repository.delete(&ada.id).await.unwrap().unwrap();
+        assert!(deleted.deleted_at.is_some());
+        assert_eq!(repository.get(&ada.id).await.unwrap(), None);
+        assert_eq!(repository.get_many(std::slice::from_ref(&ada.id)).await.unwrap(), HashMap::new());
+        assert_eq!(repository.list().await.unwrap(), Vec::new());
+        assert_eq!(repository.get_by_email("ada@example.com").await.unwrap(), None);
+        assert_eq!(repository.list_including_deleted().await.unwrap(), vec![deleted.clone()]);
+        assert_eq!(repository.get_including_deleted(&ada.id).await.unwrap(), Some(deleted));
+        assert_eq!(repository.delete(&ada.id).await.unwrap(), None);
+        assert_eq!(repository.get("not-a-number").await.unwrap(), None);
+
+        // Restore brings the user back; purge removes it for good
+        assert_eq!(repository.restore(&ada.id).await.unwrap(), Some(updated.clone()));
+        assert_eq!(repository.purge(&ada.id).await.unwrap(), Some(updated));
+        assert_eq!(repository.get_including_deleted(&ada.id).await.unwrap(), None);
+
+        // Requested IDs are kept in canonical form, generated IDs sort after them, taken IDs conflict,
+        // and anything but a UUID is rejected
+        let requested = NewUser {
+            id: Some("00000000-0000-0000-0000-00000000003A".to_string()),
+            name: "Grace".to_string(),
+            email: "grace@example.com".to_string(),
+            phone: Some("+442079460958".to_string()),
+            address: Some(Address { street: "10 Downing St".to_string(), city: "London".to_string(), country: "GB".to_string(), postal_code: Some("SW1A 2AA".to_string()) }),
+            ..Default::default()
+        };
+        let generated = NewUser { id: None, ..requested.clone() };
+        let users = repository.create_many(vec![requested.clone(), generated]).await.unwrap();
+        assert_eq!(users[0].id, "00000000-0000-0000-0000-00000000003a");
+        assert_eq!(users[0].phone.as_deref(), Some("+442079460958"));
+        assert_eq!(users[0].address.as_ref().and_then(|address| address.postal_code.as_deref()), Some("SW1A 2AA"));
+        assert!(users[1].id > users[0].id);
+        assert!(matches!(repository.create(requested.clone()).await, Err(RepositoryError::Conflict(_))));
+        let malformed = NewUser { id: Some("50".to_string()), ..requested };
+        assert!(matches!(repository.create(malformed).await, Err(RepositoryError::InvalidId(_))));
+    }
+
+    // Define a test that a failure partway through a transaction rolls back its earlier changes
+    #[tokio::test]
+    async fn test_transaction_rollback_on_failure() {
+        let config = DatabaseConfig { max_connections: 1, ..DatabaseConfig::new("sqlite::memory:") };
+        let repository = SqliteRepository::connect(&config).await.expect("Failed to open SQLite");
+        let first = NewUser { id: Some(legacy_user_id(7).to_string()), name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() };
+
+        // The second insert conflicts with the first, failing the unit of work
+        let result = with_transaction(&repository, |transaction| {
+            let first = first.clone();
+            Box::pin(async move {
+                transaction.create(first.clone()).await?;
+                transaction.create(first).await
+            })
+        })
+        .await;
+        assert!(matches!(result, Err(RepositoryError::Conflict(_))));
+        assert_eq!(repository.list().await.unwrap(), vec![]);
+
+        // A unit of work that succeeds is committed
+        let user = with_transaction(&repository, |transaction| Box::pin(async move { transaction.create(first).await }))
+            .await
+            .unwrap();
+        assert_eq!(repository.get(&legacy_user_id(7).to_string()).await.unwrap(), Some(user));
+    }
+
+    // Define a test that writes record outbox events atomically with the change
+    #[tokio::test]
+    async fn test_outbox_events() {
+        let config = DatabaseConfig { max_connections: 1, ..DatabaseConfig::new("sqlite::memory:") };
+        let repository = SqliteRepository::connect(&config).await.expect("Failed to open SQLite");
+        let ada = repository
+            .create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() })
+            .await
+            .unwrap();
+        repository.delete(&ada.id).await.unwrap();
+
+        // A rolled-back transaction leaves no events behind; a conflicting update records none
+        let mut transaction = repository.begin().await.unwrap();
+        transaction.update(&ada.id, UserUpdate::default()).await.unwrap();
+        transaction
+            .create(NewUser { name: "Ghost".to_string(), email: "ghost@example.com".to_string(), ..Default::default() })
+            .await
+            .unwrap();
+        transaction.rollback().await.unwrap();
+        repository.restore(&ada.id).await.unwrap();
+        let stale = UserUpdate { expected_version: Some(7), ..Default::default() };
+        assert!(repository.update(&ada.id, stale).await.is_err());
+
+        let events = repository.pending_events(10).await.unwrap();
+        let kinds: Vec<ChangeKind> = events.iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, vec![ChangeKind::Created, ChangeKind::Deleted, ChangeKind::Restored]);
+        assert_eq!(events[0].user, ada);
+        assert!(events[1].user.deleted_at.is_some());
+
+        // Acknowledged events are removed
+        repository.acknowledge_events(&[events[0].id, events[1].id]).await.unwrap();
+        let remaining: Vec<i64> = repository.pending_events(10).await.unwrap().iter().map(|event| event.id).collect();
+        assert_eq!(remaining, vec![events[2].id]);
+    }
+
+    // Define a test for keyset paging with deleted-user and creation-date filters
+    #[tokio::test]
+    async fn test_list_page() {
+        let config = DatabaseConfig { max_connections: 1, ..DatabaseConfig::new("sqlite::memory:") };
+        let repository = SqliteRepository::connect(&config).await.expect("Failed to open SQLite");
+        let new_users = ["Ada", "Grace", "Alan"]
+            .iter()
+            .map(|name| NewUser { name: name.to_string(), email: format!("{}@example.com", name.to_lowercase()), ..Default::default() })
+            .collect();
+        let users = repository.create_many(new_users).await.unwrap();
+        repository.delete(&users[1].id).await.unwrap();
+
+        // Pages continue after the last ID of the previous one and skip deleted users by default
+        let filter = UserFilter::default();
+        let first = repository.list_page(&filter, None, 1).await.unwrap();
+        assert_eq!(first, vec![users[0].clone()]);
+        let second = repository.list_page(&filter, Some(&first[0].id), 1).await.unwrap();
+        assert_eq!(second, vec![users[2].clone()]);
+        assert_eq!(repository.list_page(&filter, Some(&second[0].id), 1).await.unwrap(), Vec::new());
+        let everyone = UserFilter { include_deleted: true, ..Default::default() };
+        assert_eq!(repository.list_page(&everyone, None, 10).await.unwrap().len(), 3);
+
+        // Offset pages skip the filtered-out users too, and the count matches the filter
+        assert_eq!(repository.list_range(&filter, &[], 1, 10).await.unwrap(), vec![users[2].clone()]);
+        assert_eq!(repository.list_range(&everyone, &[], 1, 1).await.unwrap()[0].id, users[1].id);
+        assert_eq!(repository.list_range(&filter, &[], 5, 10).await.unwrap(), Vec::new());
+        assert_eq!(repository.count(&filter).await.unwrap(), 2);
+        assert_eq!(repository.count(&everyone).await.unwrap(), 3);
+
+        // Creation dates bound the window from below (inclusive) and above (exclusive)
+        let created_at = users[0].created_at;
+        let from_creation = UserFilter { created_after: Some(created_at), ..Default::default() };
+        assert_eq!(repository.list_page(&from_creation, None, 10).await.unwrap().first(), Some(&users[0]));
+        let before_creation = UserFilter { created_before: Some(created_at), ..Default::default() };
+        assert_eq!(repository.list_page(&before_creation, None, 10).await.unwrap(), Vec::new());
+
+        // Names match on a case-insensitive substring and emails on their whole domain
+        let named = UserFilter { name_contains: Some("AL".to_string()), include_deleted: true, ..Default::default() };
+        assert_eq!(repository.list_range(&named, &[], 0, 10).await.unwrap(), vec![users[2].clone()]);
+        assert_eq!(repository.count(&named).await.unwrap(), 1);
+        let at_domain = UserFilter { email_domain: Some("Example.COM".to_string()), ..Default::default() };
+        assert_eq!(repository.list_page(&at_domain, None, 10).await.unwrap(), vec![users[0].clone(), users[2].clone()]);
+        let at_parent_domain = UserFilter { email_domain: Some("com".to_string()), ..Default::default() };
+        assert_eq!(repository.count(&at_parent_domain).await.unwrap(), 0);
+
+        // Sort keys apply in turn and ties fall back to creation order
+        let by_name = [UserOrder { field: UserSortField::Name, direction: SortDirection::Desc }];
+        let names: Vec<String> = repository.list_range(&everyone, &by_name, 0, 10).await.unwrap().into_iter().map(|user| user.name).collect();
+        assert_eq!(names, ["Grace", "Alan", "Ada"]);
+        let by_email_then_newest = [
+            UserOrder { field: UserSortField::Email, direction: SortDirection::Asc },
+            UserOrder { field: UserSortField::CreatedAt, direction: SortDirection::Desc },
+        ];
+        let page = repository.list_range(&filter, &by_email_then_newest, 1, 1).await.unwrap();
+        assert_eq!(page, vec![users[2].clone()]);
+
+        // Roles are stored with the user and narrow every listing
+        let promote = UserUpdate { role: Some(UserRole::Admin), ..Default::default() };
+        let admin = repository.update(&users[2].id, promote).await.unwrap().unwrap();
+        assert_eq!(repository.get(&admin.id).await.unwrap().unwrap().role, UserRole::Admin);
+        let admins = UserFilter { role: Some(UserRole::Admin), ..Default::default() };
+        assert_eq!(repository.list_page(&admins, None, 10).await.unwrap(), vec![admin.clone()]);
+        assert_eq!(repository.list_range(&admins, &[], 0, 10).await.unwrap(), vec![admin]);
+        let members = UserFilter { role: Some(UserRole::Member), include_deleted: true, ..Default::default() };
+        assert_eq!(repository.count(&members).await.unwrap(), 2);
+    }
+
+    // Define a test for counting users with aggregate queries
+    #[tokio::test]
+    async fn test_stats() {
+        let config = DatabaseConfig { max_connections: 1, ..DatabaseConfig::new("sqlite::memory:") };
+        let repository = SqliteRepository::connect(&config).await.expect("Failed to open SQLite");
+        let new_users = [("Ada", "ada@Example.com", UserRole::Admin), ("Grace", "grace@example.com", UserRole::Member), ("Alan", "alan@noibu.com", UserRole::Member)]
+            .into_iter()
+            .map(|(name, email, role)| NewUser { name: name.to_string(), email: email.to_string(), role, ..Default::default() })
+            .collect();
+        let users = repository.create_many(new_users).await.unwrap();
+        let tomorrow = Utc::now() + chrono::Duration::days(1);
+        assert_eq!(repository.stats(tomorrow).await.unwrap().users_created_today, 0);
+        repository.delete(&users[2].id).await.unwrap();
+
+        // Deleted users are not counted, and ties are broken by name
+        let stats = repository.stats(users[0].created_at).await.unwrap();
+        assert_eq!(
+            stats,
+            UserStats {
+                user_count: 2,
+                users_created_today: 2,
+                by_role: vec![RoleCount { role: UserRole::Admin, count: 1 }, RoleCount { role: UserRole::Member, count: 1 }],
+                by_domain: vec![DomainCount { domain: "example.com".to_string(), count: 2 }],
+            }
+        );
+    }
+
+    // Define a test for storing posts and loading them by author
+    #[tokio::test]
+    async fn test_posts() {
+        let config = DatabaseConfig { max_connections: 1, ..DatabaseConfig::new("sqlite::memory:") };
+        let repository = SqliteRepository::connect(&config).await.expect("Failed to open SQLite");
+        let new_users = ["Ada", "Grace", "Alan"]
+            .iter()
+            .map(|name| NewUser { name: name.to_string(), email: format!("{}@example.com", name.to_lowercase()), ..Default::default() })
+            .collect();
+        let users = repository.create_many(new_users).await.unwrap();
+        let posts = repository.posts().expect("SQL backends store posts");
+
+        // Posts get an ID and creation time and can be read back
+        let mut created = Vec::new();
+        for (author, title) in [(0, "First"), (1, "Second"), (0, "Third")] {
+            let new_post = NewPost { author_id: users[author].id.clone(), title: title.to_string(), body: "Body".to_string() };
+            created.push(posts.create_post(new_post).await.unwrap());
+        }
+        assert_eq!(created[0].author_id, users[0].id);
+        assert_eq!(posts.get_post(&created[1].id).await.unwrap(), Some(created[1].clone()));
+        assert_eq!(posts.get_post("999").await.unwrap(), None);
+        assert_eq!(posts.get_post("not-a-number").await.unwrap(), None);
+
+        // Searches need every term in the title or body, ignoring case, and return posts in creation order
+        assert_eq!(posts.search_posts("first BODY", 10).await.unwrap(), vec![created[0].clone()]);
+        assert_eq!(posts.search_posts("body", 2).await.unwrap(), vec![created[0].clone(), created[1].clone()]);
+        assert_eq!(posts.search_posts("first second", 10).await.unwrap(), Vec::new());
+        assert_eq!(posts.search_posts(" ", 10).await.unwrap(), Vec::new());
+
+        // Posts are grouped by author in creation order, leaving out authors without posts
+        let ids: Vec<String> = users.iter().map(|user| user.id.clone()).chain(["bad".to_string()]).collect();
+        let by_author = posts.posts_by_authors(&ids).await.unwrap();
+        assert_eq!(
+            by_author,
+            HashMap::from([
+                (users[0].id.clone(), vec![created[0].clone(), created[2].clone()]),
+                (users[1].id.clone(), vec![created[1].clone()]),
+            ])
+        );
+
+        // Pages hold up to their limit of the author's posts after their cursor, keyed by the page
+        let page = |author: usize, after: Option<&str>, limit: usize| PostPage { author_id: users[author].id.clone(), after: after.map(str::to_string), limit };
+        let pages = [
+            page(0, None, 1),
+            page(0, Some(&created[0].id), 5),
+            page(1, None, 5),
+            page(1, Some(&created[1].id), 5),
+            page(2, None, 5),
+            page(0, Some("bad"), 5),
+        ];
+        assert_eq!(
+            posts.posts_by_author_pages(&pages).await.unwrap(),
+            HashMap::from([
+                (pages[0].clone(), vec![created[0].clone()]),
+                (pages[1].clone(), vec![created[2].clone()]),
+                (pages[2].clone(), vec![created[1].clone()]),
+            ])
+        );
+
+        // Comments page by ID under their post or parent comment
+        let comment = |parent_id: Option<String>, body: &str, depth: i32| NewComment {
+            post_id: created[0].id.clone(),
+            parent_id,
+            author_id: users[1].id.clone(),
+            body: body.to_string(),
+            depth,
+        };
+        let top = posts.create_comment(comment(None, "Top", 0)).await.unwrap();
+        let reply = posts.create_comment(comment(Some(top.id.clone()), "Reply", 1)).await.unwrap();
+        let second = posts.create_comment(comment(None, "Second", 0)).await.unwrap();
+        assert_eq!(posts.get_comment(&reply.id).await.unwrap(), Some(reply.clone()));
+        assert_eq!(posts.list_comments(&created[0].id, None, None, 10).await.unwrap(), vec![top.clone(), second.clone()]);
+        assert_eq!(posts.list_comments(&created[0].id, None, Some(&top.id), 10).await.unwrap(), vec![second.clone()]);
+        assert_eq!(posts.list_comments(&created[0].id, None, None, 1).await.unwrap(), vec![top.clone()]);
+        assert_eq!(posts.list_comments(&created[0].id, Some(&top.id), None, 10).await.unwrap(), vec![reply.clone()]);
+        assert_eq!(posts.list_comments(&created[1].id, None, None, 10).await.unwrap(), Vec::new());
+        assert_eq!(posts.list_comments(&created[0].id, None, Some("bad"), 10).await.unwrap(), Vec::new());
+
+        // Removing the commenter removes their comments, and replies go with the comment they answer
+        let other = posts.create_comment(NewComment { author_id: users[2].id.clone(), ..comment(Some(reply.id.clone()), "Below", 2) }).await.unwrap();
+        repository.purge(&users[1].id).await.unwrap();
+        assert_eq!(posts.get_comment(&other.id).await.unwrap(), None);
+        assert_eq!(posts.list_comments(&created[0].id, None, None, 10).await.unwrap(), Vec::new());
+
+        // Posts need an existing author, and purging the author removes them
+        let orphan = NewPost { author_id: legacy_user_id(999).to_string(), title: "Orphan".to_string(), body: String::new() };
+        assert!(posts.create_post(orphan).await.is_err());
+        repository.purge(&users[0].id).await.unwrap();
+        assert_eq!(posts.get_post(&created[0].id).await.unwrap(), None);
+        assert_eq!(posts.posts_by_authors(&ids).await.unwrap(), HashMap::new());
+    }
+
+    // Define a test for storing organizations and their memberships
+    #[tokio::test]
+    async fn test_organizations() {
+        let config = DatabaseConfig { max_connections: 1, ..DatabaseConfig::new("sqlite::memory:") };
+        let repository = SqliteRepository::connect(&config).await.expect("Failed to open SQLite");
+        let new_users = ["Ada", "Grace"]
+            .iter()
+            .map(|name| NewUser { name: name.to_string(), email: format!("{}@example.com", name.to_lowercase()), ..Default::default() })
+            .collect();
+        let users = repository.create_many(new_users).await.unwrap();
+        let organizations = repository.organizations().expect("SQL backends store organizations");
+
+        // Organizations get an ID and creation time and can be read back in creation order
+        let acme = organizations.create_organization("Acme".to_string()).await.unwrap();
+        let globex = organizations.create_organization("Globex".to_string()).await.unwrap();
+        assert_eq!(organizations.list_organizations().await.unwrap(), vec![acme.clone(), globex.clone()]);
+        let ids = [acme.id.clone(), "999".to_string(), "bad".to_string()];
+        assert_eq!(organizations.get_organizations(&ids).await.unwrap(), HashMap::from([(acme.id.clone(), acme.clone())]));
+        assert_eq!(organizations.search_organizations("GLOB", 10).await.unwrap(), vec![globex.clone()]);
+
+        // Adding a member twice keeps one membership and changes its role
+        let owner = organizations.add_member(&acme.id, &users[0].id, MembershipRole::Owner).await.unwrap();
+        let member = organizations.add_member(&acme.id, &users[1].id, MembershipRole::Member).await.unwrap();
+        let admin = organizations.add_member(&acme.id, &users[1].id, MembershipRole::Admin).await.unwrap();
+        assert_eq!(admin, Membership { role: MembershipRole::Admin, ..member });
+        let other = organizations.add_member(&globex.id, &users[1].id, MembershipRole::Member).await.unwrap();
+        assert!(organizations.add_member(&acme.id, &legacy_user_id(999).to_string(), MembershipRole::Member).await.is_err());
+
+        // Memberships are grouped by organization and by user in the order they were joined
+        let by_organization = organizations.memberships_by_organizations(&[acme.id.clone(), globex.id.clone()]).await.unwrap();
+        assert_eq!(by_organization[&acme.id], vec![owner.clone(), admin.clone()]);
+        assert_eq!(by_organization[&globex.id], vec![other.clone()]);
+        let user_ids: Vec<String> = users.iter().map(|user| user.id.clone()).collect();
+        let by_user = organizations.memberships_by_users(&user_ids).await.unwrap();
+        assert_eq!(by_user, HashMap::from([(users[0].id.clone(), vec![owner.clone()]), (users[1].id.clone(), vec![admin.clone(), other])]));
+
+        // Removing a member returns the membership once, and purging a user removes the rest of theirs
+        assert_eq!(organizations.remove_member(&acme.id, &users[0].id).await.unwrap(), Some(owner));
+        assert_eq!(organizations.remove_member(&acme.id, &users[0].id).await.unwrap(), None);
+        assert_eq!(organizations.remove_member("bad", &users[0].id).await.unwrap(), None);
+        repository.purge(&users[1].id).await.unwrap();
+        assert_eq!(organizations.memberships_by_users(&user_ids).await.unwrap(), HashMap::new());
+    }
+
+    // Define a test for storing API keys and finding them by their hash
+    #[tokio::test]
+    async fn test_api_keys() {
+        let config = DatabaseConfig { max_connections: 1, ..DatabaseConfig::new("sqlite::memory:") };
+        let repository = SqliteRepository::connect(&config).await.expect("Failed to open SQLite");
+        let user = repository.create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() }).await.unwrap();
+        let api_keys = repository.api_keys().expect("SQL backends store API keys");
+        let new_api_key = |user_id: &str, key_hash: &str| NewApiKey { name: "CI".to_string(), user_id: user_id.to_string(), key_hash: key_hash.to_string() };
+
+        let api_key = api_keys.create_api_key(new_api_key(&user.id, "hash-1")).await.unwrap();
+        assert_eq!((api_key.name.as_str(), api_key.user_id.as_str(), api_key.key_hash.as_str()), ("CI", user.id.as_str(), "hash-1"));
+        assert_eq!(api_keys.get_api_key_by_hash("hash-1").await.unwrap(), Some(api_key));
+        assert_eq!(api_keys.get_api_key_by_hash("hash-2").await.unwrap(), None);
+
+        // Keys need a user that exists and a hash no other key has
+        assert!(api_keys.create_api_key(new_api_key(&legacy_user_id(999).to_string(), "hash-2")).await.is_err());
+        assert!(api_keys.create_api_key(new_api_key("bad", "hash-2")).await.is_err());
+        assert!(api_keys.create_api_key(new_api_key(&user.id, "hash-1")).await.is_err());
+
+        // Purging the user removes their keys
+        repository.purge(&user.id).await.unwrap();
+        assert_eq!(api_keys.get_api_key_by_hash("hash-1").await.unwrap(), None);
+    }
+
+    // Define a test for storing, replacing, and reading password hashes
+    #[tokio::test]
+    async fn test_passwords() {
+        let config = DatabaseConfig { max_connections: 1, ..DatabaseConfig::new("sqlite::memory:") };
+        let repository = SqliteRepository::connect(&config).await.expect("Failed to open SQLite");
+        let user = repository.create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() }).await.unwrap();
+        let passwords = repository.passwords().expect("SQL backends store passwords");
+
+        assert_eq!(passwords.get_password_hash(&user.id).await.unwrap(), None);
+        passwords.set_password_hash(&user.id, "hash-1").await.unwrap();
+        passwords.set_password_hash(&user.id, "hash-2").await.unwrap();
+        assert_eq!(passwords.get_password_hash(&user.id).await.unwrap().as_deref(), Some("hash-2"));
+
+        // The user must exist, and purging them removes their password
+        assert!(passwords.set_password_hash(&legacy_user_id(999).to_string(), "hash-1").await.is_err());
+        assert!(passwords.set_password_hash("bad", "hash-1").await.is_err());
+        assert_eq!(passwords.get_password_hash("bad").await.unwrap(), None);
+        repository.purge(&user.id).await.unwrap();
+        assert_eq!(passwords.get_password_hash(&user.id).await.unwrap(), None);
+    }
+
+    // Define a test that refresh tokens are revoked on use and all at once
+    #[tokio::test]
+    async fn test_refresh_tokens() {
+        let config = DatabaseConfig { max_connections: 1, ..DatabaseConfig::new("sqlite::memory:") };
+        let repository = SqliteRepository::connect(&config).await.expect("Failed to open SQLite");
+        let user = repository.create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() }).await.unwrap();
+        let refresh_tokens = repository.refresh_tokens().expect("SQL backends store refresh tokens");
+        let expires_at = DateTime::parse_from_rfc3339("2030-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        refresh_tokens.create_refresh_token(&user.id, "hash-1", expires_at).await.unwrap();
+        refresh_tokens.create_refresh_token(&user.id, "hash-2", expires_at).await.unwrap();
+        assert!(refresh_tokens.create_refresh_token(&legacy_user_id(999).to_string(), "hash-3", expires_at).await.is_err());
+        assert!(refresh_tokens.create_refresh_token(&user.id, "hash-1", expires_at).await.is_err());
+
+        // Revoking returns the token as it was, so only the first caller sees it unrevoked
+        let token = refresh_tokens.revoke_refresh_token("hash-1").await.unwrap().unwrap();
+        assert_eq!(token, RefreshToken { user_id: user.id.clone(), token_hash: "hash-1".to_string(), expires_at, revoked_at: None });
+        assert!(refresh_tokens.revoke_refresh_token("hash-1").await.unwrap().unwrap().revoked_at.is_some());
+        assert_eq!(refresh_tokens.revoke_refresh_token("hash-3").await.unwrap(), None);
+
+        // Revoking the user's tokens only counts the ones still live, and purging removes them
+        assert_eq!(refresh_tokens.revoke_refresh_tokens(&user.id).await.unwrap(), 1);
+        assert_eq!(refresh_tokens.revoke_refresh_tokens(&user.id).await.unwrap(), 0);
+        repository.purge(&user.id).await.unwrap();
+        assert_eq!(refresh_tokens.revoke_refresh_token("hash-2").await.unwrap(), None);
+    }
+    // Define a test for enrolling, confirming, and using recovery codes of two-factor enrollments
+    #[tokio::test]
+    async fn test_two_factor() {
+        let config = DatabaseConfig { max_connections: 1, ..DatabaseConfig::new("sqlite::memory:") };
+        let repository = SqliteRepository::connect(&config).await.expect("Failed to open SQLite");
+        let user = repository.create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() }).await.unwrap();
+        let two_factor = repository.two_factor().expect("SQL backends store two-factor enrollments");
+
+        assert_eq!(two_factor.get_two_factor(&user.id).await.unwrap(), None);
+        assert!(!two_factor.confirm_two_factor(&user.id).await.unwrap());
+        two_factor.set_two_factor(&user.id, "SECRET1", &["code-1".to_string(), "code-2".to_string()]).await.unwrap();
+        assert!(two_factor.confirm_two_factor(&user.id).await.unwrap());
+
+        // Each recovery code is used once
+        assert!(two_factor.use_recovery_code(&user.id, "code-1").await.unwrap());
+        assert!(!two_factor.use_recovery_code(&user.id, "code-1").await.unwrap());
+        let enrolled = two_factor.get_two_factor(&user.id).await.unwrap().unwrap();
+        assert_eq!(enrolled, TwoFactor { secret: "SECRET1".to_string(), confirmed: true, recovery_code_hashes: vec!["code-2".to_string()] });
+
+        // Enrolling again replaces the enrollment with a pending one and its codes
+        two_factor.set_two_factor(&user.id, "SECRET2", &["code-3".to_string()]).await.unwrap();
+        let enrolled = two_factor.get_two_factor(&user.id).await.unwrap().unwrap();
+        assert_eq!(enrolled, TwoFactor { secret: "SECRET2".to_string(), confirmed: false, recovery_code_hashes: vec!["code-3".to_string()] });
+        assert!(!two_factor.use_recovery_code(&user.id, "code-2").await.unwrap());
+
+        // The user must exist, and purging them removes their enrollment
+        assert!(two_factor.set_two_factor(&legacy_user_id(999).to_string(), "SECRET1", &[]).await.is_err());
+        assert_eq!(two_factor.get_two_factor("bad").await.unwrap(), None);
+        repository.purge(&user.id).await.unwrap();
+        assert_eq!(two_factor.get_two_factor(&user.id).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,244 @@
+// Import necessary libraries and modules
+use async_graphql::{BatchRequest, BatchResponse, ErrorExtensions, Pos};
+use async_graphql_warp::{GraphQLBatchResponse, GraphQLResponse};
+use std::future::Future;
+use std::time::Duration;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::reject::{LengthRequired, PayloadTooLarge};
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::Settings;
+use crate::error::AppError;
+use crate::upload::{number_var, UploadLimits};
+
+// Define the largest GraphQL request body when REQUEST_MAX_BODY_BYTES is not set
+pub const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+
+// Define how long an operation may run when REQUEST_TIMEOUT_SECS is not set
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Define how many operations a batch may hold when REQUEST_MAX_BATCH_SIZE is not set
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 10;
+
+// Define how large a GraphQL request body may be, how many operations a batch request may hold, and how
+// long each operation may run before it is cancelled, if there is a limit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RequestLimits {
+    pub max_body_bytes: usize,
+    pub max_batch_size: usize,
+    pub timeout: Option<Duration>,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits { max_body_bytes: DEFAULT_MAX_BODY_BYTES, max_batch_size: DEFAULT_MAX_BATCH_SIZE, timeout: Some(DEFAULT_TIMEOUT) }
+    }
+}
+
+impl RequestLimits {
+    // Read REQUEST_MAX_BODY_BYTES (default 1 MiB), REQUEST_MAX_BATCH_SIZE (default 10), and
+    // REQUEST_TIMEOUT_SECS (default 30; 0 lets operations run as long as they take)
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        let timeout = match settings.var("REQUEST_TIMEOUT_SECS") {
+            Some(value) => match value.trim().parse() {
+                Ok(0) => None,
+                Ok(seconds) => Some(Duration::from_secs(seconds)),
+                Err(_) => return Err(format!("REQUEST_TIMEOUT_SECS must be a number of seconds, got {:?}", value)),
+            },
+            None => Some(DEFAULT_TIMEOUT),
+        };
+        Ok(RequestLimits {
+            max_body_bytes: number_var(settings, "REQUEST_MAX_BODY_BYTES", DEFAULT_MAX_BODY_BYTES)?,
+            max_batch_size: number_var(settings, "REQUEST_MAX_BATCH_SIZE", DEFAULT_MAX_BATCH_SIZE)?,
+            timeout,
+        })
+    }
+
+    // Build a filter refusing request bodies over the limit, by their Content-Length, before they are read.
+    // Multipart requests may also carry the files the upload limits allow; bodies without a length are
+    // refused too, since they could not be held to it
+    pub fn body_limit(&self, uploads: UploadLimits) -> BoxedFilter<()> {
+        let multipart = warp::header::<String>("content-type")
+            .and_then(|content_type: String| async move {
+                match is_multipart(&content_type) {
+                    true => Ok(()),
+                    false => Err(warp::reject::not_found()),
+                }
+            })
+            .untuple_one()
+            .and(warp::body::content_length_limit(self.multipart_bytes(uploads) as u64));
+        multipart.or(warp::body::content_length_limit(self.max_body_bytes as u64)).unify().boxed()
+    }
+
+    // Refuse a request body over the limit for its type, or sent without a length, with the response the
+    // body limit filter's requests get; for servers that read requests themselves
+    pub fn refuse_body(&self, uploads: UploadLimits, content_type: Option<&str>, content_length: Option<u64>) -> Option<Response> {
+        let limit = match content_type.is_some_and(is_multipart) {
+            true => self.multipart_bytes(uploads),
+            false => self.max_body_bytes,
+        };
+        match content_length {
+            Some(length) if length <= limit as u64 => None,
+            Some(_) => Some(error_response(AppError::RequestTooLarge, StatusCode::PAYLOAD_TOO_LARGE)),
+            None => Some(error_response(AppError::LengthRequired, StatusCode::LENGTH_REQUIRED)),
+        }
+    }
+
+    // Return the largest multipart body, which may carry the files the upload limits allow on top of the
+    // operation
+    fn multipart_bytes(&self, uploads: UploadLimits) -> usize {
+        self.max_body_bytes.saturating_add(uploads.max_files.saturating_mul(uploads.max_file_bytes))
+    }
+
+    // Run an operation, cancelling it when it runs past the timeout. The answer is a GraphQL response,
+    // with 408 and a TIMEOUT error for operations that were cancelled
+    pub async fn execute(&self, execution: impl Future<Output = async_graphql::Response>) -> Response {
+        match self.run(execution).await {
+            Ok(response) => GraphQLResponse::from(response).into_response(),
+            Err(response) => response,
+        }
+    }
+
+    // Run an operation as execute does, returning its GraphQL response for the caller to answer with, or
+    // the 408 response when it was cancelled
+    pub async fn run(&self, execution: impl Future<Output = async_graphql::Response>) -> Result<async_graphql::Response, Response> {
+        let Some(timeout) = self.timeout else {
+            return Ok(execution.await);
+        };
+        tokio::time::timeout(timeout, execution).await.map_err(|_| error_response(AppError::Timeout, StatusCode::REQUEST_TIMEOUT))
+    }
+
+    // Run a request that may be a batch, as Apollo clients send them: a single operation is answered as
+    // execute answers it, and the operations of a batch run concurrently, each cancelled on its own past
+    // the timeout with a TIMEOUT error in its place. Batches over the size limit are refused with 400 and a
+    // BATCH_TOO_LARGE error before any of their operations run
+    pub async fn execute_batch<F: Future<Output = async_graphql::Response>>(&self, request: BatchRequest, execute: impl Fn(async_graphql::Request) -> F) -> Response {
+        let requests = match request {
+            BatchRequest::Single(request) => return self.execute(execute(request)).await,
+            BatchRequest::Batch(requests) if requests.len() > self.max_batch_size => {
+                return error_response(AppError::BatchTooLarge(self.max_batch_size), StatusCode::BAD_REQUEST);
+            }
+            BatchRequest::Batch(requests) => requests,
+        };
+        let responses = futures::future::join_all(requests.into_iter().map(|request| {
+            let execution = execute(request);
+            async move {
+                match self.timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, execution).await.unwrap_or_else(|_| error_only(AppError::Timeout)),
+                    None => execution.await,
+                }
+            }
+        }));
+        GraphQLBatchResponse::from(BatchResponse::Batch(responses.await)).into_response()
+    }
+}
+
+// Tell whether a Content-Type is that of a multipart request
+fn is_multipart(content_type: &str) -> bool {
+    content_type.trim_start().to_ascii_lowercase().starts_with("multipart/form-data")
+}
+
+// Build a GraphQL response carrying only the error
+fn error_only(error: AppError) -> async_graphql::Response {
+    async_graphql::Response::from_errors(vec![error.extend().into_server_error(Pos::default())])
+}
+
+// Answer with a GraphQL response carrying only the error, and the status
+pub(crate) fn error_response(error: AppError, status: StatusCode) -> Response {
+    warp::reply::with_status(GraphQLResponse::from(error_only(error)), status).into_response()
+}
+
+// Answer requests refused by the body limit with a GraphQL error: 413 for bodies over it, and 411 for
+// bodies sent without a length. Other rejections pass through
+pub async fn recover_body_limit(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    if rejection.find::<PayloadTooLarge>().is_some() {
+        Ok(error_response(AppError::RequestTooLarge, StatusCode::PAYLOAD_TOO_LARGE))
+    } else if rejection.find::<LengthRequired>().is_some() {
+        Ok(error_response(AppError::LengthRequired, StatusCode::LENGTH_REQUIRED))
+    } else {
+        Err(rejection)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Define a test that bodies are held to the limit for their type, and refused with GraphQL errors
+    #[tokio::test]
+    async fn test_body_limit() {
+        let limits = RequestLimits { max_body_bytes: 100, timeout: None, ..Default::default() };
+        let route = warp::post().and(limits.body_limit(UploadLimits { max_file_bytes: 1000, max_files: 2 })).map(|| "accepted").recover(recover_body_limit);
+        let send = |content_type: &'static str, length: usize| {
+            let route = route.clone();
+            async move { warp::test::request().method("POST").header("content-type", content_type).body(vec![b'x'; length]).reply(&route).await }
+        };
+
+        assert_eq!(send("application/json", 100).await.status(), StatusCode::OK);
+        let response = send("application/json", 101).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["errors"][0]["extensions"]["code"], "PAYLOAD_TOO_LARGE");
+
+        // Multipart bodies may carry files on top of the limit
+        assert_eq!(send("multipart/form-data; boundary=b", 2100).await.status(), StatusCode::OK);
+        assert_eq!(send("multipart/form-data; boundary=b", 2101).await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let response = warp::test::request().method("POST").header("content-type", "application/json").reply(&route).await;
+        assert_eq!(response.status(), StatusCode::LENGTH_REQUIRED);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["errors"][0]["extensions"]["code"], "LENGTH_REQUIRED");
+
+        // Servers reading requests themselves refuse the same bodies
+        let uploads = UploadLimits { max_file_bytes: 1000, max_files: 2 };
+        assert!(limits.refuse_body(uploads, Some("application/json"), Some(100)).is_none());
+        assert!(limits.refuse_body(uploads, Some("multipart/form-data; boundary=b"), Some(2100)).is_none());
+        assert_eq!(limits.refuse_body(uploads, None, Some(101)).unwrap().status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(limits.refuse_body(uploads, Some("application/json"), None).unwrap().status(), StatusCode::LENGTH_REQUIRED);
+    }
+
+    // Define a test that operations past the timeout are cancelled with a TIMEOUT error
+    #[tokio::test]
+    async fn test_execute_timeout() {
+        let limits = RequestLimits { max_body_bytes: 100, timeout: Some(Duration::from_millis(10)), ..Default::default() };
+        let response = limits.execute(async { async_graphql::Response::new(async_graphql::Value::Null) }).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = limits.execute(std::future::pending()).await;
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        let body: serde_json::Value = serde_json::from_slice(&warp::hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body["errors"][0]["extensions"]["code"], "TIMEOUT");
+        assert_eq!(body["errors"][0]["message"], "The operation took too long and was cancelled");
+    }
+
+    // Define a test that batches run every operation, each held to the timeout, and that batches over the
+    // size limit are refused
+    #[tokio::test]
+    async fn test_execute_batch() {
+        let limits = RequestLimits { max_batch_size: 2, timeout: Some(Duration::from_millis(10)), ..Default::default() };
+        let execute = |request: async_graphql::Request| async move {
+            match request.query.as_str() {
+                "slow" => std::future::pending().await,
+                query => async_graphql::Response::new(async_graphql::Value::from(query)),
+            }
+        };
+        let batch = |queries: &[&str]| BatchRequest::Batch(queries.iter().map(|query| async_graphql::Request::new(*query)).collect());
+        let body = |response: Response| async move { serde_json::from_slice::<serde_json::Value>(&warp::hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap() };
+
+        let response = limits.execute_batch(BatchRequest::Single(async_graphql::Request::new("one")), execute).await;
+        assert_eq!(body(response).await["data"], "one");
+
+        let response = limits.execute_batch(batch(&["one", "slow"]), execute).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let responses = body(response).await;
+        assert_eq!(responses[0]["data"], "one");
+        assert_eq!(responses[1]["errors"][0]["extensions"]["code"], "TIMEOUT");
+
+        let response = limits.execute_batch(batch(&["one", "two", "three"]), execute).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(body(response).await["errors"][0]["extensions"]["code"], "BATCH_TOO_LARGE");
+    }
+}
@@ -0,0 +1,310 @@
+// Import necessary libraries and modules
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextExecute, NextParseQuery, NextPrepareRequest, NextResolve, ResolveInfo};
+use async_graphql::parser::types::{ExecutableDocument, OperationType};
+use async_graphql::registry::{MetaDirectiveInvocation, MetaType};
+use async_graphql::{Request, Response, ServerResult, TypeDirective, Value, Variables};
+use async_trait::async_trait;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::http::header::{HeaderValue, CACHE_CONTROL};
+
+use crate::config::Settings;
+use crate::http_get::{operation_type, GetRequest};
+use crate::upload::number_var;
+
+// Define who may keep a cached response: any cache, or only the client's own
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CacheScope {
+    #[default]
+    Public,
+    Private,
+}
+
+// Define the @cacheControl directive, with which fields and object types declare how long their values
+// may be cached, in seconds, and by whom: `PUBLIC`, the default, or `PRIVATE`. A response may be cached for as long as the shortest hint of
+// the fields it resolved, and is private if any of them is. Fields returning objects, and the fields of
+// the query root, may not be cached unless they or their type have a hint; other fields take the hints of
+// the fields they are selected under
+#[TypeDirective(name = "cacheControl", location = "FieldDefinition", location = "Object")]
+pub fn cache_control(max_age: Option<i32>, scope: Option<String>) {}
+
+// Define the cache policy of a response: how many seconds it may be cached for, if it may be, and by whom
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CachePolicy {
+    pub max_age: Option<u32>,
+    pub scope: CacheScope,
+}
+
+impl CachePolicy {
+    // Restrict the policy by a field's hint: the shorter age and the narrower scope win
+    fn restrict(self, max_age: Option<u32>, scope: CacheScope) -> Self {
+        let max_age = match (self.max_age, max_age) {
+            (Some(current), Some(hint)) => Some(current.min(hint)),
+            (current, hint) => current.or(hint),
+        };
+        let scope = if scope == CacheScope::Private { scope } else { self.scope };
+        CachePolicy { max_age, scope }
+    }
+
+    // Return the Cache-Control header value for the policy; responses that may not be cached, including
+    // those whose fields gave no age at all, are not stored anywhere
+    pub fn header(&self) -> String {
+        match (self.max_age, self.scope) {
+            (Some(max_age), CacheScope::Public) if max_age > 0 => format!("max-age={}, public", max_age),
+            (Some(max_age), CacheScope::Private) if max_age > 0 => format!("max-age={}, private", max_age),
+            _ => "no-store".to_string(),
+        }
+    }
+}
+
+// Define the responses kept by the server, keyed by their query, operation, and variables. Only public
+// responses without errors are kept, each for the age its policy allows, and the least recently used are
+// evicted once it is full
+#[derive(Clone)]
+pub struct ResponseCache {
+    entries: Arc<Mutex<LruCache<String, CachedResponse>>>,
+}
+
+// Define a kept response: its data, and when it stops being fresh
+struct CachedResponse {
+    data: Value,
+    expires: Instant,
+}
+
+impl ResponseCache {
+    // Create a cache keeping up to `capacity` responses
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        ResponseCache { entries: Arc::new(Mutex::new(LruCache::new(capacity))) }
+    }
+
+    // Read RESPONSE_CACHE_SIZE, how many responses the server keeps. Without it responses are not kept,
+    // though their Cache-Control headers are still sent
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+        if settings.var("RESPONSE_CACHE_SIZE").is_none() {
+            return Ok(None);
+        }
+        let capacity = number_var(settings, "RESPONSE_CACHE_SIZE", 1)?;
+        Ok(Some(ResponseCache::new(NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN))))
+    }
+
+    // Return the data of a fresh response kept under a key, and how many seconds it stays fresh
+    fn get(&self, key: &str) -> Option<(Value, u32)> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        let remaining = entry.expires.saturating_duration_since(Instant::now()).as_secs() as u32;
+        if remaining > 0 {
+            return Some((entry.data.clone(), remaining));
+        }
+        entries.pop(key);
+        None
+    }
+
+    // Keep a response's data under a key for the given number of seconds
+    fn put(&self, key: String, data: Value, max_age: u32) {
+        let expires = Instant::now() + Duration::from_secs(max_age.into());
+        self.entries.lock().unwrap().put(key, CachedResponse { data, expires });
+    }
+}
+
+// Define the extension that computes the cache policy of every query from the hints of the fields it
+// resolves, sends it as the Cache-Control header of GET requests, and answers repeated public queries
+// from the response cache, when there is one; it is installed on every schema, after the others, so
+// persisted queries are resolved before a response is looked up
+#[derive(Clone, Default)]
+pub struct ResponseCaching {
+    cache: Option<ResponseCache>,
+}
+
+impl ResponseCaching {
+    // Keep public responses in the given cache, if any
+    pub fn new(cache: Option<ResponseCache>) -> Self {
+        ResponseCaching { cache }
+    }
+}
+
+impl ExtensionFactory for ResponseCaching {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(ResponseCachingExtension { cache: self.cache.clone(), request: Mutex::default() })
+    }
+}
+
+// Each request gets its own extension, which remembers the request's cache key, the operation it asked to
+// run, and the policy of the fields resolved so far
+struct ResponseCachingExtension {
+    cache: Option<ResponseCache>,
+    request: Mutex<RequestState>,
+}
+
+#[derive(Default)]
+struct RequestState {
+    key: String,
+    operation_name: Option<String>,
+    query: bool,
+    policy: CachePolicy,
+}
+
+#[async_trait]
+impl Extension for ResponseCachingExtension {
+    async fn prepare_request(&self, ctx: &ExtensionContext<'_>, request: Request, next: NextPrepareRequest<'_>) -> ServerResult<Request> {
+        {
+            let mut state = self.request.lock().unwrap();
+            state.key = cache_key(&request.query, request.operation_name.as_deref(), &request.variables);
+            state.operation_name.clone_from(&request.operation_name);
+        }
+        next.run(ctx, request).await
+    }
+
+    async fn parse_query(&self, ctx: &ExtensionContext<'_>, query: &str, variables: &Variables, next: NextParseQuery<'_>) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+        let mut state = self.request.lock().unwrap();
+        state.query = operation_type(&document, state.operation_name.as_deref()) == Some(OperationType::Query);
+        Ok(document)
+    }
+
+    async fn resolve(&self, ctx: &ExtensionContext<'_>, info: ResolveInfo<'_>, next: NextResolve<'_>) -> ServerResult<Option<Value>> {
+        if !info.is_for_introspection {
+            let (max_age, scope) = field_hint(ctx, &info);
+            let mut state = self.request.lock().unwrap();
+            state.policy = state.policy.restrict(max_age, scope);
+        }
+        next.run(ctx, info).await
+    }
+
+    async fn execute(&self, ctx: &ExtensionContext<'_>, operation_name: Option<&str>, next: NextExecute<'_>) -> Response {
+        let (key, query) = {
+            let state = self.request.lock().unwrap();
+            (state.key.clone(), state.query)
+        };
+        let cache = self.cache.as_ref().filter(|_| query);
+        let (mut response, policy) = match cache.and_then(|cache| cache.get(&key)) {
+            Some((data, remaining)) => (Response::new(data), CachePolicy { max_age: Some(remaining), scope: CacheScope::Public }),
+            None => {
+                let response = next.run(ctx, operation_name).await;
+                let policy = match query && response.errors.is_empty() {
+                    true => self.request.lock().unwrap().policy,
+                    false => CachePolicy::default(),
+                };
+                if let (Some(cache), Some(max_age), CacheScope::Public) = (cache, policy.max_age.filter(|max_age| *max_age > 0), policy.scope) {
+                    cache.put(key, response.data.clone(), max_age);
+                }
+                (response, policy)
+            }
+        };
+        // The policy is sent only with GET requests, which are the ones caches keep
+        response.cache_control = Default::default();
+        if ctx.data_opt::<GetRequest>().is_some() {
+            if let Ok(header) = HeaderValue::from_str(&policy.header()) {
+                response.http_headers.insert(CACHE_CONTROL, header);
+            }
+        }
+        response
+    }
+}
+
+// Build the key a request's response is kept under from what decides it for every caller
+fn cache_key(query: &str, operation_name: Option<&str>, variables: &Variables) -> String {
+    let variables = serde_json::to_string(variables).unwrap_or_default();
+    format!("{}\n{}\n{}", operation_name.unwrap_or_default(), variables, query)
+}
+
+// Find the hint a resolved field restricts the policy with: its own, else its type's when it returns an
+// object. Fields returning objects and fields of the query root without either may not be cached, and
+// other fields leave the age as it is
+fn field_hint(ctx: &ExtensionContext<'_>, info: &ResolveInfo<'_>) -> (Option<u32>, CacheScope) {
+    let registry = &ctx.schema_env.registry;
+    let field = registry.types.get(info.parent_type).and_then(|parent| parent.field_by_name(info.name));
+    let (field_age, field_scope) = field.map_or((None, CacheScope::Public), |field| read_hint(&field.directive_invocations));
+    let return_type = registry.types.get(info.return_type.trim_matches(|c| c == '[' || c == ']' || c == '!'));
+    let (type_age, type_scope) = match return_type {
+        Some(MetaType::Object { directive_invocations, .. }) => read_hint(directive_invocations),
+        _ => (None, CacheScope::Public),
+    };
+    let uncached = return_type.is_some_and(MetaType::is_composite) || info.parent_type == registry.query_type;
+    let max_age = field_age.or(type_age).or(uncached.then_some(0));
+    let scope = if field_scope == CacheScope::Private { field_scope } else { type_scope };
+    (max_age, scope)
+}
+
+// Read the age and scope of a @cacheControl directive, if one is among the directives
+fn read_hint(directives: &[MetaDirectiveInvocation]) -> (Option<u32>, CacheScope) {
+    let Some(directive) = directives.iter().find(|directive| directive.name == "cacheControl") else {
+        return (None, CacheScope::Public);
+    };
+    let max_age = match directive.args.get("maxAge") {
+        Some(Value::Number(max_age)) => max_age.as_u64().map(|max_age| max_age.min(u32::MAX.into()) as u32),
+        _ => None,
+    };
+    let scope = match directive.args.get("scope") {
+        Some(Value::String(scope)) if scope.eq_ignore_ascii_case("private") => CacheScope::Private,
+        _ => CacheScope::Public,
+    };
+    (max_age, scope)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::NewUser;
+    use crate::repository::{InMemoryRepository, UserRepository};
+    use crate::schema::{build_schema, AppState};
+
+    // Run a query as a GET request and return its Cache-Control header and user count, if it read one
+    async fn get(schema: &crate::schema::AppSchema, query: &str) -> (String, Option<u64>) {
+        let response = schema.execute(Request::new(query).data(GetRequest)).await;
+        let header = response.http_headers.get(CACHE_CONTROL).map(|value| value.to_str().unwrap().to_string()).unwrap_or_default();
+        let data = serde_json::to_value(&response.data).unwrap();
+        (header, data["stats"]["userCount"].as_u64())
+    }
+
+    // Define a test that responses get the policy of the fields they resolve, and only on GET
+    #[tokio::test]
+    async fn test_cache_policy() {
+        let schema = build_schema(AppState::new(Arc::new(InMemoryRepository::with_sample_users())));
+        assert_eq!(get(&schema, "{ stats { userCount byRole { role count } } }").await.0, "max-age=30, public");
+        assert_eq!(get(&schema, r#"{ post(id: "1") { title } }"#).await.0, "max-age=60, public");
+        assert_eq!(get(&schema, r#"{ post(id: "1") { title } stats { userCount } }"#).await.0, "max-age=30, public");
+
+        // Fields without hints, private fields, and mutations are not cached
+        assert_eq!(get(&schema, r#"{ stats { userCount } userById(id: "1") { name } }"#).await.0, "no-store");
+        assert_eq!(get(&schema, "{ me { name } }").await.0, "no-store");
+        assert_eq!(get(&schema, "{ __typename }").await.0, "no-store");
+        let response = schema.execute("{ stats { userCount } }").await;
+        assert!(response.http_headers.get(CACHE_CONTROL).is_none());
+        assert_eq!(response.cache_control, Default::default());
+
+        // Scopes and ages narrow as hints are combined
+        let policy = CachePolicy::default().restrict(Some(60), CacheScope::Public).restrict(None, CacheScope::Private);
+        assert_eq!(policy.header(), "max-age=60, private");
+        assert_eq!(policy.restrict(Some(0), CacheScope::Public).header(), "no-store");
+    }
+
+    // Define a test that public responses are answered from the cache until they expire
+    #[tokio::test]
+    async fn test_response_cache() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        let cache = ResponseCache::new(NonZeroUsize::new(10).unwrap());
+        let schema = build_schema(AppState::new(repository.clone()).with_response_cache(cache.clone()));
+        let query = "{ stats { userCount } }";
+        let (_, count) = get(&schema, query).await;
+        repository.create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() }).await.unwrap();
+
+        // The kept response is served, with the age it has left, until it expires
+        let (header, cached) = get(&schema, query).await;
+        assert_eq!(cached, count);
+        assert!(header.starts_with("max-age=") && header.ends_with(", public"), "{}", header);
+        cache.put(cache_key(query, None, &Variables::default()), Value::Null, 0);
+        assert_eq!(get(&schema, query).await.1, count.map(|count| count + 1));
+
+        // Other variables and uncacheable responses are not answered from it
+        let query = "query Stats($id: ID!) { stats { userCount } post(id: $id) { title } }";
+        let variables = |id: &str| Variables::from_json(serde_json::json!({ "id": id }));
+        schema.execute(Request::new(query).variables(variables("1"))).await;
+        assert!(cache.get(&cache_key(query, None, &variables("1"))).is_some());
+        assert!(cache.get(&cache_key(query, None, &variables("2"))).is_none());
+        schema.execute(r#"{ userById(id: "1") { name } }"#).await;
+        assert!(cache.get(&cache_key(r#"{ userById(id: "1") { name } }"#, None, &Variables::default())).is_none());
+    }
+}
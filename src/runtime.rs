@@ -0,0 +1,117 @@
+// Import necessary libraries and modules
+use std::fmt;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::config::Settings;
+
+// Define how many blocking threads the runtime may start when RUNTIME_MAX_BLOCKING_THREADS is not set,
+// which is Tokio's own default
+pub const DEFAULT_MAX_BLOCKING_THREADS: usize = 512;
+
+// Define the kinds of runtime the server can run on: worker threads sharing the tasks, or every task on
+// the main thread, which suits containers limited to a single CPU
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RuntimeFlavor {
+    MultiThread,
+    CurrentThread,
+}
+
+// Define the runtime the server runs on: its flavor, its worker threads, when set, and the largest number
+// of threads it starts for blocking work such as file reads and password hashing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RuntimeConfig {
+    pub flavor: RuntimeFlavor,
+    pub worker_threads: Option<usize>,
+    pub max_blocking_threads: usize,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        RuntimeConfig { flavor: RuntimeFlavor::MultiThread, worker_threads: None, max_blocking_threads: DEFAULT_MAX_BLOCKING_THREADS }
+    }
+}
+
+impl RuntimeConfig {
+    // Read RUNTIME_FLAVOR (multi-thread, the default, or current-thread), RUNTIME_WORKER_THREADS (default one
+    // per CPU), and RUNTIME_MAX_BLOCKING_THREADS (default 512)
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        let var = |name| settings.var(name);
+        RuntimeConfig::parse(var("RUNTIME_FLAVOR").as_deref(), var("RUNTIME_WORKER_THREADS").as_deref(), var("RUNTIME_MAX_BLOCKING_THREADS").as_deref())
+    }
+
+    // Read the flavor, worker thread, and blocking thread settings, if set
+    pub fn parse(flavor: Option<&str>, worker_threads: Option<&str>, max_blocking_threads: Option<&str>) -> Result<Self, String> {
+        let flavor = match flavor.map(str::trim) {
+            None | Some("multi-thread") => RuntimeFlavor::MultiThread,
+            Some("current-thread") => RuntimeFlavor::CurrentThread,
+            Some(other) => return Err(format!("RUNTIME_FLAVOR must be multi-thread or current-thread, got {:?}", other)),
+        };
+        let count = |name: &str, value: &str| value.trim().parse().ok().filter(|count| *count > 0).ok_or_else(|| format!("{} must be a positive number, got {:?}", name, value));
+        let worker_threads = worker_threads.map(|value| count("RUNTIME_WORKER_THREADS", value)).transpose()?;
+        if flavor == RuntimeFlavor::CurrentThread && worker_threads.is_some() {
+            return Err("RUNTIME_WORKER_THREADS does not apply to the current-thread runtime, which runs every task on the main thread".to_string());
+        }
+        let max_blocking_threads = max_blocking_threads.map(|value| count("RUNTIME_MAX_BLOCKING_THREADS", value)).transpose()?.unwrap_or(DEFAULT_MAX_BLOCKING_THREADS);
+        Ok(RuntimeConfig { flavor, worker_threads, max_blocking_threads })
+    }
+
+    // Build the runtime
+    pub fn build(&self) -> std::io::Result<Runtime> {
+        let mut builder = match self.flavor {
+            RuntimeFlavor::MultiThread => Builder::new_multi_thread(),
+            RuntimeFlavor::CurrentThread => Builder::new_current_thread(),
+        };
+        if let Some(worker_threads) = self.worker_threads {
+            builder.worker_threads(worker_threads);
+        }
+        builder.max_blocking_threads(self.max_blocking_threads).enable_all().build()
+    }
+}
+
+// Describe the runtime as it is logged at startup
+impl fmt::Display for RuntimeConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.flavor, self.worker_threads) {
+            (RuntimeFlavor::CurrentThread, _) => write!(f, "current-thread runtime")?,
+            (RuntimeFlavor::MultiThread, Some(workers)) => write!(f, "multi-thread runtime with {} worker threads", workers)?,
+            (RuntimeFlavor::MultiThread, None) => {
+                let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+                write!(f, "multi-thread runtime with {} worker threads (one per CPU)", cpus)?
+            }
+        }
+        write!(f, " and up to {} blocking threads", self.max_blocking_threads)
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Define a test for reading the runtime settings
+    #[test]
+    fn test_runtime_config() {
+        assert_eq!(RuntimeConfig::parse(None, None, None).unwrap(), RuntimeConfig::default());
+        let config = RuntimeConfig::parse(Some("multi-thread"), Some("2"), Some("16")).unwrap();
+        assert_eq!(config, RuntimeConfig { flavor: RuntimeFlavor::MultiThread, worker_threads: Some(2), max_blocking_threads: 16 });
+        assert_eq!(config.to_string(), "multi-thread runtime with 2 worker threads and up to 16 blocking threads");
+        let config = RuntimeConfig::parse(Some("current-thread"), None, None).unwrap();
+        assert_eq!(config.to_string(), "current-thread runtime and up to 512 blocking threads");
+
+        assert!(RuntimeConfig::parse(Some("single"), None, None).unwrap_err().contains("RUNTIME_FLAVOR must be"));
+        assert!(RuntimeConfig::parse(None, Some("0"), None).unwrap_err().contains("RUNTIME_WORKER_THREADS must be a positive number"));
+        assert!(RuntimeConfig::parse(None, None, Some("many")).unwrap_err().contains("RUNTIME_MAX_BLOCKING_THREADS must be a positive number"));
+        assert!(RuntimeConfig::parse(Some("current-thread"), Some("4"), None).unwrap_err().contains("does not apply to the current-thread runtime"));
+    }
+
+    // Define a test that both flavors of runtime run tasks
+    #[test]
+    fn test_build_runtime() {
+        for flavor in [RuntimeFlavor::MultiThread, RuntimeFlavor::CurrentThread] {
+            let config = RuntimeConfig { flavor, worker_threads: (flavor == RuntimeFlavor::MultiThread).then_some(2), max_blocking_threads: 2 };
+            let runtime = config.build().unwrap();
+            assert_eq!(runtime.block_on(async { tokio::spawn(async { 1 + 1 }).await.unwrap() }), 2);
+            assert_eq!(runtime.block_on(async { tokio::task::spawn_blocking(|| "blocking").await.unwrap() }), "blocking");
+        }
+    }
+}
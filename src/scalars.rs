@@ -0,0 +1,199 @@
+// Import necessary libraries and modules
+use async_graphql::{InputValueError, InputValueResult, Scalar, ScalarType, Value};
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::model::{is_valid_email, normalize_phone};
+
+// Define the DateTime scalar used for every timestamp in the schema. Backends keep times at different
+// precisions, so output is always RFC 3339 in UTC with microseconds and a Z suffix; input takes any
+// RFC 3339 time and converts it to UTC
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp(pub DateTime<Utc>);
+
+#[Scalar(name = "DateTime", specified_by_url = "https://datatracker.ietf.org/doc/html/rfc3339")]
+impl ScalarType for Timestamp {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match &value {
+            Value::String(text) => Ok(Timestamp(DateTime::parse_from_rfc3339(text)?.with_timezone(&Utc))),
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.0.to_rfc3339_opts(SecondsFormat::Micros, true))
+    }
+}
+
+impl From<DateTime<Utc>> for Timestamp {
+    fn from(time: DateTime<Utc>) -> Self {
+        Timestamp(time)
+    }
+}
+
+// Define the Email scalar taken wherever an email address is input. Addresses are trimmed and lowercased,
+// so one mailbox is always stored and looked up the same way, and anything that is not an address is
+// rejected before the resolver runs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Email(pub String);
+
+#[Scalar(name = "Email", specified_by_url = "https://datatracker.ietf.org/doc/html/rfc5322#section-3.4.1")]
+impl ScalarType for Email {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match &value {
+            Value::String(text) => {
+                let email = text.trim().to_lowercase();
+                if is_valid_email(&email) {
+                    Ok(Email(email))
+                } else {
+                    Err(InputValueError::custom(format!("{:?} is not a valid email address", text)))
+                }
+            }
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.0.clone())
+    }
+}
+
+// Define the PhoneNumber scalar for phone numbers, always in E.164 form (`+14155550123`). Input may use
+// spaces, dashes, dots, and parentheses, or a 00 prefix instead of the +, and is normalized before the
+// resolver runs; numbers without a country code are rejected with the reason
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PhoneNumber(pub String);
+
+#[Scalar(name = "PhoneNumber", specified_by_url = "https://www.itu.int/rec/T-REC-E.164")]
+impl ScalarType for PhoneNumber {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match &value {
+            Value::String(text) => normalize_phone(text).map(PhoneNumber).map_err(InputValueError::custom),
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.0.clone())
+    }
+}
+
+// Define the officially assigned ISO 3166-1 alpha-2 country codes, in alphabetical order
+const COUNTRY_CODES: [&str; 249] = [
+    "AD", "AE", "AF", "AG", "AI", "AL", "AM", "AO", "AQ", "AR", "AS", "AT", "AU", "AW", "AX", "AZ",
+    "BA", "BB", "BD", "BE", "BF", "BG", "BH", "BI", "BJ", "BL", "BM", "BN", "BO", "BQ", "BR", "BS", "BT", "BV", "BW", "BY", "BZ",
+    "CA", "CC", "CD", "CF", "CG", "CH", "CI", "CK", "CL", "CM", "CN", "CO", "CR", "CU", "CV", "CW", "CX", "CY", "CZ",
+    "DE", "DJ", "DK", "DM", "DO", "DZ",
+    "EC", "EE", "EG", "EH", "ER", "ES", "ET",
+    "FI", "FJ", "FK", "FM", "FO", "FR",
+    "GA", "GB", "GD", "GE", "GF", "GG", "GH", "GI", "GL", "GM", "GN", "GP", "GQ", "GR", "GS", "GT", "GU", "GW", "GY",
+    "HK", "HM", "HN", "HR", "HT", "HU",
+    "ID", "IE", "IL", "IM", "IN", "IO", "IQ", "IR", "IS", "IT",
+    "JE", "JM", "JO", "JP",
+    "KE", "KG", "KH", "KI", "KM", "KN", "KP", "KR", "KW", "KY", "KZ",
+    "LA", "LB", "LC", "LI", "LK", "LR", "LS", "LT", "LU", "LV", "LY",
+    "MA", "MC", "MD", "ME", "MF", "MG", "MH", "MK", "ML", "MM", "MN", "MO", "MP", "MQ", "MR", "MS", "MT", "MU", "MV", "MW", "MX", "MY", "MZ",
+    "NA", "NC", "NE", "NF", "NG", "NI", "NL", "NO", "NP", "NR", "NU", "NZ",
+    "OM",
+    "PA", "PE", "PF", "PG", "PH", "PK", "PL", "PM", "PN", "PR", "PS", "PT", "PW", "PY",
+    "QA",
+    "RE", "RO", "RS", "RU", "RW",
+    "SA", "SB", "SC", "SD", "SE", "SG", "SH", "SI", "SJ", "SK", "SL", "SM", "SN", "SO", "SR", "SS", "ST", "SV", "SX", "SY", "SZ",
+    "TC", "TD", "TF", "TG", "TH", "TJ", "TK", "TL", "TM", "TN", "TO", "TR", "TT", "TV", "TW", "TZ",
+    "UA", "UG", "UM", "US", "UY", "UZ",
+    "VA", "VC", "VE", "VG", "VI", "VN", "VU",
+    "WF", "WS",
+    "YE", "YT",
+    "ZA", "ZM", "ZW",
+];
+
+// Define the CountryCode scalar taken wherever a country is input: an ISO 3166-1 alpha-2 code such as `CA`.
+// Codes are trimmed and uppercased, and ones that are not assigned to a country are rejected before the
+// resolver runs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CountryCode(pub String);
+
+#[Scalar(name = "CountryCode", specified_by_url = "https://www.iso.org/iso-3166-country-codes.html")]
+impl ScalarType for CountryCode {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match &value {
+            Value::String(text) => {
+                let code = text.trim().to_uppercase();
+                match COUNTRY_CODES.binary_search(&code.as_str()) {
+                    Ok(_) => Ok(CountryCode(code)),
+                    Err(_) => Err(InputValueError::custom(format!("{:?} is not an ISO 3166-1 alpha-2 country code", text))),
+                }
+            }
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.0.clone())
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Define a test that timestamps are written in one format and read from any RFC 3339 offset
+    #[test]
+    fn test_timestamp_format() {
+        let time = DateTime::parse_from_rfc3339("2023-10-20T10:30:00.5+02:00").unwrap().with_timezone(&Utc);
+        assert_eq!(Timestamp(time).to_value(), Value::String("2023-10-20T08:30:00.500000Z".to_string()));
+        let parsed = Timestamp::parse(Value::String("2023-10-20T10:30:00.5+02:00".to_string())).unwrap();
+        assert_eq!(parsed, Timestamp(time));
+        assert!(Timestamp::parse(Value::String("yesterday".to_string())).is_err());
+        assert!(Timestamp::parse(Value::Number(1.into())).is_err());
+    }
+
+    // Define a test that email addresses are normalized and malformed ones rejected with the offending value
+    #[test]
+    fn test_email_parsing() {
+        let parsed = Email::parse(Value::String(" Ada.Lovelace@Example.COM ".to_string())).unwrap();
+        assert_eq!(parsed, Email("ada.lovelace@example.com".to_string()));
+        assert_eq!(parsed.to_value(), Value::String("ada.lovelace@example.com".to_string()));
+        for malformed in ["ada", "@example.com", "ada@example", "ada@.com", "ada@example.", "ada@@example.com", "ada lovelace@example.com"] {
+            let error = Email::parse(Value::String(malformed.to_string())).unwrap_err();
+            assert!(error.into_server_error(Default::default()).message.contains(&format!("{:?} is not a valid email address", malformed)));
+        }
+        assert!(Email::parse(Value::Number(1.into())).is_err());
+    }
+
+    // Define a test that phone numbers are normalized to E.164 and invalid ones rejected with the reason
+    #[test]
+    fn test_phone_number_parsing() {
+        let parse = |text: &str| PhoneNumber::parse(Value::String(text.to_string())).map(|phone| phone.0);
+        for (input, expected) in [
+            ("+1 (415) 555-0123", "+14155550123"),
+            (" +44 20 7946 0958 ", "+442079460958"),
+            ("0033.1.23.45.67.89", "+33123456789"),
+            ("+6834002", "+6834002"),
+        ] {
+            assert_eq!(parse(input).unwrap(), expected, "{}", input);
+        }
+        assert_eq!(PhoneNumber("+14155550123".to_string()).to_value(), Value::String("+14155550123".to_string()));
+
+        let message = |text: &str| parse(text).unwrap_err().into_server_error(Default::default()).message;
+        assert!(message("415-555-0123").contains("\"415-555-0123\" is not a valid phone number: it must start with + and a country code"));
+        assert!(message("+1 415 555 O123").contains("'O' is not a digit or separator"));
+        assert!(message("+0 415 555 0123").contains("country codes never start with 0"));
+        assert!(message("+1 555").contains("it has 4 digits, but E.164 numbers have 7 to 15"));
+        assert!(message("+1234567890123456").contains("it has 16 digits"));
+        assert!(PhoneNumber::parse(Value::Number(1.into())).is_err());
+    }
+
+    // Define a test that country codes are normalized and unassigned ones rejected
+    #[test]
+    fn test_country_code_parsing() {
+        assert!(COUNTRY_CODES.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(CountryCode::parse(Value::String(" ca ".to_string())).unwrap(), CountryCode("CA".to_string()));
+        assert_eq!(CountryCode::parse(Value::String("GB".to_string())).unwrap().to_value(), Value::String("GB".to_string()));
+        for invalid in ["UK", "CAN", "C", "", "XX"] {
+            let error = CountryCode::parse(Value::String(invalid.to_string())).unwrap_err();
+            assert!(error.into_server_error(Default::default()).message.contains(&format!("{:?} is not an ISO 3166-1 alpha-2 country code", invalid)));
+        }
+        assert!(CountryCode::parse(Value::Number(1.into())).is_err());
+    }
+}
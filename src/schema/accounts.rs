@@ -0,0 +1,768 @@
+// Import necessary libraries and modules
+use async_graphql::{Context, ErrorExtensions, Object, Result, ResultExt, SimpleObject};
+use chrono::Utc;
+use std::time::Instant;
+
+use super::{audit, publish, unsupported, viewer_user};
+use crate::audit::diff;
+use crate::auth::{generate_refresh_token, hash_api_key, hash_password, verify_password, AuthContext, Sessions};
+use crate::error::AppError;
+use crate::jwt::JwtSigner;
+use crate::log_error;
+use crate::login_throttle::{LoginKey, LoginThrottle};
+use crate::model::{NewUser, User, UserUpdate};
+use crate::node::{global_id, USER};
+use crate::password_reset::PasswordReset;
+use crate::repository::{PasswordRepository, RefreshTokenRepository, RepositoryError, SharedRepository};
+use crate::scalars::Email;
+use crate::subscription::UserEvent;
+use crate::totp::{generate_recovery_codes, generate_secret, hash_recovery_code, otpauth_url, verify_code, TotpEnrollment, TotpIssuer};
+use crate::verification::EmailVerification;
+
+// Define the payload returned by register, login, and refreshToken: a short-lived bearer token for the
+// user, the refresh token that renews it once, and the user as the caller of the mutation sees it
+#[derive(SimpleObject)]
+pub struct AuthPayload {
+    pub token: String,
+    pub refresh_token: String,
+    pub user: User,
+}
+
+// Define the mutations that create accounts with passwords, log into them, and keep or end the sessions
+#[derive(Default)]
+pub struct AccountMutation;
+
+// Implement GraphQL Object for the AccountMutation struct
+#[Object]
+impl AccountMutation {
+    async fn register(
+        &self,
+        ctx: &Context<'_>,
+        email: Email,
+        #[graphql(validator(min_length = 8, max_length = 128))] password: String,
+        #[graphql(validator(min_length = 1, max_length = 100))] name: String,
+    ) -> Result<AuthPayload> {
+        // Create a member with a password and log them in; each email address can only register once. When
+        // verification is enabled the member starts out unverified and is mailed a link to verify the address
+        let (repository, passwords) = password_login(ctx)?;
+        let (refresh_tokens, signer) = session_tokens(ctx)?;
+        if repository.get_by_email(&email.0).await.extend()?.is_some() {
+            return Err(AppError::Conflict(format!("A user with email {} already exists", email.0)).extend());
+        }
+        let password_hash = blocking(move || hash_password(&password)).await?;
+        let verification = ctx.data_opt::<EmailVerification>();
+        let new_user = NewUser { name, email: email.0, email_verified: verification.is_none(), ..Default::default() };
+        let user = repository.create(new_user).await.extend()?;
+        if let Err(error) = passwords.set_password_hash(&user.id, &password_hash).await {
+            // Without its password the account could never be logged into, so it is removed again
+            repository.purge(&user.id).await.extend()?;
+            return Err(AppError::from(error).extend());
+        }
+        audit(ctx, "register", Some(global_id(USER, &user.id)), diff(None, Some(&user))).await;
+        publish(ctx, UserEvent::Created(user.clone()));
+        if let Some(verification) = verification {
+            // The account is usable without the email, so a failure to send it does not fail the registration
+            if let Err(error) = verification.send(&user).await {
+                log_error!("Failed to send the verification email for user {}: {}", user.id, error);
+            }
+        }
+        start_session(ctx, &user.id).await?;
+        issue(refresh_tokens, signer, user).await
+    }
+
+    async fn verify_email(&self, ctx: &Context<'_>, token: String) -> Result<User> {
+        // Mark the user a verification link was mailed to as verified; verifying again changes nothing
+        let Some(verification) = ctx.data_opt::<EmailVerification>() else {
+            return Err(AppError::Unsupported("Email verification is not enabled; set EMAIL_VERIFICATION_URL to enable it".to_string()).extend());
+        };
+        let repository = ctx.data::<SharedRepository>()?;
+        let invalid = || AppError::Validation("The verification link is invalid or has expired".to_string()).extend();
+        let user_id = EmailVerification::token_user_id(&token).ok_or_else(invalid)?;
+        let user = repository.get(user_id).await.extend()?.filter(|user| verification.verify(&token, user)).ok_or_else(invalid)?;
+        if user.email_verified {
+            return Ok(user);
+        }
+        let update = UserUpdate { email_verified: Some(true), ..Default::default() };
+        let verified = repository.update(&user.id, update).await.extend()?.ok_or_else(invalid)?;
+        audit(ctx, "verifyEmail", Some(global_id(USER, &verified.id)), diff(Some(&user), Some(&verified))).await;
+        publish(ctx, UserEvent::Updated(verified.clone()));
+        Ok(verified)
+    }
+
+    async fn request_password_reset(&self, ctx: &Context<'_>, email: Email) -> Result<bool> {
+        // Mail a reset link to the user with the email address, if they have a password. The lookup and the
+        // email happen after the answer is sent, so it is the same, and as quick, whether or not they exist
+        let reset = password_reset(ctx)?;
+        let (repository, _) = password_login(ctx)?;
+        let (repository, reset) = (repository.clone(), reset.clone());
+        tokio::spawn(async move {
+            if let Err(error) = send_password_reset(&repository, &reset, &email.0).await {
+                log_error!("Failed to send a password reset email: {}", error);
+            }
+        });
+        Ok(true)
+    }
+
+    async fn reset_password(
+        &self,
+        ctx: &Context<'_>,
+        token: String,
+        #[graphql(validator(min_length = 8, max_length = 128))] new_password: String,
+    ) -> Result<bool> {
+        // Replace the password of the user a reset link was mailed to, which uses the link up, and revoke
+        // their refresh tokens and clear any lockout of their account
+        let reset = password_reset(ctx)?;
+        let (repository, passwords) = password_login(ctx)?;
+        let invalid = || AppError::Validation("The reset link is invalid or has expired".to_string()).extend();
+        let user_id = PasswordReset::token_user_id(&token).ok_or_else(invalid)?;
+        let user = repository.get(user_id).await.extend()?.ok_or_else(invalid)?;
+        let password_hash = passwords.get_password_hash(&user.id).await.extend()?.ok_or_else(invalid)?;
+        if !reset.verify(&token, &user.id, &password_hash) {
+            return Err(invalid());
+        }
+        let new_hash = blocking(move || hash_password(&new_password)).await?;
+        passwords.set_password_hash(&user.id, &new_hash).await.extend()?;
+        if let Some(refresh_tokens) = repository.refresh_tokens() {
+            refresh_tokens.revoke_refresh_tokens(&user.id).await.extend()?;
+        }
+        ctx.data::<LoginThrottle>()?.record_success(&user.email);
+        audit(ctx, "resetPassword", Some(global_id(USER, &user.id)), serde_json::Value::Null).await;
+        Ok(true)
+    }
+
+    async fn login(
+        &self,
+        ctx: &Context<'_>,
+        email: Email,
+        password: String,
+        totp_code: Option<String>,
+        recovery_code: Option<String>,
+    ) -> Result<AuthPayload> {
+        // Check the password of the user with the email address, and the second factor of users who enabled
+        // two-factor authentication, and return fresh tokens for them, unless the account or the client
+        // address is waiting out earlier failures
+        let (repository, passwords) = password_login(ctx)?;
+        let (refresh_tokens, signer) = session_tokens(ctx)?;
+        let throttle = ctx.data::<LoginThrottle>()?;
+        let client = ctx.data_opt::<AuthContext>().and_then(|auth| auth.client_ip);
+        if let Err(wait) = throttle.check(&email.0, client, Instant::now()) {
+            return Err(AppError::TooManyAttempts(wait.as_secs_f64().ceil() as u64).extend());
+        }
+        let user = repository.get_by_email(&email.0).await.extend()?;
+        let password_hash = match &user {
+            Some(user) => passwords.get_password_hash(&user.id).await.extend()?,
+            None => None,
+        };
+        let verified = match password_hash {
+            Some(password_hash) => blocking(move || Ok(verify_password(&password, &password_hash))).await?,
+            None => false,
+        };
+        let user = match user {
+            Some(user) if verified => user,
+            user => {
+                login_failed(ctx, throttle, &email.0, client, user.as_ref()).await;
+                return Err(AppError::Unauthenticated("Invalid email or password".to_string()).extend());
+            }
+        };
+        match second_factor(repository, &user, totp_code.as_deref(), recovery_code.as_deref()).await? {
+            SecondFactor::NotEnrolled | SecondFactor::Passed => {}
+            SecondFactor::Missing => return Err(AppError::TwoFactorRequired.extend()),
+            SecondFactor::Failed => {
+                login_failed(ctx, throttle, &email.0, client, Some(&user)).await;
+                return Err(AppError::Unauthenticated("Invalid two-factor code".to_string()).extend());
+            }
+        }
+        throttle.record_success(&email.0);
+        start_session(ctx, &user.id).await?;
+        issue(refresh_tokens, signer, user).await
+    }
+
+    async fn enroll_totp(&self, ctx: &Context<'_>) -> Result<TotpEnrollment> {
+        // Start enrolling the signed-in user in two-factor authentication with a new secret and recovery codes,
+        // replacing an enrollment that was never confirmed. Logins ask for a code once confirmTotp confirms it
+        let user = viewer_user(ctx).await?;
+        let two_factor = ctx.data::<SharedRepository>()?.two_factor().ok_or_else(|| unsupported("Two-factor enrollments"))?;
+        if two_factor.get_two_factor(&user.id).await.extend()?.is_some_and(|enrollment| enrollment.confirmed) {
+            return Err(AppError::Conflict("Two-factor authentication is already enabled".to_string()).extend());
+        }
+        let secret = generate_secret();
+        let recovery_codes = generate_recovery_codes();
+        let recovery_code_hashes: Vec<String> = recovery_codes.iter().map(|code| hash_recovery_code(code)).collect();
+        two_factor.set_two_factor(&user.id, &secret, &recovery_code_hashes).await.extend()?;
+        audit(ctx, "enrollTotp", Some(global_id(USER, &user.id)), serde_json::Value::Null).await;
+        let issuer = ctx.data::<TotpIssuer>()?;
+        Ok(TotpEnrollment { otpauth_url: otpauth_url(&issuer.0, &user.email, &secret), secret, recovery_codes })
+    }
+
+    async fn confirm_totp(&self, ctx: &Context<'_>, code: String) -> Result<bool> {
+        // Confirm the signed-in user's pending enrollment with a code from their authenticator app, which
+        // shows the app was set up; confirming again changes nothing
+        let user = viewer_user(ctx).await?;
+        let two_factor = ctx.data::<SharedRepository>()?.two_factor().ok_or_else(|| unsupported("Two-factor enrollments"))?;
+        let Some(enrollment) = two_factor.get_two_factor(&user.id).await.extend()? else {
+            return Err(AppError::Validation("Start enrolling with enrollTotp first".to_string()).extend());
+        };
+        if enrollment.confirmed {
+            return Ok(true);
+        }
+        if !verify_code(&enrollment.secret, &code, Utc::now().timestamp()) {
+            return Err(AppError::Validation("The code is not valid".to_string()).extend());
+        }
+        two_factor.confirm_two_factor(&user.id).await.extend()?;
+        audit(ctx, "confirmTotp", Some(global_id(USER, &user.id)), serde_json::Value::Null).await;
+        Ok(true)
+    }
+
+    async fn refresh_token(&self, ctx: &Context<'_>, refresh_token: String) -> Result<AuthPayload> {
+        // Trade a refresh token for a new access token and a new refresh token; each refresh token works once
+        let repository = ctx.data::<SharedRepository>()?;
+        let (refresh_tokens, signer) = session_tokens(ctx)?;
+        let invalid = || AppError::Unauthenticated("The refresh token is not valid".to_string()).extend();
+        let token = refresh_tokens.revoke_refresh_token(&hash_api_key(&refresh_token)).await.extend()?.ok_or_else(invalid)?;
+        if token.revoked_at.is_some() {
+            // A token that was already used has most likely been stolen, so every session of its user ends
+            refresh_tokens.revoke_refresh_tokens(&token.user_id).await.extend()?;
+            return Err(invalid());
+        }
+        if token.expires_at <= Utc::now() {
+            return Err(invalid());
+        }
+        let user = repository.get(&token.user_id).await.extend()?.ok_or_else(invalid)?;
+        issue(refresh_tokens, signer, user).await
+    }
+
+    async fn logout_all(&self, ctx: &Context<'_>) -> Result<i32> {
+        // Revoke every refresh token of the signed-in user and return how many were live. Access tokens
+        // already issued stay valid until they expire
+        let user = viewer_user(ctx).await?;
+        let refresh_tokens = ctx.data::<SharedRepository>()?.refresh_tokens().ok_or_else(|| unsupported("Refresh tokens"))?;
+        let revoked = refresh_tokens.revoke_refresh_tokens(&user.id).await.extend()?;
+        audit(ctx, "logoutAll", Some(global_id(USER, &user.id)), serde_json::Value::Null).await;
+        Ok(revoked as i32)
+    }
+
+    async fn logout(&self, ctx: &Context<'_>) -> Result<bool> {
+        // End the session the request was sent with and remove its cookie; returns false without a session
+        let Some(sessions) = ctx.data_opt::<Sessions>() else {
+            return Err(AppError::Unsupported("Sessions are not enabled".to_string()).extend());
+        };
+        let Some(token) = ctx.data_opt::<AuthContext>().and_then(|auth| auth.session.as_deref()) else {
+            return Ok(false);
+        };
+        sessions.end(token).await.extend()?;
+        for cookie in sessions.removal_cookies() {
+            ctx.append_http_header("set-cookie", cookie);
+        }
+        Ok(true)
+    }
+}
+
+// Return what password logins need: the repository and its password storage
+fn password_login<'a>(ctx: &Context<'a>) -> Result<(&'a SharedRepository, &'a dyn PasswordRepository)> {
+    let repository = ctx.data::<SharedRepository>()?;
+    let passwords = repository.passwords().ok_or_else(|| unsupported("Passwords"))?;
+    Ok((repository, passwords))
+}
+
+// Return the password reset settings, or an error when resets are not enabled
+fn password_reset<'a>(ctx: &Context<'a>) -> Result<&'a PasswordReset> {
+    ctx.data_opt::<PasswordReset>()
+        .ok_or_else(|| AppError::Unsupported("Password reset is not enabled; set PASSWORD_RESET_URL to enable it".to_string()).extend())
+}
+
+// Mail a reset link to the user with the email address when they have a password to reset
+async fn send_password_reset(repository: &SharedRepository, reset: &PasswordReset, email: &str) -> Result<(), String> {
+    let Some(user) = repository.get_by_email(email).await.map_err(|error| error.to_string())? else {
+        return Ok(());
+    };
+    let Some(passwords) = repository.passwords() else {
+        return Ok(());
+    };
+    match passwords.get_password_hash(&user.id).await.map_err(|error| error.to_string())? {
+        Some(password_hash) => reset.send(&user, &password_hash).await,
+        None => Ok(()),
+    }
+}
+
+// Count a failed login against the email address and the client address, recording any lockout it
+// triggers in the audit log
+async fn login_failed(ctx: &Context<'_>, throttle: &LoginThrottle, email: &str, client: Option<std::net::IpAddr>, user: Option<&User>) {
+    for lockout in throttle.record_failure(email, client, Instant::now()) {
+        let (target_id, subject) = match &lockout.key {
+            LoginKey::Account(email) => (user.map(|user| global_id(USER, &user.id)), serde_json::json!({ "email": email })),
+            LoginKey::Client(ip) => (None, serde_json::json!({ "clientIp": ip.to_string() })),
+        };
+        let details = serde_json::json!({ "failures": lockout.failures, "lockedForSeconds": lockout.duration.as_secs() });
+        audit(ctx, "loginLockout", target_id, serde_json::json!([subject, details])).await;
+    }
+}
+
+// Define how a login's second factor checked out
+enum SecondFactor {
+    NotEnrolled,
+    Passed,
+    Missing,
+    Failed,
+}
+
+// Check the second factor of a user with a confirmed two-factor enrollment: a current TOTP code, or else an
+// unused recovery code, which is used up
+async fn second_factor(repository: &SharedRepository, user: &User, totp_code: Option<&str>, recovery_code: Option<&str>) -> Result<SecondFactor> {
+    let Some(two_factor) = repository.two_factor() else {
+        return Ok(SecondFactor::NotEnrolled);
+    };
+    let enrollment = match two_factor.get_two_factor(&user.id).await.extend()? {
+        Some(enrollment) if enrollment.confirmed => enrollment,
+        _ => return Ok(SecondFactor::NotEnrolled),
+    };
+    if totp_code.is_none() && recovery_code.is_none() {
+        return Ok(SecondFactor::Missing);
+    }
+    if totp_code.is_some_and(|code| verify_code(&enrollment.secret, code, Utc::now().timestamp())) {
+        return Ok(SecondFactor::Passed);
+    }
+    if let Some(code) = recovery_code {
+        if two_factor.use_recovery_code(&user.id, &hash_recovery_code(code)).await.extend()? {
+            return Ok(SecondFactor::Passed);
+        }
+    }
+    Ok(SecondFactor::Failed)
+}
+
+// Return what issuing tokens needs: the refresh token storage and the key access tokens are signed with
+fn session_tokens<'a>(ctx: &Context<'a>) -> Result<(&'a dyn RefreshTokenRepository, &'a JwtSigner)> {
+    let Some(signer) = ctx.data_opt::<JwtSigner>() else {
+        return Err(AppError::Unsupported("Password login is not enabled; set JWT_SECRET to enable it".to_string()).extend());
+    };
+    let refresh_tokens = ctx.data::<SharedRepository>()?.refresh_tokens().ok_or_else(|| unsupported("Refresh tokens"))?;
+    Ok((refresh_tokens, signer))
+}
+
+// Start a session for the user when sessions are enabled, handing its token to the browser as a cookie
+// so browser clients are logged in without having to hold on to tokens
+async fn start_session(ctx: &Context<'_>, user_id: &str) -> Result<()> {
+    if let Some(sessions) = ctx.data_opt::<Sessions>() {
+        let token = sessions.create(user_id).await.extend()?;
+        for cookie in sessions.cookies(&token) {
+            ctx.append_http_header("set-cookie", cookie);
+        }
+    }
+    Ok(())
+}
+
+// Issue an access token and a stored refresh token for the user
+async fn issue(refresh_tokens: &dyn RefreshTokenRepository, signer: &JwtSigner, user: User) -> Result<AuthPayload> {
+    let refresh_token = generate_refresh_token();
+    let expires_at = Utc::now() + signer.refresh_ttl();
+    refresh_tokens.create_refresh_token(&user.id, &hash_api_key(&refresh_token), expires_at).await.extend()?;
+    Ok(AuthPayload { token: sign(signer, &user.id)?, refresh_token, user })
+}
+
+// Run password hashing off the async runtime, since it is slow by design
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> Result<T, String> + Send + 'static) -> Result<T> {
+    let outcome = tokio::task::spawn_blocking(work).await.map_err(|error| error.to_string());
+    outcome.and_then(|outcome| outcome).map_err(|error| AppError::from(RepositoryError::Backend(error)).extend())
+}
+
+// Issue a token for the user
+fn sign(signer: &JwtSigner, user_id: &str) -> Result<String> {
+    signer.sign(user_id).map_err(|error| AppError::from(RepositoryError::Backend(format!("failed to sign token: {}", error))).extend())
+}
+
+// Integration tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::{AuditFilter, InMemoryAuditStore, SharedAuditStore};
+    use crate::auth::{AuthContext, Authenticator, Credentials, Viewer};
+    use crate::jwt::JwtVerifier;
+    use crate::login_throttle::LoginThrottleConfig;
+    use crate::repository::{InMemoryRepository, TwoFactorRepository, UserRepository};
+    use crate::schema::tests::{sample_schema, CHARLIE};
+    use crate::schema::{build_schema, AppSchema, AppState};
+    use crate::verification::{EmailMessage, Mailer};
+    use async_graphql::Request;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    // Run a mutation and return its data and the code of its first error, if any
+    async fn execute(schema: &AppSchema, query: &str) -> (serde_json::Value, Option<serde_json::Value>) {
+        let response = serde_json::to_value(schema.execute(query).await).unwrap();
+        (response["data"].clone(), response.get("errors").map(|errors| errors[0]["extensions"]["code"].clone()))
+    }
+
+    // Define a test that registered users can log in with their password and the tokens they get are accepted
+    #[tokio::test]
+    async fn test_register_and_login() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        let schema = build_schema(AppState::new(repository.clone()).with_jwt_signer(JwtSigner::new(b"shared-secret")));
+        let register = r#"mutation { register(email: " Ada@Example.com ", password: "correct horse", name: "Ada") { token, user { id, name, role } } }"#;
+        let (data, code) = execute(&schema, register).await;
+        assert_eq!(code, None);
+        let user_id = repository.get_by_email("ada@example.com").await.unwrap().unwrap().id;
+        assert_eq!(data["register"]["user"], serde_json::json!({ "id": global_id(USER, &user_id).as_str(), "name": "Ada", "role": "MEMBER" }));
+
+        // Only an Argon2 hash of the password is stored
+        let password_hash = repository.get_password_hash(&user_id).await.unwrap().unwrap();
+        assert!(password_hash.starts_with("$argon2id$") && !password_hash.contains("correct horse"));
+
+        // The token authenticates requests as the new user
+        let authenticator = Authenticator::new(HashMap::new()).with_jwt(JwtVerifier::default().with_secret(b"shared-secret"));
+        let token = data["register"]["token"].as_str().unwrap();
+        let authorization = format!("Bearer {}", token);
+        let auth = authenticator.authenticate(Credentials { authorization: Some(&authorization), ..Default::default() }).await;
+        assert_eq!(auth.viewer, Viewer::User(user_id.clone()));
+
+        // Logging in matches the email address regardless of case and returns a fresh token
+        let (data, code) = execute(&schema, r#"mutation { login(email: "ADA@example.com", password: "correct horse") { token, user { name } } }"#).await;
+        assert_eq!(code, None);
+        assert_eq!(data["login"]["user"]["name"], "Ada");
+        assert!(data["login"]["token"].is_string());
+
+        // A wrong password, an unknown email, and a user without a password all fail the same way
+        for login in [
+            r#"mutation { login(email: "ada@example.com", password: "wrong horse") { token } }"#,
+            r#"mutation { login(email: "nobody@example.com", password: "correct horse") { token } }"#,
+            r#"mutation { login(email: "pavelboukine@gmail.com", password: "correct horse") { token } }"#,
+        ] {
+            assert_eq!(execute(&schema, login).await.1, Some(serde_json::json!("UNAUTHENTICATED")), "{}", login);
+        }
+
+        // An email address registers once, and short passwords are refused before anything is stored
+        assert_eq!(execute(&schema, register).await.1, Some(serde_json::json!("CONFLICT")));
+        let (_, code) = execute(&schema, r#"mutation { register(email: "grace@example.com", password: "short", name: "Grace") { token } }"#).await;
+        assert!(code.is_some());
+        assert_eq!(repository.get_by_email("grace@example.com").await.unwrap(), None);
+
+        // Password hashes never appear in the schema
+        assert!(!schema.sdl().to_lowercase().contains("hash"));
+    }
+
+    // Define a test that refresh tokens are rotated on use, that reusing one ends every session, and that logoutAll revokes them all
+    #[tokio::test]
+    async fn test_refresh_token_rotation() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        let schema = build_schema(AppState::new(repository.clone()).with_jwt_signer(JwtSigner::new(b"shared-secret")));
+        let register = r#"mutation { register(email: "ada@example.com", password: "correct horse", name: "Ada") { refreshToken } }"#;
+        let (data, _) = execute(&schema, register).await;
+        let first = data["register"]["refreshToken"].as_str().unwrap().to_string();
+        let refresh = |token: &str| format!(r#"mutation {{ refreshToken(refreshToken: "{}") {{ token, refreshToken, user {{ name }} }} }}"#, token);
+
+        // A refresh token is traded for a new pair once
+        let (data, code) = execute(&schema, &refresh(&first)).await;
+        assert_eq!(code, None);
+        assert_eq!(data["refreshToken"]["user"]["name"], "Ada");
+        assert!(data["refreshToken"]["token"].is_string());
+        let second = data["refreshToken"]["refreshToken"].as_str().unwrap().to_string();
+        assert_ne!(first, second);
+        let (data, _) = execute(&schema, &refresh(&second)).await;
+        let third = data["refreshToken"]["refreshToken"].as_str().unwrap().to_string();
+
+        // Presenting a used token again fails and revokes the tokens issued after it as well
+        assert_eq!(execute(&schema, &refresh(&second)).await.1, Some(serde_json::json!("UNAUTHENTICATED")));
+        assert_eq!(execute(&schema, &refresh(&third)).await.1, Some(serde_json::json!("UNAUTHENTICATED")));
+        assert_eq!(execute(&schema, &refresh("refresh_unknown")).await.1, Some(serde_json::json!("UNAUTHENTICATED")));
+
+        // Logging out everywhere revokes every live token of the signed-in user only
+        let login = r#"mutation { login(email: "ada@example.com", password: "correct horse") { refreshToken } }"#;
+        let mut tokens = Vec::new();
+        for _ in 0..2 {
+            tokens.push(execute(&schema, login).await.0["login"]["refreshToken"].as_str().unwrap().to_string());
+        }
+        let user_id = repository.get_by_email("ada@example.com").await.unwrap().unwrap().id;
+        let logout = |viewer: Viewer| async { serde_json::to_value(schema.execute(Request::new("mutation { logoutAll }").data(AuthContext::from(viewer))).await).unwrap() };
+        assert_eq!(logout(Viewer::Anonymous).await["errors"][0]["extensions"]["code"], "UNAUTHENTICATED");
+        assert_eq!(logout(Viewer::User(user_id.clone())).await["data"]["logoutAll"], 2);
+        assert_eq!(logout(Viewer::User(user_id)).await["data"]["logoutAll"], 0);
+        for token in &tokens {
+            assert_eq!(execute(&schema, &refresh(token)).await.1, Some(serde_json::json!("UNAUTHENTICATED")));
+        }
+
+        // Tokens of users who have since been deleted are refused too
+        let (data, _) = execute(&schema, login).await;
+        let token = data["login"]["refreshToken"].as_str().unwrap().to_string();
+        let user_id = repository.get_by_email("ada@example.com").await.unwrap().unwrap().id;
+        repository.delete(&user_id).await.unwrap();
+        assert_eq!(execute(&schema, &refresh(&token)).await.1, Some(serde_json::json!("UNAUTHENTICATED")));
+    }
+
+    // Define a test that logging in starts a cookie session when sessions are enabled, and that logout ends it
+    #[tokio::test]
+    async fn test_session_cookies() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        let sessions = Sessions::default();
+        let state = AppState::new(repository.clone()).with_jwt_signer(JwtSigner::new(b"shared-secret")).with_sessions(sessions.clone());
+        let schema = build_schema(state);
+        let register = r#"mutation { register(email: "ada@example.com", password: "correct horse", name: "Ada") { token } }"#;
+        assert!(schema.execute(register).await.is_ok());
+        let response = schema.execute(r#"mutation { login(email: "ada@example.com", password: "correct horse") { token } }"#).await;
+        let cookie = response.http_headers.get("set-cookie").unwrap().to_str().unwrap().to_string();
+        assert!(cookie.contains("HttpOnly") && cookie.contains("SameSite=Lax") && cookie.contains("Path=/"), "{}", cookie);
+        let csrf_cookie = response.http_headers.get_all("set-cookie").iter().nth(1).unwrap().to_str().unwrap().to_string();
+        assert!(csrf_cookie.starts_with("csrf_token=") && !csrf_cookie.contains("HttpOnly"), "{}", csrf_cookie);
+
+        // The cookie carries a session of the user
+        let token = cookie.strip_prefix("session=").unwrap().split(';').next().unwrap().to_string();
+        let user_id = repository.get_by_email("ada@example.com").await.unwrap().unwrap().id;
+        assert_eq!(sessions.user_id(&token).await.unwrap(), Some(user_id.clone()));
+
+        // Logging out ends the session the request came with and clears the cookie
+        let auth = AuthContext { viewer: Viewer::User(user_id.clone()), session: Some(token.clone()), ..Default::default() };
+        let response = schema.execute(Request::new("mutation { logout }").data(auth)).await;
+        assert_eq!(response.data.to_string(), "{logout: true}");
+        assert!(response.http_headers.get("set-cookie").unwrap().to_str().unwrap().contains("Max-Age=0"));
+        assert_eq!(sessions.user_id(&token).await.unwrap(), None);
+
+        // Requests authenticated some other way have no session to end
+        let response = schema.execute(Request::new("mutation { logout }").data(AuthContext::from(Viewer::User(user_id)))).await;
+        assert_eq!(response.data.to_string(), "{logout: false}");
+        assert!(response.http_headers.get("set-cookie").is_none());
+    }
+
+    // Define a test that repeated wrong passwords lock the account out, even for the right one, and that the lockout is audited
+    #[tokio::test]
+    async fn test_login_lockout() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        let audit_store: SharedAuditStore = Arc::new(InMemoryAuditStore::default());
+        let config = LoginThrottleConfig { max_account_failures: 2, max_client_failures: 10, backoff: Duration::ZERO, lockout: Duration::from_secs(60) };
+        let state = AppState::new(repository.clone())
+            .with_jwt_signer(JwtSigner::new(b"shared-secret"))
+            .with_audit_store(audit_store.clone())
+            .with_login_throttle(LoginThrottle::new(config));
+        let schema = build_schema(state);
+        let register = r#"mutation { register(email: "ada@example.com", password: "correct horse", name: "Ada") { token } }"#;
+        assert_eq!(execute(&schema, register).await.1, None);
+        let login = |password: &str| format!(r#"mutation {{ login(email: "ada@example.com", password: "{}") {{ token }} }}"#, password);
+        for _ in 0..2 {
+            assert_eq!(execute(&schema, &login("wrong horse")).await.1, Some(serde_json::json!("UNAUTHENTICATED")));
+        }
+        assert_eq!(execute(&schema, &login("correct horse")).await.1, Some(serde_json::json!("TOO_MANY_ATTEMPTS")));
+
+        // Other accounts can still log in
+        let register = r#"mutation { register(email: "grace@example.com", password: "correct horse", name: "Grace") { token } }"#;
+        assert_eq!(execute(&schema, register).await.1, None);
+        let login = r#"mutation { login(email: "grace@example.com", password: "correct horse") { token } }"#;
+        assert_eq!(execute(&schema, login).await.1, None);
+
+        // The lockout is recorded once, against the locked user
+        let filter = AuditFilter { action: Some("loginLockout".to_string()), ..Default::default() };
+        let entries = audit_store.list(&filter, None, 10).await.unwrap();
+        let user_id = repository.get_by_email("ada@example.com").await.unwrap().unwrap().id;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].target_id.as_deref(), Some(global_id(USER, &user_id).as_str()));
+    }
+
+    // Define a mailer that keeps the messages it is asked to send
+    #[derive(Default)]
+    struct RecordingMailer(Mutex<Vec<EmailMessage>>);
+
+    #[async_trait::async_trait]
+    impl Mailer for RecordingMailer {
+        async fn send(&self, message: &EmailMessage) -> std::result::Result<(), String> {
+            self.0.lock().unwrap().push(message.clone());
+            Ok(())
+        }
+    }
+
+    // Define a test that registered users stay unverified, and are kept from guarded mutations, until they follow the mailed link
+    #[tokio::test]
+    async fn test_email_verification() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        let mailer = Arc::new(RecordingMailer::default());
+        let verification = EmailVerification::new(b"signing-key".to_vec(), "https://app.example.com/verify", mailer.clone());
+        let state = AppState::new(repository.clone()).with_jwt_signer(JwtSigner::new(b"shared-secret")).with_email_verification(verification);
+        let schema = build_schema(state);
+        let register = r#"mutation { register(email: "ada@example.com", password: "correct horse", name: "Ada") { token } }"#;
+        assert_eq!(execute(&schema, register).await.1, None);
+        let user = repository.get_by_email("ada@example.com").await.unwrap().unwrap();
+        assert!(!user.email_verified);
+
+        // The link is mailed to the new address
+        let message = mailer.0.lock().unwrap().pop().unwrap();
+        assert_eq!((message.to.as_str(), message.subject.as_str()), ("ada@example.com", "Verify your email address"));
+        let token = message.body.split("token=").nth(1).unwrap().split_whitespace().next().unwrap().to_string();
+
+        // Until then the user can log in but not write, and leaving out the credentials does not get around it
+        let login = r#"mutation { login(email: "ada@example.com", password: "correct horse") { user { emailVerified } } }"#;
+        assert_eq!(execute(&schema, login).await.0["login"]["user"]["emailVerified"], false);
+        let create_post = format!(r#"mutation {{ createPost(input: {{ authorId: "{}", title: "Hello", body: "First post" }}) {{ title }} }}"#, user.id);
+        let as_ada = || AuthContext::from(Viewer::User(user.id.clone()));
+        let response = serde_json::to_value(schema.execute(Request::new(create_post.clone()).data(as_ada())).await).unwrap();
+        assert_eq!(response["errors"][0]["extensions"]["code"], "EMAIL_NOT_VERIFIED");
+        assert_eq!(execute(&schema, &create_post).await.1, Some(serde_json::json!("UNAUTHENTICATED")));
+
+        // Tampered links are refused, and the mailed one verifies the address, as often as it is followed
+        let verify = |token: &str| format!(r#"mutation {{ verifyEmail(token: "{}") {{ emailVerified }} }}"#, token);
+        assert_eq!(execute(&schema, &verify(&format!("{}0", token))).await.1, Some(serde_json::json!("VALIDATION_FAILED")));
+        assert_eq!(execute(&schema, &verify("nonsense")).await.1, Some(serde_json::json!("VALIDATION_FAILED")));
+        for _ in 0..2 {
+            let (data, code) = execute(&schema, &verify(&token)).await;
+            assert_eq!(code, None);
+            assert_eq!(data["verifyEmail"]["emailVerified"], true);
+        }
+        let response = serde_json::to_value(schema.execute(Request::new(create_post).data(as_ada())).await).unwrap();
+        assert_eq!(response["data"]["createPost"]["title"], "Hello");
+        assert!(repository.get(&user.id).await.unwrap().unwrap().email_verified);
+
+        // Without verification enabled, registered users are verified from the start and links are unsupported
+        let schema = build_schema(AppState::new(repository.clone()).with_jwt_signer(JwtSigner::new(b"shared-secret")));
+        let register = r#"mutation { register(email: "grace@example.com", password: "correct horse", name: "Grace") { token } }"#;
+        assert_eq!(execute(&schema, register).await.1, None);
+        assert!(repository.get_by_email("grace@example.com").await.unwrap().unwrap().email_verified);
+        assert_eq!(execute(&schema, &verify(&token)).await.1, Some(serde_json::json!("UNSUPPORTED")));
+    }
+
+    // Define a test that a user who changes their email address is unverified, and kept from guarded
+    // mutations, until they follow the link mailed to the new address
+    #[tokio::test]
+    async fn test_email_change_verification() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        let mailer = Arc::new(RecordingMailer::default());
+        let verification = EmailVerification::new(b"signing-key".to_vec(), "https://app.example.com/verify", mailer.clone());
+        let schema = build_schema(AppState::new(repository.clone()).with_email_verification(verification));
+        let as_charlie = |query: String| schema.execute(Request::new(query).data(AuthContext::from(Viewer::User(CHARLIE.to_string()))));
+        let update = |version: i32, input: &str| format!(r#"mutation {{ updateUser(id: "{}", expectedVersion: {}, input: {}) {{ email, emailVerified }} }}"#, CHARLIE, version, input);
+
+        // Changing other fields, or the address only in case, leaves it verified and mails nothing
+        let response = serde_json::to_value(as_charlie(update(1, r#"{ name: "Chuck", email: "Charlie.Gracie@noibu.com" }"#)).await).unwrap();
+        assert_eq!(response["data"]["updateUser"]["emailVerified"], true, "{}", response);
+        assert!(mailer.0.lock().unwrap().is_empty());
+
+        // A new address is unverified, and the link is mailed to it
+        let response = serde_json::to_value(as_charlie(update(2, r#"{ email: "chuck@example.com" }"#)).await).unwrap();
+        assert_eq!(response["data"]["updateUser"], serde_json::json!({ "email": "chuck@example.com", "emailVerified": false }));
+        let message = mailer.0.lock().unwrap().pop().unwrap();
+        assert_eq!(message.to, "chuck@example.com");
+        let token = message.body.split("token=").nth(1).unwrap().split_whitespace().next().unwrap().to_string();
+
+        // Until it is followed, guarded mutations are refused
+        let create_post = format!(r#"mutation {{ createPost(input: {{ authorId: "{}", title: "Hello", body: "First post" }}) {{ title }} }}"#, CHARLIE);
+        let response = serde_json::to_value(as_charlie(create_post.clone()).await).unwrap();
+        assert_eq!(response["errors"][0]["extensions"]["code"], "EMAIL_NOT_VERIFIED");
+        let verify = format!(r#"mutation {{ verifyEmail(token: "{}") {{ emailVerified }} }}"#, token);
+        assert_eq!(execute(&schema, &verify).await, (serde_json::json!({ "verifyEmail": { "emailVerified": true } }), None));
+        let response = serde_json::to_value(as_charlie(create_post).await).unwrap();
+        assert_eq!(response["data"]["createPost"]["title"], "Hello", "{}", response);
+
+        // Without verification enabled, addresses are not checked, so a new one stays verified
+        let schema = build_schema(AppState::new(repository.clone()));
+        let request = Request::new(update(4, r#"{ email: "charles@example.com" }"#)).data(AuthContext::from(Viewer::User(CHARLIE.to_string())));
+        let response = serde_json::to_value(schema.execute(request).await).unwrap();
+        assert_eq!(response["data"]["updateUser"]["emailVerified"], true, "{}", response);
+    }
+
+    // Define a test that a mailed reset link sets a new password once, revoking refresh tokens, and that requests look the same for unknown addresses
+    #[tokio::test]
+    async fn test_password_reset() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        let mailer = Arc::new(RecordingMailer::default());
+        let reset = PasswordReset::new(b"signing-key".to_vec(), "https://app.example.com/reset", mailer.clone());
+        let schema = build_schema(AppState::new(repository.clone()).with_jwt_signer(JwtSigner::new(b"shared-secret")).with_password_reset(reset));
+        let register = r#"mutation { register(email: "ada@example.com", password: "correct horse", name: "Ada") { refreshToken } }"#;
+        let (data, _) = execute(&schema, register).await;
+        let refresh_token = data["register"]["refreshToken"].as_str().unwrap().to_string();
+
+        // Known addresses, unknown ones, and users without a password all get the same answer, but only the first is mailed
+        for email in ["nobody@example.com", "pavelboukine@gmail.com", "ADA@example.com"] {
+            let (data, code) = execute(&schema, &format!(r#"mutation {{ requestPasswordReset(email: "{}") }}"#, email)).await;
+            assert_eq!((data["requestPasswordReset"].clone(), code), (serde_json::json!(true), None));
+        }
+        let mailed = async {
+            loop {
+                if let Some(message) = mailer.0.lock().unwrap().pop() {
+                    break message;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let message = tokio::time::timeout(Duration::from_secs(5), mailed).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(mailer.0.lock().unwrap().is_empty());
+        assert_eq!((message.to.as_str(), message.subject.as_str()), ("ada@example.com", "Reset your password"));
+        let token = message.body.split("token=").nth(1).unwrap().split_whitespace().next().unwrap().to_string();
+
+        // Tampered links and short passwords are refused, and the mailed link sets the new password once
+        let reset = |token: &str, password: &str| format!(r#"mutation {{ resetPassword(token: "{}", newPassword: "{}") }}"#, token, password);
+        assert_eq!(execute(&schema, &reset(&format!("{}0", token), "battery staple")).await.1, Some(serde_json::json!("VALIDATION_FAILED")));
+        assert!(execute(&schema, &reset(&token, "short")).await.1.is_some());
+        assert_eq!(execute(&schema, &reset(&token, "battery staple")).await, (serde_json::json!({ "resetPassword": true }), None));
+        assert_eq!(execute(&schema, &reset(&token, "another staple")).await.1, Some(serde_json::json!("VALIDATION_FAILED")));
+
+        // The new password logs in and the old one no longer does, and refresh tokens from before the reset are revoked
+        let login = |password: &str| format!(r#"mutation {{ login(email: "ada@example.com", password: "{}") {{ token }} }}"#, password);
+        assert_eq!(execute(&schema, &login("battery staple")).await.1, None);
+        assert_eq!(execute(&schema, &login("correct horse")).await.1, Some(serde_json::json!("UNAUTHENTICATED")));
+        let refresh = format!(r#"mutation {{ refreshToken(refreshToken: "{}") {{ token }} }}"#, refresh_token);
+        assert_eq!(execute(&schema, &refresh).await.1, Some(serde_json::json!("UNAUTHENTICATED")));
+
+        // Without reset links configured, both mutations are unsupported
+        let schema = build_schema(AppState::new(repository.clone()).with_jwt_signer(JwtSigner::new(b"shared-secret")));
+        let request = r#"mutation { requestPasswordReset(email: "ada@example.com") }"#;
+        assert_eq!(execute(&schema, request).await.1, Some(serde_json::json!("UNSUPPORTED")));
+        assert_eq!(execute(&schema, &reset(&token, "battery staple")).await.1, Some(serde_json::json!("UNSUPPORTED")));
+    }
+
+    // Define a test that logins of users with confirmed two-factor enrollments need a current code or an unused recovery code
+    #[tokio::test]
+    async fn test_two_factor_login() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        let audit_store: SharedAuditStore = Arc::new(InMemoryAuditStore::default());
+        let config = LoginThrottleConfig { backoff: Duration::ZERO, ..Default::default() };
+        let state = AppState::new(repository.clone())
+            .with_jwt_signer(JwtSigner::new(b"shared-secret"))
+            .with_audit_store(audit_store.clone())
+            .with_login_throttle(LoginThrottle::new(config));
+        let schema = build_schema(state);
+        let register = r#"mutation { register(email: "ada@example.com", password: "correct horse", name: "Ada") { user { id } } }"#;
+        assert_eq!(execute(&schema, register).await.1, None);
+        let user_id = repository.get_by_email("ada@example.com").await.unwrap().unwrap().id;
+        let as_ada = |query: &str| Request::new(query).data(AuthContext::from(Viewer::User(user_id.clone())));
+
+        // Enrolling returns the secret, its URL, and recovery codes, of which only hashes are stored
+        let response = serde_json::to_value(schema.execute(as_ada("mutation { enrollTotp { secret, otpauthUrl, recoveryCodes } }")).await).unwrap();
+        let enrollment = &response["data"]["enrollTotp"];
+        let secret = enrollment["secret"].as_str().unwrap().to_string();
+        assert_eq!(enrollment["otpauthUrl"], format!("otpauth://totp/rust-graphql-server:ada%40example.com?secret={}&issuer=rust-graphql-server&algorithm=SHA1&digits=6&period=30", secret));
+        let recovery_codes: Vec<String> = serde_json::from_value(enrollment["recoveryCodes"].clone()).unwrap();
+        let stored = repository.get_two_factor(&user_id).await.unwrap().unwrap();
+        assert!(!stored.confirmed && !stored.recovery_code_hashes.contains(&recovery_codes[0]));
+
+        // Until the enrollment is confirmed with a valid code, logins need only the password
+        let login = |second_factor: &str| format!(r#"mutation {{ login(email: "ada@example.com", password: "correct horse"{}) {{ token }} }}"#, second_factor);
+        assert_eq!(execute(&schema, &login("")).await.1, None);
+        let confirm = |code: &str| as_ada(&format!(r#"mutation {{ confirmTotp(code: "{}") }}"#, code));
+        let response = serde_json::to_value(schema.execute(confirm("000000")).await).unwrap();
+        assert_eq!(response["errors"][0]["extensions"]["code"], "VALIDATION_FAILED");
+        let code = crate::totp::current_code(&secret, Utc::now().timestamp()).unwrap();
+        assert_eq!(serde_json::to_value(schema.execute(confirm(&code)).await).unwrap()["data"]["confirmTotp"], true);
+
+        // Then a login without a second factor is asked for one, and a wrong one fails
+        assert_eq!(execute(&schema, &login("")).await.1, Some(serde_json::json!("TWO_FACTOR_REQUIRED")));
+        assert_eq!(execute(&schema, &login(r#", totpCode: "000000""#)).await.1, Some(serde_json::json!("UNAUTHENTICATED")));
+        assert_eq!(execute(&schema, &login(&format!(r#", totpCode: "{}""#, code))).await.1, None);
+
+        // Recovery codes work once each
+        let recovery = login(&format!(r#", recoveryCode: "{}""#, recovery_codes[0].to_uppercase()));
+        assert_eq!(execute(&schema, &recovery).await.1, None);
+        assert_eq!(execute(&schema, &recovery).await.1, Some(serde_json::json!("UNAUTHENTICATED")));
+
+        // A confirmed enrollment cannot be replaced, and both steps are audited
+        let response = serde_json::to_value(schema.execute(as_ada("mutation { enrollTotp { secret } }")).await).unwrap();
+        assert_eq!(response["errors"][0]["extensions"]["code"], "CONFLICT");
+        for action in ["enrollTotp", "confirmTotp"] {
+            let filter = AuditFilter { action: Some(action.to_string()), ..Default::default() };
+            assert_eq!(audit_store.list(&filter, None, 10).await.unwrap().len(), 1);
+        }
+    }
+
+    // Define a test that the mutations are unsupported without a signing secret
+    #[tokio::test]
+    async fn test_password_login_disabled() {
+        let schema = sample_schema();
+        let register = r#"mutation { register(email: "ada@example.com", password: "correct horse", name: "Ada") { token } }"#;
+        assert_eq!(execute(&schema, register).await.1, Some(serde_json::json!("UNSUPPORTED")));
+        let login = r#"mutation { login(email: "ada@example.com", password: "correct horse") { token } }"#;
+        assert_eq!(execute(&schema, login).await.1, Some(serde_json::json!("UNSUPPORTED")));
+    }
+}
@@ -0,0 +1,291 @@
+// Import necessary libraries and modules
+use async_graphql::connection::{query, Connection, Edge, OpaqueCursor};
+use async_graphql::{Context, ErrorExtensions, InputObject, Object, Result, ResultExt, SimpleObject, ID};
+
+use super::{audit, unsupported, user_id_argument, MaxPageSize, RoleGuard, DEFAULT_MAX_PAGE_SIZE};
+use crate::audit::{diff, AuditEntry, AuditFilter, SharedAuditStore};
+use crate::auth::{generate_api_key, hash_api_key};
+use crate::complexity::{list_cost, page_items};
+use crate::error::AppError;
+use crate::model::{ApiKey, NewApiKey, ServiceAccount, UserRole};
+use crate::node::{global_id, USER};
+use crate::persisted::PersistedQueries;
+use crate::repository::SharedRepository;
+use crate::scalars::Timestamp;
+use crate::search::{reindex_users, SharedSearchIndex};
+use crate::service_accounts::ServiceAccounts;
+
+// Define the conditions the auditLog query can narrow its entries by; every given condition must hold
+#[derive(Default, InputObject)]
+#[graphql(name = "AuditLogFilter")]
+pub struct AuditLogFilterInput {
+    pub action: Option<String>,
+    pub actor_id: Option<ID>,
+    pub target_id: Option<ID>,
+    pub created_after: Option<Timestamp>,
+    pub created_before: Option<Timestamp>,
+}
+
+impl AuditLogFilterInput {
+    // Convert the GraphQL input into the audit store's filter; target IDs are matched as the global IDs entries report
+    fn into_filter(self) -> Result<AuditFilter> {
+        Ok(AuditFilter {
+            action: self.action,
+            actor_id: self.actor_id.map(|actor_id| user_id_argument(&actor_id)).transpose()?,
+            target_id: self.target_id.map(|target_id| target_id.0),
+            created_after: self.created_after.map(|after| after.0),
+            created_before: self.created_before.map(|before| before.0),
+        })
+    }
+}
+
+// Define the Relay connection returned by auditLog; cursors are opaque entry IDs, newest first
+pub type AuditConnection = Connection<OpaqueCursor<u64>, AuditEntry>;
+
+// Define the queries operators use to inspect the service
+#[derive(Default)]
+pub struct AdminQuery;
+
+// Implement GraphQL Object for the AdminQuery struct
+#[Object]
+impl AdminQuery {
+    #[graphql(guard = "RoleGuard::new(UserRole::Admin)", complexity = "list_cost(page_items(first), child_complexity)")]
+    async fn audit_log(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+        filter: Option<AuditLogFilterInput>,
+    ) -> Result<AuditConnection> {
+        // Page through the recorded mutations, newest first
+        let audit_store = ctx.data::<SharedAuditStore>()?;
+        let filter = filter.unwrap_or_default().into_filter()?;
+        let max_page_size = ctx.data_opt::<MaxPageSize>().map_or(DEFAULT_MAX_PAGE_SIZE, |max| max.0);
+        query(after, None, first, None, |after: Option<OpaqueCursor<u64>>, _: Option<OpaqueCursor<u64>>, first, _| async move {
+            let limit = first.unwrap_or(max_page_size).min(max_page_size);
+            let mut entries = audit_store.list(&filter, after.as_ref().map(|after| after.0), limit + 1).await.extend()?;
+            let has_next_page = entries.len() > limit;
+            entries.truncate(limit);
+
+            let mut connection = Connection::new(after.is_some(), has_next_page);
+            connection.edges.extend(entries.into_iter().map(|entry| Edge::new(OpaqueCursor(entry.id), entry)));
+            Ok::<_, async_graphql::Error>(connection)
+        })
+        .await
+    }
+
+    #[graphql(guard = "RoleGuard::new(UserRole::Admin)")]
+    async fn service_accounts(&self, ctx: &Context<'_>) -> Vec<ServiceAccount> {
+        // Return the service accounts configured with the server
+        ctx.data_opt::<ServiceAccounts>().map(|accounts| accounts.0.clone()).unwrap_or_default()
+    }
+}
+
+// Define the payload returned by the createApiKey mutation; the key itself is only ever returned here
+#[derive(SimpleObject)]
+pub struct CreateApiKeyPayload {
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+// Define the mutations operators use to maintain the service
+#[derive(Default)]
+pub struct AdminMutation;
+
+// Implement GraphQL Object for the AdminMutation struct
+#[Object]
+impl AdminMutation {
+    #[graphql(guard = "RoleGuard::new(UserRole::Admin)")]
+    async fn reindex_users(&self, ctx: &Context<'_>) -> Result<i32> {
+        // Rebuild the search index from the repository and return how many users it now holds
+        let Some(index) = ctx.data_opt::<SharedSearchIndex>() else {
+            return Err(AppError::Unsupported("Search indexing is not enabled".to_string()).extend());
+        };
+        let repository = ctx.data::<SharedRepository>()?;
+        let count = reindex_users(repository.as_ref(), index.as_ref()).await.extend()?;
+        audit(ctx, "reindexUsers", None, serde_json::json!({})).await;
+        Ok(count as i32)
+    }
+
+    #[graphql(guard = "RoleGuard::new(UserRole::Admin)")]
+    async fn create_api_key(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(validator(min_length = 1, max_length = 100))] name: String,
+        user_id: ID,
+    ) -> Result<CreateApiKeyPayload> {
+        // Create a key a machine client sends as X-Api-Key to act as a live user
+        let repository = ctx.data::<SharedRepository>()?;
+        let api_keys = repository.api_keys().ok_or_else(|| unsupported("API keys"))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(AppError::Validation("API key name must not be empty".to_string()).extend());
+        }
+        let local_user_id = user_id_argument(&user_id)?;
+        if repository.get(&local_user_id).await.extend()?.is_none() {
+            return Err(AppError::NotFound(format!("User {} not found", user_id.as_str())).extend());
+        }
+        // Only the hash is stored, so the key cannot be shown again
+        let key = generate_api_key();
+        let new_api_key = NewApiKey { name: name.to_string(), user_id: local_user_id.clone(), key_hash: hash_api_key(&key) };
+        let api_key = api_keys.create_api_key(new_api_key).await.extend()?;
+        audit(ctx, "createApiKey", Some(global_id(USER, &local_user_id)), diff(None, Some(&api_key))).await;
+        Ok(CreateApiKeyPayload { api_key, key })
+    }
+
+    #[graphql(guard = "RoleGuard::new(UserRole::Admin)")]
+    async fn register_persisted_query(&self, ctx: &Context<'_>, query: String) -> Result<String> {
+        // Register a query clients can then run by its hash, which is returned; registrations last until
+        // the server restarts, unlike the manifest loaded at startup
+        let Some(persisted) = ctx.data_opt::<PersistedQueries>() else {
+            return Err(AppError::Unsupported("Persisted queries are not enabled".to_string()).extend());
+        };
+        let hash = persisted.register(&query).map_err(|error| AppError::Validation(format!("The query does not parse: {}", error)).extend())?;
+        audit(ctx, "registerPersistedQuery", None, serde_json::json!({ "sha256Hash": hash })).await;
+        Ok(hash)
+    }
+}
+
+// Integration tests
+#[cfg(test)]
+mod tests {
+    use crate::auth::{hash_api_key, AuthContext, Viewer};
+    use crate::node::{global_id, USER};
+    use crate::persisted::{persisted_query_hash, PersistedQueries};
+    use crate::repository::{InMemoryRepository, UserRepository};
+    use crate::schema::tests::{as_pavel, promote_pavel, CHARLIE, PAVEL};
+    use crate::schema::{build_schema, AppState};
+    use async_graphql::Request;
+    use std::sync::Arc;
+
+    // Define a test that mutations are recorded in the audit log, which only admins can read
+    #[tokio::test]
+    async fn test_audit_log() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        promote_pavel(&repository).await;
+        let schema = build_schema(AppState::new(repository));
+        let charlie_id = global_id(USER, CHARLIE);
+
+        // A change by an admin, a creation by a member, and a failed update, which records nothing
+        let update = format!(r#"mutation {{ updateUser(id: "{}", expectedVersion: 1, input: {{ name: "Chuck" }}) {{ name }} }}"#, charlie_id.as_str());
+        assert!(schema.execute(as_pavel(update)).await.errors.is_empty());
+        let create = Request::new(r#"mutation { createUser(input: { name: "Ada", email: "ada@example.com" }) { id } }"#);
+        let response = schema.execute(create.data(AuthContext::from(Viewer::User(CHARLIE.to_string())))).await;
+        let ada_id = serde_json::to_value(response.data).unwrap()["createUser"]["id"].as_str().unwrap().to_string();
+        let update = format!(r#"mutation {{ updateUser(id: "{}", expectedVersion: 1, input: {{ name: "Charles" }}) {{ name }} }}"#, charlie_id.as_str());
+        assert_eq!(schema.execute(as_pavel(update)).await.errors.len(), 1);
+
+        // Entries come newest first, one page at a time
+        let audit_log = |arguments: &str| {
+            as_pavel(format!(
+                "{{ auditLog({}) {{ edges {{ node {{ action, actorId, actor {{ name }}, targetId, diff }} }} pageInfo {{ hasNextPage, endCursor }} }} }}",
+                arguments
+            ))
+        };
+        let response = schema.execute(audit_log("first: 1")).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let page = serde_json::to_value(response.data).unwrap()["auditLog"].clone();
+        let created = &page["edges"][0]["node"];
+        assert_eq!(created["action"], "createUser");
+        assert_eq!((&created["actorId"], &created["actor"]), (&serde_json::json!(charlie_id.as_str()), &serde_json::json!({ "name": "Chuck" })));
+        assert_eq!(created["targetId"], ada_id);
+        assert_eq!(created["diff"]["email"], serde_json::json!({ "before": null, "after": "ada@example.com" }));
+        assert_eq!(page["pageInfo"]["hasNextPage"], true);
+
+        let response = schema.execute(audit_log(&format!(r#"first: 5, after: "{}""#, page["pageInfo"]["endCursor"].as_str().unwrap()))).await;
+        let page = serde_json::to_value(response.data).unwrap()["auditLog"].clone();
+        assert_eq!(
+            page["edges"],
+            serde_json::json!([{ "node": {
+                "action": "updateUser",
+                "actorId": global_id(USER, PAVEL).as_str(),
+                "actor": { "name": "Pavel" },
+                "targetId": charlie_id.as_str(),
+                "diff": { "name": { "before": "Charlie", "after": "Chuck" } }
+            } }])
+        );
+        assert_eq!(page["pageInfo"]["hasNextPage"], false);
+
+        // Entries can be filtered by action, actor, and target
+        let count = |filter: String| {
+            let schema = schema.clone();
+            async move {
+                let response = schema.execute(as_pavel(format!("{{ auditLog(filter: {}) {{ edges {{ node {{ action }} }} }} }}", filter))).await;
+                serde_json::to_value(response.data).unwrap()["auditLog"]["edges"].as_array().unwrap().len()
+            }
+        };
+        assert_eq!(count(r#"{ action: "createUser" }"#.to_string()).await, 1);
+        assert_eq!(count(format!(r#"{{ actorId: "{}" }}"#, PAVEL)).await, 1);
+        assert_eq!(count(format!(r#"{{ targetId: "{}" }}"#, ada_id)).await, 1);
+        assert_eq!(count(r#"{ action: "deleteUser" }"#.to_string()).await, 0);
+
+        // Only admins may read the log
+        let code = |response: async_graphql::Response| serde_json::to_value(&response.errors[0]).unwrap()["extensions"]["code"].clone();
+        let query = "{ auditLog { edges { node { action } } } }";
+        assert_eq!(code(schema.execute(query).await), "UNAUTHENTICATED");
+        let response = schema.execute(Request::new(query).data(AuthContext::from(Viewer::User(CHARLIE.to_string())))).await;
+        assert_eq!(code(response), "FORBIDDEN");
+    }
+
+    // Define a test that admins can create API keys, which are returned once and stored only as a hash
+    #[tokio::test]
+    async fn test_create_api_key() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        promote_pavel(&repository).await;
+        let schema = build_schema(AppState::new(repository.clone()));
+        let create = |user_id: &str| format!(r#"mutation {{ createApiKey(name: "CI", userId: "{}") {{ key apiKey {{ id name userId user {{ name }} }} }} }}"#, user_id);
+
+        let response = schema.execute(as_pavel(create(CHARLIE))).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let payload = serde_json::to_value(response.data).unwrap()["createApiKey"].clone();
+        assert_eq!(
+            payload["apiKey"],
+            serde_json::json!({ "id": "1", "name": "CI", "userId": global_id(USER, CHARLIE).as_str(), "user": { "name": "Charlie" } })
+        );
+        let key = payload["key"].as_str().unwrap();
+        let stored = repository.api_keys().unwrap().get_api_key_by_hash(&hash_api_key(key)).await.unwrap().unwrap();
+        assert_eq!((stored.user_id.as_str(), stored.key_hash.len()), (CHARLIE, 64));
+        assert!(!stored.key_hash.contains(key));
+
+        // The audit log records the key without its hash
+        let response = schema.execute(as_pavel(r#"{ auditLog(filter: { action: "createApiKey" }) { edges { node { diff } } } }"#)).await;
+        let diff = serde_json::to_value(response.data).unwrap()["auditLog"]["edges"][0]["node"]["diff"].clone();
+        assert_eq!(diff["name"]["after"], "CI");
+        assert!(diff.get("key_hash").is_none());
+
+        // The user must exist, and only admins may create keys
+        let code = |response: async_graphql::Response| serde_json::to_value(&response.errors[0]).unwrap()["extensions"]["code"].clone();
+        assert_eq!(code(schema.execute(as_pavel(create("00000000-0000-0000-0000-000000000099"))).await), "NOT_FOUND");
+        assert_eq!(code(schema.execute(create(CHARLIE)).await), "UNAUTHENTICATED");
+        let response = schema.execute(Request::new(create(PAVEL)).data(AuthContext::from(Viewer::User(CHARLIE.to_string())))).await;
+        assert_eq!(code(response), "FORBIDDEN");
+    }
+
+    // Define a test that admins can register queries, which are then the only ones run when persisted queries are required
+    #[tokio::test]
+    async fn test_register_persisted_query() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        promote_pavel(&repository).await;
+        let persisted = PersistedQueries::new(true);
+        let register = r#"mutation Register($query: String!) { registerPersistedQuery(query: $query) }"#;
+        persisted.register(register).unwrap();
+        let schema = build_schema(AppState::new(repository).with_persisted_queries(persisted));
+        let query = r#"{ userById(id: "1") { name } }"#;
+        let variables = async_graphql::Variables::from_json(serde_json::json!({ "query": query }));
+
+        let response = schema.execute(as_pavel(register).variables(variables.clone())).await;
+        assert_eq!(response.data, async_graphql::value!({ "registerPersistedQuery": persisted_query_hash(query) }));
+
+        // The query now runs by its hash, while other queries are refused before they are parsed
+        let mut request = Request::new("");
+        let extension = serde_json::json!({ "sha256Hash": persisted_query_hash(query) });
+        request.extensions.insert("persistedQuery".to_string(), async_graphql::Value::from_json(extension).unwrap());
+        assert_eq!(schema.execute(request).await.data, async_graphql::value!({ "userById": { "name": "Pavel" } }));
+        let code = |response: async_graphql::Response| serde_json::to_value(&response.errors[0]).unwrap()["extensions"]["code"].clone();
+        assert_eq!(code(schema.execute(r#"{ userById(id: "2") { name } }"#).await), "FORBIDDEN");
+
+        // Only admins may register queries
+        let response = schema.execute(Request::new(register).variables(variables).data(AuthContext::from(Viewer::User(CHARLIE.to_string())))).await;
+        assert_eq!(code(response), "FORBIDDEN");
+    }
+}
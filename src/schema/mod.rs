@@ -0,0 +1,689 @@
+// Import necessary libraries and modules
+use async_graphql::{Context, ErrorExtensions, Guard, MergedObject, Object, Result, ResultExt, Schema, ID};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use crate::audit::{InMemoryAuditStore, NewAuditEntry, SharedAuditStore};
+use crate::auth::{AuthContext, Sessions, Viewer};
+use crate::csrf::CsrfProtection;
+use crate::avatar::Avatars;
+use crate::cache::{CacheMetrics, CacheStats};
+use crate::complexity::{list_cost, QueryCost, DEFAULT_MAX_QUERY_COST, SEARCH_COST};
+use crate::environment::IntrospectionDisabled;
+use crate::error::AppError;
+use crate::http_get::QueriesOnlyOverGet;
+use crate::input_limits::InputLimits;
+use crate::jwt::JwtSigner;
+use crate::login_throttle::LoginThrottle;
+use crate::loader::{membership_data_loader, organization_data_loader, post_data_loader, user_data_loader, UserDataLoader};
+use crate::log_error;
+use crate::masking::Masking;
+use crate::model::{parse_user_id, Node, ServiceAccount, User, UserRole};
+use crate::node::{fetch_node, local_id, USER};
+use crate::password_reset::PasswordReset;
+use crate::permissions::FieldPermissions;
+use crate::persisted::PersistedQueries;
+use crate::repository::SharedRepository;
+use crate::response_cache::{ResponseCache, ResponseCaching};
+use crate::search::{SearchResult, SharedSearchIndex, MAX_SEARCH_LIMIT};
+use crate::service_accounts::ServiceAccounts;
+use crate::subscription::{SubscriptionRoot, UserEvent, UserEvents};
+use crate::totp::TotpIssuer;
+use crate::upload::UploadLimits;
+use crate::verification::EmailVerification;
+
+pub mod accounts;
+pub mod admin;
+pub mod organizations;
+pub mod posts;
+pub mod users;
+
+use accounts::AccountMutation;
+use admin::{AdminMutation, AdminQuery};
+use organizations::{OrganizationMutation, OrganizationQuery};
+use posts::{PostMutation, PostQuery};
+use users::{UserMutation, UserQuery};
+
+// Define the schema type served by the application
+pub type AppSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+// Define the largest page the users query returns when no other limit is configured
+pub const DEFAULT_MAX_PAGE_SIZE: usize = 100;
+
+// Define how deeply a query may nest fields when no other limit is configured; the Playground's
+// introspection query nests 13 deep
+pub const DEFAULT_MAX_QUERY_DEPTH: usize = 15;
+
+// Define the page size limit injected into the schema data
+#[derive(Clone, Copy)]
+pub struct MaxPageSize(pub usize);
+
+// Define the QueryRoot served by the schema, merged from the queries of each domain; every field
+// name must be unique across them
+#[derive(Default, MergedObject)]
+pub struct QueryRoot(NodeQuery, UserQuery, PostQuery, OrganizationQuery, AdminQuery);
+
+// Define the MutationRoot served by the schema, merged from the mutations of each domain
+#[derive(Default, MergedObject)]
+pub struct MutationRoot(UserMutation, PostMutation, OrganizationMutation, AdminMutation, AccountMutation);
+
+// Define the queries that span every domain: refetching objects by global ID and the global search
+#[derive(Default)]
+pub struct NodeQuery;
+
+// Implement GraphQL Object for the NodeQuery struct
+#[Object]
+impl NodeQuery {
+    async fn node(&self, ctx: &Context<'_>, id: ID) -> Result<Option<Node>> {
+        // Refetch any object by the global ID it was served with
+        fetch_node(ctx, &id).await
+    }
+
+    #[graphql(complexity = "SEARCH_COST + list_cost(limit, child_complexity)")]
+    async fn search(&self, ctx: &Context<'_>, query: String, #[graphql(default = 10)] limit: usize) -> Result<Vec<SearchResult>> {
+        // Return up to `limit` matches of each type: the best-ranked users, then posts and organizations in creation order
+        let repository = ctx.data::<SharedRepository>()?;
+        let limit = limit.min(MAX_SEARCH_LIMIT);
+        let users = repository.search(&query, limit, 0).await.extend()?;
+        let mut results: Vec<SearchResult> = users.into_iter().map(|result| SearchResult::User(result.user)).collect();
+        if let Some(posts) = repository.posts() {
+            results.extend(posts.search_posts(&query, limit).await.extend()?.into_iter().map(SearchResult::Post));
+        }
+        if let Some(organizations) = repository.organizations() {
+            results.extend(organizations.search_organizations(&query, limit).await.extend()?.into_iter().map(SearchResult::Organization));
+        }
+        Ok(results)
+    }
+}
+
+// Turn a user ID argument, global or not, into the UUID text every backend stores; numeric legacy IDs
+// map to the UUIDs they were migrated to, and anything else is rejected with an INVALID_ID code
+pub(crate) fn user_id_argument(id: &str) -> Result<String> {
+    match parse_user_id(&local_id(USER, id)) {
+        Some(uuid) => Ok(uuid.to_string()),
+        None => Err(AppError::InvalidId(id.to_string()).extend()),
+    }
+}
+
+// Tell subscribers about a change, when the schema has somewhere to publish it
+fn publish(ctx: &Context<'_>, event: UserEvent) {
+    if let Some(events) = ctx.data_opt::<UserEvents>() {
+        events.publish(event);
+    }
+}
+
+// Return the user the request's bearer token belongs to, or an UNAUTHENTICATED error without one
+async fn viewer_user(ctx: &Context<'_>) -> Result<User> {
+    let user_id = match ctx.data_opt::<AuthContext>().map(|auth| &auth.viewer) {
+        Some(Viewer::User(user_id)) => user_id,
+        Some(Viewer::InvalidCredentials) => return Err(AppError::Unauthenticated("The bearer token is not valid".to_string()).extend()),
+        Some(Viewer::Anonymous) | None => return Err(AppError::Unauthenticated("Authentication required".to_string()).extend()),
+    };
+    let loader = ctx.data::<UserDataLoader>()?;
+    loader
+        .load_one(user_id.clone())
+        .await
+        .map_err(|error| error.extend())?
+        .ok_or_else(|| AppError::Unauthenticated("The user the bearer token belongs to no longer exists".to_string()).extend())
+}
+
+// Define a field guard that lets through only viewers whose role includes the given one. Requests
+// without valid credentials fail with UNAUTHENTICATED and viewers with a lesser role with FORBIDDEN
+pub struct RoleGuard {
+    role: UserRole,
+}
+
+impl RoleGuard {
+    pub fn new(role: UserRole) -> Self {
+        RoleGuard { role }
+    }
+}
+
+#[async_trait]
+impl Guard for RoleGuard {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        if viewer_user(ctx).await?.role.includes(self.role) {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(format!("This field requires the {} role", self.role.as_str())).extend())
+        }
+    }
+}
+
+// Define a field guard that lets through only signed-in users who have verified their email address.
+// Requests without valid credentials fail with UNAUTHENTICATED and unverified users with EMAIL_NOT_VERIFIED
+pub struct VerifiedGuard;
+
+#[async_trait]
+impl Guard for VerifiedGuard {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        if viewer_user(ctx).await?.email_verified {
+            Ok(())
+        } else {
+            Err(AppError::EmailNotVerified.extend())
+        }
+    }
+}
+
+// Record a mutation in the audit log along with who made it. The change has already been made by then,
+// so a failure to record it is logged rather than reported to the client
+async fn audit(ctx: &Context<'_>, action: &str, target_id: Option<ID>, diff: serde_json::Value) {
+    let Some(audit_store) = ctx.data_opt::<SharedAuditStore>() else {
+        return;
+    };
+    let actor_id = ctx.data_opt::<AuthContext>().and_then(AuthContext::principal).map(str::to_string);
+    let entry = NewAuditEntry { actor_id, action: action.to_string(), target_id: target_id.map(|id| id.0), diff };
+    if let Err(error) = audit_store.record(entry).await {
+        log_error!("Failed to record {} in the audit log: {}", action, error);
+    }
+}
+
+// Report that the storage backend cannot store the given kind of object
+fn unsupported(objects: &str) -> async_graphql::Error {
+    AppError::Unsupported(format!("{} are not supported by this storage backend", objects)).extend()
+}
+
+// Define the shared services the schema is built from
+#[derive(Clone)]
+pub struct AppState {
+    pub repository: SharedRepository,
+    pub cache_stats: Option<CacheStats>,
+    pub search_index: Option<SharedSearchIndex>,
+    pub avatars: Option<Avatars>,
+    pub service_accounts: Vec<ServiceAccount>,
+    pub user_events: UserEvents,
+    pub max_page_size: usize,
+    pub max_query_depth: usize,
+    pub max_query_cost: usize,
+    pub upload_limits: UploadLimits,
+    pub input_limits: InputLimits,
+    pub audit_store: SharedAuditStore,
+    pub jwt_signer: Option<JwtSigner>,
+    pub sessions: Option<Sessions>,
+    pub login_throttle: LoginThrottle,
+    pub totp_issuer: TotpIssuer,
+    pub email_verification: Option<EmailVerification>,
+    pub password_reset: Option<PasswordReset>,
+    pub introspection: bool,
+    pub persisted_queries: Option<PersistedQueries>,
+    pub response_cache: Option<ResponseCache>,
+}
+
+impl AppState {
+    // Create state for the given repository with no optional services enabled
+    pub fn new(repository: SharedRepository) -> Self {
+        AppState {
+            repository,
+            cache_stats: None,
+            search_index: None,
+            avatars: None,
+            service_accounts: Vec::new(),
+            user_events: UserEvents::default(),
+            max_page_size: DEFAULT_MAX_PAGE_SIZE,
+            max_query_depth: DEFAULT_MAX_QUERY_DEPTH,
+            max_query_cost: DEFAULT_MAX_QUERY_COST,
+            upload_limits: UploadLimits::default(),
+            input_limits: InputLimits::default(),
+            audit_store: Arc::new(InMemoryAuditStore::default()),
+            jwt_signer: None,
+            sessions: None,
+            login_throttle: LoginThrottle::default(),
+            totp_issuer: TotpIssuer::default(),
+            email_verification: None,
+            password_reset: None,
+            introspection: true,
+            persisted_queries: None,
+            response_cache: None,
+        }
+    }
+
+    // Report the given cache counters in the response extensions
+    pub fn with_cache_stats(mut self, stats: CacheStats) -> Self {
+        self.cache_stats = Some(stats);
+        self
+    }
+
+    // Allow the reindexUsers mutation to rebuild the given search index
+    pub fn with_search_index(mut self, index: SharedSearchIndex) -> Self {
+        self.search_index = Some(index);
+        self
+    }
+
+    // Cap the number of users a single page of the users query can return
+    pub fn with_max_page_size(mut self, max_page_size: usize) -> Self {
+        self.max_page_size = max_page_size;
+        self
+    }
+
+    // Refuse queries that nest fields more deeply than the given depth before they run
+    pub fn with_max_query_depth(mut self, max_query_depth: usize) -> Self {
+        self.max_query_depth = max_query_depth;
+        self
+    }
+
+    // Refuse queries whose cost, with list fields charged once for every item they ask for, is over the
+    // given budget before they run
+    pub fn with_max_query_cost(mut self, max_query_cost: usize) -> Self {
+        self.max_query_cost = max_query_cost;
+        self
+    }
+
+    // Allow avatar uploads into the given store
+    pub fn with_avatars(mut self, avatars: Avatars) -> Self {
+        self.avatars = Some(avatars);
+        self
+    }
+
+    // Register the service accounts that can appear as actors
+    pub fn with_service_accounts(mut self, service_accounts: Vec<ServiceAccount>) -> Self {
+        self.service_accounts = service_accounts;
+        self
+    }
+
+    // Limit the files mutations accept from multipart requests
+    pub fn with_upload_limits(mut self, upload_limits: UploadLimits) -> Self {
+        self.upload_limits = upload_limits;
+        self
+    }
+
+    // Refuse operations with longer strings or lists, or more aliases, than the given limits allow
+    pub fn with_input_limits(mut self, input_limits: InputLimits) -> Self {
+        self.input_limits = input_limits;
+        self
+    }
+
+    // Record mutations in the given audit store instead of the default in-memory one
+    pub fn with_audit_store(mut self, audit_store: SharedAuditStore) -> Self {
+        self.audit_store = audit_store;
+        self
+    }
+
+    // Enable the register and login mutations, which issue tokens signed by the given signer
+    pub fn with_jwt_signer(mut self, signer: JwtSigner) -> Self {
+        self.jwt_signer = Some(signer);
+        self
+    }
+
+    // Also start a session on every password login, handed to the browser as a cookie, and enable logout
+    pub fn with_sessions(mut self, sessions: Sessions) -> Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
+    // Throttle failed password logins with the given throttle instead of one with the default settings
+    pub fn with_login_throttle(mut self, login_throttle: LoginThrottle) -> Self {
+        self.login_throttle = login_throttle;
+        self
+    }
+
+    // List two-factor enrollments in authenticator apps under the given issuer instead of the default one
+    pub fn with_totp_issuer(mut self, totp_issuer: TotpIssuer) -> Self {
+        self.totp_issuer = totp_issuer;
+        self
+    }
+
+    // Register users unverified and mail them a verification link
+    pub fn with_email_verification(mut self, email_verification: EmailVerification) -> Self {
+        self.email_verification = Some(email_verification);
+        self
+    }
+
+    // Let users who forgot their password have a reset link mailed to them
+    pub fn with_password_reset(mut self, password_reset: PasswordReset) -> Self {
+        self.password_reset = Some(password_reset);
+        self
+    }
+
+    // Answer introspection queries, or refuse them with an error when disabled
+    pub fn with_introspection(mut self, enabled: bool) -> Self {
+        self.introspection = enabled;
+        self
+    }
+
+    // Run the given persisted queries by hash, and only them when they are required
+    pub fn with_persisted_queries(mut self, persisted_queries: PersistedQueries) -> Self {
+        self.persisted_queries = Some(persisted_queries);
+        self
+    }
+
+    // Keep public query responses in the given cache, answering repeats from it while they are fresh
+    pub fn with_response_cache(mut self, response_cache: ResponseCache) -> Self {
+        self.response_cache = Some(response_cache);
+        self
+    }
+}
+
+// Build the GraphQL schema with QueryRoot, MutationRoot, and SubscriptionRoot,
+// injecting the repository so resolvers stay independent of the storage backend
+pub fn build_schema(state: AppState) -> AppSchema {
+    let mut builder = Schema::build(QueryRoot::default(), MutationRoot::default(), SubscriptionRoot)
+        .data(user_data_loader(state.repository.clone()))
+        .data(post_data_loader(state.repository.clone()))
+        .data(organization_data_loader(state.repository.clone()))
+        .data(membership_data_loader(state.repository.clone()))
+        .data(state.repository)
+        .data(ServiceAccounts(state.service_accounts))
+        .data(state.user_events)
+        .data(MaxPageSize(state.max_page_size))
+        .data(state.upload_limits)
+        .data(state.audit_store)
+        .data(state.login_throttle)
+        .data(state.totp_issuer)
+        .limit_depth(state.max_query_depth)
+        .extension(QueryCost::new(state.max_query_cost))
+        .extension(state.input_limits)
+        .extension(Masking)
+        .extension(FieldPermissions)
+        .extension(CsrfProtection)
+        .extension(QueriesOnlyOverGet);
+    if let Some(stats) = state.cache_stats {
+        builder = builder.extension(CacheMetrics::new(stats));
+    }
+    if let Some(index) = state.search_index {
+        builder = builder.data(index);
+    }
+    if let Some(avatars) = state.avatars {
+        builder = builder.data(avatars);
+    }
+    if let Some(signer) = state.jwt_signer {
+        builder = builder.data(signer);
+    }
+    if let Some(sessions) = state.sessions {
+        builder = builder.data(sessions);
+    }
+    if let Some(email_verification) = state.email_verification {
+        builder = builder.data(email_verification);
+    }
+    if let Some(password_reset) = state.password_reset {
+        builder = builder.data(password_reset);
+    }
+    if let Some(persisted_queries) = state.persisted_queries {
+        builder = builder.extension(persisted_queries.clone()).data(persisted_queries);
+    }
+    if !state.introspection {
+        builder = builder.disable_introspection().extension(IntrospectionDisabled);
+    }
+    builder.extension(ResponseCaching::new(state.response_cache)).finish()
+}
+
+// Print the schema in SDL, as clients and code generators read it. Data and extensions do not change the
+// types, so the schema is built without them
+pub fn schema_sdl() -> String {
+    Schema::build(QueryRoot::default(), MutationRoot::default(), SubscriptionRoot).finish().sdl()
+}
+
+// Integration tests
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::model::{NewPost, UserRole, UserUpdate};
+    use crate::node::{global_id, SERVICE_ACCOUNT};
+    use crate::repository::{InMemoryRepository, PostRepository, UserRepository};
+    use async_graphql::Request;
+
+    // Name the sample users' IDs
+    pub(crate) const PAVEL: &str = "00000000-0000-0000-0000-000000000001";
+    pub(crate) const CHARLIE: &str = "00000000-0000-0000-0000-000000000002";
+
+    // Build a schema over the sample users
+    pub(crate) fn sample_schema() -> AppSchema {
+        build_schema(AppState::new(Arc::new(InMemoryRepository::with_sample_users())))
+    }
+
+    // Make Pavel an admin, for tests that read fields only admins see in full
+    pub(crate) async fn promote_pavel(repository: &InMemoryRepository) {
+        let update = UserUpdate { role: Some(UserRole::Admin), ..Default::default() };
+        repository.update(PAVEL, update).await.unwrap();
+    }
+
+    // Build a schema over the sample users in which Pavel is an admin, for tests that call admin-only fields
+    pub(crate) async fn admin_schema() -> AppSchema {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        promote_pavel(&repository).await;
+        build_schema(AppState::new(repository))
+    }
+
+    // Build a request made by Pavel
+    pub(crate) fn as_pavel(request: impl Into<Request>) -> Request {
+        request.into().data(AuthContext::from(Viewer::User(PAVEL.to_string())))
+    }
+
+    // Define a test that the exported SDL is the schema the server serves
+    #[test]
+    fn test_schema_sdl() {
+        let sdl = schema_sdl();
+        assert_eq!(sdl, sample_schema().sdl());
+        assert!(sdl.contains("type QueryRoot {") && sdl.contains("type MutationRoot {") && sdl.contains("type SubscriptionRoot {"));
+    }
+
+    // Define a test that resolver errors carry a machine-readable code
+    #[tokio::test]
+    async fn test_error_codes() {
+        let schema = admin_schema().await;
+        let missing = "00000000-0000-0000-0000-0000000000ff";
+        for (query, message, code) in [
+            (
+                format!(r#"mutation {{ updateUser(id: "{}", expectedVersion: 1, input: {{ name: "Ghost" }}) {{ id }} }}"#, missing),
+                format!("User {} not found", missing),
+                "NOT_FOUND",
+            ),
+            (
+                format!(r#"mutation {{ createPost(input: {{ authorId: "{}", title: "Hello", body: "" }}) {{ id }} }}"#, missing),
+                format!("User {} not found", missing),
+                "NOT_FOUND",
+            ),
+            (
+                format!(r#"mutation {{ createPost(input: {{ authorId: "{}", title: " ", body: "" }}) {{ id }} }}"#, PAVEL),
+                "Post title must not be empty".to_string(),
+                "VALIDATION_FAILED",
+            ),
+            (r#"mutation { importUsers(csv: "name\nAda\n") { importedCount } }"#.to_string(), "CSV header must include \"email\" column".to_string(), "VALIDATION_FAILED"),
+            (r#"mutation { reindexUsers }"#.to_string(), "Search indexing is not enabled".to_string(), "UNSUPPORTED"),
+        ] {
+            let response = serde_json::to_value(schema.execute(as_pavel(query.as_str())).await).expect("Failed to convert response to JSON");
+            assert_eq!(response["errors"][0]["message"], message.as_str(), "{}", query);
+            assert_eq!(response["errors"][0]["extensions"]["code"], code, "{}", query);
+        }
+    }
+
+    // Define a test for refetching objects by their global IDs through the node query
+    #[tokio::test]
+    async fn test_node_query() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        let new_post = NewPost { author_id: PAVEL.to_string(), title: "Hello".to_string(), body: String::new() };
+        repository.create_post(new_post).await.unwrap();
+        let schema = build_schema(AppState::new(repository));
+        let execute = |query: String| {
+            let schema = schema.clone();
+            async move { serde_json::to_value(schema.execute(query).await).expect("Failed to convert response to JSON") }
+        };
+        let node = |id: &str| {
+            format!(
+                r#"{{ node(id: "{}") {{ __typename, id, ... on User {{ name }}, ... on Post {{ title, authorId }} }} }}"#,
+                id
+            )
+        };
+
+        // Every type hands out global IDs, and node resolves them back to the object
+        let post = execute(r#"{ post(id: "1") { id, authorId } }"#.to_string()).await["data"]["post"].clone();
+        let response = execute(node(post["id"].as_str().unwrap())).await;
+        assert_eq!(
+            response["data"]["node"],
+            serde_json::json!({ "__typename": "Post", "id": post["id"], "title": "Hello", "authorId": post["authorId"] })
+        );
+        let response = execute(node(post["authorId"].as_str().unwrap())).await;
+        assert_eq!(response["data"]["node"], serde_json::json!({ "__typename": "User", "id": global_id(USER, PAVEL), "name": "Pavel" }));
+
+        // User ID arguments take global IDs as well as plain UUIDs and legacy numeric IDs
+        let users = format!(
+            r#"{{ a: userById(id: "{}") {{ name }}, b: userById(id: "{}") {{ name }}, c: userById(id: "2") {{ name }} }}"#,
+            global_id(USER, CHARLIE).as_str(),
+            CHARLIE
+        );
+        let response = execute(users).await;
+        assert_eq!(
+            response["data"],
+            serde_json::json!({ "a": { "name": "Charlie" }, "b": { "name": "Charlie" }, "c": { "name": "Charlie" } })
+        );
+
+        // Global IDs of another type are not user IDs
+        let response = execute(format!(r#"{{ userById(id: {}) {{ name }} }}"#, post["id"])).await;
+        assert_eq!(response["errors"][0]["extensions"]["code"], "INVALID_ID");
+
+        // Unknown types, missing objects, and IDs that are not global IDs resolve to null
+        for id in [global_id("Widget", "1"), global_id(USER, "99"), ID::from("1")] {
+            assert_eq!(execute(node(&id)).await["data"]["node"], serde_json::Value::Null);
+        }
+    }
+
+    // Define a test for searching users, posts, and organizations with one query
+    #[tokio::test]
+    async fn test_global_search() {
+        let schema = build_schema(AppState::new(Arc::new(InMemoryRepository::with_sample_users())));
+        for mutation in [
+            r#"mutation { createUser(input: { name: "Noibu Bot", email: "bot@example.com" }) { id } }"#.to_string(),
+            format!(r#"mutation {{ createPost(input: {{ authorId: "{}", title: "Hello", body: "Working at Noibu" }}) {{ id }} }}"#, PAVEL),
+            format!(r#"mutation {{ createPost(input: {{ authorId: "{}", title: "Unrelated", body: "Nothing here" }}) {{ id }} }}"#, PAVEL),
+            r#"mutation { createOrganization(name: "Noibu Inc") { id } }"#.to_string(),
+        ] {
+            let response = schema.execute(as_pavel(mutation)).await;
+            assert!(response.errors.is_empty(), "{:?}", response.errors);
+        }
+        let search = |query: &'static str| {
+            let schema = schema.clone();
+            async move {
+                let query = format!(
+                    r#"{{ search(query: "{}") {{ __typename, ... on User {{ name }}, ... on Post {{ title }}, ... on Organization {{ name }} }} }}"#,
+                    query
+                );
+                let response = schema.execute(query).await;
+                assert!(response.errors.is_empty(), "{:?}", response.errors);
+                serde_json::to_value(response.data).expect("Failed to convert response to JSON")["search"].clone()
+            }
+        };
+
+        // Users come first, best match first, then posts and organizations
+        assert_eq!(
+            search("NOIBU").await,
+            serde_json::json!([
+                { "__typename": "User", "name": "Noibu Bot" },
+                { "__typename": "User", "name": "Charlie" },
+                { "__typename": "Post", "title": "Hello" },
+                { "__typename": "Organization", "name": "Noibu Inc" },
+            ])
+        );
+
+        // Every term must match, and a blank query matches nothing
+        assert_eq!(search("noibu inc").await, serde_json::json!([{ "__typename": "Organization", "name": "Noibu Inc" }]));
+        assert_eq!(search(" ").await, serde_json::json!([]));
+    }
+
+    // Define a test for resolving actors through the Actor interface
+    #[tokio::test]
+    async fn test_actor_interface() {
+        let importer = ServiceAccount { id: "importer".to_string(), name: "CSV Importer".to_string() };
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        promote_pavel(&repository).await;
+        let state = AppState::new(repository).with_service_accounts(vec![importer]);
+        let schema = build_schema(state);
+        let execute = |query: String| {
+            let schema = schema.clone();
+            async move {
+                let response = schema.execute(query).await;
+                assert!(response.errors.is_empty(), "{:?}", response.errors);
+                serde_json::to_value(response.data).expect("Failed to convert response to JSON")
+            }
+        };
+
+        // Post authors resolve to users, with the shared fields and their own
+        let mutation = format!(r#"mutation {{ createPost(input: {{ authorId: "{}", title: "Hello", body: "" }}) {{ id }} }}"#, PAVEL);
+        assert!(schema.execute(as_pavel(mutation)).await.errors.is_empty());
+        let response = execute(r#"{ post(id: "1") { author { __typename, id, displayName, ... on User { email } } } }"#.to_string()).await;
+        assert_eq!(
+            response["post"]["author"],
+            serde_json::json!({
+                "__typename": "User",
+                "id": global_id(USER, PAVEL).as_str(),
+                "displayName": "Pavel",
+                "email": null,
+            })
+        );
+
+        // Service accounts are listed to admins and can be refetched by their global ID
+        let account = serde_json::json!({ "__typename": "ServiceAccount", "id": global_id(SERVICE_ACCOUNT, "importer").as_str(), "displayName": "CSV Importer" });
+        let response = schema.execute(as_pavel("{ serviceAccounts { __typename, id, displayName } }")).await;
+        assert_eq!(response.data.into_json().unwrap()["serviceAccounts"], serde_json::json!([account.clone()]));
+        let query = format!(
+            r#"{{ node(id: "{}") {{ __typename, id, ... on ServiceAccount {{ displayName }} }} }}"#,
+            global_id(SERVICE_ACCOUNT, "importer").as_str()
+        );
+        assert_eq!(execute(query).await["node"], account);
+        let query = format!(r#"{{ node(id: "{}") {{ id }} }}"#, global_id(SERVICE_ACCOUNT, "missing").as_str());
+        assert_eq!(execute(query).await["node"], serde_json::Value::Null);
+    }
+
+    // Define a test that admin-only fields refuse anonymous requests and members before their resolvers run
+    #[tokio::test]
+    async fn test_admin_only_fields() {
+        let schema = admin_schema().await;
+        let error = |response: async_graphql::Response| {
+            let error = serde_json::to_value(&response.errors[0]).unwrap();
+            (error["message"].clone(), error["extensions"]["code"].clone())
+        };
+        // The deletion comes last, since Charlie's requests fail as unauthenticated once he is gone
+        for query in [
+            "{ auditLog { edges { node { action } } } }",
+            "{ serviceAccounts { id } }",
+            r#"mutation { createApiKey(name: "CI", userId: "2") { key } }"#,
+            r#"mutation { restoreUser(id: "2") { name } }"#,
+            r#"mutation { importUsers(csv: "name,email\nAda,ada@example.com\n") { importedCount } }"#,
+            "mutation { reindexUsers }",
+            r#"mutation { deleteUser(id: "2") { success } }"#,
+        ] {
+            let response = schema.execute(query).await;
+            assert_eq!(error(response), (serde_json::json!("Authentication required"), serde_json::json!("UNAUTHENTICATED")), "{}", query);
+            let response = schema.execute(Request::new(query).data(AuthContext::from(Viewer::User(CHARLIE.to_string())))).await;
+            assert_eq!(response.data, async_graphql::Value::Null, "{}", query);
+            assert_eq!(error(response), (serde_json::json!("This field requires the admin role"), serde_json::json!("FORBIDDEN")), "{}", query);
+            // Pavel gets past the guard, though without a search index reindexUsers then reports that indexing is off
+            let response = schema.execute(as_pavel(query)).await;
+            let errors: Vec<_> = response.errors.iter().map(|error| error.message.as_str()).collect();
+            let expected: &[&str] = if query.contains("reindexUsers") { &["Search indexing is not enabled"] } else { &[] };
+            assert_eq!(errors, expected, "{}", query);
+        }
+    }
+
+    // Define a test that schemas built without introspection refuse introspection queries but still answer others
+    #[tokio::test]
+    async fn test_introspection_disabled() {
+        let query = "{ __schema { queryType { name } } }";
+        assert!(sample_schema().execute(query).await.is_ok());
+        let schema = build_schema(AppState::new(Arc::new(InMemoryRepository::with_sample_users())).with_introspection(false));
+        let response = schema.execute(query).await;
+        assert_eq!(response.data, async_graphql::Value::Null);
+        assert_eq!(response.errors[0].message, "Introspection is disabled");
+        assert!(schema.execute(r#"{ __type(name: "User") { name } }"#).await.is_err());
+        assert!(schema.execute(r#"{ userById(id: "1") { name } }"#).await.is_ok());
+    }
+
+    // Define a test that queries nesting user -> posts -> author -> posts too deeply are refused before they run
+    #[tokio::test]
+    async fn test_query_depth_limit() {
+        // Each round nests four more fields: posts, edges, node, and author; one post a page keeps the cost low
+        let nested = |rounds: usize| {
+            let round = "posts(first: 1) { edges { node { author { ... on User { ";
+            format!(r#"{{ userById(id: "1") {{ {}name{} }} }}"#, round.repeat(rounds), " } } } } }".repeat(rounds))
+        };
+        let schema = sample_schema();
+        let response = schema.execute(nested(2)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let response = schema.execute(nested(4)).await;
+        assert_eq!(response.data, async_graphql::Value::Null);
+        assert_eq!(response.errors[0].message, "Query is nested too deep.");
+
+        // The limit is configurable
+        let schema = build_schema(AppState::new(Arc::new(InMemoryRepository::with_sample_users())).with_max_query_depth(8));
+        assert!(schema.execute(nested(1)).await.is_ok());
+        assert!(schema.execute(nested(2)).await.is_err());
+    }
+}
@@ -0,0 +1,177 @@
+// Import necessary libraries and modules
+use async_graphql::{Context, ErrorExtensions, Object, Result, ResultExt, SimpleObject, ID};
+
+use super::{audit, unsupported, user_id_argument, VerifiedGuard};
+use crate::audit::diff;
+use crate::error::AppError;
+use crate::model::{Membership, MembershipRole, Organization};
+use crate::node::{global_id, local_id, ORGANIZATION};
+use crate::repository::SharedRepository;
+
+// Define the queries that read organizations
+#[derive(Default)]
+pub struct OrganizationQuery;
+
+// Implement GraphQL Object for the OrganizationQuery struct
+#[Object]
+impl OrganizationQuery {
+    async fn organization(&self, ctx: &Context<'_>, id: ID) -> Result<Option<Organization>> {
+        // Return an organization based on the provided ID; backends that cannot store organizations have none
+        let repository = ctx.data::<SharedRepository>()?;
+        let Some(organizations) = repository.organizations() else {
+            return Ok(None);
+        };
+        let id = local_id(ORGANIZATION, &id);
+        let mut found = organizations.get_organizations(std::slice::from_ref(&id)).await.extend()?;
+        Ok(found.remove(&id))
+    }
+
+    async fn organizations(&self, ctx: &Context<'_>) -> Result<Vec<Organization>> {
+        // Return every organization in creation order
+        let repository = ctx.data::<SharedRepository>()?;
+        match repository.organizations() {
+            Some(organizations) => organizations.list_organizations().await.extend(),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+// Define the payload returned by the removeMember mutation
+#[derive(SimpleObject)]
+pub struct RemoveMemberPayload {
+    pub membership: Option<Membership>,
+    pub success: bool,
+}
+
+// Define the mutations that change organizations and their members
+#[derive(Default)]
+pub struct OrganizationMutation;
+
+// Implement GraphQL Object for the OrganizationMutation struct
+#[Object]
+impl OrganizationMutation {
+    #[graphql(guard = "VerifiedGuard")]
+    async fn create_organization(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(validator(min_length = 1, max_length = 100))] name: String,
+    ) -> Result<Organization> {
+        // Store a new organization; backends that cannot store organizations refuse
+        let repository = ctx.data::<SharedRepository>()?;
+        let organizations = repository.organizations().ok_or_else(|| unsupported("Organizations"))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(AppError::Validation("Organization name must not be empty".to_string()).extend());
+        }
+        let organization = organizations.create_organization(name.to_string()).await.extend()?;
+        audit(ctx, "createOrganization", Some(global_id(ORGANIZATION, &organization.id)), diff(None, Some(&organization))).await;
+        Ok(organization)
+    }
+
+    #[graphql(guard = "VerifiedGuard")]
+    async fn add_member(
+        &self,
+        ctx: &Context<'_>,
+        organization_id: ID,
+        user_id: ID,
+        #[graphql(default)] role: MembershipRole,
+    ) -> Result<Membership> {
+        // Add a live user to an organization, or change the role of an existing member
+        let repository = ctx.data::<SharedRepository>()?;
+        let organizations = repository.organizations().ok_or_else(|| unsupported("Organizations"))?;
+        let (local_organization_id, local_user_id) = (local_id(ORGANIZATION, &organization_id), user_id_argument(&user_id)?);
+        if organizations.get_organizations(std::slice::from_ref(&local_organization_id)).await.extend()?.is_empty() {
+            return Err(AppError::NotFound(format!("Organization {} not found", organization_id.as_str())).extend());
+        }
+        if repository.get(&local_user_id).await.extend()?.is_none() {
+            return Err(AppError::NotFound(format!("User {} not found", user_id.as_str())).extend());
+        }
+        // Adding an existing member changes their role, so the diff starts from their current membership
+        let mut memberships = organizations.memberships_by_organizations(std::slice::from_ref(&local_organization_id)).await.extend()?;
+        let before = memberships
+            .remove(&local_organization_id)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|membership| membership.user_id == local_user_id);
+        let membership = organizations.add_member(&local_organization_id, &local_user_id, role).await.extend()?;
+        audit(ctx, "addMember", Some(global_id(ORGANIZATION, &local_organization_id)), diff(before.as_ref(), Some(&membership))).await;
+        Ok(membership)
+    }
+
+    #[graphql(guard = "VerifiedGuard")]
+    async fn remove_member(&self, ctx: &Context<'_>, organization_id: ID, user_id: ID) -> Result<RemoveMemberPayload> {
+        // Remove a user from an organization, and report whether they were a member
+        let repository = ctx.data::<SharedRepository>()?;
+        let organizations = repository.organizations().ok_or_else(|| unsupported("Organizations"))?;
+        let local_organization_id = local_id(ORGANIZATION, &organization_id);
+        let membership = organizations.remove_member(&local_organization_id, &user_id_argument(&user_id)?).await.extend()?;
+        if let Some(membership) = &membership {
+            audit(ctx, "removeMember", Some(global_id(ORGANIZATION, &local_organization_id)), diff(Some(membership), None)).await;
+        }
+        Ok(RemoveMemberPayload {
+            success: membership.is_some(),
+            membership,
+        })
+    }
+}
+
+// Integration tests
+#[cfg(test)]
+mod tests {
+    use crate::repository::InMemoryRepository;
+    use crate::schema::tests::as_pavel;
+    use crate::schema::{build_schema, AppState};
+    use std::sync::Arc;
+
+    // Define a test for creating organizations and managing their members
+    #[tokio::test]
+    async fn test_organization_memberships() {
+        let schema = build_schema(AppState::new(Arc::new(InMemoryRepository::with_sample_users())));
+        let execute = |query: &str| {
+            let schema = schema.clone();
+            let query = query.to_string();
+            async move { serde_json::to_value(schema.execute(as_pavel(query)).await).expect("Failed to convert response to JSON") }
+        };
+
+        // Organizations need a name, and members join with the MEMBER role unless another is given
+        let response = execute(r#"mutation { createOrganization(name: "  Acme ") { id, name } }"#).await;
+        assert_eq!(response["data"]["createOrganization"], serde_json::json!({ "id": "T3JnYW5pemF0aW9uOjE=", "name": "Acme" }));
+        let response = execute(r#"mutation { createOrganization(name: " ") { id } }"#).await;
+        assert_eq!(response["errors"][0]["message"], "Organization name must not be empty");
+        let response = execute(r#"mutation { addMember(organizationId: "1", userId: "1", role: OWNER) { role } }"#).await;
+        assert_eq!(response["data"]["addMember"]["role"], "OWNER");
+        let response = execute(r#"mutation { addMember(organizationId: "1", userId: "2") { role, user { name } } }"#).await;
+        assert_eq!(response["data"]["addMember"], serde_json::json!({ "role": "MEMBER", "user": { "name": "Charlie" } }));
+
+        // Adding an existing member changes their role instead of adding them again
+        let response = execute(r#"mutation { addMember(organizationId: "1", userId: "2", role: ADMIN) { role } }"#).await;
+        assert_eq!(response["data"]["addMember"]["role"], "ADMIN");
+        let response = execute(r#"{ organization(id: "1") { name, members { role, user { name } } } }"#).await;
+        assert_eq!(
+            response["data"]["organization"],
+            serde_json::json!({
+                "name": "Acme",
+                "members": [{ "role": "OWNER", "user": { "name": "Pavel" } }, { "role": "ADMIN", "user": { "name": "Charlie" } }],
+            })
+        );
+
+        // Users list their memberships with the organization resolved
+        let response = execute(r#"{ userById(id: "2") { memberships { role, organization { name } } } }"#).await;
+        assert_eq!(response["data"]["userById"]["memberships"], serde_json::json!([{ "role": "ADMIN", "organization": { "name": "Acme" } }]));
+
+        // Unknown organizations and users are refused
+        let response = execute(r#"mutation { addMember(organizationId: "9", userId: "1") { role } }"#).await;
+        assert_eq!(response["errors"][0]["message"], "Organization 9 not found");
+        let response = execute(r#"mutation { addMember(organizationId: "1", userId: "99") { role } }"#).await;
+        assert_eq!(response["errors"][0]["message"], "User 99 not found");
+
+        // Removing a member reports whether they belonged to the organization
+        let remove = r#"mutation { removeMember(organizationId: "1", userId: "1") { success, membership { role } } }"#;
+        let response = execute(remove).await;
+        assert_eq!(response["data"]["removeMember"], serde_json::json!({ "success": true, "membership": { "role": "OWNER" } }));
+        let response = execute(remove).await;
+        assert_eq!(response["data"]["removeMember"], serde_json::json!({ "success": false, "membership": null }));
+        let response = execute(r#"{ organizations { name, members { user { name } } } }"#).await;
+        assert_eq!(response["data"]["organizations"], serde_json::json!([{ "name": "Acme", "members": [{ "user": { "name": "Charlie" } }] }]));
+    }
+}
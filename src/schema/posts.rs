@@ -0,0 +1,254 @@
+// Import necessary libraries and modules
+use async_graphql::{Context, ErrorExtensions, InputObject, Object, Result, ResultExt, ID};
+
+use super::{audit, unsupported, user_id_argument, VerifiedGuard};
+use crate::audit::diff;
+use crate::error::AppError;
+use crate::model::{Comment, NewComment, NewPost, Post, MAX_COMMENT_DEPTH};
+use crate::node::{global_id, local_id, COMMENT, POST};
+use crate::repository::SharedRepository;
+
+// Define the queries that read posts and comments
+#[derive(Default)]
+pub struct PostQuery;
+
+// Implement GraphQL Object for the PostQuery struct
+#[Object]
+impl PostQuery {
+    async fn post(&self, ctx: &Context<'_>, id: ID) -> Result<Option<Post>> {
+        // Return a post based on the provided ID; backends that cannot store posts have none
+        let repository = ctx.data::<SharedRepository>()?;
+        match repository.posts() {
+            Some(posts) => posts.get_post(&local_id(POST, &id)).await.extend(),
+            None => Ok(None),
+        }
+    }
+}
+
+// Define the input accepted by the createPost mutation
+#[derive(InputObject)]
+pub struct CreatePostInput {
+    pub author_id: ID,
+    pub title: String,
+    pub body: String,
+}
+
+// Define the input accepted by the createComment mutation; replies name the comment they answer
+#[derive(InputObject)]
+pub struct CreateCommentInput {
+    pub post_id: ID,
+    pub parent_id: Option<ID>,
+    pub author_id: ID,
+    pub body: String,
+}
+
+// Define the mutations that write posts and comments
+#[derive(Default)]
+pub struct PostMutation;
+
+// Implement GraphQL Object for the PostMutation struct
+#[Object]
+impl PostMutation {
+    #[graphql(guard = "VerifiedGuard")]
+    async fn create_post(&self, ctx: &Context<'_>, input: CreatePostInput) -> Result<Post> {
+        // Store a post by a live user; backends that cannot store posts refuse
+        let repository = ctx.data::<SharedRepository>()?;
+        let posts = repository.posts().ok_or_else(|| unsupported("Posts"))?;
+        let title = input.title.trim();
+        if title.is_empty() {
+            return Err(AppError::Validation("Post title must not be empty".to_string()).extend());
+        }
+        let author_id = user_id_argument(&input.author_id)?;
+        if repository.get(&author_id).await.extend()?.is_none() {
+            return Err(AppError::NotFound(format!("User {} not found", input.author_id.as_str())).extend());
+        }
+        let new_post = NewPost { author_id, title: title.to_string(), body: input.body };
+        let post = posts.create_post(new_post).await.extend()?;
+        audit(ctx, "createPost", Some(global_id(POST, &post.id)), diff(None, Some(&post))).await;
+        Ok(post)
+    }
+
+    #[graphql(guard = "VerifiedGuard")]
+    async fn create_comment(&self, ctx: &Context<'_>, input: CreateCommentInput) -> Result<Comment> {
+        // Store a comment on a post, or a reply to another comment on the same post, by a live user
+        let repository = ctx.data::<SharedRepository>()?;
+        let posts = repository.posts().ok_or_else(|| unsupported("Posts"))?;
+        let body = input.body.trim();
+        if body.is_empty() {
+            return Err(AppError::Validation("Comment body must not be empty".to_string()).extend());
+        }
+        let post_id = local_id(POST, &input.post_id);
+        if posts.get_post(&post_id).await.extend()?.is_none() {
+            return Err(AppError::NotFound(format!("Post {} not found", input.post_id.as_str())).extend());
+        }
+        let parent_id = input.parent_id.as_ref().map(|parent_id| local_id(COMMENT, parent_id));
+        let depth = match (&input.parent_id, &parent_id) {
+            (Some(global_parent_id), Some(parent_id)) => match posts.get_comment(parent_id).await.extend()? {
+                Some(parent) if parent.post_id == post_id => parent.depth + 1,
+                _ => {
+                    let message = format!("Comment {} not found on post {}", global_parent_id.as_str(), input.post_id.as_str());
+                    return Err(AppError::NotFound(message).extend());
+                }
+            },
+            _ => 0,
+        };
+        if depth > MAX_COMMENT_DEPTH {
+            return Err(AppError::Validation(format!("Replies can be nested at most {} levels deep", MAX_COMMENT_DEPTH)).extend());
+        }
+        let author_id = user_id_argument(&input.author_id)?;
+        if repository.get(&author_id).await.extend()?.is_none() {
+            return Err(AppError::NotFound(format!("User {} not found", input.author_id.as_str())).extend());
+        }
+        let new_comment = NewComment {
+            post_id,
+            parent_id,
+            author_id,
+            body: body.to_string(),
+            depth,
+        };
+        let comment = posts.create_comment(new_comment).await.extend()?;
+        audit(ctx, "createComment", Some(global_id(COMMENT, &comment.id)), diff(None, Some(&comment))).await;
+        Ok(comment)
+    }
+}
+
+// Integration tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::{global_id, USER};
+    use crate::repository::{InMemoryRepository, PostRepository};
+    use crate::schema::tests::{admin_schema, as_pavel, PAVEL};
+    use crate::schema::{build_schema, AppState};
+    use std::sync::Arc;
+
+    // Define a test for creating posts and reading them back from the post and the author
+    #[tokio::test]
+    async fn test_create_post_mutation() {
+        let schema = admin_schema().await;
+        let execute = |query: &'static str| {
+            let schema = schema.clone();
+            async move { serde_json::to_value(schema.execute(as_pavel(query)).await).expect("Failed to convert response to JSON") }
+        };
+
+        // The title is trimmed and the post gets an ID and creation time
+        let response = execute(
+            r#"mutation { createPost(input: { authorId: "1", title: "  Hello  ", body: "First post" }) { id, title, authorId, createdAt } }"#,
+        )
+        .await;
+        let post = &response["data"]["createPost"];
+        assert_eq!(post["title"], "Hello");
+        assert_eq!(post["authorId"], global_id(USER, PAVEL).as_str());
+        assert!(post["createdAt"].is_string());
+        let response = execute(r#"{ post(id: "1") { title, body, author { displayName } } }"#).await;
+        assert_eq!(
+            response["data"]["post"],
+            serde_json::json!({ "title": "Hello", "body": "First post", "author": { "displayName": "Pavel" } })
+        );
+        assert_eq!(execute(r#"{ post(id: "9") { title } }"#).await["data"]["post"], serde_json::Value::Null);
+
+        // Authors list their posts in creation order; users without posts have none
+        execute(r#"mutation { createPost(input: { authorId: "1", title: "Again", body: "" }) { id } }"#).await;
+        let response = execute(r#"{ a: userById(id: "1") { posts { nodes { title } } } b: userById(id: "2") { posts { nodes { title } } } }"#).await;
+        assert_eq!(
+            response["data"],
+            serde_json::json!({ "a": { "posts": { "nodes": [{ "title": "Hello" }, { "title": "Again" }] } }, "b": { "posts": { "nodes": [] } } })
+        );
+
+        // Each author's posts page on their own cursors
+        let query = r#"{ userById(id: "1") { posts(first: 1) { edges { cursor, node { title } }, pageInfo { hasNextPage } } } }"#;
+        let page = &execute(query).await["data"]["userById"]["posts"];
+        assert_eq!(page["edges"][0]["node"]["title"], "Hello");
+        assert_eq!(page["pageInfo"]["hasNextPage"], true);
+        let query = format!(
+            r#"{{ userById(id: "1") {{ posts(first: 1, after: "{}") {{ nodes {{ title }}, pageInfo {{ hasNextPage, hasPreviousPage }} }} }} }}"#,
+            page["edges"][0]["cursor"].as_str().unwrap()
+        );
+        let response = serde_json::to_value(schema.execute(query).await).expect("Failed to convert response to JSON");
+        assert_eq!(
+            response["data"]["userById"]["posts"],
+            serde_json::json!({ "nodes": [{ "title": "Again" }], "pageInfo": { "hasNextPage": false, "hasPreviousPage": true } })
+        );
+
+        // Blank titles and missing or deleted authors are refused
+        let response = execute(r#"mutation { createPost(input: { authorId: "1", title: " ", body: "" }) { id } }"#).await;
+        assert_eq!(response["errors"][0]["message"], "Post title must not be empty");
+        let response = execute(r#"mutation { createPost(input: { authorId: "9", title: "Hi", body: "" }) { id } }"#).await;
+        assert_eq!(response["errors"][0]["message"], "User 9 not found");
+        schema.execute(as_pavel(r#"mutation { deleteUser(id: "2") { success } }"#)).await;
+        let response = execute(r#"mutation { createPost(input: { authorId: "2", title: "Hi", body: "" }) { id } }"#).await;
+        assert_eq!(response["errors"][0]["message"], "User 2 not found");
+
+        // A post whose author was deleted has no author
+        schema.execute(as_pavel(r#"mutation { deleteUser(id: "1") { success } }"#)).await;
+        assert_eq!(execute(r#"{ post(id: "1") { author { displayName } } }"#).await["data"]["post"]["author"], serde_json::Value::Null);
+    }
+
+    // Define a test for threads of comments and replies and paging through them
+    #[tokio::test]
+    async fn test_comment_threads() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        let new_post = NewPost { author_id: PAVEL.to_string(), title: "Hello".to_string(), body: String::new() };
+        let post = repository.create_post(new_post).await.unwrap();
+        let schema = build_schema(AppState::new(repository).with_max_page_size(2));
+        let execute = |query: String| {
+            let schema = schema.clone();
+            async move { serde_json::to_value(schema.execute(as_pavel(query)).await).expect("Failed to convert response to JSON") }
+        };
+        let comment = |parent_id: Option<&str>, body: &str| {
+            let parent_id = parent_id.map_or(String::new(), |parent_id| format!(r#", parentId: "{}""#, parent_id));
+            format!(
+                r#"mutation {{ createComment(input: {{ postId: "{}", authorId: "2", body: "{}"{} }}) {{ id, depth, parentId }} }}"#,
+                post.id, body, parent_id
+            )
+        };
+
+        // Top-level comments sit at depth 0 and replies one level below their parent
+        let first = execute(comment(None, "First")).await["data"]["createComment"].clone();
+        assert_eq!(first["depth"], 0);
+        assert_eq!(first["parentId"], serde_json::Value::Null);
+        let reply = execute(comment(first["id"].as_str(), "Reply")).await["data"]["createComment"].clone();
+        assert_eq!(reply["depth"], 1);
+        assert_eq!(reply["parentId"], first["id"]);
+        for body in ["Second", "Third"] {
+            execute(comment(None, body)).await;
+        }
+
+        // Top-level comments are paged by cursor, and replies nest under their parent
+        let page = |after: String| {
+            format!(
+                r#"{{ post(id: "{}") {{ comments(first: 5{}) {{
+                    edges {{ cursor, node {{ body, author {{ displayName }}, replies {{ edges {{ node {{ body }} }} }} }} }},
+                    pageInfo {{ hasNextPage, endCursor }} }} }} }}"#,
+                post.id, after
+            )
+        };
+        let response = execute(page(String::new())).await;
+        let comments = &response["data"]["post"]["comments"];
+        assert_eq!(comments["edges"].as_array().unwrap().len(), 2);
+        assert_eq!(comments["edges"][0]["node"]["body"], "First");
+        assert_eq!(comments["edges"][0]["node"]["author"]["displayName"], "Charlie");
+        assert_eq!(comments["edges"][0]["node"]["replies"]["edges"][0]["node"]["body"], "Reply");
+        assert_eq!(comments["edges"][1]["node"]["body"], "Second");
+        assert_eq!(comments["pageInfo"]["hasNextPage"], true);
+        let response = execute(page(format!(", after: {}", comments["pageInfo"]["endCursor"]))).await;
+        let comments = &response["data"]["post"]["comments"];
+        assert_eq!(comments["edges"][0]["node"]["body"], "Third");
+        assert_eq!(comments["pageInfo"]["hasNextPage"], false);
+
+        // Threads stop at the maximum depth, and the deepest comments have no replies to resolve
+        let mut parent = reply["id"].as_str().unwrap().to_string();
+        for _ in 1..MAX_COMMENT_DEPTH {
+            parent = execute(comment(Some(&parent), "Deeper")).await["data"]["createComment"]["id"].as_str().unwrap().to_string();
+        }
+        let response = execute(comment(Some(&parent), "Too deep")).await;
+        assert_eq!(response["errors"][0]["message"], format!("Replies can be nested at most {} levels deep", MAX_COMMENT_DEPTH));
+
+        // Blank bodies, unknown posts, and parents from elsewhere are refused
+        assert_eq!(execute(comment(None, " ")).await["errors"][0]["message"], "Comment body must not be empty");
+        let response = execute(r#"mutation { createComment(input: { postId: "9", authorId: "2", body: "Hi" }) { id } }"#.to_string()).await;
+        assert_eq!(response["errors"][0]["message"], "Post 9 not found");
+        let response = execute(comment(Some("99"), "Hi")).await;
+        assert_eq!(response["errors"][0]["message"], format!("Comment 99 not found on post {}", post.id));
+    }
+}
@@ -0,0 +1,1669 @@
+// Import necessary libraries and modules
+use async_graphql::connection::{query, Connection, Edge, OpaqueCursor};
+use async_graphql::{Context, ErrorExtensions, InputObject, Json, MaybeUndefined, Object, Result, ResultExt, SimpleObject, Upload, ID};
+use chrono::{NaiveTime, Utc};
+use std::collections::HashSet;
+use std::io::Read;
+
+use super::{audit, publish, user_id_argument, viewer_user, MaxPageSize, RoleGuard, VerifiedGuard, DEFAULT_MAX_PAGE_SIZE};
+use crate::audit::diff;
+use crate::avatar::{AvatarError, Avatars};
+use crate::complexity::{list_cost, page_items, SEARCH_COST, STATS_COST};
+use crate::error::AppError;
+use crate::import::{import_csv, ImportRowResult};
+use crate::loader::UserDataLoader;
+use crate::{log_error, log_warn};
+use crate::model::{duplicate_sort_field, validate_metadata, Address, NewUser, User, UserFilter, UserOrder, UserRole, UserStats, UserUpdate};
+use crate::node::{global_id, USER};
+use crate::repository::SharedRepository;
+use crate::scalars::{CountryCode, Email, PhoneNumber, Timestamp};
+use crate::search::{UserSearchResult, MAX_SEARCH_LIMIT};
+use crate::subscription::UserEvent;
+use crate::upload::UploadLimits;
+use crate::verification::EmailVerification;
+
+// Define a page of users along with how many users there are in total
+#[derive(SimpleObject)]
+pub struct UserPage {
+    pub users: Vec<User>,
+    pub total_count: usize,
+    pub has_next_page: bool,
+}
+
+// Define the conditions the users queries can narrow their results by; every given condition must hold
+#[derive(Default, InputObject)]
+#[graphql(name = "UserFilter")]
+pub struct UserFilterInput {
+    pub name_contains: Option<String>,
+    pub email_domain: Option<String>,
+    pub created_after: Option<Timestamp>,
+    pub created_before: Option<Timestamp>,
+    pub role: Option<UserRole>,
+}
+
+impl UserFilterInput {
+    // Convert the GraphQL input into the repository's filter
+    fn into_filter(self, include_deleted: bool) -> UserFilter {
+        UserFilter {
+            include_deleted,
+            created_after: self.created_after.map(|after| after.0),
+            created_before: self.created_before.map(|before| before.0),
+            name_contains: self.name_contains,
+            email_domain: self.email_domain,
+            role: self.role,
+        }
+    }
+}
+
+// Define the fields a users connection has besides its edges and page info
+#[derive(SimpleObject)]
+pub struct UserConnectionFields {
+    pub total_count: usize,
+}
+
+// Define the Relay connection returned by usersConnection; cursors are opaque positions in the listing order
+pub type UserConnection = Connection<OpaqueCursor<usize>, User, UserConnectionFields>;
+
+// Define the queries that read users
+#[derive(Default)]
+pub struct UserQuery;
+
+// Implement GraphQL Object for the UserQuery struct
+#[Object]
+impl UserQuery {
+    async fn user_by_id(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        #[graphql(default)] include_deleted: bool,
+    ) -> Result<Option<User>> {
+        // Soft-deleted users are only returned to admins who ask for them; those lookups skip the loader
+        let id = user_id_argument(&id)?;
+        if include_deleted {
+            authorize_include_deleted(ctx).await?;
+            let repository = ctx.data::<SharedRepository>()?;
+            return repository.get_including_deleted(&id).await.extend();
+        }
+
+        // Return a user based on the provided ID, batched with other lookups in the request
+        let loader = ctx.data::<UserDataLoader>()?;
+        loader.load_one(id).await.map_err(|error| error.extend())
+    }
+
+    async fn me(&self, ctx: &Context<'_>) -> Result<User> {
+        // Return the user the request's bearer token belongs to
+        viewer_user(ctx).await
+    }
+
+    #[graphql(complexity = "list_cost(ids.len(), child_complexity)")]
+    async fn users_by_ids(&self, ctx: &Context<'_>, ids: Vec<ID>) -> Result<Vec<Option<User>>> {
+        // Return users in the order their IDs were given, with null for missing or soft-deleted ones,
+        // loading them all in one batch; at most one page of IDs may be asked for at a time
+        let max_page_size = ctx.data_opt::<MaxPageSize>().map_or(DEFAULT_MAX_PAGE_SIZE, |max| max.0);
+        if ids.len() > max_page_size {
+            return Err(AppError::Validation(format!("usersByIds accepts at most {} IDs", max_page_size)).extend());
+        }
+        let loader = ctx.data::<UserDataLoader>()?;
+        let ids = ids.iter().map(|id| user_id_argument(id)).collect::<Result<Vec<String>>>()?;
+        let users = loader.load_many(ids.iter().cloned()).await.map_err(|error| error.extend())?;
+        Ok(ids.iter().map(|id| users.get(id).cloned()).collect())
+    }
+
+    async fn user_by_email(&self, ctx: &Context<'_>, email: Email) -> Result<Option<User>> {
+        // Return the live user with an email address, ignoring case
+        let repository = ctx.data::<SharedRepository>()?;
+        repository.get_by_email(&email.0).await.extend()
+    }
+
+    #[graphql(complexity = "SEARCH_COST + list_cost(limit, child_complexity)")]
+    async fn search_users(
+        &self,
+        ctx: &Context<'_>,
+        query: String,
+        #[graphql(default = 20)] limit: usize,
+        #[graphql(default)] offset: usize,
+    ) -> Result<Vec<UserSearchResult>> {
+        // Return a page of the best matches for a free-text query over names and emails
+        let repository = ctx.data::<SharedRepository>()?;
+        repository.search(&query, limit.min(MAX_SEARCH_LIMIT), offset).await.extend()
+    }
+
+    #[graphql(complexity = "list_cost(limit, child_complexity)")]
+    async fn users(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 20)] limit: usize,
+        #[graphql(default)] offset: usize,
+        #[graphql(default)] include_deleted: bool,
+        filter: Option<UserFilterInput>,
+        #[graphql(default, validator(max_items = 3))] order_by: Vec<UserOrder>,
+    ) -> Result<UserPage> {
+        // Return a page of users sorted by orderBy and then creation order, optionally including soft-deleted ones
+        if include_deleted {
+            authorize_include_deleted(ctx).await?;
+        }
+        let repository = ctx.data::<SharedRepository>()?;
+        let max_page_size = ctx.data_opt::<MaxPageSize>().map_or(DEFAULT_MAX_PAGE_SIZE, |max| max.0);
+        let filter = filter.unwrap_or_default().into_filter(include_deleted);
+        validate_order(&order_by)?;
+        let users = repository.list_range(&filter, &order_by, offset, limit.min(max_page_size)).await.extend()?;
+        let total_count = repository.count(&filter).await.extend()?;
+        Ok(UserPage {
+            has_next_page: offset + users.len() < total_count,
+            users,
+            total_count,
+        })
+    }
+
+    // Resolvers take one parameter per GraphQL argument
+    #[allow(clippy::too_many_arguments)]
+    #[graphql(complexity = "list_cost(page_items(first.or(last)), child_complexity)")]
+    async fn users_connection(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+        #[graphql(default)] include_deleted: bool,
+        filter: Option<UserFilterInput>,
+        #[graphql(default, validator(max_items = 3))] order_by: Vec<UserOrder>,
+    ) -> Result<UserConnection> {
+        // Return a Relay connection over users sorted by orderBy and then creation order, at most one page size at a time.
+        // Cursors are positions in that order, so pages should be requested with the same orderBy
+        if include_deleted {
+            authorize_include_deleted(ctx).await?;
+        }
+        let repository = ctx.data::<SharedRepository>()?;
+        let max_page_size = ctx.data_opt::<MaxPageSize>().map_or(DEFAULT_MAX_PAGE_SIZE, |max| max.0);
+        let filter = filter.unwrap_or_default().into_filter(include_deleted);
+        validate_order(&order_by)?;
+        query(after, before, first, last, |after: Option<OpaqueCursor<usize>>, before: Option<OpaqueCursor<usize>>, first, last| async move {
+            // Narrow the window between the cursors to the first or last users in it
+            let total_count = repository.count(&filter).await.extend()?;
+            let end = before.map_or(total_count, |before| before.0).min(total_count);
+            let start = after.map_or(0, |after| after.0 + 1).min(end);
+            let (start, end) = match (first, last) {
+                (_, Some(last)) => (start.max(end.saturating_sub(last.min(max_page_size))), end),
+                (first, None) => (start, end.min(start + first.unwrap_or(max_page_size).min(max_page_size))),
+            };
+
+            let users = repository.list_range(&filter, &order_by, start, end - start).await.extend()?;
+            let mut connection = Connection::with_additional_fields(start > 0, end < total_count, UserConnectionFields { total_count });
+            connection.edges.extend(
+                users.into_iter().enumerate().map(|(index, user)| Edge::new(OpaqueCursor(start + index), user)),
+            );
+            Ok::<_, async_graphql::Error>(connection)
+        })
+        .await
+    }
+
+    #[graphql(complexity = "STATS_COST + child_complexity")]
+    async fn stats(&self, ctx: &Context<'_>) -> Result<UserStats> {
+        // Count the live users, those created since midnight UTC, and how they split by role and email domain
+        let repository = ctx.data::<SharedRepository>()?;
+        let today = Utc::now().date_naive().and_time(NaiveTime::MIN).and_utc();
+        repository.stats(today).await.extend()
+    }
+}
+
+// Define the input accepted by the createUser mutation; names must be 1 to MAX_NAME_LENGTH characters
+#[derive(InputObject)]
+pub struct CreateUserInput {
+    #[graphql(validator(min_length = 1, max_length = 100))]
+    pub name: String,
+    pub email: Email,
+    pub role: Option<UserRole>,
+    pub phone: Option<PhoneNumber>,
+    pub address: Option<AddressInput>,
+}
+
+// Convert the GraphQL input into the repository's creation type
+impl From<CreateUserInput> for NewUser {
+    fn from(input: CreateUserInput) -> Self {
+        NewUser {
+            id: None,
+            name: input.name,
+            email: input.email.0,
+            role: input.role.unwrap_or_default(),
+            phone: input.phone.map(|phone| phone.0),
+            address: input.address.map(Address::from),
+            email_verified: true,
+        }
+    }
+}
+
+// Define the input accepted by the updateUser mutation; omitted fields are left unchanged, and a null phone or address removes it
+#[derive(InputObject)]
+pub struct UpdateUserInput {
+    #[graphql(validator(min_length = 1, max_length = 100))]
+    pub name: Option<String>,
+    pub email: Option<Email>,
+    pub role: Option<UserRole>,
+    pub phone: MaybeUndefined<PhoneNumber>,
+    pub address: MaybeUndefined<AddressInput>,
+}
+
+// Convert the GraphQL input into the repository's update type
+impl From<UpdateUserInput> for UserUpdate {
+    fn from(input: UpdateUserInput) -> Self {
+        UserUpdate {
+            name: input.name,
+            email: input.email.map(|email| email.0),
+            avatar_key: None,
+            role: input.role,
+            metadata: None,
+            phone: input.phone.map_value(|phone| phone.0).into(),
+            address: input.address.map_value(Address::from).into(),
+            email_verified: None,
+            expected_version: None,
+        }
+    }
+}
+
+// Define the postal address accepted when creating or updating a user; the whole address is replaced at once
+#[derive(InputObject)]
+pub struct AddressInput {
+    #[graphql(validator(min_length = 1, max_length = 200))]
+    pub street: String,
+    #[graphql(validator(min_length = 1, max_length = 100))]
+    pub city: String,
+    pub country: CountryCode,
+    #[graphql(validator(max_length = 20))]
+    pub postal_code: Option<String>,
+}
+
+// Convert the GraphQL input into the stored address
+impl From<AddressInput> for Address {
+    fn from(input: AddressInput) -> Self {
+        Address { street: input.street, city: input.city, country: input.country.0, postal_code: input.postal_code }
+    }
+}
+
+// Define the payload returned by the deleteUser mutation
+#[derive(SimpleObject)]
+pub struct DeleteUserPayload {
+    pub user: Option<User>,
+    pub success: bool,
+}
+
+// Define the payload returned by the importUsers mutation
+#[derive(SimpleObject)]
+pub struct ImportUsersPayload {
+    pub results: Vec<ImportRowResult>,
+    pub imported_count: usize,
+    pub failed_count: usize,
+}
+
+// Define the outcome of one input to the createUsers mutation: the created user, or why it was not created
+#[derive(SimpleObject)]
+pub struct CreateUserResult {
+    // Position of the input in the list, starting at 0
+    pub index: usize,
+    pub user: Option<User>,
+    pub error: Option<String>,
+    pub code: Option<String>,
+}
+
+impl CreateUserResult {
+    fn new(index: usize, outcome: std::result::Result<User, AppError>) -> Self {
+        match outcome {
+            Ok(user) => CreateUserResult { index, user: Some(user), error: None, code: None },
+            Err(error) => CreateUserResult { index, user: None, error: Some(error.to_string()), code: Some(error.code().to_string()) },
+        }
+    }
+}
+
+// Define the payload returned by the uploadAvatar mutation
+#[derive(SimpleObject)]
+pub struct UploadAvatarPayload {
+    pub user: User,
+    pub url: String,
+}
+
+// Define the mutations that change users
+#[derive(Default)]
+pub struct UserMutation;
+
+// Implement GraphQL Object for the UserMutation struct
+#[Object]
+impl UserMutation {
+    #[graphql(guard = "VerifiedGuard")]
+    async fn create_user(&self, ctx: &Context<'_>, input: CreateUserInput) -> Result<User> {
+        // Store the new user and return it with its assigned ID
+        authorize_role(ctx, input.role).await?;
+        let repository = ctx.data::<SharedRepository>()?;
+        let user = repository.create(input.into()).await.extend()?;
+        audit(ctx, "createUser", Some(global_id(USER, &user.id)), diff(None, Some(&user))).await;
+        publish(ctx, UserEvent::Created(user.clone()));
+        Ok(user)
+    }
+
+    #[graphql(guard = "VerifiedGuard")]
+    async fn create_users(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(validator(max_items = 100))] inputs: Vec<CreateUserInput>,
+    ) -> Result<Vec<CreateUserResult>> {
+        // Store each user in turn, reporting the outcome of every input instead of stopping at the first failure;
+        // an email address may only be used once per batch
+        authorize_role(ctx, inputs.iter().find_map(|input| input.role)).await?;
+        let repository = ctx.data::<SharedRepository>()?;
+        let mut seen_emails = HashSet::new();
+        let mut results = Vec::with_capacity(inputs.len());
+        for (index, input) in inputs.into_iter().enumerate() {
+            let outcome = match seen_emails.insert(input.email.0.clone()) {
+                true => repository.create(input.into()).await.map_err(AppError::from),
+                false => Err(AppError::Conflict(format!("{} appears more than once in the batch", input.email.0))),
+            };
+            if let Ok(user) = &outcome {
+                audit(ctx, "createUsers", Some(global_id(USER, &user.id)), diff(None, Some(user))).await;
+                publish(ctx, UserEvent::Created(user.clone()));
+            }
+            results.push(CreateUserResult::new(index, outcome));
+        }
+        Ok(results)
+    }
+
+    #[graphql(guard = "VerifiedGuard")]
+    async fn update_user(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+        expected_version: i32,
+        input: UpdateUserInput,
+    ) -> Result<User> {
+        // Change only the provided fields of the user, provided nobody else changed it since it was read.
+        // When verification is enabled, a new email address is unverified until the user follows the link
+        // mailed to it
+        let user_id = user_id_argument(&id)?;
+        authorize_owner(ctx, &user_id).await?;
+        authorize_role(ctx, input.role).await?;
+        let repository = ctx.data::<SharedRepository>()?;
+        let before = repository.get(&user_id).await.extend()?;
+        let verification = ctx.data_opt::<EmailVerification>();
+        let email_changed = match (&input.email, &before) {
+            (Some(email), Some(before)) => !email.0.eq_ignore_ascii_case(&before.email),
+            _ => false,
+        };
+        let email_verified = (email_changed && verification.is_some()).then_some(false);
+        let update = UserUpdate { expected_version: Some(expected_version), email_verified, ..input.into() };
+        match repository.update(&user_id, update).await {
+            Ok(Some(user)) => {
+                audit(ctx, "updateUser", Some(global_id(USER, &user.id)), diff(before.as_ref(), Some(&user))).await;
+                publish(ctx, UserEvent::Updated(user.clone()));
+                if let (Some(verification), Some(false)) = (verification, email_verified) {
+                    // As at registration, a failure to send the email does not undo the change
+                    if let Err(error) = verification.send(&user).await {
+                        log_error!("Failed to send the verification email for user {}: {}", user.id, error);
+                    }
+                }
+                Ok(user)
+            }
+            Ok(None) => Err(AppError::NotFound(format!("User {} not found", id.as_str())).extend()),
+            Err(error) => Err(error.extend()),
+        }
+    }
+
+    #[graphql(guard = "VerifiedGuard")]
+    async fn set_user_metadata(&self, ctx: &Context<'_>, id: ID, metadata: Option<Json<serde_json::Value>>) -> Result<User> {
+        // Replace the user's metadata with a JSON object within the size limits, or clear it with null
+        let user_id = user_id_argument(&id)?;
+        authorize_owner(ctx, &user_id).await?;
+        let metadata = metadata.map_or(serde_json::Value::Null, |metadata| metadata.0);
+        if !metadata.is_null() {
+            validate_metadata(&metadata).map_err(|message| AppError::Validation(message).extend())?;
+        }
+        let repository = ctx.data::<SharedRepository>()?;
+        let update = UserUpdate { metadata: Some(metadata), ..Default::default() };
+        let before = repository.get(&user_id).await.extend()?;
+        let user = repository
+            .update(&user_id, update)
+            .await.extend()?
+            .ok_or_else(|| AppError::NotFound(format!("User {} not found", id.as_str())).extend())?;
+        audit(ctx, "setUserMetadata", Some(global_id(USER, &user.id)), diff(before.as_ref(), Some(&user))).await;
+        publish(ctx, UserEvent::Updated(user.clone()));
+        Ok(user)
+    }
+
+    #[graphql(guard = "RoleGuard::new(UserRole::Admin)")]
+    async fn delete_user(&self, ctx: &Context<'_>, id: ID, #[graphql(default)] hard: bool) -> Result<DeleteUserPayload> {
+        // Soft-delete the user unless a hard delete is requested, and report whether it was found; only admins may delete users
+        let repository = ctx.data::<SharedRepository>()?;
+        let id = user_id_argument(&id)?;
+        let user = match hard {
+            true => repository.purge(&id).await.extend()?,
+            false => repository.delete(&id).await.extend()?,
+        };
+        // A soft-deleted user still exists, so watchers see it change; a purged one is simply gone.
+        // Soft deletes only stamp the deletion time, so the user before it is the one returned without it
+        match (hard, &user) {
+            (true, Some(user)) => audit(ctx, "deleteUser", Some(global_id(USER, &user.id)), diff(Some(user), None)).await,
+            (false, Some(user)) => {
+                let before = User { deleted_at: None, ..user.clone() };
+                audit(ctx, "deleteUser", Some(global_id(USER, &user.id)), diff(Some(&before), Some(user))).await;
+                publish(ctx, UserEvent::Updated(user.clone()));
+            }
+            (_, None) => {}
+        }
+        Ok(DeleteUserPayload {
+            success: user.is_some(),
+            user,
+        })
+    }
+
+    #[graphql(guard = "RoleGuard::new(UserRole::Admin)")]
+    async fn restore_user(&self, ctx: &Context<'_>, id: ID) -> Result<User> {
+        // Bring back a soft-deleted user; only admins may restore users
+        let repository = ctx.data::<SharedRepository>()?;
+        let user_id = user_id_argument(&id)?;
+        let before = repository.get_including_deleted(&user_id).await.extend()?;
+        let user = repository
+            .restore(&user_id)
+            .await.extend()?
+            .ok_or_else(|| AppError::NotFound(format!("User {} not found", id.as_str())).extend())?;
+        audit(ctx, "restoreUser", Some(global_id(USER, &user.id)), diff(before.as_ref(), Some(&user))).await;
+        publish(ctx, UserEvent::Updated(user.clone()));
+        Ok(user)
+    }
+
+    #[graphql(guard = "RoleGuard::new(UserRole::Admin)")]
+    async fn import_users(&self, ctx: &Context<'_>, csv: Option<String>, file: Option<Upload>) -> Result<ImportUsersPayload> {
+        // Validate and insert the CSV rows, given inline or as an uploaded file, reporting the outcome of each one;
+        // only admins may import users
+        let csv = match (csv, file) {
+            (Some(csv), None) => csv,
+            (None, Some(file)) => read_csv_upload(ctx, file)?,
+            _ => return Err(AppError::Validation("Pass the CSV as exactly one of csv or file".to_string()).extend()),
+        };
+        let repository = ctx.data::<SharedRepository>()?;
+        let results = import_csv(repository.as_ref(), &csv).await.map_err(|error| AppError::Validation(error.to_string()).extend())?;
+        for user in results.iter().filter_map(|result| result.user.clone()) {
+            audit(ctx, "importUsers", Some(global_id(USER, &user.id)), diff(None, Some(&user))).await;
+            publish(ctx, UserEvent::Created(user));
+        }
+        let imported_count = results.iter().filter(|result| result.user.is_some()).count();
+        Ok(ImportUsersPayload {
+            failed_count: results.len() - imported_count,
+            imported_count,
+            results,
+        })
+    }
+
+    #[graphql(guard = "VerifiedGuard")]
+    async fn upload_avatar(&self, ctx: &Context<'_>, id: ID, file: Upload) -> Result<UploadAvatarPayload> {
+        // Validate and store the image, point the user at it, and return a signed URL for it
+        let Some(avatars) = ctx.data_opt::<Avatars>() else {
+            return Err(AppError::Unsupported("Avatar uploads are not enabled".to_string()).extend());
+        };
+        let user_id = user_id_argument(&id)?;
+        authorize_owner(ctx, &user_id).await?;
+        let repository = ctx.data::<SharedRepository>()?;
+        let Some(user) = repository.get(&user_id).await.extend()? else {
+            return Err(AppError::NotFound(format!("User {} not found", id.as_str())).extend());
+        };
+
+        // Refuse oversized files before reading them into memory
+        let upload = file.value(ctx)?;
+        let size = upload.size()?;
+        if size > avatars.max_bytes() as u64 {
+            return Err(invalid_avatar(AvatarError::TooLarge { size, max: avatars.max_bytes() }));
+        }
+        let content_type = upload.content_type.clone();
+        let mut content = Vec::with_capacity(size as usize);
+        upload.into_read().read_to_end(&mut content)?;
+        let key = avatars
+            .upload(&user.id, content_type.as_deref(), content)
+            .await
+            .map_err(invalid_avatar)?;
+
+        let update = UserUpdate { avatar_key: Some(key.clone()), ..Default::default() };
+        let Some(updated) = repository.update(&user.id, update).await.extend()? else {
+            avatars.remove(&key).await.extend()?;
+            return Err(AppError::NotFound(format!("User {} not found", id.as_str())).extend());
+        };
+
+        audit(ctx, "uploadAvatar", Some(global_id(USER, &updated.id)), diff(Some(&user), Some(&updated))).await;
+
+        // The previous image is no longer reachable, so a failure to remove it only costs storage
+        if let Some(previous) = user.avatar_key.filter(|previous| *previous != key) {
+            if let Err(error) = avatars.remove(&previous).await {
+                log_warn!("Failed to remove replaced avatar {}: {}", previous, error);
+            }
+        }
+        publish(ctx, UserEvent::Updated(updated.clone()));
+        let url = avatars.url(&key).await.extend()?;
+        Ok(UploadAvatarPayload { user: updated, url })
+    }
+}
+
+// Refuse to show soft-deleted users to anyone but admins
+async fn authorize_include_deleted(ctx: &Context<'_>) -> Result<()> {
+    if !viewer_user(ctx).await?.role.includes(UserRole::Admin) {
+        return Err(AppError::Forbidden("Only admins may include deleted users".to_string()).extend());
+    }
+    Ok(())
+}
+
+// Refuse to set a role unless the viewer is an admin, so members cannot promote themselves or anyone else
+async fn authorize_role(ctx: &Context<'_>, role: Option<UserRole>) -> Result<()> {
+    if role.is_some() && !viewer_user(ctx).await?.role.includes(UserRole::Admin) {
+        return Err(AppError::Forbidden("Only admins may set a user's role".to_string()).extend());
+    }
+    Ok(())
+}
+
+// Refuse changes to another user's record unless the viewer is an admin
+async fn authorize_owner(ctx: &Context<'_>, user_id: &str) -> Result<()> {
+    let viewer = viewer_user(ctx).await?;
+    if viewer.id != user_id && !viewer.role.includes(UserRole::Admin) {
+        return Err(AppError::Forbidden("Only admins may change other users".to_string()).extend());
+    }
+    Ok(())
+}
+
+// Reject sort orders that list a field more than once, since the later key could never take effect
+fn validate_order(order: &[UserOrder]) -> Result<()> {
+    match duplicate_sort_field(order) {
+        Some(field) => Err(AppError::InvalidOrder(async_graphql::InputType::to_value(&field).to_string()).extend()),
+        None => Ok(()),
+    }
+}
+
+// Read an uploaded CSV file, refusing files over the upload limit before reading them into memory
+fn read_csv_upload(ctx: &Context<'_>, file: Upload) -> Result<String> {
+    let limits = ctx.data_opt::<UploadLimits>().copied().unwrap_or_default();
+    let upload = file.value(ctx)?;
+    let size = upload.size()?;
+    if size > limits.max_file_bytes as u64 {
+        return Err(AppError::Validation(format!("The file is {} bytes, over the {} byte limit", size, limits.max_file_bytes)).extend());
+    }
+    let mut csv = String::with_capacity(size as usize);
+    upload
+        .into_read()
+        .read_to_string(&mut csv)
+        .map_err(|_| AppError::Validation("The file is not UTF-8 text".to_string()).extend())?;
+    Ok(csv)
+}
+
+// Report a rejected upload with an INVALID_AVATAR code; storage failures keep the storage error's code
+fn invalid_avatar(error: AvatarError) -> async_graphql::Error {
+    AppError::InvalidAvatar(error).extend()
+}
+
+// Integration tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{AuthContext, Viewer};
+    use crate::node::global_id;
+    use crate::repository::{InMemoryRepository, UserRepository};
+    use crate::schema::tests::{admin_schema, as_pavel, promote_pavel, sample_schema, CHARLIE, PAVEL};
+    use crate::schema::{build_schema, AppState};
+    use async_graphql::Request;
+    use futures::{FutureExt, StreamExt};
+    use std::sync::Arc;
+
+    // Define a test for GraphQL queries
+    #[tokio::test]
+    async fn test_graphql_query() {
+        let schema = sample_schema();
+
+        // Create a request for the "userById" query
+        let request = Request::new(r#"{ userById(id: "1") { id, name, email } }"#);
+
+        // Simulate a GraphQL query by executing the request against the schema
+        let response = schema.execute(request).await;
+
+        // Assert that the response is successful
+        assert!(response.is_ok());
+
+        // Convert the async_graphql::Value to serde_json::Value
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+
+        // Assert that the response data matches the expected JSON
+        let expected_response = serde_json::json!({
+            "userById": {
+                "id": global_id(USER, PAVEL),
+                "name": "Pavel",
+                "email": null
+            }
+        });
+        assert_eq!(response_data, expected_response);
+    }
+
+    // Define a test for the searchUsers query over the in-memory fallback
+    #[tokio::test]
+    async fn test_search_users_query() {
+        let schema = admin_schema().await;
+
+        let response = schema.execute(r#"{ searchUsers(query: "noibu") { user { id }, rank, snippet } }"#).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(
+            response_data,
+            serde_json::json!({
+                "searchUsers": [{
+                    "user": { "id": global_id(USER, CHARLIE) },
+                    "rank": 0.5,
+                    "snippet": "Charlie charlie.gracie@<b>noibu</b>.com"
+                }]
+            })
+        );
+
+        // Soft-deleted users are not found
+        schema.execute(as_pavel(r#"mutation { deleteUser(id: "2") { success } }"#)).await;
+        let response = schema.execute(r#"{ searchUsers(query: "noibu") { user { id } } }"#).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(response_data, serde_json::json!({ "searchUsers": [] }));
+    }
+
+    // Define a test for the createUser mutation
+    #[tokio::test]
+    async fn test_create_user_mutation() {
+        let schema = sample_schema();
+
+        // Create a new user and check it is assigned a version 7 UUID
+        let request = Request::new(
+            r#"mutation { createUser(input: { name: "Ada", email: "ada@example.com" }) { id, uuid, name, email } }"#,
+        );
+        let response = schema.execute(as_pavel(request)).await;
+        assert!(response.is_ok());
+
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        let uuid = response_data["createUser"]["uuid"].as_str().unwrap();
+        assert_eq!(uuid::Uuid::parse_str(uuid).unwrap().get_version_num(), 7);
+        assert_eq!(
+            response_data,
+            serde_json::json!({
+                "createUser": { "id": global_id(USER, uuid), "uuid": uuid, "name": "Ada", "email": null }
+            })
+        );
+
+        // Assert that the new user can be fetched afterwards
+        let response = schema.execute(format!(r#"{{ userById(id: "{}") {{ name }} }}"#, uuid)).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(response_data, serde_json::json!({ "userById": { "name": "Ada" } }));
+    }
+
+    // Define a test for the updateUser mutation
+    #[tokio::test]
+    async fn test_update_user_mutation() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        let schema = build_schema(AppState::new(repository.clone()));
+        let mutation = r#"mutation { updateUser(id: "2", expectedVersion: 1, input: { name: "Chuck" }) { id, name, email, version } }"#;
+        let error_code = |response: async_graphql::Response| serde_json::to_value(&response.errors[0]).unwrap()["extensions"]["code"].clone();
+
+        // Anonymous callers are refused, and members may not change other users
+        assert_eq!(error_code(schema.execute(mutation).await), "UNAUTHENTICATED");
+        assert_eq!(error_code(schema.execute(as_pavel(mutation)).await), "FORBIDDEN");
+
+        // Users update their own record, changing only the name and leaving the email as it was
+        let response = schema.execute(Request::new(mutation).data(AuthContext::from(Viewer::User(CHARLIE.to_string())))).await;
+        assert!(response.is_ok());
+
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(
+            response_data,
+            serde_json::json!({
+                "updateUser": { "id": global_id(USER, CHARLIE), "name": "Chuck", "email": "charlie.gracie@noibu.com", "version": 2 }
+            })
+        );
+
+        // Admins update anyone, and are told when the user does not exist
+        promote_pavel(&repository).await;
+        let response = schema.execute(as_pavel(r#"mutation { updateUser(id: "2", expectedVersion: 2, input: { name: "Charles" }) { name } }"#)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let response = schema
+            .execute(as_pavel(r#"mutation { updateUser(id: "42", expectedVersion: 1, input: { name: "Nobody" }) { id } }"#))
+            .await;
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(response.errors[0].message, "User 42 not found");
+    }
+
+    // Define a test for setting, normalizing, masking, and removing phone numbers
+    #[tokio::test]
+    async fn test_user_phone() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        promote_pavel(&repository).await;
+        let schema = build_schema(AppState::new(repository));
+
+        // Numbers are stored in E.164 form, and only admins see them in full
+        let response = schema
+            .execute(as_pavel(r#"mutation { createUser(input: { name: "Ada", email: "ada@example.com", phone: "+1 (415) 555-0123" }) { id, phone } }"#))
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let response_data = serde_json::to_value(response.data).unwrap();
+        assert_eq!(response_data["createUser"]["phone"], "+14155550123");
+        let id = response_data["createUser"]["id"].as_str().unwrap().to_string();
+        let response = schema.execute(format!(r#"{{ userById(id: "{}") {{ phone }} }}"#, id)).await;
+        assert_eq!(serde_json::to_value(response.data).unwrap(), serde_json::json!({ "userById": { "phone": "+***" } }));
+
+        // Omitting the phone leaves it, null removes it
+        let update = |version: i32, input: &str| {
+            as_pavel(format!(r#"mutation {{ updateUser(id: "{}", expectedVersion: {}, input: {}) {{ phone }} }}"#, id, version, input))
+        };
+        let response = schema.execute(update(1, r#"{ name: "Ada L" }"#)).await;
+        assert_eq!(serde_json::to_value(response.data).unwrap(), serde_json::json!({ "updateUser": { "phone": "+14155550123" } }));
+        let response = schema.execute(update(2, "{ phone: null }")).await;
+        assert_eq!(serde_json::to_value(response.data).unwrap(), serde_json::json!({ "updateUser": { "phone": null } }));
+
+        // Invalid numbers are rejected before the resolver runs, saying why
+        let response = schema.execute(update(3, r#"{ phone: "555-0123" }"#)).await;
+        assert!(response.errors[0].message.contains("it must start with + and a country code"), "{}", response.errors[0].message);
+    }
+
+    // Define a test for setting, masking, replacing, and removing addresses
+    #[tokio::test]
+    async fn test_user_address() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        promote_pavel(&repository).await;
+        let schema = build_schema(AppState::new(repository));
+
+        // Country codes are normalized, and only admins see the street and postal code in full
+        let response = schema
+            .execute(as_pavel(
+                r#"mutation { createUser(input: { name: "Ada", email: "ada@example.com", address: { street: "1 Main St", city: "Toronto", country: " ca ", postalCode: "M5V 2T6" } }) { id, address { street, city, country, postalCode } } }"#,
+            ))
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let response_data = serde_json::to_value(response.data).unwrap();
+        assert_eq!(
+            response_data["createUser"]["address"],
+            serde_json::json!({ "street": "1 Main St", "city": "Toronto", "country": "CA", "postalCode": "M5V 2T6" })
+        );
+        let id = response_data["createUser"]["id"].as_str().unwrap().to_string();
+        let response = schema.execute(format!(r#"{{ userById(id: "{}") {{ address {{ street, city, country, postalCode }} }} }}"#, id)).await;
+        let address = serde_json::to_value(response.data).unwrap()["userById"]["address"].clone();
+        assert_eq!(address, serde_json::json!({ "street": "1***", "city": "Toronto", "country": "CA", "postalCode": "M***" }));
+
+        // Omitting the address leaves it, a new one replaces it whole, and null removes it
+        let update = |version: i32, input: &str| {
+            as_pavel(format!(r#"mutation {{ updateUser(id: "{}", expectedVersion: {}, input: {}) {{ address {{ city, postalCode }} }} }}"#, id, version, input))
+        };
+        let response = schema.execute(update(1, r#"{ name: "Ada L" }"#)).await;
+        assert_eq!(serde_json::to_value(response.data).unwrap(), serde_json::json!({ "updateUser": { "address": { "city": "Toronto", "postalCode": "M5V 2T6" } } }));
+        let response = schema.execute(update(2, r#"{ address: { street: "5 Rue Cler", city: "Paris", country: "FR" } }"#)).await;
+        assert_eq!(serde_json::to_value(response.data).unwrap(), serde_json::json!({ "updateUser": { "address": { "city": "Paris", "postalCode": null } } }));
+        let response = schema.execute(update(3, "{ address: null }")).await;
+        assert_eq!(serde_json::to_value(response.data).unwrap(), serde_json::json!({ "updateUser": { "address": null } }));
+
+        // Unknown country codes and empty streets are rejected before the resolver runs
+        let response = schema.execute(update(4, r#"{ address: { street: "1 Main St", city: "London", country: "UK" } }"#)).await;
+        assert!(response.errors[0].message.contains(r#""UK" is not an ISO 3166-1 alpha-2 country code"#), "{}", response.errors[0].message);
+        let response = schema.execute(update(4, r#"{ address: { street: "", city: "London", country: "GB" } }"#)).await;
+        assert_eq!(response.errors.len(), 1);
+    }
+
+    // Define a test for the setUserMetadata mutation and the metadata field
+    #[tokio::test]
+    async fn test_set_user_metadata_mutation() {
+        let schema = sample_schema();
+        let request = |metadata: serde_json::Value| {
+            as_pavel(r#"mutation($metadata: JSON) { setUserMetadata(id: "1", metadata: $metadata) { version, metadata } }"#)
+                .variables(async_graphql::Variables::from_json(serde_json::json!({ "metadata": metadata })))
+        };
+        let error_code = |response: async_graphql::Response| serde_json::to_value(&response.errors[0]).unwrap()["extensions"]["code"].clone();
+
+        // Users start without metadata; any JSON object can be set and read back as it was
+        let response = schema.execute(r#"{ userById(id: "1") { metadata } }"#).await;
+        assert_eq!(serde_json::to_value(response.data).unwrap(), serde_json::json!({ "userById": { "metadata": null } }));
+        let metadata = serde_json::json!({ "team": "core", "tags": ["a", 1], "preferences": { "theme": "dark" } });
+        let response = schema.execute(request(metadata.clone())).await;
+        assert_eq!(serde_json::to_value(response.data).unwrap(), serde_json::json!({ "setUserMetadata": { "version": 2, "metadata": metadata } }));
+
+        // Other updates keep it, and null clears it
+        let response = schema.execute(as_pavel(r#"mutation { updateUser(id: "1", expectedVersion: 2, input: { name: "Pavel B" }) { metadata } }"#)).await;
+        assert_eq!(serde_json::to_value(response.data).unwrap()["updateUser"]["metadata"], metadata);
+        let response = schema.execute(request(serde_json::Value::Null)).await;
+        assert_eq!(serde_json::to_value(response.data).unwrap(), serde_json::json!({ "setUserMetadata": { "version": 4, "metadata": null } }));
+
+        // Non-objects, oversized objects, and deeply nested ones are refused
+        let oversized = serde_json::json!({ "blob": "x".repeat(crate::model::MAX_METADATA_BYTES) });
+        let mut nested = serde_json::json!({});
+        for _ in 0..crate::model::MAX_METADATA_DEPTH {
+            nested = serde_json::json!({ "next": nested });
+        }
+        for metadata in [serde_json::json!(["a"]), serde_json::json!("text"), oversized, nested] {
+            assert_eq!(error_code(schema.execute(request(metadata)).await), "VALIDATION_FAILED");
+        }
+
+        // Members may not change other users' metadata
+        let response = schema.execute(as_pavel(r#"mutation { setUserMetadata(id: "2", metadata: {}) { id } }"#)).await;
+        assert_eq!(error_code(response), "FORBIDDEN");
+
+        // Missing users are reported as such
+        let schema = admin_schema().await;
+        let response = schema.execute(as_pavel(r#"mutation { setUserMetadata(id: "99", metadata: {}) { id } }"#)).await;
+        assert_eq!(error_code(response), "NOT_FOUND");
+    }
+
+    // Define a test that users report creation and update times in one RFC 3339 format
+    #[tokio::test]
+    async fn test_user_timestamps() {
+        let schema = admin_schema().await;
+        let timestamps = |response: async_graphql::Response, field: &str| {
+            let data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+            let user = &data[field];
+            let parse = |name: &str| {
+                let text = user[name].as_str().unwrap().to_string();
+                assert!(text.ends_with('Z') && text.len() == "2023-10-20T08:30:00.000000Z".len(), "{} is {}", name, text);
+                chrono::DateTime::parse_from_rfc3339(&text).unwrap()
+            };
+            (parse("createdAt"), parse("updatedAt"))
+        };
+
+        // New users were last updated when they were created
+        let response = schema
+            .execute(as_pavel(r#"mutation { createUser(input: { name: "Ada", email: "ada@example.com" }) { id, createdAt, updatedAt } }"#))
+            .await;
+        let id = serde_json::to_value(&response.data).unwrap()["createUser"]["id"].as_str().unwrap().to_string();
+        let (created_at, updated_at) = timestamps(response, "createUser");
+        assert_eq!(created_at, updated_at);
+
+        // Updating moves only the update time
+        let response = schema
+            .execute(as_pavel(format!(r#"mutation {{ updateUser(id: "{}", expectedVersion: 1, input: {{ name: "Ada L" }}) {{ createdAt, updatedAt }} }}"#, id)))
+            .await;
+        let (after_created_at, after_updated_at) = timestamps(response, "updateUser");
+        assert_eq!(after_created_at, created_at);
+        assert!(after_updated_at > updated_at);
+    }
+
+    // Define a test that a stale expectedVersion is rejected with a VERSION_CONFLICT error
+    #[tokio::test]
+    async fn test_update_user_version_conflict() {
+        let schema = sample_schema();
+        let mutation = r#"mutation { updateUser(id: "1", expectedVersion: 1, input: { name: "Pasha" }) { version } }"#;
+
+        // The first writer wins and moves the user to version 2
+        let response = schema.execute(as_pavel(mutation)).await;
+        assert!(response.is_ok());
+
+        // A second writer that read version 1 is told what the current version is
+        let response = schema.execute(as_pavel(mutation)).await;
+        let error = serde_json::to_value(&response.errors[0]).expect("Failed to convert error to JSON");
+        assert_eq!(
+            error["extensions"],
+            serde_json::json!({ "code": "VERSION_CONFLICT", "expectedVersion": 1, "currentVersion": 2 })
+        );
+        let response = schema.execute(r#"{ userById(id: "1") { name, version } }"#).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(response_data, serde_json::json!({ "userById": { "name": "Pasha", "version": 2 } }));
+    }
+
+    // Define a test for creating several users in one request, past the inputs that fail
+    #[tokio::test]
+    async fn test_create_users_mutation() {
+        let schema = admin_schema().await;
+        let mutation = r#"mutation {
+            createUsers(inputs: [
+                { name: "Ada", email: "ada@example.com" },
+                { name: "Ada Again", email: "ADA@example.com" },
+                { name: "Grace", email: "grace@example.com", role: ADMIN },
+            ]) { index, user { name, email, role }, error, code }
+        }"#;
+        let response = schema.execute(as_pavel(mutation)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(
+            data["createUsers"],
+            serde_json::json!([
+                { "index": 0, "user": { "name": "Ada", "email": "ada@example.com", "role": "MEMBER" }, "error": null, "code": null },
+                { "index": 1, "user": null, "error": "ada@example.com appears more than once in the batch", "code": "CONFLICT" },
+                { "index": 2, "user": { "name": "Grace", "email": "grace@example.com", "role": "ADMIN" }, "error": null, "code": null },
+            ])
+        );
+        let response = schema.execute("{ users { totalCount } }").await;
+        let data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(data["users"]["totalCount"], 4);
+
+        // At most 100 users can be created at once
+        let inputs = vec![r#"{ name: "Bulk", email: "bulk@example.com" }"#; 101].join(", ");
+        let response = schema.execute(as_pavel(format!("mutation {{ createUsers(inputs: [{}]) {{ index }} }}", inputs))).await;
+        assert_eq!(
+            response.errors[0].message,
+            "Failed to parse \"[CreateUserInput!]\": the value length is 101, must be less than or equal to 100"
+        );
+    }
+
+    // Define a test that userCreated streams the users created by each mutation
+    #[tokio::test]
+    async fn test_user_created_subscription() {
+        let schema = admin_schema().await;
+        let mut stream = schema.execute_stream("subscription { userCreated { name } }");
+        assert!(stream.next().now_or_never().is_none());
+
+        for mutation in [
+            r#"mutation { createUser(input: { name: "Ada", email: "ada@example.com" }) { id } }"#,
+            r#"mutation { createUsers(inputs: [{ name: "Grace", email: "grace@example.com" }]) { index } }"#,
+            r#"mutation { importUsers(csv: "name,email\nAlan,alan@example.com\n") { importedCount } }"#,
+        ] {
+            let response = schema.execute(as_pavel(mutation)).await;
+            assert!(response.errors.is_empty(), "{:?}", response.errors);
+        }
+        for name in ["Ada", "Grace", "Alan"] {
+            let response = stream.next().await.expect("Subscription ended early");
+            let data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+            assert_eq!(data, serde_json::json!({ "userCreated": { "name": name } }));
+        }
+    }
+
+    // Define a test that userUpdated streams changes to users, only to the one asked for when given an ID
+    #[tokio::test]
+    async fn test_user_updated_subscription() {
+        let schema = admin_schema().await;
+        let mut all = schema.execute_stream(Request::new("subscription { userUpdated { name, deletedAt } }"));
+        let query = format!(r#"subscription {{ userUpdated(id: "{}") {{ name }} }}"#, global_id(USER, PAVEL).as_str());
+        let mut pavel = schema.execute_stream(Request::new(query));
+        let mut legacy = schema.execute_stream(Request::new(r#"subscription { userUpdated(id: "2") { name } }"#));
+        // Subscriptions start when first polled
+        for stream in [&mut all, &mut pavel, &mut legacy] {
+            assert!(stream.next().now_or_never().is_none());
+        }
+
+        for mutation in [
+            r#"mutation { updateUser(id: "2", expectedVersion: 1, input: { name: "Charles" }) { id } }"#,
+            r#"mutation { updateUser(id: "1", expectedVersion: 2, input: { name: "Paul" }) { id } }"#,
+            r#"mutation { deleteUser(id: "2") { success } }"#,
+            r#"mutation { restoreUser(id: "2") { id } }"#,
+            r#"mutation { createUser(input: { name: "Ada", email: "ada@example.com" }) { id } }"#,
+            r#"mutation { deleteUser(id: "1", hard: true) { success } }"#,
+        ] {
+            let response = schema.execute(as_pavel(mutation)).await;
+            assert!(response.errors.is_empty(), "{:?}", response.errors);
+        }
+        async fn next(stream: &mut (impl futures::Stream<Item = async_graphql::Response> + Unpin)) -> serde_json::Value {
+            let response = stream.next().await.expect("Subscription ended early");
+            serde_json::to_value(response.data).expect("Failed to convert response to JSON")["userUpdated"].clone()
+        }
+        assert_eq!(next(&mut all).await, serde_json::json!({ "name": "Charles", "deletedAt": null }));
+        assert_eq!(next(&mut all).await, serde_json::json!({ "name": "Paul", "deletedAt": null }));
+        assert!(next(&mut all).await["deletedAt"].is_string());
+        assert_eq!(next(&mut all).await, serde_json::json!({ "name": "Charles", "deletedAt": null }));
+        assert!(all.next().now_or_never().is_none());
+        assert_eq!(next(&mut pavel).await, serde_json::json!({ "name": "Paul" }));
+        assert!(pavel.next().now_or_never().is_none());
+        for _ in 0..3 {
+            assert_eq!(next(&mut legacy).await, serde_json::json!({ "name": "Charles" }));
+        }
+        assert!(legacy.next().now_or_never().is_none());
+
+        // A malformed ID is rejected when subscribing
+        let response = schema.execute_stream(r#"subscription { userUpdated(id: "bob") { name } }"#).next().await.expect("Missing response");
+        assert_eq!(response.errors[0].message, "\"bob\" is not a valid user ID");
+    }
+
+    // Define a test for counting users overall, by role, and by email domain
+    #[tokio::test]
+    async fn test_stats_query() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        for (name, email, role) in [("Ada", "ada@Noibu.com", UserRole::Admin), ("Grace", "grace@example.com", UserRole::Guest), ("Alan", "alan@noibu.com", UserRole::Member)] {
+            let new_user = NewUser { name: name.to_string(), email: email.to_string(), role, ..Default::default() };
+            repository.create(new_user).await.unwrap();
+        }
+        let grace = repository.get_by_email("grace@example.com").await.unwrap().unwrap();
+        repository.delete(&grace.id).await.unwrap();
+        let schema = build_schema(AppState::new(repository));
+
+        // Deleted users are not counted, domains are grouped ignoring case, and groups come largest first
+        let response = schema
+            .execute("{ stats { userCount, usersCreatedToday, byRole { role, count }, byDomain { domain, count } } }")
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(
+            data["stats"],
+            serde_json::json!({
+                "userCount": 4,
+                "usersCreatedToday": 4,
+                "byRole": [{ "role": "MEMBER", "count": 3 }, { "role": "ADMIN", "count": 1 }],
+                "byDomain": [{ "domain": "noibu.com", "count": 3 }, { "domain": "gmail.com", "count": 1 }]
+            })
+        );
+    }
+
+    // Define a test for returning the user the request was authenticated as
+    #[tokio::test]
+    async fn test_me_query() {
+        let schema = sample_schema();
+        let me = |viewer: Option<Viewer>| {
+            let schema = schema.clone();
+            async move {
+                let request = Request::new("{ me { id, name } }");
+                let request = match viewer {
+                    Some(viewer) => request.data(AuthContext::from(viewer)),
+                    None => request,
+                };
+                serde_json::to_value(schema.execute(request).await).expect("Failed to convert response to JSON")
+            }
+        };
+
+        let response = me(Some(Viewer::User(PAVEL.to_string()))).await;
+        assert_eq!(response["data"]["me"], serde_json::json!({ "id": global_id(USER, PAVEL).as_str(), "name": "Pavel" }));
+
+        // Requests without valid credentials, or for a user that is gone, are unauthenticated
+        let gone = "00000000-0000-0000-0000-0000000000ff";
+        for (viewer, message) in [
+            (None, "Authentication required"),
+            (Some(Viewer::Anonymous), "Authentication required"),
+            (Some(Viewer::InvalidCredentials), "The bearer token is not valid"),
+            (Some(Viewer::User(gone.to_string())), "The user the bearer token belongs to no longer exists"),
+        ] {
+            let response = me(viewer).await;
+            assert_eq!(response["data"], serde_json::Value::Null);
+            assert_eq!(response["errors"][0]["message"], message);
+            assert_eq!(response["errors"][0]["extensions"]["code"], "UNAUTHENTICATED");
+        }
+    }
+
+    // Define a test for the deleteUser mutation
+    #[tokio::test]
+    async fn test_delete_user_mutation() {
+        let schema = admin_schema().await;
+
+        // Delete the user and check the confirmation payload
+        let response = schema.execute(as_pavel(r#"mutation { deleteUser(id: "2") { success, user { id, name } } }"#)).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(
+            response_data,
+            serde_json::json!({
+                "deleteUser": { "success": true, "user": { "id": global_id(USER, CHARLIE), "name": "Charlie" } }
+            })
+        );
+
+        // Assert that the deleted user can no longer be fetched
+        let response = schema.execute(r#"{ userById(id: "2") { id } }"#).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(response_data, serde_json::json!({ "userById": null }));
+
+        // Assert that deleting it again reports failure
+        let response = schema.execute(as_pavel(r#"mutation { deleteUser(id: "2") { success, user { id } } }"#)).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(response_data, serde_json::json!({ "deleteUser": { "success": false, "user": null } }));
+    }
+
+    // Define a test for soft deletion, includeDeleted, restoreUser, and hard deletes
+    #[tokio::test]
+    async fn test_soft_delete_and_restore_mutations() {
+        let schema = admin_schema().await;
+
+        // Only admins may ask for soft-deleted users
+        for query in [
+            r#"{ users(includeDeleted: true) { totalCount } }"#,
+            r#"{ usersConnection(includeDeleted: true) { totalCount } }"#,
+            r#"{ userById(id: "1", includeDeleted: true) { name } }"#,
+        ] {
+            let response = serde_json::to_value(schema.execute(query).await).unwrap();
+            assert_eq!(response["errors"][0]["extensions"]["code"], "UNAUTHENTICATED", "{}", query);
+            let request = Request::new(query).data(AuthContext::from(Viewer::User(CHARLIE.to_string())));
+            let response = serde_json::to_value(schema.execute(request).await).unwrap();
+            assert_eq!(response["errors"][0]["message"], "Only admins may include deleted users", "{}", query);
+            assert_eq!(response["errors"][0]["extensions"]["code"], "FORBIDDEN", "{}", query);
+        }
+
+        // A soft-deleted user is hidden from queries unless includeDeleted is set
+        let response = schema.execute(as_pavel(r#"mutation { deleteUser(id: "2") { user { deletedAt } } }"#)).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert!(response_data["deleteUser"]["user"]["deletedAt"].is_string());
+        let response = schema
+            .execute(as_pavel(r#"{ users { users { id } } all: users(includeDeleted: true) { users { id } } userById(id: "2", includeDeleted: true) { name } }"#))
+            .await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(
+            response_data,
+            serde_json::json!({
+                "users": { "users": [{ "id": global_id(USER, PAVEL) }] },
+                "all": { "users": [{ "id": global_id(USER, PAVEL) }, { "id": global_id(USER, CHARLIE) }] },
+                "userById": { "name": "Charlie" }
+            })
+        );
+
+        // Restoring makes the user visible again
+        let response = schema.execute(as_pavel(r#"mutation { restoreUser(id: "2") { id, deletedAt } }"#)).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(response_data, serde_json::json!({ "restoreUser": { "id": global_id(USER, CHARLIE), "deletedAt": null } }));
+        let response = schema.execute(r#"{ userById(id: "2") { name } }"#).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(response_data, serde_json::json!({ "userById": { "name": "Charlie" } }));
+
+        // A hard delete removes the user for good
+        let response = schema.execute(as_pavel(r#"mutation { deleteUser(id: "2", hard: true) { success } }"#)).await;
+        assert!(response.is_ok());
+        let response = schema.execute(as_pavel(r#"{ userById(id: "2", includeDeleted: true) { name } }"#)).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(response_data, serde_json::json!({ "userById": null }));
+        let response = schema.execute(as_pavel(r#"mutation { restoreUser(id: "2") { id } }"#)).await;
+        assert_eq!(response.errors[0].message, "User 2 not found");
+    }
+
+    // Define a test for paging through the users query and the page size limit
+    #[tokio::test]
+    async fn test_users_pagination() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        for name in ["Ada", "Grace", "Alan"] {
+            let new_user = NewUser { name: name.to_string(), email: format!("{}@example.com", name.to_lowercase()), ..Default::default() };
+            repository.create(new_user).await.unwrap();
+        }
+        let schema = build_schema(AppState::new(repository).with_max_page_size(2));
+        let page = |query: &'static str| {
+            let schema = schema.clone();
+            async move {
+                let response = schema.execute(query).await;
+                assert!(response.errors.is_empty(), "{:?}", response.errors);
+                serde_json::to_value(response.data).expect("Failed to convert response to JSON")
+            }
+        };
+
+        // Pages follow creation order and report the total and whether more follow
+        assert_eq!(
+            page(r#"{ users(limit: 2, offset: 1) { users { name }, totalCount, hasNextPage } }"#).await,
+            serde_json::json!({
+                "users": { "users": [{ "name": "Charlie" }, { "name": "Ada" }], "totalCount": 5, "hasNextPage": true }
+            })
+        );
+        assert_eq!(
+            page(r#"{ users(offset: 4) { users { name }, hasNextPage } }"#).await,
+            serde_json::json!({ "users": { "users": [{ "name": "Alan" }], "hasNextPage": false } })
+        );
+
+        // Larger limits are capped at the maximum page size
+        let response = page(r#"{ users(limit: 50) { users { id } } }"#).await;
+        assert_eq!(response["users"]["users"].as_array().unwrap().len(), 2);
+    }
+
+    // Define a test that malformed user IDs are rejected with an INVALID_ID error instead of matching nothing
+    #[tokio::test]
+    async fn test_malformed_user_ids() {
+        let schema = admin_schema().await;
+        let execute = |query: &'static str| {
+            let schema = schema.clone();
+            async move { serde_json::to_value(schema.execute(as_pavel(query)).await).expect("Failed to convert response to JSON") }
+        };
+
+        for query in [
+            r#"{ userById(id: "abc") { name } }"#,
+            r#"{ usersByIds(ids: ["1", "abc"]) { name } }"#,
+            r#"mutation { updateUser(id: "abc", expectedVersion: 1, input: { name: "Nobody" }) { id } }"#,
+            r#"mutation { deleteUser(id: "abc") { success } }"#,
+            r#"mutation { createPost(input: { authorId: "abc", title: "Hi", body: "" }) { id } }"#,
+        ] {
+            let response = execute(query).await;
+            let error = &response["errors"][0];
+            assert_eq!(error["message"], "\"abc\" is not a valid user ID", "{}", query);
+            assert_eq!(error["extensions"]["code"], "INVALID_ID", "{}", query);
+        }
+
+        // Zero was never a user ID, and a well-formed UUID that nobody has is simply missing
+        let response = execute(r#"{ userById(id: "0") { name } }"#).await;
+        assert_eq!(response["errors"][0]["extensions"]["code"], "INVALID_ID");
+        let response = execute(r#"{ userById(id: "0190b8d6-3c4a-7d2e-9f10-2a3b4c5d6e7f") { name } }"#).await;
+        assert_eq!(response["data"], serde_json::json!({ "userById": null }));
+
+        // The node query still resolves malformed IDs to null
+        let query = format!(r#"{{ node(id: "{}") {{ id }} }}"#, global_id(USER, "abc").as_str());
+        let response = serde_json::to_value(schema.execute(query).await).unwrap();
+        assert_eq!(response["data"], serde_json::json!({ "node": null }));
+    }
+
+    // Define a test for looking users up by email
+    #[tokio::test]
+    async fn test_user_by_email() {
+        let schema = admin_schema().await;
+        let lookup = |email: &'static str| {
+            let schema = schema.clone();
+            async move {
+                let query = format!(r#"{{ userByEmail(email: "{}") {{ id }} }}"#, email);
+                let response = schema.execute(query).await;
+                assert!(response.errors.is_empty(), "{:?}", response.errors);
+                serde_json::to_value(response.data).expect("Failed to convert response to JSON")["userByEmail"].clone()
+            }
+        };
+
+        // Matching ignores case and surrounding whitespace
+        assert_eq!(lookup("pavelboukine@gmail.com").await, serde_json::json!({ "id": global_id(USER, PAVEL) }));
+        assert_eq!(lookup(" CHARLIE.gracie@Noibu.com ").await, serde_json::json!({ "id": global_id(USER, CHARLIE) }));
+        assert_eq!(lookup("nobody@example.com").await, serde_json::Value::Null);
+
+        // Soft-deleted users are not found
+        schema.execute(as_pavel(r#"mutation { deleteUser(id: "1") { success } }"#)).await;
+        assert_eq!(lookup("pavelboukine@gmail.com").await, serde_json::Value::Null);
+    }
+
+    // Define a test that email arguments are normalized and malformed addresses are rejected
+    #[tokio::test]
+    async fn test_email_arguments() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        promote_pavel(&repository).await;
+        let schema = build_schema(AppState::new(repository));
+        let response = schema
+            .execute(as_pavel(r#"mutation { createUser(input: { name: "Ada", email: " Ada@Example.COM " }) { email } }"#))
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(data, serde_json::json!({ "createUser": { "email": "ada@example.com" } }));
+
+        // The error names the malformed address and the input it was given in, and nothing is stored
+        for (query, suffix) in [
+            (r#"mutation { createUser(input: { name: "Bob", email: "bob-at-example" }) { id } }"#, " (occurred while parsing \"CreateUserInput\")"),
+            (r#"mutation { updateUser(id: "1", expectedVersion: 1, input: { email: "bob-at-example" }) { id } }"#, " (occurred while parsing \"UpdateUserInput\")"),
+            (r#"{ userByEmail(email: "bob-at-example") { id } }"#, ""),
+        ] {
+            let response = schema.execute(query).await;
+            assert_eq!(response.errors.len(), 1);
+            assert_eq!(response.errors[0].message, format!("Failed to parse \"Email\": \"bob-at-example\" is not a valid email address{}", suffix));
+        }
+        let response = schema.execute(r#"{ users { totalCount } }"#).await;
+        let data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(data["users"]["totalCount"], 3);
+    }
+
+    // Define a test for the exact errors the input validators return
+    #[tokio::test]
+    async fn test_input_validation() {
+        let schema = sample_schema();
+        let long_name = "a".repeat(101);
+        let message = |query: String| {
+            let schema = schema.clone();
+            async move {
+                let response = schema.execute(as_pavel(query)).await;
+                assert_eq!(response.errors.len(), 1, "{:?}", response.errors);
+                response.errors[0].message.clone()
+            }
+        };
+
+        // Names must be between 1 and 100 characters
+        let create = |name: &str| format!(r#"mutation {{ createUser(input: {{ name: "{}", email: "ada@example.com" }}) {{ id }} }}"#, name);
+        assert_eq!(
+            message(create("")).await,
+            "Failed to parse \"String\": the string length is 0, must be greater than or equal to 1 (occurred while parsing \"CreateUserInput\")"
+        );
+        assert_eq!(
+            message(create(&long_name)).await,
+            "Failed to parse \"String\": the string length is 101, must be less than or equal to 100 (occurred while parsing \"CreateUserInput\")"
+        );
+        assert_eq!(
+            message(format!(r#"mutation {{ updateUser(id: "1", expectedVersion: 1, input: {{ name: "{}" }}) {{ id }} }}"#, long_name)).await,
+            "Failed to parse \"String\": the string length is 101, must be less than or equal to 100 (occurred while parsing \"UpdateUserInput\")"
+        );
+        assert_eq!(
+            message(format!(r#"mutation {{ createOrganization(name: "{}") {{ id }} }}"#, long_name)).await,
+            "Failed to parse \"String\": the string length is 101, must be less than or equal to 100"
+        );
+
+        // Emails must be well formed
+        assert_eq!(
+            message(r#"mutation { createUser(input: { name: "Ada", email: "ada@" }) { id } }"#.to_string()).await,
+            "Failed to parse \"Email\": \"ada@\" is not a valid email address (occurred while parsing \"CreateUserInput\")"
+        );
+
+        // orderBy lists at most one key per sortable field
+        let order = "orderBy: [{ field: NAME }, { field: EMAIL }, { field: CREATED_AT }, { field: NAME }]";
+        assert_eq!(
+            message(format!("{{ users({}) {{ totalCount }} }}", order)).await,
+            "Failed to parse \"[UserOrder!]\": the value length is 4, must be less than or equal to 3"
+        );
+
+        // Nothing was stored, and names at the limit are accepted
+        let response = schema.execute(as_pavel(create(&"a".repeat(100)))).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let response = schema.execute("{ users { totalCount } }").await;
+        let data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(data["users"]["totalCount"], 3);
+    }
+
+    // Define a test for setting user roles on creation and update and filtering users by them
+    #[tokio::test]
+    async fn test_user_roles() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        let schema = build_schema(AppState::new(repository.clone()));
+        let execute = |query: &'static str| {
+            let schema = schema.clone();
+            async move {
+                let response = schema.execute(as_pavel(query)).await;
+                assert!(response.errors.is_empty(), "{:?}", response.errors);
+                serde_json::to_value(response.data).expect("Failed to convert response to JSON")
+            }
+        };
+
+        // Members may not set a role anywhere, even their own
+        for query in [
+            r#"mutation { createUser(input: { name: "Ada", email: "ada@example.com", role: MEMBER }) { role } }"#,
+            r#"mutation { createUsers(inputs: [{ name: "Ada", email: "ada@example.com" }, { name: "Grace", email: "grace@example.com", role: ADMIN }]) { index } }"#,
+            r#"mutation { updateUser(id: "1", expectedVersion: 1, input: { role: ADMIN }) { role } }"#,
+        ] {
+            let response = schema.execute(as_pavel(query)).await;
+            let error = serde_json::to_value(&response.errors[0]).unwrap();
+            assert_eq!((error["message"].as_str(), error["extensions"]["code"].as_str()), (Some("Only admins may set a user's role"), Some("FORBIDDEN")), "{}", query);
+        }
+        assert_eq!(execute("{ users { totalCount } }").await["users"]["totalCount"], 2);
+
+        // Users are members unless an admin creates them with another role
+        let response = execute(r#"mutation { createUser(input: { name: "Grace", email: "grace@example.com" }) { role } }"#).await;
+        assert_eq!(response["createUser"]["role"], "MEMBER");
+        promote_pavel(&repository).await;
+        let response = execute(r#"mutation { createUser(input: { name: "Ada", email: "ada@example.com", role: ADMIN }) { role } }"#).await;
+        assert_eq!(response["createUser"]["role"], "ADMIN");
+
+        // An update changes the role and leaves it alone when omitted
+        let response = execute(r#"mutation { updateUser(id: "2", expectedVersion: 1, input: { role: GUEST }) { role } }"#).await;
+        assert_eq!(response["updateUser"]["role"], "GUEST");
+        let response = execute(r#"mutation { updateUser(id: "2", expectedVersion: 2, input: { name: "Chuck" }) { role } }"#).await;
+        assert_eq!(response["updateUser"]["role"], "GUEST");
+
+        // Both users queries narrow to one role
+        let response = execute(r#"{ users(filter: { role: MEMBER }) { users { name }, totalCount } }"#).await;
+        assert_eq!(response["users"], serde_json::json!({ "users": [{ "name": "Grace" }], "totalCount": 1 }));
+        let response = execute(r#"{ usersConnection(filter: { role: ADMIN }) { totalCount, edges { node { name } } } }"#).await;
+        assert_eq!(response["usersConnection"], serde_json::json!({ "totalCount": 2, "edges": [{ "node": { "name": "Pavel" } }, { "node": { "name": "Ada" } }] }));
+    }
+
+    // Define a test that emails are shown only to their users and admins, and withheld fields are named
+    #[tokio::test]
+    async fn test_email_visibility() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        promote_pavel(&repository).await;
+        let schema = build_schema(AppState::new(repository));
+        let query = r#"{ userById(id: "2") { email }, users { users { email } } }"#;
+        let emails = |viewer: Option<Viewer>| {
+            let schema = schema.clone();
+            async move {
+                let request = match viewer {
+                    Some(viewer) => Request::new(query).data(AuthContext::from(viewer)),
+                    None => Request::new(query),
+                };
+                let response = schema.execute(request).await;
+                assert!(response.errors.is_empty(), "{:?}", response.errors);
+                let response = serde_json::to_value(response).expect("Failed to convert response to JSON");
+                let emails = [&response["data"]["userById"]["email"], &response["data"]["users"]["users"][0]["email"], &response["data"]["users"]["users"][1]["email"]];
+                (emails.map(|email| email.as_str().map(str::to_string)), response["extensions"]["redactedFields"].clone())
+            }
+        };
+
+        // Anonymous callers and invalid credentials see no emails, and are told which fields were withheld
+        for viewer in [None, Some(Viewer::InvalidCredentials)] {
+            let redacted = serde_json::json!(["userById.email", "users.users.0.email", "users.users.1.email"]);
+            assert_eq!(emails(viewer).await, ([None, None, None], redacted));
+        }
+
+        // Members see only their own
+        let charlie = Some("charlie.gracie@noibu.com".to_string());
+        let redacted = serde_json::json!(["users.users.0.email"]);
+        assert_eq!(emails(Some(Viewer::User(CHARLIE.to_string()))).await, ([charlie.clone(), None, charlie.clone()], redacted));
+
+        // Admins see every email, and the response names no withheld fields
+        let pavel = Some("Pavelboukine@gmail.com".to_string());
+        assert_eq!(emails(Some(Viewer::User(PAVEL.to_string()))).await, ([charlie.clone(), pavel, charlie], serde_json::Value::Null));
+
+        // The email is nullable and no longer masked; the phone number still is
+        let sdl = schema.sdl_with_options(async_graphql::SDLExportOptions::new().federation());
+        assert!(sdl.contains("\temail: String\n"));
+        assert!(sdl.contains(r#"phone: PhoneNumber @masked(requires: "admin")"#));
+    }
+
+    // Define a test for narrowing both users queries with a filter
+    #[tokio::test]
+    async fn test_users_filter() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        for (name, email) in [("Ada", "ada@noibu.com"), ("Grace", "grace@example.com")] {
+            let new_user = NewUser { name: name.to_string(), email: email.to_string(), ..Default::default() };
+            repository.create(new_user).await.unwrap();
+        }
+        let schema = build_schema(AppState::new(repository));
+        let execute = |query: String| {
+            let schema = schema.clone();
+            async move {
+                let response = schema.execute(query).await;
+                assert!(response.errors.is_empty(), "{:?}", response.errors);
+                serde_json::to_value(response.data).expect("Failed to convert response to JSON")
+            }
+        };
+
+        // Every condition must hold, and names match case-insensitively
+        let response = execute(r#"{ users(filter: { emailDomain: "NOIBU.com" }) { users { name }, totalCount } }"#.to_string()).await;
+        assert_eq!(response["users"], serde_json::json!({ "users": [{ "name": "Charlie" }, { "name": "Ada" }], "totalCount": 2 }));
+        let response = execute(
+            r#"{ usersConnection(filter: { nameContains: "a", emailDomain: "noibu.com" }) { totalCount, edges { node { name } } } }"#
+                .to_string(),
+        )
+        .await;
+        assert_eq!(response["usersConnection"]["totalCount"], 2);
+        let response = execute(r#"{ users(filter: { nameContains: "GRA" }) { users { name } } }"#.to_string()).await;
+        assert_eq!(response["users"]["users"], serde_json::json!([{ "name": "Grace" }]));
+
+        // Creation dates bound the results from below and above
+        let tomorrow = (chrono::Utc::now() + chrono::Duration::days(1)).to_rfc3339();
+        let query = format!(r#"{{ users(filter: {{ createdAfter: "{}" }}) {{ totalCount }} }}"#, tomorrow);
+        assert_eq!(execute(query).await["users"]["totalCount"], 0);
+        let query = format!(r#"{{ usersConnection(filter: {{ createdBefore: "{}" }}) {{ totalCount }} }}"#, tomorrow);
+        assert_eq!(execute(query).await["usersConnection"]["totalCount"], 4);
+    }
+
+    // Define a test for sorting the users queries by several keys
+    #[tokio::test]
+    async fn test_users_order_by() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        for (name, email) in [("Ada", "ada@example.com"), ("Ada", "ada@noibu.com"), ("Ada", "lovelace@example.com")] {
+            let new_user = NewUser { name: name.to_string(), email: email.to_string(), ..Default::default() };
+            repository.create(new_user).await.unwrap();
+        }
+        promote_pavel(&repository).await;
+        let schema = build_schema(AppState::new(repository));
+        let emails = |query: &'static str| {
+            let schema = schema.clone();
+            async move {
+                let response = schema.execute(as_pavel(query)).await;
+                assert!(response.errors.is_empty(), "{:?}", response.errors);
+                let data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+                let users = data["users"]["users"].as_array().cloned().unwrap_or_else(|| {
+                    data["usersConnection"]["edges"].as_array().unwrap().iter().map(|edge| edge["node"].clone()).collect()
+                });
+                users.iter().map(|user| user["email"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+            }
+        };
+
+        // Later keys break ties left by earlier ones, and the direction defaults to ascending
+        assert_eq!(
+            emails(r#"{ users(orderBy: [{ field: NAME }, { field: EMAIL, direction: DESC }]) { users { email } } }"#).await,
+            ["lovelace@example.com", "ada@noibu.com", "ada@example.com", "charlie.gracie@noibu.com", "Pavelboukine@gmail.com"]
+        );
+
+        // Users that tie on every key stay in creation order
+        assert_eq!(
+            emails(r#"{ usersConnection(first: 3, orderBy: [{ field: NAME }]) { edges { node { email } } } }"#).await,
+            ["ada@example.com", "ada@noibu.com", "lovelace@example.com"]
+        );
+        assert_eq!(
+            emails(r#"{ users(limit: 2, offset: 1, orderBy: [{ field: NAME, direction: DESC }]) { users { email } } }"#).await,
+            ["charlie.gracie@noibu.com", "ada@example.com"]
+        );
+
+        // Listing a field twice is rejected
+        let response = schema.execute(r#"{ users(orderBy: [{ field: EMAIL }, { field: EMAIL, direction: DESC }]) { totalCount } }"#).await;
+        let error = serde_json::to_value(&response.errors[0]).unwrap();
+        assert_eq!(error["message"], "orderBy lists EMAIL more than once");
+        assert_eq!(error["extensions"]["code"], "INVALID_ORDER");
+    }
+
+    // Define a test for paging forwards and backwards through usersConnection
+    #[tokio::test]
+    async fn test_users_connection() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        for name in ["Ada", "Grace", "Alan"] {
+            let new_user = NewUser { name: name.to_string(), email: format!("{}@example.com", name.to_lowercase()), ..Default::default() };
+            repository.create(new_user).await.unwrap();
+        }
+        let schema = build_schema(AppState::new(repository).with_max_page_size(3));
+        let connection = |arguments: String| {
+            let schema = schema.clone();
+            async move {
+                let arguments = if arguments.is_empty() { arguments } else { format!("({})", arguments) };
+                let query = format!(
+                    "{{ usersConnection{} {{ totalCount, edges {{ cursor, node {{ name }} }}, pageInfo {{ hasPreviousPage, hasNextPage, startCursor, endCursor }} }} }}",
+                    arguments
+                );
+                let response = schema.execute(query).await;
+                assert!(response.errors.is_empty(), "{:?}", response.errors);
+                let data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+                data["usersConnection"].clone()
+            }
+        };
+        let names = |page: &serde_json::Value| -> Vec<String> {
+            page["edges"].as_array().unwrap().iter().map(|edge| edge["node"]["name"].as_str().unwrap().to_string()).collect()
+        };
+
+        // Walk forwards two at a time using the end cursor of each page
+        let page = connection("first: 2".to_string()).await;
+        assert_eq!(names(&page), ["Pavel", "Charlie"]);
+        assert_eq!(page["totalCount"], 5);
+        assert_eq!(page["pageInfo"]["hasPreviousPage"], false);
+        assert_eq!(page["pageInfo"]["hasNextPage"], true);
+        let page = connection(format!("first: 2, after: {}", page["pageInfo"]["endCursor"])).await;
+        assert_eq!(names(&page), ["Ada", "Grace"]);
+        let page = connection(format!("first: 2, after: {}", page["pageInfo"]["endCursor"])).await;
+        assert_eq!(names(&page), ["Alan"]);
+        assert_eq!(page["pageInfo"]["hasNextPage"], false);
+
+        // Walk backwards from the end using the start cursor of each page
+        let page = connection("last: 2".to_string()).await;
+        assert_eq!(names(&page), ["Grace", "Alan"]);
+        assert_eq!(page["pageInfo"]["hasPreviousPage"], true);
+        let page = connection(format!("last: 2, before: {}", page["pageInfo"]["startCursor"])).await;
+        assert_eq!(names(&page), ["Charlie", "Ada"]);
+
+        // Without first or last a page holds at most the maximum page size
+        assert_eq!(names(&connection("first: 10".to_string()).await).len(), 3);
+        assert_eq!(names(&connection(String::new()).await).len(), 3);
+
+        // Malformed cursors are rejected
+        let response = schema.execute(r#"{ usersConnection(after: "not-a-cursor") { totalCount } }"#).await;
+        assert!(!response.errors.is_empty());
+    }
+
+    // Define a test for the importUsers mutation
+    #[tokio::test]
+    async fn test_import_users_mutation() {
+        let schema = admin_schema().await;
+
+        let request = Request::new(
+            r#"mutation($csv: String!) { importUsers(csv: $csv) { importedCount, failedCount, results { row, user { id, name }, error } } }"#,
+        )
+        .variables(async_graphql::Variables::from_json(serde_json::json!({
+            "csv": "name,email\nAda,ada@example.com\nBob,bob-at-example\n"
+        })));
+        let response = schema.execute(as_pavel(request)).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        let id = &response_data["importUsers"]["results"][0]["user"]["id"];
+        assert!(id.is_string());
+        assert_eq!(
+            response_data,
+            serde_json::json!({
+                "importUsers": {
+                    "importedCount": 1,
+                    "failedCount": 1,
+                    "results": [
+                        { "row": 2, "user": { "id": id, "name": "Ada" }, "error": null },
+                        { "row": 3, "user": null, "error": "\"bob-at-example\" is not a valid email address" }
+                    ]
+                }
+            })
+        );
+    }
+
+    // Define a test for importUsers reading its CSV from an uploaded file
+    #[tokio::test]
+    async fn test_import_users_upload() {
+        let limits = UploadLimits { max_file_bytes: 64, max_files: 1 };
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        promote_pavel(&repository).await;
+        let schema = build_schema(AppState::new(repository).with_upload_limits(limits));
+        let request = |variables: serde_json::Value| {
+            as_pavel(r#"mutation($csv: String, $file: Upload) { importUsers(csv: $csv, file: $file) { importedCount } }"#)
+                .variables(async_graphql::Variables::from_json(variables))
+        };
+        let error_code = |response: async_graphql::Response| serde_json::to_value(&response.errors[0]).unwrap()["extensions"]["code"].clone();
+
+        let csv = b"name,email\nAda,ada@example.com\n";
+        let response = schema.execute(with_file(request(serde_json::json!({ "file": null })), "text/csv", csv)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(serde_json::to_value(response.data).unwrap(), serde_json::json!({ "importUsers": { "importedCount": 1 } }));
+
+        // Oversized or non-UTF-8 files are refused
+        for content in [&[b'a'; 65][..], &b"name,email\n\xff\n"[..]] {
+            let response = schema.execute(with_file(request(serde_json::json!({ "file": null })), "text/csv", content)).await;
+            assert_eq!(error_code(response), "VALIDATION_FAILED");
+        }
+
+        // Exactly one of csv and file must be given
+        let response = schema.execute(request(serde_json::json!({}))).await;
+        assert_eq!(error_code(response), "VALIDATION_FAILED");
+        let both = request(serde_json::json!({ "csv": "name,email\n", "file": null }));
+        let response = schema.execute(with_file(both, "text/csv", csv)).await;
+        assert_eq!(error_code(response), "VALIDATION_FAILED");
+    }
+
+    // Build an upload of the given bytes for the `file` variable of a request
+    fn with_file(request: Request, content_type: &str, content: &[u8]) -> Request {
+        use std::io::{Seek, Write};
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(content).unwrap();
+        file.rewind().unwrap();
+        let mut request = request;
+        request.set_upload(
+            "variables.file",
+            async_graphql::UploadValue {
+                filename: "avatar".to_string(),
+                content_type: Some(content_type.to_string()),
+                content: file,
+            },
+        );
+        request
+    }
+
+    // Define a test for the uploadAvatar mutation and the avatarUrl field
+    #[tokio::test]
+    async fn test_upload_avatar_mutation() {
+        use crate::avatar::LocalAvatarStore;
+        use std::time::Duration;
+
+        let directory = tempfile::tempdir().unwrap();
+        let store = LocalAvatarStore::new(directory.path(), "http://localhost:3030", b"secret".to_vec()).unwrap();
+        let avatars = Avatars::new(Arc::new(store), 64, Duration::from_secs(60));
+        let state = AppState::new(Arc::new(InMemoryRepository::with_sample_users())).with_avatars(avatars);
+        let schema = build_schema(state);
+        let mutation = r#"mutation($file: Upload!) { uploadAvatar(id: "1", file: $file) { url, user { version, avatarUrl } } }"#;
+        let request = || {
+            as_pavel(mutation).variables(async_graphql::Variables::from_json(serde_json::json!({ "file": null })))
+        };
+
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        let response = schema.execute(with_file(request(), "image/png", png)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let response_data = serde_json::to_value(response.data).unwrap();
+        let url = response_data["uploadAvatar"]["url"].as_str().unwrap();
+        assert!(url.starts_with(&format!("http://localhost:3030/avatars/{}-", PAVEL.replace('-', ""))));
+        assert!(url.contains(".png?expires="));
+        assert_eq!(response_data["uploadAvatar"]["user"]["version"], 2);
+        assert!(response_data["uploadAvatar"]["user"]["avatarUrl"].as_str().unwrap().contains(".png?"));
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 1);
+
+        // Unsupported types, mismatched content, and oversized files are refused
+        for (content_type, content) in [("image/svg+xml", &b"<svg/>"[..]), ("image/gif", &png[..]), ("image/png", &[0x89; 65][..])] {
+            let response = schema.execute(with_file(request(), content_type, content)).await;
+            let error = serde_json::to_value(&response.errors[0]).unwrap();
+            assert_eq!(error["extensions"]["code"], "INVALID_AVATAR", "{}", content_type);
+        }
+
+        // Members may not change other users' avatars
+        let others = as_pavel(r#"mutation($file: Upload!) { uploadAvatar(id: "2", file: $file) { url } }"#)
+            .variables(async_graphql::Variables::from_json(serde_json::json!({ "file": null })));
+        let response = schema.execute(with_file(others, "image/png", png)).await;
+        assert_eq!(serde_json::to_value(&response.errors[0]).unwrap()["extensions"]["code"], "FORBIDDEN");
+
+        // Without an avatar store the field is null and uploads are refused
+        let schema = sample_schema();
+        let response = schema.execute(r#"{ userById(id: "1") { avatarUrl } }"#).await;
+        assert_eq!(serde_json::to_value(response.data).unwrap(), serde_json::json!({ "userById": { "avatarUrl": null } }));
+        let response = schema.execute(with_file(request(), "image/png", png)).await;
+        assert_eq!(response.errors[0].message, "Avatar uploads are not enabled");
+    }
+}
@@ -0,0 +1,825 @@
+// Import necessary libraries and modules
+use async_graphql::{SimpleObject, Union};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::config::Settings;
+use crate::model::{NewUser, Organization, Post, User, UserFilter, UserOrder, UserStats, UserUpdate};
+use crate::outbox::ChangeEvent;
+use crate::repository::{ApiKeyRepository, OrganizationRepository, PasswordRepository, PoolStats, PostRepository, RefreshTokenRepository, RepositoryResult, SharedRepository, TwoFactorRepository, UserRepository, UserTransaction};
+#[cfg(feature = "elasticsearch")]
+use crate::repository::RepositoryError;
+use crate::{log_error, log_warn};
+
+// Define the most results a single search may return
+pub const MAX_SEARCH_LIMIT: usize = 100;
+
+// Define the markers placed around matched text in snippets
+pub const HIGHLIGHT_START: &str = "<b>";
+pub const HIGHLIGHT_END: &str = "</b>";
+
+// Define one search match: the user, how well it matched, and its name and email with the matches highlighted
+#[derive(Clone, Debug, PartialEq, SimpleObject)]
+pub struct UserSearchResult {
+    pub user: User,
+    pub rank: f64,
+    pub snippet: String,
+}
+
+// Define one match of the global search, which covers users, posts, and organizations
+#[derive(Clone, Debug, PartialEq, Union)]
+#[allow(clippy::large_enum_variant)]
+pub enum SearchResult {
+    User(User),
+    Post(Post),
+    Organization(Organization),
+}
+
+// Split a free-text query into its whitespace-separated terms, lowercased for ASCII-case-insensitive matching
+pub fn search_terms(query: &str) -> Vec<String> {
+    query.split_whitespace().map(str::to_ascii_lowercase).collect()
+}
+
+// Check whether a text contains every (lowercase) term, ignoring ASCII case
+pub fn contains_terms(text: &str, terms: &[String]) -> bool {
+    let haystack = text.to_ascii_lowercase();
+    terms.iter().all(|term| haystack.contains(term.as_str()))
+}
+
+// Match users whose name or email contains every whitespace-separated term, ignoring ASCII case.
+// Used by backends without a text index; name matches rank above email-only matches
+pub fn substring_search(users: Vec<User>, query: &str, limit: usize, offset: usize) -> Vec<UserSearchResult> {
+    let terms = search_terms(query);
+    if terms.is_empty() {
+        return Vec::new();
+    }
+
+    let mut results: Vec<UserSearchResult> = users
+        .into_iter()
+        .filter_map(|user| {
+            let text = format!("{} {}", user.name, user.email);
+            if !contains_terms(&text, &terms) {
+                return None;
+            }
+            let name = user.name.to_ascii_lowercase();
+            let score: f64 = terms.iter().map(|term| if name.contains(term.as_str()) { 1.0 } else { 0.5 }).sum();
+            Some(UserSearchResult {
+                rank: score / terms.len() as f64,
+                snippet: highlight(&text, &terms),
+                user,
+            })
+        })
+        .collect();
+
+    // A stable sort keeps equally ranked users in list order
+    results.sort_by(|a, b| b.rank.total_cmp(&a.rank));
+    results.into_iter().skip(offset).take(limit).collect()
+}
+
+// Wrap every occurrence of the (lowercase) terms in the text with the highlight markers
+fn highlight(text: &str, terms: &[String]) -> String {
+    // ASCII lowercasing keeps byte offsets, so matches in the lowercase copy line up with the text
+    let haystack = text.to_ascii_lowercase();
+    let mut ranges: Vec<(usize, usize)> = terms
+        .iter()
+        .flat_map(|term| haystack.match_indices(term.as_str()).map(|(start, term)| (start, start + term.len())))
+        .collect();
+    ranges.sort();
+
+    let mut snippet = String::with_capacity(text.len());
+    let mut position = 0;
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    for (start, end) in merged {
+        snippet.push_str(&text[position..start]);
+        snippet.push_str(HIGHLIGHT_START);
+        snippet.push_str(&text[start..end]);
+        snippet.push_str(HIGHLIGHT_END);
+        position = end;
+    }
+    snippet.push_str(&text[position..]);
+    snippet
+}
+
+// Define one match returned by a search index: the user ID, its score, and the highlighted fields, if any
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchHit {
+    pub id: String,
+    pub score: f64,
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+// Define the operations the search layer needs from an external search engine
+#[async_trait]
+pub trait SearchIndex: Send + Sync {
+    // Add or replace the document for a user
+    async fn index(&self, user: &User) -> RepositoryResult<()>;
+
+    // Remove the document for a user, succeeding if there is none
+    async fn remove(&self, id: &str) -> RepositoryResult<()>;
+
+    // Return a page of fuzzy matches for the query, best first
+    async fn search(&self, query: &str, limit: usize, offset: usize) -> RepositoryResult<Vec<SearchHit>>;
+
+    // Replace the whole index with the given users and return how many were indexed
+    async fn reindex(&self, users: Vec<User>) -> RepositoryResult<usize>;
+}
+
+// Define a shared, thread-safe handle to the search index
+pub type SharedSearchIndex = Arc<dyn SearchIndex>;
+
+// Define an IndexedRepository that keeps a search index in step with another repository and answers searches from it.
+// Writes are committed to the repository first; an index that cannot be updated is logged and repaired by reindexing
+pub struct IndexedRepository {
+    inner: SharedRepository,
+    index: SharedSearchIndex,
+}
+
+impl IndexedRepository {
+    // Wrap a repository with a search index
+    pub fn new(inner: SharedRepository, index: SharedSearchIndex) -> Self {
+        IndexedRepository { inner, index }
+    }
+
+    // Index a live user, or remove it from the index once it has been deleted
+    async fn sync(&self, user: &User) {
+        let result = match user.deleted_at {
+            Some(_) => self.index.remove(&user.id).await,
+            None => self.index.index(user).await,
+        };
+        if let Err(error) = result {
+            log_error!("Search index update for user {} failed: {}", user.id, error);
+        }
+    }
+
+    // Remove a user from the index
+    async fn unindex(&self, id: &str) {
+        if let Err(error) = self.index.remove(id).await {
+            log_error!("Search index update for user {} failed: {}", id, error);
+        }
+    }
+}
+
+// Build a search result from an index hit, falling back to the stored fields where nothing was highlighted
+fn search_result(hit: SearchHit, user: User) -> UserSearchResult {
+    let name = hit.name.unwrap_or_else(|| user.name.clone());
+    let email = hit.email.unwrap_or_else(|| user.email.clone());
+    UserSearchResult {
+        rank: hit.score,
+        snippet: format!("{} {}", name, email),
+        user,
+    }
+}
+
+// Implement the repository operations, updating the index after every successful write
+#[async_trait]
+impl UserRepository for IndexedRepository {
+    async fn get(&self, id: &str) -> RepositoryResult<Option<User>> {
+        self.inner.get(id).await
+    }
+
+    async fn get_including_deleted(&self, id: &str) -> RepositoryResult<Option<User>> {
+        self.inner.get_including_deleted(id).await
+    }
+
+    async fn get_by_email(&self, email: &str) -> RepositoryResult<Option<User>> {
+        self.inner.get_by_email(email).await
+    }
+
+    async fn get_many(&self, ids: &[String]) -> RepositoryResult<HashMap<String, User>> {
+        self.inner.get_many(ids).await
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<User>> {
+        self.inner.list().await
+    }
+
+    async fn list_including_deleted(&self) -> RepositoryResult<Vec<User>> {
+        self.inner.list_including_deleted().await
+    }
+
+    async fn list_page(&self, filter: &UserFilter, after: Option<&str>, limit: usize) -> RepositoryResult<Vec<User>> {
+        self.inner.list_page(filter, after, limit).await
+    }
+
+    async fn list_range(&self, filter: &UserFilter, order: &[UserOrder], offset: usize, limit: usize) -> RepositoryResult<Vec<User>> {
+        self.inner.list_range(filter, order, offset, limit).await
+    }
+
+    async fn count(&self, filter: &UserFilter) -> RepositoryResult<usize> {
+        self.inner.count(filter).await
+    }
+
+    async fn stats(&self, created_since: DateTime<Utc>) -> RepositoryResult<UserStats> {
+        self.inner.stats(created_since).await
+    }
+
+    async fn search(&self, query: &str, limit: usize, offset: usize) -> RepositoryResult<Vec<UserSearchResult>> {
+        let hits = match self.index.search(query, limit, offset).await {
+            Ok(hits) => hits,
+            Err(error) => {
+                log_warn!("Search index query failed, falling back to repository: {}", error);
+                return self.inner.search(query, limit, offset).await;
+            }
+        };
+
+        // Users are loaded from the repository so results never show stale data, and users
+        // deleted since they were indexed are dropped
+        let ids: Vec<String> = hits.iter().map(|hit| hit.id.clone()).collect();
+        let mut users = self.inner.get_many(&ids).await?;
+        Ok(hits
+            .into_iter()
+            .filter_map(|hit| {
+                let user = users.remove(&hit.id)?;
+                Some(search_result(hit, user))
+            })
+            .collect())
+    }
+
+    async fn create(&self, new_user: NewUser) -> RepositoryResult<User> {
+        let user = self.inner.create(new_user).await?;
+        self.sync(&user).await;
+        Ok(user)
+    }
+
+    async fn create_many(&self, new_users: Vec<NewUser>) -> RepositoryResult<Vec<User>> {
+        let users = self.inner.create_many(new_users).await?;
+        for user in &users {
+            self.sync(user).await;
+        }
+        Ok(users)
+    }
+
+    async fn update(&self, id: &str, update: UserUpdate) -> RepositoryResult<Option<User>> {
+        let user = self.inner.update(id, update).await?;
+        if let Some(user) = &user {
+            self.sync(user).await;
+        }
+        Ok(user)
+    }
+
+    async fn delete(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let user = self.inner.delete(id).await?;
+        self.unindex(id).await;
+        Ok(user)
+    }
+
+    async fn restore(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let user = self.inner.restore(id).await?;
+        if let Some(user) = &user {
+            self.sync(user).await;
+        }
+        Ok(user)
+    }
+
+    async fn purge(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let user = self.inner.purge(id).await?;
+        self.unindex(id).await;
+        Ok(user)
+    }
+
+    // Searches fall back to the repository, so an index outage does not make the service unready
+    async fn ping(&self) -> RepositoryResult<()> {
+        self.inner.ping().await
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        self.inner.pool_stats()
+    }
+
+    async fn close(&self) {
+        self.inner.close().await;
+    }
+
+    async fn pending_migrations(&self) -> RepositoryResult<usize> {
+        self.inner.pending_migrations().await
+    }
+
+    fn posts(&self) -> Option<&dyn PostRepository> {
+        self.inner.posts()
+    }
+
+    fn organizations(&self) -> Option<&dyn OrganizationRepository> {
+        self.inner.organizations()
+    }
+
+    fn api_keys(&self) -> Option<&dyn ApiKeyRepository> {
+        self.inner.api_keys()
+    }
+
+    fn passwords(&self) -> Option<&dyn PasswordRepository> {
+        self.inner.passwords()
+    }
+
+    fn refresh_tokens(&self) -> Option<&dyn RefreshTokenRepository> {
+        self.inner.refresh_tokens()
+    }
+
+    fn two_factor(&self) -> Option<&dyn TwoFactorRepository> {
+        self.inner.two_factor()
+    }
+
+    async fn begin(&self) -> RepositoryResult<Box<dyn UserTransaction>> {
+        Ok(Box::new(IndexedTransaction {
+            inner: self.inner.begin().await?,
+            repository: IndexedRepository::new(self.inner.clone(), self.index.clone()),
+            touched: Vec::new(),
+        }))
+    }
+
+    async fn pending_events(&self, limit: usize) -> RepositoryResult<Vec<ChangeEvent>> {
+        self.inner.pending_events(limit).await
+    }
+
+    async fn acknowledge_events(&self, ids: &[i64]) -> RepositoryResult<()> {
+        self.inner.acknowledge_events(ids).await
+    }
+}
+
+// Define an IndexedTransaction that re-indexes the users it wrote once the transaction commits
+struct IndexedTransaction {
+    inner: Box<dyn UserTransaction>,
+    repository: IndexedRepository,
+    touched: Vec<String>,
+}
+
+#[async_trait]
+impl UserTransaction for IndexedTransaction {
+    async fn get(&mut self, id: &str) -> RepositoryResult<Option<User>> {
+        self.inner.get(id).await
+    }
+
+    async fn create(&mut self, new_user: NewUser) -> RepositoryResult<User> {
+        let user = self.inner.create(new_user).await?;
+        self.touched.push(user.id.clone());
+        Ok(user)
+    }
+
+    async fn update(&mut self, id: &str, update: UserUpdate) -> RepositoryResult<Option<User>> {
+        self.touched.push(id.to_string());
+        self.inner.update(id, update).await
+    }
+
+    async fn delete(&mut self, id: &str) -> RepositoryResult<Option<User>> {
+        self.touched.push(id.to_string());
+        self.inner.delete(id).await
+    }
+
+    async fn commit(self: Box<Self>) -> RepositoryResult<()> {
+        self.inner.commit().await?;
+
+        // Read the committed state back so the index matches what was actually stored
+        for id in &self.touched {
+            match self.repository.inner.get(id).await? {
+                Some(user) => self.repository.sync(&user).await,
+                None => self.repository.unindex(id).await,
+            }
+        }
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> RepositoryResult<()> {
+        self.inner.rollback().await
+    }
+}
+
+// Rebuild the search index from every live user in the repository
+pub async fn reindex_users(repository: &dyn UserRepository, index: &dyn SearchIndex) -> RepositoryResult<usize> {
+    index.reindex(repository.list().await?).await
+}
+
+// Define the index used when ELASTICSEARCH_INDEX is not set
+const DEFAULT_INDEX: &str = "users";
+
+// Define the settings used to connect to the search engine
+#[derive(Clone, Debug)]
+pub struct SearchConfig {
+    pub url: String,
+    pub index: String,
+}
+
+impl SearchConfig {
+    // Read ELASTICSEARCH_URL and the optional ELASTICSEARCH_INDEX (default "users"); returns None when indexing is disabled
+    pub fn from_settings(settings: &Settings) -> Option<Self> {
+        let url = settings.var("ELASTICSEARCH_URL")?;
+        let index = settings.var("ELASTICSEARCH_INDEX").unwrap_or_else(|| DEFAULT_INDEX.to_string());
+        Some(SearchConfig {
+            url: url.trim_end_matches('/').to_string(),
+            index,
+        })
+    }
+}
+
+// Define an ElasticsearchIndex that talks to Elasticsearch or OpenSearch over their shared REST API
+#[cfg(feature = "elasticsearch")]
+#[derive(Clone)]
+pub struct ElasticsearchIndex {
+    client: reqwest::Client,
+    url: String,
+    index: String,
+}
+
+// Define the mapping used for the users index. Emails are split on punctuation so that
+// "ada" and "example" both find "ada@example.com"
+#[cfg(feature = "elasticsearch")]
+fn index_definition() -> serde_json::Value {
+    serde_json::json!({
+        "settings": {
+            "analysis": {
+                "analyzer": {
+                    "email_parts": { "type": "pattern", "pattern": "[^\\p{L}\\p{N}]+", "lowercase": true }
+                }
+            }
+        },
+        "mappings": {
+            "properties": {
+                "name": { "type": "text" },
+                "email": { "type": "text", "analyzer": "email_parts" }
+            }
+        }
+    })
+}
+
+#[cfg(feature = "elasticsearch")]
+impl ElasticsearchIndex {
+    // Connect to the search engine and create the index if it does not exist yet
+    pub async fn connect(config: &SearchConfig) -> RepositoryResult<Self> {
+        let index = ElasticsearchIndex {
+            client: reqwest::Client::new(),
+            url: config.url.clone(),
+            index: config.index.clone(),
+        };
+        let response = index.client.head(index.index_url()).send().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            index.create_index().await?;
+        }
+        Ok(index)
+    }
+
+    // Build the URL of the index itself
+    fn index_url(&self) -> String {
+        format!("{}/{}", self.url, self.index)
+    }
+
+    // Build the URL of a user's document
+    fn document_url(&self, id: &str) -> String {
+        format!("{}/_doc/{}", self.index_url(), id)
+    }
+
+    // Create the index with its mapping
+    async fn create_index(&self) -> RepositoryResult<()> {
+        let response = self.client.put(self.index_url()).json(&index_definition()).send().await?;
+        check(response).await?;
+        Ok(())
+    }
+}
+
+// Convert HTTP client errors into backend errors
+#[cfg(feature = "elasticsearch")]
+impl From<reqwest::Error> for RepositoryError {
+    fn from(error: reqwest::Error) -> Self {
+        RepositoryError::Backend(error.to_string())
+    }
+}
+
+// Turn an error status into a backend error carrying the response body
+#[cfg(feature = "elasticsearch")]
+async fn check(response: reqwest::Response) -> RepositoryResult<reqwest::Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    Err(RepositoryError::Backend(format!("search engine returned {}: {}", status, body)))
+}
+
+// Build the document stored for a user
+#[cfg(feature = "elasticsearch")]
+fn document(user: &User) -> serde_json::Value {
+    serde_json::json!({ "name": user.name, "email": user.email })
+}
+
+// Read the highlighted form of a field from a search hit
+#[cfg(feature = "elasticsearch")]
+fn highlighted(hit: &serde_json::Value, field: &str) -> Option<String> {
+    hit["highlight"][field][0].as_str().map(str::to_string)
+}
+
+#[cfg(feature = "elasticsearch")]
+#[async_trait]
+impl SearchIndex for ElasticsearchIndex {
+    async fn index(&self, user: &User) -> RepositoryResult<()> {
+        let response = self.client.put(self.document_url(&user.id)).json(&document(user)).send().await?;
+        check(response).await?;
+        Ok(())
+    }
+
+    async fn remove(&self, id: &str) -> RepositoryResult<()> {
+        let response = self.client.delete(self.document_url(id)).send().await?;
+        if response.status() != reqwest::StatusCode::NOT_FOUND {
+            check(response).await?;
+        }
+        Ok(())
+    }
+
+    async fn search(&self, query: &str, limit: usize, offset: usize) -> RepositoryResult<Vec<SearchHit>> {
+        // Every word must match a name or email part, allowing for typos; name matches weigh more
+        let body = serde_json::json!({
+            "from": offset,
+            "size": limit,
+            "query": {
+                "multi_match": {
+                    "query": query,
+                    "fields": ["name^2", "email"],
+                    "fuzziness": "AUTO",
+                    "operator": "and"
+                }
+            },
+            "highlight": {
+                "pre_tags": [HIGHLIGHT_START],
+                "post_tags": [HIGHLIGHT_END],
+                "number_of_fragments": 0,
+                "fields": { "name": {}, "email": {} }
+            }
+        });
+        let response = self.client.post(format!("{}/_search", self.index_url())).json(&body).send().await?;
+        let results: serde_json::Value = check(response).await?.json().await?;
+        let hits = results["hits"]["hits"].as_array().cloned().unwrap_or_default();
+        Ok(hits
+            .iter()
+            .filter_map(|hit| {
+                Some(SearchHit {
+                    id: hit["_id"].as_str()?.to_string(),
+                    score: hit["_score"].as_f64().unwrap_or_default(),
+                    name: highlighted(hit, "name"),
+                    email: highlighted(hit, "email"),
+                })
+            })
+            .collect())
+    }
+
+    async fn reindex(&self, users: Vec<User>) -> RepositoryResult<usize> {
+        // Drop and recreate the index so documents for users that no longer exist go away too
+        let response = self.client.delete(self.index_url()).send().await?;
+        if response.status() != reqwest::StatusCode::NOT_FOUND {
+            check(response).await?;
+        }
+        self.create_index().await?;
+
+        // Send the documents in newline-delimited bulk requests of 500
+        for chunk in users.chunks(500) {
+            let mut body = String::new();
+            for user in chunk {
+                body.push_str(&serde_json::json!({ "index": { "_id": user.id } }).to_string());
+                body.push('\n');
+                body.push_str(&document(user).to_string());
+                body.push('\n');
+            }
+            let response = self
+                .client
+                .post(format!("{}/_bulk", self.index_url()))
+                .header(reqwest::header::CONTENT_TYPE, "application/x-ndjson")
+                .body(body)
+                .send()
+                .await?;
+            let result: serde_json::Value = check(response).await?.json().await?;
+            if result["errors"].as_bool() == Some(true) {
+                return Err(RepositoryError::Backend("search engine rejected some documents".to_string()));
+            }
+        }
+
+        // Make the new documents visible to searches straight away
+        let response = self.client.post(format!("{}/_refresh", self.index_url())).send().await?;
+        check(response).await?;
+        Ok(users.len())
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{legacy_user_id, UserRole};
+    use crate::repository::{with_transaction, InMemoryRepository, RepositoryError};
+    use crate::schema::tests::{as_pavel, promote_pavel};
+    use crate::schema::{build_schema, AppState};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::sync::Mutex;
+
+    // Define a search index backed by a map that answers with substring matches and can be switched off
+    #[derive(Default)]
+    struct MapIndex {
+        documents: Mutex<HashMap<String, User>>,
+        down: AtomicBool,
+    }
+
+    impl MapIndex {
+        fn check(&self) -> RepositoryResult<()> {
+            match self.down.load(Ordering::SeqCst) {
+                true => Err(RepositoryError::Backend("connection refused".to_string())),
+                false => Ok(()),
+            }
+        }
+
+        async fn ids(&self) -> Vec<String> {
+            let mut ids: Vec<String> = self.documents.lock().await.keys().cloned().collect();
+            ids.sort();
+            ids
+        }
+    }
+
+    #[async_trait]
+    impl SearchIndex for MapIndex {
+        async fn index(&self, user: &User) -> RepositoryResult<()> {
+            self.check()?;
+            self.documents.lock().await.insert(user.id.clone(), user.clone());
+            Ok(())
+        }
+
+        async fn remove(&self, id: &str) -> RepositoryResult<()> {
+            self.check()?;
+            self.documents.lock().await.remove(id);
+            Ok(())
+        }
+
+        async fn search(&self, query: &str, limit: usize, offset: usize) -> RepositoryResult<Vec<SearchHit>> {
+            self.check()?;
+            let mut users: Vec<User> = self.documents.lock().await.values().cloned().collect();
+            users.sort_by(|a, b| a.id.cmp(&b.id));
+            Ok(substring_search(users, query, limit, offset)
+                .into_iter()
+                .map(|result| SearchHit {
+                    id: result.user.id,
+                    score: result.rank * 10.0,
+                    name: Some(format!("[{}]", result.user.name)),
+                    email: None,
+                })
+                .collect())
+        }
+
+        async fn reindex(&self, users: Vec<User>) -> RepositoryResult<usize> {
+            self.check()?;
+            let mut documents = self.documents.lock().await;
+            documents.clear();
+            documents.extend(users.into_iter().map(|user| (user.id.clone(), user)));
+            Ok(documents.len())
+        }
+    }
+
+    fn user(id: &str, name: &str, email: &str) -> User {
+        User { id: id.to_string(), name: name.to_string(), email: email.to_string(), version: 1, created_at: Default::default(), updated_at: Default::default(), deleted_at: None, avatar_key: None, role: UserRole::Member, metadata: None, phone: None, address: None, email_verified: true }
+    }
+
+    // Define a test for matching, ranking, and highlighting
+    #[test]
+    fn test_substring_search() {
+        let users = vec![
+            user("1", "Grace Hopper", "admiral@navy.mil"),
+            user("2", "Ada Lovelace", "grace.fan@example.com"),
+            user("3", "Alan Turing", "alan@example.com"),
+        ];
+
+        // Name matches come before email-only matches, and every occurrence is highlighted
+        let results = substring_search(users.clone(), "GRACE", 10, 0);
+        let found: Vec<(&str, f64, &str)> =
+            results.iter().map(|result| (result.user.id.as_str(), result.rank, result.snippet.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                ("1", 1.0, "<b>Grace</b> Hopper admiral@navy.mil"),
+                ("2", 0.5, "Ada Lovelace <b>grace</b>.fan@example.com"),
+            ]
+        );
+
+        // Every term must match, overlapping matches are merged, and results are paged
+        assert_eq!(substring_search(users.clone(), "alan example", 10, 0)[0].snippet, "<b>Alan</b> Turing <b>alan</b>@<b>example</b>.com");
+        assert_eq!(substring_search(users.clone(), "ada hopper", 10, 0), Vec::new());
+        assert_eq!(substring_search(users.clone(), "lace lovel", 10, 0)[0].snippet, "Ada <b>Lovelace</b> grace.fan@example.com");
+        assert_eq!(substring_search(users.clone(), "example", 1, 1)[0].user.id, "3");
+        assert_eq!(substring_search(users, "   ", 10, 0), Vec::new());
+    }
+
+    // Define a test that writes keep the index in step and searches are answered from it
+    #[tokio::test]
+    async fn test_indexed_repository_keeps_index_in_sync() {
+        let index = Arc::new(MapIndex::default());
+        let repository = IndexedRepository::new(Arc::new(InMemoryRepository::new()), index.clone());
+        let ada = repository
+            .create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() })
+            .await
+            .unwrap();
+        let grace = repository
+            .create(NewUser { name: "Grace".to_string(), email: "grace@example.com".to_string(), ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(index.ids().await, vec![ada.id.clone(), grace.id.clone()]);
+
+        // Hits come from the index, with users loaded from the repository
+        let update = UserUpdate { name: Some("Ada Lovelace".to_string()), ..Default::default() };
+        repository.update(&ada.id, update).await.unwrap();
+        let results = repository.search("lovelace", 10, 0).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].user.version, 2);
+        assert_eq!(results[0].rank, 10.0);
+        assert_eq!(results[0].snippet, "[Ada Lovelace] ada@example.com");
+
+        // Deleted users leave the index and come back when restored; purged users are gone for good
+        repository.delete(&grace.id).await.unwrap();
+        assert_eq!(index.ids().await, vec![ada.id.clone()]);
+        repository.restore(&grace.id).await.unwrap();
+        assert_eq!(index.ids().await, vec![ada.id.clone(), grace.id.clone()]);
+        repository.purge(&grace.id).await.unwrap();
+        assert_eq!(index.ids().await, vec![ada.id.clone()]);
+
+        // Writes made in a transaction are indexed once it commits
+        let alan = with_transaction(&repository, |transaction| {
+            Box::pin(async move {
+                transaction
+                    .create(NewUser { name: "Alan".to_string(), email: "alan@example.com".to_string(), ..Default::default() })
+                    .await
+            })
+        })
+        .await
+        .unwrap();
+        assert_eq!(index.ids().await, vec![ada.id, alan.id]);
+    }
+
+    // Define a test that an unavailable index neither fails writes nor searches
+    #[tokio::test]
+    async fn test_indexed_repository_falls_back_when_index_is_down() {
+        let index = Arc::new(MapIndex::default());
+        let repository = IndexedRepository::new(Arc::new(InMemoryRepository::with_sample_users()), index.clone());
+        index.down.store(true, Ordering::SeqCst);
+
+        let user = repository
+            .create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() })
+            .await
+            .unwrap();
+        let results = repository.search("ada", 10, 0).await.unwrap();
+        assert_eq!(results.iter().map(|result| result.user.id.as_str()).collect::<Vec<_>>(), vec![user.id.as_str()]);
+        assert_eq!(results[0].snippet, "<b>Ada</b> <b>ada</b>@example.com");
+    }
+
+    // Define a test for the reindexUsers mutation
+    #[tokio::test]
+    async fn test_reindex_users_mutation() {
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        promote_pavel(&repository).await;
+        let repository: SharedRepository = repository;
+        let mutation = "mutation { reindexUsers }";
+
+        // Without a search index the mutation reports that indexing is off
+        let response = build_schema(AppState::new(repository.clone())).execute(as_pavel(mutation)).await;
+        assert_eq!(response.errors[0].message, "Search indexing is not enabled");
+
+        // Users written before the index existed are picked up by reindexing
+        let index = Arc::new(MapIndex::default());
+        let schema = build_schema(AppState::new(repository).with_search_index(index.clone()));
+        let response = schema.execute(as_pavel(mutation)).await;
+        let response_data = serde_json::to_value(response.data).expect("Failed to convert response to JSON");
+        assert_eq!(response_data, serde_json::json!({ "reindexUsers": 2 }));
+        assert_eq!(index.ids().await, vec![legacy_user_id(1).to_string(), legacy_user_id(2).to_string()]);
+    }
+
+    // Define a test for fuzzy matching, highlighting, and paging against a real search engine
+    #[cfg(feature = "elasticsearch")]
+    #[tokio::test]
+    #[ignore = "requires Elasticsearch or OpenSearch in ELASTICSEARCH_URL"]
+    async fn test_elasticsearch_index() {
+        let config = SearchConfig::from_settings(&Settings::load(None).unwrap()).expect("ELASTICSEARCH_URL must be set");
+        let index = ElasticsearchIndex::connect(&config).await.expect("Failed to connect to the search engine");
+        let users = vec![
+            user("1", "Grace Hopper", "admiral@navy.mil"),
+            user("2", "Ada Lovelace", "ada@example.com"),
+            user("3", "Grace Grace", "gg@example.com"),
+        ];
+        assert_eq!(index.reindex(users.clone()).await.unwrap(), 3);
+
+        // A misspelled query still finds the user and highlights the matched word
+        let hits = index.search("lovelcae", 10, 0).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, "2");
+        assert_eq!(hits[0].name.as_deref(), Some("Ada <b>Lovelace</b>"));
+
+        // Email parts are searchable and results are paged
+        let ids: Vec<String> = index.search("example", 10, 0).await.unwrap().into_iter().map(|hit| hit.id).collect();
+        assert_eq!(ids.len(), 2);
+        let second = index.search("example", 1, 1).await.unwrap();
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].id, ids[1]);
+
+        // Removed documents stop matching once the index refreshes
+        index.remove("2").await.unwrap();
+        index.remove("2").await.unwrap();
+        index.client.post(format!("{}/_refresh", index.index_url())).send().await.unwrap();
+        assert!(index.search("lovelace", 10, 0).await.unwrap().is_empty());
+    }
+}
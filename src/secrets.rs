@@ -0,0 +1,215 @@
+// Import necessary libraries and modules
+use std::collections::HashMap;
+
+use crate::config::Settings;
+use crate::log_warn;
+
+// Define the settings that hold secrets. Each can also be read from the file NAME_FILE names, such as a
+// Docker or Kubernetes secret mount, and all but VAULT_TOKEN from the Vault secret at VAULT_SECRET_PATH
+pub const SECRET_VARS: &[&str] = &[
+    "ADMIN_TOKEN",
+    "AVATAR_SIGNING_KEY",
+    "DATABASE_PASSWORD",
+    "DATABASE_REPLICA_URLS",
+    "DATABASE_URL",
+    "EMAIL_VERIFICATION_SIGNING_KEY",
+    "JWT_SECRET",
+    "OIDC_CLIENT_SECRET",
+    "PASSWORD_RESET_SIGNING_KEY",
+    "REDIS_URL",
+    "SIGNING_CLIENTS",
+    "USER_TOKENS",
+    "VAULT_TOKEN",
+];
+
+// Read the secrets whose NAME_FILE is set from their files. A file's trailing newline is not part of
+// the secret, and setting both NAME and NAME_FILE is refused, since it is unclear which one is meant
+pub fn file_secrets(vars: &HashMap<String, String>) -> Result<HashMap<String, String>, String> {
+    let mut secrets = HashMap::new();
+    for name in SECRET_VARS {
+        let Some(path) = vars.get(&format!("{}_FILE", name)) else {
+            continue;
+        };
+        if vars.contains_key(*name) {
+            return Err(format!("Set {} or {}_FILE, not both", name, name));
+        }
+        let contents = std::fs::read_to_string(path).map_err(|error| format!("Failed to read {}_FILE {}: {}", name, path, error))?;
+        secrets.insert(name.to_string(), contents.trim_end_matches(['\r', '\n']).to_string());
+    }
+    Ok(secrets)
+}
+
+// Work out the secret settings to add to the environment, given the environment with the file secrets
+// already in it and the secrets read from Vault: Vault only fills in secrets that are not set otherwise,
+// and DATABASE_PASSWORD is written into DATABASE_URL. Fails listing every name in REQUIRED_SECRETS, a
+// comma-separated list of setting names, that is still missing
+pub fn resolve_secrets(vars: &HashMap<String, String>, vault: HashMap<String, String>) -> Result<HashMap<String, String>, String> {
+    let mut secrets = HashMap::new();
+    for (name, value) in vault {
+        if !SECRET_VARS.contains(&name.as_str()) || name == "VAULT_TOKEN" {
+            log_warn!("Ignoring {} from Vault, which is not a secret setting", name);
+        } else if !vars.contains_key(&name) {
+            secrets.insert(name, value);
+        }
+    }
+    if let Some(password) = secrets.get("DATABASE_PASSWORD").or(vars.get("DATABASE_PASSWORD")) {
+        let url = secrets.get("DATABASE_URL").or(vars.get("DATABASE_URL")).ok_or("DATABASE_PASSWORD needs DATABASE_URL")?;
+        let url = with_password(url, password)?;
+        secrets.insert("DATABASE_URL".to_string(), url);
+    }
+    let get = |name: &str| secrets.get(name).or_else(|| vars.get(name));
+    let required = vars.get("REQUIRED_SECRETS").map(String::as_str).unwrap_or_default();
+    let missing: Vec<_> = required.split(',').map(str::trim).filter(|name| !name.is_empty() && get(name).is_none_or(|value| value.is_empty())).collect();
+    if !missing.is_empty() {
+        return Err(format!("Missing required secrets: {}", missing.join(", ")));
+    }
+    Ok(secrets)
+}
+
+// Replace the password of a URL such as postgres://app@db/app, percent-encoding it
+fn with_password(url: &str, password: &str) -> Result<String, String> {
+    let missing_user = || "DATABASE_PASSWORD needs a DATABASE_URL with a user, such as postgres://app@db/app".to_string();
+    let (scheme, rest) = url.split_once("://").ok_or_else(missing_user)?;
+    let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (userinfo, host) = rest[..authority_end].rsplit_once('@').ok_or_else(missing_user)?;
+    let user = userinfo.split(':').next().unwrap_or_default();
+    let encoded: String = password
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect();
+    Ok(format!("{}://{}:{}@{}{}", scheme, user, encoded, host, &rest[authority_end..]))
+}
+
+// Read the values of a Vault secret from the body of a read, for both version 1 and version 2 of the
+// key/value engine; version 2 nests them under data.data, next to data.metadata
+pub fn parse_vault_secret(body: &serde_json::Value) -> Result<HashMap<String, String>, String> {
+    let data = &body["data"];
+    let values = if data.get("metadata").is_some() { &data["data"] } else { data };
+    let values = values.as_object().ok_or("The Vault secret has no data")?;
+    values
+        .iter()
+        .map(|(name, value)| match value.as_str() {
+            Some(value) => Ok((name.clone(), value.to_string())),
+            None => Err(format!("The Vault secret's {} is not a string", name)),
+        })
+        .collect()
+}
+
+// Read the secret at VAULT_SECRET_PATH, such as secret/data/graphql, from the Vault server at
+// VAULT_ADDR with VAULT_TOKEN; without VAULT_ADDR Vault is not used
+#[cfg(feature = "vault")]
+async fn vault_secrets(vars: &HashMap<String, String>) -> Result<HashMap<String, String>, String> {
+    let Some(address) = vars.get("VAULT_ADDR") else {
+        return Ok(HashMap::new());
+    };
+    let token = vars.get("VAULT_TOKEN").ok_or("VAULT_ADDR needs VAULT_TOKEN or VAULT_TOKEN_FILE")?;
+    let path = vars.get("VAULT_SECRET_PATH").ok_or("VAULT_ADDR needs VAULT_SECRET_PATH")?;
+    let url = format!("{}/v1/{}", address.trim_end_matches('/'), path.trim_start_matches('/'));
+    let client = reqwest::Client::builder().timeout(std::time::Duration::from_secs(10)).build().map_err(|error| error.to_string())?;
+    let response = client.get(&url).header("X-Vault-Token", token).send().await.map_err(|error| format!("Failed to reach Vault: {}", error))?;
+    if !response.status().is_success() {
+        return Err(format!("Vault answered {} for {}", response.status(), path));
+    }
+    let body: serde_json::Value = response.json().await.map_err(|error| format!("Vault sent an invalid secret: {}", error))?;
+    parse_vault_secret(&body)
+}
+
+#[cfg(not(feature = "vault"))]
+async fn vault_secrets(vars: &HashMap<String, String>) -> Result<HashMap<String, String>, String> {
+    match vars.get("VAULT_ADDR") {
+        Some(_) => Err("VAULT_ADDR is set but the vault feature is not compiled into this build".to_string()),
+        None => Ok(HashMap::new()),
+    }
+}
+
+// Add the secrets read from files and Vault to the settings, and check the required ones are there.
+// Called at startup, before anything reads the settings
+pub async fn load_secrets(settings: &mut Settings) -> Result<(), String> {
+    let mut vars = settings.vars().clone();
+    vars.extend(file_secrets(&vars)?);
+    let vault = vault_secrets(&vars).await?;
+    vars.extend(resolve_secrets(&vars, vault)?);
+    for name in SECRET_VARS {
+        if let Some(value) = vars.remove(*name) {
+            settings.set(name, value);
+        }
+    }
+    Ok(())
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build an environment from name and value pairs
+    fn env(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    // Define a test for reading secrets from the files their NAME_FILE settings name
+    #[test]
+    fn test_file_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let jwt_secret = dir.path().join("jwt_secret");
+        std::fs::write(&jwt_secret, "s3cret\n").unwrap();
+        let path = jwt_secret.to_str().unwrap();
+        let secrets = file_secrets(&env(&[("JWT_SECRET_FILE", path), ("SEED_FILE", "seed.json")])).unwrap();
+        assert_eq!(secrets, env(&[("JWT_SECRET", "s3cret")]));
+
+        assert_eq!(file_secrets(&env(&[("JWT_SECRET_FILE", path), ("JWT_SECRET", "other")])).unwrap_err(), "Set JWT_SECRET or JWT_SECRET_FILE, not both");
+        assert!(file_secrets(&env(&[("ADMIN_TOKEN_FILE", "/nonexistent/admin_token")])).unwrap_err().starts_with("Failed to read ADMIN_TOKEN_FILE"));
+    }
+
+    // Define a test that Vault fills in unset secrets, the database password goes into the URL, and missing
+    // required secrets are all reported
+    #[test]
+    fn test_resolve_secrets() {
+        let vars = env(&[("JWT_SECRET", "from-env"), ("DATABASE_URL", "postgres://app:old@db:5432/app?sslmode=require")]);
+        let vault = env(&[("JWT_SECRET", "from-vault"), ("ADMIN_TOKEN", "admin"), ("DATABASE_PASSWORD", "p@ss word"), ("SEED_FILE", "/etc/passwd")]);
+        let secrets = resolve_secrets(&vars, vault).unwrap();
+        assert_eq!(secrets["ADMIN_TOKEN"], "admin");
+        assert!(!secrets.contains_key("JWT_SECRET") && !secrets.contains_key("SEED_FILE"));
+        assert_eq!(secrets["DATABASE_URL"], "postgres://app:p%40ss%20word@db:5432/app?sslmode=require");
+        assert!(resolve_secrets(&env(&[("DATABASE_URL", "sqlite:dev.db"), ("DATABASE_PASSWORD", "x")]), HashMap::new()).is_err());
+
+        let vars = env(&[("REQUIRED_SECRETS", "JWT_SECRET, ADMIN_TOKEN,USER_TOKENS"), ("ADMIN_TOKEN", ""), ("USER_TOKENS", "t=1")]);
+        assert_eq!(resolve_secrets(&vars, HashMap::new()).unwrap_err(), "Missing required secrets: JWT_SECRET, ADMIN_TOKEN");
+        assert!(resolve_secrets(&vars, env(&[("JWT_SECRET", "s"), ("ADMIN_TOKEN", "a")])).is_err());
+    }
+
+    // Define a test for reading secrets from both versions of Vault's key/value engine
+    #[test]
+    fn test_parse_vault_secret() {
+        let v2 = serde_json::json!({ "data": { "data": { "JWT_SECRET": "s3cret" }, "metadata": { "version": 3 } } });
+        assert_eq!(parse_vault_secret(&v2).unwrap(), env(&[("JWT_SECRET", "s3cret")]));
+        let v1 = serde_json::json!({ "lease_duration": 3600, "data": { "JWT_SECRET": "s3cret" } });
+        assert_eq!(parse_vault_secret(&v1).unwrap(), env(&[("JWT_SECRET", "s3cret")]));
+        assert!(parse_vault_secret(&serde_json::json!({ "data": { "JWT_SECRET": 1 } })).is_err());
+        assert!(parse_vault_secret(&serde_json::json!({ "errors": [] })).is_err());
+    }
+
+    // Define a test that secrets are read from Vault with the configured token
+    #[cfg(feature = "vault")]
+    #[tokio::test]
+    async fn test_vault_secrets() {
+        use warp::Filter;
+
+        let secret = warp::path!("v1" / "secret" / "data" / "graphql").and(warp::header::exact("x-vault-token", "root")).map(|| {
+            warp::reply::json(&serde_json::json!({ "data": { "data": { "ADMIN_TOKEN": "admin" }, "metadata": {} } }))
+        });
+        let (address, server) = warp::serve(secret).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let address = format!("http://{}/", address);
+        let vars = env(&[("VAULT_ADDR", &address), ("VAULT_TOKEN", "root"), ("VAULT_SECRET_PATH", "secret/data/graphql")]);
+        assert_eq!(vault_secrets(&vars).await.unwrap(), env(&[("ADMIN_TOKEN", "admin")]));
+
+        let vars = env(&[("VAULT_ADDR", &address), ("VAULT_TOKEN", "wrong"), ("VAULT_SECRET_PATH", "secret/data/graphql")]);
+        assert!(vault_secrets(&vars).await.is_err());
+        assert!(vault_secrets(&env(&[("VAULT_ADDR", &address)])).await.is_err());
+        assert_eq!(vault_secrets(&HashMap::new()).await.unwrap(), HashMap::new());
+    }
+}
@@ -0,0 +1,209 @@
+// Import necessary libraries and modules
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use std::fmt;
+use std::path::Path;
+
+use crate::log_warn;
+use crate::model::{is_valid_email, NewUser, UserRole, MAX_NAME_LENGTH};
+use crate::repository::{RepositoryError, UserRepository};
+
+// Define a single record in the seed file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SeedRecord {
+    name: String,
+    email: String,
+    #[serde(default)]
+    role: UserRole,
+}
+
+// Define a problem found in one seed record, located by line number
+#[derive(Debug, PartialEq)]
+pub struct SeedIssue {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SeedIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// Define the result of parsing a seed file: the valid users plus any rejected records
+#[derive(Debug, Default)]
+pub struct SeedData {
+    pub users: Vec<NewUser>,
+    pub issues: Vec<SeedIssue>,
+}
+
+// Define the errors that stop seed data from being loaded
+#[derive(Debug, thiserror::Error)]
+pub enum SeedError {
+    #[error("failed to read seed file {path}: {source}")]
+    Io { path: String, source: std::io::Error },
+    #[error("seed file is not a JSON array of users (line {line}, column {column}): {message}")]
+    Syntax { line: usize, column: usize, message: String },
+    #[error("seed file has invalid records:\n{}", format_issues(.0))]
+    InvalidRecords(Vec<SeedIssue>),
+    #[error(transparent)]
+    Repository(#[from] RepositoryError),
+}
+
+// Join issues into one line each for error messages
+fn format_issues(issues: &[SeedIssue]) -> String {
+    issues.iter().map(|issue| format!("  {}", issue)).collect::<Vec<_>>().join("\n")
+}
+
+// Return the 1-based line on which `slice` starts within `contents`
+fn line_of(contents: &str, slice: &str) -> usize {
+    let offset = slice.as_ptr() as usize - contents.as_ptr() as usize;
+    contents[..offset].matches('\n').count() + 1
+}
+
+// Check the fields of a record that parsed successfully
+fn validate(record: &SeedRecord) -> Result<(), String> {
+    if record.name.trim().is_empty() {
+        return Err("name must not be empty".to_string());
+    }
+    if record.name.chars().count() > MAX_NAME_LENGTH {
+        return Err(format!("name must be at most {} characters", MAX_NAME_LENGTH));
+    }
+    if !is_valid_email(&record.email) {
+        return Err(format!("{:?} is not a valid email address", record.email));
+    }
+    Ok(())
+}
+
+// Parse seed file contents, collecting every invalid record instead of stopping at the first
+pub fn parse_seed(contents: &str) -> Result<SeedData, SeedError> {
+    let records: Vec<&RawValue> = serde_json::from_str(contents).map_err(|error| SeedError::Syntax {
+        line: error.line(),
+        column: error.column(),
+        message: error.to_string(),
+    })?;
+
+    let mut data = SeedData::default();
+    for raw in records {
+        let line = line_of(contents, raw.get());
+        let record = match serde_json::from_str::<SeedRecord>(raw.get()) {
+            Ok(record) => record,
+            Err(error) => {
+                // Errors inside a multi-line record are reported on the line where they occur
+                let line = line + error.line().saturating_sub(1);
+                let message = error.to_string();
+                let message = message.split(" at line ").next().unwrap_or(&message).to_string();
+                data.issues.push(SeedIssue { line, message });
+                continue;
+            }
+        };
+        match validate(&record) {
+            Ok(()) => data.users.push(NewUser {
+                id: None,
+                name: record.name,
+                email: record.email,
+                role: record.role,
+                phone: None,
+                address: None,
+                email_verified: true,
+            }),
+            Err(message) => data.issues.push(SeedIssue { line, message }),
+        }
+    }
+    Ok(data)
+}
+
+// Load the seed file into an empty repository, returning how many users were created.
+// Repositories that already hold users are left untouched so restarts do not duplicate data.
+// With `ignore_errors`, invalid records are reported and skipped instead of failing the load
+pub async fn load_seed(
+    path: &Path,
+    repository: &dyn UserRepository,
+    ignore_errors: bool,
+) -> Result<usize, SeedError> {
+    let contents = std::fs::read_to_string(path).map_err(|source| SeedError::Io {
+        path: path.display().to_string(),
+        source,
+    })?;
+    let data = parse_seed(&contents)?;
+    if !data.issues.is_empty() {
+        if !ignore_errors {
+            return Err(SeedError::InvalidRecords(data.issues));
+        }
+        for issue in &data.issues {
+            log_warn!("Skipping seed record at {}", issue);
+        }
+    }
+
+    if !repository.list_including_deleted().await?.is_empty() {
+        return Ok(0);
+    }
+    let count = data.users.len();
+    for user in data.users {
+        repository.create(user).await?;
+    }
+    Ok(count)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::InMemoryRepository;
+
+    // Define a test that every invalid record is reported with its line number
+    #[test]
+    fn test_invalid_records_report_lines() {
+        let contents = r#"[
+  { "name": "Ada", "email": "ada@example.com", "role": "admin" },
+  { "name": "", "email": "blank@example.com" },
+  { "name": "NoEmail" },
+  {
+    "name": "Bad",
+    "email": 42
+  },
+  { "name": "Grace", "email": "not-an-email" }
+]"#;
+        let data = parse_seed(contents).expect("Seed file should parse");
+        assert_eq!(data.users.len(), 1);
+        assert_eq!(data.users[0].name, "Ada");
+        assert_eq!(data.users[0].role, UserRole::Admin);
+
+        let lines: Vec<usize> = data.issues.iter().map(|issue| issue.line).collect();
+        assert_eq!(lines, vec![3, 4, 7, 9]);
+        assert_eq!(data.issues[0].message, "name must not be empty");
+        assert_eq!(data.issues[1].message, "missing field `email`");
+        assert_eq!(data.issues[3].message, "\"not-an-email\" is not a valid email address");
+    }
+
+    // Define a test that malformed JSON is rejected with its position
+    #[test]
+    fn test_malformed_json_is_rejected() {
+        let error = parse_seed("[\n  { \"name\": \"Ada\" ,,\n]").unwrap_err();
+        assert!(matches!(error, SeedError::Syntax { line: 2, .. }), "unexpected error: {}", error);
+    }
+
+    // Define a test for loading the bundled seed file and the ignore-errors mode
+    #[tokio::test]
+    async fn test_load_seed() {
+        let repository = InMemoryRepository::new();
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("seed.json");
+        assert_eq!(load_seed(&path, &repository, false).await.unwrap(), 2);
+        assert_eq!(repository.list().await.unwrap()[0].name, "Pavel");
+
+        // A second load leaves the populated repository alone
+        assert_eq!(load_seed(&path, &repository, false).await.unwrap(), 0);
+
+        // Invalid records fail the load unless they are ignored
+        let dir = tempfile::tempdir().unwrap();
+        let bad_path = dir.path().join("seed.json");
+        std::fs::write(&bad_path, r#"[{ "name": "Ada", "email": "ada@example.com" }, { "name": "Bob" }]"#).unwrap();
+        let empty = InMemoryRepository::new();
+        assert!(matches!(
+            load_seed(&bad_path, &empty, false).await,
+            Err(SeedError::InvalidRecords(issues)) if issues.len() == 1
+        ));
+        assert_eq!(load_seed(&bad_path, &empty, true).await.unwrap(), 1);
+    }
+}
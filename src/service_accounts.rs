@@ -0,0 +1,64 @@
+// Import necessary libraries and modules
+use std::collections::HashSet;
+
+use crate::model::ServiceAccount;
+
+// Define the service accounts injected into the schema data
+#[derive(Clone, Debug, Default)]
+pub struct ServiceAccounts(pub Vec<ServiceAccount>);
+
+impl ServiceAccounts {
+    // Find a service account by its own ID
+    pub fn get(&self, id: &str) -> Option<&ServiceAccount> {
+        self.0.iter().find(|account| account.id == id)
+    }
+}
+
+// Parse a comma-separated list of `id=Display Name` entries, as given in SERVICE_ACCOUNTS.
+// IDs are letters, digits, `-`, and `_`, and must be unique; names must not be blank
+pub fn parse_service_accounts(value: &str) -> Result<Vec<ServiceAccount>, String> {
+    let mut seen = HashSet::new();
+    let mut accounts = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let Some((id, name)) = entry.split_once('=') else {
+            return Err(format!("{:?} is not of the form id=Display Name", entry));
+        };
+        let (id, name) = (id.trim(), name.trim());
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(format!("{:?} is not a valid service account ID", id));
+        }
+        if name.is_empty() {
+            return Err(format!("service account {:?} needs a display name", id));
+        }
+        if !seen.insert(id) {
+            return Err(format!("service account {:?} is listed twice", id));
+        }
+        accounts.push(ServiceAccount { id: id.to_string(), name: name.to_string() });
+    }
+    Ok(accounts)
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Define a test for reading service accounts from their configuration
+    #[test]
+    fn test_parse_service_accounts() {
+        let accounts = parse_service_accounts(" importer=CSV Importer , relay_1=Outbox Relay,").unwrap();
+        assert_eq!(
+            accounts,
+            vec![
+                ServiceAccount { id: "importer".to_string(), name: "CSV Importer".to_string() },
+                ServiceAccount { id: "relay_1".to_string(), name: "Outbox Relay".to_string() },
+            ]
+        );
+        assert_eq!(parse_service_accounts("").unwrap(), Vec::new());
+
+        assert_eq!(parse_service_accounts("importer").unwrap_err(), "\"importer\" is not of the form id=Display Name");
+        assert_eq!(parse_service_accounts("a b=Name").unwrap_err(), "\"a b\" is not a valid service account ID");
+        assert_eq!(parse_service_accounts("bot= ").unwrap_err(), "service account \"bot\" needs a display name");
+        assert_eq!(parse_service_accounts("bot=One,bot=Two").unwrap_err(), "service account \"bot\" is listed twice");
+    }
+}
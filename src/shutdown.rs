@@ -0,0 +1,56 @@
+// Import necessary libraries and modules
+use std::future::Future;
+use std::time::Duration;
+
+use crate::config::Settings;
+use crate::log_info;
+
+// Define how long requests in flight get to finish after a shutdown signal when SHUTDOWN_TIMEOUT_SECS is
+// not set
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Read SHUTDOWN_TIMEOUT_SECS, how long to wait for requests in flight once asked to stop; 0 stops at once
+pub fn drain_timeout_from_settings(settings: &Settings) -> Result<Duration, String> {
+    match settings.var("SHUTDOWN_TIMEOUT_SECS") {
+        Some(value) => value.parse().map(Duration::from_secs).map_err(|_| format!("SHUTDOWN_TIMEOUT_SECS must be a number of seconds, got {:?}", value)),
+        None => Ok(DEFAULT_DRAIN_TIMEOUT),
+    }
+}
+
+// Wait until the process is asked to stop, by Ctrl+C (SIGINT) or, on Unix, by SIGTERM as container
+// runtimes and process managers send
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => log_info!("Received SIGINT, shutting down"),
+            _ = terminate.recv() => log_info!("Received SIGTERM, shutting down"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
+        log_info!("Received Ctrl+C, shutting down");
+    }
+}
+
+// Wait for a server that has stopped accepting connections to finish the requests in flight, up to the
+// timeout. Returns whether they all finished; the rest are dropped when the process exits
+pub async fn drain(server: impl Future, timeout: Duration) -> bool {
+    tokio::time::timeout(timeout, server).await.is_ok()
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Define a test that draining waits for the server to finish, but only up to the timeout
+    #[tokio::test]
+    async fn test_drain() {
+        assert!(drain(tokio::time::sleep(Duration::from_millis(10)), Duration::from_secs(10)).await);
+        assert!(!drain(tokio::time::sleep(Duration::from_secs(10)), Duration::from_millis(10)).await);
+        assert!(!drain(std::future::pending::<()>(), Duration::ZERO).await);
+    }
+}
@@ -0,0 +1,145 @@
+// Import necessary libraries and modules
+use async_graphql::ParseRequestError;
+use async_graphql_warp::GraphQLBadRequest;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+use warp::Rejection;
+
+use crate::avatar::decode_hex;
+use crate::model::parse_user_id;
+
+// Define the header signed requests carry the signature of their timestamp and body in, as `sha256=<hex>`
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+// Define the header signed requests carry the Unix time they were signed at in, which the signature covers
+pub const TIMESTAMP_HEADER: &str = "x-signature-timestamp";
+
+// Define how many seconds a signed request's timestamp may be from the server's clock, either way, so a
+// captured request cannot be replayed for long while clocks that drift a little still agree
+pub const SIGNATURE_WINDOW_SECS: i64 = 300;
+
+// Define the header signed requests name the client whose secret signed them in
+pub const CLIENT_ID_HEADER: &str = "x-client-id";
+
+// Define an API client that authenticates server-to-server requests by signing their bodies: the secret
+// it shares with the server, and the user its requests act as
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SigningClient {
+    pub secret: String,
+    pub user_id: String,
+}
+
+// Define the signature a request carries, along with the client it names and the timestamp and body it
+// was made over
+#[derive(Clone, Copy, Debug)]
+pub struct Signature<'a> {
+    pub client_id: Option<&'a str>,
+    pub signature: &'a str,
+    pub timestamp: Option<&'a str>,
+    pub body: &'a [u8],
+}
+
+// Parse a JSON object of `"client ID": { "secret": "...", "userId": "..." }` entries, as given in
+// SIGNING_CLIENTS; user IDs are read like user ID arguments
+pub fn parse_signing_clients(value: &str) -> Result<HashMap<String, SigningClient>, String> {
+    let entries: HashMap<String, SigningClient> =
+        serde_json::from_str(value).map_err(|_| "SIGNING_CLIENTS must be a JSON object of \"client ID\": { \"secret\": \"...\", \"userId\": \"...\" } entries".to_string())?;
+    entries
+        .into_iter()
+        .map(|(client_id, client)| {
+            if client_id.trim().is_empty() || client.secret.is_empty() {
+                return Err("SIGNING_CLIENTS entries need a non-empty client ID and secret".to_string());
+            }
+            match parse_user_id(client.user_id.trim()) {
+                Some(uuid) => Ok((client_id, SigningClient { user_id: uuid.to_string(), ..client })),
+                None => Err(format!("{:?} is not a valid user ID", client.user_id.trim())),
+            }
+        })
+        .collect()
+}
+
+// Sign a body as clients do at the given Unix time, returning the X-Signature header value: the
+// HMAC-SHA256 of the timestamp, a `.`, and the body with the secret, as lowercase hex after a `sha256=` prefix
+pub fn sign_body(secret: &[u8], timestamp: i64, body: &[u8]) -> String {
+    let signature: String = mac(secret, &timestamp.to_string(), body).finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256={}", signature)
+}
+
+// Check an X-Signature header value against the X-Signature-Timestamp it was sent with and a body,
+// comparing in constant time; the `sha256=` prefix may be left out. Timestamps more than
+// SIGNATURE_WINDOW_SECS from `now` are refused, however good the signature
+pub fn verify_signature(secret: &[u8], timestamp: &str, body: &[u8], signature: &str, now: i64) -> bool {
+    let timestamp = timestamp.trim();
+    if !timestamp.parse::<i64>().is_ok_and(|signed_at| signed_at.abs_diff(now) <= SIGNATURE_WINDOW_SECS.unsigned_abs()) {
+        return false;
+    }
+    let signature = signature.trim();
+    let hex = signature.strip_prefix("sha256=").unwrap_or(signature);
+    decode_hex(hex).is_some_and(|signature| mac(secret, timestamp, body).verify_slice(&signature).is_ok())
+}
+
+// Start the HMAC of a timestamp and body
+fn mac(secret: &[u8], timestamp: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+// Read the GraphQL request in the body of a signed request, which must be JSON, since the signature is
+// checked over the body as sent. Bodies that are not are rejected as bad requests
+pub fn parse_signed_request(body: &[u8]) -> Result<async_graphql::Request, Rejection> {
+    serde_json::from_slice(body).map_err(|error| warp::reject::custom(GraphQLBadRequest(ParseRequestError::InvalidRequest(Box::new(error)))))
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Define a test that signatures verify only for the secret, timestamp, and body they were made with
+    #[test]
+    fn test_signatures() {
+        let (body, now) = (br#"{"query":"{ users { id } }"}"#, 1_700_000_000);
+        let signature = sign_body(b"shared-secret", now, body);
+        assert!(signature.starts_with("sha256=") && signature.len() == 7 + 64);
+        assert!(verify_signature(b"shared-secret", "1700000000", body, &signature, now));
+        assert!(verify_signature(b"shared-secret", "1700000000", body, signature.trim_start_matches("sha256="), now));
+        assert!(!verify_signature(b"other-secret", "1700000000", body, &signature, now));
+        assert!(!verify_signature(b"shared-secret", "1700000000", br#"{"query":"{ user(id: 1) { id } }"}"#, &signature, now));
+        assert!(!verify_signature(b"shared-secret", "1700000000", body, "sha256=not-hex", now));
+        assert!(!verify_signature(b"shared-secret", "1700000000", body, "", now));
+        assert!(!verify_signature(b"shared-secret", "yesterday", body, &signature, now));
+    }
+
+    // Define a test that a captured request is only accepted within the window around its timestamp, and
+    // cannot be moved to a fresh timestamp without the secret
+    #[test]
+    fn test_signature_replay() {
+        let (body, signed_at) = (br#"{"query":"mutation { deleteUser(id: 2) { success } }"}"#, 1_700_000_000);
+        let signature = sign_body(b"shared-secret", signed_at, body);
+        for now in [signed_at - SIGNATURE_WINDOW_SECS, signed_at, signed_at + SIGNATURE_WINDOW_SECS] {
+            assert!(verify_signature(b"shared-secret", "1700000000", body, &signature, now), "{}", now);
+        }
+        for now in [signed_at - SIGNATURE_WINDOW_SECS - 1, signed_at + SIGNATURE_WINDOW_SECS + 1, signed_at + 86_400] {
+            assert!(!verify_signature(b"shared-secret", "1700000000", body, &signature, now), "{}", now);
+        }
+        let later = signed_at + 86_400;
+        assert!(!verify_signature(b"shared-secret", &later.to_string(), body, &signature, later));
+    }
+
+    // Define a test for reading the configured clients
+    #[test]
+    fn test_parse_signing_clients() {
+        let clients = parse_signing_clients(r#"{ "billing": { "secret": "s3cret", "userId": "2" } }"#).unwrap();
+        assert_eq!(clients["billing"].secret, "s3cret");
+        assert_eq!(clients["billing"].user_id, "00000000-0000-0000-0000-000000000002");
+        assert!(parse_signing_clients(r#"{ "billing": { "secret": "", "userId": "2" } }"#).is_err());
+        assert!(parse_signing_clients(r#"{ "billing": { "secret": "s3cret", "userId": "not-an-id" } }"#).is_err());
+        assert!(parse_signing_clients(r#"{ "billing": "s3cret" }"#).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+// Import necessary libraries and modules
+use async_graphql::{Context, Result, Subscription, ID};
+use futures::stream::{self, Stream, StreamExt};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::model::User;
+use crate::schema::user_id_argument;
+
+// Define how many events a subscriber may fall behind before it starts missing them
+pub const EVENT_CAPACITY: usize = 256;
+
+// Define a change to a user that is published to subscribers
+#[derive(Clone, Debug, PartialEq)]
+pub enum UserEvent {
+    Created(User),
+    // The user after an update, metadata change, soft delete, restore, avatar change, or email verification
+    Updated(User),
+}
+
+// Define the channel mutations publish user events to; every subscription reads its own copy of the events
+#[derive(Clone)]
+pub struct UserEvents {
+    sender: broadcast::Sender<UserEvent>,
+}
+
+impl UserEvents {
+    // Create a channel that keeps up to `capacity` events for slow subscribers
+    pub fn new(capacity: usize) -> Self {
+        UserEvents { sender: broadcast::channel(capacity).0 }
+    }
+
+    // Send an event to every current subscriber; with none, it is dropped
+    pub fn publish(&self, event: UserEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    // Stream the events published from now on. A subscriber that falls too far behind skips the events
+    // it missed rather than ending its subscription
+    pub fn subscribe(&self) -> impl Stream<Item = UserEvent> {
+        stream::unfold(self.sender.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
+
+impl Default for UserEvents {
+    fn default() -> Self {
+        UserEvents::new(EVENT_CAPACITY)
+    }
+}
+
+// Define a SubscriptionRoot struct for handling GraphQL subscriptions
+pub struct SubscriptionRoot;
+
+// Implement GraphQL Subscription for the SubscriptionRoot struct
+#[Subscription]
+impl SubscriptionRoot {
+    // Stream every user created after the subscription starts
+    async fn user_created(&self, ctx: &Context<'_>) -> Result<impl Stream<Item = User>> {
+        let events = ctx.data::<UserEvents>()?;
+        Ok(events.subscribe().filter_map(|event| async move {
+            match event {
+                UserEvent::Created(user) => Some(user),
+                UserEvent::Updated(_) => None,
+            }
+        }))
+    }
+
+    // Stream users as they change after the subscription starts, only the one with the given ID if one is
+    // given; the filter runs here, so other users' changes are never sent to the client
+    async fn user_updated(&self, ctx: &Context<'_>, id: Option<ID>) -> Result<impl Stream<Item = User>> {
+        let events = ctx.data::<UserEvents>()?;
+        let id = id.map(|id| user_id_argument(&id)).transpose()?;
+        Ok(events.subscribe().filter_map(move |event| {
+            let user = match event {
+                UserEvent::Updated(user) if id.as_ref().is_none_or(|id| *id == user.id) => Some(user),
+                _ => None,
+            };
+            async move { user }
+        }))
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::{InMemoryRepository, UserRepository};
+    use futures::FutureExt;
+
+    // Define a test that subscribers see events published after they subscribe, skipping ones they fell behind on
+    #[tokio::test]
+    async fn test_user_events() {
+        let users = InMemoryRepository::with_sample_users().list().await.unwrap();
+        let (pavel, charlie) = (UserEvent::Created(users[0].clone()), UserEvent::Created(users[1].clone()));
+        let events = UserEvents::new(1);
+        events.publish(pavel.clone());
+
+        // Earlier events are not replayed
+        let mut subscriber = Box::pin(events.subscribe());
+        assert!(subscriber.next().now_or_never().is_none());
+        events.publish(charlie.clone());
+        assert_eq!(subscriber.next().await, Some(charlie.clone()));
+
+        // With room for one event, a subscriber two behind only gets the latest
+        events.publish(pavel.clone());
+        events.publish(charlie.clone());
+        assert_eq!(subscriber.next().await, Some(charlie));
+
+        // Every kind of event reaches every subscriber
+        let updated = UserEvent::Updated(users[0].clone());
+        let mut subscriber = Box::pin(events.subscribe());
+        events.publish(updated.clone());
+        assert_eq!(subscriber.next().await, Some(updated));
+    }
+}
@@ -0,0 +1,206 @@
+// Import necessary libraries and modules
+use async_graphql::SimpleObject;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+
+use crate::auth::{hash_api_key, tokens_match};
+use crate::config::Settings;
+
+// Define the issuer authenticator apps list accounts under when TOTP_ISSUER is not set
+pub const DEFAULT_ISSUER: &str = "rust-graphql-server";
+
+// Define how many seconds each code is valid for, and how many digits it has, as authenticator apps expect
+const PERIOD_SECS: i64 = 30;
+const DIGITS: u32 = 6;
+
+// Define how many recovery codes each enrollment gets
+const RECOVERY_CODES: usize = 10;
+
+// Define the RFC 4648 base32 alphabet secrets are shared with authenticator apps in
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// Define what enrolling returns: the secret to add to an authenticator app, as text and as an otpauth://
+// URL to show as a QR code, and the recovery codes that stand in for a code once each. None of them can be
+// read back later
+#[derive(SimpleObject)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_url: String,
+    pub recovery_codes: Vec<String>,
+}
+
+// Define the issuer authenticator apps list enrolled accounts under
+#[derive(Clone, Debug, PartialEq)]
+pub struct TotpIssuer(pub String);
+
+impl Default for TotpIssuer {
+    fn default() -> Self {
+        TotpIssuer(DEFAULT_ISSUER.to_string())
+    }
+}
+
+impl TotpIssuer {
+    // Read TOTP_ISSUER, falling back to the default issuer
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        match settings.var("TOTP_ISSUER") {
+            Some(issuer) if issuer.trim().is_empty() => Err("TOTP_ISSUER must not be empty".to_string()),
+            Some(issuer) => Ok(TotpIssuer(issuer)),
+            None => Ok(TotpIssuer::default()),
+        }
+    }
+}
+
+// Generate a new secret from 20 random bytes, base32-encoded
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    encode_base32(&bytes)
+}
+
+// Generate a set of recovery codes, each 16 random base32 characters grouped in fours
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let characters: Vec<char> = (0..16).map(|_| BASE32[(rng.next_u32() % 32) as usize].to_ascii_lowercase() as char).collect();
+            characters.chunks(4).map(|group| group.iter().collect::<String>()).collect::<Vec<_>>().join("-")
+        })
+        .collect()
+}
+
+// Hash a recovery code as it is stored and looked up, ignoring case, spaces, and dashes. The codes are
+// random and long, so like API keys a plain SHA-256 is enough
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code.chars().filter(|c| !c.is_whitespace() && *c != '-').collect::<String>().to_lowercase();
+    hash_api_key(&normalized)
+}
+
+// Build the otpauth:// URL authenticator apps read a secret from, labelled with the issuer and account
+pub fn otpauth_url(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        encode_component(issuer),
+        encode_component(account),
+        secret,
+        encode_component(issuer),
+        DIGITS,
+        PERIOD_SECS
+    )
+}
+
+// Check a code against a secret at the given Unix time. Codes from the step before and after are accepted
+// too, to allow for clocks that drift and codes typed as they roll over
+pub fn verify_code(secret: &str, code: &str, now: i64) -> bool {
+    let Some(key) = decode_base32(secret) else {
+        return false;
+    };
+    let code = code.trim();
+    let step = now.div_euclid(PERIOD_SECS);
+    // Every step is checked, so the time taken does not show which one matched
+    [step - 1, step, step + 1].iter().fold(false, |matched, step| tokens_match(code, &code_at(&key, *step)) | matched)
+}
+
+// Return the code an authenticator app shows for a secret at the given Unix time, or None if the secret is
+// malformed
+pub fn current_code(secret: &str, now: i64) -> Option<String> {
+    decode_base32(secret).map(|key| code_at(&key, now.div_euclid(PERIOD_SECS)))
+}
+
+// Compute the code for a step as RFC 6238 does: HMAC-SHA1 over the step number, dynamically truncated
+fn code_at(key: &[u8], step: i64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&(step as u64).to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+    format!("{:0width$}", value % 10u32.pow(DIGITS), width = DIGITS as usize)
+}
+
+// Encode bytes as unpadded base32
+fn encode_base32(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+// Decode base32, ignoring case and padding, returning None if it is malformed
+fn decode_base32(value: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in value.trim_end_matches('=').bytes() {
+        let index = BASE32.iter().position(|digit| *digit == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | index;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+// Percent-encode a label or parameter of the otpauth:// URL
+fn encode_component(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Define a test against the RFC 6238 test vectors, truncated to six digits, and the window codes are accepted in
+    #[test]
+    fn test_codes() {
+        let secret = encode_base32(b"12345678901234567890");
+        assert_eq!(secret, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(decode_base32(&secret.to_lowercase()).as_deref(), Some(&b"12345678901234567890"[..]));
+        for (time, code) in [(59, "287082"), (1111111109, "081804"), (1234567890, "005924"), (2000000000, "279037")] {
+            assert!(verify_code(&secret, code, time), "{} at {}", code, time);
+        }
+        assert!(verify_code(&secret, "287082", 59 + PERIOD_SECS));
+        assert!(!verify_code(&secret, "287082", 59 + 2 * PERIOD_SECS));
+        assert!(!verify_code(&secret, "287083", 59));
+        assert!(!verify_code("not base32!", "287082", 59));
+        assert_eq!(decode_base32(&generate_secret()).map(|key| key.len()), Some(20));
+    }
+
+    // Define a test that recovery codes are distinct and hash the same however they are typed
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert!(codes.iter().all(|code| code.len() == 19 && code.split('-').count() == 4));
+        assert_eq!(codes.iter().collect::<std::collections::HashSet<_>>().len(), RECOVERY_CODES);
+        let typed = codes[0].replace('-', " ").to_uppercase();
+        assert_eq!(hash_recovery_code(&typed), hash_recovery_code(&codes[0]));
+        assert_ne!(hash_recovery_code(&codes[1]), hash_recovery_code(&codes[0]));
+    }
+
+    // Define a test for the URL authenticator apps read
+    #[test]
+    fn test_otpauth_url() {
+        assert_eq!(
+            otpauth_url("Acme Corp", "ada@example.com", "JBSWY3DPEHPK3PXP"),
+            "otpauth://totp/Acme%20Corp:ada%40example.com?secret=JBSWY3DPEHPK3PXP&issuer=Acme%20Corp&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
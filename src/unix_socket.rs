@@ -0,0 +1,202 @@
+// Import necessary libraries and modules
+use std::future::Future;
+use std::net::SocketAddr;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::time::Duration;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use warp::hyper::server::conn::Http;
+use warp::hyper::service::service_fn;
+
+use crate::config::Settings;
+use crate::rate_limit::PeerAddr;
+use crate::reload::SharedRoutes;
+use crate::{log_error, log_warn};
+
+// Define the address requests over the socket are seen to come from, since its peers are processes on
+// the same host, such as a reverse proxy
+pub const SOCKET_PEER: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 0);
+
+// Define the path of the socket to listen on instead of TCP, and the permissions to give it, if any
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnixSocketConfig {
+    pub path: PathBuf,
+    pub mode: Option<u32>,
+}
+
+impl UnixSocketConfig {
+    // Read UNIX_SOCKET_PATH, which turns the socket on, and UNIX_SOCKET_MODE, its permissions in octal
+    // such as 660; without a path the server listens on TCP
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+        let mode = match settings.var("UNIX_SOCKET_MODE") {
+            Some(mode) => Some(u32::from_str_radix(mode.trim(), 8).ok().filter(|mode| *mode <= 0o777).ok_or_else(|| format!("UNIX_SOCKET_MODE must be octal permissions such as 660, got {:?}", mode))?),
+            None => None,
+        };
+        match settings.var("UNIX_SOCKET_PATH") {
+            Some(path) if path.trim().is_empty() => Err("UNIX_SOCKET_PATH must not be empty".to_string()),
+            Some(path) => Ok(Some(UnixSocketConfig { path: path.into(), mode })),
+            None if mode.is_some() => Err("UNIX_SOCKET_MODE needs UNIX_SOCKET_PATH".to_string()),
+            None => Ok(None),
+        }
+    }
+}
+
+// Define a socket the server listens on, whose file is removed when it is dropped
+pub struct UnixSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        if let Err(error) = std::fs::remove_file(&self.path) {
+            log_warn!("Failed to remove the socket {}: {}", self.path.display(), error);
+        }
+    }
+}
+
+impl UnixSocket {
+    // Listen on the configured path. A socket file left behind by a server that did not stop cleanly is
+    // replaced, but not one another server is still listening on, nor a file that is not a socket
+    pub async fn bind(config: &UnixSocketConfig) -> Result<Self, String> {
+        let path = &config.path;
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(format!("{} exists and is not a socket", path.display()));
+            }
+            if UnixStream::connect(path).await.is_ok() {
+                return Err(format!("another process is listening on {}", path.display()));
+            }
+            std::fs::remove_file(path).map_err(|error| format!("Failed to remove the stale socket {}: {}", path.display(), error))?;
+        }
+        let listener = UnixListener::bind(path).map_err(|error| format!("Failed to listen on {}: {}", path.display(), error))?;
+        let socket = UnixSocket { listener, path: path.clone() };
+        if let Some(mode) = config.mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).map_err(|error| format!("Failed to set the permissions of {}: {}", path.display(), error))?;
+        }
+        Ok(socket)
+    }
+
+    // Return the path of the socket
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Serve the routes on every connection the socket accepts until `shutdown` completes, each on a task
+    // of its own, with SOCKET_PEER as its PeerAddr extension. Once shut down, no connections are
+    // accepted, and this returns when the open ones have finished the requests in flight, removing the
+    // socket file
+    pub async fn serve(self, routes: SharedRoutes, shutdown: impl Future<Output = ()>) {
+        let http = Http::new();
+        let (stop, stopped) = watch::channel(false);
+        let mut connections = JoinSet::new();
+        let mut shutdown = pin!(shutdown);
+        loop {
+            let accepted = tokio::select! {
+                _ = &mut shutdown => break,
+                accepted = self.listener.accept() => accepted,
+            };
+            let stream = match accepted {
+                Ok((stream, _)) => stream,
+                Err(error) => {
+                    // Running out of file descriptors fails every accept until a connection closes
+                    log_error!("Failed to accept a connection: {}", error);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let (http, routes) = (http.clone(), routes.clone());
+            let mut stopped = stopped.clone();
+            connections.spawn(async move {
+                let service = service_fn(move |mut request| {
+                    request.extensions_mut().insert(PeerAddr(SOCKET_PEER));
+                    let routes = routes.clone();
+                    async move { routes.answer(request).await }
+                });
+                // On shutdown the connection finishes the requests it has started, then closes
+                let mut connection = pin!(http.serve_connection(stream, service).with_upgrades());
+                let result = tokio::select! {
+                    result = connection.as_mut() => result,
+                    _ = stopped.changed() => {
+                        connection.as_mut().graceful_shutdown();
+                        connection.await
+                    }
+                };
+                if let Err(error) = result {
+                    log_warn!("Failed to serve a connection over the socket: {}", error);
+                }
+            });
+            // Let go of the connections that have closed, so the set does not grow with every one served
+            while connections.try_join_next().is_some() {}
+        }
+        let _ = stop.send(true);
+        while connections.join_next().await.is_some() {}
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::peer_addr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use warp::{Filter, Reply};
+
+    // Send a GET request over the socket at the path and return the response
+    async fn get(path: &Path) -> String {
+        let mut stream = UnixStream::connect(path).await.unwrap();
+        stream.write_all(b"GET /peer HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    // Define a test that requests are served over the socket, and its file is removed on shutdown
+    #[tokio::test]
+    async fn test_serve_over_socket() {
+        let directory = tempfile::tempdir().unwrap();
+        let config = UnixSocketConfig { path: directory.path().join("graphql.sock"), mode: Some(0o660) };
+        let socket = UnixSocket::bind(&config).await.unwrap();
+        assert_eq!(std::fs::metadata(&config.path).unwrap().permissions().mode() & 0o777, 0o660);
+
+        let routes = warp::path("peer").and(peer_addr()).map(|peer: Option<SocketAddr>| format!("{:?}", peer)).map(|reply| Box::new(reply) as Box<dyn Reply>).boxed();
+        let routes = SharedRoutes::new(routes, None);
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(socket.serve(routes, async {
+            stopped.await.ok();
+        }));
+        let response = get(&config.path).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("Some(127.0.0.1:0)"), "{}", response);
+
+        // Another server cannot take the socket while this one is listening on it
+        assert!(UnixSocket::bind(&config).await.err().unwrap().contains("another process is listening"));
+
+        stop.send(()).unwrap();
+        server.await.unwrap();
+        assert!(!config.path.exists());
+    }
+
+    // Define a test that a stale socket file is replaced, but other files are left alone
+    #[tokio::test]
+    async fn test_bind_over_existing_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("graphql.sock");
+        let config = UnixSocketConfig { path: path.clone(), mode: None };
+
+        // A socket nothing listens on any more, as a server that was killed leaves behind
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        let socket = UnixSocket::bind(&config).await.unwrap();
+        assert_eq!(socket.path(), path);
+        drop(socket);
+        assert!(!path.exists());
+
+        std::fs::write(&path, "not a socket").unwrap();
+        assert!(UnixSocket::bind(&config).await.err().unwrap().contains("is not a socket"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+    }
+}
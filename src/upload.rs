@@ -0,0 +1,136 @@
+// Import necessary libraries and modules
+use async_graphql::http::MultipartOptions;
+use async_graphql_warp::GraphQLBadRequest;
+use warp::{Rejection, Reply};
+
+use crate::config::Settings;
+
+// Define the largest file a multipart request may carry when UPLOAD_MAX_FILE_BYTES is not set
+pub const DEFAULT_MAX_FILE_BYTES: usize = 10 * 1024 * 1024;
+
+// Define how many files a multipart request may carry when UPLOAD_MAX_FILES is not set
+pub const DEFAULT_MAX_FILES: usize = 4;
+
+// Define the limits on files sent with GraphQL multipart requests. The request parser enforces them
+// before any resolver runs: no part may exceed max_file_bytes, and the request as a whole may carry at
+// most max_files files of that size. Resolvers reading an upload check its size against them too
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UploadLimits {
+    pub max_file_bytes: usize,
+    pub max_files: usize,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        UploadLimits { max_file_bytes: DEFAULT_MAX_FILE_BYTES, max_files: DEFAULT_MAX_FILES }
+    }
+}
+
+impl UploadLimits {
+    // Read UPLOAD_MAX_FILE_BYTES (default 10 MiB) and UPLOAD_MAX_FILES (default 4)
+    pub fn from_settings(settings: &Settings) -> Result<Self, String> {
+        Ok(UploadLimits {
+            max_file_bytes: number_var(settings, "UPLOAD_MAX_FILE_BYTES", DEFAULT_MAX_FILE_BYTES)?,
+            max_files: number_var(settings, "UPLOAD_MAX_FILES", DEFAULT_MAX_FILES)?,
+        })
+    }
+
+    // Return the options the GraphQL route parses multipart requests with
+    pub fn multipart_options(&self) -> MultipartOptions {
+        MultipartOptions::default().max_file_size(self.max_file_bytes).max_num_files(self.max_files)
+    }
+}
+
+// Read a positive number from a setting, falling back to a default when it is not set
+pub(crate) fn number_var(settings: &Settings, name: &str, default: usize) -> Result<usize, String> {
+    match settings.var(name) {
+        Some(value) => match value.parse() {
+            Ok(number) if number > 0 => Ok(number),
+            _ => Err(format!("{} must be a positive number, got {:?}", name, value)),
+        },
+        None => Ok(default),
+    }
+}
+
+// Answer requests the GraphQL route could not parse with the parser's message: 413 for a file over the
+// size limit and 400 otherwise, including requests cut off at the overall limit. Other rejections pass through
+pub async fn recover_bad_request(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    match rejection.find::<GraphQLBadRequest>() {
+        Some(error) => Ok(warp::reply::with_status(error.to_string(), error.status())),
+        None => Err(rejection),
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::InMemoryRepository;
+    use crate::schema::tests::{as_pavel, promote_pavel};
+    use crate::schema::{build_schema, AppState};
+    use std::sync::Arc;
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    // Build a multipart body sending an importUsers mutation with the given files
+    fn import_request(files: &[&str]) -> (String, String) {
+        let boundary = "upload-boundary";
+        let query = "mutation($file: Upload!) { importUsers(file: $file) { importedCount } }";
+        let mut body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"operations\"\r\n\r\n{}\r\n",
+            serde_json::json!({ "query": query, "variables": { "file": null } })
+        );
+        let map: serde_json::Map<String, serde_json::Value> =
+            (0..files.len()).map(|index| (index.to_string(), serde_json::json!(["variables.file"]))).collect();
+        body += &format!("--{boundary}\r\nContent-Disposition: form-data; name=\"map\"\r\n\r\n{}\r\n", serde_json::Value::Object(map));
+        for (index, content) in files.iter().enumerate() {
+            body += &format!(
+                "--{boundary}\r\nContent-Disposition: form-data; name=\"{index}\"; filename=\"users.csv\"\r\nContent-Type: text/csv\r\n\r\n{content}\r\n"
+            );
+        }
+        body += &format!("--{boundary}--\r\n");
+        (format!("multipart/form-data; boundary={}", boundary), body)
+    }
+
+    // Define a test that multipart requests carry files to mutations within the limits
+    #[tokio::test]
+    async fn test_multipart_limits() {
+        let limits = UploadLimits { max_file_bytes: 1024, max_files: 2 };
+        let repository = Arc::new(InMemoryRepository::with_sample_users());
+        promote_pavel(&repository).await;
+        let schema = build_schema(AppState::new(repository).with_upload_limits(limits));
+        let route = async_graphql_warp::graphql_opts(schema, limits.multipart_options())
+            .and_then(|(schema, request): (crate::schema::AppSchema, async_graphql::Request)| async move {
+                Ok::<_, Rejection>(warp::reply::json(&schema.execute(as_pavel(request)).await))
+            })
+            .recover(recover_bad_request);
+        let send = |files: &[&str]| {
+            let (content_type, body) = import_request(files);
+            let route = route.clone();
+            async move { warp::test::request().method("POST").header("content-type", content_type).body(body).reply(&route).await }
+        };
+
+        // A file within the limits reaches the resolver
+        let response = send(&["name,email\nAda,ada@example.com\n"]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["data"]["importUsers"]["importedCount"], 1);
+
+        // A file over the size limit, or more than the allowed files' worth of data, is refused before the resolver runs
+        let large = format!("name,email\n{}", "Ada,ada@example.com\n".repeat(60));
+        let response = send(&[&large]).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let medium = format!("name,email\n{}", "Ada,ada@example.com\n".repeat(40));
+        let response = send(&[&medium, &medium, &medium]).await;
+        assert!(response.status().is_client_error());
+
+        // Malformed multipart bodies are bad requests
+        let response = warp::test::request()
+            .method("POST")
+            .header("content-type", "multipart/form-data; boundary=upload-boundary")
+            .body("--upload-boundary\r\n")
+            .reply(&route)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
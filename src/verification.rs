@@ -0,0 +1,227 @@
+// Import necessary libraries and modules
+use async_trait::async_trait;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::avatar::decode_hex;
+use crate::config::Settings;
+use crate::environment::AppEnv;
+use crate::model::User;
+
+// Define how long verification links stay valid when EMAIL_VERIFICATION_TTL_SECS is not set
+const DEFAULT_TTL_SECS: u64 = 24 * 60 * 60;
+
+// Define an email sent by the server
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EmailMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Define a way of delivering email
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    // Deliver one message, failing if it could not be handed over
+    async fn send(&self, message: &EmailMessage) -> Result<(), String>;
+}
+
+// Define a shared, thread-safe handle to a mailer
+pub type SharedMailer = Arc<dyn Mailer>;
+
+// Define a LogMailer that prints every message instead of sending it, for development
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, message: &EmailMessage) -> Result<(), String> {
+        println!("Email to {}: {}\n{}", message.to, message.subject, message.body);
+        Ok(())
+    }
+}
+
+// Define a WebhookMailer that POSTs each message as JSON to a service that sends it
+#[cfg(feature = "webhook")]
+pub struct WebhookMailer {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[cfg(feature = "webhook")]
+impl WebhookMailer {
+    // Send messages to the given URL, giving up on a request after 10 seconds
+    pub fn new(url: &str) -> Result<Self, String> {
+        let client = reqwest::Client::builder().timeout(Duration::from_secs(10)).build().map_err(|error| error.to_string())?;
+        Ok(WebhookMailer { client, url: url.to_string() })
+    }
+}
+
+#[cfg(feature = "webhook")]
+#[async_trait]
+impl Mailer for WebhookMailer {
+    async fn send(&self, message: &EmailMessage) -> Result<(), String> {
+        let response = self.client.post(&self.url).json(message).send().await.map_err(|error| error.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("mail webhook returned {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+// Read MAILER (log or webhook, default log) and create the mailer it names. The webhook mailer needs
+// MAILER_WEBHOOK_URL and the webhook feature, and the log mailer is refused when APP_ENV is production,
+// since it would only print the links
+pub fn mailer_from_settings(settings: &Settings) -> Result<SharedMailer, String> {
+    match settings.var("MAILER").as_deref() {
+        None | Some("log") => {
+            if AppEnv::from_settings(settings)? == AppEnv::Production {
+                return Err("the log mailer only prints messages; set MAILER=webhook in production".to_string());
+            }
+            Ok(Arc::new(LogMailer))
+        }
+        #[cfg(feature = "webhook")]
+        Some("webhook") => {
+            let url = settings.var("MAILER_WEBHOOK_URL").ok_or_else(|| "MAILER_WEBHOOK_URL must be set for the webhook mailer".to_string())?;
+            Ok(Arc::new(WebhookMailer::new(&url)?))
+        }
+        #[cfg(not(feature = "webhook"))]
+        Some("webhook") => Err("the webhook mailer is not compiled in; enable the webhook feature".to_string()),
+        Some(other) => Err(format!("MAILER must be log or webhook, got {:?}", other)),
+    }
+}
+
+// Define how users who register prove they own their email address: they are mailed a link to
+// `link_url` carrying a token signed with HMAC-SHA256 over their ID, email address, and the link's
+// expiry time, so a link stops working once it expires or the address changes
+#[derive(Clone)]
+pub struct EmailVerification {
+    signing_key: Vec<u8>,
+    link_url: String,
+    ttl: Duration,
+    mailer: SharedMailer,
+}
+
+impl EmailVerification {
+    // Sign tokens with the given key and mail links to the given page, valid for a day
+    pub fn new(signing_key: Vec<u8>, link_url: &str, mailer: SharedMailer) -> Self {
+        EmailVerification { signing_key, link_url: link_url.to_string(), ttl: Duration::from_secs(DEFAULT_TTL_SECS), mailer }
+    }
+
+    // Keep links valid for the given time instead
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    // Read EMAIL_VERIFICATION_URL, the page links point to, which turns verification on; without it None
+    // is returned. EMAIL_VERIFICATION_SIGNING_KEY must then be set, EMAIL_VERIFICATION_TTL_SECS defaults
+    // to 86400, and the mailer is read by `mailer_from_settings`
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, String> {
+        let Some(link_url) = settings.var("EMAIL_VERIFICATION_URL") else {
+            return Ok(None);
+        };
+        let signing_key = match settings.var("EMAIL_VERIFICATION_SIGNING_KEY") {
+            Some(key) if !key.is_empty() => key.into_bytes(),
+            _ => return Err("EMAIL_VERIFICATION_SIGNING_KEY must be set to sign verification links".to_string()),
+        };
+        let ttl = match settings.var("EMAIL_VERIFICATION_TTL_SECS") {
+            Some(value) => match value.parse() {
+                Ok(seconds) if seconds > 0 => Duration::from_secs(seconds),
+                _ => return Err(format!("EMAIL_VERIFICATION_TTL_SECS must be a positive number, got {:?}", value)),
+            },
+            None => Duration::from_secs(DEFAULT_TTL_SECS),
+        };
+        Ok(Some(EmailVerification::new(signing_key, &link_url, mailer_from_settings(settings)?).with_ttl(ttl)))
+    }
+
+    // Start the HMAC over the signed part of a token; email addresses are compared ignoring case
+    fn mac(&self, user_id: &str, email: &str, expires: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.signing_key).expect("HMAC accepts keys of any length");
+        mac.update(format!("verify-email:{}:{}:{}", user_id, email.to_lowercase(), expires).as_bytes());
+        mac
+    }
+
+    // Build the token for a user, expiring at the given Unix time, as `<user ID>.<expiry>.<hex signature>`
+    fn token_at(&self, user: &User, expires: i64) -> String {
+        let signature: String = self.mac(&user.id, &user.email, expires).finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("{}.{}.{}", user.id, expires, signature)
+    }
+
+    // Build a token for a user that expires after the configured time
+    pub fn token(&self, user: &User) -> String {
+        let ttl = i64::try_from(self.ttl.as_secs()).unwrap_or(i64::MAX);
+        self.token_at(user, Utc::now().timestamp().saturating_add(ttl))
+    }
+
+    // Build the link mailed to a user
+    pub fn link(&self, user: &User) -> String {
+        let separator = if self.link_url.contains('?') { '&' } else { '?' };
+        format!("{}{}token={}", self.link_url, separator, self.token(user))
+    }
+
+    // Return the ID of the user a token was issued to, if it is well-formed
+    pub fn token_user_id(token: &str) -> Option<&str> {
+        token.split('.').next().filter(|user_id| !user_id.is_empty())
+    }
+
+    // Check a token was issued to the user as they are now and has not expired, comparing the signature in
+    // constant time
+    pub fn verify(&self, token: &str, user: &User) -> bool {
+        let mut parts = token.split('.');
+        let (Some(user_id), Some(expires), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return false;
+        };
+        let (Ok(expires), Some(signature)) = (expires.parse::<i64>(), decode_hex(signature)) else {
+            return false;
+        };
+        user_id == user.id && expires >= Utc::now().timestamp() && self.mac(&user.id, &user.email, expires).verify_slice(&signature).is_ok()
+    }
+
+    // Mail the user a link that verifies their email address
+    pub async fn send(&self, user: &User) -> Result<(), String> {
+        let body = format!(
+            "Hi {},\n\nOpen this link to verify your email address:\n{}\n\nThe link works for {} hours.",
+            user.name,
+            self.link(user),
+            self.ttl.as_secs().div_ceil(3600)
+        );
+        let message = EmailMessage { to: user.email.clone(), subject: "Verify your email address".to_string(), body };
+        self.mailer.send(&message).await
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::NewUser;
+    use crate::repository::{InMemoryRepository, UserRepository};
+
+    // Define a test that tokens verify only the user they were issued to, as long as the address and link are unchanged
+    #[tokio::test]
+    async fn test_verification_tokens() {
+        let repository = InMemoryRepository::new();
+        let user = repository.create(NewUser { name: "Ada".to_string(), email: "ada@example.com".to_string(), ..Default::default() }).await.unwrap();
+        let other = repository.create(NewUser { name: "Grace".to_string(), email: "grace@example.com".to_string(), ..Default::default() }).await.unwrap();
+        let verification = EmailVerification::new(b"signing-key".to_vec(), "https://app.example.com/verify", Arc::new(LogMailer));
+        let token = verification.token(&user);
+        assert_eq!(EmailVerification::token_user_id(&token), Some(user.id.as_str()));
+        assert!(verification.verify(&token, &user));
+        assert!(verification.verify(&token, &User { email: "ADA@example.com".to_string(), ..user.clone() }));
+        assert!(verification.link(&user).starts_with(&format!("https://app.example.com/verify?token={}.", user.id)));
+
+        // Another user, a changed address, another key, a tampered signature, and an expired link are all refused
+        assert!(!verification.verify(&token, &other));
+        assert!(!verification.verify(&token, &User { email: "ada@example.org".to_string(), ..user.clone() }));
+        let other_key = EmailVerification::new(b"other-key".to_vec(), "https://app.example.com/verify", Arc::new(LogMailer));
+        assert!(!other_key.verify(&token, &user));
+        let tampered = format!("{}0", token.trim_end_matches(|c: char| c.is_ascii_hexdigit()));
+        assert!(!verification.verify(&tampered, &user));
+        assert!(!verification.verify(&verification.token_at(&user, 1), &user));
+        assert!(!verification.verify("not-a-token", &user));
+    }
+}
@@ -0,0 +1,248 @@
+// Import necessary libraries and modules
+use async_graphql::http::{WebSocket, WebSocketProtocols, WsMessage};
+use async_graphql::{Data, ErrorExtensions};
+use async_graphql_warp::graphql_protocol;
+use futures::{future, SinkExt, StreamExt};
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::{interval_at, Instant, Interval};
+use warp::ws::{Message, Ws};
+use warp::{Filter, Rejection, Reply};
+
+use crate::auth::{AuthContext, Authenticator, Credentials, Viewer, API_KEY_HEADER};
+use crate::config::Settings;
+use crate::cors::{allows_cookie_origin, CorsConfig};
+use crate::error::AppError;
+use crate::rate_limit::{rate_limit, Quota, RateLimiter};
+use crate::schema::AppSchema;
+
+// Define how often idle subscription connections are pinged when WS_KEEP_ALIVE_SECS is not set; well under
+// the minute after which proxies and load balancers commonly close idle connections
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+// Read WS_KEEP_ALIVE_SECS, how often to ping clients connected to /graphql/ws; 0 sends no pings
+pub fn keep_alive_from_settings(settings: &Settings) -> Result<Option<Duration>, String> {
+    match settings.var("WS_KEEP_ALIVE_SECS") {
+        Some(value) => match value.parse() {
+            Ok(0) => Ok(None),
+            Ok(secs) => Ok(Some(Duration::from_secs(secs))),
+            Err(_) => Err(format!("WS_KEEP_ALIVE_SECS must be a number of seconds, got {:?}", value)),
+        },
+        None => Ok(Some(DEFAULT_KEEP_ALIVE)),
+    }
+}
+
+// Build the /graphql/ws route, which serves subscriptions over WebSocket connections speaking the
+// graphql-transport-ws protocol, or the older graphql-ws one. Each connection is counted against the rate
+// limit of the credentials its upgrade request carries, and acts for the ones its connection_init message
+// carries, if any, since browsers cannot set headers on WebSocket connections. A connection opened by a
+// page on another origin than the server's or the ones CORS allows does not act for a session cookie its
+// upgrade carries, so other sites cannot open connections in their visitors' names. WebSocket upgrades
+// to /graphql itself, where older clients connect, are served the same way
+pub fn websocket_route(
+    schema: AppSchema,
+    rate_limiter: Option<RateLimiter>,
+    authenticator: Authenticator,
+    keep_alive: Option<Duration>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let connection = connection(schema, rate_limiter, authenticator, keep_alive);
+    // Other GET requests to /graphql are queries, so they are let through before they count against the limit
+    let legacy = warp::path!("graphql").and(warp::header::exact_ignore_case("upgrade", "websocket")).and(connection.clone());
+    warp::path!("graphql" / "ws").and(connection).or(legacy).unify()
+}
+
+// Build the filter that upgrades a request to a subscription connection
+fn connection(
+    schema: AppSchema,
+    rate_limiter: Option<RateLimiter>,
+    authenticator: Authenticator,
+    keep_alive: Option<Duration>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    rate_limit(rate_limiter, authenticator.clone()).and(cookie_origin()).and(graphql_protocol()).and(warp::ws()).map(
+        move |auth: AuthContext, _: Option<Quota>, cookie_origin: bool, protocol: WebSocketProtocols, ws: Ws| {
+            let (schema, authenticator) = (schema.clone(), authenticator.clone());
+            let auth = match auth.session.is_some() && !cookie_origin {
+                true => AuthContext { client_ip: auth.client_ip, ..Default::default() },
+                false => auth,
+            };
+            let reply = ws.on_upgrade(move |socket| {
+                let on_init = move |payload: serde_json::Value| async move {
+                    let mut data = Data::default();
+                    data.insert(connection_auth(&authenticator, auth, &payload).await?);
+                    Ok(data)
+                };
+                serve(socket, schema, protocol, keep_alive, on_init)
+            });
+            warp::reply::with_header(reply, "sec-websocket-protocol", protocol.sec_websocket_protocol())
+        },
+    )
+}
+
+// Build a filter telling whether the page that sent a request may use the browser's cookies with it, by
+// its Origin and the CORS settings the request carries (see `allows_cookie_origin`)
+fn cookie_origin() -> impl Filter<Extract = (bool,), Error = Rejection> + Clone {
+    warp::ext::optional::<CorsConfig>()
+        .and(warp::header::optional::<String>("origin"))
+        .and(warp::header::optional::<String>("host"))
+        .map(|cors: Option<CorsConfig>, origin: Option<String>, host: Option<String>| allows_cookie_origin(cors.as_ref(), origin.as_deref(), host.as_deref()))
+}
+
+// Work out who a connection acts for from its connection_init payload, which may carry an `Authorization`
+// bearer token or an `X-API-Key`, named as the headers are, in any case. Without either, the connection
+// acts for the credentials of its upgrade request. Invalid credentials refuse the connection
+pub async fn connection_auth(authenticator: &Authenticator, upgrade: AuthContext, payload: &serde_json::Value) -> async_graphql::Result<AuthContext> {
+    let field = |name: &str| {
+        let fields = payload.as_object()?;
+        fields.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).and_then(|(_, value)| value.as_str())
+    };
+    let (authorization, api_key) = (field("authorization"), field(API_KEY_HEADER));
+    let auth = if authorization.is_some() || api_key.is_some() {
+        let credentials = Credentials { authorization, api_key, client_certificate: upgrade.client_certificate.as_ref(), ..Default::default() };
+        AuthContext { client_ip: upgrade.client_ip, ..authenticator.authenticate(credentials).await }
+    } else {
+        upgrade
+    };
+    if auth.viewer == Viewer::InvalidCredentials {
+        return Err(AppError::Unauthenticated("The credentials sent with connection_init are not valid".to_string()).extend());
+    }
+    Ok(auth)
+}
+
+// Answer the messages of one connection with the schema, with the data `on_init` makes from its
+// connection_init payload, until either side closes it. Every `keep_alive` the client is sent a ping,
+// which graphql-transport-ws clients answer with a pong, so the connection does not look idle
+async fn serve<F, R>(socket: warp::ws::WebSocket, schema: AppSchema, protocol: WebSocketProtocols, keep_alive: Option<Duration>, on_init: F)
+where
+    F: FnOnce(serde_json::Value) -> R + Send + 'static,
+    R: Future<Output = async_graphql::Result<Data>> + Send + 'static,
+{
+    let (mut sink, stream) = socket.split();
+    let stream = stream.take_while(|message| future::ready(message.is_ok())).map(Result::unwrap);
+    let stream = stream.filter(|message| future::ready(message.is_text() || message.is_binary())).map(Message::into_bytes);
+    let mut messages = WebSocket::new(schema, stream, protocol).on_connection_init(on_init);
+    let mut ticks = keep_alive.map(|period| interval_at(Instant::now() + period, period));
+    loop {
+        let message = tokio::select! {
+            message = messages.next() => match message {
+                Some(WsMessage::Text(text)) => Message::text(text),
+                Some(WsMessage::Close(code, reason)) => Message::close_with(code, reason),
+                None => break,
+            },
+            _ = tick(&mut ticks) => Message::text(ping(protocol)),
+        };
+        if sink.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+// Wait for the next keep-alive, or forever without them
+async fn tick(ticks: &mut Option<Interval>) {
+    match ticks {
+        Some(ticks) => drop(ticks.tick().await),
+        None => future::pending().await,
+    }
+}
+
+// Return the keep-alive message of a protocol: a ping for graphql-transport-ws, and `ka` for graphql-ws,
+// which clients need not answer
+fn ping(protocol: WebSocketProtocols) -> &'static str {
+    match protocol {
+        WebSocketProtocols::GraphQLWS => r#"{"type":"ping"}"#,
+        WebSocketProtocols::SubscriptionsTransportWS => r#"{"type":"ka"}"#,
+    }
+}
+
+// Unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{Sessions, SESSION_COOKIE};
+    use crate::schema::tests::{sample_schema, PAVEL};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    // Build the route with Pavel's bearer token, unlimited, pinging at the given interval
+    fn route(keep_alive: Option<Duration>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone + 'static {
+        let authenticator = Authenticator::new(HashMap::from([("secret".to_string(), PAVEL.to_string())]));
+        websocket_route(sample_schema(), None, authenticator, keep_alive)
+    }
+
+    // Connect to the route with the graphql-transport-ws protocol
+    async fn connect(keep_alive: Option<Duration>) -> warp::test::WsClient {
+        warp::test::ws().path("/graphql/ws").header("sec-websocket-protocol", "graphql-transport-ws").handshake(route(keep_alive)).await.unwrap()
+    }
+
+    // Receive the next message, which must be text, as JSON
+    async fn receive(client: &mut warp::test::WsClient) -> serde_json::Value {
+        serde_json::from_str(client.recv().await.unwrap().to_str().unwrap()).unwrap()
+    }
+
+    // Define a test that operations run as the user whose token connection_init carries
+    #[tokio::test]
+    async fn test_connection_init_auth() {
+        let mut client = connect(None).await;
+        client.send_text(json!({"type": "connection_init", "payload": {"Authorization": "Bearer secret"}}).to_string()).await;
+        assert_eq!(receive(&mut client).await, json!({"type": "connection_ack"}));
+        client.send_text(json!({"id": "1", "type": "subscribe", "payload": {"query": "{ me { name } }"}}).to_string()).await;
+        assert_eq!(receive(&mut client).await, json!({"id": "1", "type": "next", "payload": {"data": {"me": {"name": "Pavel"}}}}));
+        assert_eq!(receive(&mut client).await, json!({"id": "1", "type": "complete"}));
+
+        // Without credentials the connection is anonymous, and with invalid ones it is closed
+        let auth = connection_auth(&Authenticator::default(), AuthContext::default(), &json!({})).await.unwrap();
+        assert_eq!(auth.viewer, Viewer::Anonymous);
+        let mut client = connect(None).await;
+        client.send_text(json!({"type": "connection_init", "payload": {"authorization": "Bearer wrong"}}).to_string()).await;
+        client.recv_closed().await.unwrap();
+        let error = connection_auth(&Authenticator::default(), AuthContext::default(), &json!({"X-API-Key": "wrong"})).await.unwrap_err();
+        assert_eq!(error.message, "The credentials sent with connection_init are not valid");
+    }
+
+    // Define a test that a connection opened by a page on another origin does not act for the session
+    // cookie its upgrade carries, while one opened by a page on the server's own origin does
+    #[tokio::test]
+    async fn test_cookie_origin() {
+        let sessions = Sessions::new(chrono::Duration::hours(1));
+        let token = sessions.create(PAVEL).await.unwrap();
+        let route = websocket_route(sample_schema(), None, Authenticator::default().with_sessions(sessions), None);
+        for (origin, me) in [("https://evil.example", json!(null)), ("https://api.example.com", json!({"name": "Pavel"}))] {
+            let mut client = warp::test::ws()
+                .path("/graphql/ws")
+                .header("sec-websocket-protocol", "graphql-transport-ws")
+                .header("host", "api.example.com")
+                .header("origin", origin)
+                .header("cookie", format!("{}={}", SESSION_COOKIE, token))
+                .handshake(route.clone())
+                .await
+                .unwrap();
+            client.send_text(json!({"type": "connection_init"}).to_string()).await;
+            assert_eq!(receive(&mut client).await, json!({"type": "connection_ack"}));
+            client.send_text(json!({"id": "1", "type": "subscribe", "payload": {"query": "{ me { name } }"}}).to_string()).await;
+            assert_eq!(receive(&mut client).await["payload"]["data"]["me"], me, "{}", origin);
+        }
+    }
+
+    // Define a test that upgrades to /graphql are served like those to /graphql/ws, acting for the
+    // credentials connection_init carries
+    #[tokio::test]
+    async fn test_legacy_path() {
+        let mut client = warp::test::ws().path("/graphql").header("sec-websocket-protocol", "graphql-ws").handshake(route(None)).await.unwrap();
+        client.send_text(json!({"type": "connection_init", "payload": {"authorization": "Bearer secret"}}).to_string()).await;
+        assert_eq!(receive(&mut client).await, json!({"type": "connection_ack"}));
+        client.send_text(json!({"id": "1", "type": "start", "payload": {"query": "{ me { name } }"}}).to_string()).await;
+        assert_eq!(receive(&mut client).await, json!({"id": "1", "type": "data", "payload": {"data": {"me": {"name": "Pavel"}}}}));
+
+        // Other requests to /graphql are left to the routes after it
+        assert!(warp::test::request().path("/graphql").filter(&route(None)).await.is_err());
+    }
+
+    // Define a test that idle connections are pinged
+    #[tokio::test]
+    async fn test_keep_alive() {
+        let mut client = connect(Some(Duration::from_millis(20))).await;
+        assert_eq!(receive(&mut client).await, json!({"type": "ping"}));
+        client.send_text(json!({"type": "connection_init"}).to_string()).await;
+        assert_eq!(receive(&mut client).await, json!({"type": "connection_ack"}));
+        assert_eq!(receive(&mut client).await, json!({"type": "ping"}));
+    }
+}